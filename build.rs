@@ -0,0 +1,36 @@
+//! Embeds build metadata in the binary: the git commit it was built from and the build
+//! date, exposed as `SERVER_FORGE_GIT_COMMIT` and `SERVER_FORGE_BUILD_DATE`. Both are
+//! `unknown` when they cannot be determined (e.g. when building from a source archive).
+
+use std::process::Command;
+
+/// Returns the trimmed standard output of a command, if it succeeds.
+fn command_output(command: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(command).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let output = String::from_utf8(output.stdout).ok()?;
+    Some(output.trim().to_string()).filter(|output| !output.is_empty())
+}
+
+fn main() {
+    let commit = command_output("git", &["rev-parse", "--short", "HEAD"]);
+    // Reproducible builds set SOURCE_DATE_EPOCH to use instead of the current date
+    let date = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => command_output("date", &["-u", "-d", &format!("@{}", epoch), "+%Y-%m-%d"]),
+        Err(_) => command_output("date", &["-u", "+%Y-%m-%d"]),
+    };
+
+    println!(
+        "cargo:rustc-env=SERVER_FORGE_GIT_COMMIT={}",
+        commit.as_deref().unwrap_or("unknown")
+    );
+    println!(
+        "cargo:rustc-env=SERVER_FORGE_BUILD_DATE={}",
+        date.as_deref().unwrap_or("unknown")
+    );
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
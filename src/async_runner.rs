@@ -0,0 +1,411 @@
+//! # Async Runner Module
+//!
+//! This module is available with the `async` feature. It defines `AsyncCommandRunner`, an
+//! asynchronous counterpart of `runner::CommandRunner` whose commands are executed with
+//! `tokio::process`, so that a single thread can wait on commands of many hosts at once.
+//!
+//! The setup phases are synchronous. `BlockingCommandRunner` lets them run on an
+//! `AsyncCommandRunner` from a blocking thread, and `run_on_hosts` runs a phase function on
+//! many hosts concurrently that way, limited to a number of hosts at a time.
+
+use crate::remote::{remote_command_line, RemoteCommandRunner};
+use crate::runner::{with_runner, CommandOutput, CommandRunner};
+use std::error::Error;
+use std::future::Future;
+use std::pin::Pin;
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::runtime::Handle;
+use tokio::sync::Semaphore;
+
+/// An error that can be sent between threads, as returned by `AsyncCommandRunner`.
+pub type AsyncError = Box<dyn Error + Send + Sync>;
+
+/// A boxed future that can be sent between threads.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Executes commands and file operations on a host asynchronously.
+pub trait AsyncCommandRunner: Send + Sync {
+    /// Returns the name of the host this runner acts on.
+    fn host(&self) -> &str;
+
+    /// Runs a command with extra environment variables and captures its output.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command could not be started. A command that runs but exits
+    /// unsuccessfully is reported through `CommandOutput::success` instead.
+    fn run<'a>(
+        &'a self,
+        command: &'a str,
+        args: &'a [&'a str],
+        env: &'a [(String, String)],
+    ) -> BoxFuture<'a, Result<CommandOutput, AsyncError>>;
+
+    /// Runs a command like `run`, writing `input` to its standard input.
+    ///
+    /// The default implementation fails, for runners that cannot pass standard input.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command could not be started.
+    fn run_with_input<'a>(
+        &'a self,
+        command: &'a str,
+        _args: &'a [&'a str],
+        _env: &'a [(String, String)],
+        _input: &'a [u8],
+    ) -> BoxFuture<'a, Result<CommandOutput, AsyncError>> {
+        Box::pin(async move {
+            Err(format!(
+                "Cannot pass standard input to {} on {}",
+                command,
+                self.host()
+            )
+            .into())
+        })
+    }
+
+    /// Reads the contents of a file.
+    fn read_file<'a>(&'a self, path: &'a str) -> BoxFuture<'a, Result<Vec<u8>, AsyncError>>;
+
+    /// Writes a file, replacing any existing contents.
+    fn write_file<'a>(
+        &'a self,
+        path: &'a str,
+        contents: &'a [u8],
+    ) -> BoxFuture<'a, Result<(), AsyncError>>;
+
+    /// Creates a directory and all of its missing parents.
+    fn create_dir_all<'a>(&'a self, path: &'a str) -> BoxFuture<'a, Result<(), AsyncError>>;
+
+    /// Returns whether a file or directory exists.
+    fn path_exists<'a>(&'a self, path: &'a str) -> BoxFuture<'a, bool>;
+}
+
+/// Runs commands and file operations on the local machine asynchronously.
+#[derive(Debug, Clone, Default)]
+pub struct LocalAsyncCommandRunner;
+
+impl AsyncCommandRunner for LocalAsyncCommandRunner {
+    fn host(&self) -> &str {
+        "localhost"
+    }
+
+    fn run<'a>(
+        &'a self,
+        command: &'a str,
+        args: &'a [&'a str],
+        env: &'a [(String, String)],
+    ) -> BoxFuture<'a, Result<CommandOutput, AsyncError>> {
+        Box::pin(async move {
+            let mut command = Command::new(command);
+            command
+                .args(args)
+                .envs(env.iter().map(|(name, value)| (name, value)));
+            output_of(command, None).await
+        })
+    }
+
+    fn run_with_input<'a>(
+        &'a self,
+        command: &'a str,
+        args: &'a [&'a str],
+        env: &'a [(String, String)],
+        input: &'a [u8],
+    ) -> BoxFuture<'a, Result<CommandOutput, AsyncError>> {
+        Box::pin(async move {
+            let mut command = Command::new(command);
+            command
+                .args(args)
+                .envs(env.iter().map(|(name, value)| (name, value)));
+            output_of(command, Some(input)).await
+        })
+    }
+
+    fn read_file<'a>(&'a self, path: &'a str) -> BoxFuture<'a, Result<Vec<u8>, AsyncError>> {
+        Box::pin(async move { Ok(tokio::fs::read(path).await?) })
+    }
+
+    fn write_file<'a>(
+        &'a self,
+        path: &'a str,
+        contents: &'a [u8],
+    ) -> BoxFuture<'a, Result<(), AsyncError>> {
+        Box::pin(async move { Ok(tokio::fs::write(path, contents).await?) })
+    }
+
+    fn create_dir_all<'a>(&'a self, path: &'a str) -> BoxFuture<'a, Result<(), AsyncError>> {
+        Box::pin(async move { Ok(tokio::fs::create_dir_all(path).await?) })
+    }
+
+    fn path_exists<'a>(&'a self, path: &'a str) -> BoxFuture<'a, bool> {
+        Box::pin(async move { tokio::fs::try_exists(path).await.unwrap_or(false) })
+    }
+}
+
+/// Runs commands on a remote host over SSH asynchronously.
+///
+/// Commands run through `ssh` like `RemoteCommandRunner`. File operations, which are rare
+/// next to commands, are handed to a `RemoteCommandRunner` on a blocking thread.
+#[derive(Debug, Clone)]
+pub struct RemoteAsyncCommandRunner {
+    blocking: Arc<RemoteCommandRunner>,
+}
+
+impl RemoteAsyncCommandRunner {
+    /// Creates a runner for the given SSH destination.
+    ///
+    /// # Arguments
+    ///
+    /// * `destination` - The host to connect to, as accepted by `ssh` (e.g. `web1` or `root@10.0.0.5`)
+    pub fn new(destination: &str) -> Self {
+        RemoteAsyncCommandRunner {
+            blocking: Arc::new(RemoteCommandRunner::new(destination)),
+        }
+    }
+
+    /// Returns the `ssh` process running a command on the remote host.
+    fn ssh(&self, command: &str, args: &[&str], env: &[(String, String)]) -> Command {
+        let mut ssh = Command::new("ssh");
+        ssh.args([
+            "-o",
+            "BatchMode=yes",
+            self.host(),
+            "--",
+            &remote_command_line(command, args, env),
+        ]);
+        ssh
+    }
+
+    /// Runs a file operation of the blocking runner on a blocking thread.
+    async fn blocking<T: Send + 'static>(
+        &self,
+        operation: impl FnOnce(&RemoteCommandRunner) -> Result<T, Box<dyn Error>> + Send + 'static,
+    ) -> Result<T, AsyncError> {
+        let runner = self.blocking.clone();
+        // The error is not Send, so it crosses back to the async side as its message
+        tokio::task::spawn_blocking(move || operation(&runner).map_err(|e| e.to_string()))
+            .await?
+            .map_err(AsyncError::from)
+    }
+}
+
+impl AsyncCommandRunner for RemoteAsyncCommandRunner {
+    fn host(&self) -> &str {
+        self.blocking.host()
+    }
+
+    fn run<'a>(
+        &'a self,
+        command: &'a str,
+        args: &'a [&'a str],
+        env: &'a [(String, String)],
+    ) -> BoxFuture<'a, Result<CommandOutput, AsyncError>> {
+        Box::pin(async move { output_of(self.ssh(command, args, env), None).await })
+    }
+
+    fn run_with_input<'a>(
+        &'a self,
+        command: &'a str,
+        args: &'a [&'a str],
+        env: &'a [(String, String)],
+        input: &'a [u8],
+    ) -> BoxFuture<'a, Result<CommandOutput, AsyncError>> {
+        // ssh forwards its standard input to the remote command
+        Box::pin(async move { output_of(self.ssh(command, args, env), Some(input)).await })
+    }
+
+    fn read_file<'a>(&'a self, path: &'a str) -> BoxFuture<'a, Result<Vec<u8>, AsyncError>> {
+        let path = path.to_string();
+        Box::pin(self.blocking(move |runner| runner.read_file(&path)))
+    }
+
+    fn write_file<'a>(
+        &'a self,
+        path: &'a str,
+        contents: &'a [u8],
+    ) -> BoxFuture<'a, Result<(), AsyncError>> {
+        let (path, contents) = (path.to_string(), contents.to_vec());
+        Box::pin(self.blocking(move |runner| runner.write_file(&path, &contents)))
+    }
+
+    fn create_dir_all<'a>(&'a self, path: &'a str) -> BoxFuture<'a, Result<(), AsyncError>> {
+        let path = path.to_string();
+        Box::pin(self.blocking(move |runner| runner.create_dir_all(&path)))
+    }
+
+    fn path_exists<'a>(&'a self, path: &'a str) -> BoxFuture<'a, bool> {
+        let path = path.to_string();
+        Box::pin(async move {
+            self.blocking(move |runner| Ok(runner.path_exists(&path)))
+                .await
+                .unwrap_or(false)
+        })
+    }
+}
+
+/// Runs a process to completion, writing `input` to its standard input if given, and
+/// captures its output.
+async fn output_of(
+    mut command: Command,
+    input: Option<&[u8]>,
+) -> Result<CommandOutput, AsyncError> {
+    let Some(input) = input else {
+        let output = command.stdin(Stdio::null()).output().await?;
+        return Ok(CommandOutput {
+            success: output.status.success(),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    };
+
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().ok_or("stdin not piped")?;
+    // The input is written by its own task so that a process printing a lot before it reads
+    // its input does not block on a full pipe
+    let input = input.to_vec();
+    tokio::spawn(async move { stdin.write_all(&input).await });
+    let output = child.wait_with_output().await?;
+    Ok(CommandOutput {
+        success: output.status.success(),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    })
+}
+
+/// A `CommandRunner` driving an `AsyncCommandRunner` on a tokio runtime, so that the
+/// synchronous setup phases can run on it.
+///
+/// Every operation blocks the current thread until it completes, so the runner must be
+/// used from a thread outside of the runtime's asynchronous tasks, such as one started with
+/// `tokio::task::spawn_blocking`; blocking inside a task panics.
+pub struct BlockingCommandRunner {
+    runner: Arc<dyn AsyncCommandRunner>,
+    handle: Handle,
+}
+
+impl BlockingCommandRunner {
+    /// Creates a runner driving `runner` on the runtime of `handle`.
+    ///
+    /// # Arguments
+    ///
+    /// * `runner` - The asynchronous runner to execute operations with
+    /// * `handle` - A handle to the runtime to execute them on
+    pub fn new(runner: Arc<dyn AsyncCommandRunner>, handle: Handle) -> Self {
+        BlockingCommandRunner { runner, handle }
+    }
+}
+
+impl CommandRunner for BlockingCommandRunner {
+    fn host(&self) -> &str {
+        self.runner.host()
+    }
+
+    fn run(
+        &self,
+        command: &str,
+        args: &[&str],
+        env: &[(String, String)],
+    ) -> Result<CommandOutput, Box<dyn Error>> {
+        self.handle
+            .block_on(self.runner.run(command, args, env))
+            .map_err(|e| e as Box<dyn Error>)
+    }
+
+    fn run_with_input(
+        &self,
+        command: &str,
+        args: &[&str],
+        env: &[(String, String)],
+        input: &[u8],
+    ) -> Result<CommandOutput, Box<dyn Error>> {
+        self.handle
+            .block_on(self.runner.run_with_input(command, args, env, input))
+            .map_err(|e| e as Box<dyn Error>)
+    }
+
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.handle
+            .block_on(self.runner.read_file(path))
+            .map_err(|e| e as Box<dyn Error>)
+    }
+
+    fn write_file(&self, path: &str, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.handle
+            .block_on(self.runner.write_file(path, contents))
+            .map_err(|e| e as Box<dyn Error>)
+    }
+
+    fn create_dir_all(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        self.handle
+            .block_on(self.runner.create_dir_all(path))
+            .map_err(|e| e as Box<dyn Error>)
+    }
+
+    fn path_exists(&self, path: &str) -> bool {
+        self.handle.block_on(self.runner.path_exists(path))
+    }
+}
+
+/// Runs a function on many hosts concurrently, each with a `BlockingCommandRunner` of its
+/// host as the current runner (see `runner::with_runner`).
+///
+/// Each host runs on its own blocking thread, and at most `max_concurrent` hosts run at a
+/// time. A failing host does not stop the others.
+///
+/// # Arguments
+///
+/// * `runners` - The runners of the hosts to run `task` on
+/// * `max_concurrent` - How many hosts to run `task` on at a time (at least one)
+/// * `task` - The function to run on every host, such as a setup phase
+///
+/// # Returns
+///
+/// Returns the name and the result of every host, in the order of `runners`. Errors are
+/// returned as their messages, as are panics of `task`.
+pub async fn run_on_hosts<T, F>(
+    runners: Vec<Arc<dyn AsyncCommandRunner>>,
+    max_concurrent: usize,
+    task: F,
+) -> Vec<(String, Result<T, String>)>
+where
+    T: Send + 'static,
+    F: Fn() -> Result<T, Box<dyn Error>> + Send + Sync + 'static,
+{
+    let handle = Handle::current();
+    let permits = Arc::new(Semaphore::new(max_concurrent.max(1)));
+    let task = Arc::new(task);
+
+    let mut running = Vec::new();
+    for runner in runners {
+        let host = runner.host().to_string();
+        let (handle, permits, task) = (handle.clone(), permits.clone(), task.clone());
+        running.push((
+            host,
+            tokio::spawn(async move {
+                let _permit = permits.acquire_owned().await.map_err(|e| e.to_string())?;
+                let blocking = handle.clone();
+                tokio::task::spawn_blocking(move || {
+                    let runner = Arc::new(BlockingCommandRunner::new(runner, blocking));
+                    with_runner(runner, || task().map_err(|e| e.to_string()))
+                })
+                .await
+                .map_err(|e| e.to_string())?
+            }),
+        ));
+    }
+
+    let mut results = Vec::new();
+    for (host, running) in running {
+        let result = running.await.unwrap_or_else(|e| Err(e.to_string()));
+        results.push((host, result));
+    }
+    results
+}
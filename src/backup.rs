@@ -7,13 +7,31 @@
 //! The module includes functions for installing backup tools, configuring backup schedules,
 //! and setting up backup locations based on the server's role.
 
-use crate::config::Config;
-use crate::distro::{get_package_manager, PackageManager};
+use crate::config::{Config, ServerRole};
+use crate::deployment::MYSQL_PASSWORD_FILE;
+use crate::distro::{get_package_manager, install_package_tracked};
+use crate::maintenance;
+use crate::monitoring::TEXTFILE_COLLECTOR_DIR;
 use crate::rollback::RollbackManager;
-use crate::utils::run_command;
+use crate::secrets::{secret_store, FileSecretStore, SecretStore, MYSQL_ROOT_PASSWORD};
+use crate::utils::{
+    create_dir_all, run_command, schedule_job, set_permissions, shell_quote, write_file,
+};
 use log::info;
 use std::error::Error;
 
+/// The location of the restic repository.
+pub const BACKUP_REPOSITORY: &str = "/path/to/backup/repository";
+
+/// The metrics file the backup script writes to the textfile collector of Node Exporter.
+pub const BACKUP_METRICS_FILE: &str = "serverforge_backup.prom";
+
+/// The file restic reads backup exclude patterns from.
+pub const BACKUP_EXCLUDE_FILE: &str = "/etc/restic/excludes";
+
+/// The directory database dumps are written to before being backed up.
+pub const DATABASE_DUMP_DIR: &str = "/var/backups/serverforge/db";
+
 /// Sets up the backup system based on the provided configuration.
 ///
 /// This function orchestrates the entire backup setup process, including:
@@ -37,9 +55,9 @@ pub fn setup_backup_system(
 ) -> Result<(), Box<dyn Error>> {
     info!("Setting up backup system...");
 
-    let snapshot = rollback.create_snapshot()?;
+    let snapshot = rollback.create_snapshot("Backup system")?;
 
-    install_backup_tools()?;
+    install_backup_tools(rollback, snapshot)?;
     configure_backup_schedule(config)?;
     setup_backup_locations(config)?;
 
@@ -54,85 +72,338 @@ pub fn setup_backup_system(
 /// This function uses the appropriate package manager for the current Linux distribution
 /// to install restic.
 ///
+/// # Arguments
+///
+/// * `rollback` - The `RollbackManager` to record the installed package in
+/// * `snapshot` - The ID of the snapshot to record the installed package in
+///
 /// # Returns
 ///
 /// Returns `Ok(())` if restic is installed successfully, or an error if installation fails.
-pub fn install_backup_tools() -> Result<(), Box<dyn Error>> {
-    let package_manager = get_package_manager()?;
-    match package_manager {
-        PackageManager::Apt => run_command("apt", &["install", "-y", "restic"])?,
-        PackageManager::Yum => run_command("yum", &["install", "-y", "restic"])?,
-        PackageManager::Dnf => run_command("dnf", &["install", "-y", "restic"])?,
-    }
-    Ok(())
+pub fn install_backup_tools(
+    rollback: &RollbackManager,
+    snapshot: usize,
+) -> Result<(), Box<dyn Error>> {
+    install_package_tracked(&get_package_manager()?, "restic", rollback, snapshot)
 }
 
 /// Configures the backup schedule based on the provided configuration.
 ///
-/// This function creates a cron job for running backups at the specified frequency
-/// (hourly, daily, or weekly).
+/// This function schedules the backup script at the specified frequency (hourly, daily, or weekly)
+/// using the configured scheduler (a cron job or a systemd timer). Daily and weekly backups
+/// start when the maintenance window opens (see [`maintenance::backup_schedule`]).
 ///
 /// # Arguments
 ///
-/// * `config` - A reference to the `Config` struct containing the backup frequency
+/// * `config` - A reference to the `Config` struct containing the backup frequency, maintenance
+///   window and scheduler
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` if the backup schedule is configured successfully, or an error if configuration fails.
 pub fn configure_backup_schedule(config: &Config) -> Result<(), Box<dyn Error>> {
-    let cron_job = match config.backup_frequency.as_str() {
-        "hourly" => {
-            "0 * * * * root /usr/bin/restic backup /path/to/backup >> /var/log/restic.log 2>&1\n"
-        }
-        "daily" => {
-            "0 2 * * * root /usr/bin/restic backup /path/to/backup >> /var/log/restic.log 2>&1\n"
-        }
-        "weekly" => {
-            "0 2 * * 0 root /usr/bin/restic backup /path/to/backup >> /var/log/restic.log 2>&1\n"
-        }
-        _ => return Err("Invalid backup frequency".into()),
-    };
+    let schedule = maintenance::backup_schedule(config)?;
 
-    std::fs::write("/etc/cron.d/restic-backup", cron_job)?;
-    Ok(())
+    schedule_job(
+        &config.scheduler,
+        "restic-backup",
+        "Restic backup",
+        &schedule,
+        "/usr/local/bin/run-backup.sh >> /var/log/restic.log 2>&1",
+    )
 }
 
 /// Sets up backup locations based on the server's role.
 ///
-/// This function determines which directories to back up based on the server's role
-/// (web, database, or application server). It then initializes a restic repository
-/// and creates a backup script that includes these locations.
+/// This function initializes a restic repository, writes the configured exclude patterns
+/// to [`BACKUP_EXCLUDE_FILE`], and creates a backup script (see [`generate_backup_script`])
+/// covering the locations for the server's role.
+///
+/// The script runs from cron without Vault credentials, so when it dumps MySQL and secrets
+/// are kept in Vault, the MySQL root password is copied from Vault to
+/// [`MYSQL_PASSWORD_FILE`] (readable only by root) for the script to read.
 ///
 /// # Arguments
 ///
-/// * `config` - A reference to the `Config` struct containing the server role
+/// * `config` - A reference to the `Config` struct containing the server role and backup excludes
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` if backup locations are set up successfully, or an error if setup fails.
 pub fn setup_backup_locations(config: &Config) -> Result<(), Box<dyn Error>> {
-    // Define backup locations based on server role
-    let backup_dirs = match config.server_role.as_str() {
-        "web" => vec!["/var/www", "/etc/nginx", "/etc/apache2"],
-        "database" => vec!["/var/lib/mysql", "/var/lib/postgresql"],
-        "application" => vec!["/opt/myapp", "/etc/myapp"],
-        _ => vec![],
-    };
+    if !config.backup_excludes.is_empty() {
+        let exclude_file = generate_exclude_file(config)?;
+        create_dir_all("/etc/restic")?;
+        write_file(BACKUP_EXCLUDE_FILE, exclude_file)?;
+    }
 
     // Create restic repository
-    run_command("restic", &["init", "--repo", "/path/to/backup/repository"])?;
+    run_command("restic", &["init", "--repo", BACKUP_REPOSITORY])?;
+
+    if config.vault.is_some() && dumped_databases(config).contains(&"mysql") {
+        if let Some(password) = secret_store(config)?.get(MYSQL_ROOT_PASSWORD)? {
+            FileSecretStore.put(MYSQL_ROOT_PASSWORD, &password)?;
+        }
+    }
 
     // Create backup script
-    let mut backup_script = String::from("#!/bin/bash\n\n");
+    let backup_script = generate_backup_script(config);
+    write_file("/usr/local/bin/run-backup.sh", backup_script)?;
+    set_permissions("/usr/local/bin/run-backup.sh", 0o755)?;
+
+    Ok(())
+}
+
+/// Generates the backup script for the server's role.
+///
+/// This function determines which directories to back up based on the server's role
+/// (web, database, or application server). Database servers must not back up their live
+/// data directories, which are inconsistent while the server is running; instead the
+/// script first dumps each database (`mysqldump --all-databases` / `pg_dumpall`) into
+/// [`DATABASE_DUMP_DIR`] and backs up the dumps. The MySQL root password is read from
+/// [`MYSQL_PASSWORD_FILE`], if present (see [`setup_backup_locations`]).
+///
+/// If an admin email or notification webhook is configured, a failed backup (including a
+/// failed dump) sends a notification, and with `backup_notify_on_success` a successful one
+/// sends a summary of the snapshot id, the data added, and the duration.
+///
+/// With monitoring enabled, the script records the outcome of every run in
+/// [`BACKUP_METRICS_FILE`] in the textfile collector directory of Node Exporter: whether it
+/// succeeded, when it ran, and when a backup last succeeded.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the server role, deployed apps,
+///   and notification settings
+///
+/// # Returns
+///
+/// Returns the contents of the backup script.
+pub fn generate_backup_script(config: &Config) -> String {
+    // Define backup locations based on the server roles
+    let mut backup_dirs: Vec<&str> = Vec::new();
+    for role in &config.server_roles {
+        let role_dirs: &[&str] = match role {
+            ServerRole::Web => &["/var/www", "/etc/nginx", "/etc/apache2"],
+            ServerRole::Database => &[],
+            ServerRole::Application => &["/opt/myapp", "/etc/myapp"],
+        };
+        for dir in role_dirs {
+            if !backup_dirs.contains(dir) {
+                backup_dirs.push(dir);
+            }
+        }
+    }
+
+    let databases = dumped_databases(config);
+
+    let mut backup_script = String::from("#!/bin/bash\n");
+    backup_script.push_str("set -eo pipefail\n\n");
+    backup_script.push_str(&format!(
+        "export RESTIC_REPOSITORY='{}'\n",
+        BACKUP_REPOSITORY
+    ));
     backup_script.push_str("export RESTIC_PASSWORD='your_restic_password'\n\n");
-    backup_script.push_str("restic backup");
+
+    if config.monitoring {
+        backup_script.push_str(&metrics_function());
+        backup_script.push_str("trap 'write_backup_metrics $?' EXIT\n\n");
+    }
+
+    let notify = config.admin_email.is_some() || config.secrets.notification_webhook.is_some();
+    if notify {
+        backup_script.push_str(&notification_function(config));
+        backup_script.push_str(
+            "trap 'notify failed \"Backup script failed on $(hostname) at line $LINENO\"' ERR\n\n",
+        );
+    }
+
+    if !databases.is_empty() {
+        backup_script.push_str(&format!("mkdir -p -m 700 {}\n", DATABASE_DUMP_DIR));
+        for db in &databases {
+            backup_script.push_str(&database_dump_commands(db));
+        }
+        backup_script.push('\n');
+        backup_dirs.push(DATABASE_DUMP_DIR);
+    }
+
+    let mut restic_backup = String::from("restic backup");
     for dir in backup_dirs {
-        backup_script.push_str(&format!(" {}", dir));
+        restic_backup.push_str(&format!(" {}", dir));
     }
-    backup_script.push_str(" --tag serverforge\n");
+    if !config.backup_excludes.is_empty() {
+        restic_backup.push_str(&format!(" --exclude-file={}", BACKUP_EXCLUDE_FILE));
+    }
+    restic_backup.push_str(" --tag serverforge");
 
-    std::fs::write("/usr/local/bin/run-backup.sh", backup_script)?;
-    run_command("chmod", &["+x", "/usr/local/bin/run-backup.sh"])?;
+    if !notify {
+        backup_script.push_str(&restic_backup);
+        backup_script.push('\n');
+        return backup_script;
+    }
 
-    Ok(())
+    backup_script.push_str(&format!(
+        r#"start=$(date +%s)
+if ! output=$({} 2>&1); then
+    echo "$output"
+    trap - ERR
+    notify failed "restic backup failed on $(hostname):
+$output"
+    exit 1
+fi
+echo "$output"
+"#,
+        restic_backup
+    ));
+
+    if config.backup_notify_on_success {
+        backup_script.push_str(
+            r#"
+snapshot=$(echo "$output" | sed -n 's/^snapshot \([0-9a-f]*\) saved$/\1/p')
+added=$(echo "$output" | sed -n 's/^Added to the repository: //p')
+notify succeeded "Backup succeeded on $(hostname): snapshot ${snapshot:-unknown}, ${added:-unknown} added in $(( $(date +%s) - start ))s"
+"#,
+        );
+    }
+
+    backup_script
+}
+
+/// Generates the contents of the restic exclude file.
+///
+/// Each configured pattern is written on its own line; restic accepts both glob
+/// patterns (e.g., `node_modules`, `*.log`) and absolute paths.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the backup excludes
+///
+/// # Returns
+///
+/// Returns the exclude file contents, or an error if any pattern is empty.
+pub fn generate_exclude_file(config: &Config) -> Result<String, Box<dyn Error>> {
+    let mut exclude_file = String::new();
+    for (i, pattern) in config.backup_excludes.iter().enumerate() {
+        let pattern = pattern.trim();
+        if pattern.is_empty() {
+            return Err(format!("Backup exclude pattern #{} is empty", i + 1).into());
+        }
+        exclude_file.push_str(pattern);
+        exclude_file.push('\n');
+    }
+    Ok(exclude_file)
+}
+
+/// Returns the shell `write_backup_metrics <exit status>` function used by the backup
+/// script.
+///
+/// The metrics are written to a temporary file and moved into place, so Node Exporter never
+/// reads a partial file. The last success time is carried over from the previous file when
+/// the run failed. Nothing is written if the collector directory does not exist.
+fn metrics_function() -> String {
+    format!(
+        r#"write_backup_metrics() {{
+    local dir={dir}
+    [ -d "$dir" ] || return 0
+    local now success last_success
+    now=$(date +%s)
+    success=0
+    last_success=$(sed -n 's/^serverforge_backup_last_success_timestamp_seconds //p' "$dir/{file}" 2> /dev/null || true)
+    if [ "$1" -eq 0 ]; then
+        success=1
+        last_success=$now
+    fi
+    {{
+        echo '# HELP serverforge_backup_success Whether the last backup succeeded.'
+        echo '# TYPE serverforge_backup_success gauge'
+        echo "serverforge_backup_success $success"
+        echo '# HELP serverforge_backup_last_run_timestamp_seconds When the last backup finished.'
+        echo '# TYPE serverforge_backup_last_run_timestamp_seconds gauge'
+        echo "serverforge_backup_last_run_timestamp_seconds $now"
+        if [ -n "$last_success" ]; then
+            echo '# HELP serverforge_backup_last_success_timestamp_seconds When a backup last succeeded.'
+            echo '# TYPE serverforge_backup_last_success_timestamp_seconds gauge'
+            echo "serverforge_backup_last_success_timestamp_seconds $last_success"
+        fi
+    }} > "$dir/{file}.$$"
+    mv "$dir/{file}.$$" "$dir/{file}"
+}}
+
+"#,
+        dir = TEXTFILE_COLLECTOR_DIR,
+        file = BACKUP_METRICS_FILE
+    )
+}
+
+/// Returns the shell `notify <status> <message>` function used by the backup script.
+///
+/// The function mails the message to the admin email (via `mail`, falling back to `sendmail`)
+/// and posts it to the notification webhook as a Slack-compatible `{"text": ...}` payload.
+/// Notification failures are ignored so they never mask the backup's own exit status.
+fn notification_function(config: &Config) -> String {
+    let mut function = String::from("notify() {\n");
+
+    if let Some(admin_email) = &config.admin_email {
+        function.push_str(&format!(
+            r#"    local subject="[serverforge] Backup $1 on $(hostname)"
+    if command -v mail > /dev/null; then
+        printf '%s\n' "$2" | mail -s "$subject" {email} || true
+    elif command -v sendmail > /dev/null; then
+        printf 'Subject: %s\n\n%s\n' "$subject" "$2" | sendmail {email} || true
+    fi
+"#,
+            email = shell_quote(admin_email)
+        ));
+    }
+
+    if let Some(webhook) = &config.secrets.notification_webhook {
+        function.push_str(&format!(
+            r#"    local text
+    text=$(printf '%s' "$2" | sed -e 's/\\/\\\\/g' -e 's/"/\\"/g' -e 's/\t/\\t/g' | awk 'NR > 1 {{ printf "\\n" }} {{ printf "%s", $0 }}')
+    curl -fsS -m 30 -X POST -H 'Content-Type: application/json' \
+        -d "{{\"text\": \"$text\"}}" {webhook} > /dev/null || true
+"#,
+            webhook = shell_quote(webhook)
+        ));
+    }
+
+    function.push_str("}\n\n");
+    function
+}
+
+/// Returns the databases the backup script dumps: all of them on database servers, otherwise
+/// those among the deployed apps.
+fn dumped_databases(config: &Config) -> Vec<&'static str> {
+    let is_database_server = config.server_roles.contains(&ServerRole::Database);
+    ["mysql", "postgresql"]
+        .into_iter()
+        .filter(|db| is_database_server || config.deployed_apps.iter().any(|app| app == db))
+        .collect()
+}
+
+/// Returns the shell commands that dump the given database into the dump directory.
+///
+/// Each dump is guarded so the script still works if the database isn't installed.
+fn database_dump_commands(db: &str) -> String {
+    match db {
+        "mysql" => format!(
+            r#"if command -v mysqldump > /dev/null; then
+    if [ -f {password_file} ]; then
+        export MYSQL_PWD="$(cat {password_file})"
+    fi
+    mysqldump --user=root --all-databases --single-transaction --routines --events > {dir}/mysql.sql
+fi
+"#,
+            password_file = MYSQL_PASSWORD_FILE,
+            dir = DATABASE_DUMP_DIR
+        ),
+        "postgresql" => format!(
+            r#"if command -v pg_dumpall > /dev/null; then
+    sudo -u postgres pg_dumpall > {dir}/postgresql.sql
+fi
+"#,
+            dir = DATABASE_DUMP_DIR
+        ),
+        _ => String::new(),
+    }
 }
@@ -0,0 +1,176 @@
+//! # Check Module
+//!
+//! This module looks for mistakes in a configuration before it is applied, without changing
+//! anything: invalid options, a distribution that does not match the system, files that the
+//! configuration refers to but that do not exist, and services that would listen on the
+//! same port. Every problem found is reported, so they can all be fixed at once.
+
+use crate::config::Config;
+use crate::deployment::RABBITMQ_MANAGEMENT_PORT;
+use crate::monitoring::{blackbox_targets, CADVISOR_PORT};
+use crate::utils::{path_exists, read_file};
+use std::collections::BTreeMap;
+
+/// The distributions a configuration can target.
+const SUPPORTED_DISTROS: [&str; 3] = ["ubuntu", "centos", "fedora"];
+
+/// The file identifying the distribution of the system.
+const OS_RELEASE_PATH: &str = "/etc/os-release";
+
+/// Checks a configuration against the system of the current `CommandRunner`.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct to check
+///
+/// # Returns
+///
+/// Returns a description of every problem found, or an empty list if the configuration can
+/// be applied.
+pub fn check_config(config: &Config) -> Vec<String> {
+    let mut problems = config.validation_errors();
+    problems.extend(distro_problem(config));
+    problems.extend(missing_files(config));
+    problems.extend(port_conflicts(config));
+    problems
+}
+
+/// Returns the `ID` of the distribution described by the contents of `/etc/os-release`.
+///
+/// # Arguments
+///
+/// * `os_release` - The contents of `/etc/os-release`
+///
+/// # Returns
+///
+/// Returns the ID without quotes (e.g. "ubuntu"), or `None` if there is none.
+pub fn os_release_id(os_release: &str) -> Option<String> {
+    os_release
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("ID="))
+        .map(|id| id.trim_matches(|c| c == '"' || c == '\'').to_string())
+        .filter(|id| !id.is_empty())
+}
+
+/// Returns the ports the services set up by a configuration listen on, with the service
+/// listening on each, in setup order.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct describing the services
+pub fn listening_ports(config: &Config) -> Vec<(u16, String)> {
+    let mut ports = Vec::new();
+    let mut add = |port: u16, service: &str| ports.push((port, service.to_string()));
+
+    add(config.ssh_port, "sshd");
+
+    if config.monitoring {
+        add(config.grafana_port, "grafana");
+        add(config.prometheus_port, "prometheus");
+        add(9100, "node_exporter");
+        if !blackbox_targets(config).is_empty() {
+            add(9115, "blackbox_exporter");
+        }
+        if config.enable_logs {
+            add(3100, "loki");
+            add(9080, "promtail");
+        }
+        if config.use_containers {
+            add(CADVISOR_PORT, "cadvisor");
+        }
+    }
+
+    for app in &config.deployed_apps {
+        match app.as_str() {
+            "nginx" | "apache" => add(80, app),
+            "haproxy" => {
+                add(80, app);
+                if config.load_balancer_certificate.is_some() {
+                    add(443, app);
+                }
+            }
+            "mysql" => add(3306, app),
+            "postgresql" => add(5432, app),
+            "nodejs" => add(3000, app),
+            "python" => add(5000, app),
+            "rabbitmq" => {
+                add(5672, app);
+                add(RABBITMQ_MANAGEMENT_PORT, app);
+            }
+            "memcached" => add(11211, app),
+            "opensearch" => add(9200, app),
+            _ => {}
+        }
+    }
+    ports
+}
+
+/// Returns a description of every port more than one service of a configuration would
+/// listen on, in port order.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct describing the services
+pub fn port_conflicts(config: &Config) -> Vec<String> {
+    let mut services: BTreeMap<u16, Vec<String>> = BTreeMap::new();
+    for (port, service) in listening_ports(config) {
+        services.entry(port).or_default().push(service);
+    }
+    services
+        .into_iter()
+        .filter(|(_, services)| services.len() > 1)
+        .map(|(port, services)| {
+            format!(
+                "Port {} would be used by more than one service: {}",
+                port,
+                services.join(", ")
+            )
+        })
+        .collect()
+}
+
+/// Checks that the distribution of a configuration is supported and is the one the system
+/// runs.
+fn distro_problem(config: &Config) -> Option<String> {
+    let distro = config.linux_distro.as_str();
+    if !SUPPORTED_DISTROS.contains(&distro) {
+        return Some(format!(
+            "Unsupported Linux distribution '{}' (expected {})",
+            distro,
+            SUPPORTED_DISTROS.join(", ")
+        ));
+    }
+
+    match read_file(OS_RELEASE_PATH).map(|contents| os_release_id(&contents)) {
+        Ok(Some(id)) if id == distro => None,
+        Ok(Some(id)) => Some(format!(
+            "linux_distro is '{}' but this system runs '{}'",
+            distro, id
+        )),
+        Ok(None) => Some(format!(
+            "{} does not name the distribution",
+            OS_RELEASE_PATH
+        )),
+        Err(e) => Some(format!(
+            "Cannot determine the distribution of this system: {}",
+            e
+        )),
+    }
+}
+
+/// Checks that the files and directories a configuration refers to exist.
+fn missing_files(config: &Config) -> Vec<String> {
+    [
+        (
+            "load_balancer_certificate",
+            &config.load_balancer_certificate,
+        ),
+        ("local_artifacts_dir", &config.local_artifacts_dir),
+    ]
+    .into_iter()
+    .filter_map(|(option, path)| {
+        let path = path.as_ref()?;
+        (!path_exists(path)).then(|| format!("{} {} does not exist", option, path))
+    })
+    .collect()
+}
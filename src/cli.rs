@@ -0,0 +1,137 @@
+//! # CLI Module
+//!
+//! This module defines the command-line interface of `server_forge`, parsed with `clap`.
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+/// The version of ServerForge, with the git commit and the date of the build.
+pub const VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("SERVER_FORGE_GIT_COMMIT"),
+    ", built ",
+    env!("SERVER_FORGE_BUILD_DATE"),
+    ")"
+);
+
+/// ServerForge - A robust server setup and maintenance tool
+#[derive(Parser, Debug)]
+#[command(version = VERSION, about)]
+pub struct Cli {
+    /// The command to run (defaults to `deploy`)
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Comma-separated SSH destinations to configure instead of the local machine
+    /// (e.g. `web1,root@10.0.0.5`)
+    #[arg(
+        long,
+        value_delimiter = ',',
+        global = true,
+        conflicts_with = "inventory"
+    )]
+    pub hosts: Vec<String>,
+
+    /// Inventory file (YAML or TOML) describing the hosts to configure and their settings
+    #[arg(long, global = true)]
+    pub inventory: Option<String>,
+
+    /// Keep going when an optional phase (such as monitoring, backups or a single
+    /// application) fails, rolling back only that phase and reporting the failures at the end
+    #[arg(long, global = true)]
+    pub continue_on_error: bool,
+
+    /// Run every phase, including those whose configuration is unchanged since the last run
+    #[arg(long, global = true)]
+    pub force: bool,
+
+    /// Only show warnings and errors, without the progress of the phases
+    #[arg(long, short, global = true)]
+    pub quiet: bool,
+
+    /// Roll back the uncommitted changes of an interrupted previous run without asking
+    #[arg(long, global = true, conflicts_with = "ignore_recovery")]
+    pub recover: bool,
+
+    /// Leave the uncommitted changes of an interrupted previous run in place without asking
+    #[arg(long, global = true)]
+    pub ignore_recovery: bool,
+
+    /// Encrypt the configuration saved on each host with the key of `SERVERFORGE_CONFIG_KEY`
+    /// or the file named by `SERVERFORGE_CONFIG_KEY_FILE`, instead of saving it in plaintext
+    #[arg(long, global = true)]
+    pub encrypt_config: bool,
+
+    /// Environment file to load `SERVER_FORGE_*` options and secrets from, instead of a
+    /// `.env` file in the current directory; variables already set take precedence
+    #[arg(long, global = true)]
+    pub env_file: Option<String>,
+
+    /// Number of package installs to run at once on each host, overriding the
+    /// `max_concurrent_installs` option; package managers lock their database, so installs
+    /// beyond the first mostly wait for the lock
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..), global = true)]
+    pub max_concurrent_installs: Option<u16>,
+
+    /// Number of hosts to configure concurrently
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..), global = true)]
+    pub parallelism: u16,
+}
+
+/// The subcommands of `server_forge`.
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Set up the local machine, or the hosts given with `--hosts` or `--inventory`
+    Deploy {
+        /// Configuration file (JSON, TOML or YAML) whose options take precedence over the
+        /// `SERVER_FORGE_*` environment variables
+        #[arg(long)]
+        config: Option<String>,
+    },
+    /// Write what `deploy` would do as an Ansible playbook or cloud-init user-data,
+    /// without changing anything
+    Export {
+        /// The format to export
+        #[arg(long, value_enum, default_value_t = ExportFormat::Ansible)]
+        format: ExportFormat,
+
+        /// The file to write the export to (defaults to standard output)
+        #[arg(long, short)]
+        output: Option<String>,
+
+        /// Write the secrets the setup passes to commands, such as generated passwords and
+        /// Vault credentials, into the export in plaintext, instead of refusing to export
+        #[arg(long)]
+        include_secrets: bool,
+    },
+    /// Report whether the local machine, or the hosts given with `--hosts` or `--inventory`,
+    /// still match the configuration saved by their last run, without changing anything
+    Status,
+    /// Roll back the changes of the last run on the local machine, or on the hosts given
+    /// with `--hosts` or `--inventory`
+    Rollback {
+        /// List what the rollback would change, without changing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Only roll back the changes made since this snapshot of the run
+        #[arg(long)]
+        snapshot: Option<usize>,
+    },
+    /// Check a configuration file for mistakes against the local machine, without changing
+    /// anything
+    Check {
+        /// The configuration file to check (JSON, TOML or YAML)
+        #[arg(long)]
+        config: String,
+    },
+}
+
+/// The formats `export` can write.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    /// An Ansible playbook with one play per host
+    Ansible,
+    /// A cloud-init `#cloud-config` document for a single configuration
+    CloudInit,
+}
@@ -0,0 +1,163 @@
+//! # Concurrency Module
+//!
+//! This module bounds how many operations of a kind run at once on the host being
+//! configured.
+//!
+//! Package managers hold a global lock on their database while they work (dpkg's lock, or
+//! rpm's), so package installs cannot truly run in parallel: a second install waits for the
+//! lock or fails. Installs therefore run one at a time by default. Downloads and other
+//! independent steps take no such lock, and several of them run at once.
+//!
+//! Like the current runner (see `runner::with_runner`), the limits are tracked per thread:
+//! `with_limits` sets them for a run, and `run_concurrently` passes them, the runner and the
+//! file system on to the threads it starts.
+
+use crate::config::Config;
+use crate::filesystem::{current_filesystem, with_filesystem};
+use crate::runner::{current_runner, with_runner};
+use crate::utils::{command_env, with_command_env};
+use std::cell::Cell;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// How many package installs run at once by default.
+pub const DEFAULT_MAX_CONCURRENT_INSTALLS: usize = 1;
+
+/// How many downloads run at once by default.
+pub const DEFAULT_MAX_CONCURRENT_DOWNLOADS: usize = 4;
+
+/// How many operations of each kind may run at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Package-manager operations, which wait on the package database lock
+    pub installs: usize,
+    /// Downloads and other independent steps
+    pub downloads: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            installs: DEFAULT_MAX_CONCURRENT_INSTALLS,
+            downloads: DEFAULT_MAX_CONCURRENT_DOWNLOADS,
+        }
+    }
+}
+
+impl Limits {
+    /// Returns the limits of a configuration. A limit of 0 is taken as 1.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - A reference to the `Config` struct containing the limits
+    pub fn from_config(config: &Config) -> Self {
+        Limits {
+            installs: config.max_concurrent_installs.max(1),
+            downloads: config.max_concurrent_downloads.max(1),
+        }
+    }
+}
+
+thread_local! {
+    static CURRENT_LIMITS: Cell<Limits> = Cell::new(Limits::default());
+}
+
+/// Returns the limits of the current thread (`Limits::default()` unless set).
+pub fn current_limits() -> Limits {
+    CURRENT_LIMITS.with(Cell::get)
+}
+
+/// Runs `f` with `limits` as the current thread's limits, restoring the previous ones
+/// afterwards.
+///
+/// # Arguments
+///
+/// * `limits` - The limits to use while `f` runs
+/// * `f` - The function to run
+///
+/// # Returns
+///
+/// Returns the result of `f`.
+pub fn with_limits<T>(limits: Limits, f: impl FnOnce() -> T) -> T {
+    /// Restores the previous limits when dropped, even if `f` panics.
+    struct Restore(Limits);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CURRENT_LIMITS.with(|current| current.set(self.0));
+        }
+    }
+
+    let _restore = Restore(CURRENT_LIMITS.with(|current| current.replace(limits)));
+    f()
+}
+
+/// Runs a task for every item, with at most `limit` tasks running at a time.
+///
+/// With a limit of 1 the tasks run in order on the current thread, stopping at the first
+/// error. Otherwise they run on worker threads, which use the current runner, file system,
+/// limits, command environment and log host; once a task fails no new task is started, but the running ones complete.
+///
+/// # Arguments
+///
+/// * `items` - The items to run the task for
+/// * `limit` - How many tasks may run at a time
+/// * `task` - The task to run for every item
+///
+/// # Returns
+///
+/// Returns `Ok(())` if every task succeeds, or an error with the messages of the failed tasks.
+pub fn run_concurrently<T: Sync>(
+    items: &[T],
+    limit: usize,
+    task: impl Fn(&T) -> Result<(), Box<dyn Error>> + Sync,
+) -> Result<(), Box<dyn Error>> {
+    let workers = limit.min(items.len());
+    if workers <= 1 {
+        return items.iter().try_for_each(task);
+    }
+
+    let runner = current_runner();
+    let filesystem = current_filesystem();
+    let limits = current_limits();
+    let env = command_env();
+    let host = log_mdc::get("host", |host| host.map(str::to_string));
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let errors = Mutex::new(Vec::new());
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                if let Some(host) = &host {
+                    log_mdc::insert("host", host);
+                }
+                with_runner(runner.clone(), || {
+                    with_filesystem(filesystem.clone(), || {
+                        with_limits(limits, || {
+                            with_command_env(env.clone(), || {
+                                while !failed.load(Ordering::SeqCst) {
+                                    let Some(item) = items.get(next.fetch_add(1, Ordering::SeqCst))
+                                    else {
+                                        break;
+                                    };
+                                    if let Err(e) = task(item) {
+                                        failed.store(true, Ordering::SeqCst);
+                                        errors.lock().unwrap().push(e.to_string());
+                                    }
+                                }
+                            })
+                        })
+                    })
+                });
+            });
+        }
+    });
+
+    let errors = errors.into_inner().unwrap();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("; ").into())
+    }
+}
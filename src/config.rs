@@ -2,48 +2,835 @@
 //!
 //! This module defines the `Config` struct, which represents the configuration
 //! for the server setup and maintenance tool. It includes various settings
-//! such as the Linux distribution, server role, security level, and deployment options.
+//! such as the Linux distribution, server roles, security level, and deployment options.
 //!
 //! The `Config` struct implements `Serialize` and `Deserialize` traits from serde,
-//! allowing for easy serialization and deserialization of the configuration.
+//! allowing for easy serialization and deserialization of the configuration. Two
+//! configurations can be compared with `Config::diff`, and a configuration can be read
+//! from a file with `Config::from_file` or from `SERVER_FORGE_*` environment variables with
+//! `Config::from_env`.
 
-use serde::{Deserialize, Serialize};
+use crate::concurrency;
+use crate::encryption;
+use crate::firewall;
+use crate::maintenance;
+use crate::monitoring;
+use crate::setup;
+use crate::updates;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The prefix of the environment variables read by `Config::from_env`.
+pub const ENV_PREFIX: &str = "SERVER_FORGE_";
+
+/// Short environment variable names, without `ENV_PREFIX`, with the options they set.
+const ENV_ALIASES: [(&str, &str); 4] = [
+    ("DISTRO", "linux_distro"),
+    ("ROLE", "server_roles"),
+    ("ROLES", "server_roles"),
+    ("APPS", "deployed_apps"),
+];
+
+/// Former names of configuration options, with the options they now set.
+pub const OPTION_ALIASES: [(&str, &str); 1] = [("server_role", "server_roles")];
 
 /// Represents the configuration for the server setup and maintenance tool.
 ///
 /// This struct contains all the necessary settings and options for configuring
 /// a server, including the operating system, security settings, and deployment options.
 #[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct Config {
     /// The Linux distribution being used (e.g., "ubuntu", "centos", "fedora")
     pub linux_distro: String,
 
-    /// The role of the server (e.g., "web", "database", "application")
-    pub server_role: String,
+    /// The roles of the server. Configurations written with a single `server_role` (e.g.,
+    /// "web") are also accepted.
+    #[serde(alias = "server_role", deserialize_with = "deserialize_server_roles")]
+    pub server_roles: Vec<ServerRole>,
+
+    /// The hostname to give the server; `None` keeps the current hostname
+    pub hostname: Option<String>,
+
+    /// The name of a non-root administrator to create, with sudo rights and SSH key login
+    pub admin_user: Option<String>,
+
+    /// The SSH public key installed for `admin_user` (e.g., "ssh-ed25519 AAAA... me@laptop")
+    pub admin_ssh_key: Option<String>,
+
+    /// The port sshd is moved to; the firewall rule for SSH and the Fail2Ban SSH jail use it
+    /// too
+    pub ssh_port: u16,
+
+    /// The size of the swap file to create at `/swapfile`, in megabytes; `None` leaves swap
+    /// unconfigured
+    pub swap_size_mb: Option<u64>,
+
+    /// The timezone to set (e.g., "Europe/Berlin"); `None` leaves the system timezone untouched
+    pub timezone: Option<String>,
+
+    /// The essential packages to install; `None` installs the default list for the host's
+    /// package manager
+    pub essential_packages: Option<Vec<String>>,
 
     /// The desired security level (e.g., "basic", "intermediate", "advanced")
     pub security_level: String,
 
+    /// The schedule for security scans (e.g., "daily", "weekly", "monthly", or a cron expression)
+    pub security_scan_schedule: String,
+
     /// Whether to enable monitoring on the server
     pub monitoring: bool,
 
+    /// Additional URLs (probed over HTTP) or `host:port` addresses (probed over TCP) to monitor for uptime
+    pub uptime_probe_targets: Vec<String>,
+
+    /// The port Grafana listens on
+    pub grafana_port: u16,
+
+    /// The port Prometheus listens on
+    pub prometheus_port: u16,
+
+    /// How long Prometheus keeps metrics, as a Prometheus duration (e.g., "30d" or "12w")
+    pub prometheus_retention: String,
+
+    /// The directory Prometheus stores its metrics in; `None` keeps the default of the
+    /// installation (`/var/lib/prometheus/metrics2` for the apt package, `/var/lib/prometheus`
+    /// for source installs)
+    pub prometheus_storage_path: Option<String>,
+
+    /// The address Prometheus, Node Exporter and Grafana listen on (default "0.0.0.0", all
+    /// interfaces). Restricting it to a private or VPN interface keeps the monitoring
+    /// endpoints off the public network.
+    pub monitoring_bind_address: String,
+
+    /// Whether to open the Grafana and Prometheus ports in the firewall
+    pub expose_monitoring: bool,
+
+    /// Whether to enable centralized logging with Loki and Promtail (requires monitoring)
+    pub enable_logs: bool,
+
     /// The frequency of backups (e.g., "hourly", "daily", "weekly")
     pub backup_frequency: String,
 
+    /// Glob patterns or absolute paths excluded from backups (e.g., "*.log", "/var/www/cache")
+    pub backup_excludes: Vec<String>,
+
     /// A list of applications to be deployed on the server
     pub deployed_apps: Vec<String>,
 
-    /// A list of custom firewall rules to be applied
+    /// The servers HAProxy balances traffic across, as `host:port` (used by the "haproxy" app)
+    pub load_balancer_backends: Vec<String>,
+
+    /// Path of a PEM file (certificate and key) for HAProxy; when set, HAProxy also
+    /// terminates TLS on port 443
+    pub load_balancer_certificate: Option<String>,
+
+    /// Whether to open the RabbitMQ management port (15672) in the firewall (used by the
+    /// "rabbitmq" app)
+    pub expose_rabbitmq_management: bool,
+
+    /// The memory limit of Memcached in megabytes (default 64, used by the "memcached" app)
+    pub memcached_memory_mb: Option<u64>,
+
+    /// The address Memcached listens on (default "127.0.0.1", used by the "memcached" app)
+    pub memcached_bind_address: Option<String>,
+
+    /// The address OpenSearch listens on (default "127.0.0.1", used by the "opensearch" app)
+    pub opensearch_bind_address: Option<String>,
+
+    /// The domain name deployed applications are served under. When set and a web server
+    /// (nginx or apache) is deployed alongside an application server (nodejs or python),
+    /// the web server proxies this domain to the application.
+    pub app_domain: Option<String>,
+
+    /// Applications deployed from Git repositories and run as systemd services
+    pub git_apps: Vec<GitApp>,
+
+    /// Commands run before and after deploying applications of `deployed_apps`, by
+    /// application name (the hooks of Git applications are part of their `git_apps` entry)
+    pub deploy_hooks: BTreeMap<String, DeployHooks>,
+
+    /// What `deployment::setup_web_server_config` does with the default nginx or Apache
+    /// site (default: replace it)
+    pub web_server_config_mode: WebServerConfigMode,
+
+    /// A list of custom firewall rules to be applied: ports (e.g. "80/tcp"), port ranges
+    /// (e.g. "6000:6007/udp"), service names, or ports open to some addresses only (e.g.
+    /// "from 10.0.0.0/8 to any port 5432")
     pub custom_firewall_rules: Vec<String>,
 
     /// The schedule for automatic updates (e.g., "daily", "weekly", "monthly")
     pub update_schedule: String,
 
+    /// Whether automatic updates may reboot the server when an update (e.g., a new kernel)
+    /// requires it
+    pub auto_reboot: bool,
+
+    /// The time of day automatic reboots happen at (e.g., "03:00"), rather than right after
+    /// the update
+    pub auto_reboot_time: Option<String>,
+
+    /// Packages automatic updates must never upgrade (e.g., the kernel or a pinned database)
+    pub update_blacklist: Vec<String>,
+
+    /// The window backups, security scans and automatic reboots are scheduled in, one after
+    /// the other; `None` keeps each at its own fixed time
+    pub maintenance_window: Option<MaintenanceWindow>,
+
+    /// Whether to install ClamAV and schedule a weekly full antivirus scan
+    pub enable_clamav: bool,
+
     /// Whether to use containerization for deployments
     pub use_containers: bool,
 
     /// Whether to use Kubernetes for container orchestration
     pub use_kubernetes: bool,
+
+    /// The mechanism used to run scheduled jobs such as backups and security scans
+    pub scheduler: Scheduler,
+
+    /// The email address notifications (e.g., backup failures) are sent to
+    pub admin_email: Option<String>,
+
+    /// Whether to also send a notification when a backup succeeds
+    pub backup_notify_on_success: bool,
+
+    /// Base URL of an internal mirror to download release binaries and repositories from
+    /// instead of the public internet
+    pub download_base_url: Option<String>,
+
+    /// Directory of pre-fetched release archives; downloads are skipped for files found here
+    pub local_artifacts_dir: Option<String>,
+
+    /// How many package installs run at once on a host. Package managers lock their
+    /// database, so a second install only waits for the lock (or fails), and the default is 1
+    pub max_concurrent_installs: usize,
+
+    /// How many downloads run at once on a host
+    pub max_concurrent_downloads: usize,
+
+    /// Proxy URL for HTTP traffic (e.g. "http://proxy.internal:3128"). It applies to every
+    /// command run by server_forge and to apt.
+    pub http_proxy: Option<String>,
+
+    /// Proxy URL for HTTPS traffic, applied like `http_proxy`
+    pub https_proxy: Option<String>,
+
+    /// The HashiCorp Vault generated passwords are kept in; `None` saves them to files
+    /// under `/root`
+    pub vault: Option<VaultConfig>,
+
+    /// Passwords, tokens and webhook URLs, written as top-level options but never serialized
+    #[serde(flatten)]
+    pub secrets: Secrets,
+}
+
+/// The options holding credentials.
+///
+/// They are set like any other option, in configuration files, inventories and
+/// `SERVER_FORGE_*` variables, but are skipped when a `Config` is serialized, so they never
+/// reach the saved configuration or an export. `Config::options` includes them.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Secrets {
+    /// Grafana admin password (a random password is generated if unset)
+    #[serde(skip_serializing)]
+    pub grafana_admin_password: Option<String>,
+
+    /// A webhook URL (Slack-compatible) notifications are posted to, such as backup failures
+    /// and the outcome of each setup run
+    #[serde(skip_serializing)]
+    pub notification_webhook: Option<String>,
+
+    /// The token to authenticate to `vault` with
+    #[serde(skip_serializing)]
+    pub vault_token: Option<String>,
+
+    /// The AppRole secret ID to log in to `vault` with when there is no token
+    #[serde(skip_serializing)]
+    pub vault_secret_id: Option<String>,
+}
+
+impl Secrets {
+    /// Returns the secret options with their values, by name.
+    pub fn options(&self) -> Map<String, Value> {
+        let values = [
+            &self.grafana_admin_password,
+            &self.notification_webhook,
+            &self.vault_token,
+            &self.vault_secret_id,
+        ];
+        SECRET_FIELDS
+            .iter()
+            .zip(values)
+            .map(|(name, value)| (name.to_string(), Value::from(value.clone())))
+            .collect()
+    }
+}
+
+/// A role a server plays, which decides what is backed up and which modules are installed.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerRole {
+    /// Serves websites
+    Web,
+    /// Hosts databases
+    Database,
+    /// Runs applications
+    Application,
+}
+
+impl fmt::Display for ServerRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ServerRole::Web => "web",
+            ServerRole::Database => "database",
+            ServerRole::Application => "application",
+        })
+    }
+}
+
+impl FromStr for ServerRole {
+    type Err = String;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "web" => Ok(ServerRole::Web),
+            "database" => Ok(ServerRole::Database),
+            "application" => Ok(ServerRole::Application),
+            _ => Err(format!("Unknown server role '{}'", role)),
+        }
+    }
+}
+
+/// Deserializes `server_roles` from a list of roles, or from a single role as written by
+/// older configurations, where an empty string means no role.
+fn deserialize_server_roles<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<ServerRole>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Roles {
+        One(String),
+        Many(Vec<ServerRole>),
+    }
+
+    match Roles::deserialize(deserializer)? {
+        Roles::One(role) if role.is_empty() => Ok(Vec::new()),
+        Roles::One(role) => Ok(vec![role.parse().map_err(D::Error::custom)?]),
+        Roles::Many(roles) => Ok(roles),
+    }
+}
+
+/// The mechanism used to run scheduled jobs.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Scheduler {
+    /// Write a crontab entry to `/etc/cron.d/`
+    #[default]
+    Cron,
+
+    /// Install a systemd `.service` + `.timer` pair
+    SystemdTimer,
+}
+
+/// An application deployed from a Git repository.
+///
+/// The repository is cloned into `target_dir` (or updated if it is already there), built
+/// with `build_command` if given, and run with `run_command` by a systemd service named
+/// after the last component of `target_dir`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GitApp {
+    /// The URL of the repository to clone
+    pub repo_url: String,
+
+    /// The branch to deploy
+    #[serde(default = "default_git_branch")]
+    pub branch: String,
+
+    /// The directory to clone the repository into (e.g., "/srv/shop")
+    pub target_dir: String,
+
+    /// A shell command run in `target_dir` after each clone or pull (e.g., "npm ci")
+    #[serde(default)]
+    pub build_command: Option<String>,
+
+    /// The shell command that runs the application, from `target_dir`
+    pub run_command: String,
+
+    /// Commands run before and after the deployment; the `post_deploy` commands run in
+    /// `target_dir`
+    #[serde(flatten)]
+    pub hooks: DeployHooks,
+}
+
+/// Shell commands run around the deployment of an application, such as database
+/// migrations after the application is updated.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct DeployHooks {
+    /// Commands run, in order, before the application is deployed; the first failing
+    /// command aborts the deployment
+    pub pre_deploy: Vec<String>,
+
+    /// Commands run, in order, once the application is deployed (e.g., "php artisan migrate")
+    pub post_deploy: Vec<String>,
+
+    /// What happens when a `post_deploy` command fails
+    pub post_deploy_failure: HookFailurePolicy,
+}
+
+/// What happens to a deployment when one of its `post_deploy` commands fails.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HookFailurePolicy {
+    /// The deployment fails and its changes are rolled back
+    #[default]
+    Abort,
+
+    /// A warning is logged and the remaining commands are skipped, but the deployment succeeds
+    Warn,
+}
+
+/// How the web server configuration treats the default site of nginx or Apache, which may
+/// already serve a site of the user's.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebServerConfigMode {
+    /// Overwrite the default site
+    #[default]
+    Replace,
+
+    /// Leave an existing default site alone, writing it only if there is none
+    KeepExisting,
+
+    /// Leave the default site alone and add a `serverforge` site next to it
+    AddVhost,
+}
+
+/// The time of the day, and the days of the week, maintenance jobs may run in.
+///
+/// See the `maintenance` module for how the jobs are staggered within the window.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MaintenanceWindow {
+    /// The time the window opens at (e.g., "02:00")
+    pub start: String,
+
+    /// The days the window is open on (e.g., ["sat", "sun"]); every day when empty
+    #[serde(default)]
+    pub days: Vec<String>,
+}
+
+/// The HashiCorp Vault KV (version 2) secrets engine generated passwords are kept in.
+///
+/// A token is taken from the `vault_token` secret or the `VAULT_TOKEN` environment variable;
+/// otherwise one is obtained by logging in with the AppRole `role_id` and the
+/// `vault_secret_id` secret.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct VaultConfig {
+    /// The address of the Vault server (e.g., "https://vault.internal:8200")
+    pub address: String,
+
+    /// The path the KV secrets engine is mounted at
+    #[serde(default = "default_vault_mount")]
+    pub mount: String,
+
+    /// The path within the secrets engine secrets are kept under
+    #[serde(default = "default_vault_path")]
+    pub path: String,
+
+    /// The AppRole role ID to log in with when there is no token
+    #[serde(default)]
+    pub role_id: Option<String>,
+}
+
+fn default_vault_mount() -> String {
+    String::from("secret")
+}
+
+fn default_vault_path() -> String {
+    String::from("serverforge")
+}
+
+fn default_git_branch() -> String {
+    String::from("main")
+}
+
+/// The options of `Secrets`, whose values are not shown when they change.
+const SECRET_FIELDS: [&str; 4] = [
+    "grafana_admin_password",
+    "notification_webhook",
+    "vault_token",
+    "vault_secret_id",
+];
+
+/// An option that differs between two configurations, as returned by `Config::diff`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    /// The name of the option (e.g., "deployed_apps")
+    pub field: String,
+    /// The value in the earlier configuration
+    pub old: Value,
+    /// The value in the later configuration
+    pub new: Value,
+}
+
+impl fmt::Display for FieldChange {
+    /// Formats the change as, e.g., "grafana_port: 3000 → 3001", or
+    /// "deployed_apps: added redis; removed php" for lists. Secret values are not shown.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if SECRET_FIELDS.contains(&self.field.as_str()) {
+            return write!(f, "{}: changed", self.field);
+        }
+        match (&self.old, &self.new) {
+            (Value::Array(old), Value::Array(new)) => {
+                let list = |values: Vec<&Value>| -> String {
+                    let values: Vec<String> = values.into_iter().map(display_value).collect();
+                    values.join(", ")
+                };
+                let added = list(new.iter().filter(|value| !old.contains(value)).collect());
+                let removed = list(old.iter().filter(|value| !new.contains(value)).collect());
+                let changes: Vec<String> = [("added", added), ("removed", removed)]
+                    .into_iter()
+                    .filter(|(_, values)| !values.is_empty())
+                    .map(|(change, values)| format!("{} {}", change, values))
+                    .collect();
+                if changes.is_empty() {
+                    write!(f, "{}: reordered", self.field)
+                } else {
+                    write!(f, "{}: {}", self.field, changes.join("; "))
+                }
+            }
+            (old, new) => write!(
+                f,
+                "{}: {} → {}",
+                self.field,
+                display_value(old),
+                display_value(new)
+            ),
+        }
+    }
+}
+
+/// Formats a configuration value for display, showing strings without quotes.
+fn display_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::from("(none)"),
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+impl Config {
+    /// Checks the options that cannot be checked when the configuration is parsed, so that a
+    /// mistake is reported before the host is changed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the first invalid option or entry of `custom_firewall_rules`,
+    /// and why it is invalid.
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        match self.validation_errors().into_iter().next() {
+            Some(error) => Err(error.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Lists every mistake `validate` checks for, rather than only the first.
+    ///
+    /// # Returns
+    ///
+    /// Returns a description of every invalid entry of `custom_firewall_rules`, in order,
+    /// followed by the `deploy_hooks` entries of applications that are not deployed, an
+    /// invalid `ssh_port`, the invalid update options (schedule, reboot time and blacklist),
+    /// maintenance window, Vault address and Prometheus storage options.
+    pub fn validation_errors(&self) -> Vec<String> {
+        let mut errors: Vec<String> = self
+            .custom_firewall_rules
+            .iter()
+            .enumerate()
+            .filter_map(|(index, rule)| {
+                let error = firewall::validate_firewall_rule(rule).err()?;
+                Some(format!(
+                    "Invalid custom firewall rule #{} '{}': {}",
+                    index + 1,
+                    rule,
+                    error
+                ))
+            })
+            .collect();
+
+        for app in self.deploy_hooks.keys() {
+            if !self.deployed_apps.contains(app) {
+                errors.push(format!(
+                    "Invalid deploy_hooks entry '{}': the application is not in deployed_apps",
+                    app
+                ));
+            }
+        }
+        if self.ssh_port == 0 {
+            errors.push(String::from(
+                "Invalid ssh_port 0: expected a port from 1 to 65535",
+            ));
+        }
+        if let Err(e) = updates::update_interval_days(&self.update_schedule) {
+            errors.push(e.to_string());
+        }
+        if let Some(time) = &self.auto_reboot_time {
+            if !updates::is_time_of_day(time) {
+                errors.push(format!(
+                    "Invalid auto_reboot_time '{}': expected a time such as 03:00",
+                    time
+                ));
+            }
+        }
+        for (index, package) in self.update_blacklist.iter().enumerate() {
+            if package.trim().is_empty() {
+                errors.push(format!(
+                    "Invalid update_blacklist entry #{}: the package name is empty",
+                    index + 1
+                ));
+            }
+        }
+        if let Some(window) = &self.maintenance_window {
+            errors.extend(maintenance::window_errors(window));
+        }
+        if let Some(vault) = &self.vault {
+            if !vault.address.starts_with("http://") && !vault.address.starts_with("https://") {
+                errors.push(format!(
+                    "Invalid vault address '{}': expected an http:// or https:// URL",
+                    vault.address
+                ));
+            }
+        }
+        if !monitoring::is_prometheus_duration(&self.prometheus_retention) {
+            errors.push(format!(
+                "Invalid prometheus_retention '{}': expected a duration such as 15d or 12w",
+                self.prometheus_retention
+            ));
+        }
+        if let Some(path) = &self.prometheus_storage_path {
+            if !path.starts_with('/') {
+                errors.push(format!(
+                    "Invalid prometheus_storage_path '{}': expected an absolute path",
+                    path
+                ));
+            }
+        }
+        errors
+    }
+
+    /// Reads a configuration file, written in JSON, TOML or YAML (chosen by the `.json` or
+    /// `.toml` extension, YAML otherwise).
+    ///
+    /// Options missing from the file keep their default value, and options written under a
+    /// former name (see `OPTION_ALIASES`) are renamed.
+    ///
+    /// A file encrypted by the `encryption` module (such as a configuration saved with
+    /// `--encrypt-config`) is decrypted with the key of `encryption::config_key`; its format
+    /// is chosen by the extension before `.enc` (e.g., `config.json.enc` is JSON).
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the configuration file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read, decrypted or parsed, names an unknown
+    /// option, or gives an option a value of the wrong type.
+    pub fn from_file(path: &str) -> Result<Config, Box<dyn Error>> {
+        Config::default().with_file(path)
+    }
+
+    /// Reads a configuration file as `from_file` does, over this configuration: the options
+    /// set in the file replace those of this configuration, and the others are kept. This
+    /// layers a `--config` file over the `SERVER_FORGE_*` environment variables.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the configuration file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read, decrypted or parsed, names an unknown
+    /// option, or gives an option a value of the wrong type.
+    pub fn with_file(&self, path: &str) -> Result<Config, Box<dyn Error>> {
+        let mut contents = std::fs::read(path)
+            .map_err(|e| format!("Failed to read configuration {}: {}", path, e))?;
+        let invalid = |e: &dyn fmt::Display| format!("Invalid configuration {}: {}", path, e);
+        let mut format_path = Path::new(path);
+        if encryption::is_encrypted(&contents) {
+            let key = encryption::require_config_key(&format!("read {}", path))?;
+            contents = encryption::decrypt(&key, &contents)
+                .map_err(|e| format!("Cannot decrypt {}: {}", path, e))?;
+            if format_path.extension().is_some_and(|ext| ext == "enc") {
+                format_path = Path::new(format_path.file_stem().unwrap_or_default());
+            }
+        }
+        let contents = String::from_utf8(contents).map_err(|e| invalid(&e))?;
+
+        let options: Value = match format_path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&contents).map_err(|e| invalid(&e))?,
+            Some("toml") => toml::from_str(&contents).map_err(|e| invalid(&e))?,
+            _ => serde_yaml::from_str(&contents).map_err(|e| invalid(&e))?,
+        };
+        let Value::Object(options) = options else {
+            return Err(invalid(&"expected a map of options").into());
+        };
+
+        let mut merged = self.options()?;
+        for (name, value) in options {
+            let name = OPTION_ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == name)
+                .map_or(name, |(_, option)| option.to_string());
+            if !merged.contains_key(&name) {
+                return Err(invalid(&format!("unknown option '{}'", name)).into());
+            }
+            merged.insert(name, value);
+        }
+        Ok(serde_json::from_value(Value::Object(merged)).map_err(|e| invalid(&e))?)
+    }
+
+    /// Returns every option with its value, by name, including the `secrets` that
+    /// serializing the configuration leaves out.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration cannot be serialized.
+    pub fn options(&self) -> Result<Map<String, Value>, Box<dyn Error>> {
+        let Value::Object(mut options) = serde_json::to_value(self)? else {
+            return Err("Configuration is not an object".into());
+        };
+        options.extend(self.secrets.options());
+        Ok(options)
+    }
+
+    /// Lists the options that differ between this configuration and `other`, in
+    /// alphabetical order.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The later configuration to compare with (e.g., the one about to be applied)
+    ///
+    /// # Returns
+    ///
+    /// Returns a `FieldChange` for every option with a different value in `other`.
+    pub fn diff(&self, other: &Config) -> Vec<FieldChange> {
+        let fields = |config: &Config| config.options().unwrap_or_default();
+        let old = fields(self);
+        fields(other)
+            .into_iter()
+            .filter_map(|(field, new)| {
+                let old = old.get(&field).cloned().unwrap_or(Value::Null);
+                (old != new).then_some(FieldChange { field, old, new })
+            })
+            .collect()
+    }
+
+    /// Reads a configuration from the `SERVER_FORGE_*` environment variables, for running
+    /// without a configuration file or prompts (e.g. from a Dockerfile or a systemd unit).
+    ///
+    /// Every option can be set with `SERVER_FORGE_` followed by its name in upper case,
+    /// e.g. `SERVER_FORGE_SECURITY_LEVEL=advanced` or `SERVER_FORGE_MONITORING=true`. Options
+    /// that are not set keep their default value. These shorter names are also accepted:
+    ///
+    /// | Variable               | Option          | Example         |
+    /// |------------------------|-----------------|-----------------|
+    /// | `SERVER_FORGE_DISTRO`  | `linux_distro`  | `ubuntu`        |
+    /// | `SERVER_FORGE_ROLE`    | `server_roles`  | `web`           |
+    /// | `SERVER_FORGE_ROLES`   | `server_roles`  | `web,database`  |
+    /// | `SERVER_FORGE_APPS`    | `deployed_apps` | `nginx,php`     |
+    ///
+    /// List options take comma-separated values, or a YAML flow sequence such as
+    /// `[{repo_url: ..., target_dir: ..., run_command: ...}]` for `git_apps`. Numbers and
+    /// booleans are written as in YAML, and an empty value unsets an optional option.
+    ///
+    /// # Returns
+    ///
+    /// Returns the configuration, or `None` if no `SERVER_FORGE_*` variable is set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the variable if it does not match an option or its value is
+    /// invalid for the option.
+    pub fn from_env() -> Result<Option<Config>, Box<dyn Error>> {
+        Config::from_vars(std::env::vars())
+    }
+
+    /// Reads a configuration from `SERVER_FORGE_*` variables, as `from_env` does with the
+    /// environment. Variables without the prefix are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `vars` - The variables, as `(name, value)` pairs
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the variable if it does not match an option or its value is
+    /// invalid for the option.
+    pub fn from_vars(
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Option<Config>, Box<dyn Error>> {
+        let defaults = Config::default().options()?;
+        let mut options = defaults.clone();
+        let mut found = false;
+
+        for (name, raw) in vars {
+            let Some(suffix) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let option = ENV_ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == suffix)
+                .map_or_else(|| suffix.to_lowercase(), |(_, option)| option.to_string());
+            let default = defaults
+                .get(&option)
+                .ok_or_else(|| format!("Unknown configuration variable {}", name))?;
+
+            let value = env_value(default, &raw);
+            let parses = |value: &Value| {
+                let single = Map::from_iter([(option.clone(), value.clone())]);
+                serde_json::from_value::<Config>(Value::Object(single))
+            };
+            let value = match parses(&value) {
+                Ok(_) => value,
+                // A string that happens to look like a number or a boolean
+                Err(_) if parses(&Value::String(raw.clone())).is_ok() => Value::String(raw),
+                Err(e) => return Err(format!("Invalid value for {}: {}", name, e).into()),
+            };
+            options.insert(option, value);
+            found = true;
+        }
+
+        if !found {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_value(Value::Object(options))?))
+    }
+}
+
+/// Converts the value of a `SERVER_FORGE_*` variable to the JSON form of an option, based
+/// on the option's default value.
+fn env_value(default: &Value, raw: &str) -> Value {
+    match default {
+        Value::Array(_) if !raw.trim_start().starts_with('[') => Value::Array(
+            raw.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| Value::String(item.to_string()))
+                .collect(),
+        ),
+        Value::String(_) => Value::String(raw.to_string()),
+        _ => serde_yaml::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string())),
+    }
 }
 
 /// Provides default values for the `Config` struct.
@@ -52,15 +839,58 @@ impl Default for Config {
     fn default() -> Self {
         Config {
             linux_distro: String::from("ubuntu"),
-            server_role: String::new(),
+            server_roles: Vec::new(),
+            hostname: None,
+            admin_user: None,
+            admin_ssh_key: None,
+            ssh_port: setup::DEFAULT_SSH_PORT,
+            swap_size_mb: None,
+            timezone: None,
+            essential_packages: None,
             security_level: String::new(),
+            security_scan_schedule: String::from("weekly"),
             monitoring: false,
+            uptime_probe_targets: Vec::new(),
+            grafana_port: 3000,
+            prometheus_port: 9090,
+            prometheus_retention: String::from(monitoring::DEFAULT_PROMETHEUS_RETENTION),
+            prometheus_storage_path: None,
+            monitoring_bind_address: String::from("0.0.0.0"),
+            expose_monitoring: false,
+            enable_logs: false,
             backup_frequency: String::from("daily"),
+            backup_excludes: Vec::new(),
             deployed_apps: Vec::new(),
+            load_balancer_backends: Vec::new(),
+            load_balancer_certificate: None,
+            expose_rabbitmq_management: false,
+            memcached_memory_mb: None,
+            memcached_bind_address: None,
+            opensearch_bind_address: None,
+            app_domain: None,
+            git_apps: Vec::new(),
+            deploy_hooks: BTreeMap::new(),
+            web_server_config_mode: WebServerConfigMode::Replace,
             custom_firewall_rules: Vec::new(),
             update_schedule: String::from("weekly"),
+            auto_reboot: false,
+            auto_reboot_time: None,
+            update_blacklist: Vec::new(),
+            maintenance_window: None,
+            enable_clamav: false,
             use_containers: false,
             use_kubernetes: false,
+            scheduler: Scheduler::Cron,
+            admin_email: None,
+            backup_notify_on_success: false,
+            download_base_url: None,
+            local_artifacts_dir: None,
+            max_concurrent_installs: concurrency::DEFAULT_MAX_CONCURRENT_INSTALLS,
+            max_concurrent_downloads: concurrency::DEFAULT_MAX_CONCURRENT_DOWNLOADS,
+            http_proxy: None,
+            https_proxy: None,
+            vault: None,
+            secrets: Secrets::default(),
         }
     }
 }
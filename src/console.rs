@@ -0,0 +1,174 @@
+//! # Console Module
+//!
+//! This module renders the log on the terminal while a run is in progress. Warnings and
+//! errors are labelled by level, and the outcome of each step (a completed, skipped or
+//! failed phase) is logged under a target of its own so that it stands out: green for
+//! completed steps, yellow for skipped ones and red for failures.
+//!
+//! Colors are only used when standard output is a terminal and `NO_COLOR` is unset, so
+//! redirected output stays plain text. Lines are printed above the spinners of the
+//! `progress` module.
+
+use crate::progress;
+use log::{error, info, Level, Record};
+use log4rs::append::Append;
+use owo_colors::OwoColorize;
+use std::io::{IsTerminal, Write};
+
+/// The outcome of a step of a run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepStatus {
+    /// The step ran successfully
+    Completed,
+    /// The step did not need to run
+    Skipped,
+    /// The step failed
+    Failed,
+}
+
+impl StepStatus {
+    const ALL: [StepStatus; 3] = [
+        StepStatus::Completed,
+        StepStatus::Skipped,
+        StepStatus::Failed,
+    ];
+
+    /// Returns the log target step results with this status are logged under.
+    pub fn target(self) -> &'static str {
+        match self {
+            StepStatus::Completed => "serverforge::step::completed",
+            StepStatus::Skipped => "serverforge::step::skipped",
+            StepStatus::Failed => "serverforge::step::failed",
+        }
+    }
+
+    /// Returns the status whose step results are logged under a target, if any.
+    pub fn from_target(target: &str) -> Option<Self> {
+        StepStatus::ALL
+            .into_iter()
+            .find(|status| status.target() == target)
+    }
+
+    /// Renders a text in the color of the status.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The text to render
+    /// * `colored` - Whether to use colors
+    pub fn paint(self, text: &str, colored: bool) -> String {
+        if !colored {
+            return text.to_string();
+        }
+        match self {
+            StepStatus::Completed => text.green().to_string(),
+            StepStatus::Skipped => text.yellow().to_string(),
+            StepStatus::Failed => text.red().to_string(),
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            StepStatus::Completed => "✓",
+            StepStatus::Skipped => "-",
+            StepStatus::Failed => "✗",
+        }
+    }
+}
+
+/// Logs the outcome of a step. Failures are logged as errors, other outcomes as information.
+///
+/// # Arguments
+///
+/// * `status` - The outcome of the step
+/// * `message` - A description of the step and its outcome
+pub fn step(status: StepStatus, message: &str) {
+    match status {
+        StepStatus::Failed => error!(target: status.target(), "{}", message),
+        _ => info!(target: status.target(), "{}", message),
+    }
+}
+
+/// Returns whether terminal output should be colored: standard output must be a terminal,
+/// and the `NO_COLOR` environment variable unset or empty.
+pub fn colors_enabled() -> bool {
+    std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        && std::io::stdout().is_terminal()
+}
+
+/// Renders a log record as a line of terminal output, without the trailing newline.
+///
+/// Step results are prefixed with a mark of their status, warnings and errors with their
+/// level, and every line with the host it concerns, when one is set.
+///
+/// # Arguments
+///
+/// * `level` - The level of the record
+/// * `target` - The target of the record
+/// * `host` - The host the record concerns, if any
+/// * `message` - The message of the record
+/// * `colored` - Whether to use colors
+pub fn format_line(
+    level: Level,
+    target: &str,
+    host: Option<&str>,
+    message: &str,
+    colored: bool,
+) -> String {
+    let message = match host {
+        Some(host) => format!("[{}] {}", host, message),
+        None => message.to_string(),
+    };
+    if let Some(status) = StepStatus::from_target(target) {
+        return status.paint(&format!("{} {}", status.symbol(), message), colored);
+    }
+
+    let label = match level {
+        Level::Error => "error:",
+        Level::Warn => "warning:",
+        _ => return message,
+    };
+    let label = match (colored, level) {
+        (false, _) => label.to_string(),
+        (true, Level::Error) => label.red().bold().to_string(),
+        (true, _) => label.yellow().bold().to_string(),
+    };
+    format!("{} {}", label, message)
+}
+
+/// A log4rs appender printing records to standard output, rendered with `format_line`.
+#[derive(Debug)]
+pub struct ConsoleAppender {
+    colored: bool,
+}
+
+impl ConsoleAppender {
+    /// Creates an appender, colored if `colors_enabled` returns `true`.
+    pub fn new() -> Self {
+        ConsoleAppender {
+            colored: colors_enabled(),
+        }
+    }
+}
+
+impl Default for ConsoleAppender {
+    fn default() -> Self {
+        ConsoleAppender::new()
+    }
+}
+
+impl Append for ConsoleAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        let host = log_mdc::get("host", |host| host.map(str::to_string));
+        let line = format_line(
+            record.level(),
+            record.target(),
+            host.as_deref(),
+            &record.args().to_string(),
+            self.colored,
+        );
+        progress::suspend(|| writeln!(std::io::stdout().lock(), "{}", line))?;
+        Ok(())
+    }
+
+    fn flush(&self) {}
+}
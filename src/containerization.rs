@@ -7,31 +7,106 @@
 //! The module is designed to work across different Linux distributions by leveraging
 //! the appropriate package manager and installation methods for each system.
 
+use crate::concurrency::{current_limits, run_concurrently};
 use crate::config::Config;
-use crate::distro::{get_package_manager, PackageManager};
+use crate::distro::{
+    add_repository, enable_and_start_service, get_package_manager, install_package_tracked,
+    restart_service, PackageManager, RepositorySpec,
+};
 use crate::rollback::RollbackManager;
-use crate::utils::run_command;
+use crate::utils::{
+    check_resources, command_output, create_dir_all, download, ensure_port_free, mirror_url,
+    read_file, run_command, run_command_streaming, target_arch_suffix, write_file,
+};
 use log::info;
 use std::error::Error;
 
+/// The least total memory, in megabytes, Docker is installed on.
+pub const DOCKER_MIN_MEMORY_MB: u64 = 512;
+
+/// The least free disk space, in megabytes, Docker is installed on.
+pub const DOCKER_MIN_DISK_MB: u64 = 10 * 1024;
+
+/// The least total memory, in megabytes, Kubernetes (minikube) is installed on.
+pub const KUBERNETES_MIN_MEMORY_MB: u64 = 2048;
+
+/// The least free disk space, in megabytes, Kubernetes (minikube) is installed on.
+pub const KUBERNETES_MIN_DISK_MB: u64 = 20 * 1024;
+
+/// The least number of CPUs Kubernetes (minikube) is installed on.
+pub const KUBERNETES_MIN_CPUS: u64 = 2;
+
+/// The directory the Kubernetes manifests of deployed applications are written to.
+pub const KUBERNETES_MANIFEST_DIR: &str = "/var/lib/server_forge/kubernetes";
+
+/// The user-defined bridge network deployed Docker containers are attached to, on which they
+/// reach each other by container name.
+pub const DOCKER_NETWORK: &str = "server_forge_net";
+
+/// The storage requested by the PersistentVolumeClaim of each named volume of a container
+/// deployed to Kubernetes.
+pub const KUBERNETES_VOLUME_SIZE: &str = "1Gi";
+
+/// The resources, restart policy and volumes of a deployed container.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContainerSpec {
+    /// The most memory the container may use, in megabytes
+    pub memory_limit_mb: u64,
+    /// The most CPU time the container may use, in CPUs
+    pub cpu_limit: f64,
+    /// The memory Kubernetes reserves for the container, in megabytes
+    pub memory_request_mb: u64,
+    /// The CPU time Kubernetes reserves for the container, in CPUs
+    pub cpu_request: f64,
+    /// When Docker restarts the container (e.g. "unless-stopped", "on-failure" or "no");
+    /// Kubernetes always restarts the containers of a deployment
+    pub restart_policy: String,
+    /// The volumes mounted in the container, as `(source, path in the container)`. A source
+    /// starting with `/` is a directory of the host; any other source names a volume, which
+    /// is created if it does not exist (a PersistentVolumeClaim with Kubernetes).
+    pub volumes: Vec<(String, String)>,
+}
+
+impl Default for ContainerSpec {
+    /// Returns a spec limiting the container to 512 MB of memory and one CPU, reserving
+    /// 256 MB and a quarter of a CPU, and restarting it unless it was stopped, without
+    /// volumes.
+    fn default() -> Self {
+        ContainerSpec {
+            memory_limit_mb: 512,
+            cpu_limit: 1.0,
+            memory_request_mb: 256,
+            cpu_request: 0.25,
+            restart_policy: String::from("unless-stopped"),
+            volumes: Vec::new(),
+        }
+    }
+}
+
 /// Sets up Docker on the system.
 ///
 /// This function installs Docker, configures it, and ensures it's running and enabled on boot.
-/// It creates a snapshot before installation for potential rollback.
+/// It creates a snapshot before installation for potential rollback. It fails before
+/// installing anything if the host has less than `DOCKER_MIN_MEMORY_MB` of memory or
+/// `DOCKER_MIN_DISK_MB` of free disk space.
 ///
 /// # Arguments
 ///
+/// * `config` - A reference to the `Config` struct containing user-defined configuration options
 /// * `rollback` - A reference to the `RollbackManager` for creating snapshots
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` if Docker is set up successfully, or an error if setup fails.
-pub fn setup_docker(rollback: &RollbackManager) -> Result<(), Box<dyn Error>> {
+pub fn setup_docker(config: &Config, rollback: &RollbackManager) -> Result<(), Box<dyn Error>> {
     info!("Setting up Docker...");
 
-    let snapshot = rollback.create_snapshot()?;
+    check_resources(DOCKER_MIN_MEMORY_MB, DOCKER_MIN_DISK_MB, 1)
+        .map_err(|e| format!("Cannot install Docker: {}", e))?;
 
-    install_docker()?;
+    let snapshot = rollback.create_snapshot("Docker")?;
+
+    install_docker(config, rollback, snapshot)?;
     configure_docker()?;
 
     rollback.commit_snapshot(snapshot)?;
@@ -44,20 +119,30 @@ pub fn setup_docker(rollback: &RollbackManager) -> Result<(), Box<dyn Error>> {
 ///
 /// This function installs Kubernetes tools (kubectl and minikube), configures them,
 /// and ensures they're ready for use. It creates a snapshot before installation for potential rollback.
+/// It fails before installing anything if the host is below the `KUBERNETES_MIN_*`
+/// requirements of minikube.
 ///
 /// # Arguments
 ///
+/// * `config` - A reference to the `Config` struct containing user-defined configuration options
 /// * `rollback` - A reference to the `RollbackManager` for creating snapshots
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` if Kubernetes is set up successfully, or an error if setup fails.
-pub fn setup_kubernetes(rollback: &RollbackManager) -> Result<(), Box<dyn Error>> {
+pub fn setup_kubernetes(config: &Config, rollback: &RollbackManager) -> Result<(), Box<dyn Error>> {
     info!("Setting up Kubernetes...");
 
-    let snapshot = rollback.create_snapshot()?;
+    check_resources(
+        KUBERNETES_MIN_MEMORY_MB,
+        KUBERNETES_MIN_DISK_MB,
+        KUBERNETES_MIN_CPUS,
+    )
+    .map_err(|e| format!("Cannot install Kubernetes: {}", e))?;
+
+    let snapshot = rollback.create_snapshot("Kubernetes")?;
 
-    install_kubernetes()?;
+    install_kubernetes(config, rollback, snapshot)?;
     configure_kubernetes()?;
 
     rollback.commit_snapshot(snapshot)?;
@@ -84,10 +169,11 @@ pub fn deploy_containers(
     rollback: &RollbackManager,
 ) -> Result<(), Box<dyn Error>> {
     info!("Deploying containers...");
-    let snapshot = rollback.create_snapshot()?;
+    let snapshot = rollback.create_snapshot("Container deployment")?;
 
+    let spec = ContainerSpec::default();
     for app in &config.deployed_apps {
-        deploy_container(app, config.use_kubernetes)?;
+        deploy_container(app, config.use_kubernetes, &spec)?;
     }
 
     rollback.commit_snapshot(snapshot)?;
@@ -99,103 +185,133 @@ pub fn deploy_containers(
 /// Installs Docker on the system.
 ///
 /// This function installs Docker using the appropriate method for the current Linux distribution.
-/// It adds the Docker repository, installs necessary dependencies, and installs Docker components.
+/// It adds the Docker repository (through the configured download mirror, if any) for the
+/// machine's architecture, installs necessary dependencies, and installs Docker components.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing user-defined configuration options
+/// * `rollback` - The `RollbackManager` to record the installed packages in
+/// * `snapshot` - The ID of the snapshot to record the installed packages in
 ///
 /// # Returns
 ///
-/// Returns `Ok(())` if Docker is installed successfully, or an error if installation fails.
-pub fn install_docker() -> Result<(), Box<dyn Error>> {
+/// Returns `Ok(())` if Docker is installed successfully, or an error if installation fails
+/// or Docker CE is not published for the machine's architecture.
+pub fn install_docker(
+    config: &Config,
+    rollback: &RollbackManager,
+    snapshot: usize,
+) -> Result<(), Box<dyn Error>> {
     let package_manager = get_package_manager()?;
-
-    match package_manager {
-        PackageManager::Apt => {
-            run_command("apt", &["update"])?;
-            run_command(
-                "apt",
-                &[
-                    "install",
-                    "-y",
-                    "apt-transport-https",
-                    "ca-certificates",
-                    "curl",
-                    "gnupg",
-                    "lsb-release",
-                ],
-            )?;
-            run_command(
-                "curl",
-                &[
-                    "-fsSL",
-                    "https://download.docker.com/linux/ubuntu/gpg",
-                    "|",
-                    "gpg",
-                    "--dearmor",
-                    "-o",
-                    "/usr/share/keyrings/docker-archive-keyring.gpg",
-                ],
-            )?;
-            run_command("echo", &["\"deb [arch=amd64 signed-by=/usr/share/keyrings/docker-archive-keyring.gpg] https://download.docker.com/linux/ubuntu $(lsb_release -cs) stable\"", "|", "tee", "/etc/apt/sources.list.d/docker.list", ">", "/dev/null"])?;
-            run_command("apt", &["update"])?;
-            run_command(
-                "apt",
-                &[
-                    "install",
-                    "-y",
-                    "docker-ce",
-                    "docker-ce-cli",
-                    "containerd.io",
-                ],
-            )?;
-        }
-        PackageManager::Yum => {
-            run_command("yum", &["install", "-y", "yum-utils"])?;
-            run_command(
-                "yum-config-manager",
-                &[
-                    "--add-repo",
-                    "https://download.docker.com/linux/centos/docker-ce.repo",
-                ],
-            )?;
-            run_command(
-                "yum",
-                &[
-                    "install",
-                    "-y",
-                    "docker-ce",
-                    "docker-ce-cli",
-                    "containerd.io",
-                ],
-            )?;
-        }
-        PackageManager::Dnf => {
-            run_command("dnf", &["install", "-y", "dnf-plugins-core"])?;
-            run_command(
-                "dnf",
-                &[
-                    "config-manager",
-                    "--add-repo",
-                    "https://download.docker.com/linux/fedora/docker-ce.repo",
-                ],
-            )?;
-            run_command(
-                "dnf",
-                &[
-                    "install",
-                    "-y",
-                    "docker-ce",
-                    "docker-ce-cli",
-                    "containerd.io",
-                ],
-            )?;
+    // Fail before changing anything if there is no Docker CE repository for this machine
+    let repo_arch = docker_repo_arch(&package_manager, target_arch_suffix()?)?;
+
+    if package_manager == PackageManager::Apt {
+        run_command("apt", &["update"])?;
+        for package in [
+            "apt-transport-https",
+            "ca-certificates",
+            "curl",
+            "gnupg",
+            "lsb-release",
+        ] {
+            install_package_tracked(&package_manager, package, rollback, snapshot)?;
         }
     }
+    add_repository(
+        &package_manager,
+        &docker_repository(config, &package_manager, repo_arch),
+    )?;
+    for package in ["docker-ce", "docker-ce-cli", "containerd.io"] {
+        install_package_tracked(&package_manager, package, rollback, snapshot)?;
+    }
 
-    run_command("systemctl", &["start", "docker"])?;
-    run_command("systemctl", &["enable", "docker"])?;
+    enable_and_start_service("docker")?;
 
     Ok(())
 }
 
+/// Returns the Docker CE repository for a package manager.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the download mirror
+/// * `package_manager` - The package manager of the machine
+/// * `repo_arch` - The architecture, as returned by `docker_repo_arch`
+///
+/// # Returns
+///
+/// Returns the Ubuntu repository for APT, limited to `repo_arch`, the CentOS repository
+/// for YUM, or the Fedora repository for DNF.
+pub fn docker_repository(
+    config: &Config,
+    package_manager: &PackageManager,
+    repo_arch: &str,
+) -> RepositorySpec {
+    let (distro, url) = match package_manager {
+        PackageManager::Apt => (
+            "ubuntu",
+            String::from("https://download.docker.com/linux/ubuntu"),
+        ),
+        PackageManager::Yum => (
+            "centos",
+            String::from("https://download.docker.com/linux/centos/$releasever/$basearch/stable"),
+        ),
+        PackageManager::Dnf => (
+            "fedora",
+            String::from("https://download.docker.com/linux/fedora/$releasever/$basearch/stable"),
+        ),
+    };
+    let is_apt = *package_manager == PackageManager::Apt;
+    RepositorySpec {
+        name: String::from("docker"),
+        url: mirror_url(config, &url),
+        gpg_key_url: mirror_url(
+            config,
+            &format!("https://download.docker.com/linux/{}/gpg", distro),
+        ),
+        codename: None,
+        components: vec![String::from("stable")],
+        arch: is_apt.then(|| repo_arch.to_string()),
+    }
+}
+
+/// Returns the architecture name the Docker CE repositories use for a machine.
+///
+/// # Arguments
+///
+/// * `package_manager` - The package manager of the machine
+/// * `arch` - The machine's architecture, as returned by `utils::target_arch_suffix`
+///
+/// # Returns
+///
+/// Returns the Debian architecture (`amd64`, `arm64` or `armhf`) for APT, or the RPM
+/// architecture (`x86_64` or `aarch64`) otherwise, or an error if Docker CE is not
+/// published for the architecture.
+pub fn docker_repo_arch(
+    package_manager: &PackageManager,
+    arch: &str,
+) -> Result<&'static str, Box<dyn Error>> {
+    match (package_manager, arch) {
+        (PackageManager::Apt, "amd64") => Ok("amd64"),
+        (PackageManager::Apt, "arm64") => Ok("arm64"),
+        (PackageManager::Apt, "armv7") => Ok("armhf"),
+        (PackageManager::Yum | PackageManager::Dnf, "amd64") => Ok("x86_64"),
+        (PackageManager::Yum | PackageManager::Dnf, "arm64") => Ok("aarch64"),
+        (PackageManager::Apt, _) => Err(format!(
+            "Docker CE is not published for {} on Debian-based distributions",
+            arch
+        )
+        .into()),
+        (PackageManager::Yum | PackageManager::Dnf, _) => Err(format!(
+            "Docker CE is not published for {} on Red Hat-based distributions",
+            arch
+        )
+        .into()),
+    }
+}
+
 /// Configures Docker after installation.
 ///
 /// This function sets up the Docker daemon with optimal settings, creates a Docker group,
@@ -228,48 +344,98 @@ pub fn configure_docker() -> Result<(), Box<dyn Error>> {
   }
 }
 "#;
-    std::fs::write("/etc/docker/daemon.json", daemon_config)?;
+    write_file("/etc/docker/daemon.json", daemon_config)?;
 
     // Restart Docker to apply changes
-    run_command("systemctl", &["restart", "docker"])?;
+    restart_service("docker")?;
 
     Ok(())
 }
 
 /// Installs Kubernetes tools (kubectl and minikube) on the system.
 ///
-/// This function downloads and installs kubectl and minikube, and installs a virtualization
-/// driver (VirtualBox in this implementation) required for running Kubernetes locally.
+/// This function downloads and installs kubectl and minikube for the machine's architecture,
+/// and installs a virtualization driver (VirtualBox in this implementation) required for
+/// running Kubernetes locally.
+///
+/// Downloads go through `utils::download`, so they honor the configured mirror and local
+/// artifacts directory.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing user-defined configuration options
+/// * `rollback` - The `RollbackManager` to record the installed packages in
+/// * `snapshot` - The ID of the snapshot to record the installed packages in
 ///
 /// # Returns
 ///
-/// Returns `Ok(())` if Kubernetes tools are installed successfully, or an error if installation fails.
-pub fn install_kubernetes() -> Result<(), Box<dyn Error>> {
+/// Returns `Ok(())` if Kubernetes tools are installed successfully, or an error if installation
+/// fails or VirtualBox is not available for the machine's architecture.
+pub fn install_kubernetes(
+    config: &Config,
+    rollback: &RollbackManager,
+    snapshot: usize,
+) -> Result<(), Box<dyn Error>> {
     let package_manager = get_package_manager()?;
 
+    // Kubernetes names its 32-bit ARM builds "arm" rather than "armv7"
+    let arch = match target_arch_suffix()? {
+        "armv7" => "arm",
+        arch => arch,
+    };
+    // VirtualBox only publishes x86 builds, so fail before downloading anything
+    if arch != "amd64" {
+        return Err(format!(
+            "VirtualBox (the minikube driver) does not publish builds for {}",
+            arch
+        )
+        .into());
+    }
+
     // Install kubectl
-    run_command("curl", &["-LO", "https://storage.googleapis.com/kubernetes-release/release/$(curl -s https://storage.googleapis.com/kubernetes-release/release/stable.txt)/bin/linux/amd64/kubectl"])?;
+    download(
+        config,
+        "https://storage.googleapis.com/kubernetes-release/release/stable.txt",
+        "/tmp/kubectl-stable.txt",
+    )?;
+    let kubectl_version = read_file("/tmp/kubectl-stable.txt")?;
+
+    // Download kubectl and minikube at the same time
+    let binaries = [
+        (
+            format!(
+                "https://storage.googleapis.com/kubernetes-release/release/{}/bin/linux/{}/kubectl",
+                kubectl_version.trim(),
+                arch
+            ),
+            "./kubectl",
+        ),
+        (
+            format!(
+                "https://storage.googleapis.com/minikube/releases/latest/minikube-linux-{}",
+                arch
+            ),
+            "minikube",
+        ),
+    ];
+    run_concurrently(
+        &binaries,
+        current_limits().downloads,
+        |(url, destination)| download(config, url, destination),
+    )?;
     run_command("chmod", &["+x", "./kubectl"])?;
     run_command("mv", &["./kubectl", "/usr/local/bin/kubectl"])?;
 
     // Install minikube
-    run_command(
-        "curl",
-        &[
-            "-Lo",
-            "minikube",
-            "https://storage.googleapis.com/minikube/releases/latest/minikube-linux-amd64",
-        ],
-    )?;
     run_command("chmod", &["+x", "minikube"])?;
     run_command("mv", &["minikube", "/usr/local/bin/"])?;
 
     // Install required virtualization driver (using VirtualBox in this example)
-    match package_manager {
-        PackageManager::Apt => run_command("apt", &["install", "-y", "virtualbox"])?,
-        PackageManager::Yum => run_command("yum", &["install", "-y", "VirtualBox"])?,
-        PackageManager::Dnf => run_command("dnf", &["install", "-y", "VirtualBox"])?,
-    }
+    let virtualbox = match package_manager {
+        PackageManager::Apt => "virtualbox",
+        PackageManager::Yum | PackageManager::Dnf => "VirtualBox",
+    };
+    install_package_tracked(&package_manager, virtualbox, rollback, snapshot)?;
 
     Ok(())
 }
@@ -284,7 +450,7 @@ pub fn install_kubernetes() -> Result<(), Box<dyn Error>> {
 /// Returns `Ok(())` if Kubernetes is configured successfully, or an error if configuration fails.
 pub fn configure_kubernetes() -> Result<(), Box<dyn Error>> {
     // Start minikube
-    run_command("minikube", &["start"])?;
+    run_command_streaming("minikube", &["start"])?;
 
     // Enable necessary addons
     run_command("minikube", &["addons", "enable", "ingress"])?;
@@ -308,15 +474,20 @@ pub fn configure_kubernetes() -> Result<(), Box<dyn Error>> {
 ///
 /// * `app` - A string slice representing the application to deploy
 /// * `use_kubernetes` - A boolean indicating whether to use Kubernetes for deployment
+/// * `spec` - The resources and restart policy of the container
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` if the container is deployed successfully, or an error if deployment fails.
-pub fn deploy_container(app: &str, use_kubernetes: bool) -> Result<(), Box<dyn Error>> {
+pub fn deploy_container(
+    app: &str,
+    use_kubernetes: bool,
+    spec: &ContainerSpec,
+) -> Result<(), Box<dyn Error>> {
     if use_kubernetes {
-        deploy_to_kubernetes(app)?;
+        deploy_to_kubernetes(app, spec, KUBERNETES_MANIFEST_DIR)?;
     } else {
-        deploy_to_docker(app)?;
+        deploy_to_docker(app, spec)?;
     }
     Ok(())
 }
@@ -329,14 +500,50 @@ pub fn deploy_container(app: &str, use_kubernetes: bool) -> Result<(), Box<dyn E
 /// # Arguments
 ///
 /// * `app` - A string slice representing the application to deploy
-/// * `use_kubernetes` - A boolean indicating whether to use Kubernetes for deployment
+/// * `spec` - The resources of the container
+/// * `manifest_dir` - The directory the deployment YAML is written to
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` if the container is deployed successfully, or an error if deployment fails.
-pub fn deploy_to_kubernetes(app: &str) -> Result<(), Box<dyn Error>> {
-    // Create a basic deployment YAML
-    let deployment_yaml = format!(
+pub fn deploy_to_kubernetes(
+    app: &str,
+    spec: &ContainerSpec,
+    manifest_dir: &str,
+) -> Result<(), Box<dyn Error>> {
+    // Write the deployment YAML to a file
+    let manifest = format!("{}/{}-deployment.yaml", manifest_dir, app);
+    create_dir_all(manifest_dir)?;
+    write_file(&manifest, kubernetes_deployment_yaml(app, spec))?;
+
+    // Apply the deployment
+    run_command("kubectl", &["apply", "-f", &manifest])?;
+
+    // Expose the deployment as a service
+    run_command(
+        "kubectl",
+        &[
+            "expose",
+            "deployment",
+            app,
+            "--type=LoadBalancer",
+            "--port=80",
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// Generates the Kubernetes Deployment of an application, with the resource requests,
+/// limits and volumes of a `ContainerSpec`, followed by the PersistentVolumeClaims of its
+/// named volumes.
+///
+/// # Arguments
+///
+/// * `app` - The application, which is also the name of its image
+/// * `spec` - The resources of the container
+pub fn kubernetes_deployment_yaml(app: &str, spec: &ContainerSpec) -> String {
+    let yaml = format!(
         r#"
 apiVersion: apps/v1
 kind: Deployment
@@ -357,32 +564,85 @@ spec:
         image: {}:latest
         ports:
         - containerPort: 80
+        resources:
+          requests:
+            memory: {}Mi
+            cpu: {}m
+          limits:
+            memory: {}Mi
+            cpu: {}m
 "#,
-        app, app, app, app, app
+        app,
+        app,
+        app,
+        app,
+        app,
+        spec.memory_request_mb,
+        millicpus(spec.cpu_request),
+        spec.memory_limit_mb,
+        millicpus(spec.cpu_limit)
     );
+    if spec.volumes.is_empty() {
+        return yaml;
+    }
 
-    // Write the deployment YAML to a file
-    std::fs::write(format!("{}-deployment.yaml", app), deployment_yaml)?;
-
-    // Apply the deployment
-    run_command(
-        "kubectl",
-        &["apply", "-f", &format!("{}-deployment.yaml", app)],
-    )?;
+    let mut mounts = String::from("        volumeMounts:\n");
+    let mut volumes = String::from("      volumes:\n");
+    let mut claims = String::new();
+    for (index, (source, path)) in spec.volumes.iter().enumerate() {
+        let name = format!("volume-{}", index);
+        mounts.push_str(&format!(
+            "        - name: {}\n          mountPath: {}\n",
+            name, path
+        ));
+        if source.starts_with('/') {
+            volumes.push_str(&format!(
+                "      - name: {}\n        hostPath:\n          path: {}\n",
+                name, source
+            ));
+        } else {
+            let claim = format!("{}-{}", app, kubernetes_name(source));
+            volumes.push_str(&format!(
+                "      - name: {}\n        persistentVolumeClaim:\n          claimName: {}\n",
+                name, claim
+            ));
+            claims.push_str(&format!(
+                r#"---
+apiVersion: v1
+kind: PersistentVolumeClaim
+metadata:
+  name: {}
+spec:
+  accessModes:
+  - ReadWriteOnce
+  resources:
+    requests:
+      storage: {}
+"#,
+                claim, KUBERNETES_VOLUME_SIZE
+            ));
+        }
+    }
+    format!("{}{}{}{}", yaml, mounts, volumes, claims)
+}
 
-    // Expose the deployment as a service
-    run_command(
-        "kubectl",
-        &[
-            "expose",
-            "deployment",
-            app,
-            "--type=LoadBalancer",
-            "--port=80",
-        ],
-    )?;
+/// Turns a Docker volume name into a valid Kubernetes object name (lowercase letters,
+/// digits and dashes).
+fn kubernetes_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
 
-    Ok(())
+/// Converts a number of CPUs to the millicores Kubernetes expresses CPU resources in.
+fn millicpus(cpus: f64) -> u64 {
+    (cpus * 1000.0).round() as u64
 }
 
 /// Deploys an application to Kubernetes.
@@ -393,11 +653,12 @@ spec:
 /// # Arguments
 ///
 /// * `app` - A string slice representing the application to deploy
+/// * `spec` - The resources and restart policy of the container
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` if the application is deployed to Kubernetes successfully, or an error if deployment fails.
-pub fn deploy_to_docker(app: &str) -> Result<(), Box<dyn Error>> {
+pub fn deploy_to_docker(app: &str, spec: &ContainerSpec) -> Result<(), Box<dyn Error>> {
     // Pull the latest image
     run_command("docker", &["pull", app])?;
 
@@ -405,8 +666,80 @@ pub fn deploy_to_docker(app: &str) -> Result<(), Box<dyn Error>> {
     run_command("docker", &["stop", app]).ok();
     run_command("docker", &["rm", app]).ok();
 
+    // Fail with a clear error, rather than Docker's, if another service holds the port
+    ensure_port_free(80)?;
+    ensure_docker_network()?;
+
+    // Create the named volumes that do not exist yet
+    for (source, _) in &spec.volumes {
+        if source.starts_with('/') {
+            continue;
+        }
+        let filter = format!("name=^{}$", source);
+        if command_output("docker", &["volume", "ls", "-q", "--filter", &filter])?
+            .trim()
+            .is_empty()
+        {
+            run_command("docker", &["volume", "create", source])?;
+        }
+    }
+
     // Run the new container
-    run_command("docker", &["run", "-d", "--name", app, "-p", "80:80", app])?;
+    let args = docker_run_args(app, spec);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    run_command("docker", &args)?;
 
     Ok(())
 }
+
+/// Creates `DOCKER_NETWORK`, unless it already exists.
+///
+/// # Errors
+///
+/// Returns an error if the networks cannot be listed or the network cannot be created.
+pub fn ensure_docker_network() -> Result<(), Box<dyn Error>> {
+    let filter = format!("name=^{}$", DOCKER_NETWORK);
+    if command_output("docker", &["network", "ls", "-q", "--filter", &filter])?
+        .trim()
+        .is_empty()
+    {
+        run_command("docker", &["network", "create", DOCKER_NETWORK])?;
+    }
+    Ok(())
+}
+
+/// Returns the arguments of the `docker` command running the container of an application,
+/// limited to the resources of a `ContainerSpec`, restarted according to its policy,
+/// attached to `DOCKER_NETWORK`, and with its volumes mounted.
+///
+/// # Arguments
+///
+/// * `app` - The application, which is also the name of its image and container
+/// * `spec` - The resources and restart policy of the container
+pub fn docker_run_args(app: &str, spec: &ContainerSpec) -> Vec<String> {
+    let mut args: Vec<String> = [
+        "run",
+        "-d",
+        "--name",
+        app,
+        "-p",
+        "80:80",
+        "--memory",
+        &format!("{}m", spec.memory_limit_mb),
+        "--cpus",
+        &spec.cpu_limit.to_string(),
+        "--restart",
+        &spec.restart_policy,
+        "--network",
+        DOCKER_NETWORK,
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect();
+    for (source, path) in &spec.volumes {
+        args.push(String::from("-v"));
+        args.push(format!("{}:{}", source, path));
+    }
+    args.push(app.to_string());
+    args
+}
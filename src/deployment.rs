@@ -2,23 +2,98 @@
 //!
 //! This module provides functionality for deploying various applications and services
 //! on a Linux server. It supports deployment of web servers (Nginx, Apache), databases
-//! (MySQL, PostgreSQL), programming languages and runtimes (PHP, Node.js, Python),
-//! and configures them according to best practices.
+//! (MySQL, PostgreSQL), programming languages and runtimes (PHP, Node.js, Python), the
+//! RabbitMQ message broker, the Memcached cache, the OpenSearch search engine and the
+//! HAProxy load balancer, as well as applications deployed from Git repositories, and
+//! configures them according to best practices.
 //!
 //! The module is designed to work across different Linux distributions by leveraging
-//! the appropriate package manager for each system.
+//! the appropriate package manager for each system. Deployments are planned by the
+//! `plan_*` functions, so they can be exported as well as executed. Applications with a
+//! deployer registered in the `registry` module are deployed by it instead.
+//!
+//! The `pre_deploy` and `post_deploy` hooks of an application (see `config::DeployHooks`)
+//! run around its deployment by `deploy_application` and `deploy_git_application`. Like
+//! custom deployers, they are left out of plans.
 
-use crate::config::Config;
-use crate::distro::{get_package_manager, PackageManager};
+use crate::config::{
+    Config, DeployHooks, GitApp, HookFailurePolicy, ServerRole, WebServerConfigMode,
+};
+use crate::distro::{get_package_manager, service_name_for, PackageManager};
+use crate::firewall;
+use crate::plan::Plan;
+use crate::registry::{self, DeployContext};
 use crate::rollback::RollbackManager;
-use crate::utils::run_command;
-use log::info;
+use crate::secrets::{
+    secret_store, SecretStore, MYSQL_ROOT_PASSWORD, OPENSEARCH_ADMIN_PASSWORD, POSTGRES_PASSWORD,
+    RABBITMQ_ADMIN_PASSWORD,
+};
+use crate::systemd::{shell_exec_start, ServiceUnit};
+use crate::utils::{
+    check_resources, generate_secure_password, mirror_url, path_exists, run_command, shell_quote,
+    total_memory_mb, write_file,
+};
+use crate::validation::ServiceType;
+use log::{info, warn};
 use std::error::Error;
+use std::net::IpAddr;
+
+/// The path of the HAProxy configuration file.
+const HAPROXY_CONFIG_PATH: &str = "/etc/haproxy/haproxy.cfg";
+
+/// Where the password of the MySQL root user is saved without a Vault.
+pub const MYSQL_PASSWORD_FILE: &str = "/root/.mysql_root_password";
+
+/// The statements `mysql_secure_installation` runs, which it only does interactively:
+/// removing anonymous users, remote root logins and the test database.
+const MYSQL_SECURE_INSTALLATION_SQL: &str = "DELETE FROM mysql.user WHERE User=''; \
+     DELETE FROM mysql.user WHERE User='root' AND Host NOT IN ('localhost', '127.0.0.1', '::1'); \
+     DROP DATABASE IF EXISTS test; \
+     DELETE FROM mysql.db WHERE Db='test' OR Db='test\\_%'; \
+     FLUSH PRIVILEGES;";
+
+/// The port of the RabbitMQ management UI and HTTP API.
+pub const RABBITMQ_MANAGEMENT_PORT: u16 = 15672;
+
+/// The RabbitMQ administrator created on deployment.
+pub const RABBITMQ_ADMIN_USER: &str = "admin";
+
+/// Where the password of the RabbitMQ administrator is saved without a Vault.
+pub const RABBITMQ_PASSWORD_FILE: &str = "/root/.rabbitmq_admin_password";
+
+/// The memory limit of Memcached in megabytes when `memcached_memory_mb` is unset.
+pub const MEMCACHED_DEFAULT_MEMORY_MB: u64 = 64;
+
+/// The address Memcached listens on when `memcached_bind_address` is unset.
+pub const MEMCACHED_DEFAULT_BIND_ADDRESS: &str = "127.0.0.1";
+
+/// The address OpenSearch listens on when `opensearch_bind_address` is unset.
+pub const OPENSEARCH_DEFAULT_BIND_ADDRESS: &str = "127.0.0.1";
+
+/// The least total memory, in megabytes, OpenSearch is deployed on.
+pub const OPENSEARCH_MIN_MEMORY_MB: u64 = 2048;
+
+/// The least free disk space, in megabytes, OpenSearch is deployed on.
+pub const OPENSEARCH_MIN_DISK_MB: u64 = 10 * 1024;
+
+/// The largest OpenSearch JVM heap, in megabytes, below the compressed object pointers limit.
+const OPENSEARCH_MAX_HEAP_MB: u64 = 31 * 1024;
+
+/// Where the password of the OpenSearch `admin` user is saved without a Vault.
+pub const OPENSEARCH_PASSWORD_FILE: &str = "/root/.opensearch_admin_password";
+
+/// The OpenSearch configuration file.
+const OPENSEARCH_CONFIG_PATH: &str = "/etc/opensearch/opensearch.yml";
+
+/// The options file setting the OpenSearch JVM heap.
+const OPENSEARCH_HEAP_OPTIONS_PATH: &str = "/etc/opensearch/jvm.options.d/heap.options";
 
 /// Deploys all applications specified in the configuration.
 ///
 /// This function iterates through the list of applications specified in the configuration
 /// and deploys each one. It creates a snapshot before deployment for potential rollback.
+/// Applications with a registered deployer are deployed after the built-in ones. The
+/// deployment hooks are not run; `deploy_application` runs them.
 ///
 /// # Arguments
 ///
@@ -34,10 +109,22 @@ pub fn deploy_applications(
 ) -> Result<(), Box<dyn Error>> {
     info!("Deploying applications...");
 
-    let snapshot = rollback.create_snapshot()?;
+    let snapshot = rollback.create_snapshot("Application deployment")?;
 
-    for app in &config.deployed_apps {
-        deploy_app(app, &config.server_role)?;
+    Plan::build(|plan| plan_applications(plan, config))?
+        .execute_with_rollback(rollback, snapshot)?;
+
+    let ctx = DeployContext {
+        config,
+        rollback,
+        snapshot,
+    };
+    for deployer in config
+        .deployed_apps
+        .iter()
+        .filter_map(|app| registry::deployer(app))
+    {
+        deployer.deploy(&ctx)?;
     }
 
     rollback.commit_snapshot(snapshot)?;
@@ -46,25 +133,260 @@ pub fn deploy_applications(
     Ok(())
 }
 
-/// Deploys a single application based on its type and the server role.
+/// Plans deploying all applications specified in the configuration.
+///
+/// Applications with a registered deployer cannot be planned and are left out, as are the
+/// deployment hooks.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `config` - A reference to the `Config` struct containing deployment information
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the deployment is planned, or an error if an application is not supported.
+pub fn plan_applications(plan: &mut Plan, config: &Config) -> Result<(), Box<dyn Error>> {
+    for app in &config.deployed_apps {
+        if registry::deployer(app).is_some() {
+            warn!(
+                "{} is deployed by a custom deployer and left out of the plan",
+                app
+            );
+            continue;
+        }
+        plan_app(plan, app, config)?;
+    }
+    for app in &config.git_apps {
+        plan_git_app(plan, app)?;
+    }
+    plan_app_reverse_proxy(plan, config)
+}
+
+/// Deploys a single application in its own rollback snapshot, so that its failure can be
+/// rolled back without undoing the other deployments.
+///
+/// The application is deployed by its registered deployer if there is one, and as a
+/// built-in application otherwise, between its `deploy_hooks` (see `deploy_with_hooks`).
+///
+/// # Arguments
+///
+/// * `app` - A string slice representing the application to deploy
+/// * `config` - A reference to the `Config` struct containing the server roles and application settings
+/// * `rollback` - A reference to the `RollbackManager` for creating snapshots
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the application is deployed successfully, or an error if deployment fails.
+pub fn deploy_application(
+    app: &str,
+    config: &Config,
+    rollback: &RollbackManager,
+) -> Result<(), Box<dyn Error>> {
+    let deployer = registry::deployer(app);
+    // A built-in application is planned first, so that invalid settings are reported
+    // before any hook runs
+    let plan = match deployer {
+        Some(_) => None,
+        None => Some(Plan::build(|plan| plan_app(plan, app, config))?),
+    };
+    let hooks = config.deploy_hooks.get(app).cloned().unwrap_or_default();
+
+    let snapshot = rollback.create_snapshot(&format!("Application deployment: {}", app))?;
+    deploy_with_hooks(app, &hooks, None, || match &deployer {
+        Some(deployer) => deployer.deploy(&DeployContext {
+            config,
+            rollback,
+            snapshot,
+        }),
+        None => plan.as_ref().map_or(Ok(()), |plan| {
+            plan.execute_with_rollback(rollback, snapshot)
+        }),
+    })?;
+    rollback.commit_snapshot(snapshot)
+}
+
+/// Deploys an application from a Git repository in its own rollback snapshot, between
+/// its hooks (see `deploy_with_hooks`).
+///
+/// # Arguments
+///
+/// * `app` - A reference to the `GitApp` describing the repository and how to run it
+/// * `rollback` - A reference to the `RollbackManager` for creating snapshots
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the application is deployed successfully, or an error if deployment fails.
+pub fn deploy_git_application(
+    app: &GitApp,
+    rollback: &RollbackManager,
+) -> Result<(), Box<dyn Error>> {
+    let plan = Plan::build(|plan| plan_git_app(plan, app))?;
+    let snapshot =
+        rollback.create_snapshot(&format!("Application deployment: {}", app.repo_url))?;
+    deploy_with_hooks(
+        &app.repo_url,
+        &app.hooks,
+        Some(app.target_dir.trim_end_matches('/')),
+        || plan.execute_with_rollback(rollback, snapshot),
+    )?;
+    rollback.commit_snapshot(snapshot)
+}
+
+/// Deploys an application between its hooks.
+///
+/// The `pre_deploy` commands run first, and the first failing one aborts the deployment
+/// before anything is deployed. Once `deploy` succeeds, the `post_deploy` commands run; a
+/// failing one fails the deployment (so that it is rolled back) or, with the `Warn`
+/// policy, is logged and skips the remaining ones. Hooks run with `sh -c` through the
+/// current `CommandRunner`.
+///
+/// # Arguments
+///
+/// * `app` - The name of the application, for logs and errors
+/// * `hooks` - The commands to run around the deployment
+/// * `post_deploy_dir` - The directory the `post_deploy` commands run in, if not the
+///   working directory of the runner
+/// * `deploy` - The function deploying the application
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the application is deployed, or an error if a `pre_deploy` command,
+/// the deployment or (with the `Abort` policy) a `post_deploy` command fails.
+pub fn deploy_with_hooks(
+    app: &str,
+    hooks: &DeployHooks,
+    post_deploy_dir: Option<&str>,
+    deploy: impl FnOnce() -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    run_hooks(app, "pre_deploy", &hooks.pre_deploy, None)?;
+    deploy()?;
+    if let Err(e) = run_hooks(app, "post_deploy", &hooks.post_deploy, post_deploy_dir) {
+        match hooks.post_deploy_failure {
+            HookFailurePolicy::Abort => return Err(e),
+            HookFailurePolicy::Warn => warn!("{}; the deployment is kept", e),
+        }
+    }
+    Ok(())
+}
+
+/// Runs the hook commands of a stage of a deployment, in order, stopping at the first
+/// failing one.
+fn run_hooks(
+    app: &str,
+    stage: &str,
+    commands: &[String],
+    dir: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    for command in commands {
+        info!("Running the {} hook of {}: {}", stage, app, command);
+        let script = match dir {
+            Some(dir) => format!("cd {} && {}", shell_quote(dir), command),
+            None => command.clone(),
+        };
+        run_command("sh", &["-c", &script])
+            .map_err(|e| format!("The {} hook '{}' of {} failed: {}", stage, command, app, e))?;
+    }
+    Ok(())
+}
+
+/// Puts the first application server behind the web server, in its own rollback snapshot.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the deployed apps and `app_domain`
+/// * `rollback` - A reference to the `RollbackManager` for creating snapshots
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the reverse proxy is set up or not needed, or an error if the setup fails.
+pub fn setup_app_reverse_proxy(
+    config: &Config,
+    rollback: &RollbackManager,
+) -> Result<(), Box<dyn Error>> {
+    let plan = Plan::build(|plan| plan_app_reverse_proxy(plan, config))?;
+    execute_in_snapshot(&plan, "Reverse proxy", rollback)
+}
+
+/// Executes a plan, recording its changes in a new snapshot that is committed on success.
+fn execute_in_snapshot(
+    plan: &Plan,
+    label: &str,
+    rollback: &RollbackManager,
+) -> Result<(), Box<dyn Error>> {
+    let snapshot = rollback.create_snapshot(label)?;
+    plan.execute_with_rollback(rollback, snapshot)?;
+    rollback.commit_snapshot(snapshot)
+}
+
+/// Plans putting the first application server behind the web server, when both are
+/// deployed and an `app_domain` is configured.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `config` - A reference to the `Config` struct containing the deployed apps and `app_domain`
+///
+/// # Errors
+///
+/// Returns an error if planning the reverse proxy fails.
+pub fn plan_app_reverse_proxy(plan: &mut Plan, config: &Config) -> Result<(), Box<dyn Error>> {
+    let web = config
+        .deployed_apps
+        .iter()
+        .find(|app| *app == "nginx" || *app == "apache");
+    let app_port = config.deployed_apps.iter().find_map(|app| app_port(app));
+    match (web, app_port, &config.app_domain) {
+        (Some(web), Some(port), Some(domain)) => {
+            plan_reverse_proxy(plan, web, &format!("127.0.0.1:{}", port), domain)?;
+        }
+        (Some(_), Some(_), None) => {
+            info!("No app_domain configured; skipping the reverse proxy setup")
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Deploys a single application based on its type and the server configuration.
 ///
 /// # Arguments
 ///
 /// * `app` - A string slice representing the application to deploy
-/// * `server_role` - A string slice representing the role of the server (e.g., "web", "database")
+/// * `config` - A reference to the `Config` struct containing the server role and application settings
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` if the application is deployed successfully, or an error if deployment fails.
-pub fn deploy_app(app: &str, server_role: &str) -> Result<(), Box<dyn Error>> {
+pub fn deploy_app(app: &str, config: &Config) -> Result<(), Box<dyn Error>> {
+    Plan::build(|plan| plan_app(plan, app, config))?.execute()
+}
+
+/// Plans deploying a single application.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `app` - A string slice representing the application to deploy
+/// * `config` - A reference to the `Config` struct containing the server role and application settings
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the deployment is planned, or an error if the application is not
+/// supported or its settings are invalid.
+pub fn plan_app(plan: &mut Plan, app: &str, config: &Config) -> Result<(), Box<dyn Error>> {
     match app {
-        "nginx" => deploy_nginx()?,
-        "apache" => deploy_apache()?,
-        "mysql" => deploy_mysql()?,
-        "postgresql" => deploy_postgresql()?,
-        "php" => deploy_php(server_role)?,
-        "nodejs" => deploy_nodejs()?,
-        "python" => deploy_python()?,
+        "nginx" => plan_nginx(plan),
+        "apache" => plan_apache(plan)?,
+        "mysql" => plan_mysql(plan, config)?,
+        "postgresql" => plan_postgresql(plan)?,
+        "php" => plan_php(plan, &config.server_roles)?,
+        "nodejs" => plan_nodejs(plan),
+        "python" => plan_python(plan)?,
+        "haproxy" => plan_haproxy(plan, config)?,
+        "rabbitmq" => plan_rabbitmq(plan, config)?,
+        "memcached" => plan_memcached(plan, config)?,
+        "opensearch" => plan_opensearch(plan, config)?,
         _ => return Err(format!("Unsupported application: {}", app).into()),
     }
     Ok(())
@@ -79,18 +401,16 @@ pub fn deploy_app(app: &str, server_role: &str) -> Result<(), Box<dyn Error>> {
 ///
 /// Returns `Ok(())` if Nginx is deployed successfully, or an error if deployment fails.
 pub fn deploy_nginx() -> Result<(), Box<dyn Error>> {
-    let package_manager = get_package_manager()?;
-
-    match package_manager {
-        PackageManager::Apt => run_command("apt", &["install", "-y", "nginx"])?,
-        PackageManager::Yum => run_command("yum", &["install", "-y", "nginx"])?,
-        PackageManager::Dnf => run_command("dnf", &["install", "-y", "nginx"])?,
-    }
-
-    run_command("systemctl", &["start", "nginx"])?;
-    run_command("systemctl", &["enable", "nginx"])?;
+    let mut plan = Plan::new();
+    plan_nginx(&mut plan);
+    plan.execute()
+}
 
-    Ok(())
+/// Plans deploying Nginx.
+pub fn plan_nginx(plan: &mut Plan) {
+    plan.install(&["nginx"])
+        .start_service("nginx")
+        .enable_service("nginx");
 }
 
 /// Deploys and configures the Apache web server.
@@ -102,49 +422,78 @@ pub fn deploy_nginx() -> Result<(), Box<dyn Error>> {
 ///
 /// Returns `Ok(())` if Apache is deployed successfully, or an error if deployment fails.
 pub fn deploy_apache() -> Result<(), Box<dyn Error>> {
-    let package_manager = get_package_manager()?;
-
-    match package_manager {
-        PackageManager::Apt => run_command("apt", &["install", "-y", "apache2"])?,
-        PackageManager::Yum => run_command("yum", &["install", "-y", "httpd"])?,
-        PackageManager::Dnf => run_command("dnf", &["install", "-y", "httpd"])?,
-    }
-
-    if run_command("systemctl", &["start", "apache2"]).is_err() {
-        run_command("systemctl", &["start", "httpd"])?;
-    }
+    Plan::build(plan_apache)?.execute()
+}
 
-    if run_command("systemctl", &["enable", "apache2"]).is_err() {
-        run_command("systemctl", &["enable", "httpd"])?;
-    }
+/// Plans deploying Apache, which is packaged as `apache2` on Debian-based distributions
+/// and `httpd` elsewhere.
+///
+/// # Errors
+///
+/// Returns an error if the package manager cannot be detected.
+pub fn plan_apache(plan: &mut Plan) -> Result<(), Box<dyn Error>> {
+    // The package is named like its unit
+    let service = service_name_for(&get_package_manager()?, "apache");
 
+    plan.install(&[service])
+        .start_service(service)
+        .enable_service(service);
     Ok(())
 }
 
 /// Deploys and configures the MySQL database server.
 ///
 /// This function installs MySQL using the appropriate package manager,
-/// starts the MySQL service, enables it to start on boot, and runs the
-/// mysql_secure_installation script to set up basic security measures.
+/// starts the MySQL service, enables it to start on boot, and applies the
+/// security measures of mysql_secure_installation.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the secret store settings
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` if MySQL is deployed successfully, or an error if deployment fails.
-pub fn deploy_mysql() -> Result<(), Box<dyn Error>> {
-    let package_manager = get_package_manager()?;
-
-    match package_manager {
-        PackageManager::Apt => run_command("apt", &["install", "-y", "mysql-server"])?,
-        PackageManager::Yum => run_command("yum", &["install", "-y", "mysql-server"])?,
-        PackageManager::Dnf => run_command("dnf", &["install", "-y", "mysql-server"])?,
-    }
-
-    run_command("systemctl", &["start", "mysql"])?;
-    run_command("systemctl", &["enable", "mysql"])?;
+pub fn deploy_mysql(config: &Config) -> Result<(), Box<dyn Error>> {
+    Plan::build(|plan| plan_mysql(plan, config))?.execute()
+}
 
-    // Secure MySQL installation
-    run_command("mysql_secure_installation", &[])?;
+/// Plans deploying MySQL.
+///
+/// On the first deployment (when the secret store has no `MYSQL_ROOT_PASSWORD`), the
+/// statements of `mysql_secure_installation` are run directly, since the script itself
+/// prompts for every step, and the root user is given a generated password saved to the
+/// secret store (`MYSQL_PASSWORD_FILE` without a Vault).
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `config` - A reference to the `Config` struct containing the secret store settings
+///
+/// # Errors
+///
+/// Returns an error if the secret store cannot be read.
+pub fn plan_mysql(plan: &mut Plan, config: &Config) -> Result<(), Box<dyn Error>> {
+    let service = service_name_for(&get_package_manager()?, "mysql");
+    plan.install(&["mysql-server"])
+        .start_service(service)
+        .enable_service(service);
 
+    let store = secret_store(config)?;
+    if store.get(MYSQL_ROOT_PASSWORD)?.is_none() {
+        let password = generate_secure_password();
+        // The password is passed on standard input, to keep it out of the logs
+        plan.run("mysql", &["-e", MYSQL_SECURE_INSTALLATION_SQL])
+            .run_with_input(
+                "mysql",
+                &[],
+                format!(
+                    "ALTER USER 'root'@'localhost' IDENTIFIED BY '{}'; FLUSH PRIVILEGES;\n",
+                    password
+                ),
+            );
+        store.plan_put(plan, MYSQL_ROOT_PASSWORD, &password);
+    }
     Ok(())
 }
 
@@ -158,31 +507,28 @@ pub fn deploy_mysql() -> Result<(), Box<dyn Error>> {
 ///
 /// Returns `Ok(())` if PostgreSQL is deployed successfully, or an error if deployment fails.
 pub fn deploy_postgresql() -> Result<(), Box<dyn Error>> {
+    Plan::build(plan_postgresql)?.execute()
+}
+
+/// Plans deploying PostgreSQL.
+///
+/// # Errors
+///
+/// Returns an error if the package manager cannot be detected.
+pub fn plan_postgresql(plan: &mut Plan) -> Result<(), Box<dyn Error>> {
     let package_manager = get_package_manager()?;
 
     match package_manager {
-        PackageManager::Apt => run_command(
-            "apt",
-            &["install", "-y", "postgresql", "postgresql-contrib"],
-        )?,
-        PackageManager::Yum => run_command(
-            "yum",
-            &["install", "-y", "postgresql-server", "postgresql-contrib"],
-        )?,
-        PackageManager::Dnf => run_command(
-            "dnf",
-            &["install", "-y", "postgresql-server", "postgresql-contrib"],
-        )?,
-    }
-
-    // Initialize the database (for CentOS/Fedora)
-    if package_manager != PackageManager::Apt {
-        run_command("postgresql-setup", &["--initdb"])?;
-    }
-
-    run_command("systemctl", &["start", "postgresql"])?;
-    run_command("systemctl", &["enable", "postgresql"])?;
+        PackageManager::Apt => plan.install(&["postgresql", "postgresql-contrib"]),
+        PackageManager::Yum | PackageManager::Dnf => {
+            plan.install(&["postgresql-server", "postgresql-contrib"])
+                // Initialize the database (for CentOS/Fedora)
+                .run("postgresql-setup", &["--initdb"])
+        }
+    };
 
+    plan.start_service("postgresql")
+        .enable_service("postgresql");
     Ok(())
 }
 
@@ -193,40 +539,43 @@ pub fn deploy_postgresql() -> Result<(), Box<dyn Error>> {
 ///
 /// # Arguments
 ///
-/// * `server_role` - A string slice representing the role of the server (e.g., "web")
+/// * `server_roles` - The roles of the server; web modules are installed if it is a web server
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` if PHP is deployed successfully, or an error if deployment fails.
-pub fn deploy_php(server_role: &str) -> Result<(), Box<dyn Error>> {
-    let package_manager = get_package_manager()?;
+pub fn deploy_php(server_roles: &[ServerRole]) -> Result<(), Box<dyn Error>> {
+    Plan::build(|plan| plan_php(plan, server_roles))?.execute()
+}
 
-    match package_manager {
+/// Plans deploying PHP.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `server_roles` - The roles of the server; web modules are installed if it is a web server
+///
+/// # Errors
+///
+/// Returns an error if the package manager cannot be detected.
+pub fn plan_php(plan: &mut Plan, server_roles: &[ServerRole]) -> Result<(), Box<dyn Error>> {
+    let is_web_server = server_roles.contains(&ServerRole::Web);
+    match get_package_manager()? {
         PackageManager::Apt => {
-            run_command("apt", &["install", "-y", "php", "php-fpm", "php-mysql"])?;
-            if server_role == "web" {
-                run_command("apt", &["install", "-y", "libapache2-mod-php"])?;
+            plan.install(&["php", "php-fpm", "php-mysql"]);
+            if is_web_server {
+                plan.install(&["libapache2-mod-php"]);
             }
         }
         PackageManager::Yum | PackageManager::Dnf => {
-            let install_cmd = if package_manager == PackageManager::Yum {
-                "yum"
-            } else {
-                "dnf"
-            };
-            run_command(
-                install_cmd,
-                &["install", "-y", "php", "php-fpm", "php-mysqlnd"],
-            )?;
-            if server_role == "web" {
-                run_command(install_cmd, &["install", "-y", "php-apache"])?;
+            plan.install(&["php", "php-fpm", "php-mysqlnd"]);
+            if is_web_server {
+                plan.install(&["php-apache"]);
             }
         }
     }
 
-    run_command("systemctl", &["start", "php-fpm"])?;
-    run_command("systemctl", &["enable", "php-fpm"])?;
-
+    plan.start_service("php-fpm").enable_service("php-fpm");
     Ok(())
 }
 
@@ -239,8 +588,15 @@ pub fn deploy_php(server_role: &str) -> Result<(), Box<dyn Error>> {
 ///
 /// Returns `Ok(())` if Node.js is deployed successfully, or an error if deployment fails.
 pub fn deploy_nodejs() -> Result<(), Box<dyn Error>> {
+    let mut plan = Plan::new();
+    plan_nodejs(&mut plan);
+    plan.execute()
+}
+
+/// Plans deploying Node.js.
+pub fn plan_nodejs(plan: &mut Plan) {
     // Install Node.js using NVM (Node Version Manager)
-    run_command(
+    plan.run(
         "curl",
         &[
             "-o-",
@@ -248,15 +604,12 @@ pub fn deploy_nodejs() -> Result<(), Box<dyn Error>> {
             "|",
             "bash",
         ],
-    )?;
-    run_command("source", &["~/.nvm/nvm.sh"])?;
-    run_command("nvm", &["install", "node"])?;
-    run_command("nvm", &["use", "node"])?;
-
+    )
+    .run("source", &["~/.nvm/nvm.sh"])
+    .run("nvm", &["install", "node"])
+    .run("nvm", &["use", "node"])
     // Install PM2 process manager
-    run_command("npm", &["install", "-g", "pm2"])?;
-
-    Ok(())
+    .run("npm", &["install", "-g", "pm2"]);
 }
 
 /// Deploys and configures Python.
@@ -268,110 +621,904 @@ pub fn deploy_nodejs() -> Result<(), Box<dyn Error>> {
 ///
 /// Returns `Ok(())` if Python is deployed successfully, or an error if deployment fails.
 pub fn deploy_python() -> Result<(), Box<dyn Error>> {
-    let package_manager = get_package_manager()?;
+    Plan::build(plan_python)?.execute()
+}
+
+/// Plans deploying Python.
+///
+/// # Errors
+///
+/// Returns an error if the package manager cannot be detected.
+pub fn plan_python(plan: &mut Plan) -> Result<(), Box<dyn Error>> {
+    match get_package_manager()? {
+        PackageManager::Apt => plan.install(&["python3", "python3-pip", "python3-venv"]),
+        PackageManager::Yum | PackageManager::Dnf => plan.install(&["python3", "python3-pip"]),
+    };
+
+    // Install virtualenv
+    plan.run("pip3", &["install", "virtualenv"]);
+    Ok(())
+}
+
+/// Deploys the HAProxy load balancer in front of the configured backends.
+///
+/// This function installs HAProxy, writes its configuration, checks it with
+/// `haproxy -c -f`, then enables, starts and reloads the `haproxy` service.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the load balancer settings
+///
+/// # Returns
+///
+/// Returns `Ok(())` if HAProxy is deployed successfully, or an error if deployment fails.
+pub fn deploy_haproxy(config: &Config) -> Result<(), Box<dyn Error>> {
+    Plan::build(|plan| plan_haproxy(plan, config))?.execute()
+}
+
+/// Plans deploying HAProxy.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `config` - A reference to the `Config` struct containing the load balancer settings
+///
+/// # Errors
+///
+/// Returns an error if the backends are missing or invalid.
+pub fn plan_haproxy(plan: &mut Plan, config: &Config) -> Result<(), Box<dyn Error>> {
+    let haproxy_config = generate_haproxy_config(config)?;
+
+    plan.install(&["haproxy"])
+        .write_file(HAPROXY_CONFIG_PATH, haproxy_config)
+        .validate_config(ServiceType::Haproxy, HAPROXY_CONFIG_PATH)
+        .enable_service("haproxy")
+        .start_service("haproxy")
+        .reload_service("haproxy");
+    Ok(())
+}
+
+/// Deploys and configures the RabbitMQ message broker.
+///
+/// This function enables the RabbitMQ and Erlang package repositories (through the
+/// configured download mirror, if any), installs `rabbitmq-server`, enables the management
+/// plugin, and starts the service and enables it at boot. On the first deployment it creates
+/// the `RABBITMQ_ADMIN_USER` administrator with a generated password, saved to the secret
+/// store (`RABBITMQ_PASSWORD_FILE`, mode 0600, without a Vault). The management port is only opened in the firewall
+/// with `expose_rabbitmq_management`.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the mirror settings
+///
+/// # Returns
+///
+/// Returns `Ok(())` if RabbitMQ is deployed successfully, or an error if deployment fails.
+pub fn deploy_rabbitmq(config: &Config) -> Result<(), Box<dyn Error>> {
+    Plan::build(|plan| plan_rabbitmq(plan, config))?.execute()
+}
+
+/// Plans deploying RabbitMQ.
+///
+/// The administrator is only planned when the secret store has no
+/// `RABBITMQ_ADMIN_PASSWORD` yet, so re-deploying keeps the existing credentials.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `config` - A reference to the `Config` struct containing the mirror and secret store
+///   settings
+///
+/// # Errors
+///
+/// Returns an error if the package manager cannot be detected or the secret store cannot be
+/// read.
+pub fn plan_rabbitmq(plan: &mut Plan, config: &Config) -> Result<(), Box<dyn Error>> {
+    let script = match get_package_manager()? {
+        PackageManager::Apt => "script.deb.sh",
+        PackageManager::Yum | PackageManager::Dnf => "script.rpm.sh",
+    };
+    for repository in ["rabbitmq/erlang", "rabbitmq/rabbitmq-server"] {
+        let url = mirror_url(
+            config,
+            &format!(
+                "https://packagecloud.io/install/repositories/{}/{}",
+                repository, script
+            ),
+        );
+        plan.run(
+            "sh",
+            &["-c", &format!("curl -1sLf {} | bash", shell_quote(&url))],
+        );
+    }
+
+    plan.install(&["rabbitmq-server"])
+        .enable_service("rabbitmq-server")
+        .start_service("rabbitmq-server")
+        .run("rabbitmq-plugins", &["enable", "rabbitmq_management"]);
+
+    let store = secret_store(config)?;
+    if store.get(RABBITMQ_ADMIN_PASSWORD)?.is_none() {
+        let password = generate_secure_password();
+        plan.run("rabbitmqctl", &["add_user", RABBITMQ_ADMIN_USER, &password])
+            .run(
+                "rabbitmqctl",
+                &["set_user_tags", RABBITMQ_ADMIN_USER, "administrator"],
+            )
+            .run(
+                "rabbitmqctl",
+                &[
+                    "set_permissions",
+                    "-p",
+                    "/",
+                    RABBITMQ_ADMIN_USER,
+                    ".*",
+                    ".*",
+                    ".*",
+                ],
+            );
+        store.plan_put(plan, RABBITMQ_ADMIN_PASSWORD, &password);
+    }
+    Ok(())
+}
+
+/// Deploys and configures Memcached.
+///
+/// This function installs Memcached, writes its configuration with the configured memory
+/// limit and bind address (`/etc/memcached.conf` on Debian-based distributions,
+/// `/etc/sysconfig/memcached` elsewhere), then enables and restarts the `memcached` service.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the Memcached settings
+///
+/// # Returns
+///
+/// Returns `Ok(())` if Memcached is deployed successfully, or an error if deployment fails.
+pub fn deploy_memcached(config: &Config) -> Result<(), Box<dyn Error>> {
+    Plan::build(|plan| plan_memcached(plan, config))?.execute()
+}
+
+/// Plans deploying Memcached.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `config` - A reference to the `Config` struct containing the Memcached settings
+///
+/// # Errors
+///
+/// Returns an error if the package manager cannot be detected, or if the memory limit is
+/// zero or the bind address is not an IP address.
+pub fn plan_memcached(plan: &mut Plan, config: &Config) -> Result<(), Box<dyn Error>> {
+    let memory_mb = config
+        .memcached_memory_mb
+        .unwrap_or(MEMCACHED_DEFAULT_MEMORY_MB);
+    if memory_mb == 0 {
+        return Err("The Memcached memory limit must be at least 1 MB".into());
+    }
+    let bind_address = config
+        .memcached_bind_address
+        .as_deref()
+        .unwrap_or(MEMCACHED_DEFAULT_BIND_ADDRESS);
+    if bind_address.parse::<IpAddr>().is_err() {
+        return Err(format!("Invalid Memcached bind address: {}", bind_address).into());
+    }
 
+    let (path, contents) = match get_package_manager()? {
+        PackageManager::Apt => (
+            "/etc/memcached.conf",
+            format!(
+                "-d\n\
+                 logfile /var/log/memcached.log\n\
+                 -m {}\n\
+                 -p 11211\n\
+                 -u memcache\n\
+                 -l {}\n\
+                 -P /var/run/memcached/memcached.pid\n",
+                memory_mb, bind_address
+            ),
+        ),
+        PackageManager::Yum | PackageManager::Dnf => (
+            "/etc/sysconfig/memcached",
+            format!(
+                "PORT=\"11211\"\n\
+                 USER=\"memcached\"\n\
+                 MAXCONN=\"1024\"\n\
+                 CACHESIZE=\"{}\"\n\
+                 OPTIONS=\"-l {}\"\n",
+                memory_mb, bind_address
+            ),
+        ),
+    };
+
+    plan.install(&["memcached"])
+        .write_file(path, contents)
+        .enable_service("memcached")
+        .restart_service("memcached");
+    Ok(())
+}
+
+/// Deploys and configures OpenSearch as a single-node search backend.
+///
+/// This function checks that the host has at least `OPENSEARCH_MIN_MEMORY_MB` of memory
+/// and `OPENSEARCH_MIN_DISK_MB` of free disk space, adds the OpenSearch 2.x package repository (through the configured download mirror, if
+/// any) and installs `opensearch`. On the first deployment the security plugin is set up
+/// with a generated `admin` password, saved to the secret store (`OPENSEARCH_PASSWORD_FILE`,
+/// mode 0600, without a Vault).
+/// The JVM heap is set to half of the host's memory, `opensearch.yml` is configured for a
+/// single node listening on `opensearch_bind_address` with security enabled, and the
+/// `opensearch` service is enabled and restarted.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the OpenSearch and mirror settings
+///
+/// # Returns
+///
+/// Returns `Ok(())` if OpenSearch is deployed successfully, or an error if deployment fails.
+pub fn deploy_opensearch(config: &Config) -> Result<(), Box<dyn Error>> {
+    Plan::build(|plan| plan_opensearch(plan, config))?.execute()
+}
+
+/// Plans deploying OpenSearch.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `config` - A reference to the `Config` struct containing the OpenSearch and mirror settings
+///
+/// # Errors
+///
+/// Returns an error if the host does not have the resources OpenSearch needs, its resources
+/// or package manager cannot be detected, the bind address is not an IP address, or the
+/// secret store cannot be read.
+pub fn plan_opensearch(plan: &mut Plan, config: &Config) -> Result<(), Box<dyn Error>> {
+    check_resources(OPENSEARCH_MIN_MEMORY_MB, OPENSEARCH_MIN_DISK_MB, 1)
+        .map_err(|e| format!("Cannot deploy OpenSearch: {}", e))?;
+    let memory_mb = total_memory_mb()?;
+    let bind_address = config
+        .opensearch_bind_address
+        .as_deref()
+        .unwrap_or(OPENSEARCH_DEFAULT_BIND_ADDRESS);
+    if bind_address.parse::<IpAddr>().is_err() {
+        return Err(format!("Invalid OpenSearch bind address: {}", bind_address).into());
+    }
+
+    let releases = mirror_url(
+        config,
+        "https://artifacts.opensearch.org/releases/bundle/opensearch/2.x",
+    );
+    let package_manager = get_package_manager()?;
     match package_manager {
-        PackageManager::Apt => run_command(
-            "apt",
-            &["install", "-y", "python3", "python3-pip", "python3-venv"],
-        )?,
-        PackageManager::Yum => run_command("yum", &["install", "-y", "python3", "python3-pip"])?,
-        PackageManager::Dnf => run_command("dnf", &["install", "-y", "python3", "python3-pip"])?,
+        PackageManager::Apt => {
+            let key = mirror_url(
+                config,
+                "https://artifacts.opensearch.org/publickeys/opensearch.pgp",
+            );
+            plan.run(
+                "sh",
+                &[
+                    "-c",
+                    &format!(
+                        "curl -fsSL {} | gpg --dearmor --batch --yes -o /usr/share/keyrings/opensearch-keyring",
+                        shell_quote(&key)
+                    ),
+                ],
+            )
+            .write_file(
+                "/etc/apt/sources.list.d/opensearch-2.x.list",
+                format!(
+                    "deb [signed-by=/usr/share/keyrings/opensearch-keyring] {}/apt stable main\n",
+                    releases
+                ),
+            )
+            .run("apt-get", &["update"]);
+        }
+        PackageManager::Yum | PackageManager::Dnf => {
+            plan.run(
+                "curl",
+                &[
+                    "-fsSL",
+                    &format!("{}/opensearch-2.x.repo", releases),
+                    "-o",
+                    "/etc/yum.repos.d/opensearch-2.x.repo",
+                ],
+            );
+        }
     }
 
-    // Install virtualenv
-    run_command("pip3", &["install", "virtualenv"])?;
+    let store = secret_store(config)?;
+    if store.get(OPENSEARCH_ADMIN_PASSWORD)?.is_some() {
+        plan.install(&["opensearch"]);
+    } else {
+        // The package sets up the security plugin with this password when it is installed
+        let password = opensearch_admin_password();
+        let installer = match package_manager {
+            PackageManager::Apt => "apt-get",
+            PackageManager::Yum => "yum",
+            PackageManager::Dnf => "dnf",
+        };
+        plan.run(
+            "env",
+            &[
+                &format!("OPENSEARCH_INITIAL_ADMIN_PASSWORD={}", password),
+                installer,
+                "install",
+                "-y",
+                "opensearch",
+            ],
+        );
+        store.plan_put(plan, OPENSEARCH_ADMIN_PASSWORD, &password);
+    }
 
+    let heap_mb = (memory_mb / 2).min(OPENSEARCH_MAX_HEAP_MB);
+    let settings = format!(
+        "discovery.type: single-node\nnetwork.host: {}\nplugins.security.disabled: false\n",
+        bind_address
+    );
+    plan.write_file(
+        OPENSEARCH_HEAP_OPTIONS_PATH,
+        format!("-Xms{heap}m\n-Xmx{heap}m\n", heap = heap_mb),
+    )
+    // Replace the settings in place, keeping those the security plugin added on install
+    .run(
+        "sh",
+        &[
+            "-c",
+            &format!(
+                "sed -i -E '/^(discovery\\.type|network\\.host|plugins\\.security\\.disabled):/d' {path} && printf %s {settings} >> {path}",
+                path = OPENSEARCH_CONFIG_PATH,
+                settings = shell_quote(&settings)
+            ),
+        ],
+    )
+    .enable_service("opensearch")
+    .restart_service("opensearch");
     Ok(())
 }
 
-/// Sets up the web server configuration based on the specified application.
-/// This function configures the default web server configuration for Nginx or Apache.
-/// It creates a basic "Hello, World!" index page in the web root directory.
+/// Generates an `admin` password meeting the OpenSearch strength requirements: upper and
+/// lower case letters, digits and special characters.
+fn opensearch_admin_password() -> String {
+    loop {
+        let password = generate_secure_password();
+        let has = |class: fn(&char) -> bool| password.chars().any(|c| class(&c));
+        if has(char::is_ascii_uppercase)
+            && has(char::is_ascii_lowercase)
+            && has(char::is_ascii_digit)
+            && has(char::is_ascii_punctuation)
+        {
+            return password;
+        }
+    }
+}
+
+/// Deploys an application from a Git repository.
+///
+/// This function installs Git, clones the repository (or pulls the branch if it was
+/// already cloned), runs the build command, and installs, enables and restarts a systemd
+/// service running the application.
 ///
 /// # Arguments
 ///
-/// * `app` - The name of the application (e.g., "nginx" or "apache").
+/// * `app` - A reference to the `GitApp` describing the repository and how to run it
 ///
 /// # Returns
 ///
-/// Returns `Ok(())` if the web server configuration is set up successfully, or an error if configuration fails.
-pub fn setup_web_server_config(app: &str) -> Result<(), Box<dyn Error>> {
-    match app {
-        "nginx" => setup_nginx_config()?,
-        "apache" => setup_apache_config()?,
-        _ => return Err(format!("Unsupported web server: {}", app).into()),
+/// Returns `Ok(())` if the application is deployed successfully, or an error if deployment fails.
+pub fn deploy_from_git(app: &GitApp) -> Result<(), Box<dyn Error>> {
+    Plan::build(|plan| plan_git_app(plan, app))?.execute()
+}
+
+/// Plans deploying an application from a Git repository, as `deploy_from_git` does.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `app` - A reference to the `GitApp` describing the repository and how to run it
+///
+/// # Errors
+///
+/// Returns an error if `target_dir` is not an absolute path whose last component is a
+/// valid service name.
+pub fn plan_git_app(plan: &mut Plan, app: &GitApp) -> Result<(), Box<dyn Error>> {
+    let name = git_app_service_name(app)?;
+    let target_dir = app.target_dir.trim_end_matches('/');
+
+    plan.install(&["git"]);
+    if path_exists(format!("{}/.git", target_dir)) {
+        plan.run("git", &["-C", target_dir, "fetch", "origin", &app.branch])
+            .run("git", &["-C", target_dir, "checkout", &app.branch])
+            .run(
+                "git",
+                &[
+                    "-C",
+                    target_dir,
+                    "reset",
+                    "--hard",
+                    &format!("origin/{}", app.branch),
+                ],
+            );
+    } else {
+        plan.run(
+            "git",
+            &["clone", "--branch", &app.branch, &app.repo_url, target_dir],
+        );
     }
+
+    if let Some(build_command) = &app.build_command {
+        plan.run(
+            "sh",
+            &[
+                "-c",
+                &format!("cd {} && {}", shell_quote(target_dir), build_command),
+            ],
+        );
+    }
+
+    let unit = ServiceUnit::new(
+        &format!("{} (deployed from {})", name, app.repo_url),
+        &shell_exec_start(&app.run_command),
+    )
+    .working_directory(target_dir);
+    plan.write_file(
+        format!("/etc/systemd/system/{}.service", name),
+        unit.render(),
+    )
+    .run("systemctl", &["daemon-reload"])
+    .enable_service(&name)
+    .restart_service(&name);
     Ok(())
 }
 
-/// Creates a sample web application based on the specified application type.
-/// This function creates a basic "Hello, World!" application for PHP, Node.js, or Python,
-/// demonstrating how to set up a simple web server for each technology.
+/// Returns the systemd service name of a Git application: the last component of its
+/// target directory.
+///
 /// # Arguments
 ///
-/// * `app_type` - A string slice representing the type of application to create ("php", "nodejs", or "python")
+/// * `app` - A reference to the `GitApp`
+///
 /// # Returns
 ///
-/// Returns `Ok(())` if the sample application is created successfully, or an error if creation fails.
-fn setup_nginx_config() -> Result<(), Box<dyn Error>> {
-    let nginx_config = r#"
-server {
-    listen 80 default_server;
-    listen [::]:80 default_server;
-    root /var/www/html;
-    index index.html index.htm index.nginx-debian.html;
-    server_name _;
-    location / {
-        try_files $uri $uri/ =404;
+/// Returns the service name, or an error if the target directory is not absolute or its
+/// last component contains characters other than letters, digits, `-`, `_` and `.`.
+pub fn git_app_service_name(app: &GitApp) -> Result<String, Box<dyn Error>> {
+    let target_dir = app.target_dir.trim_end_matches('/');
+    let name = target_dir.rsplit('/').next().unwrap_or_default();
+    let valid = target_dir.starts_with('/')
+        && !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if !valid {
+        return Err(format!(
+            "Invalid Git application directory '{}': expected an absolute path such as /srv/app",
+            app.target_dir
+        )
+        .into());
     }
+    Ok(name.to_string())
 }
-"#;
-    std::fs::write("/etc/nginx/sites-available/default", nginx_config)?;
-    run_command("systemctl", &["reload", "nginx"])?;
+
+/// Returns the port an application server listens on, for applications that serve HTTP
+/// themselves (the Node.js and Python sample apps).
+///
+/// # Arguments
+///
+/// * `app` - A string slice representing the application
+///
+/// # Returns
+///
+/// Returns the port, or `None` if the application is not an application server.
+pub fn app_port(app: &str) -> Option<u16> {
+    match app {
+        "nodejs" => Some(3000),
+        "python" => Some(5000),
+        _ => None,
+    }
+}
+
+/// Configures a web server as a reverse proxy for an application server.
+///
+/// This function writes a virtual host forwarding requests for `domain` to `upstream`,
+/// checks the web server configuration and reloads the web server.
+///
+/// # Arguments
+///
+/// * `web` - The web server to configure ("nginx" or "apache")
+/// * `upstream` - The application server address, as `host:port`
+/// * `domain` - The domain name to proxy
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the reverse proxy is set up successfully, or an error if setup fails.
+pub fn setup_reverse_proxy(web: &str, upstream: &str, domain: &str) -> Result<(), Box<dyn Error>> {
+    Plan::build(|plan| plan_reverse_proxy(plan, web, upstream, domain))?.execute()
+}
+
+/// Plans configuring a web server as a reverse proxy, as `setup_reverse_proxy` does.
+///
+/// The virtual host is written to `/etc/nginx/conf.d/<domain>.conf` for nginx. For Apache
+/// it is written to `/etc/apache2/sites-available/<domain>.conf` and enabled together with
+/// the proxy modules on Debian-based distributions, or to `/etc/httpd/conf.d/<domain>.conf`.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `web` - The web server to configure ("nginx" or "apache")
+/// * `upstream` - The application server address, as `host:port`
+/// * `domain` - The domain name to proxy
+///
+/// # Errors
+///
+/// Returns an error if the web server is not supported, the upstream or domain is invalid,
+/// or the package manager cannot be detected.
+pub fn plan_reverse_proxy(
+    plan: &mut Plan,
+    web: &str,
+    upstream: &str,
+    domain: &str,
+) -> Result<(), Box<dyn Error>> {
+    let vhost = generate_reverse_proxy_config(web, upstream, domain)?;
+
+    if web == "nginx" {
+        let path = format!("/etc/nginx/conf.d/{}.conf", domain);
+        plan.write_file(&path, vhost)
+            .validate_config(ServiceType::Nginx, &path)
+            .reload_service("nginx");
+        return Ok(());
+    }
+
+    match get_package_manager()? {
+        PackageManager::Apt => {
+            let path = format!("/etc/apache2/sites-available/{}.conf", domain);
+            plan.write_file(&path, vhost)
+                .run("a2enmod", &["proxy", "proxy_http"])
+                .run("a2ensite", &[domain])
+                .validate_config(ServiceType::Apache, &path)
+                // Enabling modules needs a restart rather than a reload
+                .restart_service("apache2");
+        }
+        PackageManager::Yum | PackageManager::Dnf => {
+            let path = format!("/etc/httpd/conf.d/{}.conf", domain);
+            plan.write_file(&path, vhost)
+                .validate_config(ServiceType::Apache, &path)
+                .reload_service("httpd");
+        }
+    }
     Ok(())
 }
 
-/// Sets up the Apache web server configuration.
-/// This function configures the default Apache virtual host configuration.
+/// Generates a reverse proxy virtual host.
+///
+/// # Arguments
+///
+/// * `web` - The web server to generate the configuration for ("nginx" or "apache")
+/// * `upstream` - The application server address, as `host:port`
+/// * `domain` - The domain name to proxy
 ///
 /// # Returns
 ///
-/// Returns `Ok(())` if the Apache configuration is set up successfully, or an error if configuration fails.
-fn setup_apache_config() -> Result<(), Box<dyn Error>> {
-    let apache_config = r#"
-<VirtualHost *:80>
-    ServerAdmin webmaster@localhost
-    DocumentRoot /var/www/html
-    ErrorLog ${APACHE_LOG_DIR}/error.log
-    CustomLog ${APACHE_LOG_DIR}/access.log combined
+/// Returns the virtual host configuration, or an error if the web server is not supported,
+/// the upstream is not in `host:port` form or the domain is not a valid host name.
+pub fn generate_reverse_proxy_config(
+    web: &str,
+    upstream: &str,
+    domain: &str,
+) -> Result<String, Box<dyn Error>> {
+    if !is_host_port(upstream) {
+        return Err(format!("Invalid upstream '{}': expected host:port", upstream).into());
+    }
+    let valid_domain = !domain.is_empty()
+        && !domain.starts_with('.')
+        && domain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-');
+    if !valid_domain {
+        return Err(format!("Invalid domain '{}'", domain).into());
+    }
+
+    match web {
+        "nginx" => Ok(format!(
+            r#"server {{
+    listen 80;
+    listen [::]:80;
+    server_name {domain};
+
+    location / {{
+        proxy_pass http://{upstream};
+        proxy_http_version 1.1;
+        proxy_set_header Host $host;
+        proxy_set_header X-Real-IP $remote_addr;
+        proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;
+        proxy_set_header X-Forwarded-Proto $scheme;
+        proxy_set_header Upgrade $http_upgrade;
+        proxy_set_header Connection "upgrade";
+    }}
+}}
+"#
+        )),
+        "apache" => Ok(format!(
+            r#"<VirtualHost *:80>
+    ServerName {domain}
+    ProxyPreserveHost On
+    ProxyPass / http://{upstream}/
+    ProxyPassReverse / http://{upstream}/
 </VirtualHost>
-"#;
-    std::fs::write(
-        "/etc/apache2/sites-available/000-default.conf",
-        apache_config,
-    )?;
+"#
+        )),
+        _ => Err(format!("Unsupported web server: {}", web).into()),
+    }
+}
+
+/// Returns whether an address is in `host:port` form.
+fn is_host_port(address: &str) -> bool {
+    address
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+}
+
+/// Generates the HAProxy configuration.
+///
+/// The frontend listens on port 80 (and on 443 with TLS when `load_balancer_certificate`
+/// is set) and balances requests round-robin across `load_balancer_backends`, with health
+/// checks.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the load balancer settings
+///
+/// # Returns
+///
+/// Returns the contents of `haproxy.cfg`, or an error if there are no backends or a
+/// backend is not in `host:port` form.
+pub fn generate_haproxy_config(config: &Config) -> Result<String, Box<dyn Error>> {
+    if config.load_balancer_backends.is_empty() {
+        return Err("HAProxy needs at least one load balancer backend".into());
+    }
+
+    let mut haproxy_config = String::from(
+        r#"global
+    log /dev/log local0
+    user haproxy
+    group haproxy
+    daemon
+
+defaults
+    log global
+    mode http
+    option httplog
+    timeout connect 5s
+    timeout client 50s
+    timeout server 50s
 
-    if run_command("systemctl", &["reload", "apache2"]).is_err() {
-        run_command("systemctl", &["reload", "httpd"])?;
+frontend http_front
+    bind *:80
+"#,
+    );
+    if let Some(certificate) = &config.load_balancer_certificate {
+        haproxy_config.push_str(&format!("    bind *:443 ssl crt {}\n", certificate));
     }
+    haproxy_config.push_str(
+        "    default_backend app_servers\n\nbackend app_servers\n    balance roundrobin\n",
+    );
+
+    for (i, backend) in config.load_balancer_backends.iter().enumerate() {
+        if !is_host_port(backend) {
+            return Err(format!(
+                "Invalid load balancer backend '{}': expected host:port",
+                backend
+            )
+            .into());
+        }
+        haproxy_config.push_str(&format!("    server app{} {} check\n", i + 1, backend));
+    }
+
+    Ok(haproxy_config)
+}
+
+/// The name of the site `setup_web_server_config` adds next to the default one in
+/// `AddVhost` mode.
+pub const VHOST_NAME: &str = "serverforge";
+
+/// Sets up the web server configuration based on the specified application.
+/// This function configures a basic site for Nginx or Apache serving `/var/www/html` and
+/// reloads the web server, in its own rollback snapshot. Depending on the
+/// `web_server_config_mode`, the default site is replaced, kept if it exists, or left
+/// alone with a new site added next to it.
+///
+/// If the configuration test fails, the changes are rolled back before the web server is
+/// reloaded, so that it keeps serving its current configuration.
+///
+/// # Arguments
+///
+/// * `app` - The name of the application (e.g., "nginx" or "apache").
+/// * `config` - A reference to the `Config` struct containing the `web_server_config_mode` and `app_domain`
+/// * `rollback` - A reference to the `RollbackManager` for creating snapshots
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the web server configuration is set up successfully, or an error if configuration fails.
+pub fn setup_web_server_config(
+    app: &str,
+    config: &Config,
+    rollback: &RollbackManager,
+) -> Result<(), Box<dyn Error>> {
+    let plan = Plan::build(|plan| plan_web_server_config(plan, app, config))?;
+    let snapshot = rollback.create_snapshot("Web server configuration")?;
+    if let Err(e) = plan.execute_with_rollback(rollback, snapshot) {
+        // Rolling back restores the files that existed before, but an added site is new
+        if config.web_server_config_mode == WebServerConfigMode::AddVhost {
+            disable_vhost(app)?;
+        }
+        rollback.rollback_to(snapshot)?;
+        return Err(e);
+    }
+    rollback.commit_snapshot(snapshot)
+}
+
+/// Disables the site added in `AddVhost` mode, so that the web server no longer loads it.
+fn disable_vhost(app: &str) -> Result<(), Box<dyn Error>> {
+    if app == "nginx" {
+        let link = format!("/etc/nginx/sites-enabled/{}", VHOST_NAME);
+        return run_command("rm", &["-f", &link]);
+    }
+    match get_package_manager()? {
+        PackageManager::Apt => run_command("a2dissite", &[VHOST_NAME]),
+        PackageManager::Yum | PackageManager::Dnf => {
+            let path = format!("/etc/httpd/conf.d/{}.conf", VHOST_NAME);
+            run_command("rm", &["-f", &path])
+        }
+    }
+}
+
+/// Plans the web server configuration, as `setup_web_server_config` does.
+///
+/// - `Replace` overwrites the default site (`/etc/nginx/sites-available/default`, or
+///   `000-default.conf` for Apache); when the plan is executed with rollback, its original
+///   contents are recorded first.
+/// - `KeepExisting` writes the default site only if there is none, and otherwise plans
+///   nothing.
+/// - `AddVhost` writes a site named `VHOST_NAME` for `app_domain` (or any name) instead.
+///
+/// The site written is enabled, by creating or refreshing its `sites-enabled` symlink for
+/// nginx (on a fresh install it may be missing or point elsewhere) or with `a2ensite` for
+/// Apache on Debian-based distributions, and the configuration is tested with `nginx -t`
+/// or `apachectl configtest` before the web server is reloaded.
+///
+/// Apache sites are written to `/etc/httpd/conf.d` on other distributions.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `app` - The web server to configure ("nginx" or "apache")
+/// * `config` - A reference to the `Config` struct containing the `web_server_config_mode` and `app_domain`
+///
+/// # Errors
+///
+/// Returns an error if the web server is not supported or the package manager cannot be
+/// detected.
+pub fn plan_web_server_config(
+    plan: &mut Plan,
+    app: &str,
+    config: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let mode = config.web_server_config_mode;
+    let server_name = config.app_domain.as_deref();
+    match app {
+        "nginx" => plan_nginx_config(plan, mode, server_name),
+        "apache" => plan_apache_config(plan, mode, server_name)?,
+        _ => return Err(format!("Unsupported web server: {}", app).into()),
+    }
+    Ok(())
+}
+
+/// Plans the Nginx site.
+fn plan_nginx_config(plan: &mut Plan, mode: WebServerConfigMode, server_name: Option<&str>) {
+    let (name, site) = match mode {
+        // Only one site can be the default server
+        WebServerConfigMode::AddVhost => (VHOST_NAME, nginx_site("", server_name.unwrap_or("_"))),
+        _ => ("default", nginx_site(" default_server", "_")),
+    };
+    let path = format!("/etc/nginx/sites-available/{}", name);
+    if mode == WebServerConfigMode::KeepExisting && path_exists(&path) {
+        info!("Keeping the existing default site {}", path);
+        return;
+    }
+    plan.write_file(&path, site)
+        .run(
+            "ln",
+            &["-sfn", &path, &format!("/etc/nginx/sites-enabled/{}", name)],
+        )
+        .validate_config(ServiceType::Nginx, &path)
+        .reload_service("nginx");
+}
+
+/// Renders an Nginx site serving `/var/www/html`.
+fn nginx_site(listen_options: &str, server_name: &str) -> String {
+    format!(
+        r#"
+server {{
+    listen 80{listen_options};
+    listen [::]:80{listen_options};
+    root /var/www/html;
+    index index.html index.htm index.nginx-debian.html;
+    server_name {server_name};
+    location / {{
+        try_files $uri $uri/ =404;
+    }}
+}}
+"#
+    )
+}
+
+/// Plans the Apache site.
+fn plan_apache_config(
+    plan: &mut Plan,
+    mode: WebServerConfigMode,
+    server_name: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let package_manager = get_package_manager()?;
+    let service = service_name_for(&package_manager, "apache");
+    let (sites_dir, log_dir) = match package_manager {
+        PackageManager::Apt => ("/etc/apache2/sites-available", "${APACHE_LOG_DIR}"),
+        // Relative to the server root, /etc/httpd
+        PackageManager::Yum | PackageManager::Dnf => ("/etc/httpd/conf.d", "logs"),
+    };
+
+    let (name, server_name) = match mode {
+        WebServerConfigMode::AddVhost => (VHOST_NAME, server_name),
+        _ => ("000-default", None),
+    };
+    let path = format!("{}/{}.conf", sites_dir, name);
+    if mode == WebServerConfigMode::KeepExisting && path_exists(&path) {
+        info!("Keeping the existing default site {}", path);
+        return Ok(());
+    }
+    plan.write_file(&path, apache_site(server_name, log_dir));
+    if package_manager == PackageManager::Apt {
+        plan.run("a2ensite", &[name]);
+    }
+    plan.validate_config(ServiceType::Apache, &path)
+        .reload_service(service);
     Ok(())
 }
 
+/// Renders an Apache virtual host serving `/var/www/html`.
+fn apache_site(server_name: Option<&str>, log_dir: &str) -> String {
+    let server_name = server_name
+        .map(|name| format!("    ServerName {}\n", name))
+        .unwrap_or_default();
+    format!(
+        r#"
+<VirtualHost *:80>
+{server_name}    ServerAdmin webmaster@localhost
+    DocumentRoot /var/www/html
+    ErrorLog {log_dir}/error.log
+    CustomLog {log_dir}/access.log combined
+</VirtualHost>
+"#
+    )
+}
+
 /// Sets up the database based on the specified database type.
 /// This function sets up the MySQL or PostgreSQL database server by running the necessary
 ///
 /// # Arguments
 ///
 /// * `db` - A string slice representing the type of database to set up ("mysql" or "postgresql")
+/// * `config` - A reference to the `Config` struct containing the secret store settings
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` if the database is set up successfully, or an error if setting up fails.
-pub fn setup_database(db: &str) -> Result<(), Box<dyn Error>> {
+pub fn setup_database(db: &str, config: &Config) -> Result<(), Box<dyn Error>> {
+    let store = secret_store(config)?;
     match db {
-        "mysql" => setup_mysql()?,
-        "postgresql" => setup_postgresql()?,
+        "mysql" => setup_mysql(store.as_ref())?,
+        "postgresql" => setup_postgresql(store.as_ref())?,
         _ => return Err(format!("Unsupported database: {}", db).into()),
     }
     Ok(())
@@ -379,11 +1526,12 @@ pub fn setup_database(db: &str) -> Result<(), Box<dyn Error>> {
 
 /// Sets up the MySQL database server.
 /// This function sets the root password, removes anonymous users, and flushes privileges.
+/// The password is saved to the secret store.
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` if the MySQL server is set up successfully, or an error if setting up fails.
-fn setup_mysql() -> Result<(), Box<dyn Error>> {
+fn setup_mysql(store: &dyn SecretStore) -> Result<(), Box<dyn Error>> {
     // Generate a secure random password
     let password = generate_secure_password();
 
@@ -401,20 +1549,18 @@ fn setup_mysql() -> Result<(), Box<dyn Error>> {
     run_command("mysql", &["-e", "DELETE FROM mysql.user WHERE User='';"])?;
     run_command("mysql", &["-e", "FLUSH PRIVILEGES;"])?;
 
-    // Save the password securely (this is a placeholder - in a real-world scenario,
-    // you'd want to use a more secure method to store this password)
-    std::fs::write("/root/.mysql_root_password", &password)?;
+    store.put(MYSQL_ROOT_PASSWORD, &password)?;
 
     Ok(())
 }
 
 /// Sets up the PostgreSQL database server.
-/// This function sets the password for the postgres user and saves it securely.
+/// This function sets the password for the postgres user and saves it to the secret store.
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` if the PostgreSQL server is set up successfully, or an error if setting up fails.
-fn setup_postgresql() -> Result<(), Box<dyn Error>> {
+fn setup_postgresql(store: &dyn SecretStore) -> Result<(), Box<dyn Error>> {
     // Generate a secure random password
     let password = generate_secure_password();
 
@@ -430,39 +1576,11 @@ fn setup_postgresql() -> Result<(), Box<dyn Error>> {
         ],
     )?;
 
-    // Save the password securely
-    // you'd want to use a more secure method to store this password)
-    std::fs::write("/root/.postgres_password", &password)?;
+    store.put(POSTGRES_PASSWORD, &password)?;
 
     Ok(())
 }
 
-/// Generates a secure random password.
-///
-/// This function creates a random password of 20 characters, including uppercase and lowercase
-/// letters, numbers, and special characters.
-///
-/// # Returns
-///
-/// Returns a `String` containing the generated password.
-fn generate_secure_password() -> String {
-    use rand::Rng;
-    const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
-                            abcdefghijklmnopqrstuvwxyz\
-                            0123456789)(*&^%$#@!~";
-    const PASSWORD_LEN: usize = 20;
-    let mut rng = rand::thread_rng();
-
-    let password: String = (0..PASSWORD_LEN)
-        .map(|_| {
-            let idx = rng.gen_range(0..CHARSET.len());
-            CHARSET[idx] as char
-        })
-        .collect();
-
-    password
-}
-
 /// Creates a sample web application based on the specified application type.
 ///
 /// This function creates a basic "Hello, World!" application for PHP, Node.js, or Python,
@@ -483,7 +1601,7 @@ fn create_sample_web_app(app_type: &str) -> Result<(), Box<dyn Error>> {
 echo "Hello, World! This is a sample PHP application.";
 ?>
 "#;
-            std::fs::write("/var/www/html/index.php", php_content)?;
+            write_file("/var/www/html/index.php", php_content)?;
         }
         "nodejs" => {
             let node_content = r#"
@@ -497,7 +1615,7 @@ server.listen(3000, '127.0.0.1', () => {
   console.log('Server running on http://127.0.0.1:3000/');
 });
 "#;
-            std::fs::write("/root/app.js", node_content)?;
+            write_file("/root/app.js", node_content)?;
             run_command("pm2", &["start", "/root/app.js"])?;
         }
         "python" => {
@@ -512,7 +1630,7 @@ def hello_world():
 if __name__ == '__main__':
     app.run(host='0.0.0.0', port=5000)
 "#;
-            std::fs::write("/root/app.py", python_content)?;
+            write_file("/root/app.py", python_content)?;
             run_command("pip3", &["install", "flask"])?;
             run_command("python3", &["/root/app.py", "&"])?;
         }
@@ -534,27 +1652,8 @@ if __name__ == '__main__':
 ///
 /// Returns `Ok(())` if firewall rules are set up successfully, or an error if setup fails.
 fn setup_firewall_rules(config: &Config) -> Result<(), Box<dyn Error>> {
-    let package_manager = get_package_manager()?;
-
-    match package_manager {
-        PackageManager::Apt => {
-            run_command("ufw", &["allow", "OpenSSH"])?;
-            run_command("ufw", &["allow", "80/tcp"])?;
-            run_command("ufw", &["allow", "443/tcp"])?;
-            for rule in &config.custom_firewall_rules {
-                run_command("ufw", &["allow", rule])?;
-            }
-            run_command("ufw", &["enable"])?;
-        }
-        PackageManager::Yum | PackageManager::Dnf => {
-            run_command("firewall-cmd", &["--permanent", "--add-service=ssh"])?;
-            run_command("firewall-cmd", &["--permanent", "--add-service=http"])?;
-            run_command("firewall-cmd", &["--permanent", "--add-service=https"])?;
-            for rule in &config.custom_firewall_rules {
-                run_command("firewall-cmd", &["--permanent", "--add-port=", rule])?;
-            }
-            run_command("firewall-cmd", &["--reload"])?;
-        }
-    }
-    Ok(())
+    let firewall = firewall::for_package_manager(&get_package_manager()?);
+    let mut ports = vec![String::from("80/tcp"), String::from("443/tcp")];
+    ports.extend(config.custom_firewall_rules.iter().cloned());
+    Plan::build(|plan| firewall::plan_rules(plan, firewall.as_ref(), &["ssh"], &ports))?.execute()
 }
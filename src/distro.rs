@@ -2,10 +2,14 @@
 //!
 //! This module provides functionality for interacting with different Linux distributions
 //! and their package managers. It includes functions for detecting the package manager,
-//! updating the system, and installing or uninstalling packages.
+//! updating the system, adding package repositories, installing or uninstalling packages,
+//! and managing services, whose names can differ between distributions.
 
+use crate::rollback::RollbackManager;
+use crate::runner::current_runner;
+use crate::utils::path_exists;
 use std::error::Error;
-use std::path::Path;
+use std::time::{Duration, Instant};
 
 /// Represents the different package managers supported by the application.
 #[derive(Debug, PartialEq)]
@@ -18,19 +22,21 @@ pub enum PackageManager {
 /// Detects the package manager used by the current system.
 ///
 /// This function checks for the existence of specific package manager
-/// executables to determine which one is available on the system.
+/// executables to determine which one is available on the system being configured.
+/// DNF is checked before YUM, since systems using DNF usually provide `/usr/bin/yum` as a
+/// link to it.
 ///
 /// # Returns
 ///
 /// Returns a `Result` containing the detected `PackageManager` or an error
 /// if no supported package manager is found.
 pub fn get_package_manager() -> Result<PackageManager, Box<dyn Error>> {
-    if Path::new("/usr/bin/apt").exists() {
+    if path_exists("/usr/bin/apt") {
         Ok(PackageManager::Apt)
-    } else if Path::new("/usr/bin/yum").exists() {
-        Ok(PackageManager::Yum)
-    } else if Path::new("/usr/bin/dnf").exists() {
+    } else if path_exists("/usr/bin/dnf") {
         Ok(PackageManager::Dnf)
+    } else if path_exists("/usr/bin/yum") {
+        Ok(PackageManager::Yum)
     } else {
         Err("Unsupported package manager".into())
     }
@@ -50,14 +56,25 @@ pub fn get_package_manager() -> Result<PackageManager, Box<dyn Error>> {
 pub fn update_system(package_manager: &PackageManager) -> Result<(), Box<dyn Error>> {
     match package_manager {
         PackageManager::Apt => {
-            crate::utils::run_command("apt", &["update"])?;
-            crate::utils::run_command("apt", &["upgrade", "-y"])?;
+            crate::utils::run_command_streaming("apt", &["update"])?;
+            // Keep locally modified configuration files instead of prompting for each one
+            crate::utils::run_command_streaming(
+                "apt",
+                &[
+                    "upgrade",
+                    "-y",
+                    "-o",
+                    "Dpkg::Options::=--force-confdef",
+                    "-o",
+                    "Dpkg::Options::=--force-confold",
+                ],
+            )?;
         }
         PackageManager::Yum => {
-            crate::utils::run_command("yum", &["update", "-y"])?;
+            crate::utils::run_command_streaming("yum", &["update", "-y"])?;
         }
         PackageManager::Dnf => {
-            crate::utils::run_command("dnf", &["upgrade", "-y"])?;
+            crate::utils::run_command_streaming("dnf", &["upgrade", "-y"])?;
         }
     }
     Ok(())
@@ -87,6 +104,103 @@ pub fn install_package(
     Ok(())
 }
 
+/// Checks whether a package is installed.
+///
+/// # Arguments
+///
+/// * `package_manager` - A reference to the `PackageManager` enum representing the system's package manager.
+/// * `package` - A string slice containing the name of the package.
+///
+/// # Returns
+///
+/// Returns a `Result` containing whether the package is installed, or an error if the
+/// package database cannot be queried.
+pub fn is_package_installed(
+    package_manager: &PackageManager,
+    package: &str,
+) -> Result<bool, Box<dyn Error>> {
+    let runner = current_runner();
+    // Both commands exit with a non-zero status for unknown packages, which is not an error
+    match package_manager {
+        PackageManager::Apt => {
+            let output = runner.run("dpkg-query", &["-W", "-f=${Status}", package], &[])?;
+            Ok(output.stdout.trim() == "install ok installed")
+        }
+        PackageManager::Yum | PackageManager::Dnf => {
+            Ok(runner.run("rpm", &["-q", package], &[])?.success)
+        }
+    }
+}
+
+/// Returns the version of an installed package.
+///
+/// # Arguments
+///
+/// * `package_manager` - A reference to the `PackageManager` enum representing the system's package manager.
+/// * `package` - A string slice containing the name of the package.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the version (e.g., "1.18.0-6ubuntu14"), `None` if the
+/// package is not installed, or an error if the package database cannot be queried.
+pub fn get_installed_version(
+    package_manager: &PackageManager,
+    package: &str,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let runner = current_runner();
+    let version = match package_manager {
+        PackageManager::Apt => {
+            // Removed packages whose configuration files are left still have a version
+            let output = runner.run(
+                "dpkg-query",
+                &["-W", "-f=${Status} ${Version}", package],
+                &[],
+            )?;
+            output
+                .stdout
+                .trim()
+                .strip_prefix("install ok installed ")
+                .map(str::to_string)
+        }
+        PackageManager::Yum | PackageManager::Dnf => {
+            let output = runner.run("rpm", &["-q", "--qf", "%{VERSION}", package], &[])?;
+            output.success.then(|| output.stdout.trim().to_string())
+        }
+    };
+    Ok(version.filter(|version| !version.is_empty()))
+}
+
+/// Installs a package and records it in a rollback snapshot, so that rolling the snapshot
+/// back uninstalls it.
+///
+/// The package is only recorded once it is installed, and only if it was not installed
+/// before, so that a rollback never removes software that was already on the host.
+///
+/// # Arguments
+///
+/// * `package_manager` - A reference to the `PackageManager` enum representing the system's package manager.
+/// * `package` - A string slice containing the name of the package to install.
+/// * `rollback` - The `RollbackManager` to record the package in
+/// * `snapshot_id` - The ID of the snapshot to record the package in
+///
+/// # Returns
+///
+/// Returns a `Result` indicating success or an error if the installation fails or the
+/// package cannot be recorded.
+pub fn install_package_tracked(
+    package_manager: &PackageManager,
+    package: &str,
+    rollback: &RollbackManager,
+    snapshot_id: usize,
+) -> Result<(), Box<dyn Error>> {
+    let already_installed = is_package_installed(package_manager, package)?;
+    install_package(package_manager, package)?;
+    if already_installed {
+        return Ok(());
+    }
+    rollback.add_package_installed(snapshot_id, package)
+}
+
 /// Uninstalls a package using the specified package manager.
 ///
 /// This function runs the appropriate remove command for the given package manager.
@@ -110,3 +224,268 @@ pub fn uninstall_package(
     }
     Ok(())
 }
+
+/// Services whose units are named differently on Debian-based and Red Hat-based
+/// distributions, as `(logical name, APT name, YUM/DNF name)`.
+const SERVICE_NAMES: [(&str, &str, &str); 3] = [
+    ("apache", "apache2", "httpd"),
+    ("chrony", "chrony", "chronyd"),
+    ("mysql", "mysql", "mysqld"),
+];
+
+/// How long `start_service` and `restart_service` wait for a service to become active.
+pub const SERVICE_START_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often `wait_for_service` checks whether a service is active.
+const SERVICE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Returns the name of a service's unit on the distributions of a package manager.
+///
+/// # Arguments
+///
+/// * `package_manager` - A reference to the `PackageManager` enum representing the system's package manager.
+/// * `name` - A logical service name such as "apache", or its unit name on any distribution
+///   (e.g., "apache2" or "httpd")
+///
+/// # Returns
+///
+/// Returns the unit name (e.g., "httpd" for "apache" on DNF), or `name` itself for
+/// services named alike everywhere.
+pub fn service_name_for<'a>(package_manager: &PackageManager, name: &'a str) -> &'a str {
+    SERVICE_NAMES
+        .iter()
+        .find(|(logical, apt, rpm)| [logical, apt, rpm].contains(&&name))
+        .map_or(name, |(_, apt, rpm)| match package_manager {
+            PackageManager::Apt => apt,
+            PackageManager::Yum | PackageManager::Dnf => rpm,
+        })
+}
+
+/// Starts a service and waits for it to become active.
+///
+/// # Arguments
+///
+/// * `name` - The name of the service's unit
+///
+/// # Returns
+///
+/// Returns a `Result` indicating success, or an error if the service cannot be started or
+/// is not active within `SERVICE_START_TIMEOUT`.
+pub fn start_service(name: &str) -> Result<(), Box<dyn Error>> {
+    crate::utils::run_command("systemctl", &["start", name])?;
+    wait_for_service(name, SERVICE_START_TIMEOUT)
+}
+
+/// Enables a service at boot, without starting it.
+///
+/// # Arguments
+///
+/// * `name` - The name of the service's unit
+///
+/// # Returns
+///
+/// Returns a `Result` indicating success or an error if the service cannot be enabled.
+pub fn enable_service(name: &str) -> Result<(), Box<dyn Error>> {
+    crate::utils::run_command("systemctl", &["enable", name])
+}
+
+/// Enables a service at boot, then starts it and waits for it to become active.
+///
+/// # Arguments
+///
+/// * `name` - The name of the service's unit
+///
+/// # Returns
+///
+/// Returns a `Result` indicating success, or an error if the service cannot be enabled or
+/// started, or is not active within `SERVICE_START_TIMEOUT`.
+pub fn enable_and_start_service(name: &str) -> Result<(), Box<dyn Error>> {
+    enable_service(name)?;
+    start_service(name)
+}
+
+/// Restarts a service, so that it picks up its new configuration, and waits for it to
+/// become active again.
+///
+/// # Arguments
+///
+/// * `name` - The name of the service's unit
+///
+/// # Returns
+///
+/// Returns a `Result` indicating success, or an error if the service cannot be restarted
+/// or is not active within `SERVICE_START_TIMEOUT`.
+pub fn restart_service(name: &str) -> Result<(), Box<dyn Error>> {
+    crate::utils::run_command("systemctl", &["restart", name])?;
+    wait_for_service(name, SERVICE_START_TIMEOUT)
+}
+
+/// Waits for a service that is starting to become active.
+///
+/// A service still starting (or restarting after a crash) is checked again every second.
+/// Any other state, such as "failed", is an error right away.
+///
+/// # Arguments
+///
+/// * `name` - The name of the service's unit
+/// * `timeout` - How long to wait for the service
+///
+/// # Returns
+///
+/// Returns `Ok(())` once the service is active, or an error naming its last state.
+pub fn wait_for_service(name: &str, timeout: Duration) -> Result<(), Box<dyn Error>> {
+    let runner = current_runner();
+    let started = Instant::now();
+    loop {
+        // is-active exits with a non-zero status for every state but active
+        let output = runner.run("systemctl", &["is-active", name], &[])?;
+        let state = output.stdout.trim();
+        match state {
+            "active" => return Ok(()),
+            "activating" | "reloading" if started.elapsed() < timeout => {
+                std::thread::sleep(SERVICE_POLL_INTERVAL)
+            }
+            _ => {
+                return Err(format!(
+                    "Service {} is {} instead of active (see journalctl -u {})",
+                    name,
+                    if state.is_empty() { "unknown" } else { state },
+                    name
+                )
+                .into())
+            }
+        }
+    }
+}
+
+/// Where a package repository is published and how its packages are signed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepositorySpec {
+    /// A short name for the repository (e.g., "docker"), used for its keyring and source
+    /// files and as its YUM repository ID
+    pub name: String,
+    /// The URL of the repository: the APT archive root, or the YUM/DNF `baseurl` (which
+    /// may use `$releasever` and `$basearch`)
+    pub url: String,
+    /// The URL of the ASCII-armored GPG key the packages are signed with
+    pub gpg_key_url: String,
+    /// The APT suite: a distribution codename (e.g., "jammy") or a fixed suite such as
+    /// "stable"; `None` uses the codename of the host. Not used by YUM/DNF.
+    pub codename: Option<String>,
+    /// The APT components (e.g., `["stable"]` or `["main"]`)
+    pub components: Vec<String>,
+    /// The architecture APT limits the repository to (e.g., "amd64"); `None` keeps the
+    /// architectures of the host
+    pub arch: Option<String>,
+}
+
+impl RepositorySpec {
+    /// Returns the keyring the GPG key is saved to on APT systems.
+    pub fn keyring_path(&self) -> String {
+        format!("/usr/share/keyrings/{}-archive-keyring.gpg", self.name)
+    }
+
+    /// Returns the file the repository is written to for a package manager.
+    pub fn source_path(&self, package_manager: &PackageManager) -> String {
+        match package_manager {
+            PackageManager::Apt => format!("/etc/apt/sources.list.d/{}.list", self.name),
+            PackageManager::Yum | PackageManager::Dnf => {
+                format!("/etc/yum.repos.d/{}.repo", self.name)
+            }
+        }
+    }
+
+    /// Returns the line of the APT sources list of the repository.
+    ///
+    /// # Arguments
+    ///
+    /// * `codename` - The suite to use when `codename` is not set
+    pub fn apt_source(&self, codename: &str) -> String {
+        let mut options = Vec::new();
+        if let Some(arch) = &self.arch {
+            options.push(format!("arch={}", arch));
+        }
+        options.push(format!("signed-by={}", self.keyring_path()));
+        format!(
+            "deb [{}] {} {} {}\n",
+            options.join(" "),
+            self.url,
+            self.codename.as_deref().unwrap_or(codename),
+            self.components.join(" ")
+        )
+    }
+
+    /// Returns the YUM/DNF `.repo` file of the repository, which checks package signatures
+    /// against its GPG key.
+    pub fn yum_repo(&self) -> String {
+        format!(
+            "[{name}]\nname={name}\nbaseurl={url}\nenabled=1\ngpgcheck=1\ngpgkey={key}\n",
+            name = self.name,
+            url = self.url,
+            key = self.gpg_key_url
+        )
+    }
+}
+
+/// Adds a package repository, replacing an earlier copy of it, so that running it again
+/// changes nothing.
+///
+/// On APT systems, the GPG key is downloaded, converted into a keyring that only this
+/// repository trusts, and the package lists are refreshed. YUM and DNF get a `.repo` file
+/// and import the key when they first install from it. Neither needs `curl` and `gpg` to
+/// run in a shell pipeline, so a failed download is reported instead of leaving an empty
+/// keyring behind.
+///
+/// # Arguments
+///
+/// * `package_manager` - A reference to the `PackageManager` enum representing the system's package manager.
+/// * `spec` - The repository to add
+///
+/// # Returns
+///
+/// Returns a `Result` indicating success, or an error if the key cannot be downloaded or
+/// the repository cannot be written.
+pub fn add_repository(
+    package_manager: &PackageManager,
+    spec: &RepositorySpec,
+) -> Result<(), Box<dyn Error>> {
+    match package_manager {
+        PackageManager::Apt => {
+            let codename = match &spec.codename {
+                Some(codename) => codename.clone(),
+                None => crate::utils::command_output("lsb_release", &["-cs"])?
+                    .trim()
+                    .to_string(),
+            };
+            let key_file = crate::utils::command_output("mktemp", &[])?;
+            let key_file = key_file.trim();
+            let imported =
+                crate::utils::run_command("curl", &["-fsSL", "-o", key_file, &spec.gpg_key_url])
+                    .and_then(|_| {
+                        // gpg asks before overwriting the keyring of a previous run unless given --yes
+                        crate::utils::run_command(
+                            "gpg",
+                            &[
+                                "--batch",
+                                "--yes",
+                                "--dearmor",
+                                "-o",
+                                &spec.keyring_path(),
+                                key_file,
+                            ],
+                        )
+                    });
+            crate::utils::run_command("rm", &["-f", key_file])?;
+            imported?;
+            crate::utils::write_file(
+                spec.source_path(package_manager),
+                spec.apt_source(&codename),
+            )?;
+            crate::utils::run_command("apt", &["update"])?;
+        }
+        PackageManager::Yum | PackageManager::Dnf => {
+            crate::utils::write_file(spec.source_path(package_manager), spec.yum_repo())?;
+        }
+    }
+    Ok(())
+}
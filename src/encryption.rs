@@ -0,0 +1,174 @@
+//! # Encryption Module
+//!
+//! This module encrypts configuration files at rest with a symmetric key, using
+//! ChaCha20-Poly1305 from `ring`.
+//!
+//! The key is 32 bytes written as 64 hexadecimal digits (e.g., generated with
+//! `openssl rand -hex 32`). It is taken from the `SERVERFORGE_CONFIG_KEY` environment
+//! variable, or from the file named by `SERVERFORGE_CONFIG_KEY_FILE`, on the machine running
+//! `server_forge`, so it never has to be stored on the hosts it configures.
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::error::Error;
+
+/// The environment variable holding the key, as hexadecimal digits.
+///
+/// It does not start with `SERVER_FORGE_`, which `Config::from_env` reserves for options.
+pub const KEY_ENV: &str = "SERVERFORGE_CONFIG_KEY";
+
+/// The environment variable naming a file that holds the key, used when `KEY_ENV` is unset.
+pub const KEY_FILE_ENV: &str = "SERVERFORGE_CONFIG_KEY_FILE";
+
+/// The length of a key in bytes.
+pub const KEY_LEN: usize = 32;
+
+/// The header encrypted files start with, followed by the nonce and the ciphertext.
+const MAGIC: &[u8] = b"serverforge-encrypted-v1\n";
+
+/// A key to encrypt and decrypt files with.
+pub type Key = [u8; KEY_LEN];
+
+/// Parses a key written as hexadecimal digits.
+///
+/// # Arguments
+///
+/// * `text` - The 64 hexadecimal digits of the key; surrounding whitespace is ignored
+///
+/// # Errors
+///
+/// Returns an error if the text is not 64 hexadecimal digits.
+pub fn parse_key(text: &str) -> Result<Key, Box<dyn Error>> {
+    let text = text.trim();
+    let invalid = || format!("Invalid key: expected {} hexadecimal digits", KEY_LEN * 2);
+    if text.len() != KEY_LEN * 2 || !text.is_ascii() {
+        return Err(invalid().into());
+    }
+    let mut key = [0u8; KEY_LEN];
+    for (byte, digits) in key.iter_mut().zip(text.as_bytes().chunks(2)) {
+        let digits = std::str::from_utf8(digits)?;
+        *byte = u8::from_str_radix(digits, 16).map_err(|_| invalid())?;
+    }
+    Ok(key)
+}
+
+/// Returns the key of `KEY_ENV`, or of the file named by `KEY_FILE_ENV`.
+///
+/// # Returns
+///
+/// Returns the key, or `None` if neither variable is set.
+///
+/// # Errors
+///
+/// Returns an error if the key file cannot be read or the key is invalid.
+pub fn config_key() -> Result<Option<Key>, Box<dyn Error>> {
+    if let Some(key) = std::env::var(KEY_ENV).ok().filter(|key| !key.is_empty()) {
+        return parse_key(&key)
+            .map(Some)
+            .map_err(|e| format!("{} is invalid: {}", KEY_ENV, e).into());
+    }
+    let Some(path) = std::env::var(KEY_FILE_ENV)
+        .ok()
+        .filter(|path| !path.is_empty())
+    else {
+        return Ok(None);
+    };
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read key file {}: {}", path, e))?;
+    parse_key(&contents)
+        .map(Some)
+        .map_err(|e| format!("Key file {} is invalid: {}", path, e).into())
+}
+
+/// Returns the key of `config_key`, failing when none is set.
+///
+/// # Arguments
+///
+/// * `purpose` - What the key is needed for, used in the error message
+///
+/// # Errors
+///
+/// Returns an error if no key is set or the key is invalid.
+pub fn require_config_key(purpose: &str) -> Result<Key, Box<dyn Error>> {
+    config_key()?.ok_or_else(|| {
+        format!(
+            "A key is needed to {}: set {} or {}",
+            purpose, KEY_ENV, KEY_FILE_ENV
+        )
+        .into()
+    })
+}
+
+/// Returns whether data was encrypted by `encrypt`.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Encrypts data with a key and a random nonce.
+///
+/// # Arguments
+///
+/// * `key` - The key to encrypt with
+/// * `plaintext` - The data to encrypt
+///
+/// # Returns
+///
+/// Returns the header, the nonce and the authenticated ciphertext.
+///
+/// # Errors
+///
+/// Returns an error if no random nonce can be generated.
+pub fn encrypt(key: &Key, plaintext: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| "Failed to generate a random nonce")?;
+
+    let mut sealed = plaintext.to_vec();
+    aead_key(key)?
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(MAGIC),
+            &mut sealed,
+        )
+        .map_err(|_| "Failed to encrypt")?;
+
+    let mut encrypted = MAGIC.to_vec();
+    encrypted.extend_from_slice(&nonce);
+    encrypted.extend_from_slice(&sealed);
+    Ok(encrypted)
+}
+
+/// Decrypts data encrypted by `encrypt`.
+///
+/// # Arguments
+///
+/// * `key` - The key the data was encrypted with
+/// * `data` - The encrypted data
+///
+/// # Errors
+///
+/// Returns an error if the data was not encrypted by `encrypt`, was encrypted with another
+/// key, or was modified.
+pub fn decrypt(key: &Key, data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let sealed = data
+        .strip_prefix(MAGIC)
+        .ok_or("The data is not encrypted")?;
+    if sealed.len() < NONCE_LEN {
+        return Err("The encrypted data is truncated".into());
+    }
+    let (nonce, sealed) = sealed.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| "Invalid nonce")?;
+
+    let mut opened = sealed.to_vec();
+    let plaintext = aead_key(key)?
+        .open_in_place(nonce, Aad::from(MAGIC), &mut opened)
+        .map_err(|_| "Failed to decrypt: wrong key or modified data")?;
+    Ok(plaintext.to_vec())
+}
+
+/// Returns the ChaCha20-Poly1305 key of a key.
+fn aead_key(key: &Key) -> Result<LessSafeKey, Box<dyn Error>> {
+    let key = UnboundKey::new(&CHACHA20_POLY1305, key).map_err(|_| "Invalid key length")?;
+    Ok(LessSafeKey::new(key))
+}
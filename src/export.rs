@@ -0,0 +1,248 @@
+//! # Export Module
+//!
+//! This module renders a plan built by the `plan` module in formats other tools can
+//! review and apply: an Ansible playbook, or a cloud-init `#cloud-config` document for
+//! baking the configuration into new instances.
+
+use crate::plan::{Operation, Plan};
+use crate::utils::shell_quote;
+use serde_json::{json, Value};
+use std::error::Error;
+
+/// Renders plans as an Ansible playbook.
+///
+/// Each `(hosts, plan)` pair becomes a play targeting `hosts`, run with `become`.
+/// Package, service, file and directory operations map to the corresponding Ansible
+/// modules, as do `systemctl daemon-reload` and `chmod +x`; any other command becomes a
+/// `command` task. Commands given a secret on their standard input are only exported with
+/// `include_secrets`, and are then run with `no_log`, so that Ansible does not print it.
+///
+/// # Arguments
+///
+/// * `plays` - The host pattern and plan of each play
+/// * `include_secrets` - Whether to write the secrets of the plans into the playbook
+///
+/// # Returns
+///
+/// Returns the playbook as YAML, or an error if a plan passes a secret and `include_secrets`
+/// is not set, or if serialization fails.
+pub fn ansible_playbook(
+    plays: &[(String, Plan)],
+    include_secrets: bool,
+) -> Result<String, Box<dyn Error>> {
+    if !include_secrets {
+        for (_, plan) in plays {
+            check_no_secrets(plan)?;
+        }
+    }
+    let plays: Vec<Value> = plays
+        .iter()
+        .map(|(hosts, plan)| {
+            json!({
+                "name": "ServerForge setup",
+                "hosts": hosts,
+                "become": true,
+                "tasks": plan.operations().iter().map(ansible_task).collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+    Ok(serde_yaml::to_string(&plays)?)
+}
+
+/// Renders a plan as a cloud-init `#cloud-config` document.
+///
+/// Package installs are collected into `packages` (with `package_upgrade` set when the
+/// plan upgrades the system), file writes become `write_files` entries, and every other
+/// operation becomes a `runcmd` entry, in order. Commands given a secret on their standard
+/// input are only exported with `include_secrets`, and are then piped it by a shell
+/// `runcmd` entry.
+/// Note that cloud-init writes files before installing packages and running commands.
+///
+/// # Arguments
+///
+/// * `plan` - The plan of a single host
+/// * `include_secrets` - Whether to write the secrets of the plan into the document
+///
+/// # Returns
+///
+/// Returns the document as YAML, or an error if the plan passes a secret and
+/// `include_secrets` is not set, or if serialization fails.
+pub fn cloud_config(plan: &Plan, include_secrets: bool) -> Result<String, Box<dyn Error>> {
+    if !include_secrets {
+        check_no_secrets(plan)?;
+    }
+    let mut packages: Vec<&str> = Vec::new();
+    let mut package_upgrade = false;
+    let mut write_files = Vec::new();
+    let mut runcmd = Vec::new();
+
+    for operation in plan.operations() {
+        match operation {
+            Operation::UpgradePackages => package_upgrade = true,
+            Operation::InstallPackages {
+                packages: installed,
+            } => {
+                for package in installed {
+                    if !packages.contains(&package.as_str()) {
+                        packages.push(package);
+                    }
+                }
+            }
+            Operation::WriteFile { path, contents } => {
+                write_files.push(json!({ "path": path, "content": contents }));
+            }
+            Operation::CreateDir { path } => runcmd.push(json!(["mkdir", "-p", path])),
+            Operation::StartService { name } => runcmd.push(json!(["systemctl", "start", name])),
+            Operation::EnableService { name } => runcmd.push(json!(["systemctl", "enable", name])),
+            Operation::RestartService { name } => {
+                runcmd.push(json!(["systemctl", "restart", name]))
+            }
+            Operation::ReloadService { name } => runcmd.push(json!(["systemctl", "reload", name])),
+            Operation::RunCommand { command, args } => {
+                let mut argv = vec![command];
+                argv.extend(args);
+                runcmd.push(json!(argv));
+            }
+            Operation::RunCommandWithInput {
+                command,
+                args,
+                input,
+            } => {
+                // printf is a shell builtin, so the input does not show in the process list
+                let argv: Vec<String> = std::iter::once(command)
+                    .chain(args)
+                    .map(|arg| shell_quote(arg))
+                    .collect();
+                runcmd.push(json!(format!(
+                    "printf '%s' {} | {}",
+                    shell_quote(input),
+                    argv.join(" ")
+                )));
+            }
+        }
+    }
+
+    let document = json!({
+        "package_update": package_upgrade || !packages.is_empty(),
+        "package_upgrade": package_upgrade,
+        "packages": packages,
+        "write_files": write_files,
+        "runcmd": runcmd,
+    });
+    Ok(format!(
+        "#cloud-config\n{}",
+        serde_yaml::to_string(&document)?
+    ))
+}
+
+/// Checks that a plan passes no secret to its commands.
+///
+/// Exports hold the standard input of commands in plaintext, so the generated passwords and
+/// Vault credentials given to them would be readable by anyone with the playbook, or with
+/// access to the instance metadata for cloud-init user-data.
+fn check_no_secrets(plan: &Plan) -> Result<(), Box<dyn Error>> {
+    let commands: Vec<&str> = plan
+        .operations()
+        .iter()
+        .filter_map(|operation| match operation {
+            Operation::RunCommandWithInput { command, args, .. } => Some(
+                // The files written by `Plan::write_private_file` are named by their path
+                match args.as_slice() {
+                    [_, _, _, path] if command == "sh" => path.as_str(),
+                    _ => command.as_str(),
+                },
+            ),
+            _ => None,
+        })
+        .collect();
+    if commands.is_empty() {
+        return Ok(());
+    }
+    Err(format!(
+        "The plan passes secrets to {}, which would be written into the export in plaintext; \
+         export with --include-secrets to include them anyway",
+        commands.join(", ")
+    )
+    .into())
+}
+
+/// Converts a single operation into an Ansible task.
+fn ansible_task(operation: &Operation) -> Value {
+    match operation {
+        Operation::UpgradePackages => json!({
+            "name": "Upgrade all packages",
+            "ansible.builtin.package": { "name": "*", "state": "latest", "update_cache": true },
+        }),
+        Operation::InstallPackages { packages } => json!({
+            "name": format!("Install {}", packages.join(", ")),
+            "ansible.builtin.package": { "name": packages, "state": "present" },
+        }),
+        Operation::WriteFile { path, contents } => json!({
+            "name": format!("Write {}", path),
+            "ansible.builtin.copy": { "dest": path, "content": contents },
+        }),
+        Operation::CreateDir { path } => json!({
+            "name": format!("Create {}", path),
+            "ansible.builtin.file": { "path": path, "state": "directory" },
+        }),
+        Operation::StartService { name } => json!({
+            "name": format!("Start {}", name),
+            "ansible.builtin.systemd_service": { "name": name, "state": "started" },
+        }),
+        Operation::EnableService { name } => json!({
+            "name": format!("Enable {}", name),
+            "ansible.builtin.systemd_service": { "name": name, "enabled": true },
+        }),
+        Operation::RestartService { name } => json!({
+            "name": format!("Restart {}", name),
+            "ansible.builtin.systemd_service": { "name": name, "state": "restarted" },
+        }),
+        Operation::ReloadService { name } => json!({
+            "name": format!("Reload {}", name),
+            "ansible.builtin.systemd_service": { "name": name, "state": "reloaded" },
+        }),
+        Operation::RunCommand { command, args } => {
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            command_task(command, &args).unwrap_or_else(|| {
+                let mut argv = vec![command.as_str()];
+                argv.extend(&args);
+                json!({
+                    "name": format!("Run {}", argv.join(" ")),
+                    "ansible.builtin.command": { "argv": argv },
+                })
+            })
+        }
+        Operation::RunCommandWithInput {
+            command,
+            args,
+            input,
+        } => {
+            let mut argv = vec![command.as_str()];
+            argv.extend(args.iter().map(String::as_str));
+            json!({
+                "name": format!("Run {}", argv.join(" ")),
+                "ansible.builtin.command": {
+                    "argv": argv,
+                    "stdin": input,
+                    "stdin_add_newline": false,
+                },
+                "no_log": true,
+            })
+        }
+    }
+}
+
+/// Maps a command to a dedicated Ansible module, if there is one.
+fn command_task(command: &str, args: &[&str]) -> Option<Value> {
+    match (command, args) {
+        ("systemctl", ["daemon-reload"]) => Some(json!({
+            "name": "Reload systemd",
+            "ansible.builtin.systemd_service": { "daemon_reload": true },
+        })),
+        ("chmod", ["+x", path]) => Some(json!({
+            "name": format!("Make {} executable", path),
+            "ansible.builtin.file": { "path": path, "mode": "a+x" },
+        })),
+        _ => None,
+    }
+}
@@ -0,0 +1,108 @@
+//! # Filesystem Module
+//!
+//! This module defines the `FileSystem` trait, which abstracts over the files the setup
+//! modules read and write, such as `/etc/nginx/sites-available/default`.
+//!
+//! The file system in use is tracked per thread, like the `CommandRunner`. By default it is
+//! `RunnerFileSystem`, which acts on the files of the current runner's host; wrap code in
+//! `with_filesystem` to use another one, such as an in-memory one that lets tests check
+//! what was written without touching the real `/etc`.
+//! The file helpers in `utils` always go through `current_filesystem()`.
+
+use crate::runner::current_runner;
+use std::cell::RefCell;
+use std::error::Error;
+use std::sync::Arc;
+
+/// Reads and writes the files of a host.
+pub trait FileSystem: Send + Sync {
+    /// Reads the contents of a file.
+    fn read(&self, path: &str) -> Result<Vec<u8>, Box<dyn Error>>;
+
+    /// Writes a file, replacing any existing contents.
+    fn write(&self, path: &str, contents: &[u8]) -> Result<(), Box<dyn Error>>;
+
+    /// Returns whether a file or directory exists.
+    fn exists(&self, path: &str) -> bool;
+
+    /// Sets the permission bits of a file or directory (e.g. `0o600`).
+    fn set_permissions(&self, path: &str, mode: u32) -> Result<(), Box<dyn Error>>;
+
+    /// Creates a directory and all of its missing parents.
+    fn create_dir_all(&self, path: &str) -> Result<(), Box<dyn Error>>;
+}
+
+/// Acts on the files of the host of the current thread's `CommandRunner`, so that files
+/// are written to the host being configured, local or remote. Permissions are set with
+/// `chmod`.
+#[derive(Debug, Clone, Default)]
+pub struct RunnerFileSystem;
+
+impl FileSystem for RunnerFileSystem {
+    fn read(&self, path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        current_runner().read_file(path)
+    }
+
+    fn write(&self, path: &str, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+        current_runner().write_file(path, contents)
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        current_runner().path_exists(path)
+    }
+
+    fn set_permissions(&self, path: &str, mode: u32) -> Result<(), Box<dyn Error>> {
+        let output = current_runner().run("chmod", &[&format!("{:o}", mode), path], &[])?;
+        if !output.success {
+            return Err(format!(
+                "Failed to set the permissions of {}: {}",
+                path,
+                output.stderr.trim()
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        current_runner().create_dir_all(path)
+    }
+}
+
+thread_local! {
+    static CURRENT_FILESYSTEM: RefCell<Option<Arc<dyn FileSystem>>> = const { RefCell::new(None) };
+}
+
+/// Returns the file system used by the current thread (`RunnerFileSystem` unless set with
+/// `with_filesystem`).
+pub fn current_filesystem() -> Arc<dyn FileSystem> {
+    CURRENT_FILESYSTEM
+        .with(|current| current.borrow().clone())
+        .unwrap_or_else(|| Arc::new(RunnerFileSystem))
+}
+
+/// Runs `f` with `filesystem` as the current thread's file system, restoring the previous
+/// one afterwards.
+///
+/// # Arguments
+///
+/// * `filesystem` - The file system to use while `f` runs
+/// * `f` - The function to run
+///
+/// # Returns
+///
+/// Returns the result of `f`.
+pub fn with_filesystem<T>(filesystem: Arc<dyn FileSystem>, f: impl FnOnce() -> T) -> T {
+    /// Restores the previous file system when dropped, even if `f` panics.
+    struct Restore(Option<Arc<dyn FileSystem>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            CURRENT_FILESYSTEM.with(|current| *current.borrow_mut() = previous);
+        }
+    }
+
+    let _restore = Restore(CURRENT_FILESYSTEM.with(|current| current.replace(Some(filesystem))));
+    f()
+}
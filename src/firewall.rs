@@ -0,0 +1,581 @@
+//! # Firewall Module
+//!
+//! This module abstracts over the two firewalls server_forge configures: ufw on
+//! Debian-based distributions and firewalld on Red Hat-based ones. A `Firewall` reads its
+//! current state through the current `CommandRunner` and adds the commands changing it to a
+//! `Plan`, so callers describe which services and ports to allow once, and `plan_rules`
+//! turns that into the commands of the firewall in use.
+
+use crate::distro::PackageManager;
+use crate::filesystem::current_filesystem;
+use crate::plan::Plan;
+use crate::runner::current_runner;
+use std::error::Error;
+use std::net::IpAddr;
+
+/// Where ufw is installed.
+const UFW_PATH: &str = "/usr/sbin/ufw";
+
+/// Where the firewalld client is installed.
+const FIREWALL_CMD_PATH: &str = "/usr/bin/firewall-cmd";
+
+/// The direction of the traffic a default policy applies to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// Connections to the host
+    Incoming,
+    /// Connections from the host
+    Outgoing,
+}
+
+/// What happens to traffic no rule matches.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    /// The traffic is allowed
+    Allow,
+    /// The traffic is dropped
+    Deny,
+}
+
+/// A host firewall.
+pub trait Firewall {
+    /// Returns the name of the firewall.
+    fn name(&self) -> &'static str;
+
+    /// Returns whether the firewall must be running for rules to be added. A firewall that
+    /// can be configured while inactive is enabled after its rules are added, so that SSH is
+    /// allowed before incoming connections are filtered.
+    fn configured_while_running(&self) -> bool;
+
+    /// Returns the rules the firewall allows, as `rule_for_service` and the port rules
+    /// passed to `allow_port` name them, or `None` if the firewall is not active.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state of the firewall cannot be read.
+    fn allowed_rules(&self) -> Result<Option<Vec<String>>, Box<dyn Error>>;
+
+    /// Returns the name of the rule allowing a service, as listed by `allowed_rules`.
+    ///
+    /// # Arguments
+    ///
+    /// * `service` - The service name, as known to firewalld (e.g. `ssh` or `http`)
+    fn rule_for_service(&self, service: &str) -> String;
+
+    /// Returns the name of the rule allowing a port or port range, as listed by
+    /// `allowed_rules`.
+    fn rule_for_port(&self, port: &str) -> String {
+        port.to_string()
+    }
+
+    /// Plans setting the default policy of a direction.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the firewall does not support the policy.
+    fn set_default_policy(
+        &self,
+        plan: &mut Plan,
+        direction: Direction,
+        policy: Policy,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// Plans allowing incoming connections to a port or port range (e.g. `80/tcp` or
+    /// `6000:6007/udp`), possibly only from some addresses (e.g.
+    /// `from 10.0.0.0/8 to any port 5432`), as accepted by `validate_firewall_rule`.
+    fn allow_port(&self, plan: &mut Plan, port: &str);
+
+    /// Plans allowing incoming connections to a service.
+    ///
+    /// # Arguments
+    ///
+    /// * `plan` - The plan to add the operations to
+    /// * `service` - The service name, as known to firewalld (e.g. `ssh` or `http`)
+    fn allow_service(&self, plan: &mut Plan, service: &str);
+
+    /// Plans activating the firewall, now and at boot.
+    fn enable(&self, plan: &mut Plan);
+
+    /// Plans applying the rules added since the last reload, if the firewall needs it.
+    fn reload(&self, _plan: &mut Plan) {}
+}
+
+/// The firewall of Debian-based distributions.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ufw;
+
+impl Firewall for Ufw {
+    fn name(&self) -> &'static str {
+        "ufw"
+    }
+
+    fn configured_while_running(&self) -> bool {
+        false
+    }
+
+    fn allowed_rules(&self) -> Result<Option<Vec<String>>, Box<dyn Error>> {
+        let runner = current_runner();
+        if !current_filesystem().exists(UFW_PATH) {
+            return Ok(None);
+        }
+        Ok(parse_ufw_status(
+            &runner.run("ufw", &["status"], &[])?.stdout,
+        ))
+    }
+
+    /// SSH is allowed through the `OpenSSH` application profile installed by
+    /// openssh-server; other services are looked up by ufw in `/etc/services`.
+    fn rule_for_service(&self, service: &str) -> String {
+        match service {
+            "ssh" => String::from("OpenSSH"),
+            _ => service.to_string(),
+        }
+    }
+
+    fn set_default_policy(
+        &self,
+        plan: &mut Plan,
+        direction: Direction,
+        policy: Policy,
+    ) -> Result<(), Box<dyn Error>> {
+        let policy = match policy {
+            Policy::Allow => "allow",
+            Policy::Deny => "deny",
+        };
+        let direction = match direction {
+            Direction::Incoming => "incoming",
+            Direction::Outgoing => "outgoing",
+        };
+        plan.run("ufw", &["default", policy, direction]);
+        Ok(())
+    }
+
+    fn allow_port(&self, plan: &mut Plan, port: &str) {
+        let mut args = vec!["allow"];
+        args.extend(port.split_whitespace());
+        plan.run("ufw", &args);
+    }
+
+    fn allow_service(&self, plan: &mut Plan, service: &str) {
+        plan.run("ufw", &["allow", &self.rule_for_service(service)]);
+    }
+
+    fn enable(&self, plan: &mut Plan) {
+        // Without --force, ufw asks for confirmation when run from a terminal
+        plan.run("ufw", &["--force", "enable"]);
+    }
+}
+
+/// The firewall of Red Hat-based distributions, configured in its public zone.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Firewalld;
+
+impl Firewall for Firewalld {
+    fn name(&self) -> &'static str {
+        "firewalld"
+    }
+
+    fn configured_while_running(&self) -> bool {
+        true
+    }
+
+    fn allowed_rules(&self) -> Result<Option<Vec<String>>, Box<dyn Error>> {
+        let runner = current_runner();
+        if !current_filesystem().exists(FIREWALL_CMD_PATH)
+            || runner.run("firewall-cmd", &["--state"], &[])?.stdout.trim() != "running"
+        {
+            return Ok(None);
+        }
+        let mut rules = Vec::new();
+        for list in ["--list-services", "--list-ports"] {
+            let output =
+                runner.run("firewall-cmd", &["--zone=public", list, "--permanent"], &[])?;
+            rules.extend(output.stdout.split_whitespace().map(str::to_string));
+        }
+        Ok(Some(rules))
+    }
+
+    fn rule_for_service(&self, service: &str) -> String {
+        service.to_string()
+    }
+
+    /// firewalld writes port ranges with a dash (`6000-6007/udp`) where ufw uses a colon.
+    fn rule_for_port(&self, port: &str) -> String {
+        port.replace(':', "-")
+    }
+
+    /// Unmatched incoming connections are rejected by the `default` target of the zone, and
+    /// firewalld allows all outgoing connections, which cannot be denied by default.
+    fn set_default_policy(
+        &self,
+        plan: &mut Plan,
+        direction: Direction,
+        policy: Policy,
+    ) -> Result<(), Box<dyn Error>> {
+        let target = match (direction, policy) {
+            (Direction::Incoming, Policy::Deny) => "default",
+            (Direction::Incoming, Policy::Allow) => "ACCEPT",
+            (Direction::Outgoing, Policy::Allow) => return Ok(()),
+            (Direction::Outgoing, Policy::Deny) => {
+                return Err("firewalld cannot deny outgoing connections by default".into())
+            }
+        };
+        plan.run(
+            "firewall-cmd",
+            &[
+                "--zone=public",
+                &format!("--set-target={}", target),
+                "--permanent",
+            ],
+        );
+        Ok(())
+    }
+
+    /// Rules limited to some addresses are added as rich rules.
+    fn allow_port(&self, plan: &mut Plan, port: &str) {
+        let option = match parse_source_rule(port) {
+            Ok(rule) => format!("--add-rich-rule={}", rule.rich_rule()),
+            Err(_) => format!("--add-port={}", self.rule_for_port(port)),
+        };
+        plan.run("firewall-cmd", &["--zone=public", &option, "--permanent"]);
+    }
+
+    fn allow_service(&self, plan: &mut Plan, service: &str) {
+        plan.run(
+            "firewall-cmd",
+            &[
+                "--zone=public",
+                &format!("--add-service={}", service),
+                "--permanent",
+            ],
+        );
+    }
+
+    fn enable(&self, plan: &mut Plan) {
+        plan.start_service("firewalld").enable_service("firewalld");
+    }
+
+    fn reload(&self, plan: &mut Plan) {
+        plan.run("firewall-cmd", &["--reload"]);
+    }
+}
+
+/// Returns the firewall used with a package manager: ufw with apt, firewalld otherwise.
+///
+/// # Arguments
+///
+/// * `package_manager` - The package manager of the host
+pub fn for_package_manager(package_manager: &PackageManager) -> Box<dyn Firewall> {
+    match package_manager {
+        PackageManager::Apt => Box::new(Ufw),
+        PackageManager::Yum | PackageManager::Dnf => Box::new(Firewalld),
+    }
+}
+
+/// Returns the firewall used on a distribution.
+///
+/// # Arguments
+///
+/// * `distro` - The distribution, as in `Config::linux_distro`
+///
+/// # Errors
+///
+/// Returns an error if the distribution is not supported.
+pub fn for_distro(distro: &str) -> Result<Box<dyn Firewall>, Box<dyn Error>> {
+    match distro {
+        "ubuntu" => Ok(Box::new(Ufw)),
+        "centos" | "fedora" => Ok(Box::new(Firewalld)),
+        _ => Err("Unsupported Linux distribution".into()),
+    }
+}
+
+/// Checks that the firewall of the host of the current `CommandRunner` lets SSH connections
+/// to `port` through, so that a run never leaves sshd listening behind a closed port.
+///
+/// The port must be allowed over TCP (or over any protocol, with ufw); the SSH service rule
+/// only counts for port 22. An inactive or missing firewall lets everything through.
+///
+/// # Arguments
+///
+/// * `port` - The port sshd listens on (`Config::ssh_port`)
+///
+/// # Errors
+///
+/// Returns an error if the firewall is active and blocks the port, or if its rules cannot
+/// be listed.
+pub fn check_ssh_port_allowed(port: u16) -> Result<(), Box<dyn Error>> {
+    let Some(firewall) = installed() else {
+        return Ok(());
+    };
+    let Some(allowed) = firewall.allowed_rules()? else {
+        return Ok(());
+    };
+    let allows = |rule: String| allowed.contains(&rule);
+    if allows(firewall.rule_for_port(&format!("{}/tcp", port)))
+        || allows(port.to_string())
+        || (port == 22 && allows(firewall.rule_for_service("ssh")))
+    {
+        Ok(())
+    } else {
+        Err(format!(
+            "The SSH port {}/tcp is not allowed through {}; SSH connections would be blocked",
+            port,
+            firewall.name()
+        )
+        .into())
+    }
+}
+
+/// Returns the firewall installed on the host of the current `CommandRunner`, if any.
+pub fn installed() -> Option<Box<dyn Firewall>> {
+    let filesystem = current_filesystem();
+    if filesystem.exists(UFW_PATH) {
+        Some(Box::new(Ufw))
+    } else if filesystem.exists(FIREWALL_CMD_PATH) {
+        Some(Box::new(Firewalld))
+    } else {
+        None
+    }
+}
+
+/// Plans allowing services and ports through a firewall, and activating it.
+///
+/// An inactive firewall is set to deny incoming and allow outgoing connections by default,
+/// and enabled. A firewall that is already active is left as it is, and only the services and
+/// ports it does not allow yet are added, so re-running a setup does not re-apply its rules.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `firewall` - The firewall of the host
+/// * `services` - The services to allow, as known to firewalld (e.g. `ssh`)
+/// * `ports` - The ports and port ranges to allow (e.g. `80/tcp`)
+///
+/// # Errors
+///
+/// Returns an error if the state of the firewall cannot be read.
+pub fn plan_rules(
+    plan: &mut Plan,
+    firewall: &dyn Firewall,
+    services: &[&str],
+    ports: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let allowed = firewall.allowed_rules()?;
+    let active = allowed.is_some();
+    let allowed = allowed.unwrap_or_default();
+
+    if !active {
+        if firewall.configured_while_running() {
+            firewall.enable(plan);
+        }
+        firewall.set_default_policy(plan, Direction::Incoming, Policy::Deny)?;
+        firewall.set_default_policy(plan, Direction::Outgoing, Policy::Allow)?;
+    }
+
+    let mut changed = !active;
+    for service in services {
+        if !allowed.contains(&firewall.rule_for_service(service)) {
+            firewall.allow_service(plan, service);
+            changed = true;
+        }
+    }
+    for port in ports {
+        if !allowed.contains(&firewall.rule_for_port(port)) {
+            firewall.allow_port(plan, port);
+            changed = true;
+        }
+    }
+
+    if !active && !firewall.configured_while_running() {
+        firewall.enable(plan);
+    }
+    if changed {
+        firewall.reload(plan);
+    }
+    Ok(())
+}
+
+/// Parses the output of `ufw status`.
+///
+/// # Arguments
+///
+/// * `status` - The output of `ufw status`
+///
+/// # Returns
+///
+/// Returns the rules (the `To` column) of the `ALLOW` entries, without duplicates, or `None`
+/// if ufw is inactive.
+pub fn parse_ufw_status(status: &str) -> Option<Vec<String>> {
+    if !status.lines().any(|line| line.trim() == "Status: active") {
+        return None;
+    }
+    let mut rules: Vec<String> = Vec::new();
+    for line in status.lines() {
+        let mut words = line.split_whitespace();
+        let Some(rule) = words.next() else {
+            continue;
+        };
+        if words.any(|word| word == "ALLOW") && !rules.iter().any(|known| known == rule) {
+            rules.push(rule.to_string());
+        }
+    }
+    Some(rules)
+}
+
+/// Checks that a custom firewall rule has a form both ufw and firewalld support:
+///
+/// - a port, with an optional protocol (e.g. `80` or `80/tcp`)
+/// - a port range with a protocol (e.g. `6000:6007/udp`)
+/// - a service name (e.g. `http` or `OpenSSH`)
+/// - a port open to some addresses only (e.g. `from 10.0.0.0/8 to any port 5432` or
+///   `from 192.168.1.10 to any port 6000:6007 proto tcp`)
+///
+/// # Arguments
+///
+/// * `rule` - The rule, as listed in `custom_firewall_rules`
+///
+/// # Errors
+///
+/// Returns an error describing what is wrong with the rule.
+pub fn validate_firewall_rule(rule: &str) -> Result<(), Box<dyn Error>> {
+    let rule = rule.trim();
+    if rule.starts_with("from ") {
+        parse_source_rule(rule)?;
+    } else if rule.starts_with(|c: char| c.is_ascii_digit()) {
+        match rule.split_once('/') {
+            Some((ports, protocol)) => {
+                validate_protocol(protocol)?;
+                validate_ports(ports, true)?;
+            }
+            None => validate_ports(rule, false)?,
+        }
+    } else if !is_service_name(rule) {
+        return Err(String::from(
+            "expected a port (e.g. 80/tcp), a port range (e.g. 6000:6007/tcp), a service \
+             name, or 'from <address> to any port <port>'",
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// A rule opening a port to some addresses only, written as in ufw.
+struct SourceRule<'a> {
+    source: &'a str,
+    ports: &'a str,
+    protocol: Option<&'a str>,
+}
+
+impl SourceRule<'_> {
+    /// Returns the firewalld rich rule equivalent to the rule. firewalld needs a protocol,
+    /// so TCP is used when the rule has none.
+    fn rich_rule(&self) -> String {
+        let port = format!(
+            "port port=\"{}\" protocol=\"{}\" accept",
+            self.ports.replace(':', "-"),
+            self.protocol.unwrap_or("tcp")
+        );
+        if self.source == "any" {
+            return format!("rule {}", port);
+        }
+        let family = if self.source.contains(':') {
+            "ipv6"
+        } else {
+            "ipv4"
+        };
+        format!(
+            "rule family=\"{}\" source address=\"{}\" {}",
+            family, self.source, port
+        )
+    }
+}
+
+/// Parses a rule of the form `from <address> to any port <ports> [proto <protocol>]`.
+fn parse_source_rule(rule: &str) -> Result<SourceRule<'_>, String> {
+    let words: Vec<&str> = rule.split_whitespace().collect();
+    let (source, ports, protocol) = match words.as_slice() {
+        ["from", source, "to", "any", "port", ports] => (*source, *ports, None),
+        ["from", source, "to", "any", "port", ports, "proto", protocol] => {
+            (*source, *ports, Some(*protocol))
+        }
+        _ => {
+            return Err(String::from(
+                "expected 'from <address> to any port <port> [proto <tcp|udp>]'",
+            ))
+        }
+    };
+    validate_address(source)?;
+    if let Some(protocol) = protocol {
+        validate_protocol(protocol)?;
+    }
+    validate_ports(ports, protocol.is_some())?;
+    Ok(SourceRule {
+        source,
+        ports,
+        protocol,
+    })
+}
+
+/// Checks a port or a port range (e.g. `6000:6007`), which needs a protocol.
+fn validate_ports(ports: &str, has_protocol: bool) -> Result<(), String> {
+    let Some((first, last)) = ports.split_once(':') else {
+        return port_number(ports).map(|_| ());
+    };
+    if port_number(first)? >= port_number(last)? {
+        return Err(format!("the port range {} is empty", ports));
+    }
+    if !has_protocol {
+        return Err(format!(
+            "the port range {} needs a protocol (e.g. {}/tcp)",
+            ports, ports
+        ));
+    }
+    Ok(())
+}
+
+/// Parses a port number between 1 and 65535.
+fn port_number(port: &str) -> Result<u16, String> {
+    port.parse()
+        .ok()
+        .filter(|port| *port != 0)
+        .ok_or_else(|| format!("'{}' is not a port number", port))
+}
+
+fn validate_protocol(protocol: &str) -> Result<(), String> {
+    match protocol {
+        "tcp" | "udp" => Ok(()),
+        _ => Err(format!(
+            "unknown protocol '{}' (expected tcp or udp)",
+            protocol
+        )),
+    }
+}
+
+/// Checks an IP address, a network in CIDR notation, or `any`.
+fn validate_address(address: &str) -> Result<(), String> {
+    if address == "any" {
+        return Ok(());
+    }
+    let invalid = || format!("'{}' is not an IP address or network", address);
+    let (ip, prefix) = match address.split_once('/') {
+        Some((ip, prefix)) => (ip, Some(prefix)),
+        None => (address, None),
+    };
+    let ip: IpAddr = ip.parse().map_err(|_| invalid())?;
+    if let Some(prefix) = prefix {
+        let max = if ip.is_ipv4() { 32 } else { 128 };
+        match prefix.parse::<u8>() {
+            Ok(prefix) if prefix <= max => {}
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether a rule looks like a service or ufw application name.
+fn is_service_name(rule: &str) -> bool {
+    rule.starts_with(|c: char| c.is_ascii_alphabetic())
+        && rule
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '+'))
+}
@@ -0,0 +1,141 @@
+//! # Inventory Module
+//!
+//! This module loads inventory files describing a fleet of servers, similar to an Ansible
+//! inventory. An inventory groups hosts and layers partial `Config` overrides: global
+//! `vars` apply to every host, group `vars` to the hosts in that group, and each host can
+//! override individual options on top.
+//!
+//! Inventories can be written in YAML or TOML (chosen by the `.toml` extension):
+//!
+//! ```yaml
+//! vars:
+//!   security_level: advanced
+//!   monitoring: true
+//! groups:
+//!   web:
+//!     vars:
+//!       server_roles: [web]
+//!       deployed_apps: [nginx]
+//!     hosts:
+//!       web1:
+//!       web2:
+//!         deployed_apps: [nginx, php]
+//! ```
+//!
+//! Host names are used as SSH destinations.
+
+use crate::config::{Config, OPTION_ALIASES};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::path::Path;
+
+/// A set of `Config` option overrides, keyed by option name.
+pub type Overrides = Map<String, Value>;
+
+/// A fleet of hosts, organised in groups, with layered configuration overrides.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Inventory {
+    /// Overrides applied to every host
+    pub vars: Overrides,
+    /// The host groups, by name
+    pub groups: BTreeMap<String, HostGroup>,
+}
+
+/// A group of hosts sharing configuration overrides.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostGroup {
+    /// Overrides applied to every host in the group
+    pub vars: Overrides,
+    /// The hosts in the group, with their own overrides (if any)
+    pub hosts: BTreeMap<String, Option<Overrides>>,
+}
+
+/// Loads an inventory file.
+///
+/// Files ending in `.toml` are parsed as TOML; anything else is parsed as YAML.
+///
+/// # Arguments
+///
+/// * `path` - The path of the inventory file
+///
+/// # Returns
+///
+/// Returns the parsed `Inventory`, or an error if the file cannot be read or parsed.
+pub fn load(path: &str) -> Result<Inventory, Box<dyn Error>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read inventory {}: {}", path, e))?;
+
+    let inventory = if Path::new(path).extension().is_some_and(|ext| ext == "toml") {
+        toml::from_str(&contents).map_err(|e| format!("Invalid inventory {}: {}", path, e))?
+    } else {
+        serde_yaml::from_str(&contents).map_err(|e| format!("Invalid inventory {}: {}", path, e))?
+    };
+    Ok(inventory)
+}
+
+impl Inventory {
+    /// Returns every host in the inventory with its merged configuration.
+    ///
+    /// Each host's configuration is `base`, overridden by the global `vars`, then its
+    /// group's `vars`, then its own overrides. Hosts are returned in group order, then
+    /// host order.
+    ///
+    /// # Arguments
+    ///
+    /// * `base` - The configuration the overrides are applied to
+    ///
+    /// # Returns
+    ///
+    /// Returns `(host, config)` pairs, or an error if an override names an unknown option,
+    /// has the wrong type, or a host appears in more than one group.
+    pub fn hosts(&self, base: &Config) -> Result<Vec<(String, Config)>, Box<dyn Error>> {
+        let base = Value::Object(base.options()?);
+        let mut seen = HashSet::new();
+        let mut hosts = Vec::new();
+
+        for (group_name, group) in &self.groups {
+            for (host, host_vars) in &group.hosts {
+                if !seen.insert(host) {
+                    return Err(format!("Host {} is listed in more than one group", host).into());
+                }
+
+                let mut merged = base.clone();
+                for overrides in [Some(&self.vars), Some(&group.vars), host_vars.as_ref()]
+                    .into_iter()
+                    .flatten()
+                {
+                    apply_overrides(&mut merged, overrides)
+                        .map_err(|e| format!("{} (host {}, group {})", e, host, group_name))?;
+                }
+
+                let config = serde_json::from_value(merged)
+                    .map_err(|e| format!("Invalid configuration for host {}: {}", host, e))?;
+                hosts.push((host.clone(), config));
+            }
+        }
+
+        Ok(hosts)
+    }
+}
+
+/// Replaces the options named in `overrides` in a serialized `Config`.
+fn apply_overrides(config: &mut Value, overrides: &Overrides) -> Result<(), Box<dyn Error>> {
+    let options = config
+        .as_object_mut()
+        .ok_or("Configuration is not an object")?;
+    for (name, value) in overrides {
+        let name = OPTION_ALIASES
+            .iter()
+            .find(|(alias, _)| alias == name)
+            .map_or(name.as_str(), |(_, option)| option);
+        match options.get_mut(name) {
+            Some(option) => *option = value.clone(),
+            None => return Err(format!("Unknown configuration option '{}'", name).into()),
+        }
+    }
+    Ok(())
+}
@@ -1,11 +1,34 @@
+#[cfg(feature = "async")]
+pub mod async_runner;
 pub mod backup;
+pub mod check;
+pub mod cli;
+pub mod concurrency;
 pub mod config;
+pub mod console;
 pub mod containerization;
 pub mod deployment;
 pub mod distro;
+pub mod encryption;
+pub mod export;
+pub mod filesystem;
+pub mod firewall;
+pub mod inventory;
+pub mod maintenance;
 pub mod monitoring;
+pub mod notify;
+pub mod pipeline;
+pub mod plan;
+pub mod progress;
+pub mod registry;
+pub mod remote;
 pub mod rollback;
+pub mod runner;
+pub mod secrets;
 pub mod security;
 pub mod setup;
+pub mod status;
+pub mod systemd;
 pub mod updates;
 pub mod utils;
+pub mod validation;
@@ -6,28 +6,437 @@
 //! This module contains the main entry point for the application and orchestrates the
 //! various setup and configuration processes.
 
-use log::{error, info};
+use clap::Parser;
+use log::{error, info, warn};
+use rayon::prelude::*;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
 use std::error::Error;
+use std::io::IsTerminal;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 mod backup;
+mod check;
+mod cli;
+mod concurrency;
 mod config;
+mod console;
 mod containerization;
 mod deployment;
+mod export;
+mod filesystem;
+mod firewall;
+mod maintenance;
 mod monitoring;
+mod notify;
+mod pipeline;
+mod plan;
+mod progress;
+mod registry;
+mod remote;
 mod rollback;
+mod runner;
+mod secrets;
 mod security;
 mod setup;
+mod status;
+mod systemd;
 mod updates;
 mod utils;
+mod validation;
 
 mod distro;
+mod encryption;
+mod inventory;
 
-use rollback::RollbackManager;
-use utils::{generate_report, get_user_input, save_config, setup_logging};
+use cli::{Cli, Command, ExportFormat};
+use concurrency::{with_limits, Limits};
+use config::{Config, FieldChange};
+use console::StepStatus;
+use notify::RunOutcome;
+use pipeline::PhaseError;
+use remote::RemoteCommandRunner;
+use rollback::{Recovery, RollbackManager};
+use runner::{current_runner, with_runner, CommandRunner, LocalCommandRunner};
+use utils::{
+    configure_proxy, email_report, generate_report, get_user_input, load_env_file,
+    load_saved_config, prompt_recovery, save_config, setup_logging, REPORT_PATH,
+};
 
 /// The main entry point for the Server Forge application.
 ///
-/// This function orchestrates the entire server setup process, including:
+/// This function parses the command line, sets up logging and runs the requested
+/// command (`deploy` by default).
+///
+/// # Errors
+///
+/// Returns an error if the command fails.
+fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+
+    // Set up logging for the application
+    setup_logging(if cli.quiet {
+        log::LevelFilter::Warn
+    } else {
+        log::LevelFilter::Info
+    })?;
+    progress::set_enabled(!cli.quiet && std::io::stderr().is_terminal());
+    info!(
+        "Server Setup and Maintenance Script started (version {})",
+        cli::VERSION
+    );
+    if let Some(path) = load_env_file(cli.env_file.as_deref())? {
+        info!("Loaded environment variables from {}", path.display());
+    }
+
+    match cli
+        .command
+        .clone()
+        .unwrap_or(Command::Deploy { config: None })
+    {
+        Command::Deploy { config } => deploy(&cli, config.as_deref()),
+        Command::Export {
+            format,
+            output,
+            include_secrets,
+        } => export(&cli, format, output.as_deref(), include_secrets),
+        Command::Status => status(&cli),
+        Command::Rollback { dry_run, snapshot } => roll_back(&cli, dry_run, snapshot),
+        Command::Check { config } => check(&config),
+    }
+}
+
+/// Runs the `check` command.
+///
+/// This function reads a configuration file and prints every problem `check::check_config`
+/// finds in it on the local machine.
+///
+/// # Arguments
+///
+/// * `path` - The path of the configuration file
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be loaded or has any problem.
+fn check(path: &str) -> Result<(), Box<dyn Error>> {
+    let config = Config::from_file(path)?;
+    let problems = check::check_config(&config);
+    if problems.is_empty() {
+        println!("{}: no problems found", path);
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("  ! {}", problem);
+    }
+    Err(format!("{} has {} problem(s)", path, problems.len()).into())
+}
+
+/// Runs the `status` command.
+///
+/// This function checks the local machine, or each host given with `--hosts` or in the
+/// `--inventory` file, against the configuration saved by its last run, and prints the
+/// state of its firewall and services.
+///
+/// # Arguments
+///
+/// * `cli` - The parsed command-line arguments
+///
+/// # Errors
+///
+/// Returns an error if a host cannot be checked, has no saved configuration, or does not
+/// match it.
+fn status(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    let mut failed_hosts = Vec::new();
+    for runner in host_runners(cli)? {
+        let host = runner.host().to_string();
+        match with_runner(runner, status::host_status)? {
+            Some(status) => {
+                println!("{}", status);
+                let problems = status.problems();
+                for problem in &problems {
+                    println!("  ! {}", problem);
+                }
+                if !problems.is_empty() {
+                    failed_hosts.push(host);
+                }
+            }
+            None => {
+                println!("Host: {}\nNo saved configuration found\n", host);
+                failed_hosts.push(host);
+            }
+        }
+    }
+
+    if !failed_hosts.is_empty() {
+        return Err(format!("Status check failed on: {}", failed_hosts.join(", ")).into());
+    }
+    Ok(())
+}
+
+/// Runs the `rollback` command.
+///
+/// This function loads the latest run stored under `rollback::STORE_ROOT` on the local
+/// machine, or on each host given with `--hosts` or in the `--inventory` file, and rolls back
+/// its changes, or prints what rolling them back would change with `--dry-run`.
+///
+/// # Arguments
+///
+/// * `cli` - The parsed command-line arguments
+/// * `dry_run` - Whether to only print what the rollback would change
+/// * `snapshot` - The snapshot to roll back to, or `None` to roll back the whole run
+///
+/// # Errors
+///
+/// Returns an error if a host has no stored run, or if a run cannot be loaded or rolled back.
+fn roll_back(cli: &Cli, dry_run: bool, snapshot: Option<usize>) -> Result<(), Box<dyn Error>> {
+    for runner in host_runners(cli)? {
+        let host = runner.host().to_string();
+        with_runner(runner, || {
+            let dir = rollback::latest_run(rollback::STORE_ROOT)?
+                .ok_or_else(|| format!("No stored run found on {}", host))?;
+            if dry_run {
+                print!("{}", rollback::preview_run(&dir, snapshot)?);
+                return Ok(());
+            }
+            rollback::rollback_run(&dir, snapshot)?;
+            console::step(
+                StepStatus::Completed,
+                &format!("Rolled back {} on {}", dir, host),
+            );
+            Ok::<_, Box<dyn Error>>(())
+        })?;
+    }
+    Ok(())
+}
+
+/// Returns the runners of the hosts given with `--hosts` or in the `--inventory` file, or of
+/// the local machine when none is given.
+///
+/// # Arguments
+///
+/// * `cli` - The parsed command-line arguments
+///
+/// # Errors
+///
+/// Returns an error if the inventory cannot be loaded.
+fn host_runners(cli: &Cli) -> Result<Vec<Arc<dyn CommandRunner>>, Box<dyn Error>> {
+    let hosts: Vec<String> = match &cli.inventory {
+        Some(path) => inventory::load(path)?
+            .hosts(&Config::default())?
+            .into_iter()
+            .map(|(host, _)| host)
+            .collect(),
+        None => cli.hosts.clone(),
+    };
+    Ok(if hosts.is_empty() {
+        vec![Arc::new(LocalCommandRunner)]
+    } else {
+        hosts
+            .iter()
+            .map(|host| Arc::new(RemoteCommandRunner::new(host)) as Arc<dyn CommandRunner>)
+            .collect()
+    })
+}
+
+/// Runs the `deploy` command.
+///
+/// This function gathers the configuration and runs the setup pipeline on the local
+/// machine, over SSH on each host given with `--hosts`, or on each host of the
+/// `--inventory` file with its merged configuration. Up to `--parallelism` hosts are
+/// configured concurrently, each with its own `RollbackManager`; the failed phases of a host
+/// are rolled back and the remaining hosts still run. A summary of the results is printed at the end.
+/// If the process is interrupted with SIGINT or SIGTERM, every change of the hosts still being
+/// configured is rolled back before it exits.
+///
+/// # Arguments
+///
+/// * `cli` - The parsed command-line arguments
+///
+/// # Errors
+///
+/// Returns an error if gathering the configuration fails or if the setup fails on any host.
+fn deploy(cli: &Cli, config_path: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let targets = load_targets(cli, config_path)?;
+    install_interrupt_handler()?;
+    let targets = match targets {
+        Targets::Local(config) => return run_pipeline(&config, cli),
+        Targets::Hosts(targets) => targets,
+    };
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(cli.parallelism.into())
+        .build()?;
+    let results: Vec<(&str, Result<(), String>)> = pool.install(|| {
+        targets
+            .par_iter()
+            .map(|(host, config)| {
+                log_mdc::insert("host", host);
+                info!("Configuring {}", host);
+                let runner = Arc::new(RemoteCommandRunner::new(host));
+                let result = with_runner(runner, || run_pipeline(config, cli)).map_err(|e| {
+                    error!("Setup failed on {}: {}", host, e);
+                    e.to_string()
+                });
+                log_mdc::remove("host");
+                (host.as_str(), result)
+            })
+            .collect()
+    });
+
+    let colored = console::colors_enabled();
+    println!("\nDeployment summary:");
+    for (host, result) in &results {
+        match result {
+            Ok(()) => println!(
+                "  {}",
+                StepStatus::Completed.paint(&format!("{}: succeeded", host), colored)
+            ),
+            Err(e) => println!(
+                "  {}",
+                StepStatus::Failed.paint(&format!("{}: failed ({})", host, e), colored)
+            ),
+        }
+    }
+
+    let failed_hosts: Vec<&str> = results
+        .iter()
+        .filter(|(_, result)| result.is_err())
+        .map(|(host, _)| *host)
+        .collect();
+    if !failed_hosts.is_empty() {
+        return Err(format!("Setup failed on: {}", failed_hosts.join(", ")).into());
+    }
+    Ok(())
+}
+
+/// Runs the `export` command.
+///
+/// This function gathers the configuration like `deploy`, plans what the setup would
+/// do to each host without changing anything, and writes the plans in the requested
+/// format: an Ansible playbook with one play per host, or a cloud-init document (which
+/// describes a single host). Files are read from each host over SSH, or from the local
+/// machine when no hosts are given (the play then targets `all`).
+///
+/// # Arguments
+///
+/// * `cli` - The parsed command-line arguments
+/// * `format` - The format to export
+/// * `output` - The file to write the export to, or `None` for standard output
+/// * `include_secrets` - Whether to write the secrets passed to commands into the export
+///
+/// # Errors
+///
+/// Returns an error if gathering the configuration, planning or writing fails, if a plan
+/// passes secrets and `include_secrets` is not set, or if a cloud-init export is requested
+/// for more than one host.
+fn export(
+    cli: &Cli,
+    format: ExportFormat,
+    output: Option<&str>,
+    include_secrets: bool,
+) -> Result<(), Box<dyn Error>> {
+    let plays = match load_targets(cli, None)? {
+        Targets::Local(config) => vec![(
+            String::from("all"),
+            plan::plan_host(&config, Arc::new(LocalCommandRunner))?,
+        )],
+        Targets::Hosts(targets) => targets
+            .iter()
+            .map(|(host, config)| {
+                let source = Arc::new(RemoteCommandRunner::new(host));
+                Ok((host.clone(), plan::plan_host(config, source)?))
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?,
+    };
+
+    let document = match format {
+        ExportFormat::Ansible => export::ansible_playbook(&plays, include_secrets)?,
+        ExportFormat::CloudInit => match plays.as_slice() {
+            [(_, plan)] => export::cloud_config(plan, include_secrets)?,
+            _ => return Err("A cloud-init export describes a single host".into()),
+        },
+    };
+    match output {
+        Some(path) => std::fs::write(path, document)?,
+        None => print!("{}", document),
+    }
+    Ok(())
+}
+
+/// The machines a command acts on, with their configuration.
+enum Targets {
+    /// The local machine
+    Local(Box<Config>),
+    /// Remote hosts, by SSH destination
+    Hosts(Vec<(String, Config)>),
+}
+
+/// Gathers the configuration and the machines to act on.
+///
+/// An `--inventory` file describes both; otherwise the configuration applies to every host
+/// given with `--hosts`, or to the local machine when there are none.
+///
+/// Options are taken, in order of precedence, from the command line (such as
+/// `--max-concurrent-installs`), the inventory, the configuration file, the
+/// `SERVER_FORGE_*` environment variables, and the defaults. The user is only prompted for
+/// the configuration when none of an inventory, a configuration file and these variables
+/// is given.
+///
+/// # Arguments
+///
+/// * `cli` - The parsed command-line arguments
+/// * `config_path` - The configuration file given with `--config`, if any
+///
+/// # Errors
+///
+/// Returns an error if the inventory, the configuration file or the environment variables
+/// cannot be loaded, the prompts fail, or a configuration is invalid.
+fn load_targets(cli: &Cli, config_path: Option<&str>) -> Result<Targets, Box<dyn Error>> {
+    let env_config = Config::from_env()?;
+    let base_config = match (config_path, env_config) {
+        (Some(path), env_config) => {
+            info!("Using the configuration from {}", path);
+            Some(env_config.unwrap_or_default().with_file(path)?)
+        }
+        (None, Some(config)) => {
+            info!(
+                "Using the configuration from the {}* environment variables",
+                config::ENV_PREFIX
+            );
+            Some(config)
+        }
+        (None, None) => None,
+    };
+    if let Some(path) = &cli.inventory {
+        let hosts = inventory::load(path)?.hosts(&base_config.unwrap_or_default())?;
+        for (host, config) in &hosts {
+            config
+                .validate()
+                .map_err(|e| format!("{} (host {})", e, host))?;
+        }
+        return Ok(Targets::Hosts(hosts));
+    }
+
+    let config = match base_config {
+        Some(config) => config,
+        None => get_user_input()?,
+    };
+    config.validate()?;
+    if cli.hosts.is_empty() {
+        return Ok(Targets::Local(Box::new(config)));
+    }
+    Ok(Targets::Hosts(
+        cli.hosts
+            .iter()
+            .map(|host| (host.clone(), config.clone()))
+            .collect(),
+    ))
+}
+
+/// Runs the server setup pipeline against the current thread's `CommandRunner`.
+///
+/// This function runs the phases of `pipeline::phases` in order, including:
 /// - Initial setup
 /// - Security measures implementation
 /// - Automatic updates configuration
@@ -35,86 +444,247 @@ use utils::{generate_report, get_user_input, save_config, setup_logging};
 /// - Backup system configuration
 /// - Container or application deployment
 ///
-/// If any step fails, it attempts to rollback all changes made.
+/// Each phase commits its rollback snapshots when it completes, and the snapshots are stored
+/// on the host under a directory for this run. If a phase fails, the changes it made are
+/// rolled back, while those of the completed phases are kept. With `continue_on_error`, the
+/// failure of an optional phase is reported at the end instead of stopping the run.
+///
+/// When the host has a configuration saved by a previous run, only the phases depending on
+/// a changed option run, unless `--force` is given. The configuration is saved once every
+/// phase succeeded, so the phases of a failed run are run again next time.
+///
+/// Package installs and downloads are limited to the `max_concurrent_installs` and
+/// `max_concurrent_downloads` options (`--max-concurrent-installs` overrides the former).
+///
+/// Whether the run succeeded or failed, its outcome is then posted to the notification
+/// webhook, if one is configured.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing user-defined configuration options
+/// * `cli` - The parsed command-line arguments
 ///
 /// # Errors
 ///
-/// Returns an error if any step in the process fails.
-fn main() -> Result<(), Box<dyn Error>> {
-    // Set up logging for the application
-    setup_logging()?;
-    info!("Server Setup and Maintenance Script started");
-
-    // Get user input for configuration
-    let config = get_user_input()?;
-    save_config(&config)?;
+/// Returns an error if a phase stops the run, or if any optional phase failed.
+fn run_pipeline(config: &Config, cli: &Cli) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+    let mut failed_phases = Vec::new();
+    let limits = Limits::from_config(config);
+    let limits = Limits {
+        installs: cli
+            .max_concurrent_installs
+            .map_or(limits.installs, usize::from),
+        ..limits
+    };
+    let result = with_limits(limits, || {
+        apply_config(config, cli, started, &mut failed_phases)
+    });
+    notify::send_completion(
+        config,
+        &RunOutcome {
+            failed_phases,
+            error: result.as_ref().err().map(|e| e.to_string()),
+            duration: started.elapsed(),
+        },
+    );
+    result
+}
 
-    // Initialize the rollback manager
-    let rollback = RollbackManager::new();
+/// Runs the phases of `run_pipeline`, and saves the configuration if they all succeed.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing user-defined configuration options
+/// * `cli` - The parsed command-line arguments
+/// * `started` - When the run started
+/// * `failed_phases` - Receives the names of the phases that failed
+///
+/// # Errors
+///
+/// Returns an error if a phase stops the run, or if any optional phase failed.
+fn apply_config(
+    config: &Config,
+    cli: &Cli,
+    started: Instant,
+    failed_phases: &mut Vec<String>,
+) -> Result<(), Box<dyn Error>> {
+    // Fail before changing anything when the configuration cannot be encrypted at the end
+    let key = cli
+        .encrypt_config
+        .then(|| encryption::require_config_key("encrypt the saved configuration"))
+        .transpose()?;
+    // The saved configuration has no secrets, so they are taken as unchanged
+    let changes = load_saved_config()?.map(|mut previous| {
+        previous.secrets = config.secrets.clone();
+        previous.diff(config)
+    });
+    print_config_changes(changes.as_deref());
+    configure_proxy(config)?;
+    recover_interrupted_runs(cli)?;
 
-    // Perform initial setup
-    if let Err(e) = setup::initial_setup(&config, &rollback) {
-        error!("Error during initial setup: {}", e);
-        rollback.rollback_all()?;
-        return Err("Setup failed".into());
+    let mut phases = pipeline::phases(config);
+    if let Some(changes) = changes.as_deref().filter(|_| !cli.force) {
+        phases = pipeline::changed_phases(phases, changes);
     }
 
-    // Implement security measures
-    if let Err(e) = security::implement_security_measures(&config, &rollback) {
-        error!("Error implementing security measures: {}", e);
-        rollback.rollback_all()?;
-        return Err("Security implementation failed".into());
-    }
+    // Initialize the rollback manager, which an interrupt rolls back while the run is active
+    let rollback = Arc::new(RollbackManager::with_store(format!(
+        "{}/{}",
+        rollback::STORE_ROOT,
+        chrono::Local::now().format("%Y%m%d_%H%M%S")
+    )));
+    let _active_run = ActiveRun::register(current_runner(), Arc::clone(&rollback));
 
-    // Set up automatic updates
-    if let Err(e) = updates::setup_automatic_updates(&config, &rollback) {
-        error!("Error setting up automatic updates: {}", e);
-        rollback.rollback_all()?;
-        return Err("Update setup failed".into());
+    let summary =
+        pipeline::run_phases(&phases, &rollback, cli.continue_on_error).inspect_err(|e| {
+            if let Some(e) = e.downcast_ref::<PhaseError>() {
+                failed_phases.push(e.phase.clone());
+            }
+        })?;
+    generate_report(config, &summary.timings, started.elapsed())?;
+    if let Err(e) = email_report(config, REPORT_PATH) {
+        warn!("Could not email the setup report: {}", e);
     }
 
-    // Set up monitoring
-    if let Err(e) = monitoring::setup_monitoring(&config, &rollback) {
-        error!("Error setting up monitoring: {}", e);
-        rollback.rollback_all()?;
-        return Err("Monitoring setup failed".into());
+    let failures = summary.failures;
+    if !failures.is_empty() {
+        for failure in &failures {
+            error!("Phase {} failed: {}", failure.phase, failure.error);
+            failed_phases.push(failure.phase.clone());
+        }
+        return Err(format!(
+            "Setup completed with failed phases: {}",
+            failed_phases.join(", ")
+        )
+        .into());
     }
 
-    // Set up backup system
-    if let Err(e) = backup::setup_backup_system(&config, &rollback) {
-        error!("Error setting up backup system: {}", e);
-        rollback.rollback_all()?;
-        return Err("Backup setup failed".into());
+    save_config(config, key.as_ref())?;
+    console::step(StepStatus::Completed, "Server setup completed successfully");
+    Ok(())
+}
+
+/// Deals with the runs on the host of the current `CommandRunner` that crashed or were
+/// killed before committing their snapshots, before a new run changes the host.
+///
+/// The uncommitted changes of each interrupted run are rolled back with `--recover`, left in
+/// place with `--ignore-recovery`, and otherwise the operator is asked what to do with them.
+///
+/// # Arguments
+///
+/// * `cli` - The parsed command-line arguments
+///
+/// # Errors
+///
+/// Returns an error if the stored runs cannot be read or recovered, or if an interrupted run
+/// is found without a terminal to ask on and neither flag is given.
+fn recover_interrupted_runs(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    let host = current_runner().host().to_string();
+    for run in rollback::interrupted_runs(rollback::STORE_ROOT)? {
+        let recovery = if cli.recover {
+            Recovery::RollBack
+        } else if cli.ignore_recovery {
+            Recovery::Ignore
+        } else if std::io::stdin().is_terminal() {
+            progress::suspend(|| prompt_recovery(&host, &run))?
+        } else {
+            return Err(format!(
+                "A previous run on {} was interrupted and left uncommitted changes in {}; \
+                 pass --recover to roll them back or --ignore-recovery to leave them",
+                host, run.dir
+            )
+            .into());
+        };
+
+        match recovery {
+            Recovery::RollBack => warn!("Rolling back the interrupted run in {}", run.dir),
+            Recovery::Commit => warn!("Keeping the changes of the interrupted run in {}", run.dir),
+            Recovery::Ignore => warn!("Ignoring the interrupted run in {}", run.dir),
+        }
+        rollback::recover(&run, recovery)?;
     }
+    Ok(())
+}
 
-    // Deploy containers or applications based on configuration
-    if config.use_containers {
-        if let Err(e) = containerization::setup_docker(&rollback) {
-            error!("Error setting up Docker: {}", e);
-            rollback.rollback_all()?;
-            return Err("Docker setup failed".into());
+/// Logs how the configuration differs from the one saved by the last run on the host of
+/// the current `CommandRunner`.
+///
+/// # Arguments
+///
+/// * `changes` - The changes since the last run, or `None` if no configuration was saved
+fn print_config_changes(changes: Option<&[FieldChange]>) {
+    let host = current_runner().host().to_string();
+    let Some(changes) = changes else {
+        info!("No previous configuration found on {}", host);
+        return;
+    };
+
+    if changes.is_empty() {
+        info!("Configuration of {} is unchanged since the last run", host);
+    } else {
+        info!("Configuration changes on {} since the last run:", host);
+        for change in changes {
+            info!("  {}", change);
         }
+    }
+}
 
-        if config.use_kubernetes {
-            if let Err(e) = containerization::setup_kubernetes(&rollback) {
-                error!("Error setting up Kubernetes: {}", e);
-                rollback.rollback_all()?;
-                return Err("Kubernetes setup failed".into());
-            }
+/// A run in progress: the runner of its host and its rollback manager.
+type Run = (Arc<dyn CommandRunner>, Arc<RollbackManager>);
+
+/// The runs in progress, rolled back on an interrupt.
+static ACTIVE_RUNS: Mutex<Vec<Run>> = Mutex::new(Vec::new());
+
+/// Registers a run in `ACTIVE_RUNS` for as long as it is alive.
+struct ActiveRun(Arc<RollbackManager>);
+
+impl ActiveRun {
+    /// Registers the run of a host.
+    fn register(runner: Arc<dyn CommandRunner>, rollback: Arc<RollbackManager>) -> Self {
+        if let Ok(mut runs) = ACTIVE_RUNS.lock() {
+            runs.push((runner, Arc::clone(&rollback)));
         }
+        ActiveRun(rollback)
+    }
+}
 
-        if let Err(e) = containerization::deploy_containers(&config, &rollback) {
-            error!("Error deploying containers: {}", e);
-            rollback.rollback_all()?;
-            return Err("Container deployment failed".into());
+impl Drop for ActiveRun {
+    fn drop(&mut self) {
+        if let Ok(mut runs) = ACTIVE_RUNS.lock() {
+            runs.retain(|(_, rollback)| !Arc::ptr_eq(rollback, &self.0));
         }
-    } else if let Err(e) = deployment::deploy_applications(&config, &rollback) {
-        error!("Error deploying applications: {}", e);
-        rollback.rollback_all()?;
-        return Err("Application deployment failed".into());
     }
+}
 
-    info!("Server setup completed successfully");
-    generate_report(&config)?;
+/// Installs a handler that rolls back all changes of the active runs on SIGINT or SIGTERM,
+/// then exits.
+///
+/// The first signal is handled on a dedicated thread; signals received while the rollback
+/// is in progress are caught but not acted on, so they cannot interrupt it. A rollback that
+/// a failing run has already started is not repeated, since rolled back snapshots are skipped.
+///
+/// # Errors
+///
+/// Returns an error if the signal handler cannot be registered.
+fn install_interrupt_handler() -> Result<(), Box<dyn Error>> {
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    std::thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            error!("Interrupted by signal {}; rolling back all changes", signal);
+            let runs = match ACTIVE_RUNS.lock() {
+                Ok(runs) => runs.clone(),
+                Err(_) => Vec::new(),
+            };
+            for (runner, rollback) in runs {
+                let host = runner.host().to_string();
+                if let Err(e) = with_runner(runner, || rollback.rollback_all()) {
+                    error!("Rollback after the interrupt failed on {}: {}", host, e);
+                }
+            }
+            error!("Rollback after the interrupt completed");
+            std::process::exit(128 + signal);
+        }
+    });
     Ok(())
 }
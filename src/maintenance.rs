@@ -0,0 +1,176 @@
+//! # Maintenance Module
+//!
+//! This module derives when the recurring maintenance jobs run from the maintenance window
+//! of the configuration. The jobs are staggered so they never run at the same time: backups
+//! start when the window opens, security scans an hour later, and automatic reboots an hour
+//! after that, once both are done.
+//!
+//! Without a maintenance window, each job keeps its own fixed time.
+
+use crate::config::{Config, MaintenanceWindow};
+use crate::security::security_scan_cron_schedule;
+use crate::updates::is_time_of_day;
+use std::error::Error;
+
+/// How long after the maintenance window opens security scans start, in minutes.
+pub const SECURITY_SCAN_DELAY_MINUTES: u32 = 60;
+
+/// How long after the maintenance window opens automatic reboots happen, in minutes.
+pub const REBOOT_DELAY_MINUTES: u32 = 120;
+
+/// The days a maintenance window can be open on, in cron day-of-week order.
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// Lists the mistakes in a maintenance window.
+///
+/// # Arguments
+///
+/// * `window` - The maintenance window to check
+///
+/// # Returns
+///
+/// Returns a description of the invalid start time and of every unknown day, in order.
+pub fn window_errors(window: &MaintenanceWindow) -> Vec<String> {
+    let mut errors = Vec::new();
+    if !is_time_of_day(&window.start) {
+        errors.push(format!(
+            "Invalid maintenance_window start '{}': expected a time such as 02:00",
+            window.start
+        ));
+    }
+    for day in &window.days {
+        if weekday(day).is_none() {
+            errors.push(unknown_day(day));
+        }
+    }
+    errors
+}
+
+/// Returns the cron time fields of the backup job.
+///
+/// Hourly backups ignore the maintenance window. Daily and weekly backups start when it
+/// opens, or at 02:00 without one.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the backup frequency and the
+///   maintenance window
+///
+/// # Errors
+///
+/// Returns an error if the backup frequency or the maintenance window is invalid.
+pub fn backup_schedule(config: &Config) -> Result<String, Box<dyn Error>> {
+    match (config.backup_frequency.as_str(), &config.maintenance_window) {
+        ("hourly", _) => Ok(String::from("0 * * * *")),
+        ("daily", None) => Ok(String::from("0 2 * * *")),
+        ("weekly", None) => Ok(String::from("0 2 * * 0")),
+        (frequency @ ("daily" | "weekly"), Some(window)) => window_schedule(window, 0, frequency),
+        _ => Err("Invalid backup frequency".into()),
+    }
+}
+
+/// Returns the cron time fields of the security scan job.
+///
+/// A "daily", "weekly" or "monthly" scan starts `SECURITY_SCAN_DELAY_MINUTES` after the
+/// maintenance window opens. Cron expressions, and every schedule without a window, are
+/// used as `security_scan_cron_schedule` maps them.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the security scan schedule and
+///   the maintenance window
+///
+/// # Errors
+///
+/// Returns an error if the schedule or the maintenance window is invalid.
+pub fn security_scan_schedule(config: &Config) -> Result<String, Box<dyn Error>> {
+    let schedule = config.security_scan_schedule.as_str();
+    match &config.maintenance_window {
+        Some(window) if matches!(schedule, "daily" | "weekly" | "monthly") => {
+            window_schedule(window, SECURITY_SCAN_DELAY_MINUTES, schedule)
+        }
+        _ => security_scan_cron_schedule(schedule),
+    }
+}
+
+/// Returns the time of day automatic reboots happen at.
+///
+/// An explicit `auto_reboot_time` is kept. Otherwise reboots happen
+/// `REBOOT_DELAY_MINUTES` after the maintenance window opens, or as soon as an update
+/// requires one when there is no window.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the reboot time and the
+///   maintenance window
+pub fn reboot_time(config: &Config) -> Option<String> {
+    if let Some(time) = &config.auto_reboot_time {
+        return Some(time.clone());
+    }
+    let window = config.maintenance_window.as_ref()?;
+    let (minute, hour, _) = window_time(window, REBOOT_DELAY_MINUTES).ok()?;
+    Some(format!("{:02}:{:02}", hour, minute))
+}
+
+/// Returns the cron day-of-week number of a day name such as "sun".
+fn weekday(day: &str) -> Option<usize> {
+    WEEKDAYS
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(day.trim()))
+}
+
+/// Describes a day of a maintenance window that is not a day name.
+fn unknown_day(day: &str) -> String {
+    format!(
+        "Invalid maintenance_window day '{}': expected one of {}",
+        day,
+        WEEKDAYS.join(", ")
+    )
+}
+
+/// Returns the minute and hour `delay` minutes after a window opens, and whether that time
+/// falls on the next day.
+fn window_time(window: &MaintenanceWindow, delay: u32) -> Result<(u32, u32, bool), Box<dyn Error>> {
+    if !is_time_of_day(&window.start) {
+        return Err(window_errors(window).remove(0).into());
+    }
+    let (hours, minutes) = window.start.split_once(':').unwrap_or_default();
+    let minutes = hours.parse::<u32>()? * 60 + minutes.parse::<u32>()? + delay;
+    Ok((minutes % 60, minutes / 60 % 24, minutes >= 24 * 60))
+}
+
+/// Returns the cron time fields of a job running `delay` minutes after a window opens, on
+/// the window's days ("daily"), its first day ("weekly") or the first of the month
+/// ("monthly"). A weekly job of a window open every day runs on Sundays.
+fn window_schedule(
+    window: &MaintenanceWindow,
+    delay: u32,
+    frequency: &str,
+) -> Result<String, Box<dyn Error>> {
+    let (minute, hour, next_day) = window_time(window, delay)?;
+    let shift = usize::from(next_day);
+    let days = window
+        .days
+        .iter()
+        .map(|day| {
+            weekday(day)
+                .map(|day| ((day + shift) % 7).to_string())
+                .ok_or_else(|| unknown_day(day))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let (day_of_month, day_of_week) = match frequency {
+        "daily" if days.is_empty() => (String::from("*"), String::from("*")),
+        "daily" => (String::from("*"), days.join(",")),
+        "weekly" => (
+            String::from("*"),
+            days.first().cloned().unwrap_or_else(|| shift.to_string()),
+        ),
+        "monthly" => ((1 + shift).to_string(), String::from("*")),
+        _ => return Err(format!("Unknown frequency '{}'", frequency).into()),
+    };
+    Ok(format!(
+        "{} {} {} * {}",
+        minute, hour, day_of_month, day_of_week
+    ))
+}
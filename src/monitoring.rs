@@ -1,20 +1,84 @@
 //! # Monitoring Module
 //!
 //! This module provides functionality for setting up a comprehensive monitoring system
-//! using Prometheus, Grafana, and Node Exporter. It handles the installation, configuration,
-//! and deployment of these tools across different Linux distributions.
+//! using Prometheus, Grafana, and Node Exporter, with optional centralized logging via
+//! Loki and Promtail, and cAdvisor for per-container metrics when containers are used. It
+//! handles the installation, configuration, and deployment of these tools across different
+//! Linux distributions.
 
 use crate::config::Config;
-use crate::distro::{get_package_manager, PackageManager};
+use crate::distro::{
+    add_repository, enable_and_start_service, enable_service, get_package_manager,
+    install_package_tracked, restart_service, PackageManager, RepositorySpec,
+};
 use crate::rollback::RollbackManager;
-use crate::utils::run_command;
+use crate::secrets::{secret_store, GRAFANA_ADMIN_PASSWORD};
+use crate::systemd::ServiceUnit;
+use crate::utils::{
+    check_resources, create_dir_all, download, generate_secure_password, read_file, run_command,
+    run_command_with_input, set_permissions, target_arch_suffix, write_file,
+};
+use crate::validation::{validate_config, ServiceType};
 use log::info;
+use std::collections::HashSet;
 use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+
+/// The Prometheus release installed from source.
+pub const PROMETHEUS_VERSION: &str = "2.30.3";
+
+/// How long Prometheus keeps metrics unless `prometheus_retention` is set.
+pub const DEFAULT_PROMETHEUS_RETENTION: &str = "15d";
+
+/// Where Prometheus installed from source stores its metrics unless
+/// `prometheus_storage_path` is set.
+const PROMETHEUS_SOURCE_STORAGE_PATH: &str = "/var/lib/prometheus";
+
+/// The units of a Prometheus duration, in the order they must appear.
+const PROMETHEUS_DURATION_UNITS: [&str; 7] = ["y", "w", "d", "h", "m", "s", "ms"];
+
+/// The Node Exporter release installed from source.
+pub const NODE_EXPORTER_VERSION: &str = "1.2.2";
+
+/// The directory Node Exporter's textfile collector reads `*.prom` metrics files from, so
+/// that scripts such as the backup script can publish metrics of their own.
+pub const TEXTFILE_COLLECTOR_DIR: &str = "/var/lib/node_exporter/textfile_collector";
+
+/// The Grafana configuration file.
+pub const GRAFANA_INI_PATH: &str = "/etc/grafana/grafana.ini";
+
+/// Where the Grafana admin password is saved without a Vault.
+pub const GRAFANA_PASSWORD_FILE: &str = "/root/.grafana_admin_password";
+
+/// The Blackbox Exporter release installed from source.
+pub const BLACKBOX_EXPORTER_VERSION: &str = "0.24.0";
+
+/// The Loki and Promtail release installed from source.
+pub const LOKI_VERSION: &str = "2.9.4";
+
+/// The cAdvisor image run by `setup_cadvisor`.
+pub const CADVISOR_IMAGE: &str = "gcr.io/cadvisor/cadvisor:v0.47.2";
+
+/// The port cAdvisor serves its metrics on.
+pub const CADVISOR_PORT: u16 = 8080;
+
+/// The path of the alerting rules file written by `write_default_alert_rules`.
+pub const ALERT_RULES_PATH: &str = "/etc/prometheus/rules/server_forge.rules.yml";
+
+/// The least total memory, in megabytes, the monitoring stack is installed on.
+pub const MONITORING_MIN_MEMORY_MB: u64 = 1024;
+
+/// The least free disk space, in megabytes, the monitoring stack is installed on, mostly
+/// for the Prometheus (and Loki) data.
+pub const MONITORING_MIN_DISK_MB: u64 = 5 * 1024;
 
 /// Sets up the monitoring system based on the provided configuration.
 ///
 /// This function orchestrates the installation and configuration of Prometheus, Grafana,
-/// and Node Exporter. If monitoring is disabled in the configuration, it skips the setup.
+/// and Node Exporter, plus the Blackbox Exporter when there are uptime probe targets and
+/// Loki and Promtail when `enable_logs` is set. If monitoring is disabled in the
+/// configuration, it skips the setup. Nothing is installed on a host with less than
+/// `MONITORING_MIN_MEMORY_MB` of memory or `MONITORING_MIN_DISK_MB` of free disk space.
 ///
 /// # Arguments
 ///
@@ -28,12 +92,27 @@ pub fn setup_monitoring(config: &Config, rollback: &RollbackManager) -> Result<(
     if config.monitoring {
         info!("Setting up monitoring...");
 
-        let snapshot = rollback.create_snapshot()?;
+        // Fail before installing anything if the bind address is invalid or the host is too small
+        listen_address(config, config.prometheus_port)?;
+        check_resources(MONITORING_MIN_MEMORY_MB, MONITORING_MIN_DISK_MB, 1)
+            .map_err(|e| format!("Cannot install monitoring: {}", e))?;
+
+        let snapshot = rollback.create_snapshot("Monitoring")?;
+
+        install_monitoring_tools(config, rollback, snapshot)?;
+        write_default_alert_rules()?;
+        configure_prometheus(config)?;
+        setup_grafana(config)?;
+        setup_node_exporter(config, rollback, snapshot)?;
+
+        if !blackbox_targets(config).is_empty() {
+            setup_blackbox_exporter(config)?;
+        }
 
-        install_monitoring_tools(config)?;
-        configure_prometheus()?;
-        setup_grafana()?;
-        setup_node_exporter()?;
+        if config.enable_logs {
+            setup_loki(config)?;
+            setup_promtail(config)?;
+        }
 
         rollback.commit_snapshot(snapshot)?;
 
@@ -49,110 +128,419 @@ pub fn setup_monitoring(config: &Config, rollback: &RollbackManager) -> Result<(
 /// # Arguments
 ///
 /// * `config` - A reference to the `Config` struct (unused in the current implementation)
+/// * `rollback` - The `RollbackManager` to record the installed packages in
+/// * `snapshot` - The ID of the snapshot to record the installed packages in
 ///
 /// # Errors
 ///
 /// Returns an error if the installation of either Prometheus or Grafana fails.
-pub fn install_monitoring_tools(_config: &Config) -> Result<(), Box<dyn Error>> {
+pub fn install_monitoring_tools(
+    config: &Config,
+    rollback: &RollbackManager,
+    snapshot: usize,
+) -> Result<(), Box<dyn Error>> {
     let package_manager = get_package_manager()?;
 
     // Install Prometheus
     match package_manager {
         PackageManager::Apt => {
             run_command("apt", &["update"])?;
-            run_command("apt", &["install", "-y", "prometheus"])?;
+            install_package_tracked(&package_manager, "prometheus", rollback, snapshot)?;
         }
         PackageManager::Yum | PackageManager::Dnf => {
             // For CentOS/Fedora, we need to install from source
-            install_prometheus_from_source()?;
+            install_prometheus_from_source(config)?;
         }
     }
 
     // Install Grafana
-    match package_manager {
-        PackageManager::Apt => {
-            run_command(
-                "apt",
-                &[
-                    "install",
-                    "-y",
-                    "apt-transport-https",
-                    "software-properties-common",
-                    "wget",
-                ],
-            )?;
-            run_command(
-                "wget",
-                &[
-                    "-q",
-                    "-O",
-                    "/usr/share/keyrings/grafana.key",
-                    "https://packages.grafana.com/gpg.key",
-                ],
-            )?;
-            run_command("echo", &["deb [signed-by=/usr/share/keyrings/grafana.key] https://packages.grafana.com/oss/deb stable main", ">", "/etc/apt/sources.list.d/grafana.list"])?;
-            run_command("apt", &["update"])?;
-            run_command("apt", &["install", "-y", "grafana"])?;
-        }
-        PackageManager::Yum | PackageManager::Dnf => {
-            run_command(
-                "wget",
-                &[
-                    "-q",
-                    "-O",
-                    "/etc/yum.repos.d/grafana.repo",
-                    "https://packages.grafana.com/oss/rpm/grafana.repo",
-                ],
-            )?;
-            match package_manager {
-                PackageManager::Yum => run_command("yum", &["install", "-y", "grafana"])?,
-                PackageManager::Dnf => run_command("dnf", &["install", "-y", "grafana"])?,
-                _ => unreachable!(),
-            }
+    if package_manager == PackageManager::Apt {
+        for package in ["apt-transport-https", "ca-certificates", "curl", "gnupg"] {
+            install_package_tracked(&package_manager, package, rollback, snapshot)?;
         }
     }
+    add_repository(&package_manager, &grafana_repository(&package_manager))?;
+    install_package_tracked(&package_manager, "grafana", rollback, snapshot)?;
 
     Ok(())
 }
 
+/// Returns the Grafana OSS repository for a package manager.
+///
+/// # Arguments
+///
+/// * `package_manager` - The package manager of the machine
+pub fn grafana_repository(package_manager: &PackageManager) -> RepositorySpec {
+    let url = match package_manager {
+        PackageManager::Apt => "https://packages.grafana.com/oss/deb",
+        PackageManager::Yum | PackageManager::Dnf => "https://packages.grafana.com/oss/rpm",
+    };
+    RepositorySpec {
+        name: String::from("grafana"),
+        url: String::from(url),
+        gpg_key_url: String::from("https://packages.grafana.com/gpg.key"),
+        codename: Some(String::from("stable")),
+        components: vec![String::from("main")],
+        arch: None,
+    }
+}
+
 /// Configures Prometheus with a basic scrape configuration.
 ///
-/// This function creates a basic Prometheus configuration file and
-/// restarts the Prometheus service.
+/// This function writes the Prometheus configuration generated by
+/// `generate_prometheus_config`, which loads the alerting rules from `ALERT_RULES_PATH`,
+/// and restarts the Prometheus service. On apt-based systems the packaged service reads
+/// its flags from `/etc/default/prometheus`, so the flags of `prometheus_args` are set
+/// there, and the configured storage directory is created for it; source installs get them
+/// in their systemd unit. The configuration is checked with `promtool check config` before
+/// Prometheus is restarted, so a broken file fails the monitoring phase instead.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing user-defined configuration options
 ///
 /// # Errors
 ///
-/// Returns an error if writing the configuration file or restarting the service fails.
-pub fn configure_prometheus() -> Result<(), Box<dyn Error>> {
-    let prometheus_config = r#"
+/// Returns an error if writing the configuration file, checking it or restarting the
+/// service fails.
+pub fn configure_prometheus(config: &Config) -> Result<(), Box<dyn Error>> {
+    let prometheus_config = generate_prometheus_config(config);
+    write_file("/etc/prometheus/prometheus.yml", prometheus_config)?;
+
+    if let PackageManager::Apt = get_package_manager()? {
+        if let Some(path) = &config.prometheus_storage_path {
+            create_dir_all(path)?;
+            run_command("chown", &["prometheus:prometheus", path])?;
+        }
+        write_file(
+            "/etc/default/prometheus",
+            format!("ARGS=\"{}\"\n", prometheus_args(config)?.join(" ")),
+        )?;
+    }
+
+    validate_config(ServiceType::Prometheus, "/etc/prometheus/prometheus.yml")?;
+    restart_service("prometheus")?;
+    enable_service("prometheus")?;
+
+    Ok(())
+}
+
+/// Generates the Prometheus configuration.
+///
+/// The configuration always scrapes Node Exporter. If there are uptime probe targets
+/// (see `blackbox_targets`), it also adds `blackbox` (HTTP) and `blackbox_tcp` jobs that
+/// probe them through the Blackbox Exporter, using `__param_target` relabeling so each
+/// probed URL or address becomes the `instance` label.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing user-defined configuration options
+///
+/// # Returns
+///
+/// Returns the contents of `prometheus.yml`.
+pub fn generate_prometheus_config(config: &Config) -> String {
+    let mut prometheus_config = String::from(
+        r#"
 global:
   scrape_interval: 15s
+  evaluation_interval: 15s
+
+rule_files:
+  - /etc/prometheus/rules/*.rules.yml
 
 scrape_configs:
-  - job_name: 'node'
+"#,
+    );
+    prometheus_config.push_str(&static_scrape_job("node", &scrape_address(config, 9100)));
+    if config.use_containers {
+        prometheus_config.push_str(&static_scrape_job(
+            "cadvisor",
+            &scrape_address(config, CADVISOR_PORT),
+        ));
+    }
+
+    let (http_targets, tcp_targets): (Vec<String>, Vec<String>) = blackbox_targets(config)
+        .into_iter()
+        .partition(|target| target.starts_with("http://") || target.starts_with("https://"));
+
+    for (job, module, targets) in [
+        ("blackbox", "http_2xx", http_targets),
+        ("blackbox_tcp", "tcp_connect", tcp_targets),
+    ] {
+        if targets.is_empty() {
+            continue;
+        }
+        prometheus_config.push_str(&format!(
+            r#"  - job_name: '{}'
+    metrics_path: /probe
+    params:
+      module: [{}]
+    static_configs:
+      - targets:
+"#,
+            job, module
+        ));
+        for target in targets {
+            prometheus_config.push_str(&format!("          - '{}'\n", target));
+        }
+        prometheus_config.push_str(
+            r#"    relabel_configs:
+      - source_labels: [__address__]
+        target_label: __param_target
+      - source_labels: [__param_target]
+        target_label: instance
+      - target_label: __address__
+        replacement: localhost:9115
+"#,
+        );
+    }
+
+    prometheus_config
+}
+
+/// Returns a scrape job of the Prometheus configuration scraping a single target.
+fn static_scrape_job(job: &str, target: &str) -> String {
+    format!(
+        r#"  - job_name: '{}'
     static_configs:
-      - targets: ['localhost:9100']
+      - targets: ['{}']
+"#,
+        job, target
+    )
+}
+
+/// Returns the flags Prometheus is started with on top of those of its installation: its
+/// listen address, how long it keeps metrics and, when configured, where it stores them.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the monitoring settings
+///
+/// # Errors
+///
+/// Returns an error if the bind address is not a valid IP address.
+pub fn prometheus_args(config: &Config) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut args = vec![
+        format!(
+            "--web.listen-address={}",
+            listen_address(config, config.prometheus_port)?
+        ),
+        format!(
+            "--storage.tsdb.retention.time={}",
+            config.prometheus_retention
+        ),
+    ];
+    if let Some(path) = &config.prometheus_storage_path {
+        args.push(format!("--storage.tsdb.path={}", path));
+    }
+    Ok(args)
+}
+
+/// Returns whether a string is a Prometheus duration, such as "15d" or "1w3d12h": numbers
+/// followed by a unit (y, w, d, h, m, s or ms), largest unit first, each unit at most once.
+///
+/// # Arguments
+///
+/// * `duration` - The string to check
+pub fn is_prometheus_duration(duration: &str) -> bool {
+    let mut rest = duration;
+    let mut previous = None;
+    while !rest.is_empty() {
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits == 0 {
+            return false;
+        }
+        rest = &rest[digits..];
+
+        let unit = if rest.starts_with("ms") {
+            "ms"
+        } else {
+            rest.get(..1).unwrap_or_default()
+        };
+        let Some(index) = PROMETHEUS_DURATION_UNITS.iter().position(|u| *u == unit) else {
+            return false;
+        };
+        if previous.is_some_and(|previous| index <= previous) {
+            return false;
+        }
+        previous = Some(index);
+        rest = &rest[unit.len()..];
+    }
+    previous.is_some()
+}
+
+/// Returns the `<addr>:<port>` a monitoring service should listen on.
+///
+/// The address is the configured `monitoring_bind_address`, which must be a valid IPv4
+/// or IPv6 address.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the bind address
+/// * `port` - The port the service listens on
+///
+/// # Returns
+///
+/// Returns the listen address, or an error if the bind address is not a valid IP address.
+pub fn listen_address(config: &Config, port: u16) -> Result<String, Box<dyn Error>> {
+    let address: IpAddr = config.monitoring_bind_address.parse().map_err(|_| {
+        format!(
+            "Invalid monitoring bind address '{}': expected an IP address",
+            config.monitoring_bind_address
+        )
+    })?;
+    Ok(SocketAddr::new(address, port).to_string())
+}
+
+/// Returns the address Prometheus scrapes a local exporter on.
+///
+/// Exporters bound to all interfaces are scraped on `localhost`; otherwise they are
+/// scraped on the bind address they listen on.
+fn scrape_address(config: &Config, port: u16) -> String {
+    match config.monitoring_bind_address.parse::<IpAddr>() {
+        Ok(address) if !address.is_unspecified() => SocketAddr::new(address, port).to_string(),
+        _ => format!("localhost:{}", port),
+    }
+}
+
+/// Returns the targets the Blackbox Exporter should probe.
+///
+/// These are the configured `uptime_probe_targets` (URLs are probed over HTTP, `host:port`
+/// addresses over TCP), plus the local site for each deployed web server (nginx or apache).
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the probe targets and deployed apps
+///
+/// # Returns
+///
+/// Returns the list of probe targets in the order they were given, without duplicates.
+pub fn blackbox_targets(config: &Config) -> Vec<String> {
+    let mut targets = config.uptime_probe_targets.clone();
+    if config
+        .deployed_apps
+        .iter()
+        .any(|app| app == "nginx" || app == "apache")
+    {
+        targets.push(String::from("http://localhost/"));
+    }
+    let mut seen = HashSet::new();
+    targets.retain(|target| seen.insert(target.clone()));
+    targets
+}
+
+/// Writes the default alerting rules for Prometheus.
+///
+/// This function creates `ALERT_RULES_PATH` with alerts for low disk space, sustained
+/// high CPU usage, and low available memory, based on Node Exporter metrics, and for
+/// failing Blackbox Exporter uptime probes. The rules
+/// are validated with `promtool check rules` so that a broken file never reaches Prometheus.
+///
+/// # Errors
+///
+/// Returns an error if writing the rules file fails or if the rules fail validation.
+pub fn write_default_alert_rules() -> Result<(), Box<dyn Error>> {
+    let alert_rules = r#"groups:
+  - name: server_forge
+    rules:
+      - alert: LowDiskSpace
+        expr: node_filesystem_avail_bytes{fstype!~"tmpfs|overlay|squashfs"} / node_filesystem_size_bytes{fstype!~"tmpfs|overlay|squashfs"} * 100 < 10
+        for: 5m
+        labels:
+          severity: warning
+        annotations:
+          summary: "Low disk space on {{ $labels.instance }}"
+          description: "Filesystem {{ $labels.mountpoint }} has {{ $value | humanize }}% space available."
+      - alert: HighCpuUsage
+        expr: 100 - avg by (instance) (rate(node_cpu_seconds_total{mode="idle"}[5m])) * 100 > 90
+        for: 15m
+        labels:
+          severity: warning
+        annotations:
+          summary: "High CPU usage on {{ $labels.instance }}"
+          description: "CPU usage has been above 90% for 15 minutes (currently {{ $value | humanize }}%)."
+      - alert: LowAvailableMemory
+        expr: node_memory_MemAvailable_bytes / node_memory_MemTotal_bytes * 100 < 10
+        for: 10m
+        labels:
+          severity: warning
+        annotations:
+          summary: "Low available memory on {{ $labels.instance }}"
+          description: "Only {{ $value | humanize }}% of memory is available."
+      - alert: EndpointDown
+        expr: probe_success == 0
+        for: 2m
+        labels:
+          severity: critical
+        annotations:
+          summary: "{{ $labels.instance }} is down"
+          description: "The uptime probe for {{ $labels.instance }} has been failing for 2 minutes."
 "#;
-    std::fs::write("/etc/prometheus/prometheus.yml", prometheus_config)?;
+    create_dir_all("/etc/prometheus/rules")?;
+    write_file(ALERT_RULES_PATH, alert_rules)?;
 
-    run_command("systemctl", &["restart", "prometheus"])?;
-    run_command("systemctl", &["enable", "prometheus"])?;
+    run_command("promtool", &["check", "rules", ALERT_RULES_PATH])?;
 
     Ok(())
 }
 
 /// Sets up and starts the Grafana server.
 ///
-/// This function starts the Grafana server and enables it to start on boot.
+/// This function sets Grafana's `http_port` and `http_addr` in `GRAFANA_INI_PATH` to the
+/// configured `grafana_port` and `monitoring_bind_address`, starts the Grafana server, enables it to start on boot, and replaces
+/// the default `admin`/`admin` credentials. The admin password is taken from
+/// `grafana_admin_password` or generated if unset, and saved to the secret store
+/// (`GRAFANA_PASSWORD_FILE` without a Vault).
 /// Additional configuration (like adding data sources or creating dashboards)
 /// could be added here in the future.
 ///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing user-defined configuration options
+///
 /// # Errors
 ///
-/// Returns an error if starting or enabling the Grafana service fails.
-pub fn setup_grafana() -> Result<(), Box<dyn Error>> {
-    run_command("systemctl", &["start", "grafana-server"])?;
-    run_command("systemctl", &["enable", "grafana-server"])?;
+/// Returns an error if starting or enabling the Grafana service, resetting the admin
+/// password or saving it fails.
+pub fn setup_grafana(config: &Config) -> Result<(), Box<dyn Error>> {
+    let grafana_ini = read_file(GRAFANA_INI_PATH)?;
+    let grafana_ini = set_ini_option(
+        &grafana_ini,
+        "server",
+        "http_port",
+        &config.grafana_port.to_string(),
+    );
+    let grafana_ini = set_ini_option(
+        &grafana_ini,
+        "server",
+        "http_addr",
+        &config.monitoring_bind_address,
+    );
+    write_file(GRAFANA_INI_PATH, grafana_ini)?;
+
+    enable_and_start_service("grafana-server")?;
+
+    let password = config
+        .secrets
+        .grafana_admin_password
+        .clone()
+        .unwrap_or_else(generate_secure_password);
+    // The password is passed on standard input, to keep it out of the logs
+    run_command_with_input(
+        "grafana-cli",
+        &[
+            "--homepath",
+            "/usr/share/grafana",
+            "admin",
+            "reset-admin-password",
+            "--password-from-stdin",
+        ],
+        &password,
+    )?;
+
+    // Save the password alongside the database passwords
+    secret_store(config)?.put(GRAFANA_ADMIN_PASSWORD, &password)?;
 
     // Here we will add code to configure Grafana via its API
     // For example, adding data sources, creating dashboards, etc.
@@ -160,29 +548,117 @@ pub fn setup_grafana() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// Sets an option in an INI file, as used by `grafana.ini`.
+///
+/// Any existing assignment of `key` in `section`, including a commented-out default
+/// (`;key = ...`), is replaced. If there is none, the option is added at the end of the
+/// section, and the section is appended if it does not exist.
+///
+/// # Arguments
+///
+/// * `contents` - The current contents of the INI file
+/// * `section` - The section name, without brackets
+/// * `key` - The option name
+/// * `value` - The option value
+///
+/// # Returns
+///
+/// Returns the updated file contents.
+pub fn set_ini_option(contents: &str, section: &str, key: &str, value: &str) -> String {
+    let header = format!("[{}]", section);
+    let assignment = format!("{} = {}", key, value);
+    let is_key = |line: &str| match line.trim_start_matches([';', '#']).split_once('=') {
+        Some((name, _)) => name.trim() == key,
+        None => false,
+    };
+
+    let mut lines: Vec<String> = Vec::new();
+    let mut in_section = false;
+    let mut done = false;
+    for line in contents.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            if in_section && !done {
+                // Keep the blank lines separating this section from the next one
+                let end = lines.len()
+                    - lines
+                        .iter()
+                        .rev()
+                        .take_while(|line| line.trim().is_empty())
+                        .count();
+                lines.insert(end, assignment.clone());
+                done = true;
+            }
+            in_section = trimmed == header;
+        } else if in_section && !done && is_key(trimmed) {
+            lines.push(assignment.clone());
+            done = true;
+            continue;
+        }
+        lines.push(line.to_string());
+    }
+
+    if !done {
+        if !in_section {
+            lines.push(header);
+        }
+        lines.push(assignment);
+    }
+
+    let mut updated = lines.join("\n");
+    updated.push('\n');
+    updated
+}
+
 /// Sets up and starts the Node Exporter.
 ///
 /// This function installs Node Exporter (either via package manager or from source),
-/// starts the Node Exporter service, and enables it to start on boot.
+/// makes it listen on the configured `monitoring_bind_address` and read the metrics files
+/// in `TEXTFILE_COLLECTOR_DIR`, starts the Node Exporter service, and enables it to start
+/// on boot.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing user-defined configuration options
+/// * `rollback` - The `RollbackManager` to record the installed package in
+/// * `snapshot` - The ID of the snapshot to record the installed package in
 ///
 /// # Errors
 ///
 /// Returns an error if installation, starting, or enabling the Node Exporter service fails.
-pub fn setup_node_exporter() -> Result<(), Box<dyn Error>> {
+pub fn setup_node_exporter(
+    config: &Config,
+    rollback: &RollbackManager,
+    snapshot: usize,
+) -> Result<(), Box<dyn Error>> {
     let package_manager = get_package_manager()?;
+    create_dir_all(TEXTFILE_COLLECTOR_DIR)?;
+    set_permissions(TEXTFILE_COLLECTOR_DIR, 0o755)?;
 
     match package_manager {
         PackageManager::Apt => {
-            run_command("apt", &["install", "-y", "prometheus-node-exporter"])?;
+            install_package_tracked(
+                &package_manager,
+                "prometheus-node-exporter",
+                rollback,
+                snapshot,
+            )?;
+            write_file(
+                "/etc/default/prometheus-node-exporter",
+                format!(
+                    "ARGS=\"--web.listen-address={} --collector.textfile.directory={}\"\n",
+                    listen_address(config, 9100)?,
+                    TEXTFILE_COLLECTOR_DIR
+                ),
+            )?;
         }
         PackageManager::Yum | PackageManager::Dnf => {
             // For CentOS/Fedora, we need to install from source
-            install_node_exporter_from_source()?;
+            install_node_exporter_from_source(config)?;
         }
     }
 
-    run_command("systemctl", &["start", "node_exporter"])?;
-    run_command("systemctl", &["enable", "node_exporter"])?;
+    enable_and_start_service("node_exporter")?;
 
     Ok(())
 }
@@ -190,15 +666,32 @@ pub fn setup_node_exporter() -> Result<(), Box<dyn Error>> {
 /// Installs Prometheus from source.
 ///
 /// This function is used for systems where Prometheus is not available
-/// through the package manager (e.g., CentOS, Fedora).
+/// through the package manager (e.g., CentOS, Fedora). The systemd unit is rendered by
+/// `prometheus_service_unit`.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing user-defined configuration options
 ///
 /// # Errors
 ///
 /// Returns an error if any step of the source installation process fails.
-pub fn install_prometheus_from_source() -> Result<(), Box<dyn Error>> {
-    run_command("wget", &["https://github.com/prometheus/prometheus/releases/download/v2.30.3/prometheus-2.30.3.linux-amd64.tar.gz"])?;
-    run_command("tar", &["xvfz", "prometheus-2.30.3.linux-amd64.tar.gz"])?;
-    run_command("mv", &["prometheus-2.30.3.linux-amd64", "prometheus"])?;
+pub fn install_prometheus_from_source(config: &Config) -> Result<(), Box<dyn Error>> {
+    let release = format!(
+        "prometheus-{}.linux-{}",
+        PROMETHEUS_VERSION,
+        target_arch_suffix()?
+    );
+    download(
+        config,
+        &format!(
+            "https://github.com/prometheus/prometheus/releases/download/v{}/{}.tar.gz",
+            PROMETHEUS_VERSION, release
+        ),
+        &format!("{}.tar.gz", release),
+    )?;
+    run_command("tar", &["xvfz", &format!("{}.tar.gz", release)])?;
+    run_command("mv", &[&release, "prometheus"])?;
 
     // Create Prometheus user
     run_command(
@@ -207,14 +700,12 @@ pub fn install_prometheus_from_source() -> Result<(), Box<dyn Error>> {
     )?;
 
     // Create directories and set ownership
-    run_command("mkdir", &["/etc/prometheus", "/var/lib/prometheus"])?;
+    let storage_path = prometheus_storage_path(config);
+    run_command("mkdir", &["/etc/prometheus"])?;
+    create_dir_all(storage_path)?;
     run_command(
         "chown",
-        &[
-            "prometheus:prometheus",
-            "/etc/prometheus",
-            "/var/lib/prometheus",
-        ],
+        &["prometheus:prometheus", "/etc/prometheus", storage_path],
     )?;
 
     // Move binaries and set ownership
@@ -254,7 +745,38 @@ pub fn install_prometheus_from_source() -> Result<(), Box<dyn Error>> {
     run_command("chown", &["-R", "prometheus:prometheus", "/etc/prometheus"])?;
 
     // Create systemd service file
-    let service_file = r#"[Unit]
+    write_file(
+        "/etc/systemd/system/prometheus.service",
+        prometheus_service_unit(config)?,
+    )?;
+
+    run_command("systemctl", &["daemon-reload"])?;
+
+    Ok(())
+}
+
+/// Returns the directory Prometheus installed from source stores its metrics in.
+fn prometheus_storage_path(config: &Config) -> &str {
+    config
+        .prometheus_storage_path
+        .as_deref()
+        .unwrap_or(PROMETHEUS_SOURCE_STORAGE_PATH)
+}
+
+/// Returns the systemd unit of Prometheus installed from source, which listens on the
+/// configured `monitoring_bind_address` and `prometheus_port`, and keeps metrics in
+/// `prometheus_storage_path` for `prometheus_retention`.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the monitoring settings
+///
+/// # Errors
+///
+/// Returns an error if the bind address is not a valid IP address.
+pub fn prometheus_service_unit(config: &Config) -> Result<String, Box<dyn Error>> {
+    Ok(format!(
+        r#"[Unit]
 Description=Prometheus
 Wants=network-online.target
 After=network-online.target
@@ -265,31 +787,49 @@ Group=prometheus
 Type=simple
 ExecStart=/usr/local/bin/prometheus \
     --config.file /etc/prometheus/prometheus.yml \
-    --storage.tsdb.path /var/lib/prometheus/ \
+    --storage.tsdb.path {}/ \
+    --storage.tsdb.retention.time={} \
     --web.console.templates=/etc/prometheus/consoles \
-    --web.console.libraries=/etc/prometheus/console_libraries
+    --web.console.libraries=/etc/prometheus/console_libraries \
+    --web.listen-address={}
 
 [Install]
 WantedBy=multi-user.target
-"#;
-    std::fs::write("/etc/systemd/system/prometheus.service", service_file)?;
-
-    run_command("systemctl", &["daemon-reload"])?;
-
-    Ok(())
+"#,
+        prometheus_storage_path(config).trim_end_matches('/'),
+        config.prometheus_retention,
+        listen_address(config, config.prometheus_port)?
+    ))
 }
 
 /// Installs Node Exporter from source.
 ///
 /// This function is used for systems where Node Exporter is not available
-/// through the package manager (e.g., CentOS, Fedora).
+/// through the package manager (e.g., CentOS, Fedora). The systemd unit listens on
+/// the configured `monitoring_bind_address`.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing user-defined configuration options
 ///
 /// # Errors
 ///
 /// Returns an error if any step of the source installation process fails.
-pub fn install_node_exporter_from_source() -> Result<(), Box<dyn Error>> {
-    run_command("wget", &["https://github.com/prometheus/node_exporter/releases/download/v1.2.2/node_exporter-1.2.2.linux-amd64.tar.gz"])?;
-    run_command("tar", &["xvfz", "node_exporter-1.2.2.linux-amd64.tar.gz"])?;
+pub fn install_node_exporter_from_source(config: &Config) -> Result<(), Box<dyn Error>> {
+    let release = format!(
+        "node_exporter-{}.linux-{}",
+        NODE_EXPORTER_VERSION,
+        target_arch_suffix()?
+    );
+    download(
+        config,
+        &format!(
+            "https://github.com/prometheus/node_exporter/releases/download/v{}/{}.tar.gz",
+            NODE_EXPORTER_VERSION, release
+        ),
+        &format!("{}.tar.gz", release),
+    )?;
+    run_command("tar", &["xvfz", &format!("{}.tar.gz", release)])?;
 
     // Create Node Exporter user
     run_command(
@@ -300,10 +840,7 @@ pub fn install_node_exporter_from_source() -> Result<(), Box<dyn Error>> {
     // Move binary and set ownership
     run_command(
         "mv",
-        &[
-            "node_exporter-1.2.2.linux-amd64/node_exporter",
-            "/usr/local/bin/",
-        ],
+        &[&format!("{}/node_exporter", release), "/usr/local/bin/"],
     )?;
     run_command(
         "chown",
@@ -314,23 +851,411 @@ pub fn install_node_exporter_from_source() -> Result<(), Box<dyn Error>> {
     )?;
 
     // Create systemd service file
-    let service_file = r#"[Unit]
-Description=Node Exporter
-Wants=network-online.target
-After=network-online.target
+    let service_file = ServiceUnit::new(
+        "Node Exporter",
+        &format!(
+            "/usr/local/bin/node_exporter --web.listen-address={} \
+             --collector.textfile.directory={}",
+            listen_address(config, 9100)?,
+            TEXTFILE_COLLECTOR_DIR
+        ),
+    )
+    .user("node_exporter")
+    .render();
+    write_file("/etc/systemd/system/node_exporter.service", service_file)?;
 
-[Service]
-User=node_exporter
-Group=node_exporter
-Type=simple
-ExecStart=/usr/local/bin/node_exporter
+    run_command("systemctl", &["daemon-reload"])?;
 
-[Install]
-WantedBy=multi-user.target
+    Ok(())
+}
+
+/// Runs cAdvisor as a Docker container, so that Prometheus gets the CPU, memory, network
+/// and disk usage of every container.
+///
+/// The container replaces any previous `cadvisor` container, restarts with Docker, and
+/// publishes its metrics on `CADVISOR_PORT` at the configured `monitoring_bind_address`,
+/// where the `cadvisor` job added by `generate_prometheus_config` scrapes them. Docker must
+/// be installed, so this runs after the Docker setup rather than with the rest of the
+/// monitoring stack.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the monitoring settings
+/// * `rollback` - A reference to the `RollbackManager` for creating snapshots
+///
+/// # Errors
+///
+/// Returns an error if the bind address is invalid or the container cannot be started.
+pub fn setup_cadvisor(config: &Config, rollback: &RollbackManager) -> Result<(), Box<dyn Error>> {
+    info!("Setting up cAdvisor...");
+    let args = cadvisor_run_args(config)?;
+    let snapshot = rollback.create_snapshot("cAdvisor")?;
+
+    run_command("docker", &["pull", CADVISOR_IMAGE])?;
+    run_command("docker", &["rm", "-f", "cadvisor"]).ok();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    run_command("docker", &args)?;
+
+    rollback.commit_snapshot(snapshot)?;
+    info!("cAdvisor setup completed");
+    Ok(())
+}
+
+/// Returns the arguments of the `docker` command running cAdvisor, with the read-only host
+/// mounts it reads container statistics from.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the monitoring settings
+///
+/// # Errors
+///
+/// Returns an error if the bind address is not a valid IP address.
+pub fn cadvisor_run_args(config: &Config) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut args: Vec<String> = [
+        "run",
+        "-d",
+        "--name",
+        "cadvisor",
+        "--restart",
+        "unless-stopped",
+        "--privileged",
+        "--device",
+        "/dev/kmsg",
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect();
+    args.push(String::from("-p"));
+    args.push(format!(
+        "{}:{}",
+        listen_address(config, CADVISOR_PORT)?,
+        CADVISOR_PORT
+    ));
+    for volume in [
+        "/:/rootfs:ro",
+        "/var/run:/var/run:ro",
+        "/sys:/sys:ro",
+        "/var/lib/docker/:/var/lib/docker:ro",
+        "/dev/disk/:/dev/disk:ro",
+    ] {
+        args.push(String::from("-v"));
+        args.push(volume.to_string());
+    }
+    args.push(CADVISOR_IMAGE.to_string());
+    Ok(args)
+}
+
+/// Sets up the Blackbox Exporter for HTTP and TCP uptime probing.
+///
+/// This function installs the Blackbox Exporter from its GitHub release, creates a dedicated
+/// `blackbox_exporter` user, writes a configuration with `http_2xx` and `tcp_connect` probe
+/// modules, and starts it as a systemd service listening on port 9115. Prometheus drives
+/// the probes through the jobs added by `generate_prometheus_config`.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing user-defined configuration options
+///
+/// # Errors
+///
+/// Returns an error if any step of the installation or configuration fails.
+pub fn setup_blackbox_exporter(config: &Config) -> Result<(), Box<dyn Error>> {
+    let release = format!(
+        "blackbox_exporter-{}.linux-{}",
+        BLACKBOX_EXPORTER_VERSION,
+        target_arch_suffix()?
+    );
+    install_release_binary(
+        config,
+        &format!(
+            "https://github.com/prometheus/blackbox_exporter/releases/download/v{}/{}.tar.gz",
+            BLACKBOX_EXPORTER_VERSION, release
+        ),
+        &format!("{}/blackbox_exporter", release),
+        "blackbox_exporter",
+    )?;
+    create_system_user("blackbox_exporter")?;
+
+    let blackbox_config = r#"modules:
+  http_2xx:
+    prober: http
+    timeout: 5s
+    http:
+      preferred_ip_protocol: ip4
+      follow_redirects: true
+  tcp_connect:
+    prober: tcp
+    timeout: 5s
+"#;
+    create_dir_all("/etc/blackbox_exporter")?;
+    write_file("/etc/blackbox_exporter/blackbox.yml", blackbox_config)?;
+
+    let service_file = ServiceUnit::new(
+        "Blackbox Exporter",
+        "/usr/local/bin/blackbox_exporter --config.file=/etc/blackbox_exporter/blackbox.yml",
+    )
+    .user("blackbox_exporter")
+    .render();
+    write_file(
+        "/etc/systemd/system/blackbox_exporter.service",
+        service_file,
+    )?;
+
+    run_command("systemctl", &["daemon-reload"])?;
+    enable_and_start_service("blackbox_exporter")?;
+
+    Ok(())
+}
+
+/// Sets up Loki for centralized log storage.
+///
+/// This function installs the Loki binary from its GitHub release, creates a dedicated
+/// `loki` user, writes a single-node configuration storing data under `/var/lib/loki`,
+/// and starts Loki as a systemd service listening on port 3100. Loki is also provisioned
+/// as a Grafana datasource.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing user-defined configuration options
+///
+/// # Errors
+///
+/// Returns an error if any step of the installation or configuration fails.
+pub fn setup_loki(config: &Config) -> Result<(), Box<dyn Error>> {
+    let release = format!("loki-linux-{}", loki_arch_suffix()?);
+    install_release_binary(
+        config,
+        &format!(
+            "https://github.com/grafana/loki/releases/download/v{}/{}.zip",
+            LOKI_VERSION, release
+        ),
+        &release,
+        "loki",
+    )?;
+    create_system_user("loki")?;
+
+    let loki_config = r#"auth_enabled: false
+
+server:
+  http_listen_port: 3100
+  grpc_listen_port: 9096
+
+common:
+  instance_addr: 127.0.0.1
+  path_prefix: /var/lib/loki
+  storage:
+    filesystem:
+      chunks_directory: /var/lib/loki/chunks
+      rules_directory: /var/lib/loki/rules
+  replication_factor: 1
+  ring:
+    kvstore:
+      store: inmemory
+
+schema_config:
+  configs:
+    - from: 2020-10-24
+      store: tsdb
+      object_store: filesystem
+      schema: v12
+      index:
+        prefix: index_
+        period: 24h
+"#;
+    create_dir_all("/etc/loki")?;
+    write_file("/etc/loki/loki.yaml", loki_config)?;
+    run_command("mkdir", &["-p", "/var/lib/loki"])?;
+    run_command("chown", &["-R", "loki:loki", "/var/lib/loki"])?;
+
+    let service_file = ServiceUnit::new(
+        "Loki",
+        "/usr/local/bin/loki -config.file=/etc/loki/loki.yaml",
+    )
+    .user("loki")
+    .render();
+    write_file("/etc/systemd/system/loki.service", service_file)?;
+
+    run_command("systemctl", &["daemon-reload"])?;
+    enable_and_start_service("loki")?;
+
+    // Provision Loki as a Grafana datasource
+    let datasource = r#"apiVersion: 1
+
+datasources:
+  - name: Loki
+    type: loki
+    access: proxy
+    url: http://localhost:3100
+"#;
+    create_dir_all("/etc/grafana/provisioning/datasources")?;
+    write_file(
+        "/etc/grafana/provisioning/datasources/loki.yaml",
+        datasource,
+    )?;
+    restart_service("grafana-server")?;
+
+    Ok(())
+}
+
+/// Sets up Promtail to ship logs to the local Loki instance.
+///
+/// This function installs the Promtail binary from its GitHub release, creates a dedicated
+/// `promtail` user with read access to the system logs and the journal, and configures it
+/// to tail `/var/log/*.log` and the systemd journal.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing user-defined configuration options
+///
+/// # Errors
+///
+/// Returns an error if any step of the installation or configuration fails.
+pub fn setup_promtail(config: &Config) -> Result<(), Box<dyn Error>> {
+    let release = format!("promtail-linux-{}", loki_arch_suffix()?);
+    install_release_binary(
+        config,
+        &format!(
+            "https://github.com/grafana/loki/releases/download/v{}/{}.zip",
+            LOKI_VERSION, release
+        ),
+        &release,
+        "promtail",
+    )?;
+    create_system_user("promtail")?;
+
+    // Allow Promtail to read the journal and, on Debian-based systems, /var/log
+    run_command("usermod", &["-aG", "systemd-journal", "promtail"])?;
+    if get_package_manager()? == PackageManager::Apt {
+        run_command("usermod", &["-aG", "adm", "promtail"])?;
+    }
+
+    let promtail_config = r#"server:
+  http_listen_port: 9080
+  grpc_listen_port: 0
+
+positions:
+  filename: /var/lib/promtail/positions.yaml
+
+clients:
+  - url: http://localhost:3100/loki/api/v1/push
+
+scrape_configs:
+  - job_name: varlogs
+    static_configs:
+      - targets: [localhost]
+        labels:
+          job: varlogs
+          __path__: /var/log/*.log
+  - job_name: journal
+    journal:
+      max_age: 12h
+      labels:
+        job: systemd-journal
+    relabel_configs:
+      - source_labels: ['__journal__systemd_unit']
+        target_label: unit
 "#;
-    std::fs::write("/etc/systemd/system/node_exporter.service", service_file)?;
+    create_dir_all("/etc/promtail")?;
+    write_file("/etc/promtail/promtail.yaml", promtail_config)?;
+    run_command("mkdir", &["-p", "/var/lib/promtail"])?;
+    run_command("chown", &["-R", "promtail:promtail", "/var/lib/promtail"])?;
+
+    let service_file = ServiceUnit::new(
+        "Promtail",
+        "/usr/local/bin/promtail -config.file=/etc/promtail/promtail.yaml",
+    )
+    .user("promtail")
+    .render();
+    write_file("/etc/systemd/system/promtail.service", service_file)?;
 
     run_command("systemctl", &["daemon-reload"])?;
+    enable_and_start_service("promtail")?;
+
+    Ok(())
+}
+
+/// Returns the architecture suffix of Loki and Promtail releases.
+///
+/// Grafana names its 32-bit ARM builds `arm` rather than `armv7`.
+fn loki_arch_suffix() -> Result<&'static str, Box<dyn Error>> {
+    Ok(match target_arch_suffix()? {
+        "armv7" => "arm",
+        arch => arch,
+    })
+}
+
+/// Downloads a release archive and installs a binary from it into `/usr/local/bin`.
+///
+/// Both `.zip` and `.tar.gz` archives are supported; the archive is downloaded (see
+/// `utils::download`) and extracted under `/tmp`.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the download settings
+/// * `url` - The download URL of the release archive
+/// * `path_in_archive` - The path of the binary inside the extracted archive
+/// * `binary_name` - The name to install the binary as
+///
+/// # Errors
+///
+/// Returns an error if downloading, extracting, or installing the binary fails.
+fn install_release_binary(
+    config: &Config,
+    url: &str,
+    path_in_archive: &str,
+    binary_name: &str,
+) -> Result<(), Box<dyn Error>> {
+    let archive_name = url.rsplit('/').next().unwrap_or(binary_name);
+    let archive = format!("/tmp/{}", archive_name);
 
+    download(config, url, &archive)?;
+    if archive.ends_with(".zip") {
+        match get_package_manager()? {
+            PackageManager::Apt => run_command("apt", &["install", "-y", "unzip"])?,
+            PackageManager::Yum => run_command("yum", &["install", "-y", "unzip"])?,
+            PackageManager::Dnf => run_command("dnf", &["install", "-y", "unzip"])?,
+        }
+        run_command("unzip", &["-o", &archive, "-d", "/tmp"])?;
+    } else {
+        run_command("tar", &["xzf", &archive, "-C", "/tmp"])?;
+    }
+
+    run_command(
+        "install",
+        &[
+            "-m",
+            "0755",
+            &format!("/tmp/{}", path_in_archive),
+            &format!("/usr/local/bin/{}", binary_name),
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// Creates a system user without a home directory or login shell, if it doesn't already exist.
+///
+/// # Arguments
+///
+/// * `name` - The name of the user (a group of the same name is created too)
+///
+/// # Errors
+///
+/// Returns an error if the user cannot be created.
+fn create_system_user(name: &str) -> Result<(), Box<dyn Error>> {
+    if run_command("id", &[name]).is_err() {
+        run_command(
+            "useradd",
+            &[
+                "--system",
+                "--user-group",
+                "--no-create-home",
+                "--shell",
+                "/bin/false",
+                name,
+            ],
+        )?;
+    }
     Ok(())
 }
@@ -0,0 +1,129 @@
+//! # Notify Module
+//!
+//! This module tells an operations channel how a setup run ended. When a
+//! `notification_webhook` is configured, a JSON payload describing the outcome of the run is
+//! posted to it at the end of every run, successful or not. The payload has a `text` field,
+//! so Slack (and compatible chat services) display it as a message, and structured fields
+//! for other consumers.
+//!
+//! Notifications are best effort: a webhook that cannot be reached is logged as a warning
+//! and never fails the run.
+
+use crate::config::Config;
+use crate::runner::current_runner;
+use crate::utils::{format_duration, proxy_env};
+use log::{info, warn};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// How a setup run ended.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunOutcome {
+    /// The phases that failed, in order; empty if every phase succeeded
+    pub failed_phases: Vec<String>,
+    /// The error the run failed with, or `None` if it succeeded
+    pub error: Option<String>,
+    /// How long the run took
+    pub duration: Duration,
+}
+
+impl RunOutcome {
+    /// Returns whether the run succeeded.
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Posts the outcome of a run to the notification webhook, if one is configured.
+///
+/// Failing to reach the webhook is logged as a warning.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the webhook URL
+/// * `outcome` - How the run ended
+pub fn send_completion(config: &Config, outcome: &RunOutcome) {
+    let Some(webhook) = &config.secrets.notification_webhook else {
+        return;
+    };
+    let payload = completion_payload(&host_name(config), outcome).to_string();
+
+    // The runner is used directly, rather than through utils::run_command, which logs the
+    // arguments of commands: the webhook URL is a secret
+    let result = current_runner().run(
+        "curl",
+        &[
+            "-fsS",
+            "-m",
+            "30",
+            "-X",
+            "POST",
+            "-H",
+            "Content-Type: application/json",
+            "-d",
+            &payload,
+            webhook,
+        ],
+        &proxy_env(config),
+    );
+    match result {
+        Ok(output) if output.success => info!("Sent the completion notification"),
+        Ok(output) => warn!(
+            "Could not send the completion notification: {}",
+            output.stderr.trim()
+        ),
+        Err(e) => warn!("Could not send the completion notification: {}", e),
+    }
+}
+
+/// Returns the payload posted to the notification webhook at the end of a run.
+///
+/// # Arguments
+///
+/// * `host` - The name of the host the run configured
+/// * `outcome` - How the run ended
+///
+/// # Returns
+///
+/// Returns a JSON object with a Slack-compatible `text` summary, and the `hostname`, the
+/// `outcome` (`succeeded` or `failed`), the `failed_phases` and the `duration_secs` of the run.
+pub fn completion_payload(host: &str, outcome: &RunOutcome) -> Value {
+    let duration = format_duration(outcome.duration);
+    let text = match &outcome.error {
+        None => format!(
+            ":white_check_mark: ServerForge setup of {} succeeded in {}",
+            host, duration
+        ),
+        Some(error) => format!(
+            ":x: ServerForge setup of {} failed after {}: {}",
+            host, duration, error
+        ),
+    };
+    json!({
+        "text": text,
+        "hostname": host,
+        "outcome": if outcome.succeeded() { "succeeded" } else { "failed" },
+        "failed_phases": outcome.failed_phases,
+        "duration_secs": outcome.duration.as_secs_f64(),
+    })
+}
+
+/// Returns the name of the host of the current `CommandRunner`: the configured hostname, the
+/// SSH destination of a remote host, or the name the local machine reports.
+fn host_name(config: &Config) -> String {
+    if let Some(hostname) = &config.hostname {
+        return hostname.clone();
+    }
+    let runner = current_runner();
+    if runner.host() != "localhost" {
+        return runner.host().to_string();
+    }
+    runner
+        .run("hostname", &[], &[])
+        .ok()
+        .filter(|output| output.success && !output.stdout.trim().is_empty())
+        .map_or_else(
+            || String::from("localhost"),
+            |output| output.stdout.trim().to_string(),
+        )
+}
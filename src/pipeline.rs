@@ -0,0 +1,407 @@
+//! # Pipeline Module
+//!
+//! This module defines the phases of the server setup and runs them in order. Each phase
+//! records its changes in its own rollback snapshots and commits them when it completes.
+//!
+//! Every phase is classified by a `FailurePolicy`. The initial setup, security measures and
+//! container runtime phases are critical: if one of them fails, its changes are rolled back
+//! and the run stops. The other phases are optional, and with `--continue-on-error` a failed
+//! optional phase is rolled back and reported at the end while the run continues.
+//!
+//! Every phase also lists the `Config` options it depends on, so a re-run can skip the phases
+//! whose options did not change since the last run.
+
+use crate::config::{Config, FieldChange};
+use crate::console::{step, StepStatus};
+use crate::progress::StepProgress;
+use crate::rollback::RollbackManager;
+use crate::{backup, containerization, deployment, monitoring, security, setup, updates};
+use log::{info, warn};
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Options every phase depends on.
+pub const GLOBAL_INPUTS: &[&str] = &[
+    "linux_distro",
+    "scheduler",
+    "download_base_url",
+    "local_artifacts_dir",
+    "http_proxy",
+    "https_proxy",
+];
+
+/// Options tuning how a run executes rather than what it sets up, which no phase depends on.
+pub const RUN_OPTIONS: &[&str] = &["max_concurrent_installs", "max_concurrent_downloads"];
+
+const INITIAL_SETUP_INPUTS: &[&str] = &[
+    "hostname",
+    "admin_user",
+    "admin_ssh_key",
+    "ssh_port",
+    "swap_size_mb",
+    "timezone",
+    "essential_packages",
+    "custom_firewall_rules",
+    "deployed_apps",
+    "monitoring",
+    "expose_monitoring",
+    "grafana_port",
+    "prometheus_port",
+    "load_balancer_certificate",
+    "expose_rabbitmq_management",
+    "backup_frequency",
+    "security_scan_schedule",
+];
+const SECURITY_INPUTS: &[&str] = &[
+    "security_level",
+    "ssh_port",
+    "security_scan_schedule",
+    "maintenance_window",
+    "enable_clamav",
+    "admin_email",
+    "app_domain",
+    "deployed_apps",
+];
+const UPDATES_INPUTS: &[&str] = &[
+    "update_schedule",
+    "auto_reboot",
+    "auto_reboot_time",
+    "update_blacklist",
+    "maintenance_window",
+];
+const MONITORING_INPUTS: &[&str] = &[
+    "monitoring",
+    "use_containers",
+    "enable_logs",
+    "grafana_port",
+    "prometheus_port",
+    "prometheus_retention",
+    "prometheus_storage_path",
+    "monitoring_bind_address",
+    "expose_monitoring",
+    "grafana_admin_password",
+    "uptime_probe_targets",
+    "vault",
+];
+const BACKUP_INPUTS: &[&str] = &[
+    "monitoring",
+    "backup_frequency",
+    "maintenance_window",
+    "backup_excludes",
+    "backup_notify_on_success",
+    "admin_email",
+    "notification_webhook",
+    "deployed_apps",
+    "server_roles",
+];
+const DOCKER_INPUTS: &[&str] = &["use_containers"];
+const CONTAINER_METRICS_INPUTS: &[&str] =
+    &["use_containers", "monitoring", "monitoring_bind_address"];
+const KUBERNETES_INPUTS: &[&str] = &["use_containers", "use_kubernetes"];
+const CONTAINER_DEPLOYMENT_INPUTS: &[&str] = &["use_containers", "use_kubernetes", "deployed_apps"];
+const APP_INPUTS: &[&str] = &[
+    "use_containers",
+    "deployed_apps",
+    "deploy_hooks",
+    "server_roles",
+    "app_domain",
+    "web_server_config_mode",
+    "custom_firewall_rules",
+    "load_balancer_backends",
+    "memcached_memory_mb",
+    "memcached_bind_address",
+    "opensearch_bind_address",
+    "load_balancer_certificate",
+    "vault",
+];
+const GIT_APP_INPUTS: &[&str] = &["use_containers", "git_apps"];
+const REVERSE_PROXY_INPUTS: &[&str] = &["use_containers", "deployed_apps", "app_domain"];
+
+/// What happens to a run when a phase fails.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailurePolicy {
+    /// The phase is critical: its changes are rolled back and the run stops
+    Abort,
+    /// The phase is optional: with `--continue-on-error` its changes are rolled back and the
+    /// run continues; otherwise it is handled like `Abort`
+    Continue,
+}
+
+/// The function running a phase.
+type PhaseFn<'a> = dyn Fn(&RollbackManager) -> Result<(), Box<dyn Error>> + 'a;
+
+/// A step of the setup, run with the run's `RollbackManager`.
+pub struct Phase<'a> {
+    /// The name of the phase, used in logs and failure reports
+    pub name: String,
+    /// What happens to the run when the phase fails
+    pub policy: FailurePolicy,
+    /// The `Config` options the phase depends on, besides `GLOBAL_INPUTS`
+    pub inputs: &'static [&'static str],
+    run: Box<PhaseFn<'a>>,
+}
+
+impl<'a> Phase<'a> {
+    /// Creates a phase.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the phase
+    /// * `policy` - What happens to the run when the phase fails
+    /// * `inputs` - The `Config` options the phase depends on, besides `GLOBAL_INPUTS`
+    /// * `run` - Runs the phase, recording its changes in the given `RollbackManager`
+    pub fn new(
+        name: impl Into<String>,
+        policy: FailurePolicy,
+        inputs: &'static [&'static str],
+        run: impl Fn(&RollbackManager) -> Result<(), Box<dyn Error>> + 'a,
+    ) -> Self {
+        Phase {
+            name: name.into(),
+            policy,
+            inputs,
+            run: Box::new(run),
+        }
+    }
+
+    /// Returns whether any of the changed options is an input of the phase.
+    ///
+    /// # Arguments
+    ///
+    /// * `changes` - The options changed since the last run
+    pub fn is_affected_by(&self, changes: &[FieldChange]) -> bool {
+        changes.iter().any(|change| {
+            let field = change.field.as_str();
+            GLOBAL_INPUTS.contains(&field) || self.inputs.contains(&field)
+        })
+    }
+}
+
+/// A phase that failed without stopping the run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhaseFailure {
+    /// The name of the phase
+    pub phase: String,
+    /// The error the phase failed with
+    pub error: String,
+}
+
+/// The failure of a phase that stopped a run, as returned by `run_phases`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhaseError {
+    /// The name of the phase
+    pub phase: String,
+    /// The error the phase failed with
+    pub error: String,
+}
+
+impl fmt::Display for PhaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed: {}", self.phase, self.error)
+    }
+}
+
+impl Error for PhaseError {}
+
+/// How long a phase ran.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhaseTiming {
+    /// The name of the phase
+    pub phase: String,
+    /// How long the phase ran, until it completed or failed
+    pub duration: Duration,
+}
+
+/// The outcome of a run whose phases did not stop it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunSummary {
+    /// How long each phase ran, in order
+    pub timings: Vec<PhaseTiming>,
+    /// The failures of the optional phases that did not stop the run
+    pub failures: Vec<PhaseFailure>,
+}
+
+/// Returns the phases of the server setup for a configuration, in order.
+///
+/// Without containers, every application is deployed by its own phase, followed by the
+/// reverse proxy in front of them, so a single failing application does not undo the others.
+/// With containers and monitoring, cAdvisor is started once Docker is installed.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing user-defined configuration options
+pub fn phases(config: &Config) -> Vec<Phase<'_>> {
+    use FailurePolicy::{Abort, Continue};
+
+    let mut phases = vec![
+        Phase::new(
+            "Initial setup",
+            Abort,
+            INITIAL_SETUP_INPUTS,
+            move |rollback| setup::initial_setup(config, rollback),
+        ),
+        Phase::new(
+            "Security measures",
+            Abort,
+            SECURITY_INPUTS,
+            move |rollback| security::implement_security_measures(config, rollback),
+        ),
+        Phase::new(
+            "Automatic updates",
+            Continue,
+            UPDATES_INPUTS,
+            move |rollback| updates::setup_automatic_updates(config, rollback),
+        ),
+        Phase::new("Monitoring", Continue, MONITORING_INPUTS, move |rollback| {
+            monitoring::setup_monitoring(config, rollback)
+        }),
+        Phase::new("Backup system", Continue, BACKUP_INPUTS, move |rollback| {
+            backup::setup_backup_system(config, rollback)
+        }),
+    ];
+
+    if config.use_containers {
+        phases.push(Phase::new(
+            "Docker",
+            Abort,
+            DOCKER_INPUTS,
+            move |rollback| containerization::setup_docker(config, rollback),
+        ));
+        if config.monitoring {
+            phases.push(Phase::new(
+                "Container metrics",
+                Continue,
+                CONTAINER_METRICS_INPUTS,
+                move |rollback| monitoring::setup_cadvisor(config, rollback),
+            ));
+        }
+        if config.use_kubernetes {
+            phases.push(Phase::new(
+                "Kubernetes",
+                Abort,
+                KUBERNETES_INPUTS,
+                move |rollback| containerization::setup_kubernetes(config, rollback),
+            ));
+        }
+        phases.push(Phase::new(
+            "Container deployment",
+            Continue,
+            CONTAINER_DEPLOYMENT_INPUTS,
+            move |rollback| containerization::deploy_containers(config, rollback),
+        ));
+    } else {
+        for app in &config.deployed_apps {
+            phases.push(Phase::new(
+                format!("Deploying {}", app),
+                Continue,
+                APP_INPUTS,
+                move |rollback| deployment::deploy_application(app, config, rollback),
+            ));
+        }
+        for app in &config.git_apps {
+            phases.push(Phase::new(
+                format!("Deploying {}", app.repo_url),
+                Continue,
+                GIT_APP_INPUTS,
+                move |rollback| deployment::deploy_git_application(app, rollback),
+            ));
+        }
+        if !config.deployed_apps.is_empty() {
+            phases.push(Phase::new(
+                "Reverse proxy",
+                Continue,
+                REVERSE_PROXY_INPUTS,
+                move |rollback| deployment::setup_app_reverse_proxy(config, rollback),
+            ));
+        }
+    }
+    phases
+}
+
+/// Keeps the phases affected by the options changed since the last run.
+///
+/// # Arguments
+///
+/// * `phases` - The phases of the run
+/// * `changes` - The options changed since the last run, as returned by `Config::diff`
+///
+/// # Returns
+///
+/// Returns the phases with a changed input, in order.
+pub fn changed_phases<'a>(phases: Vec<Phase<'a>>, changes: &[FieldChange]) -> Vec<Phase<'a>> {
+    phases
+        .into_iter()
+        .filter(|phase| {
+            let affected = phase.is_affected_by(changes);
+            if !affected {
+                step(
+                    StepStatus::Skipped,
+                    &format!("Skipping {}: its configuration is unchanged", phase.name),
+                );
+            }
+            affected
+        })
+        .collect()
+}
+
+/// Runs phases in order, showing the progress of each with a `progress::StepProgress`.
+///
+/// When a phase fails, the uncommitted changes (those of the failed phase) are rolled back.
+/// If the phase is optional and `continue_on_error` is set, the failure is recorded and the
+/// next phase runs; otherwise the run stops.
+///
+/// # Arguments
+///
+/// * `phases` - The phases to run
+/// * `rollback` - The `RollbackManager` the phases record their changes in
+/// * `continue_on_error` - Whether optional phases may fail without stopping the run
+///
+/// # Returns
+///
+/// Returns how long each phase ran and the failures of the optional phases that did not
+/// stop the run.
+///
+/// # Errors
+///
+/// Returns a `PhaseError` if a phase stops the run, or an error if rolling back fails.
+pub fn run_phases(
+    phases: &[Phase],
+    rollback: &RollbackManager,
+    continue_on_error: bool,
+) -> Result<RunSummary, Box<dyn Error>> {
+    let mut summary = RunSummary::default();
+    for (index, phase) in phases.iter().enumerate() {
+        info!("Running phase: {}", phase.name);
+        let progress = StepProgress::start(index + 1, phases.len(), &phase.name);
+        let started = Instant::now();
+        let result = (phase.run)(rollback);
+        summary.timings.push(PhaseTiming {
+            phase: phase.name.clone(),
+            duration: started.elapsed(),
+        });
+        drop(progress);
+        if let Err(e) = result {
+            step(
+                StepStatus::Failed,
+                &format!("Error during {}: {}", phase.name, e),
+            );
+            rollback.rollback_uncommitted()?;
+            if !continue_on_error || phase.policy == FailurePolicy::Abort {
+                return Err(PhaseError {
+                    phase: phase.name.clone(),
+                    error: e.to_string(),
+                }
+                .into());
+            }
+            warn!("Continuing after the failure of {}", phase.name);
+            summary.failures.push(PhaseFailure {
+                phase: phase.name.clone(),
+                error: e.to_string(),
+            });
+        } else {
+            step(StepStatus::Completed, &format!("{} completed", phase.name));
+        }
+    }
+    Ok(summary)
+}
@@ -0,0 +1,341 @@
+//! # Plan Module
+//!
+//! This module separates deciding what to do to a host from doing it. The setup modules
+//! build a `Plan`, a list of `Operation`s such as installing packages, writing files and
+//! enabling services, which can then be executed, or rendered for other tools by the
+//! `export` module without touching the host.
+//!
+//! Planning may read from the host (for example the current `sshd_config`, which is edited
+//! in place, or which package manager is available) through the current `CommandRunner`,
+//! but never changes it. Executing a plan applies its operations in order and, when given a
+//! `RollbackManager`, records the files it changes and the packages it installs.
+//!
+//! The initial setup, security and application deployment phases are planned.
+
+use crate::concurrency::{current_limits, run_concurrently};
+use crate::config::Config;
+use crate::distro::{
+    enable_service, get_installed_version, get_package_manager, install_package,
+    install_package_tracked, restart_service, start_service, update_system,
+};
+use crate::rollback::RollbackManager;
+use crate::runner::{with_runner, CommandRunner};
+use crate::utils::{create_dir_all, path_exists, run_command, run_command_with_input, write_file};
+use crate::validation::ServiceType;
+use crate::{deployment, security, setup};
+use log::info;
+use std::collections::HashSet;
+use std::error::Error;
+use std::sync::Arc;
+
+/// A single change to a host.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    /// Updates the package index and upgrades all installed packages
+    UpgradePackages,
+    /// Installs packages with the host's package manager, skipping installed ones
+    InstallPackages { packages: Vec<String> },
+    /// Writes a file, replacing any existing contents
+    WriteFile { path: String, contents: String },
+    /// Creates a directory and all of its missing parents
+    CreateDir { path: String },
+    /// Starts a systemd service
+    StartService { name: String },
+    /// Enables a systemd service at boot
+    EnableService { name: String },
+    /// Restarts a systemd service
+    RestartService { name: String },
+    /// Reloads the configuration of a running systemd service
+    ReloadService { name: String },
+    /// Runs a command with the given arguments
+    RunCommand { command: String, args: Vec<String> },
+    /// Runs a command with the given arguments, writing `input` to its standard input.
+    /// Used to pass secrets, which are kept out of the arguments and the logs
+    RunCommandWithInput {
+        command: String,
+        args: Vec<String>,
+        input: String,
+    },
+}
+
+/// An ordered list of operations to apply to a host.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Plan {
+    operations: Vec<Operation>,
+}
+
+impl Plan {
+    /// Creates an empty plan.
+    pub fn new() -> Self {
+        Plan::default()
+    }
+
+    /// Creates a plan filled in by `build`.
+    ///
+    /// # Arguments
+    ///
+    /// * `build` - A function adding operations to the plan
+    ///
+    /// # Returns
+    ///
+    /// Returns the plan, or the error returned by `build`.
+    pub fn build(
+        build: impl FnOnce(&mut Plan) -> Result<(), Box<dyn Error>>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut plan = Plan::new();
+        build(&mut plan)?;
+        Ok(plan)
+    }
+
+    /// Returns the planned operations, in order.
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    /// Adds an operation to the plan.
+    pub fn push(&mut self, operation: Operation) -> &mut Self {
+        self.operations.push(operation);
+        self
+    }
+
+    /// Plans updating the package index and upgrading all installed packages.
+    pub fn upgrade_packages(&mut self) -> &mut Self {
+        self.push(Operation::UpgradePackages)
+    }
+
+    /// Plans installing packages.
+    pub fn install(&mut self, packages: &[&str]) -> &mut Self {
+        self.push(Operation::InstallPackages {
+            packages: packages.iter().map(|package| package.to_string()).collect(),
+        })
+    }
+
+    /// Plans writing a file.
+    pub fn write_file(
+        &mut self,
+        path: impl Into<String>,
+        contents: impl Into<String>,
+    ) -> &mut Self {
+        self.push(Operation::WriteFile {
+            path: path.into(),
+            contents: contents.into(),
+        })
+    }
+
+    /// Plans creating a directory and its missing parents.
+    pub fn create_dir(&mut self, path: impl Into<String>) -> &mut Self {
+        self.push(Operation::CreateDir { path: path.into() })
+    }
+
+    /// Plans starting a systemd service.
+    pub fn start_service(&mut self, name: &str) -> &mut Self {
+        self.push(Operation::StartService {
+            name: name.to_string(),
+        })
+    }
+
+    /// Plans enabling a systemd service at boot.
+    pub fn enable_service(&mut self, name: &str) -> &mut Self {
+        self.push(Operation::EnableService {
+            name: name.to_string(),
+        })
+    }
+
+    /// Plans restarting a systemd service.
+    pub fn restart_service(&mut self, name: &str) -> &mut Self {
+        self.push(Operation::RestartService {
+            name: name.to_string(),
+        })
+    }
+
+    /// Plans reloading the configuration of a systemd service.
+    pub fn reload_service(&mut self, name: &str) -> &mut Self {
+        self.push(Operation::ReloadService {
+            name: name.to_string(),
+        })
+    }
+
+    /// Plans running a command.
+    pub fn run(&mut self, command: &str, args: &[&str]) -> &mut Self {
+        self.push(Operation::RunCommand {
+            command: command.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        })
+    }
+
+    /// Plans running a command with `input` on its standard input.
+    pub fn run_with_input(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        input: impl Into<String>,
+    ) -> &mut Self {
+        self.push(Operation::RunCommandWithInput {
+            command: command.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            input: input.into(),
+        })
+    }
+
+    /// Plans writing a file readable only by its owner, such as a secret.
+    ///
+    /// The contents are written through standard input to a temporary file created with
+    /// mode `0600`, which then replaces the file, so they are never readable by others.
+    pub fn write_private_file(&mut self, path: &str, contents: impl Into<String>) -> &mut Self {
+        self.run_with_input(
+            "sh",
+            &[
+                "-c",
+                "umask 077 && rm -f \"$1.tmp\" && cat > \"$1.tmp\" && mv -f \"$1.tmp\" \"$1\"",
+                "sh",
+                path,
+            ],
+            contents,
+        )
+    }
+
+    /// Plans checking a configuration file with the tool of its service, so that later
+    /// operations (such as reloading the service) only run if it is valid.
+    pub fn validate_config(&mut self, service_type: ServiceType, path: &str) -> &mut Self {
+        let (command, args) = service_type.check_command(path);
+        self.push(Operation::RunCommand {
+            command: command.to_string(),
+            args,
+        })
+    }
+
+    /// Applies the plan to the host of the current `CommandRunner`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error as soon as an operation fails; later operations are not applied.
+    pub fn execute(&self) -> Result<(), Box<dyn Error>> {
+        self.apply(None)
+    }
+
+    /// Applies the plan, recording the changes in a rollback snapshot.
+    ///
+    /// Before a file is first overwritten its original contents are added to the snapshot,
+    /// before a service is first started, restarted or enabled its state is added to the
+    /// snapshot, as is the firewall configuration before the first `ufw` or `firewall-cmd`
+    /// command, and every package that was not installed yet is added to the snapshot's
+    /// installed packages.
+    ///
+    /// # Arguments
+    ///
+    /// * `rollback` - The `RollbackManager` to record changes in
+    /// * `snapshot` - The ID of the snapshot to record changes in
+    ///
+    /// # Errors
+    ///
+    /// Returns an error as soon as an operation fails; later operations are not applied.
+    pub fn execute_with_rollback(
+        &self,
+        rollback: &RollbackManager,
+        snapshot: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        self.apply(Some((rollback, snapshot)))
+    }
+
+    fn apply(&self, rollback: Option<(&RollbackManager, usize)>) -> Result<(), Box<dyn Error>> {
+        let mut saved_files = HashSet::new();
+        let record_service = |name: &str| -> Result<(), Box<dyn Error>> {
+            if let Some((rollback, snapshot)) = rollback {
+                rollback.add_service_modified(snapshot, name)?;
+            }
+            Ok(())
+        };
+
+        for operation in &self.operations {
+            match operation {
+                Operation::UpgradePackages => update_system(&get_package_manager()?)?,
+                Operation::InstallPackages { packages } => {
+                    let package_manager = get_package_manager()?;
+                    run_concurrently(packages, current_limits().installs, |package| {
+                        // Installing an installed package would upgrade it
+                        if let Some(version) = get_installed_version(&package_manager, package)? {
+                            info!("{} {} is already installed", package, version);
+                            return Ok(());
+                        }
+                        match rollback {
+                            Some((rollback, snapshot)) => install_package_tracked(
+                                &package_manager,
+                                package,
+                                rollback,
+                                snapshot,
+                            )?,
+                            None => install_package(&package_manager, package)?,
+                        }
+                        if let Some(version) = get_installed_version(&package_manager, package)? {
+                            info!("Installed {} {}", package, version);
+                        }
+                        Ok(())
+                    })?;
+                }
+                Operation::WriteFile { path, contents } => {
+                    if let Some((rollback, snapshot)) = rollback {
+                        if path_exists(path) && saved_files.insert(path) {
+                            rollback.add_file_change(snapshot, path)?;
+                        }
+                    }
+                    write_file(path, contents)?;
+                }
+                Operation::CreateDir { path } => create_dir_all(path)?,
+                Operation::StartService { name } => {
+                    record_service(name)?;
+                    start_service(name)?;
+                }
+                Operation::EnableService { name } => {
+                    record_service(name)?;
+                    enable_service(name)?;
+                }
+                Operation::RestartService { name } => {
+                    record_service(name)?;
+                    restart_service(name)?;
+                }
+                Operation::ReloadService { name } => run_command("systemctl", &["reload", name])?,
+                Operation::RunCommand { command, args } => {
+                    if let Some((rollback, snapshot)) = rollback {
+                        if command == "ufw" || command == "firewall-cmd" {
+                            rollback.add_firewall_state(snapshot)?;
+                        }
+                    }
+                    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                    run_command(command, &args)?;
+                }
+                Operation::RunCommandWithInput {
+                    command,
+                    args,
+                    input,
+                } => {
+                    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                    run_command_with_input(command, &args, input)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Plans the exported setup phases for a host, without changing it.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct describing the host's setup
+/// * `source` - The runner used to read files and check paths on the host while planning
+///
+/// # Returns
+///
+/// Returns the plan, or an error if planning a setup phase fails.
+pub fn plan_host(config: &Config, source: Arc<dyn CommandRunner>) -> Result<Plan, Box<dyn Error>> {
+    with_runner(source, || {
+        Plan::build(|plan| {
+            setup::plan_initial_setup(plan, config)?;
+            security::plan_security_measures(plan, config)?;
+            if !config.use_containers {
+                deployment::plan_applications(plan, config)?;
+            }
+            Ok(())
+        })
+    })
+}
@@ -0,0 +1,149 @@
+//! # Progress Module
+//!
+//! This module shows which phase a run is in and what it is doing, so that long phases
+//! (upgrading packages, starting minikube, building from source) don't look hung. While a
+//! phase runs, a spinner shows its position in the run (e.g. `[3/6] Monitoring...`) and the
+//! command it is currently running, or the last line printed by a streamed command. The
+//! spinner is cleared when the phase finishes or fails, and its outcome is then logged by the
+//! `pipeline` module.
+//!
+//! The display is off unless enabled with `set_enabled`, which the binary does when standard
+//! error is a terminal and `--quiet` is not given. Every host configured concurrently gets a
+//! spinner of its own, and console log lines are printed above the spinners through `suspend`.
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+/// How often the spinners are redrawn.
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Whether the progress display is shown.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The spinners of the phases running on every thread.
+static BARS: LazyLock<MultiProgress> = LazyLock::new(MultiProgress::new);
+
+thread_local! {
+    /// The spinner of the phase running on the current thread, and its label.
+    static CURRENT: RefCell<Option<(ProgressBar, String)>> = const { RefCell::new(None) };
+}
+
+/// Shows or hides the progress display of later phases.
+///
+/// # Arguments
+///
+/// * `enabled` - Whether to show the progress display
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether the progress display is shown.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Returns the label of a phase, e.g. `[3/6] Monitoring...`.
+///
+/// # Arguments
+///
+/// * `index` - The position of the phase in the run, starting at 1
+/// * `total` - The number of phases in the run
+/// * `name` - The name of the phase
+pub fn step_label(index: usize, total: usize, name: &str) -> String {
+    format!("[{}/{}] {}...", index, total, name)
+}
+
+/// The spinner of a running phase, cleared when dropped.
+pub struct StepProgress {
+    bar: Option<ProgressBar>,
+}
+
+impl StepProgress {
+    /// Starts the spinner of a phase on the current thread, if the progress display is shown.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The position of the phase in the run, starting at 1
+    /// * `total` - The number of phases in the run
+    /// * `name` - The name of the phase
+    pub fn start(index: usize, total: usize, name: &str) -> Self {
+        if !is_enabled() {
+            return StepProgress { bar: None };
+        }
+
+        let label = match log_mdc::get("host", |host| host.map(str::to_string)) {
+            Some(host) => format!("[{}] {}", host, step_label(index, total, name)),
+            None => step_label(index, total, name),
+        };
+        let bar = BARS.add(ProgressBar::new_spinner());
+        if let Ok(style) = ProgressStyle::with_template("{spinner} {msg} ({elapsed})") {
+            bar.set_style(style);
+        }
+        bar.set_message(label.clone());
+        bar.enable_steady_tick(TICK_INTERVAL);
+        CURRENT.with(|current| *current.borrow_mut() = Some((bar.clone(), label)));
+        StepProgress { bar: Some(bar) }
+    }
+}
+
+impl Drop for StepProgress {
+    fn drop(&mut self) {
+        if let Some(bar) = self.bar.take() {
+            CURRENT.with(|current| *current.borrow_mut() = None);
+            bar.finish_and_clear();
+            BARS.remove(&bar);
+        }
+    }
+}
+
+/// Shows a command as the current activity of the phase running on the current thread.
+///
+/// # Arguments
+///
+/// * `command` - The command
+/// * `args` - The arguments of the command
+pub fn command_started(command: &str, args: &[&str]) {
+    CURRENT.with(|current| {
+        if let Some((bar, label)) = current.borrow().as_ref() {
+            bar.set_message(format!("{} {} {}", label, command, args.join(" ")));
+        }
+    });
+}
+
+/// Shows a line printed by a streamed command as the current activity of the phase running
+/// on the current thread.
+///
+/// # Arguments
+///
+/// * `line` - The line printed by the command
+pub fn command_output_line(line: &str) {
+    let line = line.trim();
+    if line.is_empty() {
+        return;
+    }
+    CURRENT.with(|current| {
+        if let Some((bar, label)) = current.borrow().as_ref() {
+            bar.set_message(format!("{} {}", label, line));
+        }
+    });
+}
+
+/// Runs `f` with the spinners hidden, so that it can print to the terminal.
+///
+/// # Arguments
+///
+/// * `f` - The function to run
+///
+/// # Returns
+///
+/// Returns the result of `f`.
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    if is_enabled() {
+        BARS.suspend(f)
+    } else {
+        f()
+    }
+}
@@ -0,0 +1,103 @@
+//! # Registry Module
+//!
+//! This module lets library consumers deploy applications the crate does not know about.
+//! A custom application is deployed by an `AppDeployer` registered under its name with
+//! `register_deployer`; applications listed in `deployed_apps` are looked up in the registry
+//! first, and the built-in applications of the `deployment` module are used otherwise.
+//!
+//! Custom deployers change the host directly (typically through the helpers of the `utils`
+//! module, which use the current `CommandRunner`) rather than through a `Plan`, so they are
+//! left out of exported plans.
+
+use crate::config::Config;
+use crate::rollback::RollbackManager;
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+
+/// What a deployer needs to deploy an application on the host of the current `CommandRunner`.
+pub struct DeployContext<'a> {
+    /// The configuration of the host
+    pub config: &'a Config,
+    /// The `RollbackManager` to record changes in
+    pub rollback: &'a RollbackManager,
+    /// The ID of the snapshot to record changes in
+    pub snapshot: usize,
+}
+
+/// Deploys an application that is not built into the crate.
+pub trait AppDeployer: Send + Sync {
+    /// Returns the application name, as listed in `deployed_apps`.
+    fn name(&self) -> &str;
+
+    /// Deploys the application.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The configuration and the rollback snapshot of the deployment
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the deployment fails; the changes recorded in the snapshot are
+    /// then rolled back.
+    fn deploy(&self, ctx: &DeployContext) -> Result<(), Box<dyn Error>>;
+}
+
+/// A set of deployers, looked up by application name.
+#[derive(Default)]
+pub struct DeployerRegistry {
+    deployers: Vec<Arc<dyn AppDeployer>>,
+}
+
+impl DeployerRegistry {
+    /// Creates an empty registry.
+    pub const fn new() -> Self {
+        DeployerRegistry {
+            deployers: Vec::new(),
+        }
+    }
+
+    /// Adds a deployer, replacing any deployer registered under the same name.
+    pub fn register(&mut self, deployer: Arc<dyn AppDeployer>) {
+        self.deployers
+            .retain(|registered| registered.name() != deployer.name());
+        self.deployers.push(deployer);
+    }
+
+    /// Returns the deployer of an application, if one is registered.
+    pub fn get(&self, app: &str) -> Option<Arc<dyn AppDeployer>> {
+        self.deployers
+            .iter()
+            .find(|deployer| deployer.name() == app)
+            .cloned()
+    }
+}
+
+/// The deployers consulted by the `deployment` module.
+static DEPLOYERS: RwLock<DeployerRegistry> = RwLock::new(DeployerRegistry::new());
+
+/// Registers a deployer for every later deployment, replacing any deployer registered
+/// under the same name. A deployer named after a built-in application takes its place.
+///
+/// # Arguments
+///
+/// * `deployer` - The deployer to register
+///
+/// # Errors
+///
+/// Returns an error if the registry lock is poisoned.
+pub fn register_deployer(deployer: impl AppDeployer + 'static) -> Result<(), Box<dyn Error>> {
+    DEPLOYERS
+        .write()
+        .map_err(|e| e.to_string())?
+        .register(Arc::new(deployer));
+    Ok(())
+}
+
+/// Returns the registered deployer of an application, if there is one.
+///
+/// # Arguments
+///
+/// * `app` - The application name
+pub fn deployer(app: &str) -> Option<Arc<dyn AppDeployer>> {
+    DEPLOYERS.read().ok()?.get(app)
+}
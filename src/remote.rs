@@ -0,0 +1,190 @@
+//! # Remote Module
+//!
+//! This module provides `RemoteCommandRunner`, a `CommandRunner` that configures another
+//! host over SSH. Commands are executed with the system `ssh` client and files are
+//! transferred with `sftp`, so host keys, users, ports and identities are taken from the
+//! usual OpenSSH configuration (`~/.ssh/config`).
+//!
+//! Connections run in batch mode: the remote user must be able to log in without a
+//! password prompt and should be `root`, since the setup modules expect root privileges.
+
+use crate::runner::{output_with_input, spawn_streaming, CommandOutput, CommandRunner};
+use crate::utils::shell_quote;
+use std::error::Error;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Runs commands and file operations on a remote host over SSH.
+#[derive(Debug, Clone)]
+pub struct RemoteCommandRunner {
+    destination: String,
+}
+
+impl RemoteCommandRunner {
+    /// Creates a runner for the given SSH destination.
+    ///
+    /// # Arguments
+    ///
+    /// * `destination` - The host to connect to, as accepted by `ssh` (e.g. `web1` or `root@10.0.0.5`)
+    pub fn new(destination: &str) -> Self {
+        RemoteCommandRunner {
+            destination: destination.to_string(),
+        }
+    }
+
+    /// Returns the `ssh` process running a shell command line on the remote host.
+    fn ssh_command(&self, command_line: &str) -> Command {
+        let mut command = Command::new("ssh");
+        command.args(["-o", "BatchMode=yes", &self.destination, "--", command_line]);
+        command
+    }
+
+    /// Runs a shell command line on the remote host.
+    fn ssh(&self, command_line: &str) -> Result<CommandOutput, Box<dyn Error>> {
+        let output = self.ssh_command(command_line).output()?;
+        Ok(CommandOutput {
+            success: output.status.success(),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+
+    /// Runs a batch of `sftp` commands against the remote host.
+    fn sftp(&self, batch: &str) -> Result<(), Box<dyn Error>> {
+        let mut child = Command::new("sftp")
+            .args(["-q", "-o", "BatchMode=yes", "-b", "-", &self.destination])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        child
+            .stdin
+            .take()
+            .ok_or("Failed to open sftp stdin")?
+            .write_all(batch.as_bytes())?;
+
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(format!(
+                "sftp to {} failed: {}",
+                self.destination,
+                String::from_utf8_lossy(&output.stderr)
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+impl CommandRunner for RemoteCommandRunner {
+    fn host(&self) -> &str {
+        &self.destination
+    }
+
+    fn run(
+        &self,
+        command: &str,
+        args: &[&str],
+        env: &[(String, String)],
+    ) -> Result<CommandOutput, Box<dyn Error>> {
+        self.ssh(&remote_command_line(command, args, env))
+    }
+
+    fn run_streaming(
+        &self,
+        command: &str,
+        args: &[&str],
+        env: &[(String, String)],
+        on_line: &mut dyn FnMut(&str),
+    ) -> Result<CommandOutput, Box<dyn Error>> {
+        spawn_streaming(
+            &mut self.ssh_command(&remote_command_line(command, args, env)),
+            on_line,
+        )
+    }
+
+    fn run_with_input(
+        &self,
+        command: &str,
+        args: &[&str],
+        env: &[(String, String)],
+        input: &[u8],
+    ) -> Result<CommandOutput, Box<dyn Error>> {
+        // ssh forwards its standard input to the remote command
+        output_with_input(
+            &mut self.ssh_command(&remote_command_line(command, args, env)),
+            input,
+        )
+    }
+
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        let local = tempfile::NamedTempFile::new()?;
+        self.sftp(&format!(
+            "get {} {}\n",
+            sftp_quote(path),
+            sftp_quote(&local.path().to_string_lossy())
+        ))?;
+        Ok(std::fs::read(local.path())?)
+    }
+
+    fn write_file(&self, path: &str, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut local = tempfile::NamedTempFile::new()?;
+        local.write_all(contents)?;
+        local.flush()?;
+        self.sftp(&format!(
+            "put {} {}\n",
+            sftp_quote(&local.path().to_string_lossy()),
+            sftp_quote(path)
+        ))
+    }
+
+    fn create_dir_all(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let output = self.ssh(&format!("mkdir -p {}", shell_quote(path)))?;
+        if !output.success {
+            return Err(format!(
+                "Failed to create {} on {}: {}",
+                path, self.destination, output.stderr
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    fn path_exists(&self, path: &str) -> bool {
+        self.ssh(&format!("test -e {}", shell_quote(path)))
+            .map(|output| output.success)
+            .unwrap_or(false)
+    }
+}
+
+/// Builds the shell command line that runs a command on the remote host.
+///
+/// Every argument is quoted so it reaches the command unchanged, exactly as it would
+/// locally, and environment variables are passed through `env`.
+///
+/// # Arguments
+///
+/// * `command` - The command to run
+/// * `args` - The command's arguments
+/// * `env` - Extra environment variables to set
+///
+/// # Returns
+///
+/// Returns the command line to pass to `ssh`.
+pub fn remote_command_line(command: &str, args: &[&str], env: &[(String, String)]) -> String {
+    let mut parts = Vec::new();
+    if !env.is_empty() {
+        parts.push(String::from("env"));
+        for (name, value) in env {
+            parts.push(shell_quote(&format!("{}={}", name, value)));
+        }
+    }
+    parts.push(shell_quote(command));
+    parts.extend(args.iter().map(|arg| shell_quote(arg)));
+    parts.join(" ")
+}
+
+/// Quotes a path for an `sftp` batch file.
+fn sftp_quote(path: &str) -> String {
+    format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -2,22 +2,340 @@
 //!
 //! This module provides functionality for creating system snapshots and rolling back changes.
 //! It allows the application to revert the system state in case of failures during the setup process.
+//!
+//! Each setup phase records its changes in a snapshot and commits it once the phase has
+//! completed. A manager created with `RollbackManager::with_store` also writes its snapshots
+//! to disk on the host as they are recorded, so the changes of a run that crashed or was
+//! killed can still be found: `interrupted_runs` lists the runs that left pending snapshots
+//! behind, and `recover` rolls back or keeps their changes.
 
 use crate::distro::{get_package_manager, uninstall_package};
+use crate::filesystem::current_filesystem;
+use crate::runner::current_runner;
+use crate::utils::{command_output, run_command};
+use chrono::{DateTime, Local};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use log::info;
-use std::cell::RefCell;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
 use std::error::Error;
-use std::fs;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Write};
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard};
+
+/// The directory under which runs store their snapshots, one directory per run.
+pub const STORE_ROOT: &str = "/var/lib/server_forge/rollback";
 
 /// Manages the creation of snapshots and rollback operations.
+///
+/// The manager is `Send + Sync`, so it can be shared with other threads.
 pub struct RollbackManager {
-    snapshots: RefCell<Vec<Snapshot>>,
+    snapshots: Mutex<Vec<Snapshot>>,
+    /// Gzip-compressed file contents by a hash of their path and contents, so a file
+    /// recorded unchanged in several snapshots is stored once
+    contents: Mutex<StoredContents>,
+    /// The directory snapshots are written to, if they are persisted
+    store: Option<String>,
 }
 
-/// Represents a system snapshot, containing information about changed files and installed packages.
+/// Gzip-compressed file contents, by key.
+type StoredContents = HashMap<u64, Vec<u8>>;
+
+/// Represents a system snapshot, containing information about changed files, installed packages,
+/// modified services and the original firewall configuration.
+#[derive(Serialize, Deserialize)]
 struct Snapshot {
-    files_changed: Vec<(String, Vec<u8>)>, // (file path, original content)
+    label: String,
+    created_at: DateTime<Local>,
+    status: SnapshotStatus,
+    files_changed: Vec<(String, u64)>, // (file path, key of the original content)
     packages_installed: Vec<String>,
+    services_modified: Vec<(String, ServiceState)>, // (service name, original state)
+    firewall: Option<FirewallState>,
+}
+
+/// The configuration files of ufw.
+const UFW_FILES: [&str; 4] = [
+    "/etc/default/ufw",
+    "/etc/ufw/ufw.conf",
+    "/etc/ufw/user.rules",
+    "/etc/ufw/user6.rules",
+];
+
+/// The permanent configuration of firewalld's public zone, when it has been customized.
+const FIREWALLD_PUBLIC_ZONE: &str = "/etc/firewalld/zones/public.xml";
+
+/// The configuration of a host's firewall before it was changed.
+#[derive(Serialize, Deserialize)]
+enum FirewallState {
+    /// Whether ufw was active, and the contents of its configuration files
+    Ufw {
+        active: bool,
+        files: Vec<(String, Vec<u8>)>,
+    },
+    /// The public zone file of firewalld, or `None` if the zone had its default settings
+    Firewalld { public_zone: Option<Vec<u8>> },
+}
+
+impl FirewallState {
+    /// Captures the configuration of the firewall on the host of the current
+    /// `CommandRunner`, if ufw or firewalld is installed.
+    fn capture() -> Result<Option<Self>, Box<dyn Error>> {
+        let runner = current_runner();
+        let filesystem = current_filesystem();
+        let read_if_exists = |path: &str| -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+            if filesystem.exists(path) {
+                Ok(Some(filesystem.read(path)?))
+            } else {
+                Ok(None)
+            }
+        };
+
+        if filesystem.exists("/usr/sbin/ufw") {
+            let status = runner.run("ufw", &["status"], &[])?.stdout;
+            let mut files = Vec::new();
+            for path in UFW_FILES {
+                if let Some(contents) = read_if_exists(path)? {
+                    files.push((path.to_string(), contents));
+                }
+            }
+            Ok(Some(FirewallState::Ufw {
+                active: status.contains("Status: active"),
+                files,
+            }))
+        } else if filesystem.exists("/usr/bin/firewall-cmd") {
+            Ok(Some(FirewallState::Firewalld {
+                public_zone: read_if_exists(FIREWALLD_PUBLIC_ZONE)?,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns the name of the firewall.
+    fn name(&self) -> &'static str {
+        match self {
+            FirewallState::Ufw { .. } => "ufw",
+            FirewallState::Firewalld { .. } => "firewalld",
+        }
+    }
+
+    /// Restores the captured configuration.
+    ///
+    /// ufw is enabled (which reloads its rules) or disabled as it was. firewalld's public
+    /// zone is restored, or reset to its defaults, and reloaded; whether firewalld runs at
+    /// all is restored with the other services.
+    fn restore(&self) -> Result<(), Box<dyn Error>> {
+        let filesystem = current_filesystem();
+        match self {
+            FirewallState::Ufw { active, files } => {
+                for (path, contents) in files {
+                    filesystem.write(path, contents)?;
+                }
+                if *active {
+                    run_command("ufw", &["--force", "enable"])?;
+                } else {
+                    run_command("ufw", &["disable"])?;
+                }
+            }
+            FirewallState::Firewalld { public_zone } => {
+                match public_zone {
+                    Some(contents) => filesystem.write(FIREWALLD_PUBLIC_ZONE, contents)?,
+                    None => run_command(
+                        "firewall-cmd",
+                        &["--permanent", "--load-zone-defaults=public"],
+                    )?,
+                }
+                run_command("firewall-cmd", &["--reload"])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether a systemd service is running and enabled at boot.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ServiceState {
+    /// Whether the service is running
+    pub active: bool,
+    /// Whether the service is enabled at boot
+    pub enabled: bool,
+}
+
+impl ServiceState {
+    /// Queries the state of a service on the host of the current `CommandRunner`, with
+    /// `systemctl is-active` and `systemctl is-enabled`.
+    ///
+    /// # Arguments
+    ///
+    /// * `service` - The name of the service
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `systemctl` cannot be run.
+    pub fn query(service: &str) -> Result<Self, Box<dyn Error>> {
+        let runner = current_runner();
+        // Both commands exit with a non-zero status for inactive or disabled services, so
+        // only their output is checked
+        let state = |verb: &str| -> Result<String, Box<dyn Error>> {
+            Ok(runner
+                .run("systemctl", &[verb, service], &[])?
+                .stdout
+                .trim()
+                .to_string())
+        };
+        Ok(ServiceState {
+            active: state("is-active")? == "active",
+            enabled: state("is-enabled")? == "enabled",
+        })
+    }
+}
+
+impl fmt::Display for ServiceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} and {}",
+            if self.active { "active" } else { "inactive" },
+            if self.enabled { "enabled" } else { "disabled" }
+        )
+    }
+}
+
+/// Where a snapshot is in its lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SnapshotStatus {
+    /// The phase recording the snapshot has not completed yet
+    Pending,
+    /// The phase completed and its changes are kept
+    Committed,
+    /// The changes were rolled back
+    RolledBack,
+}
+
+/// Describes a snapshot, as listed by `RollbackManager::list_snapshots`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotInfo {
+    /// The ID of the snapshot, as returned by `create_snapshot`
+    pub id: usize,
+    /// What the snapshot covers (e.g., "Initial setup")
+    pub label: String,
+    /// When the snapshot was created
+    pub created_at: DateTime<Local>,
+    /// Whether the snapshot is pending, committed or rolled back
+    pub status: SnapshotStatus,
+    /// The number of files whose original contents are recorded
+    pub files_changed: usize,
+    /// The number of packages recorded as installed
+    pub packages_installed: usize,
+    /// The number of services whose original state is recorded
+    pub services_modified: usize,
+}
+
+/// A change a rollback would make, as returned by `RollbackManager::preview_rollback`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RollbackAction {
+    /// Restores the original contents of a file
+    RestoreFile { snapshot: usize, path: String },
+    /// Uninstalls a package
+    UninstallPackage { snapshot: usize, package: String },
+    /// Restores the original firewall configuration
+    RestoreFirewall { snapshot: usize, firewall: String },
+    /// Returns a service to its original state
+    RestoreService {
+        snapshot: usize,
+        service: String,
+        state: ServiceState,
+    },
+}
+
+impl fmt::Display for RollbackAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RollbackAction::RestoreFile { snapshot, path } => {
+                write!(f, "restore {} (snapshot {})", path, snapshot)
+            }
+            RollbackAction::UninstallPackage { snapshot, package } => {
+                write!(f, "uninstall {} (snapshot {})", package, snapshot)
+            }
+            RollbackAction::RestoreFirewall { snapshot, firewall } => {
+                write!(
+                    f,
+                    "restore the {} configuration (snapshot {})",
+                    firewall, snapshot
+                )
+            }
+            RollbackAction::RestoreService {
+                snapshot,
+                service,
+                state,
+            } => write!(
+                f,
+                "make service {} {} (snapshot {})",
+                service, state, snapshot
+            ),
+        }
+    }
+}
+
+/// What rolling back a stored run would do, as listed by `preview_run`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RollbackPreview {
+    /// The directory the run stored its snapshots in
+    pub dir: String,
+    /// The changes the rollback would make, in order
+    pub actions: Vec<RollbackAction>,
+}
+
+impl fmt::Display for RollbackPreview {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.actions.is_empty() {
+            return writeln!(f, "Rolling back {} would change nothing", self.dir);
+        }
+        writeln!(f, "Rolling back {} would:", self.dir)?;
+        for action in &self.actions {
+            writeln!(f, "  {}", action)?;
+        }
+        Ok(())
+    }
+}
+
+/// A run that stopped before committing all of its snapshots, found by `interrupted_runs`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterruptedRun {
+    /// The directory the run stored its snapshots in
+    pub dir: String,
+    /// The snapshots the run did not commit, oldest first
+    pub pending: Vec<SnapshotInfo>,
+}
+
+/// What to do with the pending snapshots of an interrupted run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Recovery {
+    /// Roll back the changes of the pending snapshots
+    RollBack,
+    /// Keep the changes, committing the pending snapshots
+    Commit,
+    /// Leave the snapshots pending, to decide on the next run
+    Ignore,
+}
+
+impl FromStr for Recovery {
+    type Err = String;
+
+    fn from_str(answer: &str) -> Result<Self, Self::Err> {
+        match answer.trim().to_lowercase().as_str() {
+            "r" | "rollback" | "roll back" => Ok(Recovery::RollBack),
+            "c" | "commit" => Ok(Recovery::Commit),
+            "i" | "ignore" => Ok(Recovery::Ignore),
+            _ => Err(format!("Unknown recovery '{}'", answer.trim())),
+        }
+    }
 }
 
 impl Default for RollbackManager {
@@ -27,25 +345,129 @@ impl Default for RollbackManager {
 }
 
 impl RollbackManager {
-    /// Creates a new `RollbackManager` instance.
+    /// Creates a new `RollbackManager` instance that keeps its snapshots in memory.
     pub fn new() -> Self {
         RollbackManager {
-            snapshots: RefCell::new(Vec::new()),
+            snapshots: Mutex::new(Vec::new()),
+            contents: Mutex::new(HashMap::new()),
+            store: None,
+        }
+    }
+
+    /// Creates a new `RollbackManager` instance that also writes its snapshots to a
+    /// directory on the host of the current `CommandRunner`.
+    ///
+    /// Every snapshot is written to `snapshot-<id>.json` whenever it changes, and the
+    /// original contents of files to `contents/<key>.gz` before they are overwritten. The
+    /// directory is created, readable only by its owner, with the first snapshot.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory to store this run's snapshots in
+    pub fn with_store(dir: impl Into<String>) -> Self {
+        RollbackManager {
+            store: Some(dir.into()),
+            ..RollbackManager::new()
         }
     }
 
+    /// Loads the snapshots a manager created with `with_store` wrote to a directory on the
+    /// host of the current `CommandRunner`, with the original contents of the files they
+    /// recorded. Later changes are written back to the directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory the snapshots were stored in
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a snapshot or the contents of a file it recorded cannot be read.
+    pub fn load(dir: impl Into<String>) -> Result<Self, Box<dyn Error>> {
+        let dir = dir.into();
+        let filesystem = current_filesystem();
+        let mut snapshots = Vec::new();
+        let mut contents = HashMap::new();
+
+        loop {
+            let path = format!("{}/snapshot-{}.json", dir, snapshots.len());
+            if !filesystem.exists(&path) {
+                break;
+            }
+            let snapshot: Snapshot = serde_json::from_slice(&filesystem.read(&path)?)
+                .map_err(|e| format!("Invalid snapshot {}: {}", path, e))?;
+            for (_, key) in &snapshot.files_changed {
+                if !contents.contains_key(key) {
+                    let stored = filesystem.read(&format!("{}/contents/{:016x}.gz", dir, key))?;
+                    contents.insert(*key, stored);
+                }
+            }
+            snapshots.push(snapshot);
+        }
+
+        Ok(RollbackManager {
+            snapshots: Mutex::new(snapshots),
+            contents: Mutex::new(contents),
+            store: Some(dir),
+        })
+    }
+
     /// Creates a new snapshot and returns its ID.
     ///
+    /// IDs are indices into the manager's snapshots: the first snapshot has ID 0, and each
+    /// later snapshot the next ID. The snapshot is pending until it is committed.
+    ///
+    /// # Arguments
+    ///
+    /// * `label` - A description of the changes the snapshot records (e.g., "Initial setup")
+    ///
     /// # Errors
     ///
     /// Returns an error if the snapshot creation fails.
-    pub fn create_snapshot(&self) -> Result<usize, Box<dyn Error>> {
+    pub fn create_snapshot(&self, label: &str) -> Result<usize, Box<dyn Error>> {
+        if let Some(dir) = &self.store {
+            let filesystem = current_filesystem();
+            if !filesystem.exists(dir) {
+                filesystem.create_dir_all(&format!("{}/contents", dir))?;
+                filesystem.set_permissions(dir, 0o700)?;
+            }
+        }
+
         let snapshot = Snapshot {
+            label: label.to_string(),
+            created_at: Local::now(),
+            status: SnapshotStatus::Pending,
             files_changed: Vec::new(),
             packages_installed: Vec::new(),
+            services_modified: Vec::new(),
+            firewall: None,
         };
-        self.snapshots.borrow_mut().push(snapshot);
-        Ok(self.snapshots.borrow().len() - 1)
+        let mut snapshots = self.snapshots()?;
+        snapshots.push(snapshot);
+        let snapshot_id = snapshots.len() - 1;
+        self.persist(snapshot_id, &snapshots[snapshot_id])?;
+        Ok(snapshot_id)
+    }
+
+    /// Lists the snapshots, oldest first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the rollback state is poisoned.
+    pub fn list_snapshots(&self) -> Result<Vec<SnapshotInfo>, Box<dyn Error>> {
+        Ok(self
+            .snapshots()?
+            .iter()
+            .enumerate()
+            .map(|(id, snapshot)| SnapshotInfo {
+                id,
+                label: snapshot.label.clone(),
+                created_at: snapshot.created_at,
+                status: snapshot.status,
+                files_changed: snapshot.files_changed.len(),
+                packages_installed: snapshot.packages_installed.len(),
+                services_modified: snapshot.services_modified.len(),
+            })
+            .collect())
     }
 
     /// Adds a file change to a specific snapshot.
@@ -63,14 +485,43 @@ impl RollbackManager {
         snapshot_id: usize,
         file_path: &str,
     ) -> Result<(), Box<dyn Error>> {
-        let original_content = fs::read(file_path)?;
-        self.snapshots.borrow_mut()[snapshot_id]
-            .files_changed
-            .push((file_path.to_string(), original_content));
-        Ok(())
+        let original_content = current_filesystem().read(file_path)?;
+        let key = self.store_content(file_path, &original_content)?;
+        let mut snapshots = self.snapshots()?;
+        let snapshot = snapshots
+            .get_mut(snapshot_id)
+            .ok_or("Invalid snapshot ID")?;
+        snapshot.files_changed.push((file_path.to_string(), key));
+        self.persist(snapshot_id, snapshot)
+    }
+
+    /// Compresses the contents of a file and returns the key they are stored under,
+    /// reusing the stored copy if the same contents were already recorded for the same path.
+    fn store_content(&self, path: &str, contents: &[u8]) -> Result<u64, Box<dyn Error>> {
+        let mut hasher = DefaultHasher::new();
+        (path, contents).hash(&mut hasher);
+        let mut key = hasher.finish();
+
+        let mut stored_contents = self.contents()?;
+        while let Some(stored) = stored_contents.get(&key) {
+            if decompress(stored)? == contents {
+                return Ok(key);
+            }
+            // A hash collision with different contents
+            key = key.wrapping_add(1);
+        }
+
+        let stored = compress(contents)?;
+        if let Some(dir) = &self.store {
+            current_filesystem().write(&format!("{}/contents/{:016x}.gz", dir, key), &stored)?;
+        }
+        stored_contents.insert(key, stored);
+        Ok(key)
     }
 
-    /// Adds an installed package to a specific snapshot.
+    /// Adds an installed package to a specific snapshot. Rolling the snapshot back uninstalls
+    /// the package, so only packages that were not installed before should be added (see
+    /// `distro::install_package_tracked`).
     ///
     /// # Arguments
     ///
@@ -85,22 +536,126 @@ impl RollbackManager {
         snapshot_id: usize,
         package: &str,
     ) -> Result<(), Box<dyn Error>> {
-        self.snapshots.borrow_mut()[snapshot_id]
-            .packages_installed
-            .push(package.to_string());
+        let mut snapshots = self.snapshots()?;
+        let snapshot = snapshots
+            .get_mut(snapshot_id)
+            .ok_or("Invalid snapshot ID")?;
+        snapshot.packages_installed.push(package.to_string());
+        self.persist(snapshot_id, snapshot)
+    }
+
+    /// Records the current state of a service in a specific snapshot, before it is started,
+    /// restarted or enabled.
+    ///
+    /// Only the first state recorded for a service in a snapshot is kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot_id` - The ID of the snapshot to add the service to
+    /// * `service` - The name of the service
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the snapshot ID is invalid or the state cannot be queried.
+    pub fn add_service_modified(
+        &self,
+        snapshot_id: usize,
+        service: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let recorded = |snapshots: &[Snapshot]| -> Result<bool, Box<dyn Error>> {
+            let snapshot = snapshots.get(snapshot_id).ok_or("Invalid snapshot ID")?;
+            Ok(snapshot
+                .services_modified
+                .iter()
+                .any(|(name, _)| name == service))
+        };
+        if recorded(&self.snapshots()?)? {
+            return Ok(());
+        }
+
+        // Query the state without holding the lock, since it runs commands
+        let state = ServiceState::query(service)?;
+        let mut snapshots = self.snapshots()?;
+        if !recorded(&snapshots)? {
+            snapshots[snapshot_id]
+                .services_modified
+                .push((service.to_string(), state));
+            self.persist(snapshot_id, &snapshots[snapshot_id])?;
+        }
+        Ok(())
+    }
+
+    /// Records the current firewall configuration in a specific snapshot, before the
+    /// firewall is changed.
+    ///
+    /// For ufw its configuration files and whether it is active are recorded; for firewalld
+    /// the permanent configuration of the public zone. Only the first configuration recorded
+    /// in a snapshot is kept, and nothing is recorded if neither firewall is installed.
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot_id` - The ID of the snapshot to add the firewall configuration to
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the snapshot ID is invalid or the configuration cannot be read.
+    pub fn add_firewall_state(&self, snapshot_id: usize) -> Result<(), Box<dyn Error>> {
+        let recorded = |snapshots: &[Snapshot]| -> Result<bool, Box<dyn Error>> {
+            let snapshot = snapshots.get(snapshot_id).ok_or("Invalid snapshot ID")?;
+            Ok(snapshot.firewall.is_some())
+        };
+        if recorded(&self.snapshots()?)? {
+            return Ok(());
+        }
+
+        // Capture the configuration without holding the lock, since it runs commands
+        let firewall = FirewallState::capture()?;
+        let mut snapshots = self.snapshots()?;
+        if !recorded(&snapshots)? {
+            snapshots[snapshot_id].firewall = firewall;
+            self.persist(snapshot_id, &snapshots[snapshot_id])?;
+        }
         Ok(())
     }
 
-    /// Commits a snapshot, finalizing its state.
+    /// Commits a snapshot once the phase recording it has completed.
     ///
-    /// This method is a placeholder and currently does nothing.
-    /// It could be expanded to compress the snapshot or write it to disk.
+    /// A committed snapshot is kept by `rollback_uncommitted`, and is written to the store
+    /// as committed, if the manager has one.
     ///
     /// # Arguments
     ///
-    /// * `_snapshot_id` - The ID of the snapshot to commit
-    pub fn commit_snapshot(&self, _snapshot_id: usize) -> Result<(), Box<dyn Error>> {
-        // we could compress the snapshot or write it to disk here
+    /// * `snapshot_id` - The ID of the snapshot to commit
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the snapshot ID is invalid, the snapshot was rolled back, or it
+    /// cannot be written to the store.
+    pub fn commit_snapshot(&self, snapshot_id: usize) -> Result<(), Box<dyn Error>> {
+        let mut snapshots = self.snapshots()?;
+        let snapshot = snapshots
+            .get_mut(snapshot_id)
+            .ok_or("Invalid snapshot ID")?;
+        if snapshot.status == SnapshotStatus::RolledBack {
+            return Err("Cannot commit a rolled back snapshot".into());
+        }
+        snapshot.status = SnapshotStatus::Committed;
+        self.persist(snapshot_id, snapshot)
+    }
+
+    /// Commits every pending snapshot, keeping its changes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a snapshot cannot be written to the store.
+    pub fn commit_pending(&self) -> Result<(), Box<dyn Error>> {
+        let mut snapshots = self.snapshots()?;
+        for (id, snapshot) in snapshots.iter_mut().enumerate() {
+            if snapshot.status == SnapshotStatus::Pending {
+                snapshot.status = SnapshotStatus::Committed;
+                self.persist(id, snapshot)?;
+            }
+        }
         Ok(())
     }
 
@@ -111,12 +666,41 @@ impl RollbackManager {
     /// Returns an error if any part of the rollback process fails.
     pub fn rollback_all(&self) -> Result<(), Box<dyn Error>> {
         info!("Rolling back all changes...");
+        self.rollback_snapshots(0, |_| true)?;
+        info!("Rollback completed");
+        Ok(())
+    }
 
-        for snapshot in self.snapshots.borrow().iter().rev() {
+    /// Rolls back the changes of snapshots that have not been committed, leaving the
+    /// changes of completed phases in place.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any part of the rollback process fails.
+    pub fn rollback_uncommitted(&self) -> Result<(), Box<dyn Error>> {
+        info!("Rolling back uncommitted changes...");
+        self.rollback_snapshots(0, |snapshot| snapshot.status == SnapshotStatus::Pending)?;
+        info!("Rollback completed");
+        Ok(())
+    }
+
+    /// Rolls back the snapshots from `first` onwards selected by `include`, newest first,
+    /// skipping snapshots that were already rolled back.
+    fn rollback_snapshots(
+        &self,
+        first: usize,
+        include: impl Fn(&Snapshot) -> bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut snapshots = self.snapshots()?;
+        for id in (first..snapshots.len()).rev() {
+            let snapshot = &mut snapshots[id];
+            if snapshot.status == SnapshotStatus::RolledBack || !include(snapshot) {
+                continue;
+            }
             self.rollback_snapshot(snapshot)?;
+            snapshot.status = SnapshotStatus::RolledBack;
+            self.persist(id, snapshot)?;
         }
-
-        info!("Rollback completed");
         Ok(())
     }
 
@@ -130,10 +714,36 @@ impl RollbackManager {
     ///
     /// Returns an error if any part of the rollback process fails.
     fn rollback_snapshot(&self, snapshot: &Snapshot) -> Result<(), Box<dyn Error>> {
+        info!(
+            "Rolling back snapshot '{}' from {}",
+            snapshot.label,
+            snapshot.created_at.format("%Y-%m-%d %H:%M:%S")
+        );
+
         // Rollback file changes
-        for (file_path, original_content) in &snapshot.files_changed {
+        for (file_path, key) in &snapshot.files_changed {
             info!("Rolling back changes to file: {}", file_path);
-            fs::write(file_path, original_content)?;
+            let original_content = decompress(
+                self.contents()?
+                    .get(key)
+                    .ok_or("Recorded file contents are missing")?,
+            )?;
+            current_filesystem().write(file_path, &original_content)?;
+        }
+
+        // Restore the firewall before the services, since restoring firewalld needs it running
+        if let Some(firewall) = &snapshot.firewall {
+            info!("Restoring the {} configuration", firewall.name());
+            firewall.restore()?;
+        }
+
+        // Restore service states, restarting running services so restored files take effect
+        for (service, state) in snapshot.services_modified.iter().rev() {
+            info!("Restoring service {} to {}", service, state);
+            let enable = if state.enabled { "enable" } else { "disable" };
+            run_command("systemctl", &[enable, service])?;
+            let start = if state.active { "restart" } else { "stop" };
+            run_command("systemctl", &[start, service])?;
         }
 
         // Uninstall packages
@@ -158,16 +768,219 @@ impl RollbackManager {
     pub fn rollback_to(&self, snapshot_id: usize) -> Result<(), Box<dyn Error>> {
         info!("Rolling back to snapshot {}", snapshot_id);
 
-        let snapshots = self.snapshots.borrow();
-        if snapshot_id >= snapshots.len() {
+        if snapshot_id >= self.snapshots()?.len() {
             return Err("Invalid snapshot ID".into());
         }
+        self.rollback_snapshots(snapshot_id, |_| true)?;
 
-        for snapshot in snapshots.iter().skip(snapshot_id).rev() {
-            self.rollback_snapshot(snapshot)?;
+        info!("Rollback to snapshot {} completed", snapshot_id);
+        Ok(())
+    }
+
+    /// Lists what rolling back would do, in order, without changing anything.
+    ///
+    /// Snapshots that were already rolled back are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot_id` - The snapshot `rollback_to` would roll back to, or `None` for
+    ///   `rollback_all`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the snapshot ID is invalid.
+    pub fn preview_rollback(
+        &self,
+        snapshot_id: Option<usize>,
+    ) -> Result<Vec<RollbackAction>, Box<dyn Error>> {
+        let snapshots = self.snapshots()?;
+        let first = snapshot_id.unwrap_or(0);
+        if snapshot_id.is_some() && first >= snapshots.len() {
+            return Err("Invalid snapshot ID".into());
         }
 
-        info!("Rollback to snapshot {} completed", snapshot_id);
+        let mut actions = Vec::new();
+        for (id, snapshot) in snapshots.iter().enumerate().skip(first).rev() {
+            if snapshot.status == SnapshotStatus::RolledBack {
+                continue;
+            }
+            for (path, _) in &snapshot.files_changed {
+                actions.push(RollbackAction::RestoreFile {
+                    snapshot: id,
+                    path: path.clone(),
+                });
+            }
+            if let Some(firewall) = &snapshot.firewall {
+                actions.push(RollbackAction::RestoreFirewall {
+                    snapshot: id,
+                    firewall: firewall.name().to_string(),
+                });
+            }
+            for (service, state) in snapshot.services_modified.iter().rev() {
+                actions.push(RollbackAction::RestoreService {
+                    snapshot: id,
+                    service: service.clone(),
+                    state: *state,
+                });
+            }
+            for package in &snapshot.packages_installed {
+                actions.push(RollbackAction::UninstallPackage {
+                    snapshot: id,
+                    package: package.clone(),
+                });
+            }
+        }
+        Ok(actions)
+    }
+
+    /// Writes a snapshot to the store, if the manager has one.
+    fn persist(&self, snapshot_id: usize, snapshot: &Snapshot) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = &self.store {
+            current_filesystem().write(
+                &format!("{}/snapshot-{}.json", dir, snapshot_id),
+                &serde_json::to_vec_pretty(snapshot)?,
+            )?;
+        }
         Ok(())
     }
+
+    /// Locks the list of snapshots.
+    fn snapshots(&self) -> Result<MutexGuard<'_, Vec<Snapshot>>, Box<dyn Error>> {
+        self.snapshots
+            .lock()
+            .map_err(|_| "Rollback state is poisoned".into())
+    }
+
+    /// Locks the recorded file contents.
+    fn contents(&self) -> Result<MutexGuard<'_, StoredContents>, Box<dyn Error>> {
+        self.contents
+            .lock()
+            .map_err(|_| "Rollback state is poisoned".into())
+    }
+}
+
+/// Lists the runs stored under a directory that left pending snapshots behind, because they
+/// crashed or were killed before their phases completed, oldest first.
+///
+/// # Arguments
+///
+/// * `root` - The directory holding one directory per run, normally `STORE_ROOT`
+///
+/// # Errors
+///
+/// Returns an error if the directory cannot be listed or a run cannot be loaded.
+pub fn interrupted_runs(root: &str) -> Result<Vec<InterruptedRun>, Box<dyn Error>> {
+    if !current_filesystem().exists(root) {
+        return Ok(Vec::new());
+    }
+
+    let mut runs = Vec::new();
+    for name in command_output("ls", &["-1", root])?.lines() {
+        let dir = format!("{}/{}", root, name.trim());
+        let pending: Vec<SnapshotInfo> = RollbackManager::load(dir.as_str())?
+            .list_snapshots()?
+            .into_iter()
+            .filter(|snapshot| snapshot.status == SnapshotStatus::Pending)
+            .collect();
+        if !pending.is_empty() {
+            runs.push(InterruptedRun { dir, pending });
+        }
+    }
+    Ok(runs)
+}
+
+/// Returns the directory of the latest run stored under a directory, if there is one.
+///
+/// Runs are stored in directories named after the time they started, so the latest run is
+/// the last one in name order.
+///
+/// # Arguments
+///
+/// * `root` - The directory holding one directory per run, normally `STORE_ROOT`
+///
+/// # Errors
+///
+/// Returns an error if the directory cannot be listed.
+pub fn latest_run(root: &str) -> Result<Option<String>, Box<dyn Error>> {
+    if !current_filesystem().exists(root) {
+        return Ok(None);
+    }
+
+    Ok(command_output("ls", &["-1", root])?
+        .lines()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .max()
+        .map(|name| format!("{}/{}", root, name)))
+}
+
+/// Lists what rolling back a stored run would do, without changing anything.
+///
+/// # Arguments
+///
+/// * `dir` - The directory the run stored its snapshots in
+/// * `snapshot_id` - The snapshot to roll back to, or `None` to roll back the whole run
+///
+/// # Errors
+///
+/// Returns an error if the run cannot be loaded or the snapshot ID is invalid.
+pub fn preview_run(
+    dir: &str,
+    snapshot_id: Option<usize>,
+) -> Result<RollbackPreview, Box<dyn Error>> {
+    Ok(RollbackPreview {
+        dir: dir.to_string(),
+        actions: RollbackManager::load(dir)?.preview_rollback(snapshot_id)?,
+    })
+}
+
+/// Rolls back a stored run, to a snapshot or entirely.
+///
+/// # Arguments
+///
+/// * `dir` - The directory the run stored its snapshots in
+/// * `snapshot_id` - The snapshot to roll back to, or `None` to roll back the whole run
+///
+/// # Errors
+///
+/// Returns an error if the run cannot be loaded, the snapshot ID is invalid, or the
+/// rollback fails.
+pub fn rollback_run(dir: &str, snapshot_id: Option<usize>) -> Result<(), Box<dyn Error>> {
+    let manager = RollbackManager::load(dir)?;
+    match snapshot_id {
+        Some(snapshot_id) => manager.rollback_to(snapshot_id),
+        None => manager.rollback_all(),
+    }
+}
+
+/// Rolls back or keeps the changes of an interrupted run.
+///
+/// # Arguments
+///
+/// * `run` - The run, as returned by `interrupted_runs`
+/// * `recovery` - What to do with its pending snapshots
+///
+/// # Errors
+///
+/// Returns an error if the run cannot be loaded, or the rollback or the commit fails.
+pub fn recover(run: &InterruptedRun, recovery: Recovery) -> Result<(), Box<dyn Error>> {
+    match recovery {
+        Recovery::RollBack => RollbackManager::load(run.dir.as_str())?.rollback_uncommitted(),
+        Recovery::Commit => RollbackManager::load(run.dir.as_str())?.commit_pending(),
+        Recovery::Ignore => Ok(()),
+    }
+}
+
+/// Compresses file contents with gzip.
+fn compress(contents: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(contents)?;
+    Ok(encoder.finish()?)
+}
+
+/// Decompresses file contents compressed by `compress`.
+fn decompress(compressed: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut contents = Vec::new();
+    GzDecoder::new(compressed).read_to_end(&mut contents)?;
+    Ok(contents)
 }
@@ -0,0 +1,311 @@
+//! # Runner Module
+//!
+//! This module defines the `CommandRunner` trait, which abstracts over where commands are
+//! executed and files are read and written. `LocalCommandRunner` acts on the local machine;
+//! other implementations (such as `remote::RemoteCommandRunner`) act on another host.
+//!
+//! The runner in use is tracked per thread. `utils::run_command` always goes through
+//! `current_runner()`, as do the file helpers in `utils` unless another file system is set
+//! (see the `filesystem` module), so the setup modules don't need to know which host they
+//! are configuring; wrap a pipeline in `with_runner` to retarget it.
+//!
+//! Long-running commands can be run with `CommandRunner::run_streaming`, which reports
+//! their output line by line as it is printed instead of once they exit. Secrets are passed
+//! to commands with `CommandRunner::run_with_input`, on their standard input, so that they
+//! are neither logged nor visible in the process list like arguments.
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::{mpsc, Arc};
+
+/// The result of running a command through a `CommandRunner`.
+#[derive(Debug, Clone, Default)]
+pub struct CommandOutput {
+    /// Whether the command exited successfully
+    pub success: bool,
+    /// The captured standard output
+    pub stdout: String,
+    /// The captured standard error
+    pub stderr: String,
+}
+
+/// Executes commands and file operations on a host.
+pub trait CommandRunner: Send + Sync {
+    /// Returns the name of the host this runner acts on.
+    fn host(&self) -> &str;
+
+    /// Runs a command with extra environment variables and captures its output.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command could not be started. A command that runs but exits
+    /// unsuccessfully is reported through `CommandOutput::success` instead.
+    fn run(
+        &self,
+        command: &str,
+        args: &[&str],
+        env: &[(String, String)],
+    ) -> Result<CommandOutput, Box<dyn Error>>;
+
+    /// Runs a command like `run`, calling `on_line` with every line of its standard output
+    /// and standard error (without the line ending) as the command prints it.
+    ///
+    /// The default implementation runs the command with `run` and reports its output once it
+    /// exits, standard output first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command could not be started.
+    fn run_streaming(
+        &self,
+        command: &str,
+        args: &[&str],
+        env: &[(String, String)],
+        on_line: &mut dyn FnMut(&str),
+    ) -> Result<CommandOutput, Box<dyn Error>> {
+        let output = self.run(command, args, env)?;
+        for line in output.stdout.lines().chain(output.stderr.lines()) {
+            on_line(line);
+        }
+        Ok(output)
+    }
+
+    /// Runs a command like `run`, writing `input` to its standard input.
+    ///
+    /// The default implementation fails, for runners that cannot pass standard input.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command could not be started.
+    fn run_with_input(
+        &self,
+        command: &str,
+        _args: &[&str],
+        _env: &[(String, String)],
+        _input: &[u8],
+    ) -> Result<CommandOutput, Box<dyn Error>> {
+        Err(format!(
+            "Cannot pass standard input to {} on {}",
+            command,
+            self.host()
+        )
+        .into())
+    }
+
+    /// Reads the contents of a file.
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, Box<dyn Error>>;
+
+    /// Writes a file, replacing any existing contents.
+    fn write_file(&self, path: &str, contents: &[u8]) -> Result<(), Box<dyn Error>>;
+
+    /// Creates a directory and all of its missing parents.
+    fn create_dir_all(&self, path: &str) -> Result<(), Box<dyn Error>>;
+
+    /// Returns whether a file or directory exists.
+    fn path_exists(&self, path: &str) -> bool;
+}
+
+/// Runs commands and file operations on the local machine.
+#[derive(Debug, Clone, Default)]
+pub struct LocalCommandRunner;
+
+impl CommandRunner for LocalCommandRunner {
+    fn host(&self) -> &str {
+        "localhost"
+    }
+
+    fn run(
+        &self,
+        command: &str,
+        args: &[&str],
+        env: &[(String, String)],
+    ) -> Result<CommandOutput, Box<dyn Error>> {
+        let output = Command::new(command)
+            .args(args)
+            .envs(env.iter().map(|(name, value)| (name, value)))
+            .output()?;
+        Ok(CommandOutput {
+            success: output.status.success(),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+
+    fn run_streaming(
+        &self,
+        command: &str,
+        args: &[&str],
+        env: &[(String, String)],
+        on_line: &mut dyn FnMut(&str),
+    ) -> Result<CommandOutput, Box<dyn Error>> {
+        spawn_streaming(
+            Command::new(command)
+                .args(args)
+                .envs(env.iter().map(|(name, value)| (name, value))),
+            on_line,
+        )
+    }
+
+    fn run_with_input(
+        &self,
+        command: &str,
+        args: &[&str],
+        env: &[(String, String)],
+        input: &[u8],
+    ) -> Result<CommandOutput, Box<dyn Error>> {
+        output_with_input(
+            Command::new(command)
+                .args(args)
+                .envs(env.iter().map(|(name, value)| (name, value))),
+            input,
+        )
+    }
+
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(std::fs::read(path)?)
+    }
+
+    fn write_file(&self, path: &str, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+        Ok(std::fs::write(path, contents)?)
+    }
+
+    fn create_dir_all(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        Ok(std::fs::create_dir_all(path)?)
+    }
+
+    fn path_exists(&self, path: &str) -> bool {
+        std::path::Path::new(path).exists()
+    }
+}
+
+/// Runs a process with piped standard output and standard error, calling `on_line` with
+/// every line it prints as soon as it is printed, and captures both streams.
+///
+/// # Arguments
+///
+/// * `command` - The process to run
+/// * `on_line` - Called with every line of output, without the line ending
+///
+/// # Returns
+///
+/// Returns the exit status and the captured output of the process, or an error if it could
+/// not be started.
+pub fn spawn_streaming(
+    command: &mut Command,
+    on_line: &mut dyn FnMut(&str),
+) -> Result<CommandOutput, Box<dyn Error>> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout: Box<dyn Read + Send> = Box::new(child.stdout.take().ok_or("stdout not piped")?);
+    let stderr: Box<dyn Read + Send> = Box::new(child.stderr.take().ok_or("stderr not piped")?);
+
+    let mut output = CommandOutput::default();
+    let (sender, receiver) = mpsc::channel();
+    std::thread::scope(|scope| {
+        // Each stream is read on its own thread so that neither pipe fills up and blocks
+        // the process, while the lines are reported in the order they arrive
+        for (is_stderr, pipe) in [(false, stdout), (true, stderr)] {
+            let sender = sender.clone();
+            scope.spawn(move || {
+                let mut reader = BufReader::new(pipe);
+                let mut line = Vec::new();
+                while matches!(reader.read_until(b'\n', &mut line), Ok(read) if read > 0) {
+                    let text = String::from_utf8_lossy(&line).into_owned();
+                    if sender.send((is_stderr, text)).is_err() {
+                        break;
+                    }
+                    line.clear();
+                }
+            });
+        }
+        drop(sender);
+
+        for (is_stderr, text) in receiver {
+            on_line(text.trim_end_matches(['\r', '\n']));
+            if is_stderr {
+                output.stderr.push_str(&text);
+            } else {
+                output.stdout.push_str(&text);
+            }
+        }
+    });
+
+    output.success = child.wait()?.success();
+    Ok(output)
+}
+
+/// Runs a process, writing `input` to its standard input, and captures its output.
+///
+/// # Arguments
+///
+/// * `command` - The process to run
+/// * `input` - The bytes to write to its standard input, which is closed afterwards
+///
+/// # Returns
+///
+/// Returns the exit status and the captured output of the process, or an error if it could
+/// not be started.
+pub fn output_with_input(
+    command: &mut Command,
+    input: &[u8],
+) -> Result<CommandOutput, Box<dyn Error>> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().ok_or("stdin not piped")?;
+
+    // The input is written on its own thread so that a process printing a lot before it
+    // reads its input does not block on a full pipe. A process exiting without reading all
+    // of it is reported through its exit status.
+    let output = std::thread::scope(|scope| {
+        scope.spawn(move || stdin.write_all(input));
+        child.wait_with_output()
+    })?;
+    Ok(CommandOutput {
+        success: output.status.success(),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    })
+}
+
+thread_local! {
+    static CURRENT_RUNNER: RefCell<Arc<dyn CommandRunner>> = RefCell::new(Arc::new(LocalCommandRunner));
+}
+
+/// Returns the runner used by the current thread (`LocalCommandRunner` by default).
+pub fn current_runner() -> Arc<dyn CommandRunner> {
+    CURRENT_RUNNER.with(|current| current.borrow().clone())
+}
+
+/// Runs `f` with `runner` as the current thread's runner, restoring the previous one afterwards.
+///
+/// # Arguments
+///
+/// * `runner` - The runner to use while `f` runs
+/// * `f` - The function to run
+///
+/// # Returns
+///
+/// Returns the result of `f`.
+pub fn with_runner<T>(runner: Arc<dyn CommandRunner>, f: impl FnOnce() -> T) -> T {
+    /// Restores the previous runner when dropped, even if `f` panics.
+    struct Restore(Option<Arc<dyn CommandRunner>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            if let Some(previous) = self.0.take() {
+                CURRENT_RUNNER.with(|current| *current.borrow_mut() = previous);
+            }
+        }
+    }
+
+    let _restore = Restore(Some(CURRENT_RUNNER.with(|current| current.replace(runner))));
+    f()
+}
@@ -0,0 +1,280 @@
+//! # Secrets Module
+//!
+//! This module keeps the credentials generated while setting up a server, such as the
+//! database, message broker and dashboard administrator passwords, in a secret store.
+//!
+//! By default each secret is saved to a file readable only by root under `/root`. When
+//! `vault` is configured, secrets are kept in a HashiCorp Vault KV (version 2) secrets
+//! engine instead, which is reached with `curl` from the host being configured. The Vault
+//! token and the secret values are passed to `curl` in a config on its standard input, so
+//! they never appear in its arguments, the logs or an exported plan's task names.
+
+use crate::config::{Config, Secrets, VaultConfig};
+use crate::deployment;
+use crate::monitoring;
+use crate::plan::Plan;
+use crate::runner::current_runner;
+use crate::utils::{path_exists, read_file};
+use serde_json::{json, Value};
+use std::error::Error;
+
+/// The password of the MySQL root user.
+pub const MYSQL_ROOT_PASSWORD: &str = "mysql_root_password";
+
+/// The password of the PostgreSQL `postgres` user.
+pub const POSTGRES_PASSWORD: &str = "postgres_password";
+
+/// The password of the RabbitMQ administrator.
+pub const RABBITMQ_ADMIN_PASSWORD: &str = "rabbitmq_admin_password";
+
+/// The password of the OpenSearch `admin` user.
+pub const OPENSEARCH_ADMIN_PASSWORD: &str = "opensearch_admin_password";
+
+/// The password of the Grafana `admin` user.
+pub const GRAFANA_ADMIN_PASSWORD: &str = "grafana_admin_password";
+
+/// The directory `FileSecretStore` saves secrets in.
+const SECRETS_DIR: &str = "/root";
+
+/// A place secrets are read from and saved to.
+pub trait SecretStore {
+    /// Returns the value of a secret, or `None` if it has not been stored.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the secret (e.g., `MYSQL_ROOT_PASSWORD`)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be read.
+    fn get(&self, name: &str) -> Result<Option<String>, Box<dyn Error>>;
+
+    /// Plans saving a secret, replacing any earlier value.
+    ///
+    /// # Arguments
+    ///
+    /// * `plan` - The plan to add the operations to
+    /// * `name` - The name of the secret
+    /// * `value` - The value of the secret
+    fn plan_put(&self, plan: &mut Plan, name: &str, value: &str);
+
+    /// Saves a secret on the host of the current `CommandRunner`, replacing any earlier
+    /// value.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the secret
+    /// * `value` - The value of the secret
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the secret cannot be saved.
+    fn put(&self, name: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        Plan::build(|plan| {
+            self.plan_put(plan, name, value);
+            Ok(())
+        })?
+        .execute()
+    }
+}
+
+/// Returns the secret store of a configuration: Vault when `vault` is set, files otherwise.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the Vault settings and
+///   credentials
+///
+/// # Errors
+///
+/// Returns an error if logging in to Vault fails.
+pub fn secret_store(config: &Config) -> Result<Box<dyn SecretStore>, Box<dyn Error>> {
+    Ok(match &config.vault {
+        Some(vault) => Box::new(VaultSecretStore::login(vault, &config.secrets)?),
+        None => Box::new(FileSecretStore),
+    })
+}
+
+/// Saves each secret to `/root/.<name>`, readable only by root.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileSecretStore;
+
+impl FileSecretStore {
+    /// Returns the file a secret is saved to (e.g., `/root/.mysql_root_password`).
+    ///
+    /// The secrets other modules read directly, such as the backup script reading the MySQL
+    /// root password, are saved to the files named by their constants.
+    pub fn path(name: &str) -> String {
+        match name {
+            MYSQL_ROOT_PASSWORD => String::from(deployment::MYSQL_PASSWORD_FILE),
+            RABBITMQ_ADMIN_PASSWORD => String::from(deployment::RABBITMQ_PASSWORD_FILE),
+            OPENSEARCH_ADMIN_PASSWORD => String::from(deployment::OPENSEARCH_PASSWORD_FILE),
+            GRAFANA_ADMIN_PASSWORD => String::from(monitoring::GRAFANA_PASSWORD_FILE),
+            _ => format!("{}/.{}", SECRETS_DIR, name),
+        }
+    }
+}
+
+impl SecretStore for FileSecretStore {
+    fn get(&self, name: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let path = Self::path(name);
+        if !path_exists(&path) {
+            return Ok(None);
+        }
+        Ok(Some(read_file(&path)?.trim_end_matches('\n').to_string()))
+    }
+
+    fn plan_put(&self, plan: &mut Plan, name: &str, value: &str) {
+        plan.write_private_file(&Self::path(name), value);
+    }
+}
+
+/// Keeps secrets in a Vault KV version 2 secrets engine, each under
+/// `<mount>/data/<path>/<name>` with the value in its `value` field.
+#[derive(Debug, Clone)]
+pub struct VaultSecretStore {
+    address: String,
+    mount: String,
+    path: String,
+    token: String,
+}
+
+impl VaultSecretStore {
+    /// Creates a store using a Vault token.
+    ///
+    /// # Arguments
+    ///
+    /// * `vault` - The Vault settings
+    /// * `token` - The token to authenticate with
+    pub fn new(vault: &VaultConfig, token: &str) -> Self {
+        VaultSecretStore {
+            address: vault.address.trim_end_matches('/').to_string(),
+            mount: vault.mount.trim_matches('/').to_string(),
+            path: vault.path.trim_matches('/').to_string(),
+            token: token.to_string(),
+        }
+    }
+
+    /// Creates a store with the `vault_token` secret, or the token of the `VAULT_TOKEN`
+    /// environment variable, or by logging in with the AppRole `role_id` and the
+    /// `vault_secret_id` secret.
+    ///
+    /// # Arguments
+    ///
+    /// * `vault` - The Vault settings
+    /// * `secrets` - The secrets holding the Vault credentials
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there are no credentials or the AppRole login fails.
+    pub fn login(vault: &VaultConfig, secrets: &Secrets) -> Result<Self, Box<dyn Error>> {
+        let token = secrets
+            .vault_token
+            .clone()
+            .or_else(|| std::env::var("VAULT_TOKEN").ok().filter(|t| !t.is_empty()));
+        if let Some(token) = token {
+            return Ok(Self::new(vault, &token));
+        }
+
+        let (Some(role_id), Some(secret_id)) = (&vault.role_id, &secrets.vault_secret_id) else {
+            return Err("No Vault credentials: set vault_token, VAULT_TOKEN, or vault.role_id and vault_secret_id".into());
+        };
+        let url = format!(
+            "{}/v1/auth/approle/login",
+            vault.address.trim_end_matches('/')
+        );
+        let body = json!({ "role_id": role_id, "secret_id": secret_id }).to_string();
+        let response = vault_request(
+            &["-X", "POST", &url],
+            &curl_config(&[("data", &body)]),
+            "log in to",
+        )?;
+        let token = response["auth"]["client_token"]
+            .as_str()
+            .ok_or("Vault AppRole login returned no client token")?;
+        Ok(Self::new(vault, token))
+    }
+
+    /// Returns the URL of a secret.
+    pub fn url(&self, name: &str) -> String {
+        format!(
+            "{}/v1/{}/data/{}/{}",
+            self.address, self.mount, self.path, name
+        )
+    }
+
+    fn token_header(&self) -> String {
+        format!("X-Vault-Token: {}", self.token)
+    }
+}
+
+/// Returns a `curl` config (as read by `curl --config -`) setting the given options.
+///
+/// # Arguments
+///
+/// * `options` - The long option names (without the dashes) and their values
+pub fn curl_config(options: &[(&str, &str)]) -> String {
+    options
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "{} = \"{}\"\n",
+                name,
+                value
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n")
+            )
+        })
+        .collect()
+}
+
+impl SecretStore for VaultSecretStore {
+    fn get(&self, name: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let response = vault_request(
+            &[&self.url(name)],
+            &curl_config(&[("header", &self.token_header())]),
+            "read",
+        )?;
+        if let Some(value) = response["data"]["data"]["value"].as_str() {
+            return Ok(Some(value.to_string()));
+        }
+        // Vault answers a missing secret with an empty list of errors
+        match response["errors"].as_array() {
+            Some(errors) if errors.is_empty() => Ok(None),
+            _ => Err(format!("Vault has no value for secret {}: {}", name, response).into()),
+        }
+    }
+
+    fn plan_put(&self, plan: &mut Plan, name: &str, value: &str) {
+        let body = json!({ "data": { "value": value } }).to_string();
+        plan.run_with_input(
+            "curl",
+            &[
+                "-sS",
+                "--fail",
+                "-X",
+                "POST",
+                "-H",
+                "Content-Type: application/json",
+                "--config",
+                "-",
+                &self.url(name),
+            ],
+            curl_config(&[("header", &self.token_header()), ("data", &body)]),
+        );
+    }
+}
+
+/// Sends a request to Vault with `curl`, passing `config` (the credentials) on its standard
+/// input, and returns the JSON response.
+fn vault_request(args: &[&str], config: &str, action: &str) -> Result<Value, Box<dyn Error>> {
+    let mut curl_args = vec!["-sS", "--config", "-"];
+    curl_args.extend_from_slice(args);
+    let output = current_runner().run_with_input("curl", &curl_args, &[], config.as_bytes())?;
+    if !output.success {
+        return Err(format!("Failed to {} Vault: {}", action, output.stderr.trim()).into());
+    }
+    serde_json::from_str(&output.stdout)
+        .map_err(|e| format!("Invalid response from Vault: {}", e).into())
+}
@@ -2,21 +2,37 @@
 //!
 //! This module provides functions for implementing various security measures on a Linux server.
 //! It includes functionality for configuring Fail2Ban, setting up advanced security measures
-//! (SELinux or AppArmor), implementing rootkit detection, and scheduling regular security scans.
+//! (SELinux or AppArmor), implementing rootkit detection, monitoring file integrity with AIDE,
+//! auditing with auditd, scanning for malware with ClamAV, and scheduling regular security
+//! scans.
+//!
+//! The measures are built as a `Plan` by the `plan_*` functions; the remaining public
+//! functions execute the corresponding plan right away.
 
 use crate::config::Config;
-use crate::distro::{get_package_manager, PackageManager};
+use crate::deployment::app_port;
+use crate::maintenance;
+use crate::plan::Plan;
 use crate::rollback::RollbackManager;
-use crate::utils::run_command;
-use log::info;
+use crate::utils::{path_exists, plan_job, read_file, shell_quote};
+use log::{info, warn};
 use std::error::Error;
 
+/// The script run by the scheduled AIDE check.
+const AIDE_CHECK_SCRIPT: &str = "/usr/local/bin/aide_check.sh";
+
+/// The script run by the scheduled ClamAV scan.
+const CLAMAV_SCAN_SCRIPT: &str = "/usr/local/bin/clamav_scan.sh";
+
 /// Implements all security measures based on the provided configuration.
 ///
 /// This function orchestrates the implementation of various security measures including:
 /// - Configuring Fail2Ban
 /// - Setting up advanced security (SELinux or AppArmor)
 /// - Setting up rootkit detection
+/// - Setting up AIDE file integrity monitoring (advanced security level only)
+/// - Setting up the auditd audit rules (advanced security level only)
+/// - Setting up ClamAV antivirus, if `enable_clamav` is set
 /// - Configuring regular security scans
 ///
 /// # Arguments
@@ -33,12 +49,10 @@ pub fn implement_security_measures(
 ) -> Result<(), Box<dyn Error>> {
     info!("Implementing security measures...");
 
-    let snapshot = rollback.create_snapshot()?;
+    let snapshot = rollback.create_snapshot("Security measures")?;
 
-    configure_fail2ban()?;
-    setup_advanced_security(config)?;
-    setup_rootkit_detection(config)?;
-    setup_security_scans()?;
+    Plan::build(|plan| plan_security_measures(plan, config))?
+        .execute_with_rollback(rollback, snapshot)?;
 
     rollback.commit_snapshot(snapshot)?;
 
@@ -46,43 +60,103 @@ pub fn implement_security_measures(
     Ok(())
 }
 
+/// Plans all security measures: Fail2Ban, advanced security (SELinux or AppArmor),
+/// rootkit detection, AIDE and auditd (for the `advanced` security level), ClamAV (if
+/// enabled) and regular security scans.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `config` - A reference to the `Config` struct containing user-defined configuration options
+///
+/// # Errors
+///
+/// Returns an error if the distribution or the security scan schedule is not supported
+pub fn plan_security_measures(plan: &mut Plan, config: &Config) -> Result<(), Box<dyn Error>> {
+    plan_fail2ban(plan, config);
+    plan_advanced_security(plan, config)?;
+    plan_rootkit_detection(plan, config);
+    if config.security_level == "advanced" {
+        plan_aide(plan, config)?;
+        plan_auditd(plan, config)?;
+    }
+    if config.enable_clamav {
+        plan_clamav(plan, config)?;
+    }
+    plan_security_scans(plan, config)?;
+    Ok(())
+}
+
 /// Configures and starts the Fail2Ban service.
 ///
 /// This function installs Fail2Ban, creates a basic configuration for SSH,
 /// and starts the Fail2Ban service.
 ///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the SSH port
+///
 /// # Errors
 ///
 /// Returns an error if Fail2Ban installation or configuration fails
-pub fn configure_fail2ban() -> Result<(), Box<dyn Error>> {
-    let package_manager = get_package_manager()?;
-    match package_manager {
-        PackageManager::Apt => run_command("apt", &["install", "-y", "fail2ban"])?,
-        PackageManager::Yum => run_command("yum", &["install", "-y", "fail2ban"])?,
-        PackageManager::Dnf => run_command("dnf", &["install", "-y", "fail2ban"])?,
-    }
+pub fn configure_fail2ban(config: &Config) -> Result<(), Box<dyn Error>> {
+    let mut plan = Plan::new();
+    plan_fail2ban(&mut plan, config);
+    plan.execute()
+}
+
+/// Plans installing, configuring and starting Fail2Ban.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `config` - A reference to the `Config` struct containing the SSH port
+pub fn plan_fail2ban(plan: &mut Plan, config: &Config) {
+    plan.install(&["fail2ban"])
+        .write_file("/etc/fail2ban/jail.local", fail2ban_jail(config))
+        .enable_service("fail2ban")
+        .start_service("fail2ban");
+}
 
-    let fail2ban_config = r#"
+/// Generates the Fail2Ban jail configuration, banning the addresses that repeatedly fail to
+/// log in over SSH.
+///
+/// The jail watches `ssh_port` rather than the `ssh` service name, which stands for port 22
+/// and would leave a moved sshd unprotected.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the SSH port
+///
+/// # Returns
+///
+/// Returns the contents of `/etc/fail2ban/jail.local`.
+pub fn fail2ban_jail(config: &Config) -> String {
+    format!(
+        r#"
 [sshd]
 enabled = true
-port = ssh
+port = {}
 filter = sshd
 logpath = /var/log/auth.log
 maxretry = 3
 bantime = 3600
-"#;
-    std::fs::write("/etc/fail2ban/jail.local", fail2ban_config)?;
-
-    run_command("systemctl", &["enable", "fail2ban"])?;
-    run_command("systemctl", &["start", "fail2ban"])?;
-
-    Ok(())
+"#,
+        config.ssh_port
+    )
 }
 
 /// Sets up advanced security measures based on the Linux distribution.
 ///
-/// For Ubuntu, this function sets up AppArmor.
-/// For CentOS or Fedora, this function sets up SELinux.
+/// For Ubuntu, this function sets up AppArmor and puts the profiles of the deployed
+/// applications (see `apparmor_profiles`) in enforce mode.
+/// For CentOS or Fedora, this function sets up SELinux, and enables the SELinux booleans
+/// the deployed applications need (see `selinux_booleans`).
+///
+/// If SELinux is currently disabled, switching straight to enforcing mode could leave the
+/// system unable to boot, because its files carry no SELinux labels. SELinux is then set to
+/// permissive mode and the file system is relabeled on the next reboot, after which it can
+/// be switched to enforcing.
 ///
 /// # Arguments
 ///
@@ -92,22 +166,56 @@ bantime = 3600
 ///
 /// Returns an error if the setup fails or if the Linux distribution is not supported
 pub fn setup_advanced_security(config: &Config) -> Result<(), Box<dyn Error>> {
+    Plan::build(|plan| plan_advanced_security(plan, config))?.execute()
+}
+
+/// Plans the advanced security measures for the `advanced` security level.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `config` - A reference to the `Config` struct containing user-defined configuration options
+///
+/// # Errors
+///
+/// Returns an error if the Linux distribution is not supported
+pub fn plan_advanced_security(plan: &mut Plan, config: &Config) -> Result<(), Box<dyn Error>> {
     if config.security_level == "advanced" {
         // Enable and configure SELinux or AppArmor based on the distribution
         match config.linux_distro.as_str() {
             "ubuntu" => {
-                run_command("apt", &["install", "-y", "apparmor", "apparmor-utils"])?;
-                run_command("aa-enforce", &["/etc/apparmor.d/*"])?;
+                plan.install(&["apparmor", "apparmor-utils"]);
+                let profiles = apparmor_profiles(config);
+                if profiles.is_empty() {
+                    info!("No AppArmor profiles of deployed applications found to enforce");
+                } else {
+                    let profiles: Vec<&str> = profiles.iter().map(String::as_str).collect();
+                    plan.run("aa-enforce", &profiles);
+                }
             }
             "centos" | "fedora" => {
-                run_command(
-                    "yum",
-                    &["install", "-y", "selinux-policy", "selinux-policy-targeted"],
-                )?;
-                std::fs::write(
-                    "/etc/selinux/config",
-                    "SELINUX=enforcing\nSELINUXTYPE=targeted\n",
-                )?;
+                plan.install(&["selinux-policy", "selinux-policy-targeted"]);
+                if selinux_enabled() {
+                    plan.write_file(
+                        "/etc/selinux/config",
+                        "SELINUX=enforcing\nSELINUXTYPE=targeted\n",
+                    );
+                    for boolean in selinux_booleans(config) {
+                        plan_selinux_boolean(plan, boolean, true);
+                    }
+                } else {
+                    warn!(
+                        "SELinux is disabled; setting it to permissive and relabeling the file \
+                         system on the next reboot. Reboot, then set SELINUX=enforcing in \
+                         /etc/selinux/config and run server_forge again to enable enforcing \
+                         mode and the SELinux booleans"
+                    );
+                    plan.write_file(
+                        "/etc/selinux/config",
+                        "SELINUX=permissive\nSELINUXTYPE=targeted\n",
+                    )
+                    .write_file("/.autorelabel", "");
+                }
             }
             _ => return Err("Unsupported Linux distribution for advanced security".into()),
         }
@@ -115,6 +223,103 @@ pub fn setup_advanced_security(config: &Config) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// Returns the AppArmor profiles of the deployed applications that exist on the host.
+///
+/// Only these profiles are enforced, since enforcing every installed profile can break
+/// services that were never tested under them. Profiles are shipped with the application
+/// packages, so those of applications that are not installed yet are enforced by the next
+/// run.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the deployed applications
+///
+/// # Returns
+///
+/// Returns the paths of the profiles, in the order of the deployed applications.
+pub fn apparmor_profiles(config: &Config) -> Vec<String> {
+    config
+        .deployed_apps
+        .iter()
+        .filter_map(|app| match app.as_str() {
+            "nginx" => Some("usr.sbin.nginx"),
+            "apache" => Some("usr.sbin.apache2"),
+            "mysql" => Some("usr.sbin.mysqld"),
+            "php" => Some("php-fpm"),
+            _ => None,
+        })
+        .map(|profile| format!("/etc/apparmor.d/{}", profile))
+        .filter(|profile| path_exists(profile))
+        .collect()
+}
+
+/// Sets an SELinux boolean persistently.
+///
+/// # Arguments
+///
+/// * `name` - The name of the boolean (e.g., "httpd_can_network_connect")
+/// * `value` - The value to set
+///
+/// # Errors
+///
+/// Returns an error if `setsebool` fails, for example because SELinux is disabled
+pub fn set_selinux_boolean(name: &str, value: bool) -> Result<(), Box<dyn Error>> {
+    let mut plan = Plan::new();
+    plan_selinux_boolean(&mut plan, name, value);
+    plan.execute()
+}
+
+/// Plans setting an SELinux boolean persistently with `setsebool -P`.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `name` - The name of the boolean
+/// * `value` - The value to set
+pub fn plan_selinux_boolean(plan: &mut Plan, name: &str, value: bool) {
+    plan.run("setsebool", &["-P", name, if value { "on" } else { "off" }]);
+}
+
+/// Returns the SELinux booleans to enable for the configured applications:
+/// - `httpd_can_network_connect` when a web server proxies to an application server
+/// - `haproxy_connect_any` when HAProxy balances across backends
+/// - `antivirus_can_scan_system` when ClamAV is enabled
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the deployed applications
+pub fn selinux_booleans(config: &Config) -> Vec<&'static str> {
+    let deploys = |name: &str| config.deployed_apps.iter().any(|app| app == name);
+    let mut booleans = Vec::new();
+    if (deploys("nginx") || deploys("apache"))
+        && config.app_domain.is_some()
+        && config
+            .deployed_apps
+            .iter()
+            .any(|app| app_port(app).is_some())
+    {
+        booleans.push("httpd_can_network_connect");
+    }
+    if deploys("haproxy") {
+        booleans.push("haproxy_connect_any");
+    }
+    if config.enable_clamav {
+        booleans.push("antivirus_can_scan_system");
+    }
+    booleans
+}
+
+/// Checks whether SELinux is enabled in the running kernel (in enforcing or permissive
+/// mode), and is configured not to be disabled at the next boot.
+fn selinux_enabled() -> bool {
+    path_exists("/sys/fs/selinux/enforce")
+        && !read_file("/etc/selinux/config").is_ok_and(|selinux_config| {
+            selinux_config
+                .lines()
+                .any(|line| line.trim() == "SELINUX=disabled")
+        })
+}
+
 /// Sets up rootkit detection tools (rkhunter and chkrootkit).
 ///
 /// This function installs rkhunter and chkrootkit, then updates the rkhunter database.
@@ -126,41 +331,436 @@ pub fn setup_advanced_security(config: &Config) -> Result<(), Box<dyn Error>> {
 /// # Errors
 ///
 /// Returns an error if installation or configuration of rootkit detection tools fails
-pub fn setup_rootkit_detection(_config: &Config) -> Result<(), Box<dyn Error>> {
-    let package_manager = get_package_manager()?;
-    match package_manager {
-        PackageManager::Apt => run_command("apt", &["install", "-y", "rkhunter", "chkrootkit"])?,
-        PackageManager::Yum => run_command("yum", &["install", "-y", "rkhunter", "chkrootkit"])?,
-        PackageManager::Dnf => run_command("dnf", &["install", "-y", "rkhunter", "chkrootkit"])?,
+pub fn setup_rootkit_detection(config: &Config) -> Result<(), Box<dyn Error>> {
+    let mut plan = Plan::new();
+    plan_rootkit_detection(&mut plan, config);
+    plan.execute()
+}
+
+/// Plans installing rkhunter and chkrootkit and updating the rkhunter database.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `config` - A reference to the `Config` struct (unused in the current implementation)
+pub fn plan_rootkit_detection(plan: &mut Plan, _config: &Config) {
+    plan.install(&["rkhunter", "chkrootkit"])
+        // Update rkhunter database
+        .run("rkhunter", &["--update"])
+        .run("rkhunter", &["--propupd"]);
+}
+
+/// Sets up AIDE file integrity monitoring.
+///
+/// This function installs AIDE, initializes its database with a baseline of the file system,
+/// and schedules a daily check (via cron or a systemd timer) that mails any differences to
+/// the admin email.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the Linux distribution, admin
+///   email and scheduler
+///
+/// # Errors
+///
+/// Returns an error if the Linux distribution is not supported, or if a step fails
+pub fn setup_aide(config: &Config) -> Result<(), Box<dyn Error>> {
+    Plan::build(|plan| plan_aide(plan, config))?.execute()
+}
+
+/// Plans installing AIDE, initializing its database and scheduling the check script.
+///
+/// Debian-based distributions initialize and install the database with `aideinit`; Red Hat
+/// based ones run `aide --init` and move the new database into place.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `config` - A reference to the `Config` struct containing the Linux distribution, admin
+///   email and scheduler
+///
+/// # Errors
+///
+/// Returns an error if the Linux distribution is not supported
+pub fn plan_aide(plan: &mut Plan, config: &Config) -> Result<(), Box<dyn Error>> {
+    let aide_config = match config.linux_distro.as_str() {
+        "ubuntu" => {
+            plan.install(&["aide", "aide-common"])
+                .run("aideinit", &["--yes", "--force"]);
+            "/etc/aide/aide.conf"
+        }
+        "centos" | "fedora" => {
+            plan.install(&["aide"]).run("aide", &["--init"]).run(
+                "mv",
+                &[
+                    "-f",
+                    "/var/lib/aide/aide.db.new.gz",
+                    "/var/lib/aide/aide.db.gz",
+                ],
+            );
+            "/etc/aide.conf"
+        }
+        _ => return Err("Unsupported Linux distribution for AIDE".into()),
+    };
+
+    plan.write_file(AIDE_CHECK_SCRIPT, aide_check_script(config, aide_config))
+        .run("chmod", &["+x", AIDE_CHECK_SCRIPT]);
+
+    plan_job(
+        plan,
+        &config.scheduler,
+        "aide_check",
+        "AIDE file integrity check",
+        "0 4 * * *",
+        &format!("{} > /var/log/aide_check.log 2>&1", AIDE_CHECK_SCRIPT),
+    )
+}
+
+/// Generates the AIDE check script.
+///
+/// AIDE exits with a status below 14 when it only found differences, and with 14 or more
+/// when the check itself failed; both are mailed to the admin email, if one is configured.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the admin email
+/// * `aide_config` - The path of the AIDE configuration file
+///
+/// # Returns
+///
+/// Returns the contents of the script.
+pub fn aide_check_script(config: &Config, aide_config: &str) -> String {
+    let mut script = format!(
+        r#"#!/bin/bash
+output=$(aide --check --config={} 2>&1)
+status=$?
+echo "$output"
+"#,
+        shell_quote(aide_config)
+    );
+
+    if let Some(admin_email) = &config.admin_email {
+        script.push_str(&format!(
+            r#"
+if [ "$status" -ne 0 ]; then
+    if [ "$status" -ge 14 ]; then
+        subject="[serverforge] AIDE check failed on $(hostname)"
+    else
+        subject="[serverforge] AIDE detected file changes on $(hostname)"
+    fi
+{}fi
+"#,
+            mail_output(admin_email)
+        ));
     }
 
-    // Update rkhunter database
-    run_command("rkhunter", &["--update"])?;
-    run_command("rkhunter", &["--propupd"])?;
+    script.push_str("exit $status\n");
+    script
+}
+
+/// Sets up the Linux audit daemon with a baseline rule set.
+///
+/// This function installs auditd, writes the rules returned by `audit_rules` to
+/// `/etc/audit/rules.d/server_forge.rules`, and enables and restarts the daemon, which
+/// loads them.
+///
+/// The rules end with `-e 2`, which makes the loaded audit configuration immutable: once it
+/// is in effect, changing the rules (including rolling this file back) only takes effect
+/// after a reboot.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the Linux distribution
+///
+/// # Errors
+///
+/// Returns an error if the Linux distribution is not supported, or if a step fails
+pub fn setup_auditd(config: &Config) -> Result<(), Box<dyn Error>> {
+    Plan::build(|plan| plan_auditd(plan, config))?.execute()
+}
+
+/// Plans installing auditd and loading the baseline rules.
+///
+/// The daemon is restarted with `service`, because the auditd unit refuses manual stops
+/// through `systemctl` on Red Hat based distributions.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `config` - A reference to the `Config` struct containing the Linux distribution
+///
+/// # Errors
+///
+/// Returns an error if the Linux distribution is not supported
+pub fn plan_auditd(plan: &mut Plan, config: &Config) -> Result<(), Box<dyn Error>> {
+    match config.linux_distro.as_str() {
+        "ubuntu" => plan.install(&["auditd"]),
+        "centos" | "fedora" => plan.install(&["audit"]),
+        _ => return Err("Unsupported Linux distribution for auditd".into()),
+    };
 
+    warn!(
+        "The audit rules are made immutable (-e 2); later changes to them, including a \
+         rollback, take effect only after a reboot"
+    );
+    plan.write_file("/etc/audit/rules.d/server_forge.rules", audit_rules())
+        .enable_service("auditd")
+        .run("service", &["auditd", "restart"]);
     Ok(())
 }
 
+/// Returns the baseline audit rules.
+///
+/// They watch changes to the user, group and password databases, sudo configuration, SSH
+/// configuration, login records, hostname and kernel modules, and record commands run with
+/// elevated privileges (via setuid programs such as sudo). The final `-e 2` locks the
+/// configuration until the next reboot.
+pub fn audit_rules() -> &'static str {
+    r#"## Baseline audit rules managed by server_forge
+
+# User, group and password databases
+-w /etc/passwd -p wa -k identity
+-w /etc/group -p wa -k identity
+-w /etc/shadow -p wa -k identity
+-w /etc/gshadow -p wa -k identity
+
+# Privilege configuration
+-w /etc/sudoers -p wa -k scope
+-w /etc/sudoers.d/ -p wa -k scope
+-w /etc/ssh/sshd_config -p wa -k sshd
+
+# Logins
+-w /var/log/lastlog -p wa -k logins
+-w /var/run/faillock/ -p wa -k logins
+
+# Hostname and network identity
+-a always,exit -F arch=b64 -S sethostname,setdomainname -k system-locale
+-w /etc/hosts -p wa -k system-locale
+-w /etc/hostname -p wa -k system-locale
+
+# Commands run with elevated privileges
+-a always,exit -F arch=b64 -S execve -C uid!=euid -F euid=0 -k privileged
+-w /usr/bin/sudo -p x -k privileged
+-w /usr/bin/su -p x -k privileged
+
+# Kernel modules
+-a always,exit -F arch=b64 -S init_module,finit_module,delete_module -k modules
+-w /usr/sbin/insmod -p x -k modules
+-w /usr/sbin/rmmod -p x -k modules
+-w /usr/sbin/modprobe -p x -k modules
+
+# Make the configuration immutable until the next reboot
+-e 2
+"#
+}
+
+/// Sets up ClamAV antivirus.
+///
+/// This function installs ClamAV, downloads its virus database with `freshclam`, enables
+/// the scanning daemon and the database updater, and schedules a weekly full scan (via cron
+/// or a systemd timer) that logs to `/var/log/clamav_scan.log` and mails detections to the
+/// admin email.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the Linux distribution, admin
+///   email and scheduler
+///
+/// # Errors
+///
+/// Returns an error if the Linux distribution is not supported, or if a step fails
+pub fn setup_clamav(config: &Config) -> Result<(), Box<dyn Error>> {
+    Plan::build(|plan| plan_clamav(plan, config))?.execute()
+}
+
+/// Plans installing, updating and enabling ClamAV and scheduling the scan script.
+///
+/// On Debian-based distributions the daemon is `clamav-daemon`, and the running
+/// `clamav-freshclam` service is stopped for the initial update since it locks the database.
+/// On Red Hat based distributions the daemon is the `clamd@scan` instance, whose
+/// `/etc/clamd.d/scan.conf` ships without a socket enabled and is replaced.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `config` - A reference to the `Config` struct containing the Linux distribution, admin
+///   email and scheduler
+///
+/// # Errors
+///
+/// Returns an error if the Linux distribution is not supported
+pub fn plan_clamav(plan: &mut Plan, config: &Config) -> Result<(), Box<dyn Error>> {
+    match config.linux_distro.as_str() {
+        "ubuntu" => {
+            plan.install(&["clamav", "clamav-daemon", "clamav-freshclam"])
+                .run("systemctl", &["stop", "clamav-freshclam"])
+                .run("freshclam", &[])
+                .enable_service("clamav-freshclam")
+                .start_service("clamav-freshclam")
+                .enable_service("clamav-daemon")
+                .start_service("clamav-daemon");
+        }
+        "centos" | "fedora" => {
+            plan.install(&["clamav", "clamav-update", "clamd"])
+                .write_file(
+                    "/etc/clamd.d/scan.conf",
+                    r#"LogSyslog yes
+LocalSocket /run/clamd.scan/clamd.sock
+PidFile /run/clamd.scan/clamd.pid
+DatabaseDirectory /var/lib/clamav
+TemporaryDirectory /var/tmp
+User clamscan
+"#,
+                )
+                .run("freshclam", &[])
+                .enable_service("clamav-freshclam")
+                .start_service("clamav-freshclam")
+                .enable_service("clamd@scan")
+                .start_service("clamd@scan");
+        }
+        _ => return Err("Unsupported Linux distribution for ClamAV".into()),
+    }
+
+    plan.write_file(CLAMAV_SCAN_SCRIPT, clamav_scan_script(config))
+        .run("chmod", &["+x", CLAMAV_SCAN_SCRIPT]);
+
+    plan_job(
+        plan,
+        &config.scheduler,
+        "clamav_scan",
+        "ClamAV full scan",
+        "0 3 * * 6",
+        &format!("{} > /var/log/clamav_scan.log 2>&1", CLAMAV_SCAN_SCRIPT),
+    )
+}
+
+/// Generates the ClamAV scan script.
+///
+/// The script scans the whole file system except the pseudo file systems, printing only
+/// infected files. `clamscan` exits with 1 when it found infected files and with 2 on
+/// errors; both are mailed to the admin email, if one is configured.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the admin email
+///
+/// # Returns
+///
+/// Returns the contents of the script.
+pub fn clamav_scan_script(config: &Config) -> String {
+    let mut script = String::from(
+        r#"#!/bin/bash
+output=$(clamscan --recursive --infected --exclude-dir='^/(proc|sys|dev|run)/' / 2>&1)
+status=$?
+echo "$output"
+"#,
+    );
+
+    if let Some(admin_email) = &config.admin_email {
+        script.push_str(&format!(
+            r#"
+if [ "$status" -ne 0 ]; then
+    if [ "$status" -eq 1 ]; then
+        subject="[serverforge] ClamAV found infected files on $(hostname)"
+    else
+        subject="[serverforge] ClamAV scan failed on $(hostname)"
+    fi
+{}fi
+"#,
+            mail_output(admin_email)
+        ));
+    }
+
+    script.push_str("exit $status\n");
+    script
+}
+
+/// Returns the shell lines that mail `$output` with the subject `$subject` to an address,
+/// via `mail`, falling back to `sendmail`.
+fn mail_output(admin_email: &str) -> String {
+    format!(
+        r#"    if command -v mail > /dev/null; then
+        printf '%s\n' "$output" | mail -s "$subject" {email}
+    elif command -v sendmail > /dev/null; then
+        printf 'Subject: %s\n\n%s\n' "$subject" "$output" | sendmail {email}
+    fi
+"#,
+        email = shell_quote(admin_email)
+    )
+}
+
 /// Sets up regular security scans using rkhunter and chkrootkit.
 ///
 /// This function creates a script to run both rkhunter and chkrootkit,
-/// then sets up a weekly cron job to execute this script.
+/// then schedules it (via cron or a systemd timer) on the configured schedule.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the security scan schedule and scheduler
 ///
 /// # Errors
 ///
-/// Returns an error if creating the script or setting up the cron job fails
-pub fn setup_security_scans() -> Result<(), Box<dyn Error>> {
+/// Returns an error if the schedule is invalid, or if creating the script or scheduling it fails
+pub fn setup_security_scans(config: &Config) -> Result<(), Box<dyn Error>> {
+    Plan::build(|plan| plan_security_scans(plan, config))?.execute()
+}
+
+/// Plans creating and scheduling the security scan script.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `config` - A reference to the `Config` struct containing the security scan schedule,
+///   maintenance window and scheduler
+///
+/// # Errors
+///
+/// Returns an error if the schedule or the maintenance window is invalid
+pub fn plan_security_scans(plan: &mut Plan, config: &Config) -> Result<(), Box<dyn Error>> {
+    let schedule = maintenance::security_scan_schedule(config)?;
+
     let scan_script = r#"#!/bin/bash
 rkhunter --check --skip-keypress
 chkrootkit
 "#;
-    std::fs::write("/usr/local/bin/security_scan.sh", scan_script)?;
-    run_command("chmod", &["+x", "/usr/local/bin/security_scan.sh"])?;
+    plan.write_file("/usr/local/bin/security_scan.sh", scan_script)
+        .run("chmod", &["+x", "/usr/local/bin/security_scan.sh"]);
 
-    // Add weekly cron job for security scans
-    let cron_job =
-        "0 2 * * 0 root /usr/local/bin/security_scan.sh > /var/log/security_scan.log 2>&1\n";
-    std::fs::write("/etc/cron.d/security_scan", cron_job)?;
+    plan_job(
+        plan,
+        &config.scheduler,
+        "security_scan",
+        "Security scan",
+        &schedule,
+        "/usr/local/bin/security_scan.sh > /var/log/security_scan.log 2>&1",
+    )
+}
 
-    Ok(())
+/// Maps a security scan schedule to the time fields of a cron entry.
+///
+/// The schedule may be one of "daily", "weekly" or "monthly", or a raw
+/// five-field cron expression (e.g., "30 3 * * 1-5").
+///
+/// # Arguments
+///
+/// * `schedule` - A string slice containing the configured schedule
+///
+/// # Returns
+///
+/// Returns the cron time fields, or an error if the schedule is not recognized.
+pub fn security_scan_cron_schedule(schedule: &str) -> Result<String, Box<dyn Error>> {
+    match schedule {
+        "daily" => Ok(String::from("0 2 * * *")),
+        "weekly" => Ok(String::from("0 2 * * 0")),
+        "monthly" => Ok(String::from("0 2 1 * *")),
+        expression if expression.split_whitespace().count() == 5 => Ok(expression
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")),
+        _ => Err(format!(
+            "Invalid security scan schedule '{}': expected daily, weekly, monthly or a five-field cron expression",
+            schedule
+        )
+        .into()),
+    }
 }
@@ -1,27 +1,45 @@
 //! # Setup Module
 //!
 //! This module provides functionality for performing initial setup tasks on a Linux server.
-//! It includes functions for updating the system, installing essential packages,
-//! setting up a firewall, and configuring SSH for improved security.
+//! It includes functions for setting the hostname, creating a swap file, updating the
+//! system, configuring the timezone and time synchronization, installing essential packages,
+//! rotating the logs written by server_forge, setting up a firewall, creating an administrator
+//! account, and configuring SSH for improved security.
 //!
 //! The module is designed to work across different Linux distributions by using
-//! distribution-specific commands where necessary.
+//! distribution-specific commands where necessary. Each step has a `plan_*` counterpart
+//! that adds its operations to a `Plan` instead of applying them.
 use crate::config::Config;
+use crate::deployment;
+use crate::distro::{get_package_manager, service_name_for, PackageManager};
+use crate::firewall;
+use crate::plan::Plan;
 use crate::rollback::RollbackManager;
-use crate::utils::run_command;
-use log::info;
+use crate::utils::{path_exists, read_file, shell_quote};
+use crate::validation::ServiceType;
+use log::{info, warn};
 use std::error::Error;
-use std::fs;
+
+/// The port sshd is moved to unless `ssh_port` is set.
+pub const DEFAULT_SSH_PORT: u16 = 2222;
 
 /// Performs the initial setup of the server based on the provided configuration.
 ///
 /// This function orchestrates the entire initial setup process, including:
+/// - Setting the hostname, if `hostname` is set
+/// - Creating a swap file, if `swap_size_mb` is set
 /// - Updating the system
+/// - Setting the timezone and enabling NTP synchronization, if `timezone` is set
 /// - Installing essential packages
+/// - Configuring log rotation
 /// - Setting up the firewall
+/// - Creating the administrator account, if `admin_user` and `admin_ssh_key` are set
 /// - Configuring SSH
 ///
-/// It creates a snapshot before starting the setup process for potential rollback.
+/// It creates a snapshot before starting the setup process for potential rollback. Once
+/// SSH is configured, the setup fails if the firewall blocks `ssh_port` (see
+/// `firewall::check_ssh_port_allowed`), so that the snapshot is rolled back instead of
+/// leaving the host unreachable.
 ///
 /// # Arguments
 ///
@@ -34,12 +52,11 @@ use std::fs;
 pub fn initial_setup(config: &Config, rollback: &RollbackManager) -> Result<(), Box<dyn Error>> {
     info!("Performing initial setup...");
 
-    let snapshot = rollback.create_snapshot()?;
+    let snapshot = rollback.create_snapshot("Initial setup")?;
 
-    update_system(config)?;
-    install_essential_packages(config)?;
-    setup_firewall(config)?;
-    setup_ssh()?;
+    Plan::build(|plan| plan_initial_setup(plan, config))?
+        .execute_with_rollback(rollback, snapshot)?;
+    firewall::check_ssh_port_allowed(config.ssh_port)?;
 
     rollback.commit_snapshot(snapshot)?;
 
@@ -47,9 +64,194 @@ pub fn initial_setup(config: &Config, rollback: &RollbackManager) -> Result<(),
     Ok(())
 }
 
+/// Plans the initial setup of the server: setting the hostname, creating a swap file,
+/// updating the system, configuring the time, installing essential packages, configuring log
+/// rotation, setting up the firewall, creating the administrator account and configuring SSH.
+///
+/// The administrator account is created before SSH is hardened, so that key-based login
+/// keeps working once password authentication is disabled.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `config` - A reference to the `Config` struct containing setup configuration
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the setup is planned successfully, or an error if the configuration
+/// is not supported, the hostname, timezone or administrator is invalid or a configuration
+/// file cannot be read.
+pub fn plan_initial_setup(plan: &mut Plan, config: &Config) -> Result<(), Box<dyn Error>> {
+    if let Some(hostname) = &config.hostname {
+        plan_hostname(plan, hostname)?;
+    }
+    if let Some(size_mb) = config.swap_size_mb {
+        plan_swap(plan, size_mb)?;
+    }
+    plan_update_system(plan, config)?;
+    if let Some(timezone) = &config.timezone {
+        plan_time(plan, timezone)?;
+    }
+    plan_essential_packages(plan, config)?;
+    plan_logrotate(plan, config);
+    plan_firewall(plan, config)?;
+    match (&config.admin_user, &config.admin_ssh_key) {
+        (Some(username), Some(ssh_public_key)) => plan_admin_user(plan, username, ssh_public_key)?,
+        (None, None) => {}
+        _ => return Err("admin_user and admin_ssh_key must be set together".into()),
+    }
+    plan_ssh(plan, config)?;
+    Ok(())
+}
+
+/// Sets the hostname of the server and maps it to `127.0.1.1` in `/etc/hosts`.
+///
+/// # Arguments
+///
+/// * `hostname` - The new hostname, which must be a valid RFC 1123 hostname
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the hostname is set successfully, or an error if it is invalid or a
+/// step fails.
+pub fn configure_hostname(hostname: &str) -> Result<(), Box<dyn Error>> {
+    Plan::build(|plan| plan_hostname(plan, hostname))?.execute()
+}
+
+/// Plans setting the hostname.
+///
+/// `/etc/hostname` is written before running `hostnamectl`, so that a rollback restores
+/// the original static hostname along with `/etc/hosts`. The existing `127.0.1.1` line of
+/// `/etc/hosts` is replaced, or one is added.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `hostname` - The new hostname
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the change is planned, or an error if the hostname is invalid or
+/// `/etc/hosts` cannot be read.
+pub fn plan_hostname(plan: &mut Plan, hostname: &str) -> Result<(), Box<dyn Error>> {
+    const HOSTS: &str = "/etc/hosts";
+
+    if !is_valid_hostname(hostname) {
+        return Err(format!("Invalid hostname: {}", hostname).into());
+    }
+
+    let short_name = hostname.split('.').next().unwrap_or(hostname);
+    let entry = if short_name == hostname {
+        format!("127.0.1.1\t{}", hostname)
+    } else {
+        format!("127.0.1.1\t{} {}", hostname, short_name)
+    };
+
+    let hosts = read_file(HOSTS)?;
+    let mut replaced = false;
+    let mut lines: Vec<&str> = hosts
+        .lines()
+        .map(|line| {
+            if line.split_whitespace().next() == Some("127.0.1.1") && !replaced {
+                replaced = true;
+                entry.as_str()
+            } else {
+                line
+            }
+        })
+        .collect();
+    if !replaced {
+        lines.push(&entry);
+    }
+    let updated_hosts = format!("{}\n", lines.join("\n"));
+
+    plan.write_file("/etc/hostname", format!("{}\n", hostname))
+        .run("hostnamectl", &["set-hostname", hostname]);
+    if updated_hosts != hosts {
+        plan.write_file(HOSTS, updated_hosts);
+    }
+    Ok(())
+}
+
+/// Checks that a hostname is valid according to RFC 1123: at most 253 characters of
+/// dot-separated labels, each 1 to 63 letters, digits or hyphens, not starting or ending
+/// with a hyphen.
+fn is_valid_hostname(hostname: &str) -> bool {
+    hostname.len() <= 253
+        && hostname.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Creates a swap file of the given size at `/swapfile`, enables it and adds it to
+/// `/etc/fstab` so it is used after a reboot.
+///
+/// Nothing is done if `/swapfile` already exists.
+///
+/// # Arguments
+///
+/// * `size_mb` - The size of the swap file, in megabytes
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the swap file is configured successfully, or an error if the size is
+/// zero or a step fails.
+pub fn configure_swap(size_mb: u64) -> Result<(), Box<dyn Error>> {
+    Plan::build(|plan| plan_swap(plan, size_mb))?.execute()
+}
+
+/// Plans creating and enabling a swap file.
+///
+/// The fstab entry is only added if `/etc/fstab` does not already mention the swap file.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `size_mb` - The size of the swap file, in megabytes
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the swap file is planned or already exists, or an error if the size
+/// is zero or `/etc/fstab` cannot be read.
+pub fn plan_swap(plan: &mut Plan, size_mb: u64) -> Result<(), Box<dyn Error>> {
+    const SWAP_FILE: &str = "/swapfile";
+    const FSTAB: &str = "/etc/fstab";
+
+    if size_mb == 0 {
+        return Err("Swap size must be greater than zero".into());
+    }
+    if path_exists(SWAP_FILE) {
+        info!("{} already exists, skipping swap configuration", SWAP_FILE);
+        return Ok(());
+    }
+
+    plan.run("fallocate", &["-l", &format!("{}M", size_mb), SWAP_FILE])
+        .run("chmod", &["600", SWAP_FILE])
+        .run("mkswap", &[SWAP_FILE])
+        .run("swapon", &[SWAP_FILE]);
+
+    let mut fstab = read_file(FSTAB)?;
+    let has_entry = fstab
+        .lines()
+        .any(|line| line.split_whitespace().next() == Some(SWAP_FILE));
+    if !has_entry {
+        if !fstab.is_empty() && !fstab.ends_with('\n') {
+            fstab.push('\n');
+        }
+        fstab.push_str(&format!("{} none swap sw 0 0\n", SWAP_FILE));
+        plan.write_file(FSTAB, fstab);
+    }
+    Ok(())
+}
+
 /// Updates the system using the appropriate package manager for the Linux distribution.
 ///
-/// This function runs system update commands specific to Ubuntu, CentOS, or Fedora.
+/// This function upgrades all packages with the package manager of Ubuntu (apt),
+/// CentOS (yum) or Fedora (dnf).
 ///
 /// # Arguments
 ///
@@ -59,72 +261,247 @@ pub fn initial_setup(config: &Config, rollback: &RollbackManager) -> Result<(),
 ///
 /// Returns `Ok(())` if the system is updated successfully, or an error if the update fails.
 pub fn update_system(config: &Config) -> Result<(), Box<dyn Error>> {
+    Plan::build(|plan| plan_update_system(plan, config))?.execute()
+}
+
+/// Plans updating the system.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `config` - A reference to the `Config` struct containing the Linux distribution information
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the update is planned, or an error if the distribution is not supported.
+pub fn plan_update_system(plan: &mut Plan, config: &Config) -> Result<(), Box<dyn Error>> {
     match config.linux_distro.as_str() {
-        "ubuntu" => {
-            run_command("apt", &["update"])?;
-            run_command("apt", &["upgrade", "-y"])?;
-        }
-        "centos" => {
-            run_command("yum", &["update", "-y"])?;
-        }
-        "fedora" => {
-            run_command("dnf", &["upgrade", "-y"])?;
+        "ubuntu" | "centos" | "fedora" => {
+            plan.upgrade_packages();
         }
         _ => return Err("Unsupported Linux distribution".into()),
     }
     Ok(())
 }
 
+/// Sets the system timezone and enables NTP time synchronization with chrony.
+///
+/// # Arguments
+///
+/// * `timezone` - The timezone name, as found under `/usr/share/zoneinfo` (e.g., "Europe/Berlin")
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the time is configured successfully, or an error if the timezone is
+/// unknown or a step fails.
+pub fn configure_time(timezone: &str) -> Result<(), Box<dyn Error>> {
+    Plan::build(|plan| plan_time(plan, timezone))?.execute()
+}
+
+/// Plans setting the timezone and enabling chrony.
+///
+/// The timezone is checked against `/usr/share/zoneinfo` on the host.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `timezone` - The timezone name
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the configuration is planned, or an error if the timezone is unknown
+/// or the package manager is not supported.
+pub fn plan_time(plan: &mut Plan, timezone: &str) -> Result<(), Box<dyn Error>> {
+    let valid_name = !timezone.is_empty()
+        && !timezone.starts_with('/')
+        && timezone
+            .split('/')
+            .all(|part| !part.is_empty() && part != "..")
+        && timezone
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/_-+".contains(c));
+    if !valid_name || !path_exists(format!("/usr/share/zoneinfo/{}", timezone)) {
+        return Err(format!("Unknown timezone: {}", timezone).into());
+    }
+
+    let chrony_service = service_name_for(&get_package_manager()?, "chrony");
+
+    plan.run("timedatectl", &["set-timezone", timezone])
+        .install(&["chrony"])
+        .enable_service(chrony_service)
+        .start_service(chrony_service);
+    Ok(())
+}
+
 /// Installs essential packages on the system.
 ///
-/// This function installs a predefined list of essential packages using
-/// the appropriate package manager for the Linux distribution.
+/// This function installs the packages listed in `essential_packages`, or the default
+/// list for the host's package manager, using the appropriate package manager for the
+/// Linux distribution.
 ///
 /// # Arguments
 ///
-/// * `config` - A reference to the `Config` struct containing the Linux distribution information
+/// * `config` - A reference to the `Config` struct containing the Linux distribution
+///   information and the essential packages
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` if all packages are installed successfully, or an error if installation fails.
 pub fn install_essential_packages(config: &Config) -> Result<(), Box<dyn Error>> {
-    let essential_packages = [
-        "curl",
-        "wget",
-        "vim",
-        "ufw",
-        "fail2ban",
-        "apt-listchanges",
-        "needrestart",
-        "debsums",
-        "apt-show-versions",
-    ];
+    Plan::build(|plan| plan_essential_packages(plan, config))?.execute()
+}
 
-    match config.linux_distro.as_str() {
-        "ubuntu" => {
-            for package in &essential_packages {
-                run_command("apt", &["install", "-y", package])?;
-            }
-        }
-        "centos" => {
-            for package in &essential_packages {
-                run_command("yum", &["install", "-y", package])?;
-            }
+/// Plans installing the essential packages.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `config` - A reference to the `Config` struct containing the Linux distribution
+///   information and the essential packages
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the installation is planned, or an error if the distribution or the
+/// package manager is not supported.
+pub fn plan_essential_packages(plan: &mut Plan, config: &Config) -> Result<(), Box<dyn Error>> {
+    if !matches!(config.linux_distro.as_str(), "ubuntu" | "centos" | "fedora") {
+        return Err("Unsupported Linux distribution".into());
+    }
+
+    match &config.essential_packages {
+        Some(packages) => {
+            let packages: Vec<&str> = packages.iter().map(String::as_str).collect();
+            plan.install(&packages);
         }
-        "fedora" => {
-            for package in &essential_packages {
-                run_command("dnf", &["install", "-y", package])?;
-            }
+        None => {
+            plan.install(default_essential_packages(&get_package_manager()?));
         }
-        _ => return Err("Unsupported Linux distribution".into()),
     }
     Ok(())
 }
 
+/// Returns the essential packages installed by default with a package manager.
+///
+/// Debian-based systems get ufw and the apt maintenance tools; Red Hat-based systems get
+/// firewalld and the `needs-restarting` utilities instead, with EPEL enabled first on
+/// yum-based systems, where fail2ban is not in the base repositories.
+///
+/// # Arguments
+///
+/// * `package_manager` - The package manager of the host
+pub fn default_essential_packages(package_manager: &PackageManager) -> &'static [&'static str] {
+    match package_manager {
+        PackageManager::Apt => &[
+            "curl",
+            "wget",
+            "vim",
+            "ufw",
+            "fail2ban",
+            "apt-listchanges",
+            "needrestart",
+            "debsums",
+            "apt-show-versions",
+        ],
+        PackageManager::Yum => &[
+            "epel-release",
+            "curl",
+            "wget",
+            "vim-enhanced",
+            "firewalld",
+            "fail2ban",
+            "yum-utils",
+        ],
+        PackageManager::Dnf => &[
+            "curl",
+            "wget",
+            "vim-enhanced",
+            "firewalld",
+            "fail2ban",
+            "dnf-utils",
+        ],
+    }
+}
+
+/// Configures logrotate for the log files written by server_forge and the jobs it schedules.
+///
+/// The configuration is written to `/etc/logrotate.d/server_forge`; see `logrotate_config`
+/// for the files it covers.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct describing the enabled features
+///
+/// # Returns
+///
+/// Returns `Ok(())` if log rotation is configured successfully, or an error if writing the
+/// configuration fails.
+pub fn configure_logrotate(config: &Config) -> Result<(), Box<dyn Error>> {
+    let mut plan = Plan::new();
+    plan_logrotate(&mut plan, config);
+    plan.execute()
+}
+
+/// Plans writing the logrotate configuration.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `config` - A reference to the `Config` struct describing the enabled features
+pub fn plan_logrotate(plan: &mut Plan, config: &Config) {
+    plan.write_file("/etc/logrotate.d/server_forge", logrotate_config(config));
+}
+
+/// Generates the logrotate configuration for server_forge's logs.
+///
+/// The setup logs (`/var/log/server_setup_*.log`) are always rotated, the restic log when
+/// backups are scheduled (`backup_frequency` is set) and the security scan log when scans
+/// are scheduled (`security_scan_schedule` is set). Logs are rotated weekly, or sooner once
+/// they exceed 50 MB, and four compressed generations are kept. `copytruncate` is used
+/// because the jobs write through shell redirections that keep the file open. Logs of the
+/// services that are installed from packages, such as nginx, are rotated by the
+/// configuration those packages ship.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct describing the enabled features
+///
+/// # Returns
+///
+/// Returns the contents of the logrotate configuration file.
+pub fn logrotate_config(config: &Config) -> String {
+    let mut paths = vec!["/var/log/server_setup_*.log"];
+    if !config.backup_frequency.is_empty() {
+        paths.push("/var/log/restic.log");
+    }
+    if !config.security_scan_schedule.is_empty() {
+        paths.push("/var/log/security_scan.log");
+    }
+
+    format!(
+        r#"{} {{
+    weekly
+    maxsize 50M
+    rotate 4
+    compress
+    delaycompress
+    missingok
+    notifempty
+    copytruncate
+}}
+"#,
+        paths.join(" ")
+    )
+}
+
 /// Sets up the firewall with basic rules and any custom rules specified in the configuration.
 ///
 /// This function configures either UFW (for Ubuntu) or firewalld (for CentOS/Fedora)
-/// with default deny incoming, allow outgoing policy, and opens ports for SSH and any custom rules.
+/// with default deny incoming, allow outgoing policy, and opens ports for SSH (both the
+/// default port and the one `setup_ssh` moves sshd to) and the rules returned by
+/// `firewall_rules`.
+///
+/// A firewall that is already active is left enabled as it is, and only the rules it does
+/// not allow yet are added (see `firewall::plan_rules`).
 ///
 /// # Arguments
 ///
@@ -134,57 +511,353 @@ pub fn install_essential_packages(config: &Config) -> Result<(), Box<dyn Error>>
 ///
 /// Returns `Ok(())` if the firewall is set up successfully, or an error if setup fails.
 pub fn setup_firewall(config: &Config) -> Result<(), Box<dyn Error>> {
-    match config.linux_distro.as_str() {
-        "ubuntu" => {
-            run_command("ufw", &["default", "deny", "incoming"])?;
-            run_command("ufw", &["default", "allow", "outgoing"])?;
-            run_command("ufw", &["allow", "OpenSSH"])?;
-            for rule in &config.custom_firewall_rules {
-                run_command("ufw", &["allow", rule])?;
-            }
-            run_command("ufw", &["enable"])?;
+    Plan::build(|plan| plan_firewall(plan, config))?.execute()
+}
+
+/// Plans setting up the firewall.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `config` - A reference to the `Config` struct containing firewall configuration and Linux distribution information
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the setup is planned, or an error if the distribution is not supported.
+pub fn plan_firewall(plan: &mut Plan, config: &Config) -> Result<(), Box<dyn Error>> {
+    let firewall = firewall::for_distro(&config.linux_distro)?;
+    let mut ports = vec![format!("{}/tcp", config.ssh_port)];
+    ports.extend(firewall_rules(config));
+    firewall::plan_rules(plan, firewall.as_ref(), &["ssh"], &ports)
+}
+
+/// Returns the port rules to open in the firewall.
+///
+/// These are the custom firewall rules from the configuration, plus the Grafana and
+/// Prometheus ports when monitoring is enabled and meant to be externally reachable
+/// (`expose_monitoring`), the HAProxy frontend ports when it is deployed (443 only
+/// with a `load_balancer_certificate`), and the RabbitMQ management port when RabbitMQ is
+/// deployed with `expose_rabbitmq_management`.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing firewall and monitoring settings
+///
+/// # Returns
+///
+/// Returns the rules in `port/protocol` form.
+pub fn firewall_rules(config: &Config) -> Vec<String> {
+    let mut rules = config.custom_firewall_rules.clone();
+    if config.monitoring && config.expose_monitoring {
+        rules.push(format!("{}/tcp", config.grafana_port));
+        rules.push(format!("{}/tcp", config.prometheus_port));
+    }
+    if config.deployed_apps.iter().any(|app| app == "haproxy") {
+        rules.push(String::from("80/tcp"));
+        if config.load_balancer_certificate.is_some() {
+            rules.push(String::from("443/tcp"));
         }
-        "centos" | "fedora" => {
-            run_command("systemctl", &["start", "firewalld"])?;
-            run_command("systemctl", &["enable", "firewalld"])?;
-            run_command(
-                "firewall-cmd",
-                &["--zone=public", "--add-service=ssh", "--permanent"],
-            )?;
-            for rule in &config.custom_firewall_rules {
-                run_command(
-                    "firewall-cmd",
-                    &["--zone=public", "--add-port=", rule, "--permanent"],
-                )?;
-            }
-            run_command("firewall-cmd", &["--reload"])?;
+    }
+    if config.expose_rabbitmq_management && config.deployed_apps.iter().any(|app| app == "rabbitmq")
+    {
+        rules.push(format!("{}/tcp", deployment::RABBITMQ_MANAGEMENT_PORT));
+    }
+    rules
+}
+
+/// Creates a non-root administrator who logs in with an SSH key and can use sudo.
+///
+/// The user is added to the `sudo` group (`wheel` on Red Hat based distributions), the key
+/// is added to `~/.ssh/authorized_keys` (mode 0600), and a sudoers drop-in grants sudo
+/// without a password, since the account is created without one. An existing user is
+/// added to the group and given the key.
+///
+/// # Arguments
+///
+/// * `username` - The name of the administrator
+/// * `ssh_public_key` - The public key the administrator logs in with
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the user is created successfully, or an error if the username or
+/// key is invalid or a step fails.
+pub fn create_admin_user(username: &str, ssh_public_key: &str) -> Result<(), Box<dyn Error>> {
+    Plan::build(|plan| plan_admin_user(plan, username, ssh_public_key))?.execute()
+}
+
+/// Plans creating the administrator account.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `username` - The name of the administrator
+/// * `ssh_public_key` - The public key the administrator logs in with
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the account is planned, or an error if the username or key is
+/// invalid, the package manager is not supported or `/etc/passwd` cannot be read.
+pub fn plan_admin_user(
+    plan: &mut Plan,
+    username: &str,
+    ssh_public_key: &str,
+) -> Result<(), Box<dyn Error>> {
+    if !is_valid_username(username) {
+        return Err(format!("Invalid username: {}", username).into());
+    }
+    let ssh_public_key = ssh_public_key.trim();
+    if !is_valid_ssh_public_key(ssh_public_key) {
+        return Err(format!("Invalid SSH public key for {}", username).into());
+    }
+
+    let admin_group = match get_package_manager()? {
+        PackageManager::Apt => "sudo",
+        PackageManager::Yum | PackageManager::Dnf => "wheel",
+    };
+
+    plan.install(&["sudo"]);
+    let home = match home_dir(&read_file("/etc/passwd")?, username) {
+        Some(home) => {
+            plan.run("usermod", &["-aG", admin_group, username]);
+            home
         }
-        _ => return Err("Unsupported Linux distribution".into()),
+        None => {
+            plan.run(
+                "useradd",
+                &["-m", "-s", "/bin/bash", "-G", admin_group, username],
+            );
+            format!("/home/{}", username)
+        }
+    };
+
+    let ssh_dir = format!("{}/.ssh", home);
+    let authorized_keys = format!("{}/authorized_keys", ssh_dir);
+    let mut keys = if path_exists(&authorized_keys) {
+        read_file(&authorized_keys)?
+    } else {
+        String::new()
+    };
+    if !keys.lines().any(|line| line.trim() == ssh_public_key) {
+        if !keys.is_empty() && !keys.ends_with('\n') {
+            keys.push('\n');
+        }
+        keys.push_str(ssh_public_key);
+        keys.push('\n');
     }
+
+    let owner = format!("{}:{}", username, username);
+    let sudoers = format!("/etc/sudoers.d/90-serverforge-{}", username);
+    plan.create_dir(ssh_dir.as_str())
+        .write_file(authorized_keys.as_str(), keys)
+        .run("chmod", &["700", &ssh_dir])
+        .run("chmod", &["600", &authorized_keys])
+        .run("chown", &["-R", &owner, &ssh_dir])
+        .write_file(
+            sudoers.as_str(),
+            format!("{} ALL=(ALL) NOPASSWD:ALL\n", username),
+        )
+        .run("chmod", &["440", &sudoers])
+        .run("visudo", &["-cf", &sudoers]);
     Ok(())
 }
 
+/// Checks that a username is a portable login name: a lowercase letter or underscore,
+/// followed by up to 31 lowercase letters, digits, underscores or hyphens.
+fn is_valid_username(username: &str) -> bool {
+    let mut chars = username.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_lowercase() || c == '_')
+        && username.len() <= 32
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+}
+
+/// Checks that a key looks like a single OpenSSH public key line (type, then base64 data).
+fn is_valid_ssh_public_key(key: &str) -> bool {
+    let mut fields = key.split_whitespace();
+    let key_type = fields.next().unwrap_or_default();
+    !key.contains('\n')
+        && ["ssh-", "ecdsa-", "sk-"]
+            .iter()
+            .any(|prefix| key_type.starts_with(prefix))
+        && fields.next().is_some()
+}
+
+/// Returns the home directory of a user, given the contents of `/etc/passwd`.
+fn home_dir(passwd: &str, username: &str) -> Option<String> {
+    passwd.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split(':').collect();
+        (fields.len() >= 6 && fields[0] == username).then(|| fields[5].to_string())
+    })
+}
+
 /// Configures SSH for improved security.
 ///
 /// This function modifies the SSH configuration to:
 /// - Disable root login
-/// - Disable password authentication (requiring key-based authentication)
-/// - Change the default SSH port (TODO: implement this securely)
+/// - Disable password authentication (requiring key-based authentication), but only if an
+///   administrator can log in with a key
+/// - Move sshd to `ssh_port`, the port `setup_firewall` opens
 ///
-/// After making changes, it restarts the SSH service to apply the new configuration.
+/// After making changes and passing the preflight checks described in `plan_ssh`, it
+/// restarts the SSH service to apply the new configuration. If a check fails, the original
+/// configuration is restored.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct; its `admin_user` is expected to have
+///   been created with `create_admin_user`
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` if SSH is configured successfully, or an error if configuration fails.
-pub fn setup_ssh() -> Result<(), Box<dyn Error>> {
+pub fn setup_ssh(config: &Config) -> Result<(), Box<dyn Error>> {
+    let rollback = RollbackManager::new();
+    let snapshot = rollback.create_snapshot("SSH configuration")?;
+
+    let plan = Plan::build(|plan| plan_ssh(plan, config))?;
+    if let Err(e) = plan.execute_with_rollback(&rollback, snapshot) {
+        rollback.rollback_to(snapshot)?;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Plans configuring SSH, based on the current SSH configuration of the host.
+///
+/// Password authentication is left alone unless the configuration has an administrator
+/// with an SSH key, or a member of the `sudo` or `wheel` group on the host has an
+/// `authorized_keys` file; otherwise disabling it could lock everyone out.
+///
+/// Before sshd is restarted the plan runs preflight checks, any of which aborts it: the
+/// administrator's `authorized_keys` must not be empty (when password authentication is
+/// disabled), the firewall must allow the new SSH port (when it is active and managed by
+/// `setup_firewall`), and `sshd -t` must accept the new configuration. The sshd
+/// configuration is then restored by rolling back.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `config` - A reference to the `Config` struct containing the administrator settings
+///   and Linux distribution
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the configuration is planned, or an error if the current SSH
+/// configuration cannot be read.
+pub fn plan_ssh(plan: &mut Plan, config: &Config) -> Result<(), Box<dyn Error>> {
     let ssh_config = "/etc/ssh/sshd_config";
-    let mut ssh_content = fs::read_to_string(ssh_config)?;
-    ssh_content = ssh_content
-        .replace("PermitRootLogin yes", "PermitRootLogin no")
-        .replace("#PasswordAuthentication yes", "PasswordAuthentication no")
-        .replace("#Port 22", "Port 2222"); //TODO: Change SSH port for better security
-    fs::write(ssh_config, ssh_content)?;
-
-    run_command("systemctl", &["restart", "sshd"])?;
+    let mut ssh_content = set_ssh_port(
+        &read_file(ssh_config)?.replace("PermitRootLogin yes", "PermitRootLogin no"),
+        config.ssh_port,
+    );
+    let admin_keys = admin_authorized_keys(config);
+    if admin_keys.is_some() {
+        ssh_content =
+            ssh_content.replace("#PasswordAuthentication yes", "PasswordAuthentication no");
+    } else {
+        warn!("No administrator with an SSH key found, leaving password authentication enabled");
+    }
+    let port = config.ssh_port;
+    plan.write_file(ssh_config, ssh_content);
+
+    if let Some(admin_keys) = admin_keys {
+        let quoted = shell_quote(&admin_keys);
+        plan.run(
+            "sh",
+            &[
+                "-c",
+                &format!(
+                    "grep -qvE '^[[:space:]]*(#|$)' {keys} || {{ echo 'No SSH key in '{keys} >&2; exit 1; }}",
+                    keys = quoted
+                ),
+            ],
+        );
+    }
+    if port != 22 {
+        let firewall_check = match config.linux_distro.as_str() {
+            "ubuntu" => Some(format!(
+                "! ufw status | grep -q '^Status: active' || ufw status | grep -Eq '^{port}(/tcp)? +ALLOW' \
+                 || {{ echo 'Port {port}/tcp is not allowed by ufw' >&2; exit 1; }}",
+                port = port
+            )),
+            "centos" | "fedora" => Some(format!(
+                "! firewall-cmd --state >/dev/null 2>&1 || firewall-cmd --query-port={port}/tcp >/dev/null \
+                 || {{ echo 'Port {port}/tcp is not allowed by firewalld' >&2; exit 1; }}",
+                port = port
+            )),
+            _ => None,
+        };
+        if let Some(firewall_check) = firewall_check {
+            plan.run("sh", &["-c", &firewall_check]);
+        }
+    }
+    plan.validate_config(ServiceType::Sshd, ssh_config)
+        .restart_service("sshd");
     Ok(())
 }
+
+/// Makes an sshd configuration listen on a single port.
+///
+/// The first `Port` directive, commented out or not, is replaced with `Port <port>` and
+/// the other uncommented ones are removed, since sshd listens on every port listed. The
+/// directive is appended if there is none.
+///
+/// # Arguments
+///
+/// * `ssh_config` - The contents of `sshd_config`
+/// * `port` - The port sshd should listen on
+///
+/// # Returns
+///
+/// Returns the updated configuration.
+pub fn set_ssh_port(ssh_config: &str, port: u16) -> String {
+    let directive = format!("Port {}", port);
+    let mut replaced = false;
+    let mut lines = Vec::new();
+    for line in ssh_config.lines() {
+        let mut fields = line.trim_start().trim_start_matches('#').split_whitespace();
+        let is_port = matches!(
+            (fields.next(), fields.next().map(str::parse::<u16>)),
+            (Some(keyword), Some(Ok(_))) if keyword.eq_ignore_ascii_case("Port")
+        );
+        if !is_port {
+            lines.push(line);
+        } else if !replaced {
+            lines.push(&directive);
+            replaced = true;
+        } else if line.trim_start().starts_with('#') {
+            lines.push(line);
+        }
+    }
+    if !replaced {
+        lines.push(&directive);
+    }
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    contents
+}
+
+/// Returns the `authorized_keys` file of an administrator who can log in with a key: the
+/// configured `admin_user`, or else a member of the `sudo` or `wheel` group on the host
+/// with a non-empty `authorized_keys` file.
+fn admin_authorized_keys(config: &Config) -> Option<String> {
+    let passwd = read_file("/etc/passwd").unwrap_or_default();
+    if let (Some(admin_user), Some(_)) = (&config.admin_user, &config.admin_ssh_key) {
+        let home = home_dir(&passwd, admin_user).unwrap_or_else(|| format!("/home/{}", admin_user));
+        return Some(format!("{}/.ssh/authorized_keys", home));
+    }
+
+    let group = read_file("/etc/group").ok()?;
+    group
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .filter(|fields| fields.len() == 4 && (fields[0] == "sudo" || fields[0] == "wheel"))
+        .flat_map(|fields| fields[3].split(',').map(str::to_string).collect::<Vec<_>>())
+        .filter_map(|member| home_dir(&passwd, &member))
+        .map(|home| format!("{}/.ssh/authorized_keys", home))
+        .find(|path| {
+            read_file(path).is_ok_and(|keys| {
+                keys.lines()
+                    .any(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            })
+        })
+}
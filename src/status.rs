@@ -0,0 +1,233 @@
+//! # Status Module
+//!
+//! This module reports what a host was set up with and whether it still matches: it loads
+//! the configuration saved by the last successful run and checks, without changing
+//! anything, that the firewall is active and that the services the configuration implies
+//! are running, enabled at boot, and were (re)started after the configuration was saved.
+
+use crate::config::Config;
+use crate::deployment;
+use crate::firewall;
+use crate::monitoring::blackbox_targets;
+use crate::plan::{Operation, Plan};
+use crate::rollback::ServiceState;
+use crate::runner::current_runner;
+use crate::updates::UpdateMechanism;
+use crate::utils::{command_output, load_saved_config, saved_config_path};
+use chrono::{Local, TimeZone};
+use std::error::Error;
+use std::fmt;
+
+/// The state of a service the configuration implies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceStatus {
+    /// The name of the systemd unit
+    pub name: String,
+    /// Whether the service is running and enabled at boot
+    pub state: ServiceState,
+    /// Whether the running service was started after the configuration was saved, or
+    /// `None` if it is not running
+    pub up_to_date: Option<bool>,
+}
+
+/// The state of a host compared with its saved configuration.
+#[derive(Clone)]
+pub struct HostStatus {
+    /// The name of the host
+    pub host: String,
+    /// The saved configuration
+    pub config: Config,
+    /// When the configuration was saved, as a Unix timestamp
+    pub saved_at: i64,
+    /// Whether the firewall is active, or `None` if neither ufw nor firewalld is installed
+    pub firewall_active: Option<bool>,
+    /// The services the configuration implies
+    pub services: Vec<ServiceStatus>,
+}
+
+impl HostStatus {
+    /// Returns a description of every check that failed.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        match self.firewall_active {
+            Some(true) => {}
+            Some(false) => problems.push(String::from("the firewall is inactive")),
+            None => problems.push(String::from("no firewall is installed")),
+        }
+        for service in &self.services {
+            if !service.state.active {
+                problems.push(format!("{} is not running", service.name));
+            }
+            if !service.state.enabled {
+                problems.push(format!("{} is not enabled at boot", service.name));
+            }
+            if service.up_to_date == Some(false) {
+                problems.push(format!(
+                    "{} was started before the configuration was saved",
+                    service.name
+                ));
+            }
+        }
+        problems
+    }
+}
+
+impl fmt::Display for HostStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let yes_no = |value: bool| if value { "yes" } else { "no" };
+        let saved_at = Local
+            .timestamp_opt(self.saved_at, 0)
+            .single()
+            .map_or_else(|| self.saved_at.to_string(), |time| time.to_rfc2822());
+
+        writeln!(f, "Host: {}", self.host)?;
+        writeln!(f, "Configuration saved: {}", saved_at)?;
+        writeln!(
+            f,
+            "Firewall: {}",
+            match self.firewall_active {
+                Some(true) => "active",
+                Some(false) => "inactive",
+                None => "not installed",
+            }
+        )?;
+        let width = self
+            .services
+            .iter()
+            .map(|service| service.name.len())
+            .max()
+            .unwrap_or(0)
+            .max("SERVICE".len());
+        writeln!(
+            f,
+            "{:<width$}  {:<7}  {:<7}  UP TO DATE",
+            "SERVICE",
+            "ACTIVE",
+            "ENABLED",
+            width = width
+        )?;
+        for service in &self.services {
+            writeln!(
+                f,
+                "{:<width$}  {:<7}  {:<7}  {}",
+                service.name,
+                yes_no(service.state.active),
+                yes_no(service.state.enabled),
+                service.up_to_date.map_or("-", yes_no),
+                width = width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks the host of the current `CommandRunner` against its saved configuration.
+///
+/// # Returns
+///
+/// Returns the status of the host, or `None` if no configuration was saved on it.
+///
+/// # Errors
+///
+/// Returns an error if the saved configuration cannot be read, the services it implies
+/// cannot be determined, or a check cannot be run.
+pub fn host_status() -> Result<Option<HostStatus>, Box<dyn Error>> {
+    let (Some(config), Some(saved_path)) = (load_saved_config()?, saved_config_path()) else {
+        return Ok(None);
+    };
+    let saved_at = unix_time(&command_output("stat", &["-c", "%Y", saved_path])?)?;
+
+    let mut services = Vec::new();
+    for name in expected_services(&config)? {
+        let state = ServiceState::query(&name)?;
+        let up_to_date = if state.active {
+            let started = command_output(
+                "systemctl",
+                &["show", "--property=ActiveEnterTimestamp", "--value", &name],
+            )?;
+            let started = unix_time(&command_output("date", &["-d", started.trim(), "+%s"])?)?;
+            Some(started >= saved_at)
+        } else {
+            None
+        };
+        services.push(ServiceStatus {
+            name,
+            state,
+            up_to_date,
+        });
+    }
+
+    Ok(Some(HostStatus {
+        host: current_runner().host().to_string(),
+        config,
+        saved_at,
+        firewall_active: firewall_active()?,
+        services,
+    }))
+}
+
+/// Returns the systemd units a configuration sets up, in setup order.
+///
+/// These are Fail2Ban, the automatic updates service of the package manager, the monitoring
+/// services when monitoring is enabled, and Docker or the services started by the planned
+/// application deployments.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct describing the host's setup
+///
+/// # Errors
+///
+/// Returns an error if the application deployments cannot be planned.
+pub fn expected_services(config: &Config) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut services = vec![String::from("fail2ban")];
+    if let Ok(mechanism) = UpdateMechanism::detect() {
+        services.push(mechanism.service().to_string());
+    }
+
+    if config.monitoring {
+        for service in ["prometheus", "grafana-server", "node_exporter"] {
+            services.push(service.to_string());
+        }
+        if !blackbox_targets(config).is_empty() {
+            services.push(String::from("blackbox_exporter"));
+        }
+        if config.enable_logs {
+            services.push(String::from("loki"));
+            services.push(String::from("promtail"));
+        }
+    }
+
+    if config.use_containers {
+        services.push(String::from("docker"));
+    } else {
+        let plan = Plan::build(|plan| deployment::plan_applications(plan, config))?;
+        for operation in plan.operations() {
+            if let Operation::StartService { name }
+            | Operation::EnableService { name }
+            | Operation::RestartService { name } = operation
+            {
+                if !services.contains(name) {
+                    services.push(name.clone());
+                }
+            }
+        }
+    }
+    Ok(services)
+}
+
+/// Checks whether the installed firewall is active.
+fn firewall_active() -> Result<Option<bool>, Box<dyn Error>> {
+    match firewall::installed() {
+        Some(firewall) => Ok(Some(firewall.allowed_rules()?.is_some())),
+        None => Ok(None),
+    }
+}
+
+/// Parses a Unix timestamp printed by a command.
+fn unix_time(output: &str) -> Result<i64, Box<dyn Error>> {
+    output
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid timestamp '{}': {}", output.trim(), e).into())
+}
@@ -0,0 +1,290 @@
+//! # Systemd Module
+//!
+//! This module provides helpers for generating and installing systemd unit files.
+//! It includes simple builders for service and timer units, a converter from cron
+//! schedules to systemd `OnCalendar=` expressions, and a function for installing
+//! a service + timer pair as an alternative to a cron job.
+
+use crate::plan::Plan;
+use crate::utils::shell_quote;
+use std::error::Error;
+
+/// Builder for a systemd `.service` unit file.
+#[derive(Debug, Clone)]
+pub struct ServiceUnit {
+    description: String,
+    exec_start: String,
+    user: Option<String>,
+    working_directory: Option<String>,
+    service_type: String,
+    wait_for_network: bool,
+    install: bool,
+}
+
+impl ServiceUnit {
+    /// Creates a new long-running (`Type=simple`) service unit that starts after the network
+    /// is online and is installed into `multi-user.target`.
+    ///
+    /// # Arguments
+    ///
+    /// * `description` - The human-readable description of the service
+    /// * `exec_start` - The command line to run
+    pub fn new(description: &str, exec_start: &str) -> Self {
+        ServiceUnit {
+            description: description.to_string(),
+            exec_start: exec_start.to_string(),
+            user: None,
+            working_directory: None,
+            service_type: String::from("simple"),
+            wait_for_network: true,
+            install: true,
+        }
+    }
+
+    /// Creates a `Type=oneshot` service unit, as used for jobs triggered by a timer.
+    ///
+    /// Oneshot units have no `[Install]` section since they are started by their timer.
+    pub fn oneshot(description: &str, exec_start: &str) -> Self {
+        ServiceUnit {
+            service_type: String::from("oneshot"),
+            wait_for_network: false,
+            install: false,
+            ..Self::new(description, exec_start)
+        }
+    }
+
+    /// Runs the service as the given user and group of the same name.
+    pub fn user(mut self, user: &str) -> Self {
+        self.user = Some(user.to_string());
+        self
+    }
+
+    /// Runs the service from the given directory.
+    pub fn working_directory(mut self, directory: &str) -> Self {
+        self.working_directory = Some(directory.to_string());
+        self
+    }
+
+    /// Renders the unit file contents.
+    pub fn render(&self) -> String {
+        let mut unit = String::from("[Unit]\n");
+        unit.push_str(&format!("Description={}\n", self.description));
+        if self.wait_for_network {
+            unit.push_str("Wants=network-online.target\nAfter=network-online.target\n");
+        }
+
+        unit.push_str("\n[Service]\n");
+        if let Some(user) = &self.user {
+            unit.push_str(&format!("User={}\nGroup={}\n", user, user));
+        }
+        if let Some(directory) = &self.working_directory {
+            unit.push_str(&format!("WorkingDirectory={}\n", directory));
+        }
+        unit.push_str(&format!("Type={}\n", self.service_type));
+        unit.push_str(&format!("ExecStart={}\n", self.exec_start));
+
+        if self.install {
+            unit.push_str("\n[Install]\nWantedBy=multi-user.target\n");
+        }
+        unit
+    }
+}
+
+/// Builder for a systemd `.timer` unit file.
+#[derive(Debug, Clone)]
+pub struct TimerUnit {
+    description: String,
+    on_calendar: String,
+}
+
+impl TimerUnit {
+    /// Creates a new persistent timer firing on the given `OnCalendar=` expression.
+    pub fn new(description: &str, on_calendar: &str) -> Self {
+        TimerUnit {
+            description: description.to_string(),
+            on_calendar: on_calendar.to_string(),
+        }
+    }
+
+    /// Renders the unit file contents.
+    pub fn render(&self) -> String {
+        format!(
+            "[Unit]\nDescription={}\n\n[Timer]\nOnCalendar={}\nPersistent=true\n\n[Install]\nWantedBy=timers.target\n",
+            self.description, self.on_calendar
+        )
+    }
+}
+
+/// Installs a oneshot service and a timer that triggers it, then enables the timer.
+///
+/// The units are written to `/etc/systemd/system/<name>.service` and `<name>.timer`.
+///
+/// # Arguments
+///
+/// * `name` - The unit name (without suffix)
+/// * `description` - The human-readable description of the job
+/// * `exec_start` - The command line the service runs
+/// * `on_calendar` - The systemd `OnCalendar=` expression for the timer
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the units are installed and enabled successfully, or an error otherwise.
+pub fn install_timer(
+    name: &str,
+    description: &str,
+    exec_start: &str,
+    on_calendar: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut plan = Plan::new();
+    plan_timer(&mut plan, name, description, exec_start, on_calendar);
+    plan.execute()
+}
+
+/// Plans installing a oneshot service and a timer that triggers it, as `install_timer` does.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `name` - The unit name (without suffix)
+/// * `description` - The human-readable description of the job
+/// * `exec_start` - The command line the service runs
+/// * `on_calendar` - The systemd `OnCalendar=` expression for the timer
+pub fn plan_timer(
+    plan: &mut Plan,
+    name: &str,
+    description: &str,
+    exec_start: &str,
+    on_calendar: &str,
+) {
+    let service = ServiceUnit::oneshot(description, exec_start);
+    let timer = TimerUnit::new(description, on_calendar);
+    let timer_name = format!("{}.timer", name);
+
+    plan.write_file(
+        format!("/etc/systemd/system/{}.service", name),
+        service.render(),
+    )
+    .write_file(
+        format!("/etc/systemd/system/{}", timer_name),
+        timer.render(),
+    )
+    .run("systemctl", &["daemon-reload"])
+    .enable_service(&timer_name)
+    .start_service(&timer_name);
+}
+
+/// Returns the `ExecStart=` command line running a shell command with `/bin/sh -c`.
+///
+/// The command is quoted with `shell_quote` once the characters systemd would otherwise
+/// interpret are escaped: `%` specifiers, `$` variables and backslashes.
+///
+/// # Arguments
+///
+/// * `command` - The shell command to run
+pub fn shell_exec_start(command: &str) -> String {
+    let escaped = command
+        .replace('\\', "\\\\")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    format!("/bin/sh -c {}", shell_quote(&escaped))
+}
+
+/// Converts a five-field cron schedule into a systemd `OnCalendar=` expression.
+///
+/// Supports `*`, numbers, comma-separated lists, ranges (`1-5`) and steps (`*/15`).
+/// For example, `"0 2 * * 0"` becomes `"Sun *-*-* 02:00:00"`.
+///
+/// When both the day of the month and the day of the week are restricted, cron runs the job
+/// on the days matching either, while `OnCalendar=` requires both to match, so such
+/// schedules are rejected.
+///
+/// # Arguments
+///
+/// * `cron` - A string slice containing the cron time fields
+///
+/// # Returns
+///
+/// Returns the `OnCalendar=` expression, or an error if the schedule cannot be converted.
+pub fn cron_to_on_calendar(cron: &str) -> Result<String, Box<dyn Error>> {
+    let fields: Vec<&str> = cron.split_whitespace().collect();
+    if fields.len() != 5 {
+        return Err(format!("Invalid cron schedule '{}': expected five fields", cron).into());
+    }
+
+    if !fields[2].starts_with('*') && !fields[4].starts_with('*') {
+        return Err(format!(
+            "Unsupported cron schedule '{}': the day of the month and the day of the week \
+             cannot both be restricted",
+            cron
+        )
+        .into());
+    }
+
+    let minute = convert_cron_field(fields[0], 0, cron)?;
+    let hour = convert_cron_field(fields[1], 0, cron)?;
+    let day = convert_cron_field(fields[2], 1, cron)?;
+    let month = convert_cron_field(fields[3], 1, cron)?;
+    let weekday = convert_cron_weekday(fields[4], cron)?;
+
+    let date_time = format!("*-{}-{} {}:{}:00", month, day, hour, minute);
+    Ok(match weekday {
+        Some(weekday) => format!("{} {}", weekday, date_time),
+        None => date_time,
+    })
+}
+
+/// Converts a single numeric cron field into its `OnCalendar=` form.
+fn convert_cron_field(field: &str, step_start: u32, cron: &str) -> Result<String, Box<dyn Error>> {
+    let invalid = || format!("Unsupported cron field '{}' in '{}'", field, cron);
+
+    field
+        .split(',')
+        .map(|part| {
+            if part == "*" {
+                Ok(String::from("*"))
+            } else if let Some(step) = part.strip_prefix("*/") {
+                let step: u32 = step.parse().map_err(|_| invalid())?;
+                Ok(format!("{:02}/{}", step_start, step))
+            } else if let Some((start, end)) = part.split_once('-') {
+                let start: u32 = start.parse().map_err(|_| invalid())?;
+                let end: u32 = end.parse().map_err(|_| invalid())?;
+                Ok(format!("{:02}..{:02}", start, end))
+            } else {
+                let value: u32 = part.parse().map_err(|_| invalid())?;
+                Ok(format!("{:02}", value))
+            }
+        })
+        .collect::<Result<Vec<_>, String>>()
+        .map(|parts| parts.join(","))
+        .map_err(|e| e.into())
+}
+
+/// Converts the cron day-of-week field into systemd weekday names.
+///
+/// Returns `None` when the field is `*` (every day).
+fn convert_cron_weekday(field: &str, cron: &str) -> Result<Option<String>, Box<dyn Error>> {
+    const WEEKDAYS: [&str; 8] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+    if field == "*" {
+        return Ok(None);
+    }
+
+    let invalid = || format!("Unsupported cron day-of-week '{}' in '{}'", field, cron);
+    let name = |value: &str| -> Result<&str, String> {
+        value
+            .parse::<usize>()
+            .ok()
+            .and_then(|day| WEEKDAYS.get(day).copied())
+            .ok_or_else(invalid)
+    };
+
+    field
+        .split(',')
+        .map(|part| match part.split_once('-') {
+            Some((start, end)) => Ok(format!("{}..{}", name(start)?, name(end)?)),
+            None => name(part).map(String::from),
+        })
+        .collect::<Result<Vec<_>, String>>()
+        .map(|parts| Some(parts.join(",")))
+        .map_err(|e| e.into())
+}
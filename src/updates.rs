@@ -1,26 +1,98 @@
 //! # Updates Module
 //!
 //! This module provides functionality for setting up and configuring automatic updates
-//! on Linux servers. It supports different update mechanisms for Ubuntu, CentOS, and Fedora,
+//! on Linux servers. The update mechanism follows the package manager of the system,
 //! ensuring that the server stays up-to-date with the latest security patches and software versions.
 //!
-//! The module includes functions for configuring unattended-upgrades on Ubuntu,
-//! yum-cron on CentOS, and dnf-automatic on Fedora.
+//! The module includes functions for configuring unattended-upgrades on APT systems,
+//! yum-cron on YUM systems (e.g. CentOS 7), and dnf-automatic on DNF systems (e.g. Fedora,
+//! CentOS Stream, RHEL 8 and later).
 use crate::config::Config;
+use crate::distro::{
+    enable_and_start_service, get_package_manager, install_package_tracked, PackageManager,
+};
+use crate::maintenance;
 use crate::rollback::RollbackManager;
-use crate::utils::run_command;
-use log::info;
+use crate::utils::{create_dir_all, path_exists, read_file, run_command, write_file};
+use log::{info, warn};
 use std::error::Error;
 
-/// Sets up automatic updates based on the Linux distribution specified in the configuration.
+/// The schedules automatic updates can run on.
+const UPDATE_SCHEDULES: [&str; 3] = ["daily", "weekly", "monthly"];
+
+/// The script yum-cron installs into `/etc/cron.daily` to apply updates.
+const YUM_CRON_SCRIPT: &str = "0yum-daily.cron";
+
+/// The drop-in overriding when the dnf-automatic timer fires.
+const DNF_AUTOMATIC_TIMER_OVERRIDE: &str =
+    "/etc/systemd/system/dnf-automatic.timer.d/serverforge-schedule.conf";
+
+/// The mechanisms automatic updates are applied with.
+///
+/// The mechanism follows the package manager rather than the distribution, so that every
+/// distribution sharing a package manager (e.g., Debian and Ubuntu, or Rocky and Alma
+/// Linux) is set up the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateMechanism {
+    /// unattended-upgrades, on APT systems
+    UnattendedUpgrades,
+    /// yum-cron, on YUM systems
+    YumCron,
+    /// dnf-automatic, on DNF systems
+    DnfAutomatic,
+}
+
+impl UpdateMechanism {
+    /// Returns the mechanism used on systems with a package manager.
+    ///
+    /// # Arguments
+    ///
+    /// * `package_manager` - A reference to the `PackageManager` of the system
+    pub fn for_package_manager(package_manager: &PackageManager) -> Self {
+        match package_manager {
+            PackageManager::Apt => UpdateMechanism::UnattendedUpgrades,
+            PackageManager::Yum => UpdateMechanism::YumCron,
+            PackageManager::Dnf => UpdateMechanism::DnfAutomatic,
+        }
+    }
+
+    /// Returns the mechanism used on the host of the current `CommandRunner`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the host's package manager is not supported.
+    pub fn detect() -> Result<Self, Box<dyn Error>> {
+        Ok(Self::for_package_manager(&get_package_manager()?))
+    }
+
+    /// Returns the package installing the mechanism.
+    pub fn package(&self) -> &'static str {
+        match self {
+            UpdateMechanism::UnattendedUpgrades => "unattended-upgrades",
+            UpdateMechanism::YumCron => "yum-cron",
+            UpdateMechanism::DnfAutomatic => "dnf-automatic",
+        }
+    }
+
+    /// Returns the systemd unit that applies the updates.
+    pub fn service(&self) -> &'static str {
+        match self {
+            UpdateMechanism::UnattendedUpgrades => "unattended-upgrades",
+            UpdateMechanism::YumCron => "yum-cron",
+            UpdateMechanism::DnfAutomatic => "dnf-automatic.timer",
+        }
+    }
+}
+
+/// Sets up automatic updates with the mechanism of the system's package manager.
 ///
-/// This function determines the appropriate update mechanism based on the Linux distribution
-/// and calls the corresponding setup function. It creates a snapshot before starting the setup
+/// This function determines the appropriate update mechanism based on the detected package
+/// manager and calls the corresponding setup function. It creates a snapshot before starting the setup
 /// process for potential rollback.
 ///
 /// # Arguments
 ///
-/// * `config` - A reference to the `Config` struct containing the Linux distribution information
+/// * `config` - A reference to the `Config` struct containing the update schedule
 /// * `rollback` - A reference to the `RollbackManager` for creating snapshots
 ///
 /// # Returns
@@ -32,13 +104,14 @@ pub fn setup_automatic_updates(
 ) -> Result<(), Box<dyn Error>> {
     info!("Setting up automatic updates...");
 
-    let snapshot = rollback.create_snapshot()?;
+    let snapshot = rollback.create_snapshot("Automatic updates")?;
 
-    match config.linux_distro.as_str() {
-        "ubuntu" => setup_ubuntu_updates(config)?,
-        "centos" => setup_centos_updates(config)?,
-        "fedora" => setup_fedora_updates(config)?,
-        _ => return Err("Unsupported Linux distribution".into()),
+    match UpdateMechanism::detect()? {
+        UpdateMechanism::UnattendedUpgrades => {
+            setup_unattended_upgrades(config, rollback, snapshot)?
+        }
+        UpdateMechanism::YumCron => setup_yum_cron_updates(config, rollback, snapshot)?,
+        UpdateMechanism::DnfAutomatic => setup_dnf_automatic_updates(config, rollback, snapshot)?,
     }
 
     rollback.commit_snapshot(snapshot)?;
@@ -47,32 +120,89 @@ pub fn setup_automatic_updates(
     Ok(())
 }
 
-/// Sets up automatic updates for Ubuntu using unattended-upgrades.
+/// Returns the number of days between automatic updates on a schedule.
 ///
-/// This function installs unattended-upgrades, configures it to automatically install
-/// security updates, and sets up the update schedule based on the configuration.
+/// # Arguments
+///
+/// * `schedule` - The update schedule ("daily", "weekly" or "monthly")
+///
+/// # Errors
+///
+/// Returns an error if the schedule is not one of these.
+pub fn update_interval_days(schedule: &str) -> Result<u32, Box<dyn Error>> {
+    match schedule {
+        "daily" => Ok(1),
+        "weekly" => Ok(7),
+        "monthly" => Ok(30),
+        _ => Err(format!(
+            "Invalid update_schedule '{}': expected {}",
+            schedule,
+            UPDATE_SCHEDULES.join(", ")
+        )
+        .into()),
+    }
+}
+
+/// Generates `/etc/apt/apt.conf.d/20auto-upgrades` for an update schedule.
+///
+/// APT's daily timers only refresh the package lists and run unattended-upgrades once the
+/// configured number of days has passed since the last run.
 ///
 /// # Arguments
 ///
-/// * `config` - A reference to the `Config` struct containing update schedule information
+/// * `schedule` - The update schedule ("daily", "weekly" or "monthly")
 ///
-/// # Returns
+/// # Errors
 ///
-/// Returns `Ok(())` if unattended-upgrades is set up successfully, or an error if setup fails.
-fn setup_ubuntu_updates(config: &Config) -> Result<(), Box<dyn Error>> {
-    run_command(
-        "apt",
-        &["install", "-y", "unattended-upgrades", "apt-listchanges"],
-    )?;
+/// Returns an error if the schedule is not recognized.
+pub fn generate_auto_upgrades_conf(schedule: &str) -> Result<String, Box<dyn Error>> {
+    let days = update_interval_days(schedule)?;
+    Ok(format!(
+        "APT::Periodic::Update-Package-Lists \"{}\";\nAPT::Periodic::Unattended-Upgrade \"{}\";\n",
+        days, days
+    ))
+}
 
-    let unattended_upgrades_conf = "/etc/apt/apt.conf.d/50unattended-upgrades";
-    let conf_content = r#"
+/// Checks that a value is a time of day such as "03:00", as accepted for `auto_reboot_time`.
+///
+/// # Arguments
+///
+/// * `value` - The value to check
+pub fn is_time_of_day(value: &str) -> bool {
+    let Some((hours, minutes)) = value.split_once(':') else {
+        return false;
+    };
+    let field = |field: &str, max: u32| {
+        field.len() == 2
+            && field.chars().all(|c| c.is_ascii_digit())
+            && field.parse::<u32>().is_ok_and(|n| n <= max)
+    };
+    field(hours, 23) && field(minutes, 59)
+}
+
+/// Generates `/etc/apt/apt.conf.d/50unattended-upgrades`.
+///
+/// The packages of `update_blacklist` are never upgraded. The server is only rebooted when
+/// `auto_reboot` is set, at the time `maintenance::reboot_time` gives if any and otherwise
+/// as soon as an update requires it.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the blacklist and reboot options
+pub fn generate_unattended_upgrades_conf(config: &Config) -> String {
+    let blacklist: String = config
+        .update_blacklist
+        .iter()
+        .map(|package| format!("    \"{}\";\n", package))
+        .collect();
+    let mut conf = String::from(
+        r#"
 Unattended-Upgrade::Allowed-Origins {
     "${distro_id}:${distro_codename}";
     "${distro_id}:${distro_codename}-security";
 };
 Unattended-Upgrade::Package-Blacklist {
-};
+{blacklist}};
 Unattended-Upgrade::AutoFixInterruptedDpkg "true";
 Unattended-Upgrade::MinimalSteps "true";
 Unattended-Upgrade::InstallOnShutdown "false";
@@ -80,78 +210,271 @@ Unattended-Upgrade::Mail "root";
 Unattended-Upgrade::MailReport "on-change";
 Unattended-Upgrade::Remove-Unused-Kernel-Packages "true";
 Unattended-Upgrade::Remove-Unused-Dependencies "true";
-Unattended-Upgrade::Automatic-Reboot "false";
-"#;
-    std::fs::write(unattended_upgrades_conf, conf_content)?;
+"#,
+    )
+    .replace("{blacklist}", &blacklist);
+    conf.push_str(&format!(
+        "Unattended-Upgrade::Automatic-Reboot \"{}\";\n",
+        config.auto_reboot
+    ));
+    if let (true, Some(time)) = (config.auto_reboot, maintenance::reboot_time(config)) {
+        conf.push_str(&format!(
+            "Unattended-Upgrade::Automatic-Reboot-Time \"{}\";\n",
+            time
+        ));
+    }
+    conf
+}
 
-    let auto_upgrades_conf = "/etc/apt/apt.conf.d/20auto-upgrades";
-    let auto_upgrades_content = match config.update_schedule.as_str() {
-        "daily" => {
-            "APT::Periodic::Update-Package-Lists \"1\";\nAPT::Periodic::Unattended-Upgrade \"1\";\n"
-        }
-        "weekly" => {
-            "APT::Periodic::Update-Package-Lists \"7\";\nAPT::Periodic::Unattended-Upgrade \"7\";\n"
+/// Configures the contents of `/etc/dnf/automatic.conf` to apply updates except to the
+/// packages of `update_blacklist`, and to reboot the server when they require it if
+/// `auto_reboot` is set.
+///
+/// With a reboot time (`auto_reboot_time`, or one derived from the maintenance window), the
+/// reboot is scheduled for that time instead of a few minutes after the updates.
+///
+/// # Arguments
+///
+/// * `contents` - The contents of the configuration installed with dnf-automatic
+/// * `config` - A reference to the `Config` struct containing the blacklist and reboot options
+pub fn configure_dnf_automatic(contents: &str, config: &Config) -> String {
+    let contents = configure_applied_updates(contents, config);
+    let reboot = if config.auto_reboot {
+        "when-needed"
+    } else {
+        "never"
+    };
+    let contents = set_conf_option(&contents, "commands", "reboot", reboot);
+    match (config.auto_reboot, maintenance::reboot_time(config)) {
+        (true, Some(time)) => set_conf_option(
+            &contents,
+            "commands",
+            "reboot_command",
+            &format!(
+                "\"shutdown -r {} 'Rebooting after applying package updates'\"",
+                time
+            ),
+        ),
+        _ => contents,
+    }
+}
+
+/// Configures the contents of `/etc/yum/yum-cron.conf` to apply updates except to the
+/// packages of `update_blacklist`.
+///
+/// # Arguments
+///
+/// * `contents` - The contents of the configuration installed with yum-cron
+/// * `config` - A reference to the `Config` struct containing the blacklist
+pub fn configure_yum_cron(contents: &str, config: &Config) -> String {
+    configure_applied_updates(contents, config)
+}
+
+/// Turns on applying updates in a yum-cron or dnf-automatic configuration, and excludes the
+/// blacklisted packages through the `[base]` section overriding the package manager's own
+/// configuration.
+fn configure_applied_updates(contents: &str, config: &Config) -> String {
+    let contents = contents.replace("apply_updates = no", "apply_updates = yes");
+    if config.update_blacklist.is_empty() {
+        return contents;
+    }
+    set_conf_option(
+        &contents,
+        "base",
+        "exclude",
+        &config.update_blacklist.join(" "),
+    )
+}
+
+/// Sets an option of an INI-style configuration, replacing its line (even if commented
+/// out) or adding it at the start of its section.
+fn set_conf_option(contents: &str, section: &str, key: &str, value: &str) -> String {
+    let line = format!("{} = {}", key, value);
+    let is_option = |candidate: &str| {
+        candidate
+            .trim_start_matches('#')
+            .trim()
+            .split_once('=')
+            .is_some_and(|(name, _)| name.trim() == key)
+    };
+
+    let header = format!("[{}]", section);
+    let mut lines: Vec<String> = contents.lines().map(String::from).collect();
+    let mut current = None;
+    let mut found = false;
+    for candidate in lines.iter_mut() {
+        if candidate.trim().starts_with('[') {
+            current = Some(candidate.trim().to_string());
+        } else if current.as_deref() == Some(header.as_str()) && is_option(candidate) {
+            *candidate = line.clone();
+            found = true;
+            break;
         }
-        _ => {
-            "APT::Periodic::Update-Package-Lists \"1\";\nAPT::Periodic::Unattended-Upgrade \"1\";\n"
+    }
+    if !found {
+        match lines
+            .iter()
+            .position(|candidate| candidate.trim() == header)
+        {
+            Some(index) => lines.insert(index + 1, line),
+            None => lines.extend([String::new(), header, line]),
         }
-    };
-    std::fs::write(auto_upgrades_conf, auto_upgrades_content)?;
+    }
+
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    contents
+}
+
+/// Generates the drop-in making the dnf-automatic timer fire on an update schedule instead
+/// of every day.
+///
+/// # Arguments
+///
+/// * `schedule` - The update schedule ("daily", "weekly" or "monthly")
+///
+/// # Errors
+///
+/// Returns an error if the schedule is not recognized.
+pub fn generate_dnf_automatic_timer_override(schedule: &str) -> Result<String, Box<dyn Error>> {
+    update_interval_days(schedule)?;
+    Ok(format!("[Timer]\nOnCalendar=\nOnCalendar={}\n", schedule))
+}
+
+/// Sets up automatic updates for APT systems using unattended-upgrades.
+///
+/// This function installs unattended-upgrades, configures it to automatically install
+/// security updates and to reboot if allowed, and sets up the update schedule based on the
+/// configuration.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing update schedule information
+/// * `rollback` - The `RollbackManager` to record the installed packages in
+/// * `snapshot` - The ID of the snapshot to record the installed packages in
+///
+/// # Returns
+///
+/// Returns `Ok(())` if unattended-upgrades is set up successfully, or an error if setup fails.
+fn setup_unattended_upgrades(
+    config: &Config,
+    rollback: &RollbackManager,
+    snapshot: usize,
+) -> Result<(), Box<dyn Error>> {
+    let mechanism = UpdateMechanism::UnattendedUpgrades;
+    for package in [mechanism.package(), "apt-listchanges"] {
+        install_package_tracked(&PackageManager::Apt, package, rollback, snapshot)?;
+    }
 
-    run_command("systemctl", &["enable", "unattended-upgrades"])?;
-    run_command("systemctl", &["start", "unattended-upgrades"])?;
+    let unattended_upgrades_conf = "/etc/apt/apt.conf.d/50unattended-upgrades";
+    write_file(
+        unattended_upgrades_conf,
+        generate_unattended_upgrades_conf(config),
+    )?;
+
+    let auto_upgrades_conf = "/etc/apt/apt.conf.d/20auto-upgrades";
+    write_file(
+        auto_upgrades_conf,
+        generate_auto_upgrades_conf(&config.update_schedule)?,
+    )?;
+
+    enable_and_start_service(mechanism.service())?;
 
     Ok(())
 }
 
-/// Sets up automatic updates for CentOS using yum-cron.
+/// Sets up automatic updates for YUM systems using yum-cron.
 ///
-/// This function installs yum-cron, configures it to automatically apply updates,
-/// and enables the yum-cron service.
+/// This function installs yum-cron, configures it to automatically apply updates except to
+/// blacklisted packages, and enables the yum-cron service. yum-cron runs from `/etc/cron.daily`, so its script is
+/// moved to `/etc/cron.weekly` or `/etc/cron.monthly` for the other schedules.
 ///
 /// # Arguments
 ///
-/// * `config` - A reference to the `Config` struct (unused in the current implementation)
+/// * `config` - A reference to the `Config` struct containing the update schedule
+/// * `rollback` - The `RollbackManager` to record the installed package in
+/// * `snapshot` - The ID of the snapshot to record the installed package in
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` if yum-cron is set up successfully, or an error if setup fails.
-fn setup_centos_updates(_config: &Config) -> Result<(), Box<dyn Error>> {
-    run_command("yum", &["install", "-y", "yum-cron"])?;
+fn setup_yum_cron_updates(
+    config: &Config,
+    rollback: &RollbackManager,
+    snapshot: usize,
+) -> Result<(), Box<dyn Error>> {
+    let mechanism = UpdateMechanism::YumCron;
+    install_package_tracked(
+        &PackageManager::Yum,
+        mechanism.package(),
+        rollback,
+        snapshot,
+    )?;
 
     let yum_cron_conf = "/etc/yum/yum-cron.conf";
-    let mut conf_content = std::fs::read_to_string(yum_cron_conf)?;
-    conf_content = conf_content.replace("apply_updates = no", "apply_updates = yes");
-    std::fs::write(yum_cron_conf, conf_content)?;
+    let conf_content = read_file(yum_cron_conf)?;
+    write_file(yum_cron_conf, configure_yum_cron(&conf_content, config))?;
+    if config.auto_reboot {
+        warn!("yum-cron cannot reboot the server after updates; auto_reboot is ignored");
+    }
+
+    update_interval_days(&config.update_schedule)?;
+    let script = format!("/etc/cron.{}/{}", config.update_schedule, YUM_CRON_SCRIPT);
+    for schedule in UPDATE_SCHEDULES {
+        let installed = format!("/etc/cron.{}/{}", schedule, YUM_CRON_SCRIPT);
+        if installed != script && path_exists(&installed) {
+            run_command("mv", &[&installed, &script])?;
+        }
+    }
 
-    run_command("systemctl", &["enable", "yum-cron"])?;
-    run_command("systemctl", &["start", "yum-cron"])?;
+    enable_and_start_service(mechanism.service())?;
 
     Ok(())
 }
 
-/// Sets up automatic updates for Fedora using dnf-automatic.
+/// Sets up automatic updates for DNF systems using dnf-automatic.
 ///
-/// This function installs dnf-automatic, configures it to automatically apply updates,
-/// and enables the dnf-automatic timer.
+/// DNF systems do not ship yum-cron, so this is used on every one of them, including
+/// CentOS Stream and RHEL 8 and later.
+///
+/// This function installs dnf-automatic, configures it to automatically apply updates
+/// and to reboot if allowed, makes its timer fire on the update schedule and enables the timer.
 ///
 /// # Arguments
 ///
-/// * `config` - A reference to the `Config` struct (unused in the current implementation)
+/// * `config` - A reference to the `Config` struct containing the update schedule
+/// * `rollback` - The `RollbackManager` to record the installed package in
+/// * `snapshot` - The ID of the snapshot to record the installed package in
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` if dnf-automatic is set up successfully, or an error if setup fails.
-fn setup_fedora_updates(_config: &Config) -> Result<(), Box<dyn Error>> {
-    run_command("dnf", &["install", "-y", "dnf-automatic"])?;
+fn setup_dnf_automatic_updates(
+    config: &Config,
+    rollback: &RollbackManager,
+    snapshot: usize,
+) -> Result<(), Box<dyn Error>> {
+    let mechanism = UpdateMechanism::DnfAutomatic;
+    install_package_tracked(
+        &PackageManager::Dnf,
+        mechanism.package(),
+        rollback,
+        snapshot,
+    )?;
 
     let dnf_automatic_conf = "/etc/dnf/automatic.conf";
-    let mut conf_content = std::fs::read_to_string(dnf_automatic_conf)?;
-    conf_content = conf_content.replace("apply_updates = no", "apply_updates = yes");
-    std::fs::write(dnf_automatic_conf, conf_content)?;
+    let conf_content = read_file(dnf_automatic_conf)?;
+    write_file(
+        dnf_automatic_conf,
+        configure_dnf_automatic(&conf_content, config),
+    )?;
+
+    let timer_override = generate_dnf_automatic_timer_override(&config.update_schedule)?;
+    create_dir_all("/etc/systemd/system/dnf-automatic.timer.d")?;
+    write_file(DNF_AUTOMATIC_TIMER_OVERRIDE, timer_override)?;
+    run_command("systemctl", &["daemon-reload"])?;
 
-    run_command("systemctl", &["enable", "dnf-automatic.timer"])?;
-    run_command("systemctl", &["start", "dnf-automatic.timer"])?;
+    enable_and_start_service(mechanism.service())?;
 
     Ok(())
 }
@@ -4,45 +4,135 @@
 //! and maintenance tool. It includes functions for logging, user input, configuration
 //! management, command execution, and report generation.
 
-use crate::config::Config;
+use crate::config::{Config, DeployHooks, GitApp, Scheduler, ServerRole};
+use crate::console::ConsoleAppender;
+use crate::distro::{get_installed_version, get_package_manager, PackageManager};
+use crate::encryption;
+use crate::filesystem::current_filesystem;
+use crate::pipeline::PhaseTiming;
+use crate::plan::Plan;
+use crate::progress;
+use crate::rollback::{InterruptedRun, Recovery};
+use crate::runner::current_runner;
 use chrono::Local;
-use log::{error, info};
+use log::{debug, error, info, warn};
+use std::cell::RefCell;
 use std::error::Error;
 use std::fs;
 use std::io::{self, Write};
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Environment variables set on every command spawned by `run_command`, so that package
+/// installations never stop at a debconf question.
+const NONINTERACTIVE_ENV: &[(&str, &str)] = &[("DEBIAN_FRONTEND", "noninteractive")];
+
+thread_local! {
+    /// Extra environment variables set on every command spawned by `run_command` on this
+    /// thread, so that hosts configured in parallel each use their own proxy.
+    static COMMAND_ENV: RefCell<Vec<(String, String)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The commands that take the package database lock, and are retried by `run_command`
+/// while another process holds it.
+const PACKAGE_MANAGERS: &[&str] = &["apt", "apt-get", "dpkg", "yum", "dnf", "rpm"];
+
+/// How long a package-manager command keeps being retried while the package database is
+/// locked, as unattended-upgrades does for several minutes on the first boot of a cloud
+/// instance.
+pub const PACKAGE_LOCK_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// The longest wait between two attempts of a package-manager command; the waits double
+/// from one second up to it.
+const PACKAGE_LOCK_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Where the apt proxy configuration is written.
+pub const APT_PROXY_CONF: &str = "/etc/apt/apt.conf.d/95serverforge-proxy";
 
 /// Sets up logging for the application.
 ///
-/// This function configures log4rs to write logs to a file in the /var/log directory.
-/// The log file name includes a timestamp to ensure uniqueness.
+/// This function configures log4rs to write logs, down to debug level, to a file in the
+/// /var/log directory.
+/// The log file name includes a timestamp to ensure uniqueness. Each line is prefixed
+/// with the `host` set in the logging MDC (`localhost` if unset), so that output from
+/// hosts configured concurrently can be told apart.
+///
+/// Logs are also written to standard output by a `console::ConsoleAppender`.
+///
+/// # Arguments
+///
+/// * `console_level` - The least severe level logged to standard output
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` if logging is set up successfully, or an error if setup fails.
-pub fn setup_logging() -> Result<(), Box<dyn Error>> {
+pub fn setup_logging(console_level: log::LevelFilter) -> Result<(), Box<dyn Error>> {
     let log_file = format!(
         "/var/log/server_setup_{}.log",
         Local::now().format("%Y%m%d_%H%M%S")
     );
     let file_appender = log4rs::append::file::FileAppender::builder()
         .encoder(Box::new(log4rs::encode::pattern::PatternEncoder::new(
-            "{d} - {l} - {m}\n",
+            "{d} - {l} - [{X(host)(localhost)}] {m}\n",
         )))
         .build(log_file)?;
 
     let config = log4rs::config::Config::builder()
         .appender(log4rs::config::Appender::builder().build("file", Box::new(file_appender)))
+        .appender(
+            log4rs::config::Appender::builder()
+                .filter(Box::new(log4rs::filter::threshold::ThresholdFilter::new(
+                    console_level,
+                )))
+                .build("console", Box::new(ConsoleAppender::new())),
+        )
         .build(
             log4rs::config::Root::builder()
                 .appender("file")
-                .build(log::LevelFilter::Info),
+                .appender("console")
+                .build(log::LevelFilter::Debug),
         )?;
 
     log4rs::init_config(config)?;
     Ok(())
 }
 
+/// Loads environment variables from a dotenv file, such as the `SERVER_FORGE_*` options
+/// and the secrets of local and development setups.
+///
+/// Variables already set in the environment are kept, so the real environment takes
+/// precedence over the file.
+///
+/// # Arguments
+///
+/// * `path` - The file to load, or `None` for a `.env` file in the current directory or
+///   one of its parents
+///
+/// # Returns
+///
+/// Returns the path of the file loaded, or `None` if no `path` was given and there is no
+/// `.env` file.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or parsed, or if `path` does not exist.
+pub fn load_env_file(path: Option<&str>) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    let loaded = match path {
+        Some(path) => dotenvy::from_path(path).map(|_| PathBuf::from(path)),
+        None => dotenvy::dotenv(),
+    };
+    match loaded {
+        Ok(path) => Ok(Some(path)),
+        Err(e) if path.is_none() && e.not_found() => Ok(None),
+        Err(e) => Err(format!(
+            "Failed to load environment file {}: {}",
+            path.unwrap_or(".env"),
+            e
+        )
+        .into()),
+    }
+}
+
 /// Prompts the user for input to configure the server setup.
 ///
 /// This function interactively asks the user for various configuration options
@@ -54,23 +144,61 @@ pub fn setup_logging() -> Result<(), Box<dyn Error>> {
 pub fn get_user_input() -> Result<Config, Box<dyn Error>> {
     let mut config = Config {
         linux_distro: prompt("Enter Linux distribution (ubuntu/centos/fedora): ")?,
-        server_role: prompt("Enter server role (web/database/application): ")?,
+        server_roles: prompt_server_roles()?,
         security_level: prompt("Enter desired security level (basic/intermediate/advanced): ")?,
+        security_scan_schedule: prompt_optional(
+            "Enter security scan schedule (daily/weekly/monthly or a cron expression, leave empty for weekly): ",
+        )?
+        .unwrap_or_else(|| String::from("weekly")),
+        enable_clamav: prompt("Enable ClamAV antivirus scanning? (y/n): ")?.to_lowercase() == "y",
         monitoring: prompt("Enable monitoring? (y/n): ")?.to_lowercase() == "y",
         backup_frequency: prompt("Enter backup frequency (hourly/daily/weekly): ")?,
         update_schedule: prompt("Enter update schedule (daily/weekly/monthly): ")?,
         use_containers: prompt("Use containerization? (y/n): ")?.to_lowercase() == "y",
+        scheduler: if prompt("Use systemd timers instead of cron for scheduled jobs? (y/n): ")?
+            .to_lowercase()
+            == "y"
+        {
+            Scheduler::SystemdTimer
+        } else {
+            Scheduler::Cron
+        },
         ..Default::default()
     };
 
+    config.admin_user =
+        prompt_optional("Enter a non-root admin user to create (leave empty to skip): ")?;
+    if let Some(admin_user) = &config.admin_user {
+        config.admin_ssh_key = Some(prompt(&format!(
+            "Enter the SSH public key for {}: ",
+            admin_user
+        ))?);
+    }
+    config.hostname = prompt_optional("Enter hostname (leave empty to keep the current one): ")?;
+    config.timezone = prompt_optional(
+        "Enter timezone, e.g. Europe/Berlin (leave empty to keep the current one): ",
+    )?;
+    config.swap_size_mb = prompt_optional("Enter swap file size in MB (leave empty to skip): ")?
+        .map(|size| size.parse())
+        .transpose()?;
+
     // config.linux_distro = prompt("Enter Linux distribution (ubuntu/centos/fedora): ")?;
-    // config.server_role = prompt("Enter server role (web/database/application): ")?;
+    // config.server_roles = prompt_server_roles()?;
     // config.security_level = prompt("Enter desired security level (basic/intermediate/advanced): ")?;
     // config.monitoring = prompt("Enable monitoring? (y/n): ")?.to_lowercase() == "y";
     // config.backup_frequency = prompt("Enter backup frequency (hourly/daily/weekly): ")?;
     // config.update_schedule = prompt("Enter update schedule (daily/weekly/monthly): ")?;
     // config.use_containers = prompt("Use containerization? (y/n): ")?.to_lowercase() == "y";
 
+    if config.monitoring {
+        config.enable_logs =
+            prompt("Enable centralized logging with Loki? (y/n): ")?.to_lowercase() == "y";
+        config.expose_monitoring =
+            prompt("Open the Grafana and Prometheus ports in the firewall? (y/n): ")?
+                .to_lowercase()
+                == "y";
+    }
+
     if config.use_containers {
         config.use_kubernetes = prompt("Use Kubernetes? (y/n): ")?.to_lowercase() == "y";
     }
@@ -81,12 +209,85 @@ pub fn get_user_input() -> Result<Config, Box<dyn Error>> {
         config.deployed_apps.push(app);
     }
 
+    let num_git_apps: usize = prompt("How many applications to deploy from Git? ")?.parse()?;
+    for i in 0..num_git_apps {
+        let n = i + 1;
+        config.git_apps.push(GitApp {
+            repo_url: prompt(&format!(
+                "Enter the repository URL of Git application #{}: ",
+                n
+            ))?,
+            branch: prompt_optional(&format!(
+                "Enter the branch of Git application #{} (leave empty for main): ",
+                n
+            ))?
+            .unwrap_or_else(|| String::from("main")),
+            target_dir: prompt(&format!(
+                "Enter the directory to deploy Git application #{} to: ",
+                n
+            ))?,
+            build_command: prompt_optional(&format!(
+                "Enter the build command of Git application #{} (leave empty for none): ",
+                n
+            ))?,
+            run_command: prompt(&format!(
+                "Enter the command that runs Git application #{}: ",
+                n
+            ))?,
+            hooks: DeployHooks::default(),
+        });
+    }
+
+    let deploys = |apps: &[&str]| {
+        config
+            .deployed_apps
+            .iter()
+            .any(|app| apps.contains(&app.as_str()))
+    };
+    if deploys(&["nginx", "apache"]) && deploys(&["nodejs", "python"]) {
+        config.app_domain = prompt_optional(
+            "Enter the domain to serve the application under (leave empty to skip the reverse proxy): ",
+        )?;
+    }
+
+    if config.deployed_apps.iter().any(|app| app == "haproxy") {
+        config.load_balancer_backends =
+            prompt("Enter load balancer backends (comma-separated host:port): ")?
+                .split(',')
+                .map(|backend| backend.trim().to_string())
+                .filter(|backend| !backend.is_empty())
+                .collect();
+        config.load_balancer_certificate = prompt_optional(
+            "Enter the PEM certificate path for HTTPS on the load balancer (leave empty for HTTP only): ",
+        )?;
+    }
+
+    if config.deployed_apps.iter().any(|app| app == "rabbitmq") {
+        config.expose_rabbitmq_management =
+            prompt("Open the RabbitMQ management port in the firewall? (y/n): ")?.to_lowercase()
+                == "y";
+    }
+
+    if let Some(excludes) =
+        prompt_optional("Enter backup exclude patterns (comma-separated, leave empty for none): ")?
+    {
+        config.backup_excludes = excludes
+            .split(',')
+            .map(|pattern| pattern.trim().to_string())
+            .collect();
+    }
+
     let num_rules: usize = prompt("How many custom firewall rules to add? ")?.parse()?;
     for i in 0..num_rules {
         let rule = prompt(&format!("Enter custom firewall rule #{}: ", i + 1))?;
         config.custom_firewall_rules.push(rule);
     }
 
+    config.admin_email =
+        prompt_optional("Enter admin email for notifications (leave empty to skip): ")?;
+    config.secrets.notification_webhook =
+        prompt_optional("Enter notification webhook URL (leave empty to skip): ")?;
+
     Ok(config)
 }
 
@@ -109,29 +310,182 @@ fn prompt(question: &str) -> Result<String, Box<dyn Error>> {
     Ok(input.trim().to_string())
 }
 
+/// Prompts the user with a question whose answer may be left empty.
+///
+/// # Arguments
+///
+/// * `question` - A string slice containing the question to ask the user
+///
+/// # Returns
+///
+/// Returns `Some` with the user's response, `None` if the response was empty, or an error if input fails.
+fn prompt_optional(question: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let answer = prompt(question)?;
+    Ok(if answer.is_empty() {
+        None
+    } else {
+        Some(answer)
+    })
+}
+
+/// Prompts the user for the roles of the server, separated by commas.
+///
+/// # Returns
+///
+/// Returns the roles, or an error if input fails or a role is unknown.
+fn prompt_server_roles() -> Result<Vec<ServerRole>, Box<dyn Error>> {
+    let answer = prompt("Enter server roles, separated by commas (web/database/application): ")?;
+    Ok(answer
+        .split(',')
+        .map(str::trim)
+        .filter(|role| !role.is_empty())
+        .map(str::parse)
+        .collect::<Result<_, _>>()?)
+}
+
+/// Asks the operator what to do with the uncommitted changes of an interrupted run, until
+/// a valid answer is given.
+///
+/// # Arguments
+///
+/// * `host` - The host the run was configuring
+/// * `run` - The interrupted run, with its pending snapshots
+///
+/// # Returns
+///
+/// Returns the chosen `Recovery`, or an error if input fails.
+pub fn prompt_recovery(host: &str, run: &InterruptedRun) -> Result<Recovery, Box<dyn Error>> {
+    println!(
+        "A previous run on {} was interrupted before completing (snapshots in {}):",
+        host, run.dir
+    );
+    for snapshot in &run.pending {
+        println!(
+            "  {} (started {}): {} file(s), {} package(s), {} service(s) changed",
+            snapshot.label,
+            snapshot.created_at.format("%Y-%m-%d %H:%M:%S"),
+            snapshot.files_changed,
+            snapshot.packages_installed,
+            snapshot.services_modified
+        );
+    }
+    loop {
+        match prompt("Roll back, commit or ignore these changes? [r/c/i]: ")?.parse() {
+            Ok(recovery) => return Ok(recovery),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+/// Quotes a string for safe use as a single word in a generated shell script.
+///
+/// # Arguments
+///
+/// * `value` - A string slice containing the value to quote
+///
+/// # Returns
+///
+/// Returns the value wrapped in single quotes, with embedded single quotes escaped.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// The file the setup report is written to by `generate_report`.
+pub const REPORT_PATH: &str = "/root/server_setup_report.txt";
+
+/// The packages of key components whose versions `generate_report` records, to audit drift
+/// between hosts.
+pub const REPORTED_PACKAGES: [&str; 3] = ["nginx", "mysql-server", "docker-ce"];
+
+/// The mail transfer agents `email_report` sends mail with, in order of preference.
+const MAIL_COMMANDS: [&str; 2] = ["/usr/sbin/sendmail", "/usr/bin/mail"];
+
+/// The file the configuration of the last run is saved to by `save_config`.
+pub const SAVED_CONFIG_PATH: &str = "/etc/server_setup_config.json";
+
+/// The file `save_config` saves the configuration to instead of `SAVED_CONFIG_PATH` when it
+/// is given a key.
+pub const ENCRYPTED_CONFIG_PATH: &str = "/etc/server_setup_config.json.enc";
+
+/// Returns the file the configuration of the last run was saved to, encrypted or not.
+///
+/// # Returns
+///
+/// Returns `ENCRYPTED_CONFIG_PATH` or `SAVED_CONFIG_PATH`, whichever exists, or `None` if no
+/// configuration was saved.
+pub fn saved_config_path() -> Option<&'static str> {
+    [ENCRYPTED_CONFIG_PATH, SAVED_CONFIG_PATH]
+        .into_iter()
+        .find(|path| path_exists(path))
+}
+
+/// Loads the configuration saved by the last run, if there is one.
+///
+/// An encrypted configuration is decrypted with the key of `encryption::config_key`.
+///
+/// # Returns
+///
+/// Returns the saved configuration, `None` if no configuration was saved, or an error if the
+/// saved file cannot be read, decrypted or parsed.
+pub fn load_saved_config() -> Result<Option<Config>, Box<dyn Error>> {
+    let Some(path) = saved_config_path() else {
+        return Ok(None);
+    };
+    let mut contents = current_filesystem().read(path)?;
+    if encryption::is_encrypted(&contents) {
+        let key = encryption::require_config_key(&format!("read {}", path))?;
+        contents = encryption::decrypt(&key, &contents)
+            .map_err(|e| format!("Cannot decrypt {}: {}", path, e))?;
+    }
+    Ok(Some(serde_json::from_slice(&contents).map_err(|e| {
+        format!("Invalid saved configuration {}: {}", path, e)
+    })?))
+}
+
 /// Saves the configuration to a JSON file.
 ///
-/// This function serializes the `Config` struct to JSON and saves it to /etc/server_setup_config.json.
+/// This function serializes the `Config` struct to JSON and saves it to `SAVED_CONFIG_PATH`,
+/// or encrypts it to `ENCRYPTED_CONFIG_PATH` when a key is given. The file saved by an
+/// earlier run in the other form is removed, so a plaintext copy never outlives the switch
+/// to encryption.
 ///
 /// # Arguments
 ///
 /// * `config` - A reference to the `Config` struct to be saved
+/// * `key` - The key to encrypt the configuration with, or `None` to save it in plaintext
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` if the config is saved successfully, or an error if saving fails.
-pub fn save_config(config: &Config) -> Result<(), Box<dyn Error>> {
-    let config_path = "/etc/server_setup_config.json";
+pub fn save_config(config: &Config, key: Option<&encryption::Key>) -> Result<(), Box<dyn Error>> {
     let config_json = serde_json::to_string_pretty(config)?;
-    fs::write(config_path, config_json)?;
-    info!("Configuration saved to {}", config_path);
+    let (path, stale, contents) = match key {
+        Some(key) => (
+            ENCRYPTED_CONFIG_PATH,
+            SAVED_CONFIG_PATH,
+            encryption::encrypt(key, config_json.as_bytes())?,
+        ),
+        None => (
+            SAVED_CONFIG_PATH,
+            ENCRYPTED_CONFIG_PATH,
+            config_json.into_bytes(),
+        ),
+    };
+    write_file(path, contents)?;
+    if path_exists(stale) {
+        run_command("rm", &["-f", stale])?;
+    }
+    info!("Configuration saved to {}", path);
     Ok(())
 }
 
 /// Executes a system command and logs the result.
 ///
 /// This function runs a command with the given arguments, logs the execution,
-/// and returns an error if the command fails.
+/// and returns an error if the command fails. The command runs through the current
+/// thread's `CommandRunner`, so it may execute on a remote host. Package managers (apt,
+/// dpkg, yum, dnf and rpm) are run again while another process holds the package database
+/// lock, for up to `PACKAGE_LOCK_TIMEOUT`.
 ///
 /// # Arguments
 ///
@@ -142,48 +496,778 @@ pub fn save_config(config: &Config) -> Result<(), Box<dyn Error>> {
 ///
 /// Returns `Ok(())` if the command executes successfully, or an error if execution fails.
 pub fn run_command(command: &str, args: &[&str]) -> Result<(), Box<dyn Error>> {
-    info!("Running command: {} {:?}", command, args);
-    let output = Command::new(command).args(args).output()?;
-    if !output.status.success() {
+    command_output(command, args).map(|_| ())
+}
+
+/// Executes a system command and returns its standard output.
+///
+/// This behaves like `run_command`, but captures the output of the command.
+///
+/// # Arguments
+///
+/// * `command` - A string slice containing the command to run
+/// * `args` - A slice of string slices containing the arguments for the command
+///
+/// # Returns
+///
+/// Returns the command's standard output, or an error if execution fails.
+pub fn command_output(command: &str, args: &[&str]) -> Result<String, Box<dyn Error>> {
+    execute_command(command, args, false, None)
+}
+
+/// Executes a system command, writing `input` to its standard input.
+///
+/// This behaves like `run_command`, but is meant for commands given a secret, such as a
+/// password: only the arguments are logged, and unlike them the input is not visible to
+/// other processes of the host.
+///
+/// # Arguments
+///
+/// * `command` - A string slice containing the command to run
+/// * `args` - A slice of string slices containing the arguments for the command
+/// * `input` - The text to write to the command's standard input
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the command executes successfully, or an error if execution fails.
+pub fn run_command_with_input(
+    command: &str,
+    args: &[&str],
+    input: &str,
+) -> Result<(), Box<dyn Error>> {
+    execute_command(command, args, false, Some(input.as_bytes())).map(|_| ())
+}
+
+/// Executes a long-running system command, logging its output as it is printed.
+///
+/// This behaves like `command_output`, but every line the command prints is logged at
+/// debug level and shown by the progress display as soon as it is printed, instead of
+/// being reported only when the command exits.
+///
+/// # Arguments
+///
+/// * `command` - A string slice containing the command to run
+/// * `args` - A slice of string slices containing the arguments for the command
+///
+/// # Returns
+///
+/// Returns the command's standard output, or an error if execution fails.
+pub fn run_command_streaming(command: &str, args: &[&str]) -> Result<String, Box<dyn Error>> {
+    execute_command(command, args, true, None)
+}
+
+/// Runs a command through the current `CommandRunner`, streaming its output if `streaming`
+/// is set and writing `input` to its standard input if given, and returns its standard
+/// output.
+///
+/// A package-manager command failing because another process holds the package database
+/// lock (see `is_package_lock_error`) is run again after a wait, until it gets the lock or
+/// `PACKAGE_LOCK_TIMEOUT` has been spent waiting.
+fn execute_command(
+    command: &str,
+    args: &[&str],
+    streaming: bool,
+    input: Option<&[u8]>,
+) -> Result<String, Box<dyn Error>> {
+    let runner = current_runner();
+    info!(
+        "Running command on {}: {} {:?}",
+        runner.host(),
+        command,
+        args
+    );
+    progress::command_started(command, args);
+    let mut env: Vec<(String, String)> = NONINTERACTIVE_ENV
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    env.extend(command_env());
+    let mut waited = Duration::ZERO;
+    let mut delay = Duration::from_secs(1);
+    let output = loop {
+        let output = if let Some(input) = input {
+            runner.run_with_input(command, args, &env, input)?
+        } else if streaming {
+            runner.run_streaming(command, args, &env, &mut |line| {
+                debug!("{}: {}", command, line);
+                progress::command_output_line(line);
+            })?
+        } else {
+            runner.run(command, args, &env)?
+        };
+        if output.success
+            || !PACKAGE_MANAGERS.contains(&command)
+            || !is_package_lock_error(&output.stderr)
+            || waited >= PACKAGE_LOCK_TIMEOUT
+        {
+            break output;
+        }
+        warn!(
+            "The package database is locked by another process, retrying {} in {}",
+            command,
+            format_duration(delay)
+        );
+        std::thread::sleep(delay);
+        waited += delay;
+        delay = (delay * 2).min(PACKAGE_LOCK_MAX_DELAY);
+    };
+    if !output.success {
         let error_message = format!(
             "Command failed: {} {:?}\nError: {}",
-            command,
-            args,
-            String::from_utf8_lossy(&output.stderr)
+            command, args, output.stderr
         );
         error!("{}", error_message);
         return Err(error_message.into());
     }
+    Ok(output.stdout)
+}
+
+/// Checks whether a package manager failed because another process, such as
+/// unattended-upgrades, holds the package database lock.
+///
+/// # Arguments
+///
+/// * `stderr` - The standard error of the failed apt, dpkg, yum, dnf or rpm command
+///
+/// # Returns
+///
+/// Returns `true` if the error reports a held lock.
+pub fn is_package_lock_error(stderr: &str) -> bool {
+    const LOCK_ERRORS: &[&str] = &[
+        // apt and dpkg
+        "Could not get lock",
+        "Unable to acquire the dpkg frontend lock",
+        "Unable to lock the administration directory",
+        "dpkg status database is locked by another process",
+        // yum, dnf and rpm
+        "Another app is currently holding the yum lock",
+        "Waiting for process with pid",
+        "Failed to obtain the transaction lock",
+        "can't create transaction lock",
+    ];
+    LOCK_ERRORS.iter().any(|error| stderr.contains(error))
+}
+
+/// Reads a file through the current thread's `FileSystem`.
+///
+/// # Arguments
+///
+/// * `path` - The path of the file to read
+///
+/// # Returns
+///
+/// Returns the file contents, or an error if the file cannot be read or is not valid UTF-8.
+pub fn read_file(path: impl AsRef<str>) -> Result<String, Box<dyn Error>> {
+    let contents = current_filesystem().read(path.as_ref())?;
+    Ok(String::from_utf8(contents)?)
+}
+
+/// Writes a file through the current thread's `FileSystem`, replacing any existing contents.
+///
+/// # Arguments
+///
+/// * `path` - The path of the file to write
+/// * `contents` - The contents to write
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the file is written successfully, or an error otherwise.
+pub fn write_file(path: impl AsRef<str>, contents: impl AsRef<[u8]>) -> Result<(), Box<dyn Error>> {
+    current_filesystem().write(path.as_ref(), contents.as_ref())
+}
+
+/// Creates a directory and its missing parents through the current thread's `FileSystem`.
+///
+/// # Arguments
+///
+/// * `path` - The path of the directory to create
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the directory exists afterwards, or an error otherwise.
+pub fn create_dir_all(path: impl AsRef<str>) -> Result<(), Box<dyn Error>> {
+    current_filesystem().create_dir_all(path.as_ref())
+}
+
+/// Returns whether a path exists, checked through the current thread's `FileSystem`.
+///
+/// # Arguments
+///
+/// * `path` - The path to check
+pub fn path_exists(path: impl AsRef<str>) -> bool {
+    current_filesystem().exists(path.as_ref())
+}
+
+/// Sets the permission bits of a file or directory through the current thread's `FileSystem`.
+///
+/// # Arguments
+///
+/// * `path` - The path of the file or directory
+/// * `mode` - The permission bits to set, such as `0o755`
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the permissions are set successfully, or an error otherwise.
+pub fn set_permissions(path: impl AsRef<str>, mode: u32) -> Result<(), Box<dyn Error>> {
+    current_filesystem().set_permissions(path.as_ref(), mode)
+}
+
+/// Configures the HTTP(S) proxy for all network-touching commands.
+///
+/// The variables returned by `proxy_env` are set on every command spawned by
+/// `run_command` on the current thread from now on, and on apt-based systems
+/// `APT_PROXY_CONF` is written so that apt uses the proxy as well. If no proxy is
+/// configured, the commands run without one, even if a previous run on the thread set it.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the proxy settings
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the proxy is configured successfully, or an error otherwise.
+pub fn configure_proxy(config: &Config) -> Result<(), Box<dyn Error>> {
+    let env = proxy_env(config);
+    let has_proxy = !env.is_empty();
+    COMMAND_ENV.with(|current| *current.borrow_mut() = env);
+    if !has_proxy {
+        return Ok(());
+    }
+
+    info!("Routing network access through the configured proxy");
+
+    if let PackageManager::Apt = get_package_manager()? {
+        write_file(APT_PROXY_CONF, generate_apt_proxy_conf(config))?;
+    }
+
     Ok(())
 }
 
+/// Returns the extra environment variables set on the commands of the current thread.
+pub fn command_env() -> Vec<(String, String)> {
+    COMMAND_ENV.with(|current| current.borrow().clone())
+}
+
+/// Runs `f` with `env` as the extra environment of the current thread's commands,
+/// restoring the previous one afterwards.
+///
+/// # Arguments
+///
+/// * `env` - The variable name/value pairs to set while `f` runs
+/// * `f` - The function to run
+///
+/// # Returns
+///
+/// Returns the result of `f`.
+pub fn with_command_env<T>(env: Vec<(String, String)>, f: impl FnOnce() -> T) -> T {
+    /// Restores the previous environment when dropped, even if `f` panics.
+    struct Restore(Vec<(String, String)>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            COMMAND_ENV.with(|current| *current.borrow_mut() = std::mem::take(&mut self.0));
+        }
+    }
+
+    let _restore = Restore(COMMAND_ENV.with(|current| current.replace(env)));
+    f()
+}
+
+/// Returns the proxy environment variables for the configured `http_proxy`/`https_proxy`.
+///
+/// Both the lower- and upper-case forms are set since tools disagree on which they read,
+/// and local addresses are excluded via `no_proxy` so that local services stay reachable.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the proxy settings
+///
+/// # Returns
+///
+/// Returns the variable name/value pairs, or an empty list if no proxy is configured.
+pub fn proxy_env(config: &Config) -> Vec<(String, String)> {
+    let mut env = Vec::new();
+    for (name, proxy) in [
+        ("http_proxy", &config.http_proxy),
+        ("https_proxy", &config.https_proxy),
+    ] {
+        if let Some(proxy) = proxy {
+            env.push((name.to_string(), proxy.clone()));
+            env.push((name.to_uppercase(), proxy.clone()));
+        }
+    }
+
+    if !env.is_empty() {
+        let no_proxy = String::from("localhost,127.0.0.1,::1");
+        env.push((String::from("NO_PROXY"), no_proxy.clone()));
+        env.push((String::from("no_proxy"), no_proxy));
+    }
+    env
+}
+
+/// Generates the apt configuration for the configured proxy.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the proxy settings
+///
+/// # Returns
+///
+/// Returns the contents of `APT_PROXY_CONF`.
+pub fn generate_apt_proxy_conf(config: &Config) -> String {
+    let mut conf = String::new();
+    if let Some(proxy) = &config.http_proxy {
+        conf.push_str(&format!("Acquire::http::Proxy \"{}\";\n", proxy));
+    }
+    if let Some(proxy) = &config.https_proxy {
+        conf.push_str(&format!("Acquire::https::Proxy \"{}\";\n", proxy));
+    }
+    conf
+}
+
+/// Rewrites a download URL to point at the configured mirror.
+///
+/// When `download_base_url` is set, the scheme is replaced by the mirror, keeping the
+/// original host in the path so artifacts from different sites don't collide, e.g.
+/// `https://github.com/prometheus/...` becomes `<download_base_url>/github.com/prometheus/...`.
+/// Without a mirror the URL is returned unchanged.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the mirror settings
+/// * `url` - The upstream download URL
+///
+/// # Returns
+///
+/// Returns the URL to download from.
+pub fn mirror_url(config: &Config, url: &str) -> String {
+    match &config.download_base_url {
+        Some(base) => {
+            let path = url.split_once("://").map_or(url, |(_, path)| path);
+            format!("{}/{}", base.trim_end_matches('/'), path)
+        }
+        None => url.to_string(),
+    }
+}
+
+/// Downloads a file, preferring a local copy or the configured mirror.
+///
+/// If the local `local_artifacts_dir` contains a file with the same name as the last segment
+/// of `url`, it is copied to `destination` (on the host being configured) and nothing is
+/// downloaded. Otherwise the file is fetched with `wget` from the URL returned by `mirror_url`.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the mirror settings
+/// * `url` - The upstream download URL
+/// * `destination` - The path to save the file to
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the file is in place, or an error if copying or downloading fails.
+pub fn download(config: &Config, url: &str, destination: &str) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = &config.local_artifacts_dir {
+        let file_name = url.rsplit('/').next().unwrap_or(url);
+        let local = Path::new(dir).join(file_name);
+        if local.is_file() {
+            info!("Using local artifact {}", local.display());
+            write_file(destination, fs::read(&local)?)?;
+            return Ok(());
+        }
+    }
+
+    run_command("wget", &["-q", "-O", destination, &mirror_url(config, url)])
+}
+
+/// Returns the architecture suffix used by release downloads for the host being configured.
+///
+/// Maps the output of `uname -m` to the naming used by most Linux release archives
+/// (e.g. `prometheus-2.30.3.linux-arm64.tar.gz`).
+///
+/// # Returns
+///
+/// Returns `amd64`, `arm64` or `armv7`, or an error if the architecture is not supported.
+pub fn target_arch_suffix() -> Result<&'static str, Box<dyn Error>> {
+    arch_suffix(command_output("uname", &["-m"])?.trim())
+}
+
+/// Maps a machine architecture name to its release download suffix.
+///
+/// # Arguments
+///
+/// * `arch` - The architecture name, as reported by `uname -m` or `std::env::consts::ARCH`
+///
+/// # Returns
+///
+/// Returns the download suffix, or an error if the architecture is not supported.
+pub fn arch_suffix(arch: &str) -> Result<&'static str, Box<dyn Error>> {
+    match arch {
+        "x86_64" => Ok("amd64"),
+        "aarch64" | "arm64" => Ok("arm64"),
+        "arm" | "armv7l" => Ok("armv7"),
+        _ => Err(format!(
+            "Unsupported CPU architecture for binary downloads: {}",
+            arch
+        )
+        .into()),
+    }
+}
+
+/// Returns the total memory of the host being configured, in megabytes.
+///
+/// # Returns
+///
+/// Returns the `MemTotal` of `/proc/meminfo`, or an error if it cannot be read or parsed.
+pub fn total_memory_mb() -> Result<u64, Box<dyn Error>> {
+    let meminfo = read_file("/proc/meminfo")?;
+    let total_kb = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|value| value.split_whitespace().next())
+        .and_then(|kb| kb.parse::<u64>().ok())
+        .ok_or("MemTotal not found in /proc/meminfo")?;
+    Ok(total_kb / 1024)
+}
+
+/// The path whose filesystem `check_resources` checks the free space of, where packages
+/// and services keep their data.
+pub const RESOURCE_CHECK_PATH: &str = "/var/lib";
+
+/// Checks that the host being configured has enough memory, disk space and CPUs.
+///
+/// Memory is the `MemTotal` of `/proc/meminfo`, disk space is the space available to
+/// unprivileged users on the filesystem of `RESOURCE_CHECK_PATH` (as reported by `df`), and
+/// CPUs are counted with `nproc`.
+///
+/// # Arguments
+///
+/// * `min_memory_mb` - The least total memory, in megabytes
+/// * `min_disk_mb` - The least available disk space, in megabytes
+/// * `min_cpus` - The least number of CPUs
+///
+/// # Errors
+///
+/// Returns an error listing every resource below its minimum, or if the resources cannot
+/// be read.
+pub fn check_resources(
+    min_memory_mb: u64,
+    min_disk_mb: u64,
+    min_cpus: u64,
+) -> Result<(), Box<dyn Error>> {
+    let memory_mb = total_memory_mb()?;
+    let disk_mb = available_disk_mb(RESOURCE_CHECK_PATH)?;
+    let cpus: u64 = command_output("nproc", &[])?
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid nproc output: {}", e))?;
+
+    let mut shortfalls = Vec::new();
+    if memory_mb < min_memory_mb {
+        shortfalls.push(format!(
+            "{} MB of memory (at least {} MB required)",
+            memory_mb, min_memory_mb
+        ));
+    }
+    if disk_mb < min_disk_mb {
+        shortfalls.push(format!(
+            "{} MB of free disk space on {} (at least {} MB required)",
+            disk_mb, RESOURCE_CHECK_PATH, min_disk_mb
+        ));
+    }
+    if cpus < min_cpus {
+        shortfalls.push(format!("{} CPUs (at least {} required)", cpus, min_cpus));
+    }
+    if !shortfalls.is_empty() {
+        return Err(format!(
+            "Insufficient resources on {}: {}",
+            current_runner().host(),
+            shortfalls.join(", ")
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Returns the space available to unprivileged users on the filesystem of `path`, in megabytes.
+fn available_disk_mb(path: &str) -> Result<u64, Box<dyn Error>> {
+    let output = command_output("df", &["-Pm", path])?;
+    output
+        .lines()
+        .nth(1)
+        .and_then(|line| line.split_whitespace().nth(3))
+        .and_then(|available| available.parse().ok())
+        .ok_or_else(|| format!("Cannot read the free disk space of {} from df", path).into())
+}
+
+/// Checks whether nothing listens on a TCP port of the host of the current `CommandRunner`.
+///
+/// # Arguments
+///
+/// * `port` - The port to check
+///
+/// # Errors
+///
+/// Returns an error if the listening sockets cannot be listed with `ss`.
+pub fn is_port_free(port: u16) -> Result<bool, Box<dyn Error>> {
+    Ok(port_user(port)?.is_none())
+}
+
+/// Checks that nothing listens on a TCP port of the host of the current `CommandRunner`,
+/// before a service that binds it is started.
+///
+/// # Arguments
+///
+/// * `port` - The port the service binds
+///
+/// # Errors
+///
+/// Returns an error naming the process listening on the port, or if the listening sockets
+/// cannot be listed.
+pub fn ensure_port_free(port: u16) -> Result<(), Box<dyn Error>> {
+    match port_user(port)? {
+        Some(user) => Err(format!(
+            "Port {} is already in use by {} on {}",
+            port,
+            user,
+            current_runner().host()
+        )
+        .into()),
+        None => Ok(()),
+    }
+}
+
+/// Returns the name of the process listening on a TCP port, if any.
+fn port_user(port: u16) -> Result<Option<String>, Box<dyn Error>> {
+    Ok(listening_process(&command_output("ss", &["-Htlnp"])?, port))
+}
+
+/// Finds the process listening on a TCP port in the output of `ss -Htlnp`.
+///
+/// # Arguments
+///
+/// * `ss_output` - The output of `ss -Htlnp`
+/// * `port` - The port
+///
+/// # Returns
+///
+/// Returns the name of the process listening on the port ("another process" if `ss` could
+/// not tell, which happens without root privileges), or `None` if nothing listens on it.
+pub fn listening_process(ss_output: &str, port: u16) -> Option<String> {
+    let port = port.to_string();
+    ss_output
+        .lines()
+        .find(|line| {
+            line.split_whitespace()
+                .nth(3)
+                .and_then(|local| local.rsplit_once(':'))
+                .is_some_and(|(_, local_port)| local_port == port)
+        })
+        .map(|line| {
+            line.split_once("users:((\"")
+                .and_then(|(_, users)| users.split('"').next())
+                .unwrap_or("another process")
+                .to_string()
+        })
+}
+
+/// Generates a secure random password.
+///
+/// This function creates a random password of 20 characters, including uppercase and lowercase
+/// letters, numbers, and special characters.
+///
+/// # Returns
+///
+/// Returns a `String` containing the generated password.
+pub fn generate_secure_password() -> String {
+    use rand::Rng;
+    const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
+                            abcdefghijklmnopqrstuvwxyz\
+                            0123456789)(*&^%$#@!~";
+    const PASSWORD_LEN: usize = 20;
+    let mut rng = rand::thread_rng();
+
+    let password: String = (0..PASSWORD_LEN)
+        .map(|_| {
+            let idx = rng.gen_range(0..CHARSET.len());
+            CHARSET[idx] as char
+        })
+        .collect();
+
+    password
+}
+
+/// Schedules a recurring job using the configured scheduler.
+///
+/// With `Scheduler::Cron` this writes `/etc/cron.d/<name>`; with `Scheduler::SystemdTimer`
+/// it installs and enables a `<name>.service` + `<name>.timer` pair instead.
+///
+/// # Arguments
+///
+/// * `scheduler` - The scheduling mechanism to use
+/// * `name` - The name of the cron file or systemd units
+/// * `description` - A human-readable description of the job
+/// * `schedule` - The five cron time fields (e.g., "0 2 * * *")
+/// * `command` - The shell command to run, including any output redirection
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the job is scheduled successfully, or an error if scheduling fails.
+pub fn schedule_job(
+    scheduler: &Scheduler,
+    name: &str,
+    description: &str,
+    schedule: &str,
+    command: &str,
+) -> Result<(), Box<dyn Error>> {
+    Plan::build(|plan| plan_job(plan, scheduler, name, description, schedule, command))?
+        .execute()?;
+    info!("Scheduled {} ({})", name, schedule);
+    Ok(())
+}
+
+/// Plans scheduling a recurring job, as `schedule_job` does.
+///
+/// # Arguments
+///
+/// * `plan` - The plan to add the operations to
+/// * `scheduler` - The scheduling mechanism to use
+/// * `name` - The name of the cron file or systemd units
+/// * `description` - A human-readable description of the job
+/// * `schedule` - The five cron time fields (e.g., "0 2 * * *")
+/// * `command` - The shell command to run, including any output redirection
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the job is planned, or an error if the schedule cannot be converted
+/// for the systemd timer.
+pub fn plan_job(
+    plan: &mut Plan,
+    scheduler: &Scheduler,
+    name: &str,
+    description: &str,
+    schedule: &str,
+    command: &str,
+) -> Result<(), Box<dyn Error>> {
+    match scheduler {
+        Scheduler::Cron => {
+            // cron turns an unescaped `%` into a newline
+            let cron_job = format!("{} root {}\n", schedule, command.replace('%', "\\%"));
+            plan.write_file(format!("/etc/cron.d/{}", name), cron_job);
+        }
+        Scheduler::SystemdTimer => {
+            let on_calendar = crate::systemd::cron_to_on_calendar(schedule)?;
+            let exec_start = crate::systemd::shell_exec_start(command);
+            crate::systemd::plan_timer(plan, name, description, &exec_start, &on_calendar);
+        }
+    }
+    Ok(())
+}
+
+/// Formats a duration for reports, e.g. `42.5s`, `3m 07.2s` or `1h 02m 05s`.
+///
+/// # Arguments
+///
+/// * `duration` - The duration to format
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64();
+    let whole = duration.as_secs();
+    if whole >= 3600 {
+        format!(
+            "{}h {:02}m {:02}s",
+            whole / 3600,
+            whole % 3600 / 60,
+            whole % 60
+        )
+    } else if whole >= 60 {
+        format!(
+            "{}m {:04.1}s",
+            whole / 60,
+            seconds - (whole / 60 * 60) as f64
+        )
+    } else {
+        format!("{:.1}s", seconds)
+    }
+}
+
+/// Renders the "Timing" section of the setup report: how long each phase took and the
+/// wall-clock time of the whole run.
+///
+/// # Arguments
+///
+/// * `timings` - How long each phase of the run took
+/// * `total` - The wall-clock time of the whole run
+pub fn timing_section(timings: &[PhaseTiming], total: Duration) -> String {
+    let mut section = String::from("\nTiming:\n");
+    for timing in timings {
+        section.push_str(&format!(
+            "- {}: {}\n",
+            timing.phase,
+            format_duration(timing.duration)
+        ));
+    }
+    section.push_str(&format!("Total: {}\n", format_duration(total)));
+    section
+}
+
 /// Generates a report of the server setup.
 ///
-/// This function creates a text file report containing details of the server configuration,
-/// deployed applications, firewall rules, and system information.
+/// This function creates a text file report containing the version of ServerForge, details
+/// of the server configuration, deployed applications, firewall rules, the installed versions
+/// of `REPORTED_PACKAGES`, system information, and how long each phase of the run took.
 ///
 /// # Arguments
 ///
 /// * `config` - A reference to the `Config` struct containing the server configuration
+/// * `timings` - How long each phase of the run took
+/// * `total` - The wall-clock time of the whole run
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` if the report is generated successfully, or an error if generation fails.
-pub fn generate_report(config: &Config) -> Result<(), Box<dyn Error>> {
-    let report_path = "/root/server_setup_report.txt";
+pub fn generate_report(
+    config: &Config,
+    timings: &[PhaseTiming],
+    total: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let report_path = REPORT_PATH;
     let mut report = String::new();
 
     report.push_str("Server Setup Report\n");
     report.push_str("===================\n\n");
 
+    report.push_str(&format!("ServerForge Version: {}\n", crate::cli::VERSION));
+
     report.push_str(&format!("Linux Distribution: {}\n", config.linux_distro));
-    report.push_str(&format!("Server Role: {}\n", config.server_role));
+    let server_roles: Vec<String> = config
+        .server_roles
+        .iter()
+        .map(|role| role.to_string())
+        .collect();
+    report.push_str(&format!("Server Roles: {}\n", server_roles.join(", ")));
     report.push_str(&format!("Security Level: {}\n", config.security_level));
+    report.push_str(&format!(
+        "Security Scan Schedule: {}\n",
+        config.security_scan_schedule
+    ));
     report.push_str(&format!("Monitoring Enabled: {}\n", config.monitoring));
+    report.push_str(&format!("Centralized Logging: {}\n", config.enable_logs));
+    if config.monitoring {
+        report.push_str(&format!(
+            "Grafana Port: {} / Prometheus Port: {}\n",
+            config.grafana_port, config.prometheus_port
+        ));
+    }
     report.push_str(&format!("Backup Frequency: {}\n", config.backup_frequency));
     report.push_str(&format!("Update Schedule: {}\n", config.update_schedule));
     report.push_str(&format!("Containerization: {}\n", config.use_containers));
     report.push_str(&format!("Kubernetes: {}\n", config.use_kubernetes));
+    report.push_str(&format!("Scheduler: {:?}\n", config.scheduler));
+    if let Some(admin_email) = &config.admin_email {
+        report.push_str(&format!("Admin Email: {}\n", admin_email));
+    }
+    if let Some(admin_user) = &config.admin_user {
+        report.push_str(&format!("Admin User: {}\n", admin_user));
+    }
 
     report.push_str("\nDeployed Applications:\n");
     for app in &config.deployed_apps {
@@ -195,28 +1279,98 @@ pub fn generate_report(config: &Config) -> Result<(), Box<dyn Error>> {
         report.push_str(&format!("- {}\n", rule));
     }
 
+    if let Ok(package_manager) = get_package_manager() {
+        report.push_str("\nComponent Versions:\n");
+        for package in REPORTED_PACKAGES {
+            let version = get_installed_version(&package_manager, package)?;
+            report.push_str(&format!(
+                "- {}: {}\n",
+                package,
+                version.as_deref().unwrap_or("not installed")
+            ));
+        }
+    }
+
     // Add system information
     report.push_str("\nSystem Information:\n");
-    if let Ok(output) = Command::new("uname").arg("-a").output() {
-        report.push_str(&format!(
-            "OS: {}\n",
-            String::from_utf8_lossy(&output.stdout).trim()
-        ));
+    if let Ok(output) = command_output("uname", &["-a"]) {
+        report.push_str(&format!("OS: {}\n", output.trim()));
     }
-    if let Ok(output) = Command::new("lscpu").output() {
-        report.push_str(&format!(
-            "CPU: {}\n",
-            String::from_utf8_lossy(&output.stdout).trim()
-        ));
+    if let Ok(output) = command_output("lscpu", &[]) {
+        report.push_str(&format!("CPU: {}\n", output.trim()));
     }
-    if let Ok(output) = Command::new("free").arg("-h").output() {
-        report.push_str(&format!(
-            "Memory: {}\n",
-            String::from_utf8_lossy(&output.stdout).trim()
-        ));
+    if let Ok(output) = command_output("free", &["-h"]) {
+        report.push_str(&format!("Memory: {}\n", output.trim()));
     }
 
-    fs::write(report_path, report)?;
+    report.push_str(&timing_section(timings, total));
+
+    write_file(report_path, report)?;
     info!("Setup report generated at {}", report_path);
     Ok(())
 }
+
+/// Emails the setup report to the admin email, if one is configured, with the mail transfer
+/// agent of the host of the current `CommandRunner` (`sendmail`, or else `mail`).
+///
+/// A host without a mail transfer agent is not an error: a warning is logged instead.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the `Config` struct containing the admin email
+/// * `report_path` - The path of the report on the host
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the report was sent or there is nothing to send it with, or an error
+/// if sending it fails.
+pub fn email_report(config: &Config, report_path: &str) -> Result<(), Box<dyn Error>> {
+    let Some(admin_email) = &config.admin_email else {
+        return Ok(());
+    };
+    let Some(mail_command) = MAIL_COMMANDS.into_iter().find(|path| path_exists(path)) else {
+        warn!(
+            "Not emailing the setup report to {}: neither sendmail nor mail is installed",
+            admin_email
+        );
+        return Ok(());
+    };
+
+    run_command(
+        "sh",
+        &[
+            "-c",
+            &report_email_command(mail_command, admin_email, report_path),
+        ],
+    )?;
+    info!("Setup report emailed to {}", admin_email);
+    Ok(())
+}
+
+/// Returns the shell command emailing a report with a mail transfer agent.
+///
+/// # Arguments
+///
+/// * `mail_command` - The path of `sendmail` or `mail`
+/// * `email` - The address to send the report to
+/// * `report_path` - The path of the report
+pub fn report_email_command(mail_command: &str, email: &str, report_path: &str) -> String {
+    let subject = "\"[serverforge] Setup report for $(hostname)\"";
+    if mail_command.ends_with("sendmail") {
+        format!(
+            "{{ printf 'To: %s\\nSubject: %s\\n\\n' {email} {subject}; cat {report}; }} | {mta} {email}",
+            email = shell_quote(email),
+            subject = subject,
+            report = shell_quote(report_path),
+            mta = mail_command
+        )
+    } else {
+        format!(
+            "{mta} -s {subject} {email} < {report}",
+            mta = mail_command,
+            subject = subject,
+            email = shell_quote(email),
+            report = shell_quote(report_path)
+        )
+    }
+}
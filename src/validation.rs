@@ -0,0 +1,81 @@
+//! # Validation Module
+//!
+//! This module checks the configuration files the setup modules generate with the tool of
+//! their service (`promtool check config`, `haproxy -c`, `sshd -t`, `nginx -t` or
+//! `apachectl configtest`), right after they are written and before the service is
+//! reloaded or restarted. A file the service would reject then fails the phase, which is
+//! rolled back, instead of taking the running service down.
+//!
+//! Planned phases check their files with `Plan::validate_config`, which adds the same
+//! command to the plan, and the others call `validate_config` directly.
+
+use crate::utils::run_command;
+use std::error::Error;
+use std::fmt;
+
+/// A service whose configuration files can be checked before they are loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceType {
+    /// Prometheus, whose `prometheus.yml` is checked with `promtool check config`
+    Prometheus,
+    /// HAProxy, checked with `haproxy -c -f`
+    Haproxy,
+    /// The OpenSSH server, checked with `sshd -t -f`
+    Sshd,
+    /// Nginx, whose whole configuration (including every enabled site) is checked with
+    /// `nginx -t`
+    Nginx,
+    /// Apache, whose whole configuration is checked with `apachectl configtest`
+    Apache,
+}
+
+impl ServiceType {
+    /// Returns the command checking a configuration file of the service, and its arguments.
+    ///
+    /// Nginx and Apache can only check their whole configuration, so the file must be one
+    /// it includes, such as an enabled site.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the configuration file to check
+    pub fn check_command(&self, path: &str) -> (&'static str, Vec<String>) {
+        let (command, args): (&str, Vec<&str>) = match self {
+            ServiceType::Prometheus => ("promtool", vec!["check", "config", path]),
+            ServiceType::Haproxy => ("haproxy", vec!["-c", "-f", path]),
+            ServiceType::Sshd => ("sshd", vec!["-t", "-f", path]),
+            ServiceType::Nginx => ("nginx", vec!["-t"]),
+            ServiceType::Apache => ("apachectl", vec!["configtest"]),
+        };
+        (command, args.into_iter().map(String::from).collect())
+    }
+}
+
+impl fmt::Display for ServiceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ServiceType::Prometheus => "prometheus",
+            ServiceType::Haproxy => "haproxy",
+            ServiceType::Sshd => "sshd",
+            ServiceType::Nginx => "nginx",
+            ServiceType::Apache => "apache",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Checks a configuration file with the tool of its service, without loading it.
+///
+/// # Arguments
+///
+/// * `service_type` - The service the file configures
+/// * `path` - The path of the configuration file to check
+///
+/// # Errors
+///
+/// Returns an error if the service rejects the configuration, or the check cannot run.
+pub fn validate_config(service_type: ServiceType, path: &str) -> Result<(), Box<dyn Error>> {
+    let (command, args) = service_type.check_command(path);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    run_command(command, &args)
+        .map_err(|e| format!("Invalid {} configuration {}: {}", service_type, path, e).into())
+}
@@ -0,0 +1,180 @@
+#![cfg(feature = "async")]
+
+use server_forge::async_runner::{
+    run_on_hosts, AsyncCommandRunner, AsyncError, BoxFuture, LocalAsyncCommandRunner,
+};
+use server_forge::runner::{current_runner, CommandOutput};
+use server_forge::utils::run_command;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+/// A host recording the commands it runs, with files kept in memory. `false` fails.
+struct FakeHost {
+    name: String,
+    commands: Mutex<Vec<String>>,
+    files: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl FakeHost {
+    fn new(name: &str) -> Arc<Self> {
+        Arc::new(FakeHost {
+            name: name.to_string(),
+            commands: Mutex::new(Vec::new()),
+            files: Mutex::new(HashMap::new()),
+        })
+    }
+}
+
+impl AsyncCommandRunner for FakeHost {
+    fn host(&self) -> &str {
+        &self.name
+    }
+
+    fn run<'a>(
+        &'a self,
+        command: &'a str,
+        args: &'a [&'a str],
+        _env: &'a [(String, String)],
+    ) -> BoxFuture<'a, Result<CommandOutput, AsyncError>> {
+        Box::pin(async move {
+            self.commands
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", command, args.join(" ")));
+            Ok(CommandOutput {
+                success: command != "false",
+                ..Default::default()
+            })
+        })
+    }
+
+    fn read_file<'a>(&'a self, path: &'a str) -> BoxFuture<'a, Result<Vec<u8>, AsyncError>> {
+        Box::pin(async move {
+            self.files
+                .lock()
+                .unwrap()
+                .get(path)
+                .cloned()
+                .ok_or_else(|| format!("{} not found", path).into())
+        })
+    }
+
+    fn write_file<'a>(
+        &'a self,
+        path: &'a str,
+        contents: &'a [u8],
+    ) -> BoxFuture<'a, Result<(), AsyncError>> {
+        Box::pin(async move {
+            self.files
+                .lock()
+                .unwrap()
+                .insert(path.to_string(), contents.to_vec());
+            Ok(())
+        })
+    }
+
+    fn create_dir_all<'a>(&'a self, _path: &'a str) -> BoxFuture<'a, Result<(), AsyncError>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn path_exists<'a>(&'a self, path: &'a str) -> BoxFuture<'a, bool> {
+        Box::pin(async move { self.files.lock().unwrap().contains_key(path) })
+    }
+}
+
+#[test]
+fn test_local_async_command_runner() {
+    let runner = LocalAsyncCommandRunner;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("nested/file.txt");
+    let path = path.to_str().unwrap();
+
+    Runtime::new().unwrap().block_on(async {
+        let output = runner
+            .run(
+                "sh",
+                &["-c", "echo $GREETING"],
+                &[(String::from("GREETING"), String::from("hello"))],
+            )
+            .await
+            .unwrap();
+        assert!(output.success);
+        assert_eq!(output.stdout, "hello\n");
+        assert!(!runner.run("false", &[], &[]).await.unwrap().success);
+        assert!(runner.run("no-such-command", &[], &[]).await.is_err());
+        let output = runner
+            .run_with_input("cat", &[], &[], b"s3cret")
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, "s3cret");
+
+        runner
+            .create_dir_all(dir.path().join("nested").to_str().unwrap())
+            .await
+            .unwrap();
+        assert!(!runner.path_exists(path).await);
+        runner.write_file(path, b"contents").await.unwrap();
+        assert!(runner.path_exists(path).await);
+        assert_eq!(runner.read_file(path).await.unwrap(), b"contents");
+    });
+}
+
+#[test]
+fn test_run_on_hosts() {
+    let hosts = [
+        FakeHost::new("web1"),
+        FakeHost::new("db1"),
+        FakeHost::new("web2"),
+    ];
+    let runners: Vec<Arc<dyn AsyncCommandRunner>> = hosts
+        .iter()
+        .map(|host| host.clone() as Arc<dyn AsyncCommandRunner>)
+        .collect();
+
+    let results = Runtime::new()
+        .unwrap()
+        .block_on(run_on_hosts(runners, 2, || {
+            // The synchronous helpers run on the host of the task
+            let host = current_runner().host().to_string();
+            run_command("hostname", &[])?;
+            if host == "db1" {
+                run_command("false", &[])?;
+            }
+            Ok(host)
+        }));
+
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0], (String::from("web1"), Ok(String::from("web1"))));
+    assert_eq!(results[1].0, "db1");
+    assert!(results[1].1.is_err());
+    assert_eq!(results[2], (String::from("web2"), Ok(String::from("web2"))));
+    for host in &hosts {
+        assert_eq!(host.commands.lock().unwrap()[0], "hostname ");
+    }
+}
+
+#[test]
+fn test_run_on_hosts_limits_concurrency() {
+    let runners: Vec<Arc<dyn AsyncCommandRunner>> = (0..6)
+        .map(|i| FakeHost::new(&format!("web{}", i)) as Arc<dyn AsyncCommandRunner>)
+        .collect();
+    let running = Arc::new(AtomicUsize::new(0));
+    let most_running = Arc::new(AtomicUsize::new(0));
+
+    let (counter, most) = (running.clone(), most_running.clone());
+    let results = Runtime::new()
+        .unwrap()
+        .block_on(run_on_hosts(runners, 2, move || {
+            let now = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            most.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(20));
+            counter.fetch_sub(1, Ordering::SeqCst);
+            Ok(())
+        }));
+
+    assert!(results.iter().all(|(_, result)| result.is_ok()));
+    assert!(most_running.load(Ordering::SeqCst) <= 2);
+}
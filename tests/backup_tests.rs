@@ -1,12 +1,18 @@
+use crate::common::RecordingRunner;
 use server_forge::backup;
-use server_forge::config::Config;
+use server_forge::config::{Config, Secrets, ServerRole, VaultConfig};
+use server_forge::deployment;
 use server_forge::rollback::RollbackManager;
+use server_forge::runner::with_runner;
 use std::fs;
 use std::os::unix::fs::PermissionsExt;
+use std::sync::Arc;
 
 #[test]
 fn test_install_backup_tools() {
-    assert!(backup::install_backup_tools().is_ok());
+    let rollback = RollbackManager::new();
+    let snapshot = rollback.create_snapshot("Backup tools").unwrap();
+    assert!(backup::install_backup_tools(&rollback, snapshot).is_ok());
 
     // Verify restic installation
     let restic_status = std::process::Command::new("restic")
@@ -27,13 +33,13 @@ fn test_configure_backup_schedule() {
 
     // Verify cron job creation
     let cron_content = fs::read_to_string("/etc/cron.d/restic-backup").unwrap();
-    assert!(cron_content.contains("0 2 * * * root /usr/bin/restic backup"));
+    assert!(cron_content.contains("0 2 * * * root /usr/local/bin/run-backup.sh"));
 }
 
 #[test]
 fn test_setup_backup_locations() {
     let config = Config {
-        server_role: String::from("web"),
+        server_roles: vec![ServerRole::Web],
         ..Default::default()
     };
 
@@ -50,11 +56,167 @@ fn test_setup_backup_locations() {
     assert!(script_metadata.permissions().mode() & 0o111 != 0);
 }
 
+#[test]
+fn test_setup_backup_locations_with_vault() {
+    let config = Config {
+        server_roles: vec![ServerRole::Database],
+        vault: Some(VaultConfig {
+            address: String::from("https://vault.internal:8200"),
+            mount: String::from("kv"),
+            path: String::from("servers/db1"),
+            role_id: None,
+        }),
+        secrets: Secrets {
+            vault_token: Some(String::from("t0ken")),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let host = Arc::new(RecordingRunner::new("fake").respond(
+        "curl -sS --config - https://vault.internal:8200/v1/kv/data/servers/db1/mysql_root_password",
+        r#"{"data":{"data":{"value":"s3cret"},"metadata":{"version":1}}}"#,
+    ));
+
+    with_runner(host.clone(), || backup::setup_backup_locations(&config)).unwrap();
+
+    // The unattended script can't read Vault, so the password is copied to the file it reads
+    assert_eq!(
+        host.file(deployment::MYSQL_PASSWORD_FILE).as_deref(),
+        Some("s3cret")
+    );
+    let script = host.file("/usr/local/bin/run-backup.sh").unwrap();
+    assert!(script.contains(&format!(
+        "export MYSQL_PWD=\"$(cat {})\"",
+        deployment::MYSQL_PASSWORD_FILE
+    )));
+    assert!(!script.contains("s3cret"));
+
+    // Without a MySQL dump, the password stays in Vault only
+    let config = Config {
+        server_roles: vec![ServerRole::Web],
+        ..config
+    };
+    let host = Arc::new(RecordingRunner::new("fake"));
+    with_runner(host.clone(), || backup::setup_backup_locations(&config)).unwrap();
+    assert!(host.commands().iter().all(|c| !c.starts_with("curl")));
+    assert_eq!(host.file(deployment::MYSQL_PASSWORD_FILE), None);
+}
+
+#[test]
+fn test_generate_backup_script_dumps_databases() {
+    let config = Config {
+        server_roles: vec![ServerRole::Database],
+        ..Default::default()
+    };
+
+    let script = backup::generate_backup_script(&config);
+    assert!(script.contains("mysqldump --user=root --all-databases"));
+    assert!(script.contains("pg_dumpall"));
+    assert!(script.contains(&format!("cat {}", deployment::MYSQL_PASSWORD_FILE)));
+    assert!(script.contains(&format!("restic backup {}", backup::DATABASE_DUMP_DIR)));
+    assert!(!script.contains("/var/lib/mysql"));
+    assert!(!script.contains("/var/lib/postgresql"));
+
+    // Dumps happen before restic runs
+    assert!(script.find("mysqldump").unwrap() < script.find("restic backup").unwrap());
+}
+
+#[test]
+fn test_generate_backup_script_web_with_mysql() {
+    let config = Config {
+        server_roles: vec![ServerRole::Web],
+        deployed_apps: vec![String::from("nginx"), String::from("mysql")],
+        ..Default::default()
+    };
+
+    let script = backup::generate_backup_script(&config);
+    assert!(script.contains("/var/www"));
+    assert!(script.contains("mysqldump"));
+    assert!(!script.contains("pg_dumpall"));
+}
+
+#[test]
+fn test_generate_backup_script_multiple_roles() {
+    let config = Config {
+        server_roles: vec![ServerRole::Web, ServerRole::Database, ServerRole::Web],
+        ..Default::default()
+    };
+
+    let script = backup::generate_backup_script(&config);
+    assert!(script.contains(&format!(
+        "restic backup /var/www /etc/nginx /etc/apache2 {} --tag serverforge",
+        backup::DATABASE_DUMP_DIR
+    )));
+    assert!(script.contains("mysqldump"));
+    assert!(script.contains("pg_dumpall"));
+}
+
+#[test]
+fn test_generate_backup_script_notifications() {
+    let config = Config {
+        server_roles: vec![ServerRole::Web],
+        admin_email: Some(String::from("ops@example.com")),
+        secrets: Secrets {
+            notification_webhook: Some(String::from("https://hooks.example.com/backup")),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let script = backup::generate_backup_script(&config);
+    assert!(script.contains("notify() {"));
+    assert!(script.contains("mail -s \"$subject\" 'ops@example.com'"));
+    assert!(script.contains("'https://hooks.example.com/backup'"));
+    assert!(script.contains("notify failed"));
+    assert!(!script.contains("notify succeeded"));
+
+    let config = Config {
+        backup_notify_on_success: true,
+        ..config
+    };
+    assert!(backup::generate_backup_script(&config).contains("notify succeeded"));
+
+    // Without a notification target the script just runs restic
+    let script = backup::generate_backup_script(&Config::default());
+    assert!(!script.contains("notify"));
+}
+
+#[test]
+fn test_backup_excludes() {
+    let config = Config {
+        server_roles: vec![ServerRole::Web],
+        backup_excludes: vec![
+            String::from("node_modules"),
+            String::from("*.log"),
+            String::from("/var/www/cache"),
+        ],
+        ..Default::default()
+    };
+
+    let exclude_file = backup::generate_exclude_file(&config).unwrap();
+    assert_eq!(exclude_file, "node_modules\n*.log\n/var/www/cache\n");
+
+    let script = backup::generate_backup_script(&config);
+    assert!(script.contains(&format!(
+        "restic backup /var/www /etc/nginx /etc/apache2 --exclude-file={} --tag serverforge",
+        backup::BACKUP_EXCLUDE_FILE
+    )));
+
+    // Without excludes no exclude file is referenced
+    assert!(!backup::generate_backup_script(&Config::default()).contains("--exclude-file"));
+
+    let config = Config {
+        backup_excludes: vec![String::from("*.tmp"), String::from("  ")],
+        ..Default::default()
+    };
+    assert!(backup::generate_exclude_file(&config).is_err());
+}
+
 #[test]
 fn test_setup_backup_system() {
     let config = Config {
         backup_frequency: String::from("daily"),
-        server_role: String::from("web"),
+        server_roles: vec![ServerRole::Web],
         ..Default::default()
     };
     let rollback_manager = RollbackManager::new();
@@ -74,3 +236,61 @@ fn test_setup_backup_system() {
     // Verify backup script creation
     assert!(fs::read_to_string("/usr/local/bin/run-backup.sh").is_ok());
 }
+
+#[test]
+fn test_generate_backup_script_metrics() {
+    let config = Config {
+        server_roles: vec![ServerRole::Web],
+        monitoring: true,
+        ..Default::default()
+    };
+    let script = backup::generate_backup_script(&config);
+    assert!(script.contains("trap 'write_backup_metrics $?' EXIT\n"));
+    assert!(
+        script.find("trap 'write_backup_metrics").unwrap() < script.find("restic backup").unwrap()
+    );
+    assert!(!backup::generate_backup_script(&Config::default()).contains("write_backup_metrics"));
+
+    // Run the metrics function against a temporary collector directory
+    let start = script.find("write_backup_metrics() {").unwrap();
+    let end = start + script[start..].find("\n}\n").unwrap() + 3;
+    let dir = tempfile::tempdir().unwrap();
+    let function = script[start..end].replace(
+        "/var/lib/node_exporter/textfile_collector",
+        dir.path().to_str().unwrap(),
+    );
+    let metrics_after = |status: i32| {
+        let status = std::process::Command::new("bash")
+            .args([
+                "-c",
+                &format!("{}write_backup_metrics {}", function, status),
+            ])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        fs::read_to_string(dir.path().join(backup::BACKUP_METRICS_FILE)).unwrap()
+    };
+
+    let metrics = metrics_after(1);
+    assert!(metrics.contains("serverforge_backup_success 0\n"));
+    assert!(metrics.contains("serverforge_backup_last_run_timestamp_seconds "));
+    assert!(!metrics.contains("serverforge_backup_last_success_timestamp_seconds"));
+
+    let metrics = metrics_after(0);
+    assert!(metrics.contains("serverforge_backup_success 1\n"));
+    let last_success = |metrics: &str| {
+        metrics
+            .lines()
+            .find_map(|line| {
+                line.strip_prefix("serverforge_backup_last_success_timestamp_seconds ")
+            })
+            .map(str::to_string)
+    };
+    let succeeded_at = last_success(&metrics).unwrap();
+
+    // A failed run keeps the time of the last success
+    let metrics = metrics_after(2);
+    assert!(metrics.contains("serverforge_backup_success 0\n"));
+    assert_eq!(last_success(&metrics), Some(succeeded_at));
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+}
@@ -0,0 +1,119 @@
+use crate::common::RecordingRunner;
+use server_forge::check::{check_config, listening_ports, os_release_id, port_conflicts};
+use server_forge::config::Config;
+use server_forge::runner::with_runner;
+use std::sync::Arc;
+
+/// Checks a configuration on a host, making sure the check does not write any file.
+fn check_on(host: RecordingRunner, config: &Config) -> Vec<String> {
+    let host = Arc::new(host);
+    let problems = with_runner(host.clone(), || check_config(config));
+    assert!(
+        host.log().iter().all(|entry| !entry.starts_with("write ")),
+        "The check must not write files"
+    );
+    problems
+}
+
+const UBUNTU_OS_RELEASE: &str =
+    "NAME=\"Ubuntu\"\nVERSION_ID=\"24.04\"\nID=ubuntu\nID_LIKE=debian\n";
+
+#[test]
+fn test_os_release_id() {
+    assert_eq!(os_release_id(UBUNTU_OS_RELEASE).as_deref(), Some("ubuntu"));
+    assert_eq!(
+        os_release_id("NAME=\"CentOS Stream\"\nID=\"centos\"\nID_LIKE=\"rhel fedora\"\n")
+            .as_deref(),
+        Some("centos")
+    );
+    assert_eq!(os_release_id("NAME=Unknown\n"), None);
+}
+
+#[test]
+fn test_port_conflicts() {
+    let mut config = Config {
+        deployed_apps: vec![String::from("nginx"), String::from("postgresql")],
+        monitoring: true,
+        ..Config::default()
+    };
+    assert!(listening_ports(&config).contains(&(5432, String::from("postgresql"))));
+    assert!(port_conflicts(&config).is_empty());
+
+    config.ssh_port = 9090;
+    assert_eq!(
+        port_conflicts(&config),
+        vec!["Port 9090 would be used by more than one service: sshd, prometheus"]
+    );
+    config.ssh_port = 2222;
+
+    config.deployed_apps.push(String::from("apache"));
+    config.grafana_port = 3000;
+    config.deployed_apps.push(String::from("nodejs"));
+    assert_eq!(
+        port_conflicts(&config),
+        vec![
+            "Port 80 would be used by more than one service: nginx, apache",
+            "Port 3000 would be used by more than one service: grafana, nodejs",
+        ]
+    );
+}
+
+#[test]
+fn test_check_config_valid() {
+    let config = Config {
+        load_balancer_certificate: Some(String::from("/etc/ssl/private/site.pem")),
+        deployed_apps: vec![String::from("haproxy")],
+        ..Config::default()
+    };
+    let host = RecordingRunner::new("fake")
+        .with_file("/etc/os-release", UBUNTU_OS_RELEASE)
+        .with_file("/etc/ssl/private/site.pem", "");
+    let problems = check_on(host, &config);
+    assert!(problems.is_empty(), "{:?}", problems);
+}
+
+#[test]
+fn test_check_config_reports_every_problem() {
+    let config = Config {
+        linux_distro: String::from("fedora"),
+        custom_firewall_rules: vec![
+            String::from("80/tpc"),
+            String::from("443/tcp"),
+            String::from("99999"),
+        ],
+        local_artifacts_dir: Some(String::from("/srv/artifacts")),
+        deployed_apps: vec![String::from("nginx"), String::from("haproxy")],
+        ..Config::default()
+    };
+    let host = RecordingRunner::new("fake").with_file("/etc/os-release", UBUNTU_OS_RELEASE);
+    let problems = check_on(host, &config);
+
+    assert_eq!(problems.len(), 5, "{:?}", problems);
+    assert!(problems[0].starts_with("Invalid custom firewall rule #1 '80/tpc': "));
+    assert!(problems[1].starts_with("Invalid custom firewall rule #3 '99999': "));
+    assert_eq!(
+        problems[2],
+        "linux_distro is 'fedora' but this system runs 'ubuntu'"
+    );
+    assert_eq!(
+        problems[3],
+        "local_artifacts_dir /srv/artifacts does not exist"
+    );
+    assert_eq!(
+        problems[4],
+        "Port 80 would be used by more than one service: nginx, haproxy"
+    );
+}
+
+#[test]
+fn test_check_config_unsupported_distro() {
+    let config = Config {
+        linux_distro: String::from("arch"),
+        ..Config::default()
+    };
+    let problems = check_on(RecordingRunner::new("fake"), &config);
+    assert_eq!(
+        problems,
+        vec!["Unsupported Linux distribution 'arch' (expected ubuntu, centos, fedora)"]
+    );
+}
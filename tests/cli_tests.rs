@@ -0,0 +1,171 @@
+use clap::error::ErrorKind;
+use clap::Parser;
+use server_forge::cli::{Cli, Command, ExportFormat, VERSION};
+
+#[test]
+fn test_parse_deploy_options() {
+    let cli = Cli::try_parse_from(["server_forge"]).unwrap();
+    assert_eq!(cli.command, None);
+    assert!(cli.hosts.is_empty());
+    assert_eq!(cli.parallelism, 1);
+    assert_eq!(cli.max_concurrent_installs, None);
+    assert!(!cli.continue_on_error);
+    assert!(!cli.force);
+
+    let cli = Cli::try_parse_from([
+        "server_forge",
+        "deploy",
+        "--hosts",
+        "web1,root@10.0.0.5",
+        "--parallelism",
+        "4",
+        "--max-concurrent-installs",
+        "2",
+        "--continue-on-error",
+        "--force",
+    ])
+    .unwrap();
+    assert_eq!(cli.command, Some(Command::Deploy { config: None }));
+    assert!(cli.continue_on_error);
+    assert!(cli.force);
+    assert_eq!(cli.hosts, vec!["web1", "root@10.0.0.5"]);
+    assert_eq!(cli.parallelism, 4);
+    assert_eq!(cli.max_concurrent_installs, Some(2));
+
+    let cli = Cli::try_parse_from(["server_forge", "deploy", "--inventory", "infra.yaml"]).unwrap();
+    assert_eq!(cli.inventory.as_deref(), Some("infra.yaml"));
+
+    let cli = Cli::try_parse_from(["server_forge", "deploy", "--config", "server.yaml"]).unwrap();
+    assert_eq!(
+        cli.command,
+        Some(Command::Deploy {
+            config: Some(String::from("server.yaml"))
+        })
+    );
+}
+
+#[test]
+fn test_reject_invalid_options() {
+    assert!(Cli::try_parse_from(["server_forge", "--parallelism", "0"]).is_err());
+    assert!(Cli::try_parse_from(["server_forge", "--max-concurrent-installs", "0"]).is_err());
+    assert!(Cli::try_parse_from([
+        "server_forge",
+        "--hosts",
+        "web1",
+        "--inventory",
+        "infra.yaml"
+    ])
+    .is_err());
+}
+
+#[test]
+fn test_parse_export_options() {
+    let cli = Cli::try_parse_from(["server_forge", "export"]).unwrap();
+    assert_eq!(
+        cli.command,
+        Some(Command::Export {
+            format: ExportFormat::Ansible,
+            output: None,
+            include_secrets: false
+        })
+    );
+
+    let cli = Cli::try_parse_from([
+        "server_forge",
+        "export",
+        "--format",
+        "cloud-init",
+        "--output",
+        "user-data.yml",
+        "--hosts",
+        "web1",
+        "--include-secrets",
+    ])
+    .unwrap();
+    assert_eq!(
+        cli.command,
+        Some(Command::Export {
+            format: ExportFormat::CloudInit,
+            output: Some(String::from("user-data.yml")),
+            include_secrets: true
+        })
+    );
+    assert_eq!(cli.hosts, vec!["web1"]);
+}
+
+#[test]
+fn test_parse_status() {
+    let cli = Cli::try_parse_from(["server_forge", "status", "--hosts", "web1,web2"]).unwrap();
+    assert_eq!(cli.command, Some(Command::Status));
+    assert_eq!(cli.hosts, vec!["web1", "web2"]);
+}
+
+#[test]
+fn test_parse_rollback() {
+    let cli = Cli::try_parse_from(["server_forge", "rollback"]).unwrap();
+    assert_eq!(
+        cli.command,
+        Some(Command::Rollback {
+            dry_run: false,
+            snapshot: None
+        })
+    );
+
+    let cli =
+        Cli::try_parse_from(["server_forge", "rollback", "--dry-run", "--snapshot", "2"]).unwrap();
+    assert_eq!(
+        cli.command,
+        Some(Command::Rollback {
+            dry_run: true,
+            snapshot: Some(2)
+        })
+    );
+}
+
+#[test]
+fn test_parse_quiet() {
+    assert!(!Cli::try_parse_from(["server_forge"]).unwrap().quiet);
+    assert!(Cli::try_parse_from(["server_forge", "-q"]).unwrap().quiet);
+    assert!(
+        Cli::try_parse_from(["server_forge", "status", "--quiet"])
+            .unwrap()
+            .quiet
+    );
+}
+
+#[test]
+fn test_version() {
+    assert!(VERSION.starts_with(env!("CARGO_PKG_VERSION")));
+
+    let error = Cli::try_parse_from(["server_forge", "--version"]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::DisplayVersion);
+    assert!(error.to_string().contains(VERSION));
+}
+
+#[test]
+fn test_parse_check() {
+    let cli = Cli::try_parse_from(["server_forge", "check", "--config", "server.yaml"]).unwrap();
+    assert_eq!(
+        cli.command,
+        Some(Command::Check {
+            config: String::from("server.yaml")
+        })
+    );
+
+    let error = Cli::try_parse_from(["server_forge", "check"]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::MissingRequiredArgument);
+}
+
+#[test]
+fn test_parse_recovery_flags() {
+    let cli = Cli::try_parse_from(["server_forge", "deploy", "--recover"]).unwrap();
+    assert!(cli.recover);
+    assert!(!cli.ignore_recovery);
+
+    let cli = Cli::try_parse_from(["server_forge", "--ignore-recovery"]).unwrap();
+    assert!(cli.ignore_recovery);
+
+    let error =
+        Cli::try_parse_from(["server_forge", "--recover", "--ignore-recovery"]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::ArgumentConflict);
+}
@@ -1 +1,484 @@
+//! Test helpers shared by the test modules.
 
+// Every test target includes this module, and none uses all of it
+#![allow(dead_code)]
+
+use server_forge::filesystem::FileSystem;
+use server_forge::runner::{CommandOutput, CommandRunner};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::sync::Mutex;
+
+/// Something a `RecordingRunner` was asked to do.
+enum Event {
+    /// A command line, with its arguments joined by spaces
+    Run(String),
+    /// A file write, by path
+    Write(String),
+}
+
+/// A canned result for the commands whose command line starts with `prefix`.
+struct Response {
+    prefix: String,
+    output: CommandOutput,
+    /// How many more times the response is given, or `None` for every time
+    remaining: Option<usize>,
+}
+
+/// A `CommandRunner` recording every command it runs and every file it writes, in order,
+/// with an in-memory filesystem.
+///
+/// Commands succeed with no output unless they are given one with `respond` or are made to
+/// fail with `fail` or `fail_with`, and the input of those run with standard input is kept
+/// (see `inputs`). Like a real host, the files removed with `rm` are gone, the packages
+/// installed with apt, yum or dnf are reported as installed (at version 1.0) by `dpkg-query`
+/// and `rpm` afterwards, until they are removed, and the services started or enabled with
+/// `systemctl` are reported as active or enabled by `systemctl is-active` and
+/// `systemctl is-enabled`.
+pub struct RecordingRunner {
+    host: String,
+    events: Mutex<Vec<Event>>,
+    files: Mutex<BTreeMap<String, Vec<u8>>>,
+    dirs: Mutex<BTreeSet<String>>,
+    packages: Mutex<BTreeSet<String>>,
+    active_services: Mutex<BTreeSet<String>>,
+    enabled_services: Mutex<BTreeSet<String>>,
+    responses: Mutex<Vec<Response>>,
+    inputs: Mutex<Vec<(String, String)>>,
+}
+
+impl RecordingRunner {
+    /// Creates a runner for a host with no files.
+    pub fn new(host: &str) -> Self {
+        RecordingRunner {
+            host: host.to_string(),
+            events: Mutex::new(Vec::new()),
+            files: Mutex::new(BTreeMap::new()),
+            dirs: Mutex::new(BTreeSet::new()),
+            packages: Mutex::new(BTreeSet::new()),
+            active_services: Mutex::new(BTreeSet::new()),
+            enabled_services: Mutex::new(BTreeSet::new()),
+            responses: Mutex::new(Vec::new()),
+            inputs: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Creates a runner for a fresh Ubuntu host named "ubuntu", with apt, and sshd running
+    /// with a default `sshd_config`.
+    pub fn ubuntu(host: &str) -> Self {
+        RecordingRunner::new(host)
+            .with_service("sshd")
+            .with_file("/usr/bin/apt", "")
+            .with_file("/etc/hostname", "ubuntu\n")
+            .with_file("/etc/hosts", "127.0.0.1 localhost\n127.0.1.1 ubuntu\n")
+            .with_file(
+                "/etc/ssh/sshd_config",
+                "#PermitRootLogin prohibit-password\n#PasswordAuthentication yes\n#Port 22\n",
+            )
+    }
+
+    /// Adds a file to the filesystem.
+    pub fn with_file(self, path: &str, contents: &str) -> Self {
+        self.files
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), contents.as_bytes().to_vec());
+        self
+    }
+
+    /// Marks a package as installed.
+    pub fn with_package(self, package: &str) -> Self {
+        self.packages.lock().unwrap().insert(package.to_string());
+        self
+    }
+
+    /// Marks a service as running and enabled at boot.
+    pub fn with_service(self, service: &str) -> Self {
+        self.active_services
+            .lock()
+            .unwrap()
+            .insert(service.to_string());
+        self.enabled_services
+            .lock()
+            .unwrap()
+            .insert(service.to_string());
+        self
+    }
+
+    /// Makes the commands whose command line starts with `prefix` succeed with `stdout`.
+    pub fn respond(self, prefix: &str, stdout: &str) -> Self {
+        self.respond_with(prefix, stdout, None)
+    }
+
+    /// Makes the commands whose command line starts with `prefix` succeed with `stdout`, the
+    /// first `times` times they run (every time if `None`).
+    pub fn respond_with(self, prefix: &str, stdout: &str, times: Option<usize>) -> Self {
+        self.responses.lock().unwrap().push(Response {
+            prefix: prefix.to_string(),
+            output: CommandOutput {
+                success: true,
+                stdout: stdout.to_string(),
+                ..Default::default()
+            },
+            remaining: times,
+        });
+        self
+    }
+
+    /// Makes the commands whose command line starts with `prefix` fail.
+    pub fn fail(self, prefix: &str) -> Self {
+        let stderr = format!("{} failed", prefix);
+        self.fail_with(prefix, &stderr, None)
+    }
+
+    /// Makes the commands whose command line starts with `prefix` fail with `stderr`, the
+    /// first `times` times they run (every time if `None`).
+    pub fn fail_with(self, prefix: &str, stderr: &str, times: Option<usize>) -> Self {
+        self.responses.lock().unwrap().push(Response {
+            prefix: prefix.to_string(),
+            output: CommandOutput {
+                success: false,
+                stderr: stderr.to_string(),
+                ..Default::default()
+            },
+            remaining: times,
+        });
+        self
+    }
+
+    /// Returns how many times a command line was run.
+    pub fn count(&self, command_line: &str) -> usize {
+        self.log()
+            .iter()
+            .filter(|entry| *entry == command_line)
+            .count()
+    }
+
+    /// Returns everything the runner was asked to do, in order: the command lines it ran,
+    /// and `write <path>` for the files it wrote.
+    pub fn log(&self) -> Vec<String> {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|event| match event {
+                Event::Run(command_line) => command_line.clone(),
+                Event::Write(path) => format!("write {}", path),
+            })
+            .collect()
+    }
+
+    /// Returns the command lines it ran, in order.
+    pub fn commands(&self) -> Vec<String> {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|event| match event {
+                Event::Run(command_line) => Some(command_line.clone()),
+                Event::Write(_) => None,
+            })
+            .collect()
+    }
+
+    /// Returns the command lines run with standard input, and their input, in order.
+    pub fn inputs(&self) -> Vec<(String, String)> {
+        self.inputs.lock().unwrap().clone()
+    }
+
+    /// Returns the contents of a file, if it exists.
+    pub fn file(&self, path: &str) -> Option<String> {
+        self.files
+            .lock()
+            .unwrap()
+            .get(path)
+            .map(|contents| String::from_utf8_lossy(contents).into_owned())
+    }
+
+    /// Records a command line, and returns it.
+    fn record(&self, command: &str, args: &[&str]) -> String {
+        let command_line = std::iter::once(command)
+            .chain(args.iter().copied())
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.events
+            .lock()
+            .unwrap()
+            .push(Event::Run(command_line.clone()));
+        command_line
+    }
+
+    /// Returns the output of a recorded command, applying its effects.
+    fn output(
+        &self,
+        command: &str,
+        args: &[&str],
+        command_line: &str,
+    ) -> Result<CommandOutput, Box<dyn Error>> {
+        // The last matching response wins, so presets can be overridden
+        if let Some(response) = self
+            .responses
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .rev()
+            .find(|response| {
+                command_line.starts_with(response.prefix.as_str()) && response.remaining != Some(0)
+            })
+        {
+            if let Some(remaining) = &mut response.remaining {
+                *remaining -= 1;
+            }
+            return Ok(response.output.clone());
+        }
+
+        let mut packages = self.packages.lock().unwrap();
+        let (success, stdout) = match (command, args) {
+            ("apt" | "yum" | "dnf", ["install", "-y", names @ ..]) => {
+                packages.extend(names.iter().map(|name| name.to_string()));
+                (true, String::new())
+            }
+            ("apt" | "yum" | "dnf", ["remove", "-y", names @ ..]) => {
+                for name in names {
+                    packages.remove(*name);
+                }
+                (true, String::new())
+            }
+            ("dpkg-query", ["-W", format, name]) => {
+                let installed = packages.contains(*name);
+                let stdout = format
+                    .trim_start_matches("-f=")
+                    .replace("${Status}", "install ok installed")
+                    .replace("${Version}", "1.0");
+                (installed, if installed { stdout } else { String::new() })
+            }
+            ("rm", paths) => {
+                let mut files = self.files.lock().unwrap();
+                for path in paths.iter().filter(|path| !path.starts_with('-')) {
+                    files.remove(*path);
+                }
+                (true, String::new())
+            }
+            ("rpm", [.., name]) => {
+                let installed = packages.contains(*name);
+                (installed, String::from(if installed { "1.0" } else { "" }))
+            }
+            ("systemctl", [action, service]) => {
+                let mut active = self.active_services.lock().unwrap();
+                let mut enabled = self.enabled_services.lock().unwrap();
+                let service = service.to_string();
+                match *action {
+                    "start" | "restart" => active.insert(service),
+                    "stop" => active.remove(&service),
+                    "enable" => enabled.insert(service),
+                    "disable" => enabled.remove(&service),
+                    "is-active" => {
+                        let state = if active.contains(&service) {
+                            "active"
+                        } else {
+                            "inactive"
+                        };
+                        return Ok(CommandOutput {
+                            success: state == "active",
+                            stdout: format!("{}\n", state),
+                            ..Default::default()
+                        });
+                    }
+                    "is-enabled" => {
+                        let state = if enabled.contains(&service) {
+                            "enabled"
+                        } else {
+                            "disabled"
+                        };
+                        return Ok(CommandOutput {
+                            success: state == "enabled",
+                            stdout: format!("{}\n", state),
+                            ..Default::default()
+                        });
+                    }
+                    _ => true,
+                };
+                (true, String::new())
+            }
+            _ => (true, String::new()),
+        };
+        Ok(CommandOutput {
+            success,
+            stdout,
+            ..Default::default()
+        })
+    }
+}
+
+impl CommandRunner for RecordingRunner {
+    fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn run(
+        &self,
+        command: &str,
+        args: &[&str],
+        _env: &[(String, String)],
+    ) -> Result<CommandOutput, Box<dyn Error>> {
+        let command_line = self.record(command, args);
+        self.output(command, args, &command_line)
+    }
+
+    fn run_with_input(
+        &self,
+        command: &str,
+        args: &[&str],
+        _env: &[(String, String)],
+        input: &[u8],
+    ) -> Result<CommandOutput, Box<dyn Error>> {
+        let command_line = self.record(command, args);
+        let input = String::from_utf8_lossy(input).into_owned();
+        self.inputs
+            .lock()
+            .unwrap()
+            .push((command_line.clone(), input.clone()));
+        // The files written with `Plan::write_private_file` hold the input
+        if let ("sh", ["-c", script, "sh", path]) = (command, args) {
+            if script.contains("cat > \"$1.tmp\"") {
+                self.files
+                    .lock()
+                    .unwrap()
+                    .insert(path.to_string(), input.into_bytes());
+            }
+        }
+        self.output(command, args, &command_line)
+    }
+
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.files
+            .lock()
+            .unwrap()
+            .get(path)
+            .cloned()
+            .ok_or_else(|| format!("{} not found", path).into())
+    }
+
+    fn write_file(&self, path: &str, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.events
+            .lock()
+            .unwrap()
+            .push(Event::Write(path.to_string()));
+        self.files
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), contents.to_vec());
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        self.dirs.lock().unwrap().insert(path.to_string());
+        Ok(())
+    }
+
+    fn path_exists(&self, path: &str) -> bool {
+        self.files.lock().unwrap().contains_key(path) || self.dirs.lock().unwrap().contains(path)
+    }
+}
+
+/// A `FileSystem` keeping files in memory.
+///
+/// Files are created with mode `0o644` and directories with `0o755`. Writing a file does
+/// not require its parent directory to exist.
+#[derive(Debug, Default)]
+pub struct MockFileSystem {
+    files: Mutex<BTreeMap<String, Vec<u8>>>,
+    dirs: Mutex<BTreeSet<String>>,
+    modes: Mutex<BTreeMap<String, u32>>,
+}
+
+impl MockFileSystem {
+    /// Creates an empty file system.
+    pub fn new() -> Self {
+        MockFileSystem::default()
+    }
+
+    /// Adds a file to the file system.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file
+    /// * `contents` - The contents of the file
+    pub fn with_file(self, path: &str, contents: &str) -> Self {
+        self.files
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), contents.as_bytes().to_vec());
+        self
+    }
+
+    /// Returns the contents of a file as text, if it exists.
+    pub fn file(&self, path: &str) -> Option<String> {
+        self.files
+            .lock()
+            .unwrap()
+            .get(path)
+            .map(|contents| String::from_utf8_lossy(contents).into_owned())
+    }
+
+    /// Returns the permission bits of a file or directory, if it exists.
+    pub fn mode(&self, path: &str) -> Option<u32> {
+        if let Some(mode) = self.modes.lock().unwrap().get(path) {
+            return Some(*mode);
+        }
+        if self.files.lock().unwrap().contains_key(path) {
+            Some(0o644)
+        } else if self.dirs.lock().unwrap().contains(path) {
+            Some(0o755)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the paths of all files, sorted.
+    pub fn paths(&self) -> Vec<String> {
+        self.files.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+impl FileSystem for MockFileSystem {
+    fn read(&self, path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.files
+            .lock()
+            .unwrap()
+            .get(path)
+            .cloned()
+            .ok_or_else(|| format!("{}: No such file or directory", path).into())
+    }
+
+    fn write(&self, path: &str, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.files
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), contents.to_vec());
+        Ok(())
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.files.lock().unwrap().contains_key(path) || self.dirs.lock().unwrap().contains(path)
+    }
+
+    fn set_permissions(&self, path: &str, mode: u32) -> Result<(), Box<dyn Error>> {
+        if !self.exists(path) {
+            return Err(format!("{}: No such file or directory", path).into());
+        }
+        self.modes.lock().unwrap().insert(path.to_string(), mode);
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut dirs = self.dirs.lock().unwrap();
+        let mut parent = String::new();
+        for component in path.split('/').filter(|component| !component.is_empty()) {
+            parent.push('/');
+            parent.push_str(component);
+            dirs.insert(parent.clone());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use crate::common::RecordingRunner;
+use server_forge::concurrency::{
+    current_limits, run_concurrently, with_limits, Limits, DEFAULT_MAX_CONCURRENT_DOWNLOADS,
+};
+use server_forge::config::Config;
+use server_forge::plan::Plan;
+use server_forge::runner::{current_runner, with_runner};
+use server_forge::utils::{command_env, configure_proxy};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// An Ubuntu host with no packages installed, recording the commands it runs.
+fn ubuntu_host() -> RecordingRunner {
+    RecordingRunner::new("web1").with_file("/usr/bin/apt", "")
+}
+
+#[test]
+fn test_limits_from_config() {
+    assert_eq!(Limits::from_config(&Config::default()), Limits::default());
+    assert_eq!(Limits::default().installs, 1);
+    assert_eq!(
+        Limits::default().downloads,
+        DEFAULT_MAX_CONCURRENT_DOWNLOADS
+    );
+
+    let config = Config {
+        max_concurrent_installs: 0,
+        max_concurrent_downloads: 8,
+        ..Default::default()
+    };
+    assert_eq!(
+        Limits::from_config(&config),
+        Limits {
+            installs: 1,
+            downloads: 8
+        }
+    );
+}
+
+#[test]
+fn test_run_concurrently_one_at_a_time() {
+    let done = Mutex::new(Vec::new());
+    let result = run_concurrently(&[1, 2, 3, 4], 1, |item| {
+        if *item == 3 {
+            return Err("3 failed".into());
+        }
+        done.lock().unwrap().push(*item);
+        Ok(())
+    });
+
+    // The tasks run in order, and none runs after a failure
+    assert_eq!(result.unwrap_err().to_string(), "3 failed");
+    assert_eq!(*done.lock().unwrap(), vec![1, 2]);
+}
+
+#[test]
+fn test_run_concurrently_bounds_running_tasks() {
+    let limits = Limits {
+        installs: 1,
+        downloads: 3,
+    };
+    let running = AtomicUsize::new(0);
+    let most_running = AtomicUsize::new(0);
+    let hosts = Mutex::new(Vec::new());
+
+    with_runner(Arc::new(ubuntu_host()), || {
+        with_limits(limits, || {
+            run_concurrently(&[0; 8], current_limits().downloads, |_| {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                most_running.fetch_max(now, Ordering::SeqCst);
+                // The worker threads act on the same host with the same limits
+                hosts
+                    .lock()
+                    .unwrap()
+                    .push(current_runner().host().to_string());
+                assert_eq!(current_limits(), limits);
+                std::thread::sleep(Duration::from_millis(20));
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(())
+            })
+        })
+    })
+    .unwrap();
+
+    assert!(most_running.load(Ordering::SeqCst) <= 3);
+    assert_eq!(*hosts.lock().unwrap(), vec!["web1"; 8]);
+}
+
+#[test]
+fn test_run_concurrently_reports_failures() {
+    let result = run_concurrently(
+        &["a", "b"],
+        2,
+        |item| Err(format!("{} failed", item).into()),
+    );
+    let message = result.unwrap_err().to_string();
+    assert!(message.contains("failed"), "{}", message);
+}
+
+#[test]
+fn test_plan_installs_within_limit() {
+    let host = Arc::new(ubuntu_host());
+    let mut plan = Plan::new();
+    plan.install(&["nginx", "curl", "git"]);
+    let limits = Limits {
+        installs: 2,
+        ..Default::default()
+    };
+    with_runner(host.clone(), || with_limits(limits, || plan.execute())).unwrap();
+
+    let commands = host.log();
+    for package in ["nginx", "curl", "git"] {
+        assert!(commands.contains(&format!("apt install -y {}", package)));
+    }
+}
+
+#[test]
+fn test_proxy_env_is_per_run() {
+    let mut config = Config {
+        http_proxy: Some(String::from("http://proxy.internal:3128")),
+        ..Default::default()
+    };
+    let host = Arc::new(ubuntu_host());
+    with_runner(host, || {
+        configure_proxy(&config).unwrap();
+        let env = command_env();
+        assert!(env.contains(&(
+            String::from("http_proxy"),
+            String::from("http://proxy.internal:3128")
+        )));
+
+        // Worker threads of the run use its proxy, other threads do not
+        run_concurrently(&[1, 2, 3], 2, |_| {
+            assert_eq!(command_env(), env);
+            Ok(())
+        })
+        .unwrap();
+        std::thread::spawn(|| assert!(command_env().is_empty()))
+            .join()
+            .unwrap();
+
+        // A later run without a proxy does not inherit it
+        config.http_proxy = None;
+        configure_proxy(&config).unwrap();
+        assert!(command_env().is_empty());
+    });
+}
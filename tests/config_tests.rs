@@ -1,14 +1,18 @@
 #[cfg(test)]
 mod config_tests {
     use super::*;
-    use server_forge::config::Config;
+    use server_forge::config::{
+        Config, FieldChange, MaintenanceWindow, Scheduler, Secrets, ServerRole, VaultConfig,
+        WebServerConfigMode,
+    };
 
     #[test]
     fn test_config_default() {
         let config = Config::default();
         assert_eq!(config.linux_distro, "ubuntu");
-        assert_eq!(config.server_role, "");
+        assert_eq!(config.server_roles, Vec::<ServerRole>::new());
         assert_eq!(config.security_level, "");
+        assert_eq!(config.security_scan_schedule, "weekly");
         assert_eq!(config.monitoring, false);
         assert_eq!(config.backup_frequency, "daily");
         assert_eq!(config.deployed_apps, Vec::<String>::new());
@@ -16,13 +20,19 @@ mod config_tests {
         assert_eq!(config.update_schedule, "weekly");
         assert_eq!(config.use_containers, false);
         assert_eq!(config.use_kubernetes, false);
+        assert_eq!(config.scheduler, Scheduler::Cron);
+        assert_eq!(config.web_server_config_mode, WebServerConfigMode::Replace);
+        assert_eq!(config.grafana_port, 3000);
+        assert_eq!(config.prometheus_port, 9090);
+        assert_eq!(config.prometheus_retention, "15d");
+        assert_eq!(config.prometheus_storage_path, None);
     }
 
     #[test]
     fn test_config_custom() {
         let config = Config {
             linux_distro: "centos".to_string(),
-            server_role: "web".to_string(),
+            server_roles: vec![ServerRole::Web],
             security_level: "high".to_string(),
             monitoring: true,
             backup_frequency: "hourly".to_string(),
@@ -31,10 +41,11 @@ mod config_tests {
             update_schedule: "daily".to_string(),
             use_containers: true,
             use_kubernetes: true,
+            ..Config::default()
         };
 
         assert_eq!(config.linux_distro, "centos");
-        assert_eq!(config.server_role, "web");
+        assert_eq!(config.server_roles, vec![ServerRole::Web]);
         assert_eq!(config.security_level, "high");
         assert_eq!(config.monitoring, true);
         assert_eq!(config.backup_frequency, "hourly");
@@ -49,14 +60,14 @@ mod config_tests {
     fn test_config_clone() {
         let config1 = Config {
             linux_distro: "fedora".to_string(),
-            server_role: "database".to_string(),
+            server_roles: vec![ServerRole::Database],
             ..Config::default()
         };
 
         let config2 = config1.clone();
 
         assert_eq!(config1.linux_distro, config2.linux_distro);
-        assert_eq!(config1.server_role, config2.server_role);
+        assert_eq!(config1.server_roles, config2.server_roles);
         assert_eq!(config1.security_level, config2.security_level);
         assert_eq!(config1.monitoring, config2.monitoring);
         assert_eq!(config1.backup_frequency, config2.backup_frequency);
@@ -71,7 +82,7 @@ mod config_tests {
     fn test_config_serialization() {
         let config = Config {
             linux_distro: "debian".to_string(),
-            server_role: "application".to_string(),
+            server_roles: vec![ServerRole::Application],
             security_level: "medium".to_string(),
             monitoring: true,
             backup_frequency: "weekly".to_string(),
@@ -80,13 +91,14 @@ mod config_tests {
             update_schedule: "monthly".to_string(),
             use_containers: true,
             use_kubernetes: false,
+            ..Config::default()
         };
 
         let serialized = serde_json::to_string(&config).unwrap();
         let deserialized: Config = serde_json::from_str(&serialized).unwrap();
 
         assert_eq!(config.linux_distro, deserialized.linux_distro);
-        assert_eq!(config.server_role, deserialized.server_role);
+        assert_eq!(config.server_roles, deserialized.server_roles);
         assert_eq!(config.security_level, deserialized.security_level);
         assert_eq!(config.monitoring, deserialized.monitoring);
         assert_eq!(config.backup_frequency, deserialized.backup_frequency);
@@ -99,4 +111,342 @@ mod config_tests {
         assert_eq!(config.use_containers, deserialized.use_containers);
         assert_eq!(config.use_kubernetes, deserialized.use_kubernetes);
     }
+
+    #[test]
+    fn test_config_secrets_not_serialized() {
+        let config = Config {
+            monitoring: true,
+            secrets: Secrets {
+                grafana_admin_password: Some("grafana-s3cret".to_string()),
+                notification_webhook: Some("https://hooks.example.com/T0/B0/s3cret".to_string()),
+                vault_token: Some("vault-s3cret".to_string()),
+                vault_secret_id: Some("approle-s3cret".to_string()),
+            },
+            ..Config::default()
+        };
+
+        for serialized in [
+            serde_json::to_string(&config).unwrap(),
+            serde_yaml::to_string(&config).unwrap(),
+            toml::to_string(&config).unwrap(),
+        ] {
+            assert!(serialized.contains("monitoring"));
+            assert!(!serialized.contains("s3cret"), "{}", serialized);
+            assert!(!serialized.contains("grafana_admin_password"));
+        }
+
+        // Secrets are still read as top-level options, and listed by `options`
+        let deserialized: Config = serde_json::from_str(
+            r#"{"grafana_admin_password": "grafana-s3cret", "vault_token": "vault-s3cret"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            deserialized.secrets.grafana_admin_password.as_deref(),
+            Some("grafana-s3cret")
+        );
+        assert_eq!(
+            deserialized.secrets.vault_token.as_deref(),
+            Some("vault-s3cret")
+        );
+        let options = config.options().unwrap();
+        assert_eq!(options["vault_secret_id"], "approle-s3cret");
+        assert_eq!(options["monitoring"], true);
+    }
+
+    #[test]
+    fn test_config_single_server_role() {
+        let config: Config = serde_json::from_str(r#"{"server_role": "database"}"#).unwrap();
+        assert_eq!(config.server_roles, vec![ServerRole::Database]);
+
+        let config: Config = serde_json::from_str(r#"{"server_role": ""}"#).unwrap();
+        assert!(config.server_roles.is_empty());
+
+        let config: Config =
+            serde_json::from_str(r#"{"server_roles": ["web", "database"]}"#).unwrap();
+        assert_eq!(
+            config.server_roles,
+            vec![ServerRole::Web, ServerRole::Database]
+        );
+
+        assert!(serde_json::from_str::<Config>(r#"{"server_role": "mail"}"#).is_err());
+    }
+
+    #[test]
+    fn test_config_diff() {
+        let old = Config {
+            grafana_port: 3000,
+            deployed_apps: vec!["nginx".to_string(), "php".to_string()],
+            secrets: Secrets {
+                grafana_admin_password: Some("old-secret".to_string()),
+                ..Secrets::default()
+            },
+            ..Config::default()
+        };
+        let new = Config {
+            grafana_port: 3001,
+            deployed_apps: vec!["nginx".to_string(), "redis".to_string()],
+            secrets: Secrets {
+                grafana_admin_password: Some("new-secret".to_string()),
+                ..Secrets::default()
+            },
+            timezone: Some("Europe/Berlin".to_string()),
+            ..Config::default()
+        };
+
+        let changes = old.diff(&new);
+        let fields: Vec<&str> = changes.iter().map(|change| change.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            vec![
+                "deployed_apps",
+                "grafana_admin_password",
+                "grafana_port",
+                "timezone"
+            ]
+        );
+        assert_eq!(
+            changes[2],
+            FieldChange {
+                field: "grafana_port".to_string(),
+                old: serde_json::json!(3000),
+                new: serde_json::json!(3001),
+            }
+        );
+
+        let lines: Vec<String> = changes.iter().map(|change| change.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "deployed_apps: added redis; removed php",
+                "grafana_admin_password: changed",
+                "grafana_port: 3000 → 3001",
+                "timezone: (none) → Europe/Berlin",
+            ]
+        );
+
+        assert!(new.diff(&new.clone()).is_empty());
+    }
+
+    #[test]
+    fn test_config_from_vars() {
+        let vars = |pairs: &[(&str, &str)]| {
+            Config::from_vars(
+                pairs
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string())),
+            )
+        };
+
+        assert!(vars(&[("PATH", "/usr/bin")]).unwrap().is_none());
+
+        let config = vars(&[
+            ("SERVER_FORGE_DISTRO", "fedora"),
+            ("SERVER_FORGE_ROLE", "web"),
+            ("SERVER_FORGE_APPS", "nginx, php"),
+            ("SERVER_FORGE_MONITORING", "true"),
+            ("SERVER_FORGE_GRAFANA_PORT", "3001"),
+            ("SERVER_FORGE_GRAFANA_ADMIN_PASSWORD", "12345678"),
+            ("SERVER_FORGE_SWAP_SIZE_MB", "2048"),
+            ("SERVER_FORGE_ESSENTIAL_PACKAGES", "[curl, vim]"),
+            (
+                "SERVER_FORGE_GIT_APPS",
+                "[{repo_url: https://git.example.com/shop.git, target_dir: /srv/shop, run_command: ./shop}]",
+            ),
+            ("SERVER_FORGE_SCHEDULER", "systemd_timer"),
+            ("SERVER_FORGE_WEB_SERVER_CONFIG_MODE", "add_vhost"),
+            ("HOME", "/root"),
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(config.linux_distro, "fedora");
+        assert_eq!(config.server_roles, vec![ServerRole::Web]);
+        assert_eq!(config.deployed_apps, vec!["nginx", "php"]);
+        assert!(config.monitoring);
+        assert_eq!(config.grafana_port, 3001);
+        assert_eq!(
+            config.secrets.grafana_admin_password.as_deref(),
+            Some("12345678")
+        );
+        assert_eq!(config.swap_size_mb, Some(2048));
+        assert_eq!(
+            config.essential_packages,
+            Some(vec![String::from("curl"), String::from("vim")])
+        );
+        assert_eq!(config.git_apps[0].branch, "main");
+        assert_eq!(config.scheduler, Scheduler::SystemdTimer);
+        assert_eq!(config.web_server_config_mode, WebServerConfigMode::AddVhost);
+        // Unset options keep their defaults
+        assert_eq!(config.security_scan_schedule, "weekly");
+
+        let config = vars(&[("SERVER_FORGE_ROLES", "web,database")])
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            config.server_roles,
+            vec![ServerRole::Web, ServerRole::Database]
+        );
+
+        let Err(error) = vars(&[("SERVER_FORGE_MONITORNG", "true")]) else {
+            panic!("An unknown variable was accepted");
+        };
+        assert_eq!(
+            error.to_string(),
+            "Unknown configuration variable SERVER_FORGE_MONITORNG"
+        );
+        let Err(error) = vars(&[("SERVER_FORGE_GRAFANA_PORT", "high")]) else {
+            panic!("An invalid port was accepted");
+        };
+        assert!(error
+            .to_string()
+            .starts_with("Invalid value for SERVER_FORGE_GRAFANA_PORT: "));
+    }
+
+    #[test]
+    fn test_config_validate() {
+        let mut config = Config {
+            custom_firewall_rules: vec![
+                String::from("80/tcp"),
+                String::from("6000:6007/udp"),
+                String::from("http"),
+                String::from("from 10.0.0.0/8 to any port 5432"),
+            ],
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        config.custom_firewall_rules.push(String::from("8080/tpc"));
+        let Err(error) = config.validate() else {
+            panic!("An invalid firewall rule was accepted");
+        };
+        assert_eq!(
+            error.to_string(),
+            "Invalid custom firewall rule #5 '8080/tpc': unknown protocol 'tpc' (expected tcp or udp)"
+        );
+
+        let config = Config {
+            ssh_port: 0,
+            update_schedule: String::from("hourly"),
+            auto_reboot_time: Some(String::from("3am")),
+            update_blacklist: vec![String::from("linux-image-generic"), String::from(" ")],
+            maintenance_window: Some(MaintenanceWindow {
+                start: String::from("02:00"),
+                days: vec![String::from("weekend")],
+            }),
+            vault: Some(VaultConfig {
+                address: String::from("vault.internal:8200"),
+                mount: String::from("secret"),
+                path: String::from("serverforge"),
+                role_id: None,
+            }),
+            prometheus_retention: String::from("30 days"),
+            prometheus_storage_path: Some(String::from("prometheus")),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.validation_errors(),
+            vec![
+                "Invalid ssh_port 0: expected a port from 1 to 65535",
+                "Invalid update_schedule 'hourly': expected daily, weekly, monthly",
+                "Invalid auto_reboot_time '3am': expected a time such as 03:00",
+                "Invalid update_blacklist entry #2: the package name is empty",
+                "Invalid maintenance_window day 'weekend': expected one of sun, mon, tue, wed, thu, fri, sat",
+                "Invalid vault address 'vault.internal:8200': expected an http:// or https:// URL",
+                "Invalid prometheus_retention '30 days': expected a duration such as 15d or 12w",
+                "Invalid prometheus_storage_path 'prometheus': expected an absolute path",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+
+        std::fs::write(
+            path("server.yaml"),
+            "linux_distro: fedora\nserver_role: web\nmonitoring: true\n",
+        )
+        .unwrap();
+        let config = Config::from_file(&path("server.yaml")).unwrap();
+        assert_eq!(config.linux_distro, "fedora");
+        assert_eq!(config.server_roles, vec![ServerRole::Web]);
+        assert!(config.monitoring);
+        assert_eq!(config.security_level, Config::default().security_level);
+
+        std::fs::write(
+            path("server.toml"),
+            "server_roles = [\"web\", \"database\"]\ngrafana_port = 3001\n",
+        )
+        .unwrap();
+        let config = Config::from_file(&path("server.toml")).unwrap();
+        assert_eq!(
+            config.server_roles,
+            vec![ServerRole::Web, ServerRole::Database]
+        );
+        assert_eq!(config.grafana_port, 3001);
+
+        std::fs::write(
+            path("server.json"),
+            r#"{"deployed_apps": ["nginx"], "notification_webhook": "https://hooks.example.com"}"#,
+        )
+        .unwrap();
+        let config = Config::from_file(&path("server.json")).unwrap();
+        assert_eq!(config.deployed_apps, vec!["nginx"]);
+        assert_eq!(
+            config.secrets.notification_webhook.as_deref(),
+            Some("https://hooks.example.com")
+        );
+
+        std::fs::write(path("typo.yaml"), "monitoing: true\n").unwrap();
+        let Err(error) = Config::from_file(&path("typo.yaml")) else {
+            panic!("An unknown option was accepted");
+        };
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Invalid configuration {}: unknown option 'monitoing'",
+                path("typo.yaml")
+            )
+        );
+    }
+
+    #[test]
+    fn test_config_file_over_env() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir
+            .path()
+            .join("server.yaml")
+            .to_string_lossy()
+            .into_owned();
+        std::fs::write(&path, "linux_distro: fedora\ngrafana_port: 3001\n").unwrap();
+
+        let env = Config::from_vars([
+            (String::from("SERVER_FORGE_DISTRO"), String::from("debian")),
+            (
+                String::from("SERVER_FORGE_MONITORING"),
+                String::from("true"),
+            ),
+            (
+                String::from("SERVER_FORGE_GRAFANA_ADMIN_PASSWORD"),
+                String::from("12345678"),
+            ),
+        ])
+        .unwrap()
+        .unwrap();
+        let config = env.with_file(&path).unwrap();
+
+        // The file takes precedence over the environment
+        assert_eq!(config.linux_distro, "fedora");
+        assert_eq!(config.grafana_port, 3001);
+        // Options missing from the file keep the value of the environment, then the default
+        assert!(config.monitoring);
+        assert_eq!(
+            config.secrets.grafana_admin_password.as_deref(),
+            Some("12345678")
+        );
+        assert_eq!(config.security_level, Config::default().security_level);
+
+        std::fs::write(&path, "monitoing: true\n").unwrap();
+        assert!(env.with_file(&path).is_err());
+    }
 }
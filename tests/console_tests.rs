@@ -0,0 +1,94 @@
+use log::Level;
+use server_forge::console::{format_line, StepStatus};
+
+#[test]
+fn test_format_line_without_colors() {
+    assert_eq!(
+        format_line(
+            Level::Info,
+            "server_forge::pipeline",
+            None,
+            "Running phase: Monitoring",
+            false
+        ),
+        "Running phase: Monitoring"
+    );
+    assert_eq!(
+        format_line(
+            Level::Warn,
+            "server_forge::pipeline",
+            Some("web1"),
+            "Continuing",
+            false
+        ),
+        "warning: [web1] Continuing"
+    );
+    assert_eq!(
+        format_line(Level::Error, "server_forge", None, "Setup failed", false),
+        "error: Setup failed"
+    );
+    assert_eq!(
+        format_line(
+            Level::Info,
+            StepStatus::Completed.target(),
+            Some("web1"),
+            "Monitoring completed",
+            false
+        ),
+        "✓ [web1] Monitoring completed"
+    );
+    assert_eq!(
+        format_line(
+            Level::Error,
+            StepStatus::Failed.target(),
+            None,
+            "Error during Monitoring",
+            false
+        ),
+        "✗ Error during Monitoring"
+    );
+}
+
+#[test]
+fn test_format_line_with_colors() {
+    let line = format_line(
+        Level::Info,
+        StepStatus::Completed.target(),
+        None,
+        "Monitoring completed",
+        true,
+    );
+    assert_eq!(line, "\u{1b}[32m✓ Monitoring completed\u{1b}[39m");
+
+    let line = format_line(
+        Level::Info,
+        StepStatus::Skipped.target(),
+        None,
+        "Skipping",
+        true,
+    );
+    assert!(line.starts_with("\u{1b}[33m"));
+
+    let line = format_line(Level::Error, "server_forge", None, "Setup failed", true);
+    assert!(line.contains("\u{1b}[31m"));
+    assert!(line.ends_with(" Setup failed"));
+
+    // Plain information is never colored
+    assert_eq!(
+        format_line(Level::Info, "server_forge", None, "Configuring web1", true),
+        "Configuring web1"
+    );
+}
+
+#[test]
+fn test_step_status_targets() {
+    for status in [
+        StepStatus::Completed,
+        StepStatus::Skipped,
+        StepStatus::Failed,
+    ] {
+        assert_eq!(StepStatus::from_target(status.target()), Some(status));
+    }
+    assert_eq!(StepStatus::from_target("server_forge::pipeline"), None);
+    assert_eq!(StepStatus::Failed.paint("failed", false), "failed");
+}
@@ -1,11 +1,15 @@
 use server_forge::config::Config;
-use server_forge::containerization;
+use server_forge::containerization::{self, ContainerSpec};
+use server_forge::distro::PackageManager;
 use server_forge::rollback::RollbackManager;
 use std::fs;
+use tempfile::tempdir;
 
 #[test]
 fn test_install_docker() {
-    assert!(containerization::install_docker().is_ok());
+    let rollback = RollbackManager::new();
+    let snapshot = rollback.create_snapshot("Docker").unwrap();
+    assert!(containerization::install_docker(&Config::default(), &rollback, snapshot).is_ok());
 
     // Verify Docker installation
     let docker_status = std::process::Command::new("docker")
@@ -22,6 +26,53 @@ fn test_install_docker() {
     assert!(service_status.success());
 }
 
+#[test]
+fn test_docker_repo_arch() {
+    let arch = containerization::docker_repo_arch;
+    assert_eq!(arch(&PackageManager::Apt, "amd64").unwrap(), "amd64");
+    assert_eq!(arch(&PackageManager::Apt, "arm64").unwrap(), "arm64");
+    assert_eq!(arch(&PackageManager::Apt, "armv7").unwrap(), "armhf");
+    assert_eq!(arch(&PackageManager::Dnf, "arm64").unwrap(), "aarch64");
+    assert_eq!(arch(&PackageManager::Yum, "amd64").unwrap(), "x86_64");
+    assert_eq!(
+        arch(&PackageManager::Yum, "armv7").unwrap_err().to_string(),
+        "Docker CE is not published for armv7 on Red Hat-based distributions"
+    );
+}
+
+#[test]
+fn test_docker_repository() {
+    let config = Config::default();
+    let apt = containerization::docker_repository(&config, &PackageManager::Apt, "arm64");
+    assert_eq!(apt.url, "https://download.docker.com/linux/ubuntu");
+    assert_eq!(
+        apt.gpg_key_url,
+        "https://download.docker.com/linux/ubuntu/gpg"
+    );
+    assert_eq!(apt.arch.as_deref(), Some("arm64"));
+    assert_eq!(apt.codename, None);
+
+    let dnf = containerization::docker_repository(&config, &PackageManager::Dnf, "aarch64");
+    assert_eq!(
+        dnf.url,
+        "https://download.docker.com/linux/fedora/$releasever/$basearch/stable"
+    );
+    assert_eq!(
+        dnf.gpg_key_url,
+        "https://download.docker.com/linux/fedora/gpg"
+    );
+    assert_eq!(dnf.arch, None);
+
+    // Both the repository and its key come from the mirror when one is configured
+    let mirrored = Config {
+        download_base_url: Some(String::from("https://mirror.internal")),
+        ..Default::default()
+    };
+    let yum = containerization::docker_repository(&mirrored, &PackageManager::Yum, "x86_64");
+    assert!(yum.url.starts_with("https://mirror.internal/"));
+    assert!(yum.gpg_key_url.starts_with("https://mirror.internal/"));
+}
+
 #[test]
 fn test_configure_docker() {
     assert!(containerization::configure_docker().is_ok());
@@ -35,7 +86,9 @@ fn test_configure_docker() {
 
 #[test]
 fn test_install_kubernetes() {
-    assert!(containerization::install_kubernetes().is_ok());
+    let rollback = RollbackManager::new();
+    let snapshot = rollback.create_snapshot("Kubernetes").unwrap();
+    assert!(containerization::install_kubernetes(&Config::default(), &rollback, snapshot).is_ok());
 
     // Verify kubectl installation
     let kubectl_status = std::process::Command::new("kubectl")
@@ -76,7 +129,7 @@ fn test_configure_kubernetes() {
 #[test]
 fn test_deploy_to_docker() {
     let test_app = "nginx";
-    assert!(containerization::deploy_to_docker(test_app).is_ok());
+    assert!(containerization::deploy_to_docker(test_app, &ContainerSpec::default()).is_ok());
 
     // Verify container is running
     let container_status = std::process::Command::new("docker")
@@ -89,7 +142,14 @@ fn test_deploy_to_docker() {
 #[test]
 fn test_deploy_to_kubernetes() {
     let test_app = "nginx";
-    assert!(containerization::deploy_to_kubernetes(test_app).is_ok());
+    let manifest_dir = tempdir().unwrap();
+    assert!(containerization::deploy_to_kubernetes(
+        test_app,
+        &ContainerSpec::default(),
+        manifest_dir.path().to_str().unwrap()
+    )
+    .is_ok());
+    assert!(manifest_dir.path().join("nginx-deployment.yaml").exists());
 
     // Verify deployment is created
     let deployment_status = std::process::Command::new("kubectl")
@@ -109,7 +169,7 @@ fn test_deploy_to_kubernetes() {
 #[test]
 fn test_setup_docker() {
     let rollback_manager = RollbackManager::new();
-    assert!(containerization::setup_docker(&rollback_manager).is_ok());
+    assert!(containerization::setup_docker(&Config::default(), &rollback_manager).is_ok());
 
     // Verify Docker is installed and configured
     assert!(std::process::Command::new("docker")
@@ -122,7 +182,7 @@ fn test_setup_docker() {
 #[test]
 fn test_setup_kubernetes() {
     let rollback_manager = RollbackManager::new();
-    assert!(containerization::setup_kubernetes(&rollback_manager).is_ok());
+    assert!(containerization::setup_kubernetes(&Config::default(), &rollback_manager).is_ok());
 
     // Verify Kubernetes is installed and configured
     assert!(std::process::Command::new("kubectl")
@@ -152,3 +212,88 @@ fn test_deploy_containers() {
         assert!(deployment_status.success());
     }
 }
+
+#[test]
+fn test_docker_run_args() {
+    assert_eq!(
+        containerization::docker_run_args("nginx", &ContainerSpec::default()),
+        vec![
+            "run",
+            "-d",
+            "--name",
+            "nginx",
+            "-p",
+            "80:80",
+            "--memory",
+            "512m",
+            "--cpus",
+            "1",
+            "--restart",
+            "unless-stopped",
+            "--network",
+            "server_forge_net",
+            "nginx",
+        ]
+    );
+}
+
+#[test]
+fn test_kubernetes_deployment_resources() {
+    let spec = ContainerSpec {
+        cpu_limit: 1.5,
+        ..Default::default()
+    };
+    let yaml = containerization::kubernetes_deployment_yaml("nginx", &spec);
+    assert!(yaml.contains(
+        "        resources:\n          requests:\n            memory: 256Mi\n            cpu: 250m\n          limits:\n            memory: 512Mi\n            cpu: 1500m\n"
+    ));
+    let deployment: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+    assert_eq!(
+        deployment["spec"]["template"]["spec"]["containers"][0]["image"],
+        "nginx:latest"
+    );
+}
+
+#[test]
+fn test_container_volumes() {
+    let spec = ContainerSpec {
+        volumes: vec![
+            (
+                String::from("pg_data"),
+                String::from("/var/lib/postgresql/data"),
+            ),
+            (String::from("/srv/backups"), String::from("/backups")),
+        ],
+        ..Default::default()
+    };
+
+    let args = containerization::docker_run_args("postgres", &spec);
+    assert_eq!(
+        args[args.len() - 5..],
+        [
+            "-v",
+            "pg_data:/var/lib/postgresql/data",
+            "-v",
+            "/srv/backups:/backups",
+            "postgres",
+        ]
+    );
+
+    let yaml = containerization::kubernetes_deployment_yaml("postgres", &spec);
+    let documents: Vec<serde_yaml::Value> = serde_yaml::Deserializer::from_str(&yaml)
+        .map(|document| serde::Deserialize::deserialize(document).unwrap())
+        .collect();
+    assert_eq!(documents.len(), 2);
+    let pod = &documents[0]["spec"]["template"]["spec"];
+    assert_eq!(
+        pod["containers"][0]["volumeMounts"][0]["mountPath"],
+        "/var/lib/postgresql/data"
+    );
+    assert_eq!(
+        pod["volumes"][0]["persistentVolumeClaim"]["claimName"],
+        "postgres-pg-data"
+    );
+    assert_eq!(pod["volumes"][1]["hostPath"]["path"], "/srv/backups");
+    assert_eq!(documents[1]["kind"], "PersistentVolumeClaim");
+    assert_eq!(documents[1]["metadata"]["name"], "postgres-pg-data");
+}
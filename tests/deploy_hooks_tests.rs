@@ -0,0 +1,151 @@
+use crate::common::RecordingRunner;
+use server_forge::config::{Config, DeployHooks, GitApp, HookFailurePolicy};
+use server_forge::deployment::{deploy_git_application, deploy_with_hooks};
+use server_forge::rollback::RollbackManager;
+use server_forge::runner::with_runner;
+use std::sync::Arc;
+
+fn hooks(pre_deploy: &[&str], post_deploy: &[&str]) -> DeployHooks {
+    DeployHooks {
+        pre_deploy: pre_deploy
+            .iter()
+            .map(|command| command.to_string())
+            .collect(),
+        post_deploy: post_deploy
+            .iter()
+            .map(|command| command.to_string())
+            .collect(),
+        post_deploy_failure: HookFailurePolicy::Abort,
+    }
+}
+
+#[test]
+fn test_hooks_run_around_the_deployment() {
+    let runner = Arc::new(RecordingRunner::new("web1"));
+    let hooks = hooks(&["./backup-db"], &["./migrate", "./warm-cache"]);
+
+    with_runner(runner.clone(), || {
+        deploy_with_hooks("shop", &hooks, None, || {
+            server_forge::utils::run_command("deploy", &["shop"])
+        })
+    })
+    .unwrap();
+
+    assert_eq!(
+        runner.log(),
+        vec![
+            "sh -c ./backup-db",
+            "deploy shop",
+            "sh -c ./migrate",
+            "sh -c ./warm-cache",
+        ]
+    );
+}
+
+#[test]
+fn test_failing_pre_deploy_hook_skips_the_deployment() {
+    let runner = Arc::new(RecordingRunner::new("web1").fail("sh -c ./backup-db"));
+    let hooks = hooks(&["./backup-db", "./notify"], &["./migrate"]);
+    let mut deployed = false;
+
+    let error = with_runner(runner.clone(), || {
+        deploy_with_hooks("shop", &hooks, None, || {
+            deployed = true;
+            Ok(())
+        })
+    })
+    .unwrap_err();
+
+    assert!(error
+        .to_string()
+        .starts_with("The pre_deploy hook './backup-db' of shop failed"));
+    assert!(!deployed);
+    assert_eq!(runner.log(), vec!["sh -c ./backup-db"]);
+}
+
+#[test]
+fn test_post_deploy_failure_policy() {
+    let mut hooks = hooks(&[], &["./migrate", "./warm-cache"]);
+
+    let runner = Arc::new(RecordingRunner::new("web1").fail("sh -c ./migrate"));
+    let result = with_runner(runner.clone(), || {
+        deploy_with_hooks("shop", &hooks, None, || Ok(()))
+    });
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .starts_with("The post_deploy hook './migrate' of shop failed"));
+
+    // With the warn policy the deployment is kept, but the remaining hooks are skipped
+    hooks.post_deploy_failure = HookFailurePolicy::Warn;
+    let runner = Arc::new(RecordingRunner::new("web1").fail("sh -c ./migrate"));
+    with_runner(runner.clone(), || {
+        deploy_with_hooks("shop", &hooks, None, || Ok(()))
+    })
+    .unwrap();
+    assert_eq!(runner.log(), vec!["sh -c ./migrate"]);
+}
+
+#[test]
+fn test_git_app_post_deploy_hooks_run_in_the_target_dir() {
+    let app = GitApp {
+        repo_url: String::from("https://git.example.com/shop.git"),
+        branch: String::from("main"),
+        target_dir: String::from("/srv/shop/"),
+        build_command: None,
+        run_command: String::from("./shop"),
+        hooks: hooks(&["./backup-db"], &["./migrate"]),
+    };
+    let runner = Arc::new(RecordingRunner::ubuntu("web1"));
+
+    with_runner(runner.clone(), || {
+        deploy_git_application(&app, &RollbackManager::new())
+    })
+    .unwrap();
+
+    let log = runner.log();
+    assert_eq!(log.first().unwrap(), "sh -c ./backup-db");
+    assert_eq!(log.last().unwrap(), "sh -c cd '/srv/shop' && ./migrate");
+}
+
+#[test]
+fn test_deploy_hooks_config() {
+    let config: Config = serde_yaml::from_str(
+        r#"
+deployed_apps: [nginx]
+deploy_hooks:
+  nginx:
+    pre_deploy: ["nginx -t"]
+    post_deploy_failure: warn
+git_apps:
+  - repo_url: https://git.example.com/shop.git
+    target_dir: /srv/shop
+    run_command: ./shop
+    post_deploy: ["./migrate"]
+"#,
+    )
+    .unwrap();
+
+    let nginx = &config.deploy_hooks["nginx"];
+    assert_eq!(nginx.pre_deploy, vec!["nginx -t"]);
+    assert!(nginx.post_deploy.is_empty());
+    assert_eq!(nginx.post_deploy_failure, HookFailurePolicy::Warn);
+    assert_eq!(config.git_apps[0].hooks, hooks(&[], &["./migrate"]));
+    assert!(config.validate().is_ok());
+}
+
+#[test]
+fn test_deploy_hooks_must_belong_to_a_deployed_app() {
+    let mut config = Config {
+        deployed_apps: vec![String::from("nginx")],
+        ..Config::default()
+    };
+    config
+        .deploy_hooks
+        .insert(String::from("mysql"), hooks(&["./backup-db"], &[]));
+
+    let errors = config.validation_errors();
+    assert!(errors.contains(&String::from(
+        "Invalid deploy_hooks entry 'mysql': the application is not in deployed_apps"
+    )));
+}
@@ -1,6 +1,11 @@
-use server_forge::config::Config;
+use crate::common::{MockFileSystem, RecordingRunner};
+use server_forge::config::{Config, DeployHooks, GitApp, ServerRole, WebServerConfigMode};
 use server_forge::deployment;
+use server_forge::filesystem::with_filesystem;
+use server_forge::plan::{Operation, Plan};
 use server_forge::rollback::RollbackManager;
+use server_forge::runner::with_runner;
+use std::sync::Arc;
 
 #[test]
 fn test_deploy_nginx() {
@@ -52,7 +57,7 @@ fn test_deploy_apache() {
 
 #[test]
 fn test_deploy_mysql() {
-    assert!(deployment::deploy_mysql().is_ok());
+    assert!(deployment::deploy_mysql(&Config::default()).is_ok());
 
     // Verify MySQL installation
     let mysql_status = std::process::Command::new("which")
@@ -90,8 +95,7 @@ fn test_deploy_postgresql() {
 
 #[test]
 fn test_deploy_php() {
-    let server_role = "web";
-    assert!(deployment::deploy_php(server_role).is_ok());
+    assert!(deployment::deploy_php(&[ServerRole::Web]).is_ok());
 
     // Verify PHP installation
     let php_status = std::process::Command::new("which")
@@ -122,3 +126,592 @@ fn test_deploy_applications() {
 
     assert!(deployment::deploy_applications(&config, &rollback_manager).is_ok());
 }
+
+#[test]
+fn test_generate_haproxy_config() {
+    let mut config = Config {
+        load_balancer_backends: vec![String::from("10.0.0.1:8080"), String::from("app2:8080")],
+        ..Default::default()
+    };
+
+    let haproxy_config = deployment::generate_haproxy_config(&config).unwrap();
+    assert!(haproxy_config.contains("bind *:80\n"));
+    assert!(!haproxy_config.contains("bind *:443"));
+    assert!(haproxy_config.contains("server app1 10.0.0.1:8080 check\n"));
+    assert!(haproxy_config.contains("server app2 app2:8080 check\n"));
+
+    config.load_balancer_certificate = Some(String::from("/etc/haproxy/site.pem"));
+    let haproxy_config = deployment::generate_haproxy_config(&config).unwrap();
+    assert!(haproxy_config.contains("bind *:443 ssl crt /etc/haproxy/site.pem\n"));
+
+    config.load_balancer_backends = vec![String::from("10.0.0.1")];
+    assert!(deployment::generate_haproxy_config(&config).is_err());
+
+    config.load_balancer_backends.clear();
+    assert!(deployment::generate_haproxy_config(&config).is_err());
+}
+
+#[test]
+fn test_plan_haproxy() {
+    let config = Config {
+        load_balancer_backends: vec![String::from("10.0.0.1:8080")],
+        ..Default::default()
+    };
+    let mut plan = Plan::new();
+    deployment::plan_app(&mut plan, "haproxy", &config).unwrap();
+
+    let operations = plan.operations();
+    let check = operations
+        .iter()
+        .position(|operation| {
+            *operation
+                == Operation::RunCommand {
+                    command: String::from("haproxy"),
+                    args: vec![
+                        String::from("-c"),
+                        String::from("-f"),
+                        String::from("/etc/haproxy/haproxy.cfg"),
+                    ],
+                }
+        })
+        .unwrap();
+    let reload = operations
+        .iter()
+        .position(|operation| {
+            *operation
+                == Operation::ReloadService {
+                    name: String::from("haproxy"),
+                }
+        })
+        .unwrap();
+    assert!(check < reload);
+}
+
+#[test]
+fn test_generate_reverse_proxy_config() {
+    let nginx =
+        deployment::generate_reverse_proxy_config("nginx", "127.0.0.1:3000", "app.example.com")
+            .unwrap();
+    assert!(nginx.contains("server_name app.example.com;"));
+    assert!(nginx.contains("proxy_pass http://127.0.0.1:3000;"));
+
+    let apache =
+        deployment::generate_reverse_proxy_config("apache", "127.0.0.1:5000", "app.example.com")
+            .unwrap();
+    assert!(apache.contains("ServerName app.example.com"));
+    assert!(apache.contains("ProxyPass / http://127.0.0.1:5000/"));
+
+    assert!(deployment::generate_reverse_proxy_config("nginx", "127.0.0.1", "app").is_err());
+    assert!(
+        deployment::generate_reverse_proxy_config("nginx", "127.0.0.1:3000", "../etc").is_err()
+    );
+    assert!(deployment::generate_reverse_proxy_config("caddy", "127.0.0.1:3000", "app").is_err());
+}
+
+#[test]
+fn test_plan_applications_with_reverse_proxy() {
+    let mut config = Config {
+        deployed_apps: vec![String::from("nginx"), String::from("nodejs")],
+        ..Default::default()
+    };
+
+    let proxies = |config: &Config| {
+        let mut plan = Plan::new();
+        deployment::plan_applications(&mut plan, config).unwrap();
+        plan.operations()
+            .iter()
+            .filter_map(|operation| match operation {
+                Operation::WriteFile { path, contents } => Some((path.clone(), contents.clone())),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // Without a domain there is nothing to proxy
+    assert!(proxies(&config).is_empty());
+
+    config.app_domain = Some(String::from("app.example.com"));
+    let files = proxies(&config);
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].0, "/etc/nginx/conf.d/app.example.com.conf");
+    assert!(files[0].1.contains("proxy_pass http://127.0.0.1:3000;"));
+}
+
+#[test]
+fn test_plan_git_app() {
+    let app = GitApp {
+        repo_url: String::from("https://git.example.com/shop.git"),
+        branch: String::from("release"),
+        target_dir: String::from("/srv/serverforge-test-shop/"),
+        build_command: Some(String::from("npm ci")),
+        run_command: String::from("node server.js"),
+        hooks: DeployHooks::default(),
+    };
+
+    let mut plan = Plan::new();
+    deployment::plan_git_app(&mut plan, &app).unwrap();
+    let operations = plan.operations();
+
+    assert!(operations.contains(&Operation::RunCommand {
+        command: String::from("git"),
+        args: [
+            "clone",
+            "--branch",
+            "release",
+            "https://git.example.com/shop.git",
+            "/srv/serverforge-test-shop",
+        ]
+        .map(String::from)
+        .to_vec(),
+    }));
+    assert!(operations.contains(&Operation::RunCommand {
+        command: String::from("sh"),
+        args: ["-c", "cd '/srv/serverforge-test-shop' && npm ci"]
+            .map(String::from)
+            .to_vec(),
+    }));
+
+    let unit = operations
+        .iter()
+        .find_map(|operation| match operation {
+            Operation::WriteFile { path, contents }
+                if path == "/etc/systemd/system/serverforge-test-shop.service" =>
+            {
+                Some(contents.clone())
+            }
+            _ => None,
+        })
+        .unwrap();
+    assert!(unit.contains("WorkingDirectory=/srv/serverforge-test-shop\n"));
+    assert!(unit.contains("ExecStart=/bin/sh -c 'node server.js'\n"));
+    assert_eq!(
+        operations.last(),
+        Some(&Operation::RestartService {
+            name: String::from("serverforge-test-shop")
+        })
+    );
+}
+
+#[test]
+fn test_git_app_service_name() {
+    let mut app = GitApp {
+        repo_url: String::from("https://git.example.com/shop.git"),
+        branch: String::from("main"),
+        target_dir: String::from("/srv/shop"),
+        build_command: None,
+        run_command: String::from("./shop"),
+        hooks: DeployHooks::default(),
+    };
+    assert_eq!(deployment::git_app_service_name(&app).unwrap(), "shop");
+
+    app.target_dir = String::from("srv/shop");
+    assert!(deployment::git_app_service_name(&app).is_err());
+
+    app.target_dir = String::from("/srv/my shop");
+    assert!(deployment::git_app_service_name(&app).is_err());
+}
+
+/// Returns the value a plan saves to a secret file, if it does.
+fn saved_secret(plan: &Plan, path: &str) -> Option<String> {
+    plan.operations()
+        .iter()
+        .find_map(|operation| match operation {
+            Operation::RunCommandWithInput {
+                command,
+                args,
+                input,
+            } if command == "sh" && args.last().is_some_and(|arg| arg == path) => {
+                Some(input.clone())
+            }
+            _ => None,
+        })
+}
+
+#[test]
+fn test_plan_mysql_is_non_interactive() {
+    let plan_on = |host: RecordingRunner| {
+        with_runner(Arc::new(host), || {
+            Plan::build(|plan| deployment::plan_app(plan, "mysql", &Config::default()))
+        })
+        .unwrap()
+    };
+
+    let plan = plan_on(fake_host(&["/usr/bin/apt"]));
+    let commands: Vec<&str> = plan
+        .operations()
+        .iter()
+        .filter_map(|operation| match operation {
+            Operation::RunCommand { command, .. } => Some(command.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(commands, vec!["mysql"]);
+    assert!(plan.operations().iter().any(|operation| matches!(
+        operation,
+        Operation::RunCommand { command, args }
+            if command == "mysql" && args[1].contains("DROP DATABASE IF EXISTS test")
+    )));
+    let contents = saved_secret(&plan, deployment::MYSQL_PASSWORD_FILE)
+        .expect("the root password is not saved");
+    // The root password is only passed on standard input
+    assert!(plan.operations().iter().any(|operation| matches!(
+        operation,
+        Operation::RunCommandWithInput { command, args, input }
+            if command == "mysql" && args.is_empty() && input.contains(&format!("IDENTIFIED BY '{}'", contents))
+    )));
+
+    // MySQL is only secured once
+    let plan = plan_on(fake_host(&[
+        "/usr/bin/apt",
+        deployment::MYSQL_PASSWORD_FILE,
+    ]));
+    assert!(!plan.operations().iter().any(|operation| matches!(
+        operation,
+        Operation::RunCommand { .. } | Operation::RunCommandWithInput { .. }
+    )));
+}
+
+#[test]
+fn test_plan_rabbitmq() {
+    let config = Config {
+        download_base_url: Some(String::from("https://mirror.internal")),
+        ..Default::default()
+    };
+    let plan_on = |host: RecordingRunner| {
+        with_runner(Arc::new(host), || {
+            Plan::build(|plan| deployment::plan_app(plan, "rabbitmq", &config))
+        })
+        .unwrap()
+    };
+
+    let plan = plan_on(fake_host(&["/usr/bin/apt"]));
+    let operations = plan.operations();
+    assert_eq!(
+        operations[1],
+        Operation::RunCommand {
+            command: String::from("sh"),
+            args: vec![
+                String::from("-c"),
+                String::from(
+                    "curl -1sLf 'https://mirror.internal/packagecloud.io/install/repositories/rabbitmq/rabbitmq-server/script.deb.sh' | bash"
+                ),
+            ],
+        }
+    );
+    assert!(operations.contains(&Operation::InstallPackages {
+        packages: vec![String::from("rabbitmq-server")],
+    }));
+    assert!(operations.contains(&Operation::RunCommand {
+        command: String::from("rabbitmq-plugins"),
+        args: vec![String::from("enable"), String::from("rabbitmq_management")],
+    }));
+    let password = operations
+        .iter()
+        .find_map(|operation| match operation {
+            Operation::RunCommand { command, args } if command == "rabbitmqctl" => {
+                Some(args.clone())
+            }
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(password[..2], ["add_user", "admin"]);
+    assert_eq!(
+        operations.last(),
+        Plan::new()
+            .write_private_file(deployment::RABBITMQ_PASSWORD_FILE, password[2].clone())
+            .operations()
+            .last()
+    );
+
+    // Re-deploying keeps the existing administrator
+    let plan = plan_on(fake_host(&[
+        "/usr/bin/dnf",
+        deployment::RABBITMQ_PASSWORD_FILE,
+    ]));
+    assert!(!plan.operations().iter().any(|operation| matches!(
+        operation,
+        Operation::RunCommand { command, .. } if command == "rabbitmqctl"
+    )));
+    assert!(matches!(
+        &plan.operations()[0],
+        Operation::RunCommand { args, .. } if args[1].contains("rabbitmq/erlang/script.rpm.sh")
+    ));
+}
+
+#[test]
+fn test_plan_memcached() {
+    let plan_on = |host: RecordingRunner, config: &Config| {
+        with_runner(Arc::new(host), || {
+            Plan::build(|plan| deployment::plan_app(plan, "memcached", config))
+        })
+    };
+
+    let plan = plan_on(fake_host(&["/usr/bin/apt"]), &Config::default()).unwrap();
+    let expected = Plan::new()
+        .install(&["memcached"])
+        .write_file(
+            "/etc/memcached.conf",
+            "-d\nlogfile /var/log/memcached.log\n-m 64\n-p 11211\n-u memcache\n-l 127.0.0.1\n\
+             -P /var/run/memcached/memcached.pid\n",
+        )
+        .enable_service("memcached")
+        .restart_service("memcached")
+        .clone();
+    assert_eq!(plan, expected);
+
+    let config = Config {
+        memcached_memory_mb: Some(512),
+        memcached_bind_address: Some(String::from("10.0.0.5")),
+        ..Default::default()
+    };
+    let plan = plan_on(fake_host(&["/usr/bin/dnf"]), &config).unwrap();
+    assert_eq!(
+        plan.operations()[1],
+        Operation::WriteFile {
+            path: String::from("/etc/sysconfig/memcached"),
+            contents: String::from(
+                "PORT=\"11211\"\nUSER=\"memcached\"\nMAXCONN=\"1024\"\nCACHESIZE=\"512\"\n\
+                 OPTIONS=\"-l 10.0.0.5\"\n"
+            ),
+        }
+    );
+
+    let config = Config {
+        memcached_bind_address: Some(String::from("0.0.0.0 -vv")),
+        ..Default::default()
+    };
+    assert!(plan_on(fake_host(&["/usr/bin/apt"]), &config).is_err());
+}
+
+#[test]
+fn test_plan_opensearch() {
+    let meminfo = |total_kb: u64| {
+        format!(
+            "MemTotal:       {} kB\nMemFree:         123456 kB\n",
+            total_kb
+        )
+    };
+    let plan_on = |host: RecordingRunner| {
+        with_runner(Arc::new(host), || {
+            Plan::build(|plan| deployment::plan_app(plan, "opensearch", &Config::default()))
+        })
+    };
+
+    let host = fake_host(&["/usr/bin/apt"]).with_file("/proc/meminfo", &meminfo(8 * 1024 * 1024));
+    let plan = plan_on(host).unwrap();
+    let operations = plan.operations();
+    let install = operations
+        .iter()
+        .find_map(|operation| match operation {
+            Operation::RunCommand { command, args } if command == "env" => Some(args.clone()),
+            _ => None,
+        })
+        .unwrap();
+    let password = install[0]
+        .strip_prefix("OPENSEARCH_INITIAL_ADMIN_PASSWORD=")
+        .unwrap();
+    assert_eq!(install[1..], ["apt-get", "install", "-y", "opensearch"]);
+    assert_eq!(
+        saved_secret(&plan, deployment::OPENSEARCH_PASSWORD_FILE).as_deref(),
+        Some(password)
+    );
+    assert!(operations.contains(&Operation::WriteFile {
+        path: String::from("/etc/opensearch/jvm.options.d/heap.options"),
+        contents: String::from("-Xms4096m\n-Xmx4096m\n"),
+    }));
+    let settings = operations
+        .iter()
+        .find_map(|operation| match operation {
+            Operation::RunCommand { command, args }
+                if command == "sh" && args[1].contains("opensearch.yml") =>
+            {
+                Some(args[1].clone())
+            }
+            _ => None,
+        })
+        .unwrap();
+    assert!(settings.contains("discovery.type: single-node\nnetwork.host: 127.0.0.1\n"));
+    assert!(settings.contains("plugins.security.disabled: false"));
+    assert_eq!(
+        operations.last(),
+        Some(&Operation::RestartService {
+            name: String::from("opensearch")
+        })
+    );
+
+    // Re-deploying keeps the existing password
+    let host = fake_host(&["/usr/bin/dnf", deployment::OPENSEARCH_PASSWORD_FILE])
+        .with_file("/proc/meminfo", &meminfo(4 * 1024 * 1024));
+    let plan = plan_on(host).unwrap();
+    assert!(plan.operations().contains(&Operation::InstallPackages {
+        packages: vec![String::from("opensearch")],
+    }));
+    assert!(!plan.operations().iter().any(|operation| matches!(
+        operation,
+        Operation::RunCommand { command, .. } if command == "env"
+    )));
+
+    let host = fake_host(&["/usr/bin/apt"]).with_file("/proc/meminfo", &meminfo(1024 * 1024));
+    let error = plan_on(host).unwrap_err().to_string();
+    assert!(error.contains("at least 2048 MB"), "{}", error);
+}
+
+#[test]
+fn test_plan_web_server_config() {
+    let plan_on = |host: RecordingRunner, app: &str, mode: WebServerConfigMode| {
+        let config = Config {
+            web_server_config_mode: mode,
+            app_domain: Some(String::from("shop.example.com")),
+            ..Default::default()
+        };
+        with_runner(Arc::new(host), || {
+            Plan::build(|plan| deployment::plan_web_server_config(plan, app, &config)).unwrap()
+        })
+    };
+    let written = |plan: &Plan| {
+        plan.operations()
+            .iter()
+            .filter_map(|operation| match operation {
+                Operation::WriteFile { path, contents } => Some((path.clone(), contents.clone())),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    let nginx_default = "/etc/nginx/sites-available/default";
+
+    let plan = plan_on(fake_host(&[]), "nginx", WebServerConfigMode::Replace);
+    let files = written(&plan);
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].0, nginx_default);
+    assert!(files[0].1.contains("listen 80 default_server;"));
+    assert_eq!(
+        plan.operations()[1..],
+        [
+            Operation::RunCommand {
+                command: String::from("ln"),
+                args: [
+                    "-sfn",
+                    "/etc/nginx/sites-available/default",
+                    "/etc/nginx/sites-enabled/default",
+                ]
+                .map(String::from)
+                .to_vec(),
+            },
+            Operation::RunCommand {
+                command: String::from("nginx"),
+                args: vec![String::from("-t")],
+            },
+            Operation::ReloadService {
+                name: String::from("nginx")
+            },
+        ]
+    );
+
+    // An existing default site is only kept when asked to
+    let plan = plan_on(
+        fake_host(&[nginx_default]),
+        "nginx",
+        WebServerConfigMode::KeepExisting,
+    );
+    assert!(plan.operations().is_empty());
+    let plan = plan_on(fake_host(&[]), "nginx", WebServerConfigMode::KeepExisting);
+    assert_eq!(written(&plan)[0].0, nginx_default);
+
+    let plan = plan_on(
+        fake_host(&[nginx_default]),
+        "nginx",
+        WebServerConfigMode::AddVhost,
+    );
+    let files = written(&plan);
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].0, "/etc/nginx/sites-available/serverforge");
+    assert!(files[0].1.contains("listen 80;"));
+    assert!(files[0].1.contains("server_name shop.example.com;"));
+    assert!(plan.operations().contains(&Operation::RunCommand {
+        command: String::from("ln"),
+        args: [
+            "-sfn",
+            "/etc/nginx/sites-available/serverforge",
+            "/etc/nginx/sites-enabled/serverforge",
+        ]
+        .map(String::from)
+        .to_vec(),
+    }));
+
+    let plan = plan_on(
+        fake_host(&["/usr/bin/apt"]),
+        "apache",
+        WebServerConfigMode::AddVhost,
+    );
+    let files = written(&plan);
+    assert_eq!(files[0].0, "/etc/apache2/sites-available/serverforge.conf");
+    assert!(files[0].1.contains("ServerName shop.example.com\n"));
+    assert!(plan.operations().contains(&Operation::RunCommand {
+        command: String::from("a2ensite"),
+        args: vec![String::from("serverforge")],
+    }));
+
+    let plan = plan_on(
+        fake_host(&["/usr/bin/dnf"]),
+        "apache",
+        WebServerConfigMode::Replace,
+    );
+    let files = written(&plan);
+    assert_eq!(files[0].0, "/etc/httpd/conf.d/000-default.conf");
+    assert!(files[0].1.contains("ErrorLog logs/error.log"));
+    assert_eq!(
+        plan.operations()[1..],
+        [
+            Operation::RunCommand {
+                command: String::from("apachectl"),
+                args: vec![String::from("configtest")],
+            },
+            Operation::ReloadService {
+                name: String::from("httpd")
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_replaced_default_site_is_rolled_back() {
+    let nginx_default = "/etc/nginx/sites-available/default";
+    let filesystem = Arc::new(
+        MockFileSystem::new()
+            .with_file("/usr/bin/apt", "")
+            .with_file(nginx_default, "server { listen 80; root /srv/mine; }\n"),
+    );
+    let rollback = RollbackManager::new();
+
+    with_runner(Arc::new(fake_host(&["/usr/bin/apt"])), || {
+        with_filesystem(filesystem.clone(), || {
+            deployment::setup_web_server_config("nginx", &Config::default(), &rollback)?;
+            assert!(filesystem
+                .file(nginx_default)
+                .unwrap()
+                .contains("default_server"));
+            rollback.rollback_all()
+        })
+    })
+    .unwrap();
+
+    assert_eq!(
+        filesystem.file(nginx_default).unwrap(),
+        "server { listen 80; root /srv/mine; }\n"
+    );
+}
+
+/// A host with the given (empty) files, 4 CPUs and 80000 MB of free disk space.
+fn fake_host(paths: &[&str]) -> RecordingRunner {
+    paths
+        .iter()
+        .fold(RecordingRunner::new("fake"), |host, path| {
+            host.with_file(path, "")
+        })
+        .respond("nproc", "4\n")
+        .respond(
+            "df",
+            "Filesystem 1048576-blocks Used Available Capacity Mounted on\n\
+             /dev/sda1 100000 20000 80000 20% /\n",
+        )
+}
@@ -1 +1,198 @@
+use crate::common::RecordingRunner;
+use server_forge::config::Config;
+use server_forge::distro::{
+    add_repository, get_installed_version, restart_service, service_name_for, start_service,
+    PackageManager, RepositorySpec,
+};
+use server_forge::runner::with_runner;
+use server_forge::utils::{generate_report, REPORT_PATH};
+use std::sync::Arc;
+use std::time::Duration;
 
+/// A host on which `lsb_release` prints "jammy" and `mktemp` a fixed path, nginx is
+/// installed, and only the configuration files of apache2 are left.
+fn fake_host() -> RecordingRunner {
+    RecordingRunner::new("fake")
+        .respond("lsb_release", "jammy\n")
+        .respond("mktemp", "/tmp/tmp.key\n")
+        .respond(
+            "dpkg-query -W -f=${Status} ${Version} nginx",
+            "install ok installed 1.18.0-6ubuntu14",
+        )
+        .respond(
+            "dpkg-query -W -f=${Status} ${Version} apache2",
+            "deinstall ok config-files 2.4.52-1ubuntu4",
+        )
+        .respond("rpm -q --qf %{VERSION} nginx", "1.20.1")
+}
+
+fn example_repository() -> RepositorySpec {
+    RepositorySpec {
+        name: String::from("example"),
+        url: String::from("https://packages.example.com/deb"),
+        gpg_key_url: String::from("https://packages.example.com/gpg"),
+        codename: None,
+        components: vec![String::from("stable")],
+        arch: Some(String::from("amd64")),
+    }
+}
+
+#[test]
+fn test_add_apt_repository() {
+    let host = Arc::new(fake_host());
+    with_runner(host.clone(), || {
+        add_repository(&PackageManager::Apt, &example_repository())
+    })
+    .unwrap();
+
+    assert_eq!(
+        host.commands(),
+        vec![
+            "lsb_release -cs",
+            "mktemp",
+            "curl -fsSL -o /tmp/tmp.key https://packages.example.com/gpg",
+            "gpg --batch --yes --dearmor -o /usr/share/keyrings/example-archive-keyring.gpg /tmp/tmp.key",
+            "rm -f /tmp/tmp.key",
+            "apt update",
+        ]
+    );
+    assert_eq!(
+        host.file("/etc/apt/sources.list.d/example.list").unwrap(),
+        "deb [arch=amd64 signed-by=/usr/share/keyrings/example-archive-keyring.gpg] \
+         https://packages.example.com/deb jammy stable\n"
+    );
+
+    // A fixed suite is used as is
+    let spec = RepositorySpec {
+        codename: Some(String::from("stable")),
+        components: vec![String::from("main")],
+        arch: None,
+        ..example_repository()
+    };
+    assert_eq!(
+        spec.apt_source("jammy"),
+        "deb [signed-by=/usr/share/keyrings/example-archive-keyring.gpg] \
+         https://packages.example.com/deb stable main\n"
+    );
+}
+
+#[test]
+fn test_add_apt_repository_key_download_fails() {
+    let host = Arc::new(fake_host().fail("curl"));
+    let result = with_runner(host.clone(), || {
+        add_repository(&PackageManager::Apt, &example_repository())
+    });
+
+    assert!(result.is_err());
+    let commands = host.commands();
+    assert!(!commands.iter().any(|command| command.starts_with("gpg")));
+    // The downloaded key is removed, and the repository is not added
+    assert_eq!(commands.last().unwrap(), "rm -f /tmp/tmp.key");
+    assert_eq!(host.file("/etc/apt/sources.list.d/example.list"), None);
+}
+
+#[test]
+fn test_add_yum_repository() {
+    let spec = RepositorySpec {
+        url: String::from("https://packages.example.com/rpm/$releasever/$basearch"),
+        ..example_repository()
+    };
+    for package_manager in [PackageManager::Yum, PackageManager::Dnf] {
+        let host = Arc::new(fake_host());
+        with_runner(host.clone(), || add_repository(&package_manager, &spec)).unwrap();
+
+        assert!(host.commands().is_empty());
+        assert_eq!(
+            host.file("/etc/yum.repos.d/example.repo").unwrap(),
+            "[example]\nname=example\n\
+             baseurl=https://packages.example.com/rpm/$releasever/$basearch\n\
+             enabled=1\ngpgcheck=1\ngpgkey=https://packages.example.com/gpg\n"
+        );
+    }
+}
+
+#[test]
+fn test_get_installed_version() {
+    let host = Arc::new(fake_host());
+    with_runner(host.clone(), || {
+        let version =
+            |package_manager, package| get_installed_version(&package_manager, package).unwrap();
+        assert_eq!(
+            version(PackageManager::Apt, "nginx").as_deref(),
+            Some("1.18.0-6ubuntu14")
+        );
+        assert_eq!(version(PackageManager::Apt, "apache2"), None);
+        assert_eq!(version(PackageManager::Apt, "mysql-server"), None);
+        assert_eq!(
+            version(PackageManager::Dnf, "nginx").as_deref(),
+            Some("1.20.1")
+        );
+        assert_eq!(version(PackageManager::Yum, "httpd"), None);
+    });
+
+    assert_eq!(
+        host.commands()[..2],
+        [
+            "dpkg-query -W -f=${Status} ${Version} nginx",
+            "dpkg-query -W -f=${Status} ${Version} apache2",
+        ]
+    );
+    assert_eq!(host.commands()[3], "rpm -q --qf %{VERSION} nginx");
+}
+
+#[test]
+fn test_report_records_component_versions() {
+    let host = Arc::new(fake_host().with_file("/usr/bin/apt", ""));
+    with_runner(host.clone(), || {
+        generate_report(&Config::default(), &[], Duration::from_secs(1))
+    })
+    .unwrap();
+
+    assert!(host.file(REPORT_PATH).unwrap().contains(
+        "\nComponent Versions:\n\
+         - nginx: 1.18.0-6ubuntu14\n\
+         - mysql-server: not installed\n\
+         - docker-ce: not installed\n"
+    ));
+}
+
+#[test]
+fn test_service_name_for() {
+    assert_eq!(service_name_for(&PackageManager::Apt, "apache"), "apache2");
+    assert_eq!(service_name_for(&PackageManager::Dnf, "apache"), "httpd");
+    assert_eq!(service_name_for(&PackageManager::Apt, "httpd"), "apache2");
+    assert_eq!(service_name_for(&PackageManager::Yum, "mysql"), "mysqld");
+    assert_eq!(service_name_for(&PackageManager::Dnf, "chrony"), "chronyd");
+    assert_eq!(service_name_for(&PackageManager::Yum, "nginx"), "nginx");
+}
+
+#[test]
+fn test_start_service_waits_until_active() {
+    let host =
+        Arc::new(fake_host().respond_with("systemctl is-active nginx", "activating\n", Some(1)));
+    with_runner(host.clone(), || start_service("nginx")).unwrap();
+
+    assert_eq!(
+        host.commands(),
+        vec![
+            "systemctl start nginx",
+            "systemctl is-active nginx",
+            "systemctl is-active nginx",
+        ]
+    );
+}
+
+#[test]
+fn test_restart_service_fails_when_not_active() {
+    let host = Arc::new(fake_host().respond("systemctl is-active nginx", "failed\n"));
+    let error = with_runner(host.clone(), || restart_service("nginx")).unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "Service nginx is failed instead of active (see journalctl -u nginx)"
+    );
+    assert_eq!(
+        host.commands(),
+        vec!["systemctl restart nginx", "systemctl is-active nginx"]
+    );
+}
@@ -0,0 +1,136 @@
+use crate::common::RecordingRunner;
+use server_forge::config::{Config, Secrets};
+use server_forge::encryption::{self, decrypt, encrypt, is_encrypted, parse_key, KEY_ENV};
+use server_forge::runner::{with_runner, CommandRunner};
+use server_forge::utils::{
+    generate_report, load_saved_config, save_config, saved_config_path, ENCRYPTED_CONFIG_PATH,
+    REPORT_PATH, SAVED_CONFIG_PATH,
+};
+use std::sync::Arc;
+use std::time::Duration;
+
+const KEY_HEX: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+#[test]
+fn test_parse_key() {
+    let key = parse_key(&format!("  {}\n", KEY_HEX)).unwrap();
+    assert_eq!(key[0], 0x00);
+    assert_eq!(key[31], 0x1f);
+
+    assert!(parse_key("0011").is_err());
+    assert!(parse_key(&KEY_HEX.replace("1f", "zz")).is_err());
+}
+
+#[test]
+fn test_encrypt_and_decrypt() {
+    let key = parse_key(KEY_HEX).unwrap();
+    let encrypted = encrypt(&key, b"{\"hostname\": \"web1\"}").unwrap();
+
+    assert!(is_encrypted(&encrypted));
+    assert!(!is_encrypted(b"{\"hostname\": \"web1\"}"));
+    assert!(!encrypted.windows(4).any(|window| window == b"web1"));
+    assert_eq!(
+        decrypt(&key, &encrypted).unwrap(),
+        b"{\"hostname\": \"web1\"}"
+    );
+    // A random nonce makes every encryption different
+    assert_ne!(
+        encrypt(&key, b"same").unwrap(),
+        encrypt(&key, b"same").unwrap()
+    );
+}
+
+#[test]
+fn test_decrypt_rejects_wrong_key_and_modified_data() {
+    let key = parse_key(KEY_HEX).unwrap();
+    let encrypted = encrypt(&key, b"secret").unwrap();
+
+    let mut other_key = key;
+    other_key[0] ^= 1;
+    assert!(decrypt(&other_key, &encrypted).is_err());
+
+    let mut modified = encrypted.clone();
+    *modified.last_mut().unwrap() ^= 1;
+    assert!(decrypt(&key, &modified).is_err());
+
+    assert!(decrypt(&key, b"secret").is_err());
+}
+
+#[test]
+fn test_save_config_in_plaintext_by_default() {
+    let host = Arc::new(RecordingRunner::new("fake"));
+    let config = Config {
+        hostname: Some(String::from("web1")),
+        ..Default::default()
+    };
+    with_runner(host.clone(), || save_config(&config, None)).unwrap();
+
+    let saved = host.file(SAVED_CONFIG_PATH).unwrap();
+    assert!(saved.contains("\"web1\""));
+    assert_eq!(host.file(ENCRYPTED_CONFIG_PATH), None);
+}
+
+#[test]
+fn test_saved_config_and_report_leave_out_secrets() {
+    let host = Arc::new(RecordingRunner::new("fake"));
+    let config = Config {
+        admin_email: Some(String::from("ops@example.com")),
+        secrets: Secrets {
+            grafana_admin_password: Some(String::from("grafana-s3cret")),
+            notification_webhook: Some(String::from("https://hooks.example.com/s3cret")),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    with_runner(host.clone(), || {
+        save_config(&config, None)?;
+        generate_report(&config, &[], Duration::from_secs(1))
+    })
+    .unwrap();
+
+    for path in [SAVED_CONFIG_PATH, REPORT_PATH] {
+        let contents = host.file(path).unwrap();
+        assert!(contents.contains("ops@example.com"), "{}", path);
+        assert!(!contents.contains("s3cret"), "{} leaks a secret", path);
+    }
+}
+
+#[test]
+fn test_encrypted_saved_config() {
+    // The only test setting the key, which the process shares with every test
+    std::env::set_var(KEY_ENV, KEY_HEX);
+    let key = encryption::config_key().unwrap().unwrap();
+    let host = Arc::new(RecordingRunner::new("fake"));
+    let config = Config {
+        hostname: Some(String::from("web1")),
+        ..Default::default()
+    };
+
+    with_runner(host.clone(), || {
+        save_config(&config, None)?;
+        save_config(&config, Some(&key))
+    })
+    .unwrap();
+    let saved = host.read_file(ENCRYPTED_CONFIG_PATH).unwrap();
+    assert!(is_encrypted(&saved));
+    assert_eq!(
+        host.file(SAVED_CONFIG_PATH),
+        None,
+        "plaintext copy was kept"
+    );
+
+    let loaded = with_runner(host.clone(), || {
+        assert_eq!(saved_config_path(), Some(ENCRYPTED_CONFIG_PATH));
+        load_saved_config()
+    })
+    .unwrap()
+    .unwrap();
+    assert_eq!(loaded.hostname.as_deref(), Some("web1"));
+
+    // `from_file` reads the saved file, and chooses the format by the extension before .enc
+    let path = std::env::temp_dir().join(format!("serverforge-{}.json.enc", std::process::id()));
+    std::fs::write(&path, &saved).unwrap();
+    let from_file = Config::from_file(path.to_str().unwrap());
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(from_file.unwrap().hostname.as_deref(), Some("web1"));
+}
@@ -0,0 +1,168 @@
+use crate::common::RecordingRunner;
+use server_forge::config::Config;
+use server_forge::export::{ansible_playbook, cloud_config};
+use server_forge::plan::{plan_host, Operation, Plan};
+use std::os::unix::fs::PermissionsExt;
+use std::sync::Arc;
+
+#[test]
+fn test_ansible_playbook() {
+    let mut plan = Plan::new();
+    plan.install(&["nginx", "curl"])
+        .restart_service("sshd")
+        .enable_service("backup.timer")
+        .run("ufw", &["allow", "OpenSSH"])
+        .write_file("/etc/cron.d/scan", "0 3 * * * root scan\n")
+        .run("chmod", &["+x", "/usr/local/bin/scan.sh"])
+        .run_with_input(
+            "mysql",
+            &["--batch"],
+            "ALTER USER root IDENTIFIED BY 's3cret';",
+        );
+
+    let playbook = ansible_playbook(&[(String::from("web1"), plan)], true).unwrap();
+    let plays: serde_yaml::Value = serde_yaml::from_str(&playbook).unwrap();
+    let play = &plays[0];
+    assert_eq!(play["hosts"], "web1");
+    assert_eq!(play["become"], true);
+
+    let tasks = &play["tasks"];
+    assert_eq!(
+        tasks[0]["ansible.builtin.package"]["name"],
+        serde_yaml::from_str::<serde_yaml::Value>("[nginx, curl]").unwrap()
+    );
+    assert_eq!(
+        tasks[1]["ansible.builtin.systemd_service"]["state"],
+        "restarted"
+    );
+    assert_eq!(tasks[2]["ansible.builtin.systemd_service"]["enabled"], true);
+    assert_eq!(
+        tasks[3]["ansible.builtin.command"]["argv"],
+        serde_yaml::from_str::<serde_yaml::Value>("[ufw, allow, OpenSSH]").unwrap()
+    );
+    assert_eq!(tasks[4]["ansible.builtin.copy"]["dest"], "/etc/cron.d/scan");
+    assert_eq!(
+        tasks[4]["ansible.builtin.copy"]["content"],
+        "0 3 * * * root scan\n"
+    );
+    assert_eq!(tasks[5]["ansible.builtin.file"]["mode"], "a+x");
+    // Secrets are passed on standard input, and not printed by Ansible
+    assert_eq!(tasks[6]["name"], "Run mysql --batch");
+    assert_eq!(
+        tasks[6]["ansible.builtin.command"]["stdin"],
+        "ALTER USER root IDENTIFIED BY 's3cret';"
+    );
+    assert_eq!(tasks[6]["no_log"], true);
+    assert!(!playbook.contains("Run mysql --batch ALTER"));
+}
+
+#[test]
+fn test_cloud_config() {
+    let mut plan = Plan::new();
+    plan.upgrade_packages()
+        .install(&["fail2ban"])
+        .install(&["nginx", "fail2ban"])
+        .write_file("/etc/fail2ban/jail.local", "[sshd]\nenabled = true\n")
+        .enable_service("fail2ban");
+
+    let document = cloud_config(&plan, false).unwrap();
+    assert!(document.starts_with("#cloud-config\n"));
+
+    let document: serde_yaml::Value = serde_yaml::from_str(&document).unwrap();
+    assert_eq!(document["package_update"], true);
+    assert_eq!(document["package_upgrade"], true);
+    assert_eq!(
+        document["packages"],
+        serde_yaml::from_str::<serde_yaml::Value>("[fail2ban, nginx]").unwrap()
+    );
+    assert_eq!(
+        document["write_files"][0]["path"],
+        "/etc/fail2ban/jail.local"
+    );
+    assert_eq!(
+        document["write_files"][0]["content"],
+        "[sshd]\nenabled = true\n"
+    );
+    assert_eq!(
+        document["runcmd"],
+        serde_yaml::from_str::<serde_yaml::Value>("[[systemctl, enable, fail2ban]]").unwrap()
+    );
+}
+
+#[test]
+fn test_cloud_config_private_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(".secret");
+    let path = path.to_str().unwrap();
+    let mut plan = Plan::new();
+    plan.write_private_file(path, "it's s3cret");
+
+    let document: serde_yaml::Value =
+        serde_yaml::from_str(&cloud_config(&plan, true).unwrap()).unwrap();
+    let command = document["runcmd"][0].as_str().unwrap();
+    assert!(command.starts_with("printf '%s' 'it'\\''s s3cret' | 'sh' '-c' "));
+
+    // The shell command writes the file, readable only by its owner
+    assert!(std::process::Command::new("sh")
+        .args(["-c", command])
+        .status()
+        .unwrap()
+        .success());
+    assert_eq!(std::fs::read_to_string(path).unwrap(), "it's s3cret");
+    let mode = std::fs::metadata(path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn test_export_refuses_secrets() {
+    let mut plan = Plan::new();
+    plan.install(&["mysql-server"])
+        .run_with_input("mysql", &[], "ALTER USER root IDENTIFIED BY 's3cret';")
+        .write_private_file("/root/.mysql_root_password", "s3cret");
+    let plays = [(String::from("web1"), plan.clone())];
+
+    for error in [
+        ansible_playbook(&plays, false).unwrap_err(),
+        cloud_config(&plan, false).unwrap_err(),
+    ] {
+        let error = error.to_string();
+        assert!(!error.contains("s3cret"));
+        assert_eq!(
+            error,
+            "The plan passes secrets to mysql, /root/.mysql_root_password, which would be \
+             written into the export in plaintext; export with --include-secrets to include \
+             them anyway"
+        );
+    }
+}
+
+#[test]
+fn test_export_leaves_out_generated_passwords() {
+    let config = Config {
+        linux_distro: String::from("ubuntu"),
+        deployed_apps: vec![String::from("mysql")],
+        ..Config::default()
+    };
+    let host = RecordingRunner::ubuntu("web1");
+    let plan = plan_host(&config, Arc::new(host)).unwrap();
+    let passwords: Vec<&str> = plan
+        .operations()
+        .iter()
+        .filter_map(|operation| match operation {
+            Operation::RunCommandWithInput { input, .. } => Some(input.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert!(!passwords.is_empty());
+
+    let plays = [(String::from("web1"), plan.clone())];
+    for error in [
+        ansible_playbook(&plays, false).unwrap_err(),
+        cloud_config(&plan, false).unwrap_err(),
+    ] {
+        let error = error.to_string();
+        assert!(error.starts_with("The plan passes secrets to mysql, "));
+        assert!(passwords.iter().all(|password| !error.contains(password)));
+    }
+}
@@ -0,0 +1,115 @@
+use crate::common::{MockFileSystem, RecordingRunner};
+use server_forge::backup::setup_backup_locations;
+use server_forge::concurrency::run_concurrently;
+use server_forge::config::Config;
+use server_forge::filesystem::{current_filesystem, with_filesystem, FileSystem};
+use server_forge::runner::with_runner;
+use server_forge::utils::{path_exists, read_file, set_permissions, write_file};
+use std::sync::Arc;
+
+#[test]
+fn test_mock_filesystem() {
+    let filesystem = MockFileSystem::new().with_file("/etc/hostname", "web1\n");
+
+    assert_eq!(filesystem.read("/etc/hostname").unwrap(), b"web1\n");
+    assert!(filesystem.read("/etc/hosts").is_err());
+    assert!(filesystem.set_permissions("/etc/hosts", 0o600).is_err());
+
+    filesystem.create_dir_all("/etc/restic").unwrap();
+    assert!(filesystem.exists("/etc"));
+    assert_eq!(filesystem.mode("/etc/restic"), Some(0o755));
+    filesystem
+        .write("/etc/restic/excludes", b"*.tmp\n")
+        .unwrap();
+    assert_eq!(filesystem.mode("/etc/restic/excludes"), Some(0o644));
+    filesystem
+        .set_permissions("/etc/restic/excludes", 0o600)
+        .unwrap();
+    assert_eq!(filesystem.mode("/etc/restic/excludes"), Some(0o600));
+    assert_eq!(
+        filesystem.paths(),
+        ["/etc/hostname", "/etc/restic/excludes"]
+    );
+}
+
+#[test]
+fn test_with_filesystem() {
+    let filesystem = Arc::new(MockFileSystem::new());
+
+    with_filesystem(filesystem.clone(), || {
+        write_file("/etc/server_forge/motd", "Managed by ServerForge\n").unwrap();
+        assert_eq!(
+            read_file("/etc/server_forge/motd").unwrap(),
+            "Managed by ServerForge\n"
+        );
+    });
+
+    assert_eq!(
+        filesystem.file("/etc/server_forge/motd").as_deref(),
+        Some("Managed by ServerForge\n")
+    );
+    // The previous file system is restored
+    assert!(!path_exists("/etc/server_forge/motd"));
+    assert!(!current_filesystem().exists("/etc/server_forge/motd"));
+}
+
+#[test]
+fn test_default_filesystem_uses_the_current_runner() {
+    let runner = Arc::new(RecordingRunner::new("web1"));
+
+    with_runner(runner.clone(), || {
+        write_file("/usr/local/bin/script.sh", "#!/bin/sh\n").unwrap();
+        set_permissions("/usr/local/bin/script.sh", 0o755).unwrap();
+    });
+
+    assert_eq!(
+        runner.log(),
+        [
+            "write /usr/local/bin/script.sh",
+            "chmod 755 /usr/local/bin/script.sh"
+        ]
+    );
+    assert_eq!(
+        runner.file("/usr/local/bin/script.sh").as_deref(),
+        Some("#!/bin/sh\n")
+    );
+}
+
+#[test]
+fn test_setup_backup_locations_on_mock_filesystem() {
+    let runner = Arc::new(RecordingRunner::new("web1"));
+    let filesystem = Arc::new(MockFileSystem::new());
+    let config = Config {
+        backup_excludes: vec![String::from("*.tmp")],
+        ..Default::default()
+    };
+
+    with_runner(runner.clone(), || {
+        with_filesystem(filesystem.clone(), || setup_backup_locations(&config))
+    })
+    .unwrap();
+
+    // Commands still go through the runner, files through the file system
+    assert_eq!(runner.log().len(), 1);
+    assert!(runner.log()[0].starts_with("restic init --repo "));
+    assert_eq!(filesystem.mode("/usr/local/bin/run-backup.sh"), Some(0o755));
+    assert!(filesystem
+        .file("/usr/local/bin/run-backup.sh")
+        .unwrap()
+        .starts_with("#!/bin/bash"));
+    assert!(filesystem.exists("/etc/restic"));
+    assert_eq!(filesystem.paths().len(), 2);
+}
+
+#[test]
+fn test_run_concurrently_uses_the_current_filesystem() {
+    let filesystem = Arc::new(MockFileSystem::new());
+    let paths = ["/srv/a", "/srv/b", "/srv/c", "/srv/d"];
+
+    with_filesystem(filesystem.clone(), || {
+        run_concurrently(&paths, 4, |path| write_file(path, "contents"))
+    })
+    .unwrap();
+
+    assert_eq!(filesystem.paths(), paths);
+}
@@ -0,0 +1,148 @@
+use server_forge::distro::PackageManager;
+use server_forge::firewall::{self, Direction, Firewall, Firewalld, Policy, Ufw};
+use server_forge::plan::{Operation, Plan};
+
+fn commands(plan: &Plan) -> Vec<String> {
+    plan.operations()
+        .iter()
+        .map(|operation| match operation {
+            Operation::RunCommand { command, args } => format!("{} {}", command, args.join(" ")),
+            other => format!("{:?}", other),
+        })
+        .collect()
+}
+
+#[test]
+fn test_ufw_operations() {
+    let mut plan = Plan::new();
+    Ufw.set_default_policy(&mut plan, Direction::Incoming, Policy::Deny)
+        .unwrap();
+    Ufw.allow_service(&mut plan, "ssh");
+    Ufw.allow_port(&mut plan, "6000:6007/udp");
+    Ufw.enable(&mut plan);
+    Ufw.reload(&mut plan);
+
+    assert_eq!(
+        commands(&plan),
+        vec![
+            "ufw default deny incoming",
+            "ufw allow OpenSSH",
+            "ufw allow 6000:6007/udp",
+            "ufw --force enable",
+        ]
+    );
+    assert_eq!(Ufw.rule_for_service("ssh"), "OpenSSH");
+    assert_eq!(Ufw.rule_for_port("6000:6007/udp"), "6000:6007/udp");
+}
+
+#[test]
+fn test_firewalld_operations() {
+    let mut plan = Plan::new();
+    Firewalld
+        .set_default_policy(&mut plan, Direction::Outgoing, Policy::Allow)
+        .unwrap();
+    Firewalld.allow_service(&mut plan, "ssh");
+    Firewalld.allow_port(&mut plan, "6000:6007/udp");
+    Firewalld.reload(&mut plan);
+
+    assert_eq!(
+        commands(&plan),
+        vec![
+            "firewall-cmd --zone=public --add-service=ssh --permanent",
+            "firewall-cmd --zone=public --add-port=6000-6007/udp --permanent",
+            "firewall-cmd --reload",
+        ]
+    );
+    assert!(Firewalld
+        .set_default_policy(&mut plan, Direction::Outgoing, Policy::Deny)
+        .is_err());
+}
+
+#[test]
+fn test_firewall_selection() {
+    assert_eq!(firewall::for_distro("ubuntu").unwrap().name(), "ufw");
+    assert_eq!(firewall::for_distro("centos").unwrap().name(), "firewalld");
+    assert!(firewall::for_distro("arch").is_err());
+    assert_eq!(
+        firewall::for_package_manager(&PackageManager::Dnf).name(),
+        "firewalld"
+    );
+}
+
+#[test]
+fn test_parse_ufw_status() {
+    let status = "Status: active\n\n\
+                  To                         Action      From\n\
+                  --                         ------      ----\n\
+                  OpenSSH                    ALLOW       Anywhere\n\
+                  2222/tcp                   ALLOW       Anywhere\n\
+                  80/tcp                     ALLOW       Anywhere\n\
+                  OpenSSH (v6)               ALLOW       Anywhere (v6)\n";
+    assert_eq!(
+        firewall::parse_ufw_status(status).unwrap(),
+        vec!["OpenSSH", "2222/tcp", "80/tcp"]
+    );
+    assert!(firewall::parse_ufw_status("Status: inactive\n").is_none());
+}
+
+#[test]
+fn test_validate_firewall_rule() {
+    for rule in [
+        "22",
+        "80/tcp",
+        "6000:6007/udp",
+        "OpenSSH",
+        "http",
+        "from 192.168.1.10 to any port 22",
+        "from 10.0.0.0/8 to any port 5432 proto tcp",
+        "from 2001:db8::/32 to any port 6000:6007 proto udp",
+        "from any to any port 443",
+    ] {
+        assert!(
+            firewall::validate_firewall_rule(rule).is_ok(),
+            "{} was rejected",
+            rule
+        );
+    }
+
+    for rule in [
+        "",
+        "0/tcp",
+        "70000/tcp",
+        "80/tpc",
+        "6000:6007",
+        "6007:6000/udp",
+        "allow 80/tcp",
+        "from 10.0.0.300 to any port 22",
+        "from 10.0.0.0/33 to any port 22",
+        "from 10.0.0.1 port 22",
+        "from 10.0.0.1 to any port 6000:6007",
+    ] {
+        assert!(
+            firewall::validate_firewall_rule(rule).is_err(),
+            "{} was accepted",
+            rule
+        );
+    }
+}
+
+#[test]
+fn test_source_rules() {
+    let rule = "from 10.0.0.0/8 to any port 6000:6007 proto udp";
+    let mut plan = Plan::new();
+    Ufw.allow_port(&mut plan, rule);
+    Firewalld.allow_port(&mut plan, rule);
+    Firewalld.allow_port(&mut plan, "from any to any port 22");
+
+    assert_eq!(
+        commands(&plan),
+        vec![
+            "ufw allow from 10.0.0.0/8 to any port 6000:6007 proto udp",
+            "firewall-cmd --zone=public --add-rich-rule=rule family=\"ipv4\" \
+             source address=\"10.0.0.0/8\" port port=\"6000-6007\" protocol=\"udp\" accept \
+             --permanent",
+            "firewall-cmd --zone=public --add-rich-rule=rule port port=\"22\" \
+             protocol=\"tcp\" accept --permanent",
+        ]
+    );
+}
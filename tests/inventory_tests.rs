@@ -0,0 +1,135 @@
+use server_forge::config::{Config, Secrets, ServerRole};
+use server_forge::inventory;
+use std::fs;
+use tempfile::tempdir;
+
+const INVENTORY_YAML: &str = r#"
+vars:
+  security_level: advanced
+  monitoring: true
+groups:
+  db:
+    vars:
+      server_role: database
+      deployed_apps: [postgresql]
+    hosts:
+      db1:
+  web:
+    vars:
+      server_roles: [web, application]
+      deployed_apps: [nginx]
+    hosts:
+      web1:
+      web2:
+        deployed_apps: [nginx, php]
+"#;
+
+#[test]
+fn test_load_yaml_inventory() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("infra.yaml");
+    fs::write(&path, INVENTORY_YAML).unwrap();
+
+    let inventory = inventory::load(path.to_str().unwrap()).unwrap();
+    let base = Config {
+        secrets: Secrets {
+            grafana_admin_password: Some(String::from("s3cret")),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let hosts = inventory.hosts(&base).unwrap();
+
+    let names: Vec<&str> = hosts.iter().map(|(host, _)| host.as_str()).collect();
+    assert_eq!(names, vec!["db1", "web1", "web2"]);
+
+    let (_, db1) = &hosts[0];
+    assert_eq!(db1.server_roles, vec![ServerRole::Database]);
+    assert_eq!(db1.deployed_apps, vec!["postgresql"]);
+    assert_eq!(db1.security_level, "advanced");
+    assert!(db1.monitoring);
+
+    let (_, web1) = &hosts[1];
+    assert_eq!(
+        web1.server_roles,
+        vec![ServerRole::Web, ServerRole::Application]
+    );
+    assert_eq!(web1.deployed_apps, vec!["nginx"]);
+
+    // Host overrides win over group vars; other settings are shared
+    let (_, web2) = &hosts[2];
+    assert_eq!(web2.deployed_apps, vec!["nginx", "php"]);
+    assert_eq!(web2.security_level, "advanced");
+    assert_eq!(web2.linux_distro, "ubuntu");
+    assert_eq!(
+        web2.secrets.grafana_admin_password.as_deref(),
+        Some("s3cret")
+    );
+}
+
+#[test]
+fn test_load_toml_inventory() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("infra.toml");
+    fs::write(
+        &path,
+        r#"
+[vars]
+monitoring = true
+
+[groups.app.vars]
+server_role = "application"
+
+[groups.app.hosts.app1]
+use_containers = true
+"#,
+    )
+    .unwrap();
+
+    let inventory = inventory::load(path.to_str().unwrap()).unwrap();
+    let hosts = inventory.hosts(&Config::default()).unwrap();
+    assert_eq!(hosts.len(), 1);
+    let (host, config) = &hosts[0];
+    assert_eq!(host, "app1");
+    assert_eq!(config.server_roles, vec![ServerRole::Application]);
+    assert!(config.monitoring);
+    assert!(config.use_containers);
+}
+
+#[test]
+fn test_inventory_errors() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("infra.yaml");
+
+    // Unknown options are rejected rather than silently ignored
+    fs::write(
+        &path,
+        "groups:\n  web:\n    hosts:\n      web1:\n        monitorin: true\n",
+    )
+    .unwrap();
+    let inventory = inventory::load(path.to_str().unwrap()).unwrap();
+    match inventory.hosts(&Config::default()) {
+        Err(error) => assert!(error.to_string().contains("monitorin")),
+        Ok(_) => panic!("unknown option was accepted"),
+    }
+
+    // Options must have the right type
+    fs::write(
+        &path,
+        "vars:\n  monitoring: sometimes\ngroups:\n  web:\n    hosts:\n      web1:\n",
+    )
+    .unwrap();
+    let inventory = inventory::load(path.to_str().unwrap()).unwrap();
+    assert!(inventory.hosts(&Config::default()).is_err());
+
+    // A host can only belong to one group
+    fs::write(
+        &path,
+        "groups:\n  a:\n    hosts:\n      web1:\n  b:\n    hosts:\n      web1:\n",
+    )
+    .unwrap();
+    let inventory = inventory::load(path.to_str().unwrap()).unwrap();
+    assert!(inventory.hosts(&Config::default()).is_err());
+
+    assert!(inventory::load("/nonexistent/infra.yaml").is_err());
+}
@@ -0,0 +1,123 @@
+use server_forge::config::{Config, MaintenanceWindow};
+use server_forge::maintenance::{
+    backup_schedule, reboot_time, security_scan_schedule, window_errors,
+};
+use server_forge::systemd::cron_to_on_calendar;
+
+fn window(start: &str, days: &[&str]) -> Option<MaintenanceWindow> {
+    Some(MaintenanceWindow {
+        start: start.to_string(),
+        days: days.iter().map(|day| day.to_string()).collect(),
+    })
+}
+
+#[test]
+fn test_window_errors() {
+    assert!(window_errors(&window("02:00", &["sat", "Sun"]).unwrap()).is_empty());
+    assert_eq!(
+        window_errors(&window("2am", &["sat", "sunday"]).unwrap()),
+        vec![
+            "Invalid maintenance_window start '2am': expected a time such as 02:00",
+            "Invalid maintenance_window day 'sunday': expected one of sun, mon, tue, wed, thu, fri, sat",
+        ]
+    );
+}
+
+#[test]
+fn test_schedules_without_window() {
+    let config = Config {
+        backup_frequency: String::from("daily"),
+        security_scan_schedule: String::from("weekly"),
+        ..Default::default()
+    };
+    assert_eq!(backup_schedule(&config).unwrap(), "0 2 * * *");
+    assert_eq!(security_scan_schedule(&config).unwrap(), "0 2 * * 0");
+    assert_eq!(reboot_time(&config), None);
+
+    let config = Config {
+        backup_frequency: String::from("monthly"),
+        ..config
+    };
+    assert!(backup_schedule(&config).is_err());
+}
+
+#[test]
+fn test_schedules_are_staggered() {
+    let config = Config {
+        backup_frequency: String::from("daily"),
+        security_scan_schedule: String::from("daily"),
+        maintenance_window: window("01:30", &[]),
+        ..Default::default()
+    };
+    assert_eq!(backup_schedule(&config).unwrap(), "30 1 * * *");
+    assert_eq!(security_scan_schedule(&config).unwrap(), "30 2 * * *");
+    assert_eq!(reboot_time(&config).as_deref(), Some("03:30"));
+
+    // An explicit reboot time is kept
+    let config = Config {
+        auto_reboot_time: Some(String::from("05:00")),
+        ..config
+    };
+    assert_eq!(reboot_time(&config).as_deref(), Some("05:00"));
+}
+
+#[test]
+fn test_schedules_follow_window_days() {
+    let config = Config {
+        backup_frequency: String::from("weekly"),
+        security_scan_schedule: String::from("daily"),
+        maintenance_window: window("03:00", &["sat", "sun"]),
+        ..Default::default()
+    };
+    assert_eq!(backup_schedule(&config).unwrap(), "0 3 * * 6");
+    assert_eq!(security_scan_schedule(&config).unwrap(), "0 4 * * 6,0");
+
+    let config = Config {
+        security_scan_schedule: String::from("monthly"),
+        ..config
+    };
+    assert_eq!(security_scan_schedule(&config).unwrap(), "0 4 1 * *");
+
+    // Hourly backups and cron expressions are not moved into the window
+    let config = Config {
+        backup_frequency: String::from("hourly"),
+        security_scan_schedule: String::from("15 6 * * 1-5"),
+        ..config
+    };
+    assert_eq!(backup_schedule(&config).unwrap(), "0 * * * *");
+    assert_eq!(security_scan_schedule(&config).unwrap(), "15 6 * * 1-5");
+}
+
+#[test]
+fn test_schedules_past_midnight() {
+    // The scan and the reboot fall on the day after the window opens
+    let config = Config {
+        backup_frequency: String::from("weekly"),
+        security_scan_schedule: String::from("weekly"),
+        maintenance_window: window("23:30", &["sat"]),
+        ..Default::default()
+    };
+    assert_eq!(backup_schedule(&config).unwrap(), "30 23 * * 6");
+    assert_eq!(security_scan_schedule(&config).unwrap(), "30 0 * * 0");
+    assert_eq!(reboot_time(&config).as_deref(), Some("01:30"));
+
+    let config = Config {
+        maintenance_window: window("23:30", &[]),
+        ..config
+    };
+    assert_eq!(security_scan_schedule(&config).unwrap(), "30 0 * * 1");
+}
+
+#[test]
+fn test_schedules_convert_to_timers() {
+    let config = Config {
+        backup_frequency: String::from("daily"),
+        security_scan_schedule: String::from("daily"),
+        maintenance_window: window("02:00", &["mon", "thu"]),
+        ..Default::default()
+    };
+    assert_eq!(
+        cron_to_on_calendar(&security_scan_schedule(&config).unwrap()).unwrap(),
+        "Mon,Thu *-*-* 03:00:00"
+    );
+}
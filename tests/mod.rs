@@ -1,13 +1,37 @@
+mod async_runner_tests;
 mod backup_tests;
+mod check_tests;
+mod cli_tests;
 mod common;
+mod concurrency_tests;
+mod console_tests;
+mod deploy_hooks_tests;
 mod deployment_tests;
 mod distro_tests;
+mod encryption_tests;
+mod export_tests;
+mod filesystem_tests;
+mod firewall_tests;
+mod inventory_tests;
+mod maintenance_tests;
 mod monitoring_tests;
+mod notify_tests;
+mod orchestration_tests;
+mod package_lock_tests;
+mod pipeline_tests;
+mod plan_tests;
+mod progress_tests;
+mod registry_tests;
+mod remote_tests;
 mod rollback_tests;
+mod secrets_tests;
 
 mod config_tests;
 mod containerization_tests;
 mod security_tests;
 mod setup_tests;
+mod status_tests;
+mod systemd_tests;
 mod updates_tests;
-// mod common;
+mod validation_tests;
+mod web_server_tests;
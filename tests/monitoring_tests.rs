@@ -1,7 +1,10 @@
-use server_forge::config::Config;
+use crate::common::RecordingRunner;
+use server_forge::config::{Config, Secrets};
 use server_forge::monitoring;
 use server_forge::rollback::RollbackManager;
+use server_forge::runner::with_runner;
 use std::fs;
+use std::sync::Arc;
 
 #[test]
 fn test_install_monitoring_tools() {
@@ -10,7 +13,9 @@ fn test_install_monitoring_tools() {
         ..Default::default()
     };
 
-    assert!(monitoring::install_monitoring_tools(&config).is_ok());
+    let rollback = RollbackManager::new();
+    let snapshot = rollback.create_snapshot("Monitoring tools").unwrap();
+    assert!(monitoring::install_monitoring_tools(&config, &rollback, snapshot).is_ok());
 
     // Verify Prometheus installation
     let prometheus_status = std::process::Command::new("which")
@@ -29,7 +34,11 @@ fn test_install_monitoring_tools() {
 
 #[test]
 fn test_configure_prometheus() {
-    assert!(monitoring::configure_prometheus().is_ok());
+    let config = Config {
+        monitoring: true,
+        ..Default::default()
+    };
+    assert!(monitoring::configure_prometheus(&config).is_ok());
 
     // Verify Prometheus configuration
     let prometheus_config = fs::read_to_string("/etc/prometheus/prometheus.yml").unwrap();
@@ -44,9 +53,132 @@ fn test_configure_prometheus() {
     assert!(status.success());
 }
 
+#[test]
+fn test_write_default_alert_rules() {
+    assert!(monitoring::write_default_alert_rules().is_ok());
+
+    // Verify the alerting rules
+    let rules = fs::read_to_string(monitoring::ALERT_RULES_PATH).unwrap();
+    assert!(rules.contains("node_filesystem_avail_bytes"));
+    assert!(rules.contains("node_cpu_seconds_total"));
+    assert!(rules.contains("node_memory_MemAvailable_bytes"));
+
+    // Verify Prometheus loads the rules
+    assert!(monitoring::configure_prometheus(&Config::default()).is_ok());
+    let prometheus_config = fs::read_to_string("/etc/prometheus/prometheus.yml").unwrap();
+    assert!(prometheus_config.contains("rule_files:"));
+}
+
+#[test]
+fn test_generate_prometheus_config_blackbox() {
+    let config = Config {
+        monitoring: true,
+        deployed_apps: vec![String::from("nginx")],
+        uptime_probe_targets: vec![
+            String::from("https://example.com/health"),
+            String::from("db.internal:5432"),
+        ],
+        ..Default::default()
+    };
+
+    let targets = monitoring::blackbox_targets(&config);
+    assert_eq!(
+        targets,
+        vec![
+            "https://example.com/health",
+            "db.internal:5432",
+            "http://localhost/"
+        ]
+    );
+
+    // Repeated targets are probed once, even when they are not next to each other
+    let repeated = Config {
+        uptime_probe_targets: vec![
+            String::from("http://localhost/"),
+            String::from("db.internal:5432"),
+            String::from("http://localhost/"),
+        ],
+        ..config.clone()
+    };
+    assert_eq!(
+        monitoring::blackbox_targets(&repeated),
+        vec!["http://localhost/", "db.internal:5432"]
+    );
+
+    let prometheus_config = monitoring::generate_prometheus_config(&config);
+    assert!(prometheus_config.contains("job_name: 'blackbox'"));
+    assert!(prometheus_config.contains("module: [http_2xx]"));
+    assert!(prometheus_config.contains("- 'https://example.com/health'"));
+    assert!(prometheus_config.contains("- 'http://localhost/'"));
+    assert!(prometheus_config.contains("job_name: 'blackbox_tcp'"));
+    assert!(prometheus_config.contains("- 'db.internal:5432'"));
+    assert!(prometheus_config.contains("target_label: __param_target"));
+    assert!(prometheus_config.contains("replacement: localhost:9115"));
+
+    // No probe jobs without targets
+    let prometheus_config = monitoring::generate_prometheus_config(&Config::default());
+    assert!(!prometheus_config.contains("blackbox"));
+}
+
+#[test]
+fn test_monitoring_bind_address() {
+    let mut config = Config::default();
+    assert_eq!(
+        monitoring::listen_address(&config, 9090).unwrap(),
+        "0.0.0.0:9090"
+    );
+    assert!(monitoring::generate_prometheus_config(&config).contains("['localhost:9100']"));
+
+    config.monitoring_bind_address = String::from("10.8.0.1");
+    assert_eq!(
+        monitoring::listen_address(&config, 9100).unwrap(),
+        "10.8.0.1:9100"
+    );
+    assert!(monitoring::generate_prometheus_config(&config).contains("['10.8.0.1:9100']"));
+
+    config.monitoring_bind_address = String::from("fd00::1");
+    assert_eq!(
+        monitoring::listen_address(&config, 9090).unwrap(),
+        "[fd00::1]:9090"
+    );
+
+    config.monitoring_bind_address = String::from("vpn0");
+    assert!(monitoring::listen_address(&config, 9090).is_err());
+}
+
+#[test]
+fn test_set_ini_option() {
+    let ini =
+        "[server]\n;http_port = 3000\ndomain = localhost\n\n[security]\n;admin_user = admin\n";
+
+    let updated = monitoring::set_ini_option(ini, "server", "http_port", "3300");
+    assert!(updated.contains("[server]\nhttp_port = 3300\ndomain = localhost"));
+    assert!(!updated.contains(";http_port"));
+
+    // Keys missing from an existing section are appended to it
+    let updated = monitoring::set_ini_option(ini, "server", "http_addr", "10.0.0.1");
+    assert!(updated.contains("domain = localhost\nhttp_addr = 10.0.0.1\n\n[security]"));
+
+    // Missing sections are created
+    let updated = monitoring::set_ini_option(ini, "users", "allow_sign_up", "false");
+    assert!(updated.ends_with("[users]\nallow_sign_up = false\n"));
+}
+
 #[test]
 fn test_setup_grafana() {
-    assert!(monitoring::setup_grafana().is_ok());
+    let config = Config {
+        monitoring: true,
+        secrets: Secrets {
+            grafana_admin_password: Some(String::from("s3cure-Grafana-pass")),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    assert!(monitoring::setup_grafana(&config).is_ok());
+    assert_eq!(
+        std::fs::read_to_string(monitoring::GRAFANA_PASSWORD_FILE).unwrap(),
+        "s3cure-Grafana-pass"
+    );
 
     // Verify Grafana service is running
     let status = std::process::Command::new("systemctl")
@@ -56,9 +188,40 @@ fn test_setup_grafana() {
     assert!(status.success());
 }
 
+#[test]
+fn test_grafana_admin_password_is_kept_out_of_the_logs() {
+    let config = Config {
+        secrets: Secrets {
+            grafana_admin_password: Some(String::from("s3cure-Grafana-pass")),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let host =
+        Arc::new(RecordingRunner::ubuntu("web1").with_file(monitoring::GRAFANA_INI_PATH, ""));
+    with_runner(host.clone(), || monitoring::setup_grafana(&config)).unwrap();
+
+    assert!(host.log().iter().all(|line| !line.contains("s3cure")));
+    assert_eq!(
+        host.inputs()[0],
+        (
+            String::from(
+                "grafana-cli --homepath /usr/share/grafana admin reset-admin-password --password-from-stdin"
+            ),
+            String::from("s3cure-Grafana-pass")
+        )
+    );
+    assert_eq!(
+        host.file(monitoring::GRAFANA_PASSWORD_FILE).as_deref(),
+        Some("s3cure-Grafana-pass")
+    );
+}
+
 #[test]
 fn test_setup_node_exporter() {
-    assert!(monitoring::setup_node_exporter().is_ok());
+    let rollback = RollbackManager::new();
+    let snapshot = rollback.create_snapshot("Node Exporter").unwrap();
+    assert!(monitoring::setup_node_exporter(&Config::default(), &rollback, snapshot).is_ok());
 
     // Verify Node Exporter service is running
     let status = std::process::Command::new("systemctl")
@@ -68,6 +231,27 @@ fn test_setup_node_exporter() {
     assert!(status.success());
 }
 
+#[test]
+fn test_setup_loki_and_promtail() {
+    let config = Config::default();
+    assert!(monitoring::setup_loki(&config).is_ok());
+    assert!(monitoring::setup_promtail(&config).is_ok());
+
+    // Verify Promtail ships to the local Loki
+    let promtail_config = fs::read_to_string("/etc/promtail/promtail.yaml").unwrap();
+    assert!(promtail_config.contains("http://localhost:3100/loki/api/v1/push"));
+    assert!(promtail_config.contains("/var/log/*.log"));
+
+    // Verify both services are running
+    for service in ["loki", "promtail"] {
+        let status = std::process::Command::new("systemctl")
+            .args(["is-active", service])
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+}
+
 #[test]
 fn test_setup_monitoring() {
     let config = Config {
@@ -78,3 +262,67 @@ fn test_setup_monitoring() {
 
     assert!(monitoring::setup_monitoring(&config, &rollback_manager).is_ok());
 }
+
+#[test]
+fn test_is_prometheus_duration() {
+    for duration in ["15d", "12w", "1y", "1w3d12h", "90m", "500ms", "1h30m15s"] {
+        assert!(monitoring::is_prometheus_duration(duration), "{}", duration);
+    }
+    for duration in ["", "15", "d", "30 days", "3d1w", "1d1d", "15D", "-1d"] {
+        assert!(
+            !monitoring::is_prometheus_duration(duration),
+            "{}",
+            duration
+        );
+    }
+}
+
+#[test]
+fn test_prometheus_storage_flags() {
+    let mut config = Config::default();
+    assert_eq!(
+        monitoring::prometheus_args(&config).unwrap(),
+        vec![
+            "--web.listen-address=0.0.0.0:9090",
+            "--storage.tsdb.retention.time=15d",
+        ]
+    );
+    let unit = monitoring::prometheus_service_unit(&config).unwrap();
+    assert!(unit.contains("--storage.tsdb.path /var/lib/prometheus/ \\\n"));
+    assert!(unit.contains("--storage.tsdb.retention.time=15d \\\n"));
+
+    config.prometheus_retention = String::from("30d");
+    config.prometheus_storage_path = Some(String::from("/data/prometheus/"));
+    assert_eq!(
+        monitoring::prometheus_args(&config).unwrap(),
+        vec![
+            "--web.listen-address=0.0.0.0:9090",
+            "--storage.tsdb.retention.time=30d",
+            "--storage.tsdb.path=/data/prometheus/",
+        ]
+    );
+    let unit = monitoring::prometheus_service_unit(&config).unwrap();
+    assert!(unit.contains("--storage.tsdb.path /data/prometheus/ \\\n"));
+    assert!(unit.contains("--storage.tsdb.retention.time=30d \\\n"));
+}
+
+#[test]
+fn test_cadvisor() {
+    let mut config = Config::default();
+    assert!(!monitoring::generate_prometheus_config(&config).contains("cadvisor"));
+
+    config.use_containers = true;
+    assert!(monitoring::generate_prometheus_config(&config).contains(
+        "  - job_name: 'cadvisor'\n    static_configs:\n      - targets: ['localhost:8080']\n"
+    ));
+
+    config.monitoring_bind_address = String::from("10.8.0.1");
+    let args = monitoring::cadvisor_run_args(&config).unwrap();
+    assert_eq!(&args[..4], ["run", "-d", "--name", "cadvisor"]);
+    assert!(args
+        .windows(2)
+        .any(|pair| pair == ["-p", "10.8.0.1:8080:8080"]));
+    assert!(args.windows(2).any(|pair| pair == ["-v", "/sys:/sys:ro"]));
+    assert_eq!(args.last().unwrap(), monitoring::CADVISOR_IMAGE);
+    assert!(monitoring::generate_prometheus_config(&config).contains("['10.8.0.1:8080']"));
+}
@@ -0,0 +1,78 @@
+use crate::common::RecordingRunner;
+use serde_json::json;
+use server_forge::config::{Config, Secrets};
+use server_forge::notify::{completion_payload, send_completion, RunOutcome};
+use server_forge::runner::with_runner;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn failed_outcome() -> RunOutcome {
+    RunOutcome {
+        failed_phases: vec![String::from("Monitoring")],
+        error: Some(String::from("Monitoring failed: prometheus did not start")),
+        duration: Duration::from_millis(187_200),
+    }
+}
+
+#[test]
+fn test_completion_payload() {
+    let succeeded = RunOutcome {
+        failed_phases: Vec::new(),
+        error: None,
+        duration: Duration::from_millis(42_500),
+    };
+    assert_eq!(
+        completion_payload("web1", &succeeded),
+        json!({
+            "text": ":white_check_mark: ServerForge setup of web1 succeeded in 42.5s",
+            "hostname": "web1",
+            "outcome": "succeeded",
+            "failed_phases": [],
+            "duration_secs": 42.5,
+        })
+    );
+
+    let payload = completion_payload("web1", &failed_outcome());
+    assert_eq!(
+        payload["text"],
+        ":x: ServerForge setup of web1 failed after 3m 07.2s: \
+         Monitoring failed: prometheus did not start"
+    );
+    assert_eq!(payload["outcome"], "failed");
+    assert_eq!(payload["failed_phases"], json!(["Monitoring"]));
+}
+
+#[test]
+fn test_send_completion() {
+    // Nothing is sent without a webhook
+    let host = Arc::new(RecordingRunner::new("web1"));
+    with_runner(host.clone(), || {
+        send_completion(&Config::default(), &failed_outcome())
+    });
+    assert!(host.log().is_empty());
+
+    let config = Config {
+        secrets: Secrets {
+            notification_webhook: Some(String::from("https://hooks.example.com/T0/B0/x")),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let host = Arc::new(RecordingRunner::new("web1"));
+    with_runner(host.clone(), || send_completion(&config, &failed_outcome()));
+    let curl = host.log().pop().unwrap();
+    assert!(curl.starts_with("curl "));
+    assert!(curl.ends_with(" https://hooks.example.com/T0/B0/x"));
+    let payload = &curl[curl.find('{').unwrap()..=curl.rfind('}').unwrap()];
+    let payload: serde_json::Value = serde_json::from_str(payload).unwrap();
+    assert_eq!(payload["hostname"], "web1");
+
+    // An unreachable webhook does not fail the run
+    let host = Arc::new(RecordingRunner::new("web1").fail_with(
+        "curl",
+        "curl: (7) Failed to connect",
+        None,
+    ));
+    with_runner(host.clone(), || send_completion(&config, &failed_outcome()));
+    assert_eq!(host.log().len(), 1);
+}
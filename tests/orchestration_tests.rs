@@ -0,0 +1,154 @@
+use crate::common::RecordingRunner;
+use server_forge::config::Config;
+use server_forge::rollback::RollbackManager;
+use server_forge::runner::with_runner;
+use server_forge::security::implement_security_measures;
+use server_forge::setup::initial_setup;
+use std::error::Error;
+use std::sync::Arc;
+
+/// What the initial setup and the security measures do to a fresh Ubuntu host named "ubuntu"
+/// with curl installed, in order.
+const EXPECTED_LOG: &[&str] = &[
+    "write /etc/hostname",
+    "hostnamectl set-hostname web1",
+    "write /etc/hosts",
+    "apt update",
+    "apt upgrade -y -o Dpkg::Options::=--force-confdef -o Dpkg::Options::=--force-confold",
+    "dpkg-query -W -f=${Status} ${Version} curl",
+    "dpkg-query -W -f=${Status} ${Version} wget",
+    "dpkg-query -W -f=${Status} wget",
+    "apt install -y wget",
+    "dpkg-query -W -f=${Status} ${Version} wget",
+    "dpkg-query -W -f=${Status} ${Version} vim",
+    "dpkg-query -W -f=${Status} vim",
+    "apt install -y vim",
+    "dpkg-query -W -f=${Status} ${Version} vim",
+    "dpkg-query -W -f=${Status} ${Version} ufw",
+    "dpkg-query -W -f=${Status} ufw",
+    "apt install -y ufw",
+    "dpkg-query -W -f=${Status} ${Version} ufw",
+    "dpkg-query -W -f=${Status} ${Version} fail2ban",
+    "dpkg-query -W -f=${Status} fail2ban",
+    "apt install -y fail2ban",
+    "dpkg-query -W -f=${Status} ${Version} fail2ban",
+    "dpkg-query -W -f=${Status} ${Version} apt-listchanges",
+    "dpkg-query -W -f=${Status} apt-listchanges",
+    "apt install -y apt-listchanges",
+    "dpkg-query -W -f=${Status} ${Version} apt-listchanges",
+    "dpkg-query -W -f=${Status} ${Version} needrestart",
+    "dpkg-query -W -f=${Status} needrestart",
+    "apt install -y needrestart",
+    "dpkg-query -W -f=${Status} ${Version} needrestart",
+    "dpkg-query -W -f=${Status} ${Version} debsums",
+    "dpkg-query -W -f=${Status} debsums",
+    "apt install -y debsums",
+    "dpkg-query -W -f=${Status} ${Version} debsums",
+    "dpkg-query -W -f=${Status} ${Version} apt-show-versions",
+    "dpkg-query -W -f=${Status} apt-show-versions",
+    "apt install -y apt-show-versions",
+    "dpkg-query -W -f=${Status} ${Version} apt-show-versions",
+    "write /etc/logrotate.d/server_forge",
+    "ufw default deny incoming",
+    "ufw default allow outgoing",
+    "ufw allow OpenSSH",
+    "ufw allow 2222/tcp",
+    "ufw --force enable",
+    "write /etc/ssh/sshd_config",
+    "sh -c ! ufw status | grep -q '^Status: active' || ufw status | grep -Eq '^2222(/tcp)? +ALLOW' || { echo 'Port 2222/tcp is not allowed by ufw' >&2; exit 1; }",
+    "sshd -t -f /etc/ssh/sshd_config",
+    "systemctl is-active sshd",
+    "systemctl is-enabled sshd",
+    "systemctl restart sshd",
+    "systemctl is-active sshd",
+    "dpkg-query -W -f=${Status} ${Version} fail2ban",
+    "write /etc/fail2ban/jail.local",
+    "systemctl is-active fail2ban",
+    "systemctl is-enabled fail2ban",
+    "systemctl enable fail2ban",
+    "systemctl start fail2ban",
+    "systemctl is-active fail2ban",
+    "dpkg-query -W -f=${Status} ${Version} rkhunter",
+    "dpkg-query -W -f=${Status} rkhunter",
+    "apt install -y rkhunter",
+    "dpkg-query -W -f=${Status} ${Version} rkhunter",
+    "dpkg-query -W -f=${Status} ${Version} chkrootkit",
+    "dpkg-query -W -f=${Status} chkrootkit",
+    "apt install -y chkrootkit",
+    "dpkg-query -W -f=${Status} ${Version} chkrootkit",
+    "rkhunter --update",
+    "rkhunter --propupd",
+    "write /usr/local/bin/security_scan.sh",
+    "chmod +x /usr/local/bin/security_scan.sh",
+    "write /etc/cron.d/security_scan",
+];
+
+/// A configuration setting the hostname, with basic security.
+fn config() -> Config {
+    Config {
+        hostname: Some(String::from("web1")),
+        security_level: String::from("basic"),
+        ..Default::default()
+    }
+}
+
+/// Runs the initial setup and the security measures on a host, as the pipeline does.
+fn run_setup(
+    runner: &Arc<RecordingRunner>,
+    rollback: &RollbackManager,
+) -> Result<(), Box<dyn Error>> {
+    with_runner(runner.clone(), || {
+        initial_setup(&config(), rollback)?;
+        implement_security_measures(&config(), rollback)
+    })
+}
+
+#[test]
+fn test_initial_setup_and_security_measures() {
+    let runner = Arc::new(RecordingRunner::ubuntu("web1").with_package("curl"));
+    run_setup(&runner, &RollbackManager::new()).unwrap();
+
+    assert_eq!(runner.log(), EXPECTED_LOG);
+    assert_eq!(runner.file("/etc/hostname").as_deref(), Some("web1\n"));
+    assert!(runner.file("/etc/hosts").unwrap().contains("web1"));
+    assert!(runner
+        .file("/etc/ssh/sshd_config")
+        .unwrap()
+        .lines()
+        .any(|line| line == "Port 2222"));
+    assert!(runner
+        .file("/etc/cron.d/security_scan")
+        .unwrap()
+        .contains("/usr/local/bin/security_scan.sh"));
+}
+
+#[test]
+fn test_failed_security_measures_are_rolled_back() {
+    let runner = Arc::new(
+        RecordingRunner::ubuntu("web1")
+            .with_package("curl")
+            .fail("rkhunter --update"),
+    );
+    let rollback = RollbackManager::new();
+    assert!(run_setup(&runner, &rollback).is_err());
+
+    // The run stops at the failed command
+    let failed = EXPECTED_LOG
+        .iter()
+        .position(|entry| *entry == "rkhunter --update")
+        .unwrap();
+    assert_eq!(runner.log(), EXPECTED_LOG[..=failed]);
+
+    // Only the security measures are rolled back: the committed initial setup stays
+    with_runner(runner.clone(), || rollback.rollback_uncommitted()).unwrap();
+    assert_eq!(
+        runner.log()[failed + 1..],
+        [
+            "systemctl disable fail2ban",
+            "systemctl stop fail2ban",
+            "apt remove -y rkhunter",
+            "apt remove -y chkrootkit",
+        ]
+    );
+    assert_eq!(runner.file("/etc/hostname").as_deref(), Some("web1\n"));
+}
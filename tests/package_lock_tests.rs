@@ -0,0 +1,63 @@
+use crate::common::RecordingRunner;
+use server_forge::distro::{install_package, PackageManager};
+use server_forge::runner::with_runner;
+use server_forge::utils::{is_package_lock_error, run_command};
+use std::sync::Arc;
+
+const APT_LOCK_ERROR: &str = "E: Could not get lock /var/lib/dpkg/lock-frontend. It is held by \
+                              process 1234 (unattended-upgr)\n\
+                              E: Unable to acquire the dpkg frontend lock \
+                              (/var/lib/dpkg/lock-frontend), is another process using it?\n";
+
+#[test]
+fn test_is_package_lock_error() {
+    assert!(is_package_lock_error(APT_LOCK_ERROR));
+    assert!(is_package_lock_error(
+        "E: Unable to lock the administration directory (/var/lib/dpkg/), is another process using it?"
+    ));
+    assert!(is_package_lock_error(
+        "Another app is currently holding the yum lock; waiting for it to exit..."
+    ));
+    assert!(!is_package_lock_error(
+        "E: Unable to locate package nginx-full"
+    ));
+    assert!(!is_package_lock_error(""));
+}
+
+#[test]
+fn test_package_manager_retries_while_locked() {
+    let runner = Arc::new(RecordingRunner::new("web1").fail_with(
+        "apt install -y nginx",
+        APT_LOCK_ERROR,
+        Some(1),
+    ));
+
+    with_runner(runner.clone(), || {
+        install_package(&PackageManager::Apt, "nginx")
+    })
+    .unwrap();
+
+    assert_eq!(runner.count("apt install -y nginx"), 2);
+}
+
+#[test]
+fn test_other_failures_are_not_retried() {
+    let runner = Arc::new(
+        RecordingRunner::new("web1")
+            .fail_with(
+                "apt install -y nginx-full",
+                "E: Unable to locate package nginx-full",
+                None,
+            )
+            .fail_with("sh -c", APT_LOCK_ERROR, Some(1)),
+    );
+
+    with_runner(runner.clone(), || {
+        assert!(install_package(&PackageManager::Apt, "nginx-full").is_err());
+        // Only package managers are retried, whatever the error says
+        assert!(run_command("sh", &["-c", "apt install -y nginx"]).is_err());
+    });
+
+    assert_eq!(runner.count("apt install -y nginx-full"), 1);
+    assert_eq!(runner.count("sh -c apt install -y nginx"), 1);
+}
@@ -0,0 +1,240 @@
+use server_forge::config::Config;
+use server_forge::pipeline::{self, FailurePolicy, Phase, PhaseFailure};
+use server_forge::rollback::{RollbackManager, SnapshotStatus};
+use std::fs;
+use std::sync::Mutex;
+
+/// Builds a phase that records a snapshot, changes `file`, and fails if `fail` is set.
+fn phase<'a>(
+    name: &'a str,
+    policy: FailurePolicy,
+    fail: bool,
+    file: &'a str,
+    ran: &'a Mutex<Vec<&'a str>>,
+) -> Phase<'a> {
+    Phase::new(name, policy, &[], move |rollback| {
+        ran.lock().unwrap().push(name);
+        let snapshot = rollback.create_snapshot(name)?;
+        rollback.add_file_change(snapshot, file)?;
+        fs::write(file, name)?;
+        if fail {
+            return Err(format!("{} broke", name).into());
+        }
+        rollback.commit_snapshot(snapshot)
+    })
+}
+
+#[test]
+fn test_run_phases_continues_after_optional_failure() {
+    let file = "/tmp/test_pipeline_continue.txt";
+    fs::write(file, "original").unwrap();
+    let ran = Mutex::new(Vec::new());
+    let phases = [
+        phase("Setup", FailurePolicy::Abort, false, file, &ran),
+        phase("Monitoring", FailurePolicy::Continue, true, file, &ran),
+        phase("Backups", FailurePolicy::Continue, false, file, &ran),
+    ];
+    let rollback = RollbackManager::new();
+
+    let summary = pipeline::run_phases(&phases, &rollback, true).unwrap();
+
+    let timed: Vec<&str> = summary
+        .timings
+        .iter()
+        .map(|timing| timing.phase.as_str())
+        .collect();
+    assert_eq!(timed, vec!["Setup", "Monitoring", "Backups"]);
+    assert_eq!(
+        summary.failures,
+        vec![PhaseFailure {
+            phase: String::from("Monitoring"),
+            error: String::from("Monitoring broke"),
+        }]
+    );
+    assert_eq!(*ran.lock().unwrap(), vec!["Setup", "Monitoring", "Backups"]);
+    let statuses: Vec<SnapshotStatus> = rollback
+        .list_snapshots()
+        .unwrap()
+        .iter()
+        .map(|info| info.status)
+        .collect();
+    assert_eq!(
+        statuses,
+        vec![
+            SnapshotStatus::Committed,
+            SnapshotStatus::RolledBack,
+            SnapshotStatus::Committed,
+        ]
+    );
+    assert_eq!(fs::read_to_string(file).unwrap(), "Backups");
+}
+
+#[test]
+fn test_run_phases_stops_on_critical_failure() {
+    let file = "/tmp/test_pipeline_abort.txt";
+    fs::write(file, "original").unwrap();
+    let ran = Mutex::new(Vec::new());
+    let phases = [
+        phase("Setup", FailurePolicy::Abort, false, file, &ran),
+        phase("Security", FailurePolicy::Abort, true, file, &ran),
+        phase("Backups", FailurePolicy::Continue, false, file, &ran),
+    ];
+    let rollback = RollbackManager::new();
+
+    let error = pipeline::run_phases(&phases, &rollback, true).unwrap_err();
+
+    assert_eq!(error.to_string(), "Security failed: Security broke");
+    assert_eq!(*ran.lock().unwrap(), vec!["Setup", "Security"]);
+    // The completed phase is kept
+    assert_eq!(fs::read_to_string(file).unwrap(), "Setup");
+}
+
+#[test]
+fn test_run_phases_stops_on_optional_failure_by_default() {
+    let file = "/tmp/test_pipeline_default.txt";
+    fs::write(file, "original").unwrap();
+    let ran = Mutex::new(Vec::new());
+    let phases = [
+        phase("Monitoring", FailurePolicy::Continue, true, file, &ran),
+        phase("Backups", FailurePolicy::Continue, false, file, &ran),
+    ];
+
+    assert!(pipeline::run_phases(&phases, &RollbackManager::new(), false).is_err());
+    assert_eq!(*ran.lock().unwrap(), vec!["Monitoring"]);
+    assert_eq!(fs::read_to_string(file).unwrap(), "original");
+}
+
+#[test]
+fn test_phases_classification() {
+    let config = Config {
+        deployed_apps: vec![String::from("nginx"), String::from("nodejs")],
+        ..Default::default()
+    };
+
+    let phases: Vec<(String, FailurePolicy)> = pipeline::phases(&config)
+        .iter()
+        .map(|phase| (phase.name.clone(), phase.policy))
+        .collect();
+    let expected = [
+        ("Initial setup", FailurePolicy::Abort),
+        ("Security measures", FailurePolicy::Abort),
+        ("Automatic updates", FailurePolicy::Continue),
+        ("Monitoring", FailurePolicy::Continue),
+        ("Backup system", FailurePolicy::Continue),
+        ("Deploying nginx", FailurePolicy::Continue),
+        ("Deploying nodejs", FailurePolicy::Continue),
+        ("Reverse proxy", FailurePolicy::Continue),
+    ];
+    assert_eq!(
+        phases,
+        expected
+            .iter()
+            .map(|(name, policy)| (name.to_string(), *policy))
+            .collect::<Vec<_>>()
+    );
+
+    let config = Config {
+        use_containers: true,
+        use_kubernetes: true,
+        ..Default::default()
+    };
+    let policies: Vec<(String, FailurePolicy)> = pipeline::phases(&config)
+        .iter()
+        .skip(5)
+        .map(|phase| (phase.name.clone(), phase.policy))
+        .collect();
+    assert_eq!(
+        policies,
+        vec![
+            (String::from("Docker"), FailurePolicy::Abort),
+            (String::from("Kubernetes"), FailurePolicy::Abort),
+            (
+                String::from("Container deployment"),
+                FailurePolicy::Continue
+            ),
+        ]
+    );
+
+    let config = Config {
+        use_containers: true,
+        monitoring: true,
+        ..Default::default()
+    };
+    let names: Vec<String> = pipeline::phases(&config)
+        .iter()
+        .skip(5)
+        .map(|phase| phase.name.clone())
+        .collect();
+    assert_eq!(
+        names,
+        vec!["Docker", "Container metrics", "Container deployment"]
+    );
+}
+
+#[test]
+fn test_phase_inputs_cover_every_option() {
+    let containers = Config {
+        use_containers: true,
+        use_kubernetes: true,
+        ..Default::default()
+    };
+    let apps = Config {
+        deployed_apps: vec![String::from("nginx")],
+        git_apps: serde_yaml::from_str(
+            "[{repo_url: https://example.com/app.git, target_dir: /opt/app, run_command: ./app}]",
+        )
+        .unwrap(),
+        ..Default::default()
+    };
+    let mut phases = pipeline::phases(&containers);
+    phases.extend(pipeline::phases(&apps));
+
+    let serde_json::Value::Object(fields) = serde_json::to_value(Config::default()).unwrap() else {
+        panic!("Config does not serialize to an object");
+    };
+    for field in fields.keys() {
+        assert!(
+            pipeline::GLOBAL_INPUTS.contains(&field.as_str())
+                || pipeline::RUN_OPTIONS.contains(&field.as_str())
+                || phases
+                    .iter()
+                    .any(|phase| phase.inputs.contains(&field.as_str())),
+            "{} is not an input of any phase",
+            field
+        );
+    }
+}
+
+#[test]
+fn test_changed_phases() {
+    let previous = Config {
+        deployed_apps: vec![String::from("nginx")],
+        ..Default::default()
+    };
+    let names = |config: &Config| -> Vec<String> {
+        pipeline::changed_phases(pipeline::phases(config), &previous.diff(config))
+            .iter()
+            .map(|phase| phase.name.clone())
+            .collect()
+    };
+
+    assert!(names(&previous).is_empty());
+
+    let config = Config {
+        update_schedule: String::from("daily"),
+        ..previous.clone()
+    };
+    assert_eq!(names(&config), vec!["Automatic updates"]);
+
+    let config = Config {
+        grafana_port: 3001,
+        ..previous.clone()
+    };
+    assert_eq!(names(&config), vec!["Initial setup", "Monitoring"]);
+
+    let config = Config {
+        https_proxy: Some(String::from("http://proxy:3128")),
+        ..previous.clone()
+    };
+    assert_eq!(names(&config).len(), pipeline::phases(&config).len());
+}
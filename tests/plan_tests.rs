@@ -0,0 +1,210 @@
+use crate::common::RecordingRunner;
+use server_forge::config::Config;
+use server_forge::distro::{install_package_tracked, PackageManager};
+use server_forge::plan::{plan_host, Operation, Plan};
+use server_forge::rollback::{RollbackAction, RollbackManager, ServiceState};
+use server_forge::runner::{with_runner, CommandRunner};
+use std::sync::Arc;
+
+/// A host running Ubuntu, recording the commands it runs. Only sshd is running and enabled
+/// until other services are started, curl is the only installed package (version 7.81.0),
+/// and the package `no-such-package` cannot be installed.
+fn fake_host() -> Arc<RecordingRunner> {
+    Arc::new(
+        RecordingRunner::new("web1")
+            .with_file("/usr/bin/apt", "")
+            .with_file("/etc/ssh/sshd_config", "PermitRootLogin yes\n#Port 22\n")
+            .with_service("sshd")
+            .with_package("curl")
+            .respond(
+                "dpkg-query -W -f=${Status} ${Version} curl",
+                "install ok installed 7.81.0",
+            )
+            .fail("apt install -y no-such-package"),
+    )
+}
+
+#[test]
+fn test_plan_host() {
+    let host = fake_host();
+    let config = Config {
+        linux_distro: String::from("ubuntu"),
+        deployed_apps: vec![String::from("apache")],
+        ..Config::default()
+    };
+
+    let plan = plan_host(&config, host.clone()).unwrap();
+
+    // Planning reads from the host but never changes it
+    assert!(host.log().is_empty());
+    assert_eq!(
+        host.file("/etc/ssh/sshd_config").as_deref(),
+        Some("PermitRootLogin yes\n#Port 22\n")
+    );
+
+    let operations = plan.operations();
+    assert_eq!(operations[0], Operation::UpgradePackages);
+    assert!(operations.contains(&Operation::WriteFile {
+        path: String::from("/etc/ssh/sshd_config"),
+        contents: String::from("PermitRootLogin no\nPort 2222\n"),
+    }));
+    assert!(operations.contains(&Operation::InstallPackages {
+        packages: vec![String::from("fail2ban")],
+    }));
+    assert!(operations.contains(&Operation::EnableService {
+        name: String::from("apache2"),
+    }));
+}
+
+#[test]
+fn test_execute_with_rollback() {
+    let host = fake_host();
+    let mut plan = Plan::new();
+    plan.install(&["nginx"])
+        .write_file("/etc/ssh/sshd_config", "PermitRootLogin no\n")
+        .write_file("/etc/ssh/sshd_config", "PermitRootLogin no\nPort 2222\n")
+        .restart_service("sshd")
+        .start_service("nginx")
+        .enable_service("nginx");
+
+    let rollback = RollbackManager::new();
+    with_runner(host.clone(), || {
+        let snapshot = rollback.create_snapshot("Test").unwrap();
+        plan.execute_with_rollback(&rollback, snapshot).unwrap();
+    });
+
+    assert_eq!(
+        host.commands(),
+        vec![
+            "dpkg-query -W -f=${Status} ${Version} nginx",
+            "dpkg-query -W -f=${Status} nginx",
+            "apt install -y nginx",
+            "dpkg-query -W -f=${Status} ${Version} nginx",
+            "systemctl is-active sshd",
+            "systemctl is-enabled sshd",
+            "systemctl restart sshd",
+            "systemctl is-active sshd",
+            "systemctl is-active nginx",
+            "systemctl is-enabled nginx",
+            "systemctl start nginx",
+            "systemctl is-active nginx",
+            "systemctl enable nginx",
+        ]
+    );
+    assert_eq!(
+        host.file("/etc/ssh/sshd_config").as_deref(),
+        Some("PermitRootLogin no\nPort 2222\n")
+    );
+
+    // Rolling back restores the original file and service states, and removes the
+    // installed package
+    let preview = rollback.preview_rollback(None).unwrap();
+    assert!(preview.contains(&RollbackAction::RestoreService {
+        snapshot: 0,
+        service: String::from("sshd"),
+        state: ServiceState {
+            active: true,
+            enabled: true
+        },
+    }));
+    assert_eq!(
+        preview[0].to_string(),
+        "restore /etc/ssh/sshd_config (snapshot 0)"
+    );
+    assert_eq!(
+        preview[1].to_string(),
+        "make service nginx inactive and disabled (snapshot 0)"
+    );
+
+    let ran = host.commands().len();
+    with_runner(host.clone(), || rollback.rollback_all().unwrap());
+    assert_eq!(
+        host.file("/etc/ssh/sshd_config").as_deref(),
+        Some("PermitRootLogin yes\n#Port 22\n")
+    );
+    assert_eq!(
+        host.commands()[ran..],
+        [
+            "systemctl disable nginx",
+            "systemctl stop nginx",
+            "systemctl enable sshd",
+            "systemctl restart sshd",
+            "apt remove -y nginx",
+        ]
+    );
+}
+
+#[test]
+fn test_execute_skips_installed_packages() {
+    let host = fake_host();
+    let mut plan = Plan::new();
+    plan.install(&["curl"]);
+    with_runner(host.clone(), || plan.execute()).unwrap();
+
+    // Installing curl again would upgrade it
+    assert_eq!(
+        host.commands(),
+        vec!["dpkg-query -W -f=${Status} ${Version} curl"]
+    );
+}
+
+#[test]
+fn test_install_package_tracked() {
+    let host = fake_host();
+    let rollback = RollbackManager::new();
+    with_runner(host.clone(), || {
+        let snapshot = rollback.create_snapshot("Test").unwrap();
+        install_package_tracked(&PackageManager::Apt, "nginx", &rollback, snapshot).unwrap();
+        install_package_tracked(&PackageManager::Apt, "curl", &rollback, snapshot).unwrap();
+        assert!(install_package_tracked(
+            &PackageManager::Apt,
+            "no-such-package",
+            &rollback,
+            snapshot
+        )
+        .is_err());
+    });
+
+    // Only the package that was newly installed is uninstalled on rollback
+    assert_eq!(rollback.list_snapshots().unwrap()[0].packages_installed, 1);
+    let ran = host.commands().len();
+    with_runner(host.clone(), || rollback.rollback_all().unwrap());
+    assert_eq!(host.commands()[ran..], ["apt remove -y nginx"]);
+}
+
+#[test]
+fn test_firewall_rollback() {
+    let host = fake_host();
+    host.write_file("/usr/sbin/ufw", b"").unwrap();
+    host.write_file("/etc/ufw/user.rules", b"*filter\nCOMMIT\n")
+        .unwrap();
+    let mut plan = Plan::new();
+    plan.run("ufw", &["allow", "80/tcp"])
+        .run("ufw", &["enable"]);
+
+    let rollback = RollbackManager::new();
+    with_runner(host.clone(), || {
+        let snapshot = rollback.create_snapshot("Firewall").unwrap();
+        plan.execute_with_rollback(&rollback, snapshot).unwrap();
+        // ufw saves the added rule
+        host.write_file(
+            "/etc/ufw/user.rules",
+            b"*filter\n-A ufw-user-input -p tcp --dport 80 -j ACCEPT\nCOMMIT\n",
+        )
+        .unwrap();
+    });
+    assert_eq!(host.count("ufw status"), 1);
+    assert_eq!(
+        rollback.preview_rollback(None).unwrap()[0].to_string(),
+        "restore the ufw configuration (snapshot 0)"
+    );
+
+    // ufw was inactive before, so rolling back restores its rules and disables it
+    let ran = host.commands().len();
+    with_runner(host.clone(), || rollback.rollback_all().unwrap());
+    assert_eq!(
+        host.file("/etc/ufw/user.rules").as_deref(),
+        Some("*filter\nCOMMIT\n")
+    );
+    assert_eq!(host.commands()[ran..], ["ufw disable"]);
+}
@@ -0,0 +1,14 @@
+use server_forge::progress::{command_started, is_enabled, step_label, StepProgress};
+
+#[test]
+fn test_step_label() {
+    assert_eq!(step_label(3, 6, "Monitoring"), "[3/6] Monitoring...");
+}
+
+#[test]
+fn test_step_progress_disabled_by_default() {
+    assert!(!is_enabled());
+    let progress = StepProgress::start(1, 2, "Initial setup");
+    command_started("apt-get", &["upgrade", "-y"]);
+    drop(progress);
+}
@@ -0,0 +1,107 @@
+use server_forge::config::Config;
+use server_forge::deployment;
+use server_forge::plan::Plan;
+use server_forge::registry::{self, AppDeployer, DeployContext, DeployerRegistry};
+use server_forge::rollback::{RollbackManager, SnapshotStatus};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+/// A deployer recording the snapshots it deployed in, failing if `fail` is set.
+struct RecordingDeployer {
+    name: &'static str,
+    fail: bool,
+    snapshots: Arc<Mutex<Vec<usize>>>,
+}
+
+impl AppDeployer for RecordingDeployer {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn deploy(&self, ctx: &DeployContext) -> Result<(), Box<dyn Error>> {
+        self.snapshots.lock().unwrap().push(ctx.snapshot);
+        if self.fail {
+            return Err(format!("{} broke", self.name).into());
+        }
+        Ok(())
+    }
+}
+
+fn deployer(name: &'static str, fail: bool) -> (RecordingDeployer, Arc<Mutex<Vec<usize>>>) {
+    let snapshots = Arc::new(Mutex::new(Vec::new()));
+    let deployer = RecordingDeployer {
+        name,
+        fail,
+        snapshots: Arc::clone(&snapshots),
+    };
+    (deployer, snapshots)
+}
+
+#[test]
+fn test_deployer_registry() {
+    let mut registry = DeployerRegistry::new();
+    assert!(registry.get("registry-test-app").is_none());
+
+    let (first, _) = deployer("registry-test-app", false);
+    let (second, _) = deployer("registry-test-app", true);
+    registry.register(Arc::new(first));
+    registry.register(Arc::new(second));
+
+    let config = Config::default();
+    let rollback = RollbackManager::new();
+    let ctx = DeployContext {
+        config: &config,
+        rollback: &rollback,
+        snapshot: 0,
+    };
+    // The second deployer replaced the first one
+    assert!(registry
+        .get("registry-test-app")
+        .unwrap()
+        .deploy(&ctx)
+        .is_err());
+}
+
+#[test]
+fn test_deploy_application_with_registered_deployer() {
+    let (custom, snapshots) = deployer("registry-test-deployed", false);
+    registry::register_deployer(custom).unwrap();
+    let config = Config {
+        deployed_apps: vec![String::from("registry-test-deployed")],
+        ..Default::default()
+    };
+    let rollback = RollbackManager::new();
+
+    deployment::deploy_application("registry-test-deployed", &config, &rollback).unwrap();
+
+    assert_eq!(*snapshots.lock().unwrap(), vec![0]);
+    let snapshot = &rollback.list_snapshots().unwrap()[0];
+    assert_eq!(
+        snapshot.label,
+        "Application deployment: registry-test-deployed"
+    );
+    assert_eq!(snapshot.status, SnapshotStatus::Committed);
+
+    // Custom applications are left out of plans
+    let plan = Plan::build(|plan| deployment::plan_applications(plan, &config)).unwrap();
+    assert!(plan.operations().is_empty());
+}
+
+#[test]
+fn test_failed_custom_deployment_is_not_committed() {
+    let (custom, _) = deployer("registry-test-failing", true);
+    registry::register_deployer(custom).unwrap();
+    let rollback = RollbackManager::new();
+
+    let result =
+        deployment::deploy_application("registry-test-failing", &Config::default(), &rollback);
+
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "registry-test-failing broke"
+    );
+    assert_eq!(
+        rollback.list_snapshots().unwrap()[0].status,
+        SnapshotStatus::Pending
+    );
+}
@@ -0,0 +1,95 @@
+use crate::common::RecordingRunner;
+use server_forge::remote::remote_command_line;
+use server_forge::runner::{current_runner, with_runner, CommandRunner, LocalCommandRunner};
+use server_forge::utils::{read_file, run_command, run_command_streaming, write_file};
+use std::sync::Arc;
+
+#[test]
+fn test_with_runner_dispatches_commands_and_files() {
+    let runner = Arc::new(RecordingRunner::new("fake").fail("false"));
+
+    with_runner(runner.clone(), || {
+        run_command("systemctl", &["restart", "nginx"]).unwrap();
+        assert!(run_command("false", &[]).is_err());
+        write_file("/etc/example.conf", "setting = 1\n").unwrap();
+        assert_eq!(read_file("/etc/example.conf").unwrap(), "setting = 1\n");
+    });
+
+    assert_eq!(
+        runner.log(),
+        vec![
+            "systemctl restart nginx",
+            "false",
+            "write /etc/example.conf"
+        ]
+    );
+
+    // The local runner is restored afterwards
+    assert_eq!(current_runner().host(), "localhost");
+}
+
+#[test]
+fn test_remote_command_line() {
+    assert_eq!(
+        remote_command_line("apt", &["install", "-y", "nginx"], &[]),
+        "'apt' 'install' '-y' 'nginx'"
+    );
+    assert_eq!(
+        remote_command_line(
+            "mysql",
+            &["-e", "DELETE FROM mysql.user WHERE User='';"],
+            &[(String::from("http_proxy"), String::from("http://proxy:3128"))]
+        ),
+        "env 'http_proxy=http://proxy:3128' 'mysql' '-e' 'DELETE FROM mysql.user WHERE User='\\'''\\'';'"
+    );
+}
+
+#[test]
+fn test_local_run_streaming() {
+    let mut lines = Vec::new();
+    let output = LocalCommandRunner
+        .run_streaming(
+            "sh",
+            &["-c", "echo one; echo two >&2; printf three"],
+            &[],
+            &mut |line| lines.push(line.to_string()),
+        )
+        .unwrap();
+
+    assert!(output.success);
+    assert_eq!(output.stdout, "one\nthree");
+    assert_eq!(output.stderr, "two\n");
+    lines.sort();
+    assert_eq!(lines, vec!["one", "three", "two"]);
+
+    let output = LocalCommandRunner
+        .run_streaming("sh", &["-c", "exit 3"], &[], &mut |_| {})
+        .unwrap();
+    assert!(!output.success);
+}
+
+#[test]
+fn test_local_run_with_input() {
+    let output = LocalCommandRunner
+        .run_with_input("cat", &[], &[], b"s3cret\n")
+        .unwrap();
+    assert!(output.success);
+    assert_eq!(output.stdout, "s3cret\n");
+
+    let output = LocalCommandRunner
+        .run_with_input("sh", &["-c", "exit 3"], &[], b"unread")
+        .unwrap();
+    assert!(!output.success);
+}
+
+#[test]
+fn test_run_command_streaming_through_runner() {
+    let runner = Arc::new(RecordingRunner::new("fake").fail("false"));
+
+    with_runner(runner.clone(), || {
+        run_command_streaming("apt", &["upgrade", "-y"]).unwrap();
+        assert!(run_command_streaming("false", &[]).is_err());
+    });
+
+    assert_eq!(runner.log(), vec!["apt upgrade -y", "false"]);
+}
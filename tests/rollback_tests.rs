@@ -1,17 +1,35 @@
-use server_forge::rollback::RollbackManager;
+use server_forge::rollback::{
+    interrupted_runs, latest_run, preview_run, recover, rollback_run, Recovery, RollbackAction,
+    RollbackManager, SnapshotStatus,
+};
 use std::fs;
 
 #[test]
 fn test_create_snapshot() {
     let rollback_manager = RollbackManager::new();
-    let snapshot_id = rollback_manager.create_snapshot().unwrap();
-    assert!(snapshot_id > 0);
+    let snapshot_id = rollback_manager.create_snapshot("Test").unwrap();
+    assert_eq!(snapshot_id, 0);
+    assert!(rollback_manager.rollback_to(snapshot_id).is_ok());
+}
+
+#[test]
+fn test_create_snapshot_sequential_ids() {
+    let rollback_manager = RollbackManager::new();
+    let first = rollback_manager.create_snapshot("Initial setup").unwrap();
+    let second = rollback_manager
+        .create_snapshot("Security measures")
+        .unwrap();
+    assert_eq!((first, second), (0, 1));
+    assert_eq!(
+        rollback_manager.list_snapshots().unwrap()[second].id,
+        second
+    );
 }
 
 #[test]
 fn test_add_file_change() {
     let rollback_manager = RollbackManager::new();
-    let snapshot_id = rollback_manager.create_snapshot().unwrap();
+    let snapshot_id = rollback_manager.create_snapshot("Test").unwrap();
 
     let test_file = "/tmp/test_rollback.txt";
     fs::write(test_file, "original content").unwrap();
@@ -34,7 +52,7 @@ fn test_add_file_change() {
 #[test]
 fn test_add_package_installed() {
     let rollback_manager = RollbackManager::new();
-    let snapshot_id = rollback_manager.create_snapshot().unwrap();
+    let snapshot_id = rollback_manager.create_snapshot("Test").unwrap();
 
     let test_package = "htop";
 
@@ -64,3 +82,270 @@ fn test_add_package_installed() {
         .unwrap();
     assert!(status.success());
 }
+
+#[test]
+fn test_rollback_manager_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<RollbackManager>();
+
+    let rollback_manager = RollbackManager::new();
+    std::thread::scope(|scope| {
+        scope.spawn(|| rollback_manager.create_snapshot("Test").unwrap());
+    });
+    assert!(rollback_manager.rollback_to(0).is_ok());
+}
+
+#[test]
+fn test_list_snapshots() {
+    let rollback_manager = RollbackManager::new();
+    assert!(rollback_manager.list_snapshots().unwrap().is_empty());
+
+    let before = chrono::Local::now();
+    rollback_manager.create_snapshot("Initial setup").unwrap();
+    let security = rollback_manager
+        .create_snapshot("Security measures")
+        .unwrap();
+    rollback_manager
+        .add_package_installed(security, "fail2ban")
+        .unwrap();
+
+    let snapshots = rollback_manager.list_snapshots().unwrap();
+    let summary: Vec<(usize, &str, usize)> = snapshots
+        .iter()
+        .map(|info| (info.id, info.label.as_str(), info.packages_installed))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![(0, "Initial setup", 0), (1, "Security measures", 1)]
+    );
+    assert!(snapshots
+        .iter()
+        .all(|info| info.created_at >= before && info.files_changed == 0));
+}
+
+#[test]
+fn test_preview_rollback() {
+    let rollback_manager = RollbackManager::new();
+    let test_file = "/tmp/test_preview_rollback.txt";
+    fs::write(test_file, "original content").unwrap();
+
+    let setup = rollback_manager.create_snapshot("Initial setup").unwrap();
+    rollback_manager.add_file_change(setup, test_file).unwrap();
+    let security = rollback_manager
+        .create_snapshot("Security measures")
+        .unwrap();
+    rollback_manager
+        .add_package_installed(security, "fail2ban")
+        .unwrap();
+    fs::write(test_file, "modified content").unwrap();
+
+    let actions = rollback_manager.preview_rollback(None).unwrap();
+    assert_eq!(
+        actions,
+        vec![
+            RollbackAction::UninstallPackage {
+                snapshot: 1,
+                package: String::from("fail2ban"),
+            },
+            RollbackAction::RestoreFile {
+                snapshot: 0,
+                path: String::from(test_file),
+            },
+        ]
+    );
+    assert_eq!(actions[0].to_string(), "uninstall fail2ban (snapshot 1)");
+    assert_eq!(
+        rollback_manager.preview_rollback(Some(1)).unwrap(),
+        actions[..1]
+    );
+    assert!(rollback_manager.preview_rollback(Some(2)).is_err());
+
+    // Nothing was rolled back
+    assert_eq!(fs::read_to_string(test_file).unwrap(), "modified content");
+}
+
+#[test]
+fn test_preview_and_rollback_latest_run() {
+    let store = tempfile::tempdir().unwrap();
+    let root = store.path().to_str().unwrap();
+    assert_eq!(latest_run(&format!("{}/missing", root)).unwrap(), None);
+    let test_file = "/tmp/test_rollback_latest_run.txt";
+    fs::write(test_file, "original content").unwrap();
+
+    let earlier = RollbackManager::with_store(format!("{}/20240101_000000", root));
+    earlier.create_snapshot("Initial setup").unwrap();
+    let latest = RollbackManager::with_store(format!("{}/20240102_000000", root));
+    let setup = latest.create_snapshot("Initial setup").unwrap();
+    latest.add_file_change(setup, test_file).unwrap();
+    latest.commit_snapshot(setup).unwrap();
+    latest.create_snapshot("Security measures").unwrap();
+    fs::write(test_file, "modified content").unwrap();
+    drop(latest);
+
+    let dir = latest_run(root).unwrap().unwrap();
+    assert_eq!(dir, format!("{}/20240102_000000", root));
+    assert_eq!(
+        preview_run(&dir, None).unwrap().to_string(),
+        format!(
+            "Rolling back {} would:\n  restore {} (snapshot 0)\n",
+            dir, test_file
+        )
+    );
+    assert_eq!(
+        preview_run(&dir, Some(1)).unwrap().to_string(),
+        format!("Rolling back {} would change nothing\n", dir)
+    );
+    assert!(preview_run(&dir, Some(2)).is_err());
+    assert_eq!(fs::read_to_string(test_file).unwrap(), "modified content");
+
+    rollback_run(&dir, None).unwrap();
+    assert_eq!(fs::read_to_string(test_file).unwrap(), "original content");
+    assert!(preview_run(&dir, None).unwrap().actions.is_empty());
+}
+
+#[test]
+fn test_file_changes_across_snapshots() {
+    let rollback_manager = RollbackManager::new();
+    let test_file = "/tmp/test_rollback_snapshots.txt";
+    let original: Vec<u8> = (0..=255u8).cycle().take(64 * 1024).collect();
+    fs::write(test_file, &original).unwrap();
+
+    // The same contents recorded twice, then different contents for the same path
+    let setup = rollback_manager.create_snapshot("Initial setup").unwrap();
+    rollback_manager.add_file_change(setup, test_file).unwrap();
+    rollback_manager.add_file_change(setup, test_file).unwrap();
+    fs::write(test_file, "security content").unwrap();
+    let security = rollback_manager
+        .create_snapshot("Security measures")
+        .unwrap();
+    rollback_manager
+        .add_file_change(security, test_file)
+        .unwrap();
+    fs::write(test_file, "modified content").unwrap();
+
+    rollback_manager.rollback_to(security).unwrap();
+    assert_eq!(fs::read_to_string(test_file).unwrap(), "security content");
+
+    rollback_manager.rollback_all().unwrap();
+    assert_eq!(fs::read(test_file).unwrap(), original);
+}
+
+#[test]
+fn test_commit_snapshot_persists_and_rollback_uncommitted() {
+    let store = tempfile::tempdir().unwrap();
+    let dir = store.path().join("run");
+    let rollback_manager = RollbackManager::with_store(dir.to_str().unwrap());
+    let test_file = "/tmp/test_rollback_uncommitted.txt";
+    fs::write(test_file, "original content").unwrap();
+
+    let setup = rollback_manager.create_snapshot("Initial setup").unwrap();
+    rollback_manager.add_file_change(setup, test_file).unwrap();
+    fs::write(test_file, "setup content").unwrap();
+    rollback_manager.commit_snapshot(setup).unwrap();
+    let security = rollback_manager
+        .create_snapshot("Security measures")
+        .unwrap();
+    rollback_manager
+        .add_file_change(security, test_file)
+        .unwrap();
+    fs::write(test_file, "security content").unwrap();
+
+    let persisted = |id: usize| -> serde_json::Value {
+        let json = fs::read(dir.join(format!("snapshot-{}.json", id))).unwrap();
+        serde_json::from_slice(&json).unwrap()
+    };
+    assert_eq!(persisted(setup)["status"], "Committed");
+    assert_eq!(persisted(setup)["label"], "Initial setup");
+    assert_eq!(persisted(security)["status"], "Pending");
+    assert_eq!(fs::read_dir(dir.join("contents")).unwrap().count(), 2);
+
+    // Only the in-progress phase is rolled back
+    rollback_manager.rollback_uncommitted().unwrap();
+    assert_eq!(fs::read_to_string(test_file).unwrap(), "setup content");
+    let statuses: Vec<SnapshotStatus> = rollback_manager
+        .list_snapshots()
+        .unwrap()
+        .iter()
+        .map(|info| info.status)
+        .collect();
+    assert_eq!(
+        statuses,
+        vec![SnapshotStatus::Committed, SnapshotStatus::RolledBack]
+    );
+    assert_eq!(persisted(security)["status"], "RolledBack");
+    assert!(rollback_manager.commit_snapshot(security).is_err());
+}
+
+#[test]
+fn test_recovery_from_str() {
+    assert_eq!("r".parse(), Ok(Recovery::RollBack));
+    assert_eq!("Roll back".parse(), Ok(Recovery::RollBack));
+    assert_eq!(" c\n".parse(), Ok(Recovery::Commit));
+    assert_eq!("ignore".parse(), Ok(Recovery::Ignore));
+    assert_eq!(
+        "later".parse::<Recovery>(),
+        Err(String::from("Unknown recovery 'later'"))
+    );
+}
+
+#[test]
+fn test_interrupted_runs_recover() {
+    let store = tempfile::tempdir().unwrap();
+    let root = store.path().to_str().unwrap();
+    let test_file = "/tmp/test_rollback_interrupted.txt";
+    fs::write(test_file, "original content").unwrap();
+
+    // A completed run, and a run killed during its second phase
+    let completed = RollbackManager::with_store(format!("{}/20240101_000000", root));
+    let setup = completed.create_snapshot("Initial setup").unwrap();
+    completed.commit_snapshot(setup).unwrap();
+    let killed = RollbackManager::with_store(format!("{}/20240102_000000", root));
+    let setup = killed.create_snapshot("Initial setup").unwrap();
+    killed.commit_snapshot(setup).unwrap();
+    let security = killed.create_snapshot("Security measures").unwrap();
+    killed.add_file_change(security, test_file).unwrap();
+    fs::write(test_file, "security content").unwrap();
+    drop(killed);
+
+    let runs = interrupted_runs(root).unwrap();
+    assert_eq!(runs.len(), 1);
+    assert_eq!(runs[0].dir, format!("{}/20240102_000000", root));
+    let pending: Vec<&str> = runs[0]
+        .pending
+        .iter()
+        .map(|snapshot| snapshot.label.as_str())
+        .collect();
+    assert_eq!(pending, vec!["Security measures"]);
+
+    // Ignoring leaves the run to decide on later
+    recover(&runs[0], Recovery::Ignore).unwrap();
+    assert_eq!(interrupted_runs(root).unwrap(), runs);
+
+    recover(&runs[0], Recovery::RollBack).unwrap();
+    assert_eq!(fs::read_to_string(test_file).unwrap(), "original content");
+    assert!(interrupted_runs(root).unwrap().is_empty());
+    let statuses: Vec<SnapshotStatus> = RollbackManager::load(runs[0].dir.as_str())
+        .unwrap()
+        .list_snapshots()
+        .unwrap()
+        .iter()
+        .map(|info| info.status)
+        .collect();
+    assert_eq!(
+        statuses,
+        vec![SnapshotStatus::Committed, SnapshotStatus::RolledBack]
+    );
+}
+
+#[test]
+fn test_recover_commit() {
+    let store = tempfile::tempdir().unwrap();
+    let root = store.path().to_str().unwrap();
+    let killed = RollbackManager::with_store(format!("{}/20240102_000000", root));
+    killed.create_snapshot("Initial setup").unwrap();
+
+    let runs = interrupted_runs(root).unwrap();
+    assert_eq!(runs.len(), 1);
+    recover(&runs[0], Recovery::Commit).unwrap();
+    assert!(interrupted_runs(root).unwrap().is_empty());
+}
@@ -0,0 +1,236 @@
+use crate::common::RecordingRunner;
+use server_forge::config::{Config, Secrets, VaultConfig};
+use server_forge::deployment;
+use server_forge::plan::{Operation, Plan};
+use server_forge::runner::with_runner;
+use server_forge::secrets::{
+    curl_config, secret_store, FileSecretStore, SecretStore, VaultSecretStore, MYSQL_ROOT_PASSWORD,
+};
+use std::error::Error;
+use std::sync::Arc;
+
+/// A host answering Vault requests with an empty list of errors, as Vault answers for
+/// missing secrets.
+fn vault_host() -> RecordingRunner {
+    RecordingRunner::new("fake").respond("curl", r#"{"errors":[]}"#)
+}
+
+/// Returns the command line reading or writing a Vault URL with `curl`.
+fn curl(url: &str) -> String {
+    format!("curl -sS --config - {}", url)
+}
+
+fn vault() -> VaultConfig {
+    VaultConfig {
+        address: String::from("https://vault.internal:8200/"),
+        mount: String::from("kv"),
+        path: String::from("servers/web1"),
+        role_id: None,
+    }
+}
+
+const SECRET_URL: &str = "https://vault.internal:8200/v1/kv/data/servers/web1/mysql_root_password";
+
+#[test]
+fn test_file_secret_store() {
+    assert_eq!(
+        FileSecretStore::path(MYSQL_ROOT_PASSWORD),
+        deployment::MYSQL_PASSWORD_FILE
+    );
+
+    let host = Arc::new(RecordingRunner::new("fake"));
+    with_runner(host.clone(), || {
+        assert_eq!(FileSecretStore.get(MYSQL_ROOT_PASSWORD)?, None);
+        FileSecretStore.put(MYSQL_ROOT_PASSWORD, "s3cret")?;
+        assert_eq!(
+            FileSecretStore.get(MYSQL_ROOT_PASSWORD)?.as_deref(),
+            Some("s3cret")
+        );
+        Ok::<_, Box<dyn Error>>(())
+    })
+    .unwrap();
+    // The secret is only passed on standard input
+    assert!(host.log().iter().all(|line| !line.contains("s3cret")));
+
+    let plan = Plan::build(|plan| {
+        FileSecretStore.plan_put(plan, MYSQL_ROOT_PASSWORD, "s3cret");
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(
+        plan.operations(),
+        Plan::new()
+            .write_private_file(deployment::MYSQL_PASSWORD_FILE, "s3cret")
+            .operations()
+    );
+}
+
+#[test]
+fn test_curl_config() {
+    assert_eq!(
+        curl_config(&[
+            ("header", "X-Vault-Token: t0ken"),
+            ("data", r#"{"value":"a\b"}"#),
+        ]),
+        "header = \"X-Vault-Token: t0ken\"\ndata = \"{\\\"value\\\":\\\"a\\\\b\\\"}\"\n"
+    );
+}
+
+#[test]
+fn test_vault_secret_store_get() {
+    let store = VaultSecretStore::new(&vault(), "t0ken");
+    assert_eq!(store.url(MYSQL_ROOT_PASSWORD), SECRET_URL);
+
+    let host = Arc::new(vault_host().respond(
+        &curl(SECRET_URL),
+        r#"{"data":{"data":{"value":"s3cret"},"metadata":{"version":1}}}"#,
+    ));
+    with_runner(host.clone(), || {
+        assert_eq!(store.get(MYSQL_ROOT_PASSWORD)?.as_deref(), Some("s3cret"));
+        assert_eq!(store.get("grafana_admin_password")?, None);
+        Ok::<_, Box<dyn Error>>(())
+    })
+    .unwrap();
+    // The token is passed on standard input
+    assert_eq!(
+        host.inputs()[0],
+        (
+            curl(SECRET_URL),
+            String::from("header = \"X-Vault-Token: t0ken\"\n")
+        )
+    );
+
+    // Errors other than a missing secret, such as a denied token, are reported
+    let host =
+        Arc::new(vault_host().respond(&curl(SECRET_URL), r#"{"errors":["permission denied"]}"#));
+    let result = with_runner(host, || store.get(MYSQL_ROOT_PASSWORD));
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("permission denied"));
+}
+
+#[test]
+fn test_vault_secret_store_plan_put() {
+    let store = VaultSecretStore::new(&vault(), "t0ken");
+    let plan = Plan::build(|plan| {
+        store.plan_put(plan, MYSQL_ROOT_PASSWORD, "s3'cret");
+        Ok(())
+    })
+    .unwrap();
+    let [Operation::RunCommandWithInput {
+        command,
+        args,
+        input,
+    }] = plan.operations()
+    else {
+        panic!("expected a single command");
+    };
+    assert_eq!(command, "curl");
+    assert_eq!(args.last().unwrap(), SECRET_URL);
+    // The token and the secret are only passed on standard input
+    assert!(args
+        .iter()
+        .all(|arg| !arg.contains("t0ken") && !arg.contains("s3'cret")));
+    assert_eq!(
+        input,
+        &curl_config(&[
+            ("header", "X-Vault-Token: t0ken"),
+            ("data", r#"{"data":{"value":"s3'cret"}}"#),
+        ])
+    );
+}
+
+#[test]
+fn test_vault_login() {
+    let approle = VaultConfig {
+        role_id: Some(String::from("role")),
+        ..vault()
+    };
+    let secrets = Secrets {
+        vault_secret_id: Some(String::from("secret")),
+        ..Default::default()
+    };
+    let host = Arc::new(
+        vault_host()
+            .respond(
+                "curl -sS --config - -X POST https://vault.internal:8200/v1/auth/approle/login",
+                r#"{"auth":{"client_token":"from-approle"}}"#,
+            )
+            .respond(&curl(SECRET_URL), r#"{"data":{"data":{"value":"s3cret"}}}"#),
+    );
+    with_runner(host.clone(), || {
+        let store = VaultSecretStore::login(&approle, &secrets)?;
+        store.get(MYSQL_ROOT_PASSWORD)
+    })
+    .unwrap();
+    let inputs = host.inputs();
+    assert_eq!(
+        inputs[0].1,
+        curl_config(&[("data", r#"{"role_id":"role","secret_id":"secret"}"#)])
+    );
+    assert_eq!(
+        inputs[1].1,
+        curl_config(&[("header", "X-Vault-Token: from-approle")])
+    );
+    assert!(host.log().iter().all(|line| !line.contains("secret\"")));
+
+    // A configured token is used without logging in
+    let host = Arc::new(vault_host());
+    with_runner(host.clone(), || {
+        VaultSecretStore::login(
+            &approle,
+            &Secrets {
+                vault_token: Some(String::from("t0ken")),
+                ..Default::default()
+            },
+        )
+    })
+    .unwrap();
+    assert!(host.log().is_empty());
+}
+
+#[test]
+fn test_secret_store_follows_config() {
+    let host = Arc::new(vault_host().with_file("/usr/bin/apt", ""));
+    let config = Config {
+        vault: Some(vault()),
+        secrets: Secrets {
+            vault_token: Some(String::from("t0ken")),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let plan = with_runner(host.clone(), || {
+        let store = secret_store(&config)?;
+        assert_eq!(store.get(MYSQL_ROOT_PASSWORD)?, None);
+        Plan::build(|plan| deployment::plan_app(plan, "mysql", &config))
+    })
+    .unwrap();
+
+    // The generated root password is sent to Vault instead of a file
+    assert!(!plan.operations().iter().any(|operation| matches!(
+        operation,
+        Operation::WriteFile { path, .. } if path == deployment::MYSQL_PASSWORD_FILE
+    )));
+    assert!(plan.operations().iter().any(|operation| matches!(
+        operation,
+        Operation::RunCommandWithInput { command, args, .. } if command == "curl" && args.last().unwrap() == SECRET_URL
+    )));
+
+    // An existing secret keeps MySQL from being secured again
+    let host = Arc::new(
+        vault_host()
+            .with_file("/usr/bin/apt", "")
+            .respond(&curl(SECRET_URL), r#"{"data":{"data":{"value":"s3cret"}}}"#),
+    );
+    let plan = with_runner(host, || {
+        Plan::build(|plan| deployment::plan_app(plan, "mysql", &config))
+    })
+    .unwrap();
+    assert!(!plan.operations().iter().any(|operation| matches!(
+        operation,
+        Operation::RunCommand { command, .. } | Operation::RunCommandWithInput { command, .. }
+            if command == "mysql"
+    )));
+}
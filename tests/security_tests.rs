@@ -1,11 +1,15 @@
+use crate::common::RecordingRunner;
 use server_forge::config::Config;
+use server_forge::plan::{Operation, Plan};
 use server_forge::rollback::RollbackManager;
+use server_forge::runner::with_runner;
 use server_forge::security;
 use std::fs;
+use std::sync::Arc;
 
 #[test]
 fn test_configure_fail2ban() {
-    assert!(security::configure_fail2ban().is_ok());
+    assert!(security::configure_fail2ban(&Config::default()).is_ok());
 
     // Verify fail2ban configuration
     let fail2ban_config = fs::read_to_string("/etc/fail2ban/jail.local").unwrap();
@@ -20,6 +24,24 @@ fn test_configure_fail2ban() {
     assert!(status.success());
 }
 
+#[test]
+fn test_fail2ban_jail_uses_ssh_port() {
+    let config = Config {
+        ssh_port: 2200,
+        ..Default::default()
+    };
+    let jail = security::fail2ban_jail(&config);
+    assert!(jail.contains("[sshd]"));
+    assert!(jail.contains("\nport = 2200\n"));
+
+    let mut plan = Plan::new();
+    security::plan_fail2ban(&mut plan, &config);
+    assert!(plan.operations().contains(&Operation::WriteFile {
+        path: String::from("/etc/fail2ban/jail.local"),
+        contents: jail,
+    }));
+}
+
 #[test]
 fn test_setup_advanced_security() {
     let config = Config {
@@ -58,7 +80,8 @@ fn test_setup_rootkit_detection() {
 
 #[test]
 fn test_setup_security_scans() {
-    assert!(security::setup_security_scans().is_ok());
+    let config = Config::default();
+    assert!(security::setup_security_scans(&config).is_ok());
 
     // Verify security scan script
     assert!(fs::metadata("/usr/local/bin/security_scan.sh").is_ok());
@@ -68,6 +91,28 @@ fn test_setup_security_scans() {
     assert!(cron_config.contains("security_scan.sh"));
 }
 
+#[test]
+fn test_security_scan_cron_schedule() {
+    assert_eq!(
+        security::security_scan_cron_schedule("daily").unwrap(),
+        "0 2 * * *"
+    );
+    assert_eq!(
+        security::security_scan_cron_schedule("weekly").unwrap(),
+        "0 2 * * 0"
+    );
+    assert_eq!(
+        security::security_scan_cron_schedule("monthly").unwrap(),
+        "0 2 1 * *"
+    );
+    assert_eq!(
+        security::security_scan_cron_schedule("30 3 * * 1-5").unwrap(),
+        "30 3 * * 1-5"
+    );
+    assert!(security::security_scan_cron_schedule("fortnightly").is_err());
+    assert!(security::security_scan_cron_schedule("30 3 * *").is_err());
+}
+
 #[test]
 fn test_implement_security_measures() {
     let config = Config {
@@ -79,3 +124,292 @@ fn test_implement_security_measures() {
 
     assert!(security::implement_security_measures(&config, &rollback_manager).is_ok());
 }
+
+#[test]
+fn test_plan_aide() {
+    let config = Config {
+        linux_distro: String::from("fedora"),
+        admin_email: Some(String::from("ops@example.com")),
+        ..Default::default()
+    };
+
+    let plan = Plan::build(|plan| security::plan_aide(plan, &config)).unwrap();
+    let operations = plan.operations();
+
+    assert_eq!(
+        &operations[..3],
+        Plan::new()
+            .install(&["aide"])
+            .run("aide", &["--init"])
+            .run(
+                "mv",
+                &[
+                    "-f",
+                    "/var/lib/aide/aide.db.new.gz",
+                    "/var/lib/aide/aide.db.gz"
+                ]
+            )
+            .operations()
+    );
+    assert_eq!(
+        operations[3],
+        Operation::WriteFile {
+            path: String::from("/usr/local/bin/aide_check.sh"),
+            contents: security::aide_check_script(&config, "/etc/aide.conf"),
+        }
+    );
+    assert!(operations.iter().any(|operation| matches!(
+        operation,
+        Operation::WriteFile { path, contents }
+            if path == "/etc/cron.d/aide_check"
+                && contents.contains("/usr/local/bin/aide_check.sh > /var/log/aide_check.log")
+    )));
+
+    let config = Config {
+        linux_distro: String::from("arch"),
+        ..Default::default()
+    };
+    assert!(Plan::build(|plan| security::plan_aide(plan, &config)).is_err());
+}
+
+#[test]
+fn test_aide_check_script() {
+    let config = Config::default();
+    let script = security::aide_check_script(&config, "/etc/aide/aide.conf");
+    assert!(script.contains("aide --check --config='/etc/aide/aide.conf'"));
+    assert!(!script.contains("mail"));
+    assert!(script.ends_with("exit $status\n"));
+
+    let config = Config {
+        admin_email: Some(String::from("ops@example.com")),
+        ..Default::default()
+    };
+    let script = security::aide_check_script(&config, "/etc/aide/aide.conf");
+    assert!(script.contains("mail -s \"$subject\" 'ops@example.com'"));
+}
+
+#[test]
+fn test_plan_clamav() {
+    let config = Config {
+        linux_distro: String::from("centos"),
+        ..Default::default()
+    };
+    let plan = Plan::build(|plan| security::plan_clamav(plan, &config)).unwrap();
+    let operations = plan.operations();
+    assert_eq!(
+        operations[0],
+        Operation::InstallPackages {
+            packages: vec![
+                String::from("clamav"),
+                String::from("clamav-update"),
+                String::from("clamd")
+            ],
+        }
+    );
+    assert!(matches!(
+        &operations[1],
+        Operation::WriteFile { path, contents }
+            if path == "/etc/clamd.d/scan.conf"
+                && contents.contains("LocalSocket /run/clamd.scan/clamd.sock")
+    ));
+    assert!(operations.contains(&Operation::EnableService {
+        name: String::from("clamd@scan")
+    }));
+
+    let config = Config {
+        linux_distro: String::from("ubuntu"),
+        ..Default::default()
+    };
+    let plan = Plan::build(|plan| security::plan_clamav(plan, &config)).unwrap();
+    let operations = plan.operations();
+    let stop = Operation::RunCommand {
+        command: String::from("systemctl"),
+        args: vec![String::from("stop"), String::from("clamav-freshclam")],
+    };
+    let update = Operation::RunCommand {
+        command: String::from("freshclam"),
+        args: Vec::new(),
+    };
+    let position = |operation: &Operation| operations.iter().position(|o| o == operation);
+    assert!(position(&stop) < position(&update));
+    assert!(operations.contains(&Operation::StartService {
+        name: String::from("clamav-daemon")
+    }));
+    assert!(operations.contains(&Operation::WriteFile {
+        path: String::from("/usr/local/bin/clamav_scan.sh"),
+        contents: security::clamav_scan_script(&config),
+    }));
+}
+
+#[test]
+fn test_clamav_scan_script() {
+    let script = security::clamav_scan_script(&Config::default());
+    assert!(script.contains("clamscan --recursive --infected"));
+    assert!(!script.contains("mail"));
+
+    let config = Config {
+        admin_email: Some(String::from("ops@example.com")),
+        ..Default::default()
+    };
+    let script = security::clamav_scan_script(&config);
+    assert!(script.contains("ClamAV found infected files"));
+    assert!(script.contains("printf '%s\\n' \"$output\" | mail -s \"$subject\" 'ops@example.com'"));
+    assert!(script.ends_with("fi\nexit $status\n"));
+}
+
+#[test]
+fn test_plan_auditd() {
+    let config = Config {
+        linux_distro: String::from("ubuntu"),
+        ..Default::default()
+    };
+    let plan = Plan::build(|plan| security::plan_auditd(plan, &config)).unwrap();
+    assert_eq!(
+        plan.operations(),
+        Plan::new()
+            .install(&["auditd"])
+            .write_file(
+                "/etc/audit/rules.d/server_forge.rules",
+                security::audit_rules()
+            )
+            .enable_service("auditd")
+            .run("service", &["auditd", "restart"])
+            .operations()
+    );
+
+    let rules = security::audit_rules();
+    for watch in [
+        "-w /etc/passwd -p wa",
+        "-w /etc/sudoers -p wa",
+        "-k privileged",
+    ] {
+        assert!(rules.contains(watch), "missing {}", watch);
+    }
+    assert!(rules.trim_end().ends_with("-e 2"));
+
+    let config = Config {
+        linux_distro: String::from("arch"),
+        ..Default::default()
+    };
+    assert!(Plan::build(|plan| security::plan_auditd(plan, &config)).is_err());
+}
+
+#[test]
+fn test_selinux_booleans() {
+    let mut config = Config {
+        deployed_apps: vec![String::from("nginx"), String::from("nodejs")],
+        ..Default::default()
+    };
+    assert!(security::selinux_booleans(&config).is_empty());
+
+    config.app_domain = Some(String::from("app.example.com"));
+    config.deployed_apps.push(String::from("haproxy"));
+    config.enable_clamav = true;
+    assert_eq!(
+        security::selinux_booleans(&config),
+        vec![
+            "httpd_can_network_connect",
+            "haproxy_connect_any",
+            "antivirus_can_scan_system"
+        ]
+    );
+}
+
+#[test]
+fn test_plan_advanced_security_selinux() {
+    let config = Config {
+        linux_distro: String::from("fedora"),
+        security_level: String::from("advanced"),
+        deployed_apps: vec![String::from("haproxy")],
+        ..Default::default()
+    };
+    let plan_on = |host: RecordingRunner| {
+        with_runner(Arc::new(host), || {
+            Plan::build(|plan| security::plan_advanced_security(plan, &config))
+        })
+        .unwrap()
+    };
+
+    let plan = plan_on(
+        RecordingRunner::new("fake")
+            .with_file("/sys/fs/selinux/enforce", "0")
+            .with_file("/etc/selinux/config", "SELINUX=permissive\n"),
+    );
+    assert!(plan.operations().contains(&Operation::WriteFile {
+        path: String::from("/etc/selinux/config"),
+        contents: String::from("SELINUX=enforcing\nSELINUXTYPE=targeted\n"),
+    }));
+    assert_eq!(
+        plan.operations().last(),
+        Some(&Operation::RunCommand {
+            command: String::from("setsebool"),
+            args: vec![
+                String::from("-P"),
+                String::from("haproxy_connect_any"),
+                String::from("on")
+            ],
+        })
+    );
+
+    let plan = plan_on(
+        RecordingRunner::new("fake").with_file("/etc/selinux/config", "SELINUX=disabled\n"),
+    );
+    assert_eq!(
+        &plan.operations()[1..],
+        Plan::new()
+            .write_file(
+                "/etc/selinux/config",
+                "SELINUX=permissive\nSELINUXTYPE=targeted\n"
+            )
+            .write_file("/.autorelabel", "")
+            .operations()
+    );
+}
+
+#[test]
+fn test_plan_advanced_security_apparmor() {
+    let config = Config {
+        linux_distro: String::from("ubuntu"),
+        security_level: String::from("advanced"),
+        deployed_apps: vec![
+            String::from("mysql"),
+            String::from("nodejs"),
+            String::from("nginx"),
+            String::from("apache"),
+        ],
+        ..Default::default()
+    };
+    let host = RecordingRunner::new("fake")
+        .with_file("/etc/apparmor.d/usr.sbin.nginx", "")
+        .with_file("/etc/apparmor.d/usr.sbin.mysqld", "");
+
+    let plan = with_runner(Arc::new(host), || {
+        Plan::build(|plan| security::plan_advanced_security(plan, &config))
+    })
+    .unwrap();
+    assert_eq!(
+        plan.operations(),
+        Plan::new()
+            .install(&["apparmor", "apparmor-utils"])
+            .run(
+                "aa-enforce",
+                &[
+                    "/etc/apparmor.d/usr.sbin.mysqld",
+                    "/etc/apparmor.d/usr.sbin.nginx"
+                ]
+            )
+            .operations()
+    );
+
+    // Without profiles, none is enforced
+    let plan = with_runner(Arc::new(RecordingRunner::new("fake")), || {
+        Plan::build(|plan| security::plan_advanced_security(plan, &config))
+    })
+    .unwrap();
+    assert_eq!(
+        plan.operations(),
+        Plan::new()
+            .install(&["apparmor", "apparmor-utils"])
+            .operations()
+    );
+}
@@ -1,7 +1,13 @@
+use crate::common::RecordingRunner;
 use server_forge::config::Config;
+use server_forge::distro::PackageManager;
+use server_forge::firewall;
+use server_forge::plan::{Operation, Plan};
 use server_forge::rollback::RollbackManager;
+use server_forge::runner::with_runner;
 use server_forge::setup;
 use std::fs;
+use std::sync::Arc;
 
 #[test]
 fn test_update_system() {
@@ -42,6 +48,41 @@ fn test_install_essential_packages() {
     }
 }
 
+#[test]
+fn test_firewall_rules() {
+    let mut config = Config {
+        monitoring: true,
+        grafana_port: 3300,
+        custom_firewall_rules: vec![String::from("8080/tcp")],
+        ..Default::default()
+    };
+    assert_eq!(setup::firewall_rules(&config), vec!["8080/tcp"]);
+
+    config.expose_monitoring = true;
+    assert_eq!(
+        setup::firewall_rules(&config),
+        vec!["8080/tcp", "3300/tcp", "9090/tcp"]
+    );
+
+    let mut config = Config {
+        deployed_apps: vec![String::from("haproxy")],
+        ..Default::default()
+    };
+    assert_eq!(setup::firewall_rules(&config), vec!["80/tcp"]);
+
+    config.load_balancer_certificate = Some(String::from("/etc/haproxy/site.pem"));
+    assert_eq!(setup::firewall_rules(&config), vec!["80/tcp", "443/tcp"]);
+
+    let mut config = Config {
+        deployed_apps: vec![String::from("rabbitmq")],
+        ..Default::default()
+    };
+    assert!(setup::firewall_rules(&config).is_empty());
+
+    config.expose_rabbitmq_management = true;
+    assert_eq!(setup::firewall_rules(&config), vec!["15672/tcp"]);
+}
+
 #[test]
 fn test_setup_firewall() {
     let config = Config {
@@ -65,7 +106,20 @@ fn test_setup_firewall() {
 
 #[test]
 fn test_setup_ssh() {
-    assert!(setup::setup_ssh().is_ok());
+    let config = Config {
+        linux_distro: String::from("ubuntu"),
+        admin_user: Some(String::from("forgeadmin")),
+        admin_ssh_key: Some(String::from(
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIGtest admin",
+        )),
+        ..Default::default()
+    };
+    assert!(setup::create_admin_user(
+        config.admin_user.as_deref().unwrap(),
+        config.admin_ssh_key.as_deref().unwrap()
+    )
+    .is_ok());
+    assert!(setup::setup_ssh(&config).is_ok());
 
     // Verify SSH configuration
     let ssh_config = fs::read_to_string("/etc/ssh/sshd_config").unwrap();
@@ -120,3 +174,555 @@ fn test_initial_setup() {
         .unwrap()
         .contains("PermitRootLogin no"));
 }
+
+#[test]
+fn test_configure_swap() {
+    let host = Arc::new(
+        RecordingRunner::new("fake").with_file("/etc/fstab", "/dev/sda1 / ext4 defaults 0 1"),
+    );
+
+    with_runner(host.clone(), || setup::configure_swap(2048)).unwrap();
+
+    assert_eq!(
+        host.commands(),
+        vec![
+            "fallocate -l 2048M /swapfile",
+            "chmod 600 /swapfile",
+            "mkswap /swapfile",
+            "swapon /swapfile",
+        ]
+    );
+    assert_eq!(
+        host.file("/etc/fstab").unwrap(),
+        "/dev/sda1 / ext4 defaults 0 1\n/swapfile none swap sw 0 0\n"
+    );
+}
+
+#[test]
+fn test_configure_swap_is_idempotent() {
+    let fstab = "/swapfile none swap sw 0 0\n";
+    let host = Arc::new(
+        RecordingRunner::new("fake")
+            .with_file("/etc/fstab", fstab)
+            .with_file("/swapfile", ""),
+    );
+
+    with_runner(host.clone(), || setup::configure_swap(1024)).unwrap();
+
+    assert!(host.commands().is_empty());
+    assert_eq!(host.file("/etc/fstab").unwrap(), fstab);
+
+    let mut plan = Plan::new();
+    assert!(setup::plan_swap(&mut plan, 0).is_err());
+}
+
+#[test]
+fn test_configure_time() {
+    let host = Arc::new(
+        RecordingRunner::new("fake")
+            .with_file("/usr/bin/apt", "")
+            .with_file("/usr/share/zoneinfo/Europe/Berlin", ""),
+    );
+
+    let plan = with_runner(host.clone(), || {
+        Plan::build(|plan| setup::plan_time(plan, "Europe/Berlin"))
+    })
+    .unwrap();
+    assert_eq!(
+        plan.operations(),
+        Plan::new()
+            .run("timedatectl", &["set-timezone", "Europe/Berlin"])
+            .install(&["chrony"])
+            .enable_service("chrony")
+            .start_service("chrony")
+            .operations()
+    );
+
+    for timezone in ["Mars/Olympus", "../../etc/passwd", "", "/etc/localtime"] {
+        let result = with_runner(host.clone(), || setup::configure_time(timezone));
+        assert!(result.is_err(), "{:?} should be rejected", timezone);
+    }
+    assert!(host.commands().is_empty());
+}
+
+#[test]
+fn test_plan_essential_packages_defaults() {
+    let config = Config {
+        linux_distro: String::from("centos"),
+        ..Default::default()
+    };
+    for (package_manager, path) in [
+        (PackageManager::Apt, "/usr/bin/apt"),
+        (PackageManager::Yum, "/usr/bin/yum"),
+        (PackageManager::Dnf, "/usr/bin/dnf"),
+    ] {
+        let host = Arc::new(RecordingRunner::new("fake").with_file(path, ""));
+        let plan = with_runner(host, || {
+            Plan::build(|plan| setup::plan_essential_packages(plan, &config))
+        })
+        .unwrap();
+        let defaults = setup::default_essential_packages(&package_manager);
+        assert_eq!(
+            plan.operations(),
+            Plan::new().install(defaults).operations()
+        );
+    }
+
+    assert!(setup::default_essential_packages(&PackageManager::Apt).contains(&"ufw"));
+    for package_manager in [PackageManager::Yum, PackageManager::Dnf] {
+        let defaults = setup::default_essential_packages(&package_manager);
+        assert!(defaults.contains(&"firewalld") && defaults.contains(&"fail2ban"));
+        assert!(!defaults.iter().any(|package| package.starts_with("apt")));
+    }
+}
+
+#[test]
+fn test_plan_essential_packages_override() {
+    let config = Config {
+        linux_distro: String::from("ubuntu"),
+        essential_packages: Some(vec![String::from("curl"), String::from("htop")]),
+        ..Default::default()
+    };
+    let host = Arc::new(RecordingRunner::new("fake"));
+
+    let plan = with_runner(host, || {
+        Plan::build(|plan| setup::plan_essential_packages(plan, &config))
+    })
+    .unwrap();
+    assert_eq!(
+        plan.operations(),
+        Plan::new().install(&["curl", "htop"]).operations()
+    );
+}
+
+#[test]
+fn test_configure_hostname() {
+    let host = Arc::new(
+        RecordingRunner::new("fake")
+            .with_file("/etc/hostname", "ip-10-0-0-12\n")
+            .with_file(
+                "/etc/hosts",
+                "127.0.0.1\tlocalhost\n127.0.1.1\tip-10-0-0-12\n::1\tlocalhost ip6-localhost\n",
+            ),
+    );
+
+    with_runner(host.clone(), || {
+        setup::configure_hostname("web1.example.com")
+    })
+    .unwrap();
+
+    assert_eq!(host.file("/etc/hostname").unwrap(), "web1.example.com\n");
+    assert_eq!(
+        host.file("/etc/hosts").unwrap(),
+        "127.0.0.1\tlocalhost\n127.0.1.1\tweb1.example.com web1\n::1\tlocalhost ip6-localhost\n"
+    );
+    assert_eq!(
+        host.commands(),
+        vec!["hostnamectl set-hostname web1.example.com"]
+    );
+}
+
+#[test]
+fn test_configure_hostname_adds_hosts_entry() {
+    let host =
+        Arc::new(RecordingRunner::new("fake").with_file("/etc/hosts", "127.0.0.1 localhost\n"));
+
+    with_runner(host.clone(), || setup::configure_hostname("db-1")).unwrap();
+
+    assert_eq!(
+        host.file("/etc/hosts").unwrap(),
+        "127.0.0.1 localhost\n127.0.1.1\tdb-1\n"
+    );
+}
+
+#[test]
+fn test_configure_hostname_rejects_invalid_names() {
+    let long_label = "a".repeat(64);
+    for hostname in [
+        "",
+        "-web",
+        "web-",
+        "web_1",
+        "web..example",
+        &long_label,
+        "web1.local.",
+    ] {
+        let mut plan = Plan::new();
+        assert!(
+            setup::plan_hostname(&mut plan, hostname).is_err(),
+            "{:?} should be rejected",
+            hostname
+        );
+    }
+}
+
+#[test]
+fn test_create_admin_user() {
+    let key = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIGtest admin@laptop";
+    let host = Arc::new(
+        RecordingRunner::new("fake")
+            .with_file("/usr/bin/apt", "")
+            .with_file("/etc/passwd", "root:x:0:0:root:/root:/bin/bash\n"),
+    );
+
+    with_runner(host.clone(), || setup::create_admin_user("deploy", key)).unwrap();
+
+    assert_eq!(
+        host.file("/home/deploy/.ssh/authorized_keys").unwrap(),
+        format!("{}\n", key)
+    );
+    assert_eq!(
+        host.file("/etc/sudoers.d/90-serverforge-deploy").unwrap(),
+        "deploy ALL=(ALL) NOPASSWD:ALL\n"
+    );
+    let commands = host.commands();
+    assert!(commands.contains(&String::from("useradd -m -s /bin/bash -G sudo deploy")));
+    assert!(commands.contains(&String::from("chmod 600 /home/deploy/.ssh/authorized_keys")));
+    assert!(commands.contains(&String::from(
+        "visudo -cf /etc/sudoers.d/90-serverforge-deploy"
+    )));
+}
+
+#[test]
+fn test_create_admin_user_existing_user() {
+    let key = "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQtest";
+    let existing_keys = format!("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIGold old\n{}\n", key);
+    let host = Arc::new(
+        RecordingRunner::new("fake")
+            .with_file("/usr/bin/dnf", "")
+            .with_file("/etc/passwd", "ops:x:1000:1000::/srv/ops:/bin/bash\n")
+            .with_file("/srv/ops/.ssh/authorized_keys", &existing_keys),
+    );
+
+    let plan = with_runner(host.clone(), || {
+        Plan::build(|plan| setup::plan_admin_user(plan, "ops", key))
+    })
+    .unwrap();
+
+    assert!(plan.operations().contains(&Operation::RunCommand {
+        command: String::from("usermod"),
+        args: vec![
+            String::from("-aG"),
+            String::from("wheel"),
+            String::from("ops")
+        ],
+    }));
+    assert!(plan.operations().contains(&Operation::WriteFile {
+        path: String::from("/srv/ops/.ssh/authorized_keys"),
+        contents: existing_keys.clone(),
+    }));
+
+    for (username, key) in [
+        ("Root", key),
+        ("-x", key),
+        ("ops", "not a key"),
+        ("ops", ""),
+    ] {
+        let result = with_runner(host.clone(), || {
+            Plan::build(|plan| setup::plan_admin_user(plan, username, key))
+        });
+        assert!(result.is_err(), "{:?} should be rejected", (username, key));
+    }
+}
+
+#[test]
+fn test_plan_ssh_requires_key_based_admin() {
+    let sshd_config = "PermitRootLogin yes\n#PasswordAuthentication yes\n";
+    let host = || {
+        RecordingRunner::new("fake")
+            .with_file("/etc/ssh/sshd_config", sshd_config)
+            .with_file("/etc/passwd", "ops:x:1000:1000::/home/ops:/bin/bash\n")
+            .with_file("/etc/group", "sudo:x:27:ops\n")
+    };
+    let planned_sshd_config = |host: RecordingRunner, config: &Config| {
+        let plan = with_runner(Arc::new(host), || {
+            Plan::build(|plan| setup::plan_ssh(plan, config))
+        })
+        .unwrap();
+        match &plan.operations()[0] {
+            Operation::WriteFile { contents, .. } => contents.clone(),
+            operation => panic!("unexpected operation {:?}", operation),
+        }
+    };
+
+    let config = Config::default();
+    assert!(planned_sshd_config(host(), &config).contains("#PasswordAuthentication yes"));
+
+    let with_key = host().with_file("/home/ops/.ssh/authorized_keys", "ssh-ed25519 AAAA ops\n");
+    assert!(planned_sshd_config(with_key, &config).contains("\nPasswordAuthentication no"));
+
+    let config = Config {
+        admin_user: Some(String::from("deploy")),
+        admin_ssh_key: Some(String::from("ssh-ed25519 AAAA deploy")),
+        ..Default::default()
+    };
+    let contents = planned_sshd_config(host(), &config);
+    assert!(contents.contains("PermitRootLogin no"));
+    assert!(contents.contains("\nPasswordAuthentication no"));
+}
+
+#[test]
+fn test_plan_ssh_preflight_checks() {
+    let host = Arc::new(
+        RecordingRunner::new("fake")
+            .with_file("/etc/ssh/sshd_config", "PermitRootLogin yes\n#Port 22\n")
+            .with_file("/etc/passwd", "ops:x:1000:1000::/home/ops:/bin/bash\n"),
+    );
+    let config = Config {
+        linux_distro: String::from("ubuntu"),
+        admin_user: Some(String::from("ops")),
+        admin_ssh_key: Some(String::from("ssh-ed25519 AAAA ops")),
+        ..Default::default()
+    };
+
+    let plan = with_runner(host.clone(), || {
+        Plan::build(|plan| setup::plan_ssh(plan, &config))
+    })
+    .unwrap();
+    let operations = plan.operations();
+
+    let commands: Vec<String> = operations
+        .iter()
+        .filter_map(|operation| match operation {
+            Operation::RunCommand { command, args } => Some(format!("{} {}", command, args[0])),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(commands, vec!["sh -c", "sh -c", "sshd -t"]);
+    match &operations[1] {
+        Operation::RunCommand { args, .. } => {
+            assert!(args[1].contains("'/home/ops/.ssh/authorized_keys'"))
+        }
+        operation => panic!("unexpected operation {:?}", operation),
+    }
+    match &operations[2] {
+        Operation::RunCommand { args, .. } => assert!(args[1].contains("2222(/tcp)? +ALLOW")),
+        operation => panic!("unexpected operation {:?}", operation),
+    }
+    assert_eq!(
+        operations.last(),
+        Some(&Operation::RestartService {
+            name: String::from("sshd")
+        })
+    );
+}
+
+#[test]
+fn test_set_ssh_port() {
+    assert_eq!(
+        setup::set_ssh_port("#Port 22\nPermitRootLogin no\n", 2222),
+        "Port 2222\nPermitRootLogin no\n"
+    );
+    // sshd listens on every uncommented Port, so only one is kept
+    assert_eq!(
+        setup::set_ssh_port(
+            "Port 22\n# Port numbers are below 65536\nPort 2022\n#Port 23\n",
+            2200
+        ),
+        "Port 2200\n# Port numbers are below 65536\n#Port 23\n"
+    );
+    assert_eq!(
+        setup::set_ssh_port("PermitRootLogin no", 2200),
+        "PermitRootLogin no\nPort 2200\n"
+    );
+}
+
+#[test]
+fn test_ssh_port_is_shared_by_ssh_and_firewall() {
+    let host = Arc::new(
+        RecordingRunner::new("fake")
+            .with_file("/etc/ssh/sshd_config", "Port 22\n")
+            .with_file("/etc/passwd", ""),
+    );
+    let config = Config {
+        linux_distro: String::from("ubuntu"),
+        ssh_port: 2200,
+        ..Default::default()
+    };
+
+    let plan = with_runner(host, || {
+        Plan::build(|plan| {
+            setup::plan_firewall(plan, &config)?;
+            setup::plan_ssh(plan, &config)
+        })
+    })
+    .unwrap();
+    let operations = plan.operations();
+
+    assert!(operations.contains(&Operation::RunCommand {
+        command: String::from("ufw"),
+        args: vec![String::from("allow"), String::from("2200/tcp")],
+    }));
+    assert!(operations.contains(&Operation::WriteFile {
+        path: String::from("/etc/ssh/sshd_config"),
+        contents: String::from("Port 2200\n"),
+    }));
+    assert!(operations.iter().any(|operation| matches!(
+        operation,
+        Operation::RunCommand { command, args } if command == "sh" && args[1].contains("'^2200(/tcp)? +ALLOW'")
+    )));
+}
+
+#[test]
+fn test_check_ssh_port_allowed() {
+    let status = "Status: active\n\n\
+                  To                         Action      From\n\
+                  --                         ------      ----\n\
+                  OpenSSH                    ALLOW       Anywhere\n\
+                  2222/tcp                   ALLOW       Anywhere\n";
+    let host = Arc::new(
+        RecordingRunner::new("fake")
+            .with_file("/usr/sbin/ufw", "")
+            .respond("ufw status", status),
+    );
+    with_runner(host, || {
+        assert!(firewall::check_ssh_port_allowed(2222).is_ok());
+        assert!(firewall::check_ssh_port_allowed(22).is_ok());
+        let error = firewall::check_ssh_port_allowed(2200).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The SSH port 2200/tcp is not allowed through ufw; SSH connections would be blocked"
+        );
+    });
+
+    // An inactive firewall blocks nothing
+    let host = Arc::new(
+        RecordingRunner::new("fake")
+            .with_file("/usr/sbin/ufw", "")
+            .respond("ufw status", "Status: inactive\n"),
+    );
+    with_runner(host, || {
+        assert!(firewall::check_ssh_port_allowed(2200).is_ok())
+    });
+}
+
+#[test]
+fn test_setup_ssh_restores_config_when_preflight_fails() {
+    let sshd_config = "PermitRootLogin yes\n#Port 22\n";
+    let host = Arc::new(
+        RecordingRunner::new("fake")
+            .with_file("/usr/bin/apt", "")
+            .with_file("/etc/ssh/sshd_config", sshd_config)
+            .fail("sshd -t -f /etc/ssh/sshd_config"),
+    );
+
+    let result = with_runner(host.clone(), || setup::setup_ssh(&Config::default()));
+
+    assert!(result.is_err());
+    assert_eq!(host.file("/etc/ssh/sshd_config").unwrap(), sshd_config);
+    assert!(!host
+        .commands()
+        .contains(&String::from("systemctl restart sshd")));
+}
+
+#[test]
+fn test_logrotate_config() {
+    let config = Config {
+        backup_frequency: String::new(),
+        security_scan_schedule: String::new(),
+        ..Default::default()
+    };
+    assert!(setup::logrotate_config(&config).starts_with("/var/log/server_setup_*.log {\n"));
+
+    let config = Config::default();
+    let logrotate = setup::logrotate_config(&config);
+    assert!(logrotate.starts_with(
+        "/var/log/server_setup_*.log /var/log/restic.log /var/log/security_scan.log {\n"
+    ));
+    for directive in ["maxsize 50M", "rotate 4", "compress", "copytruncate"] {
+        assert!(logrotate.contains(directive), "missing {}", directive);
+    }
+
+    let mut plan = Plan::new();
+    setup::plan_logrotate(&mut plan, &config);
+    assert_eq!(
+        plan.operations(),
+        [Operation::WriteFile {
+            path: String::from("/etc/logrotate.d/server_forge"),
+            contents: logrotate,
+        }]
+    );
+}
+
+fn firewall_commands(host: RecordingRunner, distro: &str) -> Vec<String> {
+    let config = Config {
+        linux_distro: distro.to_string(),
+        custom_firewall_rules: vec![String::from("80/tcp"), String::from("443/tcp")],
+        ..Default::default()
+    };
+    let plan = with_runner(Arc::new(host), || {
+        Plan::build(|plan| setup::plan_firewall(plan, &config))
+    })
+    .unwrap();
+    plan.operations()
+        .iter()
+        .map(|operation| match operation {
+            Operation::RunCommand { command, args } => format!("{} {}", command, args.join(" ")),
+            other => format!("{:?}", other),
+        })
+        .collect()
+}
+
+#[test]
+fn test_plan_firewall_inactive_ufw() {
+    let host = RecordingRunner::new("fake")
+        .with_file("/usr/sbin/ufw", "")
+        .respond("ufw status", "Status: inactive\n");
+    assert_eq!(
+        firewall_commands(host, "ubuntu"),
+        vec![
+            "ufw default deny incoming",
+            "ufw default allow outgoing",
+            "ufw allow OpenSSH",
+            "ufw allow 2222/tcp",
+            "ufw allow 80/tcp",
+            "ufw allow 443/tcp",
+            "ufw --force enable",
+        ]
+    );
+}
+
+#[test]
+fn test_plan_firewall_active_ufw_adds_missing_rules() {
+    let status = "Status: active\n\n\
+                  To                         Action      From\n\
+                  --                         ------      ----\n\
+                  OpenSSH                    ALLOW       Anywhere\n\
+                  2222/tcp                   ALLOW       Anywhere\n\
+                  80/tcp                     ALLOW       Anywhere\n\
+                  OpenSSH (v6)               ALLOW       Anywhere (v6)\n";
+    let host = RecordingRunner::new("fake")
+        .with_file("/usr/sbin/ufw", "")
+        .respond("ufw status", status);
+    assert_eq!(firewall_commands(host, "ubuntu"), vec!["ufw allow 443/tcp"]);
+}
+
+#[test]
+fn test_plan_firewall_running_firewalld() {
+    let host = RecordingRunner::new("fake")
+        .with_file("/usr/bin/firewall-cmd", "")
+        .respond("firewall-cmd --state", "running\n")
+        .respond(
+            "firewall-cmd --zone=public --list-services --permanent",
+            "dhcpv6-client ssh\n",
+        )
+        .respond(
+            "firewall-cmd --zone=public --list-ports --permanent",
+            "2222/tcp 80/tcp 443/tcp\n",
+        );
+    assert!(firewall_commands(host, "fedora").is_empty());
+
+    let host = RecordingRunner::new("fake")
+        .with_file("/usr/bin/firewall-cmd", "")
+        .respond("firewall-cmd --state", "not running\n");
+    let commands = firewall_commands(host, "fedora");
+    assert_eq!(
+        commands.first().unwrap(),
+        r#"StartService { name: "firewalld" }"#
+    );
+    assert_eq!(commands.last().unwrap(), "firewall-cmd --reload");
+    assert!(commands.contains(&String::from(
+        "firewall-cmd --zone=public --set-target=default --permanent"
+    )));
+    assert_eq!(commands.len(), 8);
+}
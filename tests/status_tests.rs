@@ -0,0 +1,133 @@
+use crate::common::RecordingRunner;
+use server_forge::config::Config;
+use server_forge::runner::with_runner;
+use server_forge::status::{expected_services, host_status};
+use server_forge::utils::SAVED_CONFIG_PATH;
+use std::sync::Arc;
+
+const SAVED_AT: i64 = 1_700_000_000;
+
+/// A host with apt and an active ufw, on which `config` was saved. All of its services are
+/// inactive until given a state with `with_service`.
+fn fake_host(config: &Config) -> RecordingRunner {
+    RecordingRunner::new("fake")
+        .with_file(SAVED_CONFIG_PATH, &serde_json::to_string(config).unwrap())
+        .with_file("/usr/bin/apt", "")
+        .with_file("/usr/sbin/ufw", "")
+        .respond("stat", &format!("{}\n", SAVED_AT))
+        .respond("ufw status", "Status: active\n")
+}
+
+/// Makes a service of `host` report its state, and the time it was started at.
+fn with_service(
+    host: RecordingRunner,
+    name: &str,
+    active: bool,
+    enabled: bool,
+    started_at: i64,
+) -> RecordingRunner {
+    let active = if active { "active\n" } else { "inactive\n" };
+    let enabled = if enabled { "enabled\n" } else { "disabled\n" };
+    host.respond(&format!("systemctl is-active {}", name), active)
+        .respond(&format!("systemctl is-enabled {}", name), enabled)
+        .respond(
+            &format!(
+                "systemctl show --property=ActiveEnterTimestamp --value {}",
+                name
+            ),
+            &format!("@{}\n", started_at),
+        )
+        .respond(
+            &format!("date -d @{} +%s", started_at),
+            &format!("{}\n", started_at),
+        )
+}
+
+fn nginx_config() -> Config {
+    Config {
+        deployed_apps: vec![String::from("nginx")],
+        ..Config::default()
+    }
+}
+
+#[test]
+fn test_expected_services() {
+    let config = nginx_config();
+    let services =
+        with_runner(Arc::new(fake_host(&config)), || expected_services(&config)).unwrap();
+    assert_eq!(services, vec!["fail2ban", "unattended-upgrades", "nginx"]);
+
+    let config = Config {
+        linux_distro: String::from("fedora"),
+        monitoring: true,
+        use_containers: true,
+        ..nginx_config()
+    };
+    let host = RecordingRunner::new("fake").with_file("/usr/bin/dnf", "");
+    let services = with_runner(Arc::new(host), || expected_services(&config)).unwrap();
+    assert_eq!(
+        services,
+        vec![
+            "fail2ban",
+            "dnf-automatic.timer",
+            "prometheus",
+            "grafana-server",
+            "node_exporter",
+            "blackbox_exporter",
+            "docker"
+        ]
+    );
+}
+
+#[test]
+fn test_host_status_up_to_date() {
+    let config = nginx_config();
+    let host = fake_host(&config);
+    let host = with_service(host, "fail2ban", true, true, SAVED_AT + 10);
+    let host = with_service(host, "unattended-upgrades", true, true, SAVED_AT + 20);
+    let host = with_service(host, "nginx", true, true, SAVED_AT + 30);
+
+    let status = with_runner(Arc::new(host), host_status).unwrap().unwrap();
+    assert_eq!(status.host, "fake");
+    assert_eq!(status.saved_at, SAVED_AT);
+    assert_eq!(status.firewall_active, Some(true));
+    assert_eq!(status.services.len(), 3);
+    assert!(status
+        .services
+        .iter()
+        .all(|service| service.up_to_date == Some(true)));
+    assert!(status.problems().is_empty());
+
+    let table = status.to_string();
+    assert!(table.contains("Firewall: active"));
+    assert!(table.contains("SERVICE"));
+    assert!(table.contains("nginx"));
+}
+
+#[test]
+fn test_host_status_problems() {
+    let config = nginx_config();
+    let host = fake_host(&config).respond("ufw status", "Status: inactive\n");
+    let host = with_service(host, "fail2ban", true, true, SAVED_AT - 10);
+    let host = with_service(host, "unattended-upgrades", true, false, SAVED_AT + 20);
+
+    let status = with_runner(Arc::new(host), host_status).unwrap().unwrap();
+    assert_eq!(status.services[0].up_to_date, Some(false));
+    assert_eq!(status.services[2].up_to_date, None);
+    assert_eq!(
+        status.problems(),
+        vec![
+            "the firewall is inactive",
+            "fail2ban was started before the configuration was saved",
+            "unattended-upgrades is not enabled at boot",
+            "nginx is not running",
+            "nginx is not enabled at boot",
+        ]
+    );
+}
+
+#[test]
+fn test_host_status_without_saved_configuration() {
+    let host = RecordingRunner::new("fake").with_file("/usr/bin/apt", "");
+    assert!(with_runner(Arc::new(host), host_status).unwrap().is_none());
+}
@@ -0,0 +1,95 @@
+use server_forge::config::Scheduler;
+use server_forge::plan::{Operation, Plan};
+use server_forge::systemd::{cron_to_on_calendar, shell_exec_start, ServiceUnit, TimerUnit};
+use server_forge::utils::plan_job;
+
+#[test]
+fn test_cron_to_on_calendar() {
+    assert_eq!(cron_to_on_calendar("0 2 * * *").unwrap(), "*-*-* 02:00:00");
+    assert_eq!(cron_to_on_calendar("0 * * * *").unwrap(), "*-*-* *:00:00");
+    assert_eq!(
+        cron_to_on_calendar("0 2 * * 0").unwrap(),
+        "Sun *-*-* 02:00:00"
+    );
+    assert_eq!(cron_to_on_calendar("0 2 1 * *").unwrap(), "*-*-01 02:00:00");
+    assert_eq!(
+        cron_to_on_calendar("*/15 3 * * 1-5").unwrap(),
+        "Mon..Fri *-*-* 03:00/15:00"
+    );
+    assert!(cron_to_on_calendar("0 2 * *").is_err());
+    assert!(cron_to_on_calendar("0 2 * * MON").is_err());
+
+    // cron runs this on the 1st and on every Monday, which `OnCalendar=` cannot express
+    assert!(cron_to_on_calendar("0 0 1 * 1").is_err());
+    assert_eq!(
+        cron_to_on_calendar("0 0 */2 * 1").unwrap(),
+        "Mon *-*-01/2 00:00:00"
+    );
+}
+
+#[test]
+fn test_service_unit_render() {
+    let unit = ServiceUnit::new("Node Exporter", "/usr/local/bin/node_exporter")
+        .user("node_exporter")
+        .render();
+    assert!(unit.contains("Description=Node Exporter"));
+    assert!(unit.contains("User=node_exporter\nGroup=node_exporter"));
+    assert!(unit.contains("Type=simple"));
+    assert!(unit.contains("ExecStart=/usr/local/bin/node_exporter"));
+    assert!(unit.contains("WantedBy=multi-user.target"));
+    assert!(!unit.contains("WorkingDirectory="));
+
+    let unit = ServiceUnit::new("Shop", "/bin/sh -c './shop'")
+        .working_directory("/srv/shop")
+        .render();
+    assert!(unit.contains("WorkingDirectory=/srv/shop\nType=simple"));
+
+    let oneshot = ServiceUnit::oneshot("Restic backup", "/usr/local/bin/run-backup.sh").render();
+    assert!(oneshot.contains("Type=oneshot"));
+    assert!(!oneshot.contains("[Install]"));
+}
+
+#[test]
+fn test_timer_unit_render() {
+    let timer = TimerUnit::new("Restic backup", "*-*-* 02:00:00").render();
+    assert!(timer.contains("OnCalendar=*-*-* 02:00:00"));
+    assert!(timer.contains("Persistent=true"));
+    assert!(timer.contains("WantedBy=timers.target"));
+}
+
+#[test]
+fn test_shell_exec_start() {
+    assert_eq!(shell_exec_start("./shop"), "/bin/sh -c './shop'");
+    assert_eq!(
+        shell_exec_start("tar czf /backup/$(date +%F).tgz /srv | grep -v '^tar: '"),
+        r"/bin/sh -c 'tar czf /backup/$$(date +%%F).tgz /srv | grep -v '\''^tar: '\'''"
+    );
+    assert_eq!(
+        shell_exec_start(r"printf 'a\n'"),
+        r"/bin/sh -c 'printf '\''a\\n'\'''"
+    );
+}
+
+#[test]
+fn test_plan_job_escapes_the_command() {
+    let command = r#"echo "$(date +%F)" >> /var/log/job.log"#;
+    let written = |scheduler: Scheduler| {
+        let plan =
+            Plan::build(|plan| plan_job(plan, &scheduler, "job", "Job", "0 2 * * *", command))
+                .unwrap();
+        plan.operations()
+            .iter()
+            .filter_map(|operation| match operation {
+                Operation::WriteFile { contents, .. } => Some(contents.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        written(Scheduler::Cron),
+        [r#"0 2 * * * root echo "$(date +\%F)" >> /var/log/job.log"#.to_string() + "\n"]
+    );
+    assert!(written(Scheduler::SystemdTimer)[0]
+        .contains(r#"ExecStart=/bin/sh -c 'echo "$$(date +%%F)" >> /var/log/job.log'"#));
+}
@@ -282,3 +282,292 @@
 //         assert!(result.is_err());
 //     }
 // }
+
+use crate::common::RecordingRunner;
+use server_forge::config::{Config, MaintenanceWindow};
+use server_forge::distro::PackageManager;
+use server_forge::rollback::RollbackManager;
+use server_forge::runner::with_runner;
+use server_forge::updates::{
+    configure_dnf_automatic, configure_yum_cron, generate_auto_upgrades_conf,
+    generate_dnf_automatic_timer_override, generate_unattended_upgrades_conf, is_time_of_day,
+    setup_automatic_updates, update_interval_days, UpdateMechanism,
+};
+use std::sync::Arc;
+
+#[test]
+fn test_update_mechanism() {
+    for (package_manager, mechanism, package, service) in [
+        (
+            PackageManager::Apt,
+            UpdateMechanism::UnattendedUpgrades,
+            "unattended-upgrades",
+            "unattended-upgrades",
+        ),
+        (
+            PackageManager::Yum,
+            UpdateMechanism::YumCron,
+            "yum-cron",
+            "yum-cron",
+        ),
+        (
+            PackageManager::Dnf,
+            UpdateMechanism::DnfAutomatic,
+            "dnf-automatic",
+            "dnf-automatic.timer",
+        ),
+    ] {
+        assert_eq!(
+            UpdateMechanism::for_package_manager(&package_manager),
+            mechanism
+        );
+        assert_eq!(mechanism.package(), package);
+        assert_eq!(mechanism.service(), service);
+    }
+}
+
+#[test]
+fn test_update_mechanism_ignores_distro() {
+    // Debian, Rocky and Alma Linux are set up like the distributions sharing their package
+    // manager
+    for (distro, path, package) in [
+        (
+            "debian",
+            "/usr/bin/apt",
+            "apt install -y unattended-upgrades",
+        ),
+        ("rocky", "/usr/bin/dnf", "dnf install -y dnf-automatic"),
+        ("almalinux", "/usr/bin/dnf", "dnf install -y dnf-automatic"),
+    ] {
+        let host = Arc::new(RecordingRunner::new("fake").with_file(path, "").with_file(
+            "/etc/dnf/automatic.conf",
+            "[commands]\napply_updates = no\n",
+        ));
+        let config = Config {
+            linux_distro: String::from(distro),
+            ..Default::default()
+        };
+        let rollback = RollbackManager::new();
+        with_runner(host.clone(), || setup_automatic_updates(&config, &rollback)).unwrap();
+        assert!(host.commands().contains(&String::from(package)));
+    }
+}
+
+#[test]
+fn test_update_interval_days() {
+    assert_eq!(update_interval_days("daily").unwrap(), 1);
+    assert_eq!(update_interval_days("weekly").unwrap(), 7);
+    assert_eq!(update_interval_days("monthly").unwrap(), 30);
+    assert!(update_interval_days("hourly").is_err());
+}
+
+#[test]
+fn test_generate_auto_upgrades_conf() {
+    assert_eq!(
+        generate_auto_upgrades_conf("daily").unwrap(),
+        "APT::Periodic::Update-Package-Lists \"1\";\nAPT::Periodic::Unattended-Upgrade \"1\";\n"
+    );
+    assert_eq!(
+        generate_auto_upgrades_conf("monthly").unwrap(),
+        "APT::Periodic::Update-Package-Lists \"30\";\nAPT::Periodic::Unattended-Upgrade \"30\";\n"
+    );
+    assert!(generate_auto_upgrades_conf("").is_err());
+}
+
+#[test]
+fn test_generate_dnf_automatic_timer_override() {
+    for schedule in ["daily", "weekly", "monthly"] {
+        assert_eq!(
+            generate_dnf_automatic_timer_override(schedule).unwrap(),
+            format!("[Timer]\nOnCalendar=\nOnCalendar={}\n", schedule)
+        );
+    }
+    assert!(generate_dnf_automatic_timer_override("0 2 * * *").is_err());
+}
+
+#[test]
+fn test_is_time_of_day() {
+    for time in ["00:00", "03:00", "23:59"] {
+        assert!(is_time_of_day(time), "{}", time);
+    }
+    for time in ["3:00", "24:00", "03:60", "03-00", "now", ""] {
+        assert!(!is_time_of_day(time), "{}", time);
+    }
+}
+
+#[test]
+fn test_generate_unattended_upgrades_conf() {
+    let conf = generate_unattended_upgrades_conf(&Config::default());
+    assert!(conf.contains("Unattended-Upgrade::Automatic-Reboot \"false\";\n"));
+    assert!(!conf.contains("Automatic-Reboot-Time"));
+
+    // The reboot time is only used when rebooting is allowed
+    let config = Config {
+        auto_reboot_time: Some(String::from("03:00")),
+        ..Default::default()
+    };
+    assert!(!generate_unattended_upgrades_conf(&config).contains("Automatic-Reboot-Time"));
+
+    let config = Config {
+        auto_reboot: true,
+        ..config
+    };
+    let conf = generate_unattended_upgrades_conf(&config);
+    assert!(conf.contains("Unattended-Upgrade::Automatic-Reboot \"true\";\n"));
+    assert!(conf.contains("Unattended-Upgrade::Automatic-Reboot-Time \"03:00\";\n"));
+
+    // Without a reboot time, the reboot happens once the maintenance window's jobs are done
+    let config = Config {
+        auto_reboot_time: None,
+        maintenance_window: Some(MaintenanceWindow {
+            start: String::from("01:00"),
+            days: Vec::new(),
+        }),
+        ..config
+    };
+    let conf = generate_unattended_upgrades_conf(&config);
+    assert!(conf.contains("Unattended-Upgrade::Automatic-Reboot-Time \"03:00\";\n"));
+}
+
+#[test]
+fn test_generate_unattended_upgrades_conf_blacklist() {
+    let conf = generate_unattended_upgrades_conf(&Config::default());
+    assert!(conf.contains("Unattended-Upgrade::Package-Blacklist {\n};\n"));
+
+    let config = Config {
+        update_blacklist: vec![
+            String::from("linux-image-.*"),
+            String::from("postgresql-14"),
+        ],
+        ..Default::default()
+    };
+    let conf = generate_unattended_upgrades_conf(&config);
+    assert!(conf.contains(
+        "Unattended-Upgrade::Package-Blacklist {\n    \"linux-image-.*\";\n    \"postgresql-14\";\n};\n"
+    ));
+}
+
+#[test]
+fn test_configure_yum_cron() {
+    let installed =
+        "[commands]\napply_updates = no\n\n[base]\n# exclude =\nmdpolicy = group:main\n";
+    assert_eq!(
+        configure_yum_cron(installed, &Config::default()),
+        "[commands]\napply_updates = yes\n\n[base]\n# exclude =\nmdpolicy = group:main\n"
+    );
+
+    let config = Config {
+        update_blacklist: vec![String::from("kernel*"), String::from("mariadb-server")],
+        ..Default::default()
+    };
+    assert_eq!(
+        configure_yum_cron(installed, &config),
+        "[commands]\napply_updates = yes\n\n[base]\nexclude = kernel* mariadb-server\nmdpolicy = group:main\n"
+    );
+}
+
+#[test]
+fn test_configure_dnf_automatic() {
+    let installed = "[commands]\nupgrade_type = default\napply_updates = no\n# reboot = never\n\n[emitters]\nemit_via = stdio\n";
+    assert_eq!(
+        configure_dnf_automatic(installed, &Config::default()),
+        "[commands]\nupgrade_type = default\napply_updates = yes\nreboot = never\n\n[emitters]\nemit_via = stdio\n"
+    );
+
+    // Older versions have no reboot option, so it is added to the commands section
+    let installed = "[commands]\napply_updates = no\n\n[emitters]\nemit_via = stdio\n";
+    let config = Config {
+        auto_reboot: true,
+        auto_reboot_time: Some(String::from("03:00")),
+        update_blacklist: vec![String::from("kernel*")],
+        ..Default::default()
+    };
+    assert_eq!(
+        configure_dnf_automatic(installed, &config),
+        "[commands]\nreboot_command = \"shutdown -r 03:00 'Rebooting after applying package updates'\"\nreboot = when-needed\napply_updates = yes\n\n[emitters]\nemit_via = stdio\n\n[base]\nexclude = kernel*\n"
+    );
+}
+
+#[test]
+fn test_setup_automatic_updates_apt() {
+    let host = Arc::new(RecordingRunner::new("fake").with_file("/usr/bin/apt", ""));
+    let config = Config {
+        linux_distro: String::from("ubuntu"),
+        update_schedule: String::from("weekly"),
+        ..Default::default()
+    };
+    let rollback = RollbackManager::new();
+    with_runner(host.clone(), || setup_automatic_updates(&config, &rollback)).unwrap();
+
+    let commands = host.commands();
+    assert!(commands.contains(&String::from("apt install -y unattended-upgrades")));
+    assert!(commands.contains(&String::from("systemctl enable unattended-upgrades")));
+    assert!(host
+        .file("/etc/apt/apt.conf.d/20auto-upgrades")
+        .unwrap()
+        .contains("APT::Periodic::Unattended-Upgrade \"7\";"));
+}
+
+#[test]
+fn test_setup_automatic_updates_yum() {
+    let host = Arc::new(
+        RecordingRunner::new("fake")
+            .with_file("/usr/bin/yum", "")
+            .with_file("/etc/yum/yum-cron.conf", "apply_updates = no\n")
+            .with_file("/etc/cron.daily/0yum-daily.cron", ""),
+    );
+    let config = Config {
+        linux_distro: String::from("centos"),
+        update_schedule: String::from("monthly"),
+        ..Default::default()
+    };
+    let rollback = RollbackManager::new();
+    with_runner(host.clone(), || setup_automatic_updates(&config, &rollback)).unwrap();
+
+    let commands = host.commands();
+    assert!(commands.contains(&String::from("yum install -y yum-cron")));
+    assert!(commands.contains(&String::from("systemctl enable yum-cron")));
+    assert!(!commands.iter().any(|command| command.contains("dnf")));
+    assert!(commands.contains(&String::from(
+        "mv /etc/cron.daily/0yum-daily.cron /etc/cron.monthly/0yum-daily.cron"
+    )));
+    assert_eq!(
+        host.file("/etc/yum/yum-cron.conf").unwrap(),
+        "apply_updates = yes\n"
+    );
+}
+
+#[test]
+fn test_setup_automatic_updates_dnf() {
+    // A CentOS Stream or RHEL 8+ system: yum is a link to dnf, and yum-cron is not available
+    let host = Arc::new(
+        RecordingRunner::new("fake")
+            .with_file("/usr/bin/yum", "")
+            .with_file("/usr/bin/dnf", "")
+            .with_file(
+                "/etc/dnf/automatic.conf",
+                "[commands]\napply_updates = no\n",
+            ),
+    );
+    let config = Config {
+        linux_distro: String::from("centos"),
+        update_schedule: String::from("monthly"),
+        ..Default::default()
+    };
+    let rollback = RollbackManager::new();
+    with_runner(host.clone(), || setup_automatic_updates(&config, &rollback)).unwrap();
+
+    let commands = host.commands();
+    assert!(commands.contains(&String::from("dnf install -y dnf-automatic")));
+    assert!(commands.contains(&String::from("systemctl enable dnf-automatic.timer")));
+    assert!(!commands.iter().any(|command| command.contains("yum-cron")));
+    assert_eq!(
+        host.file("/etc/dnf/automatic.conf").unwrap(),
+        "[commands]\nreboot = never\napply_updates = yes\n"
+    );
+    assert_eq!(
+        host.file("/etc/systemd/system/dnf-automatic.timer.d/serverforge-schedule.conf")
+            .unwrap(),
+        "[Timer]\nOnCalendar=\nOnCalendar=monthly\n"
+    );
+}
@@ -28,10 +28,17 @@ use std::error::Error;
 mod tests {
     use super::*;
     use server_forge::config::Config;
-    use server_forge::utils::{generate_report, get_user_input, run_command, save_config};
+    use server_forge::pipeline::PhaseTiming;
+    use server_forge::utils::{
+        arch_suffix, check_resources, download, format_duration, generate_apt_proxy_conf,
+        generate_report, generate_secure_password, get_user_input, listening_process,
+        load_env_file, mirror_url, proxy_env, report_email_command, run_command, save_config,
+        shell_quote, target_arch_suffix, timing_section,
+    };
     use std::error::Error;
     use std::fs;
     use std::io::Cursor;
+    use std::time::Duration;
     use tempfile::tempdir;
 
     // #[test]
@@ -99,6 +106,188 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("plain"), "'plain'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn test_proxy_env() {
+        let mut config = Config::default();
+        assert!(proxy_env(&config).is_empty());
+        assert_eq!(generate_apt_proxy_conf(&config), "");
+
+        config.http_proxy = Some(String::from("http://proxy.internal:3128"));
+        config.https_proxy = Some(String::from("http://proxy.internal:3129"));
+        let env = proxy_env(&config);
+        for (name, value) in [
+            ("http_proxy", "http://proxy.internal:3128"),
+            ("HTTP_PROXY", "http://proxy.internal:3128"),
+            ("https_proxy", "http://proxy.internal:3129"),
+            ("HTTPS_PROXY", "http://proxy.internal:3129"),
+        ] {
+            assert!(env.contains(&(name.to_string(), value.to_string())));
+        }
+        assert!(env.iter().any(|(name, _)| name == "no_proxy"));
+
+        assert_eq!(
+            generate_apt_proxy_conf(&config),
+            "Acquire::http::Proxy \"http://proxy.internal:3128\";\nAcquire::https::Proxy \"http://proxy.internal:3129\";\n"
+        );
+    }
+
+    #[test]
+    fn test_mirror_url() {
+        let url = "https://github.com/prometheus/prometheus/releases/download/v2.30.3/prometheus-2.30.3.linux-amd64.tar.gz";
+        let mut config = Config::default();
+        assert_eq!(mirror_url(&config, url), url);
+
+        config.download_base_url = Some(String::from("https://mirror.internal/artifacts/"));
+        assert_eq!(
+            mirror_url(&config, url),
+            "https://mirror.internal/artifacts/github.com/prometheus/prometheus/releases/download/v2.30.3/prometheus-2.30.3.linux-amd64.tar.gz"
+        );
+    }
+
+    #[test]
+    fn test_download_from_local_artifacts() -> Result<(), Box<dyn Error>> {
+        let artifacts = tempdir()?;
+        fs::write(artifacts.path().join("minikube-linux-amd64"), "binary")?;
+        let config = Config {
+            // Unreachable, so the test fails if it tries to download
+            download_base_url: Some(String::from("http://127.0.0.1:9")),
+            local_artifacts_dir: Some(artifacts.path().to_string_lossy().into_owned()),
+            ..Config::default()
+        };
+
+        let destination = artifacts.path().join("minikube");
+        download(
+            &config,
+            "https://storage.googleapis.com/minikube/releases/latest/minikube-linux-amd64",
+            &destination.to_string_lossy(),
+        )?;
+        assert_eq!(fs::read_to_string(destination)?, "binary");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_env_file() -> Result<(), Box<dyn Error>> {
+        let dir = tempdir()?;
+        let path = dir.path().join("dev.env");
+        fs::write(
+            &path,
+            "SERVERFORGE_TEST_FROM_FILE=from-file\nSERVERFORGE_TEST_OVERRIDDEN=from-file\n",
+        )?;
+        std::env::set_var("SERVERFORGE_TEST_OVERRIDDEN", "from-environment");
+
+        let path = path.to_string_lossy().into_owned();
+        assert_eq!(
+            load_env_file(Some(&path))?,
+            Some(std::path::PathBuf::from(&path))
+        );
+        assert_eq!(std::env::var("SERVERFORGE_TEST_FROM_FILE")?, "from-file");
+        // The real environment wins over the file
+        assert_eq!(
+            std::env::var("SERVERFORGE_TEST_OVERRIDDEN")?,
+            "from-environment"
+        );
+
+        // A file that was asked for must exist
+        let missing = dir.path().join("missing.env");
+        assert!(load_env_file(Some(&missing.to_string_lossy())).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_arch_suffix() {
+        assert_eq!(arch_suffix("x86_64").unwrap(), "amd64");
+        assert_eq!(arch_suffix("aarch64").unwrap(), "arm64");
+        assert_eq!(arch_suffix("arm").unwrap(), "armv7");
+        assert!(arch_suffix("riscv64").is_err());
+        assert!(target_arch_suffix().is_ok());
+    }
+
+    #[test]
+    fn test_check_resources() {
+        assert!(check_resources(0, 0, 1).is_ok());
+
+        let error = check_resources(u64::MAX, u64::MAX, u64::MAX)
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("Insufficient resources on localhost: "));
+        assert!(error.contains("MB of memory (at least"));
+        assert!(error.contains("MB of free disk space on /var/lib (at least"));
+        assert!(error.contains("CPUs (at least"));
+    }
+
+    #[test]
+    fn test_generate_secure_password() {
+        let password = generate_secure_password();
+        assert_eq!(password.len(), 20);
+        assert_ne!(password, generate_secure_password());
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_millis(42_450)), "42.5s");
+        assert_eq!(format_duration(Duration::from_millis(187_200)), "3m 07.2s");
+        assert_eq!(format_duration(Duration::from_secs(3725)), "1h 02m 05s");
+    }
+
+    #[test]
+    fn test_timing_section() {
+        let timings = [
+            PhaseTiming {
+                phase: String::from("Initial setup"),
+                duration: Duration::from_secs(95),
+            },
+            PhaseTiming {
+                phase: String::from("Monitoring"),
+                duration: Duration::from_millis(12_300),
+            },
+        ];
+        assert_eq!(
+            timing_section(&timings, Duration::from_secs(110)),
+            "\nTiming:\n- Initial setup: 1m 35.0s\n- Monitoring: 12.3s\nTotal: 1m 50.0s\n"
+        );
+    }
+
+    #[test]
+    fn test_listening_process() {
+        let output = "LISTEN 0      511          0.0.0.0:80        0.0.0.0:*    users:((\"nginx\",pid=812,fd=6),(\"nginx\",pid=811,fd=6))\n\
+                      LISTEN 0      4096       127.0.0.53%lo:53      0.0.0.0:*    users:((\"systemd-resolve\",pid=530,fd=14))\n\
+                      LISTEN 0      128             [::]:2222         [::]:*\n";
+        assert_eq!(listening_process(output, 80).as_deref(), Some("nginx"));
+        assert_eq!(
+            listening_process(output, 53).as_deref(),
+            Some("systemd-resolve")
+        );
+        assert_eq!(
+            listening_process(output, 2222).as_deref(),
+            Some("another process")
+        );
+        assert_eq!(listening_process(output, 8080), None);
+        assert_eq!(listening_process(output, 8), None);
+    }
+
+    #[test]
+    fn test_report_email_command() {
+        assert_eq!(
+            report_email_command("/usr/sbin/sendmail", "ops@example.com", "/root/report.txt"),
+            "{ printf 'To: %s\\nSubject: %s\\n\\n' 'ops@example.com' \
+             \"[serverforge] Setup report for $(hostname)\"; cat '/root/report.txt'; } \
+             | /usr/sbin/sendmail 'ops@example.com'"
+        );
+        assert_eq!(
+            report_email_command("/usr/bin/mail", "ops@example.com", "/root/report.txt"),
+            "/usr/bin/mail -s \"[serverforge] Setup report for $(hostname)\" \
+             'ops@example.com' < '/root/report.txt'"
+        );
+    }
+
     // #[test]
     // fn test_generate_report() -> Result<(), Box<dyn Error>> {
     //     let temp_dir = tempdir()?;
@@ -0,0 +1,97 @@
+use crate::common::RecordingRunner;
+use server_forge::config::Config;
+use server_forge::monitoring::configure_prometheus;
+use server_forge::plan::{Operation, Plan};
+use server_forge::runner::with_runner;
+use server_forge::validation::{validate_config, ServiceType};
+use std::sync::Arc;
+
+#[test]
+fn test_check_command() {
+    let command_line = |service_type: ServiceType, path: &str| {
+        let (command, args) = service_type.check_command(path);
+        std::iter::once(command.to_string())
+            .chain(args)
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    assert_eq!(
+        command_line(ServiceType::Prometheus, "/etc/prometheus/prometheus.yml"),
+        "promtool check config /etc/prometheus/prometheus.yml"
+    );
+    assert_eq!(
+        command_line(ServiceType::Haproxy, "/etc/haproxy/haproxy.cfg"),
+        "haproxy -c -f /etc/haproxy/haproxy.cfg"
+    );
+    assert_eq!(
+        command_line(ServiceType::Sshd, "/etc/ssh/sshd_config"),
+        "sshd -t -f /etc/ssh/sshd_config"
+    );
+    // Web servers check their whole configuration, which includes the site
+    assert_eq!(
+        command_line(ServiceType::Nginx, "/etc/nginx/conf.d/shop.conf"),
+        "nginx -t"
+    );
+    assert_eq!(
+        command_line(ServiceType::Apache, "/etc/httpd/conf.d/shop.conf"),
+        "apachectl configtest"
+    );
+}
+
+#[test]
+fn test_validate_config() {
+    let runner = Arc::new(RecordingRunner::new("lb1").fail("haproxy -c"));
+
+    with_runner(runner.clone(), || {
+        assert!(validate_config(ServiceType::Sshd, "/etc/ssh/sshd_config").is_ok());
+        let error = validate_config(ServiceType::Haproxy, "/etc/haproxy/haproxy.cfg")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.starts_with("Invalid haproxy configuration /etc/haproxy/haproxy.cfg"),
+            "{}",
+            error
+        );
+    });
+
+    assert_eq!(
+        runner.log(),
+        vec![
+            "sshd -t -f /etc/ssh/sshd_config",
+            "haproxy -c -f /etc/haproxy/haproxy.cfg"
+        ]
+    );
+}
+
+#[test]
+fn test_plan_validate_config() {
+    let mut plan = Plan::new();
+    plan.write_file("/etc/ssh/sshd_config", "Port 2222\n")
+        .validate_config(ServiceType::Sshd, "/etc/ssh/sshd_config")
+        .restart_service("sshd");
+
+    assert_eq!(
+        plan.operations()[1],
+        Operation::RunCommand {
+            command: String::from("sshd"),
+            args: ["-t", "-f", "/etc/ssh/sshd_config"]
+                .map(String::from)
+                .to_vec(),
+        }
+    );
+}
+
+#[test]
+fn test_invalid_prometheus_config_is_not_loaded() {
+    let runner = Arc::new(RecordingRunner::ubuntu("mon1").fail("promtool check config"));
+
+    let result = with_runner(runner.clone(), || configure_prometheus(&Config::default()));
+
+    assert!(result.is_err());
+    assert_eq!(
+        runner.count("promtool check config /etc/prometheus/prometheus.yml"),
+        1
+    );
+    assert_eq!(runner.count("systemctl restart prometheus"), 0);
+}
@@ -0,0 +1,90 @@
+use crate::common::RecordingRunner;
+use server_forge::config::{Config, WebServerConfigMode};
+use server_forge::deployment::setup_web_server_config;
+use server_forge::rollback::RollbackManager;
+use server_forge::runner::with_runner;
+use std::sync::Arc;
+
+const DEFAULT_SITE: &str = "/etc/nginx/sites-available/default";
+const EXISTING_SITE: &str = "server {\n    listen 80;\n    root /srv/mine;\n}\n";
+
+fn config(mode: WebServerConfigMode) -> Config {
+    Config {
+        web_server_config_mode: mode,
+        ..Config::default()
+    }
+}
+
+#[test]
+fn test_default_site_is_enabled_and_tested_before_reload() {
+    let runner = Arc::new(RecordingRunner::ubuntu("web1").with_file(DEFAULT_SITE, EXISTING_SITE));
+
+    with_runner(runner.clone(), || {
+        setup_web_server_config(
+            "nginx",
+            &config(WebServerConfigMode::Replace),
+            &RollbackManager::new(),
+        )
+    })
+    .unwrap();
+
+    assert_eq!(
+        runner.log(),
+        vec![
+            "write /etc/nginx/sites-available/default",
+            "ln -sfn /etc/nginx/sites-available/default /etc/nginx/sites-enabled/default",
+            "nginx -t",
+            "systemctl reload nginx",
+        ]
+    );
+    assert!(runner
+        .file(DEFAULT_SITE)
+        .unwrap()
+        .contains("listen 80 default_server;"));
+}
+
+#[test]
+fn test_failed_config_test_is_rolled_back() {
+    let runner = Arc::new(
+        RecordingRunner::ubuntu("web1")
+            .with_file(DEFAULT_SITE, EXISTING_SITE)
+            .fail("nginx -t"),
+    );
+
+    let result = with_runner(runner.clone(), || {
+        setup_web_server_config(
+            "nginx",
+            &config(WebServerConfigMode::Replace),
+            &RollbackManager::new(),
+        )
+    });
+
+    assert!(result.is_err());
+    assert_eq!(runner.file(DEFAULT_SITE).unwrap(), EXISTING_SITE);
+    assert_eq!(runner.count("systemctl reload nginx"), 0);
+}
+
+#[test]
+fn test_failed_config_test_disables_the_added_site() {
+    let runner = Arc::new(
+        RecordingRunner::ubuntu("web1")
+            .with_file(DEFAULT_SITE, EXISTING_SITE)
+            .fail("nginx -t"),
+    );
+
+    let result = with_runner(runner.clone(), || {
+        setup_web_server_config(
+            "nginx",
+            &config(WebServerConfigMode::AddVhost),
+            &RollbackManager::new(),
+        )
+    });
+
+    assert!(result.is_err());
+    assert_eq!(runner.file(DEFAULT_SITE).unwrap(), EXISTING_SITE);
+    assert_eq!(
+        runner.count("rm -f /etc/nginx/sites-enabled/serverforge"),
+        1
+    );
+    assert_eq!(runner.count("systemctl reload nginx"), 0);
+}